@@ -0,0 +1,269 @@
+//! Simulates busy rooms against a running wormhole server, so capacity can be planned from
+//! measurements rather than guesses
+//!
+//! Creates `--rooms` rooms through the REST API and joins `--clients` anonymous clients to
+//! each over WebSockets. Every client then broadcasts `--rate` times a second for
+//! `--duration-secs`, stamping each broadcast with when it was sent, and times how long the
+//! broadcasts of everyone in its room take to reach it. The run ends with the percentiles of
+//! those latencies and how many errors of each kind happened along the way, exiting with a
+//! failure if there were any.
+//!
+//! Broadcasts count against the server's rate limits like any other client's, which should be
+//! raised or turned off on the server being measured.
+
+mod stats;
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio::net::TcpStream;
+use tokio::time::{interval_at, timeout, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use wormhole_protocol::{ClientEnvelope, ClientMessage, RoomId, ServerMessage, PROTOCOL_VERSION};
+
+use crate::stats::{Report, Stats};
+
+const TOKEN_ENV_VAR: &str = "WORMHOLE_API_TOKEN";
+
+/// How long a client may take to join its room
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long clients keep reading after they stop sending, for broadcasts still on their way
+const DRAIN_PERIOD: Duration = Duration::from_secs(2);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "wormhole-bench",
+    version,
+    about,
+    after_help = "The API token is read from WORMHOLE_API_TOKEN."
+)]
+struct Cli {
+    /// The server's URL
+    #[arg(long, env = "WORMHOLE_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// How many rooms to create
+    #[arg(long, default_value_t = 10)]
+    rooms: usize,
+    /// How many clients join each room
+    #[arg(long, default_value_t = 4)]
+    clients: usize,
+    /// How many broadcasts each client sends a second
+    #[arg(long, default_value_t = 5.0)]
+    rate: f64,
+    /// How long clients keep broadcasting for
+    #[arg(long, default_value_t = 30)]
+    duration_secs: u64,
+    /// How many bytes of padding each broadcast carries on top of its timestamp
+    #[arg(long, default_value_t = 64)]
+    payload_bytes: usize,
+}
+
+/// Creates a room through the REST API, returning its ID
+async fn create_room(http: &reqwest::Client, url: &str, token: Option<&str>) -> Result<RoomId> {
+    let mut request = http.post(format!("{url}/api/v1/rooms/"));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.context("The server is unreachable")?;
+    if response.status() != reqwest::StatusCode::CREATED {
+        bail!("Creating a room failed with {}", response.status());
+    }
+    response
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .and_then(|location| location.strip_prefix("/ws/"))
+        .and_then(|room_id| room_id.parse().ok())
+        .context("The created room's location isn't its WebSocket URL")
+}
+
+/// Joins the room, returning the socket once the server has welcomed the client
+async fn connect(ws_url: &str, room_id: RoomId) -> Result<Socket> {
+    let url = format!("{ws_url}/ws/{room_id}?protocol_version={PROTOCOL_VERSION}");
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await?;
+    while let Some(frame) = socket.next().await {
+        if let Message::Text(text) = frame? {
+            return match serde_json::from_str(&text)? {
+                ServerMessage::Welcome { .. } => Ok(socket),
+                message => bail!("Expected to be welcomed, got {message:?}"),
+            };
+        }
+    }
+    bail!("The server closed the connection before welcoming the client")
+}
+
+/// Tallies a message from the server, timing broadcasts against `start`
+fn record(message: ServerMessage<'static>, start: Instant, stats: &mut Stats) {
+    match message {
+        ServerMessage::Broadcast { payload, .. } => match payload["sent_at_us"].as_u64() {
+            Some(sent_at) => stats.record_latency(
+                start
+                    .elapsed()
+                    .saturating_sub(Duration::from_micros(sent_at)),
+            ),
+            None => stats.record_error("unstamped_broadcast"),
+        },
+        ServerMessage::Batch { messages } => {
+            for message in messages {
+                record(message, start, stats);
+            }
+        }
+        ServerMessage::Error { .. } => stats.record_error("server_error"),
+        _ => {}
+    }
+}
+
+/// Broadcasts every `period` until `deadline`, starting after `offset` so clients don't all
+/// send at once, then reads whatever is still on its way for the [DRAIN_PERIOD]
+async fn run_client(
+    socket: Socket,
+    start: Instant,
+    offset: Duration,
+    period: Duration,
+    deadline: Instant,
+    padding: String,
+) -> Stats {
+    let mut stats = Stats::default();
+    let (mut sink, mut stream) = socket.split();
+    let mut sends = interval_at(start + offset, period);
+    let drained = deadline + DRAIN_PERIOD;
+    loop {
+        tokio::select! {
+            _ = sends.tick(), if Instant::now() < deadline => {
+                let envelope = ClientEnvelope::from(ClientMessage::Broadcast {
+                    payload: json!({
+                        "sent_at_us": start.elapsed().as_micros() as u64,
+                        "padding": padding,
+                    }),
+                });
+                let text = serde_json::to_string(&envelope).expect("Envelopes always serialize");
+                match sink.send(Message::Text(text)).await {
+                    Ok(()) => stats.sent += 1,
+                    Err(_) => {
+                        stats.record_error("send_failed");
+                        break;
+                    }
+                }
+            }
+            frame = stream.next() => match frame {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(message) => record(message, start, &mut stats),
+                    Err(_) => stats.record_error("unreadable_message"),
+                },
+                Some(Ok(Message::Close(_))) | None => {
+                    stats.record_error("closed_early");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) => {
+                    stats.record_error("connection_failed");
+                    break;
+                }
+            },
+            _ = tokio::time::sleep_until(drained) => {
+                let _ = sink.close().await;
+                break;
+            }
+        }
+    }
+    stats
+}
+
+async fn run(cli: Cli) -> Result<Report> {
+    if cli.rooms == 0 || cli.clients == 0 || cli.rate <= 0.0 {
+        bail!("There must be at least one room and one client sending at a positive rate");
+    }
+    let url = cli.url.trim_end_matches('/').to_owned();
+    let ws_url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{rest}"),
+        Some((_, rest)) => format!("ws://{rest}"),
+        None => bail!("The server's URL must start with http:// or https://"),
+    };
+    let token = std::env::var(TOKEN_ENV_VAR).ok();
+    let http = reqwest::Client::new();
+    let mut stats = Stats::default();
+
+    let mut rooms = Vec::with_capacity(cli.rooms);
+    for _ in 0..cli.rooms {
+        match create_room(&http, &url, token.as_deref()).await {
+            Ok(room_id) => rooms.push(room_id),
+            Err(e) => {
+                eprintln!("{e:#}");
+                stats.record_error("room_creation_failed");
+            }
+        }
+    }
+    if rooms.is_empty() {
+        bail!("No rooms could be created");
+    }
+
+    let connections = rooms.iter().flat_map(|&room_id| {
+        let ws_url = &ws_url;
+        (0..cli.clients)
+            .map(move |_| async move { timeout(CONNECT_TIMEOUT, connect(ws_url, room_id)).await })
+    });
+    let mut sockets = Vec::with_capacity(rooms.len() * cli.clients);
+    for connection in futures_util::future::join_all(connections).await {
+        match connection {
+            Ok(Ok(socket)) => sockets.push(socket),
+            Ok(Err(e)) => {
+                eprintln!("{e:#}");
+                stats.record_error("connect_failed");
+            }
+            Err(_) => stats.record_error("connect_timed_out"),
+        }
+    }
+    println!(
+        "Joined {} clients to {} rooms, broadcasting for {}s",
+        sockets.len(),
+        rooms.len(),
+        cli.duration_secs
+    );
+
+    let period = Duration::from_secs_f64(1.0 / cli.rate);
+    let padding = "x".repeat(cli.payload_bytes);
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(cli.duration_secs);
+    let client_count = sockets.len() as u32;
+    let clients: Vec<_> = sockets
+        .into_iter()
+        .zip(0..)
+        .map(|(socket, i)| {
+            let offset = period * i / client_count;
+            tokio::spawn(run_client(
+                socket,
+                start,
+                offset,
+                period,
+                deadline,
+                padding.clone(),
+            ))
+        })
+        .collect();
+    for client in clients {
+        match client.await {
+            Ok(client_stats) => stats.merge(client_stats),
+            Err(_) => stats.record_error("client_panicked"),
+        }
+    }
+    Ok(Report::new(stats, deadline - start))
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let report = run(Cli::parse()).await?;
+    println!("{report}");
+    Ok(if report.error_count() == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
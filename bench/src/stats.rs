@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// The percentiles of broadcast latency a report shows
+const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+/// What one client saw over a run, or every client once [merged][Stats::merge]
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub sent: u64,
+    pub received: u64,
+    /// How long each broadcast sent during the run took to arrive, in microseconds
+    latencies: Vec<u64>,
+    /// How many times each kind of error happened
+    errors: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    /// Records a broadcast arriving `latency` after it was sent
+    pub fn record_latency(&mut self, latency: Duration) {
+        self.received += 1;
+        self.latencies
+            .push(latency.as_micros().try_into().unwrap_or(u64::MAX));
+    }
+
+    pub fn record_error(&mut self, kind: &'static str) {
+        *self.errors.entry(kind).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        self.sent += other.sent;
+        self.received += other.received;
+        self.latencies.extend(other.latencies);
+        for (kind, count) in other.errors {
+            *self.errors.entry(kind).or_default() += count;
+        }
+    }
+}
+
+/// The outcome of a run, printed once it's over
+#[derive(Debug)]
+pub struct Report {
+    stats: Stats,
+    elapsed: Duration,
+}
+
+impl Report {
+    pub fn new(mut stats: Stats, elapsed: Duration) -> Self {
+        stats.latencies.sort_unstable();
+        Self { stats, elapsed }
+    }
+
+    /// The latency `percentile` percent of broadcasts arrived within, by nearest rank
+    pub fn latency(&self, percentile: f64) -> Option<Duration> {
+        let latencies = &self.stats.latencies;
+        let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies
+            .get(rank.clamp(1, latencies.len().max(1)) - 1)
+            .map(|&micros| Duration::from_micros(micros))
+    }
+
+    /// How many errors of any kind happened
+    pub fn error_count(&self) -> u64 {
+        self.stats.errors.values().sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "Sent {} broadcasts ({:.1}/s), received {} ({:.1}/s) over {:.1}s",
+            self.stats.sent,
+            self.stats.sent as f64 / secs,
+            self.stats.received,
+            self.stats.received as f64 / secs,
+            secs
+        )?;
+        match self.latency(100.0) {
+            Some(max) => {
+                write!(f, "Latency")?;
+                for percentile in PERCENTILES {
+                    let latency = self.latency(percentile).unwrap_or(max);
+                    write!(f, " p{percentile} {latency:.2?}")?;
+                }
+                writeln!(f, " max {max:.2?}")?;
+            }
+            None => writeln!(f, "No broadcasts arrived")?,
+        }
+        if self.stats.errors.is_empty() {
+            write!(f, "No errors")
+        } else {
+            write!(f, "{} errors:", self.error_count())?;
+            for (kind, count) in &self.stats.errors {
+                write!(f, " {kind} {count}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod latency {
+    use super::*;
+
+    #[test]
+    fn takes_percentiles_by_nearest_rank() {
+        let mut stats = Stats::default();
+        for millis in (1..=100).rev() {
+            stats.record_latency(Duration::from_millis(millis));
+        }
+        let report = Report::new(stats, Duration::from_secs(1));
+
+        assert_eq!(report.latency(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(report.latency(99.9), Some(Duration::from_millis(100)));
+        assert_eq!(report.latency(0.0), Some(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn has_none_without_broadcasts() {
+        let report = Report::new(Stats::default(), Duration::from_secs(1));
+
+        assert_eq!(report.latency(50.0), None);
+    }
+}
+
+#[cfg(test)]
+mod merge {
+    use super::*;
+
+    #[test]
+    fn adds_up_counts_and_errors() {
+        let mut total = Stats::default();
+        for _ in 0..2 {
+            let mut stats = Stats {
+                sent: 3,
+                ..Default::default()
+            };
+            stats.record_latency(Duration::from_millis(1));
+            stats.record_error("closed_early");
+            total.merge(stats);
+        }
+
+        assert_eq!((total.sent, total.received), (6, 2));
+        assert_eq!(Report::new(total, Duration::from_secs(1)).error_count(), 2);
+    }
+}
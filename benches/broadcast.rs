@@ -0,0 +1,48 @@
+use std::borrow::Cow;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::json;
+use wormhole::game::Broadcaster;
+use wormhole::protocol::ServerMessage;
+
+fn broadcast_fanout(c: &mut Criterion) {
+    let payload = json!({
+        "tick": 1024,
+        "entities": (0..32)
+            .map(|i| json!({ "id": i, "x": i * 3, "y": i * 7, "state": "moving" }))
+            .collect::<Vec<_>>(),
+    });
+
+    let mut group = c.benchmark_group("broadcaster/broadcast");
+    for connections in [2, 16, 100] {
+        group.throughput(Throughput::Elements(connections as u64));
+        group.bench_with_input(
+            BenchmarkId::new("connections", connections),
+            &connections,
+            |b, &connections| {
+                let broadcaster = Broadcaster::default();
+                let mut queues: Vec<_> =
+                    (0..connections).map(|_| broadcaster.subscribe()).collect();
+                let (from, _) = queues[0];
+
+                b.iter(|| {
+                    let message = ServerMessage::Broadcast {
+                        from,
+                        from_player: None,
+                        payload: Cow::Borrowed(&payload),
+                        seq: None,
+                    };
+                    broadcaster.broadcast(&message).unwrap();
+                    // Drain the queues so they don't grow across iterations
+                    for (_, queue) in queues.iter_mut() {
+                        queue.try_recv().unwrap();
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broadcast_fanout);
+criterion_main!(benches);
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use wormhole::game::{RoomId, RoomRegistry};
+
+const SHARD_COUNT: usize = 16;
+
+fn populated_registry(room_count: usize) -> (RoomRegistry, Vec<RoomId>) {
+    let registry = RoomRegistry::new(SHARD_COUNT);
+    let ids = (0..room_count)
+        .map(|_| registry.create_room().unwrap())
+        .collect();
+    (registry, ids)
+}
+
+fn room_creation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry/create_room");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("empty", |b| {
+        b.iter_batched(
+            || RoomRegistry::new(SHARD_COUNT),
+            |registry| registry.create_room().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("10k_rooms", |b| {
+        let (registry, _) = populated_registry(10_000);
+        b.iter(|| registry.create_room().unwrap())
+    });
+    group.finish();
+}
+
+fn lookup_under_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry/get_room_for_id");
+    for contending_threads in [0, 4, 16] {
+        group.bench_with_input(
+            BenchmarkId::new("contending_threads", contending_threads),
+            &contending_threads,
+            |b, &contending_threads| {
+                let (registry, ids) = populated_registry(10_000);
+                let registry = Arc::new(registry);
+                let running = Arc::new(AtomicBool::new(true));
+
+                // Background threads hammer the registry with a mix of creations
+                // and lookups while the measured thread performs lookups
+                let contenders: Vec<_> = (0..contending_threads)
+                    .map(|thread| {
+                        let registry = registry.clone();
+                        let running = running.clone();
+                        let ids = ids.clone();
+                        thread::spawn(move || {
+                            let mut i = thread;
+                            while running.load(Ordering::Relaxed) {
+                                if i % 8 == 0 {
+                                    let id = registry.create_room().unwrap();
+                                    registry.delete_room(id);
+                                } else {
+                                    registry.get_room_for_id(ids[i % ids.len()]);
+                                }
+                                i += 1;
+                            }
+                        })
+                    })
+                    .collect();
+
+                b.iter_custom(|iterations| {
+                    let start = Instant::now();
+                    for i in 0..iterations as usize {
+                        registry.get_room_for_id(ids[i % ids.len()]);
+                    }
+                    start.elapsed()
+                });
+
+                running.store(false, Ordering::Relaxed);
+                for contender in contenders {
+                    contender.join().unwrap();
+                }
+            },
+        );
+    }
+    group.finish();
+}
+
+fn deletion_sweep(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry/delete_room");
+    for room_count in [100, 1_000, 10_000] {
+        group.throughput(Throughput::Elements(room_count as u64));
+        group.bench_with_input(
+            BenchmarkId::new("sweep", room_count),
+            &room_count,
+            |b, &room_count| {
+                b.iter_batched(
+                    || populated_registry(room_count),
+                    |(registry, ids)| {
+                        for id in ids {
+                            registry.delete_room(id);
+                        }
+                        registry
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = room_creation, lookup_under_contention, deletion_sweep
+}
+criterion_main!(benches);
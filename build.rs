@@ -0,0 +1,11 @@
+//! Generates the gRPC service from its protobuf definition, parsing it with protox so
+//! building doesn't need protoc installed
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let file_descriptors = protox::compile(["wormhole/v1/rooms.proto"], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)?;
+    println!("cargo:rerun-if-changed=proto");
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+/// A problem response, describing why the server turned a request down
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Problem {
+    /// A URI reference identifying the kind of problem, such as `/problems/banned`
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// A short summary of the kind of problem
+    pub title: String,
+    /// An explanation specific to this occurrence of the problem
+    pub detail: Option<String>,
+}
+
+/// Raised when a request can't be made or the server turns it down
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("The server URL is invalid: {0}")]
+    InvalidUrl(String),
+    #[error("The server couldn't be reached: {0}")]
+    Unreachable(#[source] reqwest::Error),
+    /// The server answered with an unsuccessful status, with a problem describing why when
+    /// it sent one
+    #[error("{status}{}", describe(.problem))]
+    Status {
+        status: reqwest::StatusCode,
+        problem: Option<Problem>,
+    },
+    #[error("The server's response couldn't be read: {0}")]
+    InvalidResponse(String),
+    #[error("The room couldn't be created: {reason}")]
+    CreationFailed { reason: String },
+}
+
+fn describe(problem: &Option<Problem>) -> String {
+    match problem {
+        Some(Problem {
+            title,
+            detail: Some(detail),
+            ..
+        }) => format!(": {title}: {detail}"),
+        Some(Problem { title, .. }) => format!(": {title}"),
+        None => String::new(),
+    }
+}
+
+impl ClientError {
+    /// The status the server answered with, if it answered
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the server said there's no such room, ticket, tournament or ban
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(reqwest::StatusCode::NOT_FOUND)
+    }
+
+    /// The kind of problem the server described, such as `/problems/banned`
+    pub fn problem_type(&self) -> Option<&str> {
+        match self {
+            Self::Status {
+                problem: Some(problem),
+                ..
+            } => Some(&problem.problem_type),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() || e.is_body() {
+            Self::InvalidResponse(e.to_string())
+        } else {
+            Self::Unreachable(e)
+        }
+    }
+}
+
+pub type Result<T, E = ClientError> = std::result::Result<T, E>;
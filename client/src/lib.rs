@@ -0,0 +1,645 @@
+//! A typed client for the REST and admin APIs of wormhole servers
+//!
+//! Services such as matchmakers and tournament runners drive the server through
+//! [WormholeClient] instead of hand-crafting requests. It sends the API token as a bearer
+//! token, turns problem responses into [ClientError]s, and retries requests the server
+//! turned away without acting on them, waiting as long as its `Retry-After` header asks.
+//!
+//! Requests are retried when the server couldn't be reached, and on `429` and `503`
+//! responses. Requests that are safe to repeat, `GET`, `PUT` and `DELETE` ones, are also
+//! retried when they time out and on `502` and `504` responses, as the server may have acted
+//! on them without answering.
+
+mod error;
+pub mod types;
+
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, LOCATION, RETRY_AFTER};
+use reqwest::{Method, Request, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use wormhole_protocol::{Invite, PlayerId, RoomId, RoomSummary};
+
+pub use error::{ClientError, Problem, Result};
+use types::*;
+
+/// How long a request may take unless the client is built with another timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a queued room creation request is polled for its outcome
+const CREATION_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many times a request is attempted and how long to wait in between
+#[derive(Debug, PartialEq, Clone)]
+pub struct RetryPolicy {
+    /// How many times a request is sent at most, 1 to never retry
+    pub attempts: u32,
+    /// How long to wait before the first retry, doubling for every retry after it
+    pub backoff: Duration,
+    /// The longest wait between attempts, requests the server asks to retry later than this
+    /// failing straight away instead
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before sending the request again after its `attempt`th failure, given
+    /// how long the server asked to wait, `None` to give up
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        match retry_after {
+            Some(wait) if wait > self.max_backoff => None,
+            Some(wait) => Some(wait),
+            None => Some(
+                self.backoff
+                    .saturating_mul(2_u32.saturating_pow(attempt - 1))
+                    .min(self.max_backoff),
+            ),
+        }
+    }
+}
+
+/// How long the response asks to wait before retrying, when it gives a number of seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Whether repeating the request has the same effect as sending it once
+fn is_idempotent(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::PUT, Method::DELETE].contains(method)
+}
+
+/// The outcome of a queued room creation request that failed
+#[derive(Debug, Deserialize)]
+struct CreationFailedBody {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceBody {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationBody {
+    closed_connections: usize,
+}
+
+/// Builds a [WormholeClient]
+#[derive(Debug)]
+pub struct ClientBuilder {
+    url: String,
+    admin_url: Option<String>,
+    token: Option<String>,
+    timeout: Duration,
+    retry: RetryPolicy,
+}
+
+impl ClientBuilder {
+    /// Sends admin requests to `admin_url`, for servers serving their admin endpoints on a
+    /// separate listener
+    pub fn admin_url(mut self, admin_url: impl Into<String>) -> Self {
+        self.admin_url = Some(admin_url.into());
+        self
+    }
+
+    /// Sends the API token as a bearer token with every request
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// How long each attempt at a request may take
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> Result<WormholeClient> {
+        let base = |url: &str| {
+            Url::parse(&format!("{}/", url.trim_end_matches('/')))
+                .map_err(|e| ClientError::InvalidUrl(format!("{url}: {e}")))
+        };
+        Ok(WormholeClient {
+            http: reqwest::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .map_err(ClientError::Unreachable)?,
+            url: base(&self.url)?,
+            admin_url: base(self.admin_url.as_deref().unwrap_or(&self.url))?,
+            token: self.token,
+            retry: self.retry,
+        })
+    }
+}
+
+/// Talks to a server's REST and admin APIs on behalf of another service
+#[derive(Debug, Clone)]
+pub struct WormholeClient {
+    http: reqwest::Client,
+    /// The server's URL, which the API is served under
+    url: Url,
+    admin_url: Url,
+    token: Option<String>,
+    retry: RetryPolicy,
+}
+
+impl WormholeClient {
+    /// Creates a client for the server at `url`, such as `https://wormhole.example.com`
+    pub fn builder(url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            url: url.into(),
+            admin_url: None,
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn api(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = self
+            .url
+            .join(&format!("api/v1{path}"))
+            .expect("API paths are always valid");
+        self.request(method, url)
+    }
+
+    fn admin(&self, method: Method, path: &str) -> RequestBuilder {
+        let url = self
+            .admin_url
+            .join(&format!("api/v1{path}"))
+            .expect("API paths are always valid");
+        self.request(method, url)
+    }
+
+    /// Sends the request, retrying as the [RetryPolicy] allows, and returns whatever the server
+    /// last answered with
+    async fn execute(&self, request: RequestBuilder) -> Result<Response> {
+        let request = request.build().map_err(ClientError::Unreachable)?;
+        let idempotent = is_idempotent(request.method());
+        let mut attempt = 1;
+        loop {
+            let outcome = self.http.execute(clone(&request)).await;
+            let retryable = match &outcome {
+                Ok(response) => match response.status() {
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                        Some(retry_after(response.headers()))
+                    }
+                    StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT if idempotent => {
+                        Some(retry_after(response.headers()))
+                    }
+                    _ => None,
+                },
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => Some(None),
+                Err(_) => None,
+            };
+            match retryable.and_then(|retry_after| self.retry.delay(attempt, retry_after)) {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Ok(outcome?),
+            }
+        }
+    }
+
+    /// Sends the request, turning any unsuccessful response into an error describing it
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = self.execute(request).await?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(failure(response).await)
+        }
+    }
+
+    async fn send_json<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        Ok(self.send(request).await?.json().await?)
+    }
+
+    /// The URL in the response's `Location` header, resolved against the server's
+    fn location(&self, response: &Response) -> Result<Url> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| ClientError::InvalidResponse("No Location header".to_owned()))?;
+        self.url
+            .join(location)
+            .map_err(|e| ClientError::InvalidResponse(format!("Invalid Location header: {e}")))
+    }
+
+    /// The room whose WebSocket URL is in the response's `Location` header
+    fn created_room(&self, response: &Response) -> Result<CreatedRoom> {
+        let url = self.location(response)?;
+        let room_id = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| ClientError::InvalidResponse(format!("No room ID in {url}")))?;
+        Ok(CreatedRoom {
+            room_id,
+            url: url.to_string(),
+        })
+    }
+
+    /// Every active room, including those hosted by other instances sharing the lobby,
+    /// narrowed down to the region if one is given
+    pub async fn list_rooms(&self, region: Option<&str>) -> Result<Vec<RoomSummary>> {
+        let mut request = self.api(Method::GET, "/rooms/");
+        if let Some(region) = region {
+            request = request.query(&[("region", region)]);
+        }
+        self.send_json(request).await
+    }
+
+    pub async fn room(&self, id: RoomId) -> Result<Room> {
+        self.send_json(self.api(Method::GET, &format!("/rooms/{id}")))
+            .await
+    }
+
+    /// Creates a room, hosted in the region if one is given, waiting for the creation queue
+    /// when the server is busy
+    pub async fn create_room(&self, region: Option<&str>) -> Result<CreatedRoom> {
+        let mut request = self.api(Method::POST, "/rooms/");
+        if let Some(region) = region {
+            request = request.query(&[("region", region)]);
+        }
+        let response = self.send(request).await?;
+        if response.status() != StatusCode::ACCEPTED {
+            return self.created_room(&response);
+        }
+
+        let ticket = self.location(&response)?;
+        loop {
+            tokio::time::sleep(CREATION_POLL_INTERVAL).await;
+            let response = self
+                .execute(self.request(Method::GET, ticket.clone()))
+                .await?;
+            match response.status() {
+                StatusCode::OK => return self.created_room(&response),
+                StatusCode::ACCEPTED => {}
+                StatusCode::INTERNAL_SERVER_ERROR => {
+                    let CreationFailedBody { reason } = response.json().await?;
+                    return Err(ClientError::CreationFailed { reason });
+                }
+                _ => return Err(failure(response).await),
+            }
+        }
+    }
+
+    /// Creates a rematch of a room, with the same owner, settings and teams
+    pub async fn rematch_room(&self, id: RoomId) -> Result<CreatedRoom> {
+        let response = self
+            .send(self.api(Method::POST, &format!("/rooms/{id}/rematch")))
+            .await?;
+        self.created_room(&response)
+    }
+
+    pub async fn delete_room(&self, id: RoomId) -> Result<()> {
+        self.send(self.api(Method::DELETE, &format!("/rooms/{id}")))
+            .await?;
+        Ok(())
+    }
+
+    /// Mints an invite to a private room, lasting an hour with unlimited uses unless told
+    /// otherwise
+    pub async fn create_invite(
+        &self,
+        id: RoomId,
+        ttl: Option<Duration>,
+        max_uses: Option<u32>,
+    ) -> Result<Invite> {
+        let request = self
+            .api(Method::POST, &format!("/rooms/{id}/invites"))
+            .json(&json!({
+                "ttl_secs": ttl.map(|ttl| ttl.as_secs()),
+                "max_uses": max_uses,
+            }));
+        self.send_json(request).await
+    }
+
+    /// Creates a private room for a match, with a seat reserved in it for each player
+    pub async fn create_match(&self, request: &MatchRequest) -> Result<Match> {
+        self.send_json(self.api(Method::POST, "/matches").json(request))
+            .await
+    }
+
+    /// Queues a party to be matched with others wanting to play the same game type
+    pub async fn enqueue(&self, game_type: &str, players: &[PlayerId]) -> Result<QueueTicket> {
+        let request = self
+            .api(Method::POST, "/matchmaking/queue")
+            .json(&json!({ "game_type": game_type, "players": players }));
+        self.send_json(request).await
+    }
+
+    pub async fn queue_status(&self, ticket: QueueTicket) -> Result<QueueStatus> {
+        self.send_json(self.api(Method::GET, &format!("/matchmaking/queue/{ticket}")))
+            .await
+    }
+
+    pub async fn leave_queue(&self, ticket: QueueTicket) -> Result<()> {
+        self.send(self.api(Method::DELETE, &format!("/matchmaking/queue/{ticket}")))
+            .await?;
+        Ok(())
+    }
+
+    /// Holds a single elimination tournament, seeding the players by their rating
+    pub async fn create_tournament(
+        &self,
+        game_type: &str,
+        players: &[PlayerId],
+    ) -> Result<Tournament> {
+        let request = self
+            .api(Method::POST, "/tournaments/")
+            .json(&json!({ "game_type": game_type, "players": players }));
+        self.send_json(request).await
+    }
+
+    pub async fn tournament(&self, id: TournamentId) -> Result<Tournament> {
+        self.send_json(self.api(Method::GET, &format!("/tournaments/{id}")))
+            .await
+    }
+
+    pub async fn delete_tournament(&self, id: TournamentId) -> Result<()> {
+        self.send(self.api(Method::DELETE, &format!("/tournaments/{id}")))
+            .await?;
+        Ok(())
+    }
+
+    /// Reserves seats for a bracket match's players in the room it's played in
+    pub async fn seat_tournament_match(
+        &self,
+        id: TournamentId,
+        round: usize,
+        index: usize,
+    ) -> Result<Match> {
+        let path = format!("/tournaments/{id}/matches/{round}/{index}/seats");
+        self.send_json(self.api(Method::POST, &path)).await
+    }
+
+    /// Reports who won a bracket match, answering with the bracket as it now stands
+    pub async fn report_tournament_match(
+        &self,
+        id: TournamentId,
+        round: usize,
+        index: usize,
+        winner: PlayerId,
+    ) -> Result<Tournament> {
+        let path = format!("/tournaments/{id}/matches/{round}/{index}/result");
+        let request = self
+            .api(Method::POST, &path)
+            .json(&json!({ "winner": winner }));
+        self.send_json(request).await
+    }
+
+    /// Rates the players of a finished game, answering with their new ratings
+    pub async fn record_result(&self, result: &GameResult) -> Result<Vec<PlayerRating>> {
+        self.send_json(self.api(Method::POST, "/results").json(result))
+            .await
+    }
+
+    pub async fn bans(&self) -> Result<Vec<Ban>> {
+        self.send_json(self.admin(Method::GET, "/bans/")).await
+    }
+
+    /// Keeps the player or network off the server, for good unless a duration is given
+    pub async fn ban(
+        &self,
+        target: BanTarget,
+        reason: &str,
+        duration: Option<Duration>,
+    ) -> Result<Ban> {
+        let request = self.admin(Method::POST, "/bans/").json(&json!({
+            "target": target,
+            "reason": reason,
+            "duration_secs": duration.map(|duration| duration.as_secs()),
+        }));
+        self.send_json(request).await
+    }
+
+    pub async fn lift_ban(&self, id: BanId) -> Result<()> {
+        self.send(self.admin(Method::DELETE, &format!("/bans/{id}")))
+            .await?;
+        Ok(())
+    }
+
+    /// The bans keeping players out of a room and its rematches
+    pub async fn room_bans(&self, id: RoomId) -> Result<Vec<RoomBan>> {
+        self.send_json(self.admin(Method::GET, &format!("/admin/rooms/{id}/bans")))
+            .await
+    }
+
+    pub async fn lift_room_ban(&self, id: RoomId, player_id: PlayerId) -> Result<()> {
+        let path = format!("/admin/rooms/{id}/bans/{player_id}");
+        self.send(self.admin(Method::DELETE, &path)).await?;
+        Ok(())
+    }
+
+    /// Revokes every session of the player, answering with how many connections were closed
+    pub async fn revoke_sessions(&self, player_id: PlayerId) -> Result<usize> {
+        let path = format!("/players/{player_id}/sessions");
+        let body: RevocationBody = self.send_json(self.admin(Method::DELETE, &path)).await?;
+        Ok(body.closed_connections)
+    }
+
+    pub async fn maintenance(&self) -> Result<bool> {
+        let body: MaintenanceBody = self
+            .send_json(self.admin(Method::GET, "/maintenance"))
+            .await?;
+        Ok(body.enabled)
+    }
+
+    pub async fn set_maintenance(&self, enabled: bool) -> Result<()> {
+        let request = self
+            .admin(Method::PUT, "/maintenance")
+            .json(&json!({ "enabled": enabled }));
+        self.send(request).await?;
+        Ok(())
+    }
+}
+
+/// A copy of the request to send again, which is always possible as bodies are never streamed
+fn clone(request: &Request) -> Request {
+    request
+        .try_clone()
+        .expect("Requests without streamed bodies can always be cloned")
+}
+
+/// The error describing an unsuccessful response
+async fn failure(response: Response) -> ClientError {
+    let status = response.status();
+    ClientError::Status {
+        status,
+        problem: response.json().await.ok(),
+    }
+}
+
+#[cfg(test)]
+mod delay {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_limit() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.delay(attempt, None)).collect();
+
+        assert_eq!(
+            delays,
+            [100, 200, 300, 300]
+                .map(|millis| Some(Duration::from_millis(millis)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn waits_as_long_as_the_server_asks() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(60))), None);
+    }
+}
+
+#[cfg(test)]
+mod send {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves each of the responses in turn, one per connection, keeping the head of every
+    /// request it was sent
+    async fn serve(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(stream.read_u8().await.unwrap());
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(head).unwrap());
+                stream.write_all(response.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn client(url: &str) -> WormholeClient {
+        WormholeClient::builder(url)
+            .token("secret")
+            .retry(RetryPolicy {
+                attempts: 2,
+                backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_secs(1),
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_when_the_server_is_unavailable() {
+        let (url, requests) = serve(vec![
+            response("503 Service Unavailable", "Retry-After: 0\r\n", ""),
+            response("200 OK", "", r#"{"enabled":true}"#),
+        ])
+        .await;
+
+        assert!(client(&url).maintenance().await.unwrap());
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("GET /api/v1/maintenance "));
+        assert!(requests[1].contains("authorization: Bearer secret"));
+    }
+
+    #[tokio::test]
+    async fn describes_problems() {
+        let body = r#"{"type":"/problems/banned","title":"Banned","status":403}"#;
+        let (url, requests) = serve(vec![response("403 Forbidden", "", body)]).await;
+
+        let error = client(&url)
+            .enqueue("chess", &[1.into()])
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.status(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(error.problem_type(), Some("/problems/banned"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_the_created_room_from_its_location() {
+        let (url, _) = serve(vec![response(
+            "201 Created",
+            "Location: /ws/00000000-0000-0000-0000-000000000007\r\n",
+            "",
+        )])
+        .await;
+
+        let room = client(&url).create_room(None).await.unwrap();
+
+        assert_eq!(room.room_id, 7_u128.into());
+        assert_eq!(
+            room.url,
+            format!("{url}/ws/00000000-0000-0000-0000-000000000007")
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! The bodies sent to and received from the server, mirroring its OpenAPI document
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use wormhole_protocol::{PlayerId, RoomId};
+
+/// An ID that identifies a party's place in a matchmaking queue
+pub type QueueTicket = Uuid;
+
+/// An ID that identifies a [tournament][Tournament]
+pub type TournamentId = Uuid;
+
+/// An ID that identifies a [ban][Ban] so it can be lifted
+pub type BanId = Uuid;
+
+/// A room that was just created
+#[derive(Debug, PartialEq, Clone)]
+pub struct CreatedRoom {
+    pub room_id: RoomId,
+    /// The room's WebSocket URL, which may be on another instance sharing the lobby
+    pub url: String,
+}
+
+/// A single room
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Room {
+    pub id: RoomId,
+    pub name: Option<String>,
+    pub private: bool,
+    /// The first authenticated player to join the room
+    pub owner: Option<PlayerId>,
+    /// How many distinct authenticated players are connected
+    pub player_count: usize,
+    /// How many connections are open, anonymous ones included
+    pub connection_count: usize,
+    /// How many seconds are left until the room is deleted unless someone joins it, if it's
+    /// waiting for players
+    #[serde(default)]
+    pub deletes_in_secs: Option<u64>,
+    /// How long each turn may take, if the room is turn-based
+    #[serde(default)]
+    pub turn_timeout_secs: Option<u64>,
+    /// Whose turn it is, once a turn-based room's turn has been passed
+    #[serde(default)]
+    pub turn: Option<Turn>,
+}
+
+/// Whose turn it is in a turn-based room, and until when
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Turn {
+    pub player_id: PlayerId,
+    /// Seconds since the Unix epoch, the room is deleted unless the turn is passed on by then
+    pub deadline: u64,
+}
+
+/// A request to place players in a room of their own
+#[derive(Debug, Default, PartialEq, Clone, Serialize)]
+pub struct MatchRequest {
+    /// The players to reserve seats for
+    pub players: Vec<PlayerId>,
+    /// The team each player is on, keyed by player, for matches played in teams
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub teams: HashMap<PlayerId, String>,
+    /// How long the players have to take their seats, an hour if `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+/// A seat reserved for a player in a match's room
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Seat {
+    pub player_id: PlayerId,
+    pub team: Option<String>,
+    /// Passed in the `invite` query parameter when the player joins the room
+    pub token: String,
+    /// The URL the player joins the room with, token included
+    pub url: String,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// The room created for a match, with a seat in it for each player
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Match {
+    pub room_id: RoomId,
+    pub seats: Vec<Seat>,
+}
+
+/// Where a queued party stands
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum QueueStatus {
+    Waiting {
+        game_type: String,
+        waited_secs: u64,
+    },
+    /// The party's seats in the room its match is played in
+    Matched {
+        room_id: RoomId,
+        seats: Vec<Seat>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Running,
+    Finished { champion: PlayerId },
+}
+
+/// A match in a tournament's bracket
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct BracketMatch {
+    /// The players meeting in the match, absent while the match they come from is undecided,
+    /// or for a bye in the first round
+    pub players: [Option<PlayerId>; 2],
+    pub winner: Option<PlayerId>,
+    /// The room the match is played in, once both of its players are known
+    pub room_id: Option<RoomId>,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Tournament {
+    pub id: TournamentId,
+    pub game_type: String,
+    #[serde(flatten)]
+    pub status: TournamentStatus,
+    /// The bracket's matches round by round, the first round first and the final last
+    pub rounds: Vec<Vec<BracketMatch>>,
+}
+
+/// The outcome of a game, to rate its players by
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct GameResult {
+    pub game_type: String,
+    /// The players in each place, from first to last, with teammates and tied players
+    /// sharing a place
+    pub standings: Vec<Vec<PlayerId>>,
+}
+
+/// A player's rating in one game type
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct PlayerRating {
+    pub player_id: PlayerId,
+    pub rating: f64,
+    /// How many rated games the rating is based on
+    pub games: u32,
+}
+
+/// Who or what a [ban][Ban] keeps out
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BanTarget {
+    Player {
+        player_id: PlayerId,
+    },
+    /// Every address in the network, given in CIDR notation
+    Network {
+        network: String,
+    },
+}
+
+/// A player or network kept off the server
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct Ban {
+    pub id: BanId,
+    pub target: BanTarget,
+    pub reason: String,
+    /// Who issued the ban, such as `api` or the moderator's player id
+    pub issued_by: String,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    /// Seconds since the Unix epoch, `None` for permanent bans
+    pub expires_at: Option<u64>,
+}
+
+/// A player kept out of a room and its rematches
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct RoomBan {
+    /// The room the line of rematches the ban applies to started with
+    pub lineage: RoomId,
+    pub player_id: PlayerId,
+    /// Who issued the ban, such as `api` or the owner's player id
+    pub issued_by: String,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+}
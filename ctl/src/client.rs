@@ -0,0 +1,297 @@
+use std::io::BufRead;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use wormhole_protocol::{PlayerId, RoomId};
+
+/// How long a request may take, other than following events
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The parts of a problem response worth showing an operator
+#[derive(Debug, Deserialize)]
+struct Problem {
+    title: String,
+    detail: Option<String>,
+}
+
+/// A single Server-Sent Event
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub name: String,
+    pub data: String,
+}
+
+/// Reads Server-Sent Events from the stream until it ends, skipping comments
+pub fn read_events(stream: impl BufRead, mut on_event: impl FnMut(Event)) -> Result<()> {
+    let mut name = String::new();
+    let mut data = Vec::new();
+    for line in stream.lines() {
+        let line = line.context("The event stream broke off")?;
+        if line.is_empty() {
+            if !data.is_empty() {
+                on_event(Event {
+                    name: std::mem::take(&mut name),
+                    data: data.join("\n"),
+                });
+                data.clear();
+            }
+            name.clear();
+        } else if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim_start().to_owned();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.trim_start().to_owned());
+        }
+    }
+    Ok(())
+}
+
+/// Talks to a server's REST API on behalf of an operator
+pub struct AdminClient {
+    http: Client,
+    api_url: String,
+    admin_url: String,
+    token: Option<String>,
+}
+
+impl AdminClient {
+    /// Creates a client for the server at `url`, whose admin endpoints are served at
+    /// `admin_url` if it has a separate admin listener
+    pub fn new(url: &str, admin_url: Option<&str>, token: Option<String>) -> Result<Self> {
+        let base = |url: &str| format!("{}/api/v1", url.trim_end_matches('/'));
+        Ok(Self {
+            // Following events takes as long as the operator wants it to
+            http: Client::builder().timeout(None).build()?,
+            api_url: base(url),
+            admin_url: base(admin_url.unwrap_or(url)),
+            token,
+        })
+    }
+
+    fn request(&self, method: Method, url: String) -> RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn api(&self, method: Method, path: &str) -> RequestBuilder {
+        self.request(method, format!("{}{path}", self.api_url))
+            .timeout(REQUEST_TIMEOUT)
+    }
+
+    fn admin(&self, method: Method, path: &str) -> RequestBuilder {
+        self.request(method, format!("{}{path}", self.admin_url))
+            .timeout(REQUEST_TIMEOUT)
+    }
+
+    /// Sends the request, turning any unsuccessful response into an error describing it
+    fn send(request: RequestBuilder) -> Result<Response> {
+        let response = request.send().context("The server couldn't be reached")?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        match response.json::<Problem>() {
+            Ok(Problem {
+                title,
+                detail: Some(detail),
+            }) => bail!("{status}: {title}: {detail}"),
+            Ok(Problem { title, .. }) => bail!("{status}: {title}"),
+            Err(_) => bail!("{status}"),
+        }
+    }
+
+    pub fn list_rooms(&self, region: Option<&str>) -> Result<Vec<Value>> {
+        let mut request = self.api(Method::GET, "/rooms/");
+        if let Some(region) = region {
+            request = request.query(&[("region", region)]);
+        }
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn room(&self, id: RoomId) -> Result<Value> {
+        Ok(Self::send(self.api(Method::GET, &format!("/rooms/{id}")))?.json()?)
+    }
+
+    pub fn close_room(&self, id: RoomId) -> Result<()> {
+        Self::send(self.api(Method::DELETE, &format!("/rooms/{id}")))?;
+        Ok(())
+    }
+
+    pub fn force_close_room(&self, id: RoomId) -> Result<Value> {
+        let request = self.admin(Method::POST, &format!("/admin/rooms/{id}/force-close"));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn room_bans(&self, id: RoomId) -> Result<Vec<Value>> {
+        let request = self.admin(Method::GET, &format!("/admin/rooms/{id}/bans"));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn lift_room_ban(&self, id: RoomId, player_id: PlayerId) -> Result<()> {
+        Self::send(self.admin(
+            Method::DELETE,
+            &format!("/admin/rooms/{id}/bans/{player_id}"),
+        ))?;
+        Ok(())
+    }
+
+    pub fn ban_player(
+        &self,
+        player_id: PlayerId,
+        reason: &str,
+        duration_secs: Option<u64>,
+    ) -> Result<Value> {
+        let request = self.admin(Method::POST, "/bans/").json(&json!({
+            "target": { "type": "player", "player_id": player_id },
+            "reason": reason,
+            "duration_secs": duration_secs,
+        }));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn player(&self, id: PlayerId) -> Result<Value> {
+        Ok(Self::send(self.admin(Method::GET, &format!("/admin/players/{id}")))?.json()?)
+    }
+
+    pub fn find_players(&self, display_name: &str) -> Result<Vec<Value>> {
+        let request = self
+            .admin(Method::GET, "/admin/players")
+            .query(&[("display_name", display_name)]);
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn disconnect_player(&self, id: PlayerId) -> Result<Value> {
+        let request = self.admin(Method::POST, &format!("/admin/players/{id}/disconnect"));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn ban_and_disconnect_player(
+        &self,
+        id: PlayerId,
+        reason: &str,
+        duration_secs: Option<u64>,
+    ) -> Result<Value> {
+        let request = self
+            .admin(Method::POST, &format!("/admin/players/{id}/ban"))
+            .json(&json!({ "reason": reason, "duration_secs": duration_secs }));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn wipe_display_name(&self, id: PlayerId) -> Result<()> {
+        Self::send(self.admin(Method::DELETE, &format!("/admin/players/{id}/display-name")))?;
+        Ok(())
+    }
+
+    pub fn reports(&self, status: &str) -> Result<Vec<Value>> {
+        let request = self
+            .admin(Method::GET, "/admin/reports")
+            .query(&[("status", status)]);
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn report(&self, id: &str) -> Result<Value> {
+        Ok(Self::send(self.admin(Method::GET, &format!("/admin/reports/{id}")))?.json()?)
+    }
+
+    pub fn act_on_report(&self, id: &str, action: Value) -> Result<Value> {
+        let request = self
+            .admin(Method::POST, &format!("/admin/reports/{id}/actions"))
+            .json(&action);
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn resolve_report(&self, id: &str, note: Option<&str>) -> Result<Value> {
+        let request = self
+            .admin(Method::POST, &format!("/admin/reports/{id}/resolution"))
+            .json(&json!({ "note": note }));
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn announce(&self, text: &str, rooms: &[RoomId], regions: &[String]) -> Result<Value> {
+        let mut body = json!({ "text": text });
+        if !rooms.is_empty() {
+            body["rooms"] = json!(rooms);
+        }
+        if !regions.is_empty() {
+            body["regions"] = json!(regions);
+        }
+        let request = self.admin(Method::POST, "/admin/announcements").json(&body);
+        Ok(Self::send(request)?.json()?)
+    }
+
+    pub fn maintenance(&self) -> Result<bool> {
+        let body: Value = Self::send(self.admin(Method::GET, "/maintenance"))?.json()?;
+        Ok(body["enabled"].as_bool().unwrap_or_default())
+    }
+
+    pub fn set_maintenance(&self, enabled: bool) -> Result<()> {
+        let request = self
+            .admin(Method::PUT, "/maintenance")
+            .json(&json!({ "enabled": enabled }));
+        Self::send(request)?;
+        Ok(())
+    }
+
+    pub fn drain(&self) -> Result<Value> {
+        Ok(Self::send(self.admin(Method::POST, "/drain"))?.json()?)
+    }
+
+    /// Follows changes to the set of rooms until the server closes the stream
+    pub fn follow_room_events(&self, on_event: impl FnMut(Event)) -> Result<()> {
+        let request = self.request(Method::GET, format!("{}/rooms/events", self.api_url));
+        let response = Self::send(request)?;
+        read_events(std::io::BufReader::new(response), on_event)
+    }
+
+    /// Follows what happens in a room until it's deleted
+    pub fn watch_room(&self, id: RoomId, on_event: impl FnMut(Event)) -> Result<()> {
+        let url = format!("{}/admin/rooms/{id}/watch", self.admin_url);
+        let request = self.request(Method::GET, url);
+        let response = Self::send(request)?;
+        read_events(std::io::BufReader::new(response), on_event)
+    }
+}
+
+#[cfg(test)]
+mod read_events {
+    use super::*;
+
+    fn events(stream: &str) -> Vec<Event> {
+        let mut events = Vec::new();
+        read_events(stream.as_bytes(), |event| events.push(event)).unwrap();
+        events
+    }
+
+    #[test]
+    fn separates_events_by_blank_lines() {
+        assert_eq!(
+            events("event: room_created\ndata: {\"id\":1}\n\nevent: resync\ndata: {}\n\n"),
+            [
+                Event {
+                    name: "room_created".to_owned(),
+                    data: "{\"id\":1}".to_owned(),
+                },
+                Event {
+                    name: "resync".to_owned(),
+                    data: "{}".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(events(": heartbeat\n\n"), []);
+    }
+
+    #[test]
+    fn leaves_unfinished_events_out() {
+        assert_eq!(events("event: room_created\ndata: {}\n"), []);
+    }
+}
@@ -0,0 +1,313 @@
+//! Operates a wormhole server through its REST API, so incidents can be handled without
+//! hand-crafting requests
+//!
+//! The server is found through `--url` and, when its admin endpoints are served on a
+//! separate listener, `--admin-url`. The API token is read from `WORMHOLE_API_TOKEN`
+//! rather than passed as an argument, so it stays out of shell history.
+
+mod client;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use wormhole_protocol::{PlayerId, RoomId};
+
+use crate::client::AdminClient;
+
+const TOKEN_ENV_VAR: &str = "WORMHOLE_API_TOKEN";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "wormhole-ctl",
+    version,
+    about,
+    after_help = "The API token is read from WORMHOLE_API_TOKEN."
+)]
+struct Cli {
+    /// The server's URL
+    #[arg(long, env = "WORMHOLE_URL", default_value = "http://localhost:8080")]
+    url: String,
+    /// The admin listener's URL, if the server has one
+    #[arg(long, env = "WORMHOLE_ADMIN_URL")]
+    admin_url: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Lists, inspects and closes rooms
+    #[command(subcommand)]
+    Rooms(RoomsCommand),
+    /// Looks players up and acts on them wherever they're connected
+    #[command(subcommand)]
+    Players(PlayersCommand),
+    /// Reviews, acts on and resolves players' reports of each other
+    #[command(subcommand)]
+    Reports(ReportsCommand),
+    /// Bans a player from the whole server
+    Ban {
+        player_id: PlayerId,
+        #[arg(long)]
+        reason: String,
+        /// How long the ban lasts, permanent if omitted
+        #[arg(long)]
+        duration_secs: Option<u64>,
+    },
+    /// Shows a notice to every connected player, or only those in some rooms or regions
+    Announce {
+        text: String,
+        /// Only announce to this room, can be repeated
+        #[arg(long = "room")]
+        rooms: Vec<RoomId>,
+        /// Only announce to rooms hosted in this region, can be repeated
+        #[arg(long = "region")]
+        regions: Vec<String>,
+    },
+    /// Shows whether the server is in maintenance mode, or turns it on or off
+    Maintenance { state: Option<Toggle> },
+    /// Hands every room over to the other instances, ahead of taking the server down
+    Drain,
+    /// Prints rooms being created, updated and deleted as it happens
+    Events,
+}
+
+#[derive(Debug, Subcommand)]
+enum RoomsCommand {
+    /// Lists the IDs of every active room
+    List {
+        /// Only list rooms hosted in this region
+        #[arg(long)]
+        region: Option<String>,
+    },
+    /// Shows a room's details
+    Show { room_id: RoomId },
+    /// Disconnects everyone from a room and removes it
+    Close {
+        room_id: RoomId,
+        /// Remove the room at once, without waiting on its deletion, for rooms stuck open
+        #[arg(long)]
+        force: bool,
+    },
+    /// Prints the joins, departures and messages in a room as they happen, until it's deleted
+    Watch { room_id: RoomId },
+    /// Lists the players kept out of a room and its rematches
+    Bans { room_id: RoomId },
+    /// Lets a player back into a room and its rematches
+    Unban {
+        room_id: RoomId,
+        player_id: PlayerId,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PlayersCommand {
+    /// Shows a player's display name, connections and ban
+    Show { player_id: PlayerId },
+    /// Lists the connected players whose display name contains the text
+    Find { display_name: String },
+    /// Closes a player's connections, leaving them free to connect again
+    Disconnect { player_id: PlayerId },
+    /// Bans a player from the server and closes their connections
+    Ban {
+        player_id: PlayerId,
+        #[arg(long)]
+        reason: String,
+        /// How long the ban lasts, permanent if omitted
+        #[arg(long)]
+        duration_secs: Option<u64>,
+    },
+    /// Stops showing the display name a player's tokens give them
+    WipeName { player_id: PlayerId },
+}
+
+#[derive(Debug, Subcommand)]
+enum ReportsCommand {
+    /// Lists the reports waiting for a moderator, oldest first
+    List {
+        /// List the reports with this status instead, such as `actioned` or `resolved`
+        #[arg(long, default_value = "open")]
+        status: String,
+    },
+    /// Shows a report, along with the chat captured with it
+    Show { report_id: String },
+    /// Closes the reported player's connections
+    Disconnect { report_id: String },
+    /// Bans the reported player and closes their connections
+    Ban {
+        report_id: String,
+        /// The report's reason if omitted
+        #[arg(long)]
+        reason: Option<String>,
+        /// How long the ban lasts, permanent if omitted
+        #[arg(long)]
+        duration_secs: Option<u64>,
+    },
+    /// Stops showing the reported player's display name
+    WipeName { report_id: String },
+    /// Takes a report off the queue
+    Resolve {
+        report_id: String,
+        /// What was done about the report, for other moderators
+        #[arg(long)]
+        note: Option<String>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum Toggle {
+    On,
+    Off,
+}
+
+fn print_json(value: &serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let token = std::env::var(TOKEN_ENV_VAR).ok();
+    let client = AdminClient::new(&cli.url, cli.admin_url.as_deref(), token)?;
+
+    match cli.command {
+        Command::Rooms(RoomsCommand::List { region }) => {
+            for room in client.list_rooms(region.as_deref())? {
+                println!("{}", room["id"].as_str().unwrap_or_default());
+            }
+        }
+        Command::Rooms(RoomsCommand::Show { room_id }) => print_json(&client.room(room_id)?)?,
+        Command::Rooms(RoomsCommand::Close {
+            room_id,
+            force: false,
+        }) => {
+            client.close_room(room_id)?;
+            println!("Closed room {room_id}");
+        }
+        Command::Rooms(RoomsCommand::Close {
+            room_id,
+            force: true,
+        }) => {
+            let report = client.force_close_room(room_id)?;
+            println!(
+                "Force closed room {room_id}, disconnecting {} connections",
+                report["closed_connections"]
+            );
+        }
+        Command::Players(PlayersCommand::Show { player_id }) => {
+            print_json(&client.player(player_id)?)?
+        }
+        Command::Players(PlayersCommand::Find { display_name }) => {
+            for player in client.find_players(&display_name)? {
+                println!(
+                    "{} {}",
+                    player["player_id"].as_str().unwrap_or_default(),
+                    player["display_name"].as_str().unwrap_or_default()
+                );
+            }
+        }
+        Command::Players(PlayersCommand::Disconnect { player_id }) => {
+            let report = client.disconnect_player(player_id)?;
+            println!(
+                "Disconnected player {player_id} from {} connections",
+                report["closed_connections"]
+            );
+        }
+        Command::Players(PlayersCommand::Ban {
+            player_id,
+            reason,
+            duration_secs,
+        }) => print_json(&client.ban_and_disconnect_player(player_id, &reason, duration_secs)?)?,
+        Command::Players(PlayersCommand::WipeName { player_id }) => {
+            client.wipe_display_name(player_id)?;
+            println!("Wiped the display name of player {player_id}");
+        }
+        Command::Rooms(RoomsCommand::Watch { room_id }) => client.watch_room(room_id, |event| {
+            println!("{} {}", event.name, event.data);
+        })?,
+        Command::Rooms(RoomsCommand::Bans { room_id }) => {
+            for ban in client.room_bans(room_id)? {
+                println!(
+                    "{} banned by {}",
+                    ban["player_id"].as_str().unwrap_or_default(),
+                    ban["issued_by"].as_str().unwrap_or_default()
+                );
+            }
+        }
+        Command::Rooms(RoomsCommand::Unban { room_id, player_id }) => {
+            client.lift_room_ban(room_id, player_id)?;
+            println!("Let player {player_id} back into room {room_id} and its rematches");
+        }
+        Command::Reports(ReportsCommand::List { status }) => {
+            for report in client.reports(&status)? {
+                println!(
+                    "{} {} reported {}: {}",
+                    report["id"].as_str().unwrap_or_default(),
+                    report["reporter"].as_str().unwrap_or_default(),
+                    report["reported"].as_str().unwrap_or_default(),
+                    report["reason"].as_str().unwrap_or_default()
+                );
+            }
+        }
+        Command::Reports(ReportsCommand::Show { report_id }) => {
+            print_json(&client.report(&report_id)?)?
+        }
+        Command::Reports(ReportsCommand::Disconnect { report_id }) => {
+            print_json(&client.act_on_report(&report_id, json!({ "type": "disconnect" }))?)?
+        }
+        Command::Reports(ReportsCommand::Ban {
+            report_id,
+            reason,
+            duration_secs,
+        }) => print_json(&client.act_on_report(
+            &report_id,
+            json!({ "type": "ban", "reason": reason, "duration_secs": duration_secs }),
+        )?)?,
+        Command::Reports(ReportsCommand::WipeName { report_id }) => {
+            print_json(&client.act_on_report(&report_id, json!({ "type": "wipe_display_name" }))?)?
+        }
+        Command::Reports(ReportsCommand::Resolve { report_id, note }) => {
+            client.resolve_report(&report_id, note.as_deref())?;
+            println!("Resolved report {report_id}");
+        }
+        Command::Ban {
+            player_id,
+            reason,
+            duration_secs,
+        } => print_json(&client.ban_player(player_id, &reason, duration_secs)?)?,
+        Command::Announce {
+            text,
+            rooms,
+            regions,
+        } => {
+            let report = client.announce(&text, &rooms, &regions)?;
+            let relayed = if report["relayed"] == true {
+                ", and relayed to the other instances"
+            } else {
+                ""
+            };
+            println!(
+                "Announced to {} connections in {} rooms{relayed}",
+                report["connections"], report["rooms"]
+            );
+        }
+        Command::Maintenance { state } => {
+            if let Some(state) = state {
+                client.set_maintenance(matches!(state, Toggle::On))?;
+            }
+            let enabled = client.maintenance()?;
+            println!("Maintenance mode is {}", if enabled { "on" } else { "off" });
+        }
+        Command::Drain => {
+            let report = client.drain()?;
+            println!(
+                "Migrated {} rooms, {} couldn't be handed over",
+                report["migrated"], report["failed"]
+            );
+        }
+        Command::Events => client.follow_room_events(|event| {
+            println!("{} {}", event.name, event.data);
+        })?,
+    }
+    Ok(())
+}
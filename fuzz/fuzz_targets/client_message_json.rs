@@ -0,0 +1,20 @@
+//! Feeds arbitrary text frames to the JSON decoder, checking that whatever it accepts survives
+//! being encoded and decoded again
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wormhole_protocol::ClientEnvelope;
+
+fuzz_target!(|data: &[u8]| {
+    // Text frames are always valid UTF-8 by the time they reach the decoder
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(envelope) = serde_json::from_str::<ClientEnvelope>(text) else {
+        return;
+    };
+    let json = serde_json::to_string(&envelope).expect("Decoded envelopes always encode");
+    let decoded: ClientEnvelope =
+        serde_json::from_str(&json).expect("Encoded envelopes always decode");
+    assert_eq!(decoded, envelope);
+});
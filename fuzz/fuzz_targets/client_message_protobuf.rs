@@ -0,0 +1,15 @@
+//! Feeds arbitrary binary frames to the protobuf decoder, checking that whatever it accepts
+//! survives being encoded and decoded again
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use wormhole_protocol::ClientEnvelope;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(envelope) = ClientEnvelope::decode_protobuf(data) else {
+        return;
+    };
+    let decoded = ClientEnvelope::decode_protobuf(&envelope.encode_protobuf())
+        .expect("Encoded envelopes always decode");
+    assert_eq!(decoded, envelope);
+});
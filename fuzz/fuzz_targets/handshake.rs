@@ -0,0 +1,32 @@
+//! Feeds arbitrary requests to join a room through everything the server checks before
+//! upgrading them to a WebSocket that doesn't need a room or an identity provider
+//!
+//! The input's first line is taken as the room ID from the path, the rest as the query string.
+#![no_main]
+
+use std::sync::LazyLock;
+
+use libfuzzer_sys::fuzz_target;
+use wormhole::challenge::{ChallengeVerifier, ProofOfWork, DEFAULT_CHALLENGE_TTL};
+use wormhole::invites::InviteSigner;
+use wormhole::ws::{negotiate_protocol_version, JoinQuery};
+use wormhole_protocol::RoomId;
+
+static INVITE_SIGNER: LazyLock<InviteSigner> = LazyLock::new(|| InviteSigner::new([7; 32]));
+static CHALLENGE: LazyLock<ProofOfWork> =
+    LazyLock::new(|| ProofOfWork::new(8, DEFAULT_CHALLENGE_TTL));
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(request) = std::str::from_utf8(data) else {
+        return;
+    };
+    let (path, query) = request.split_once('\n').unwrap_or((request, ""));
+    let room_id = path.parse::<RoomId>().unwrap_or_else(|_| 0_u128.into());
+
+    let query = JoinQuery::parse(query);
+    let _ = negotiate_protocol_version(query.protocol_version);
+    if let Some(token) = &query.invite {
+        let _ = INVITE_SIGNER.verify(token, room_id);
+    }
+    let _ = futures_executor::block_on(CHALLENGE.verify(&query.challenge));
+});
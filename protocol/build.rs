@@ -0,0 +1,12 @@
+//! Generates the protobuf encoding of the messages when the `protobuf` feature is enabled,
+//! parsing the definitions with protox so building doesn't need protoc installed
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    #[cfg(feature = "protobuf")]
+    {
+        let file_descriptors = protox::compile(["wormhole/v1/messages.proto"], ["proto"])?;
+        prost_build::Config::new().compile_fds(file_descriptors)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,457 @@
+//! Golden files pinning down the wire format of every message
+//!
+//! Each sample message is serialized and compared with its file under `golden/`, and the file
+//! read back into the message, so a change to how any message is written or read fails here
+//! before it breaks clients built against an older version of this crate. Intended changes
+//! are accepted by rerunning the tests with `WORMHOLE_UPDATE_GOLDEN=1`, which rewrites the
+//! files, and reviewing their diff.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::*;
+
+const UPDATE_ENV_VAR: &str = "WORMHOLE_UPDATE_GOLDEN";
+
+fn golden_dir(kind: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("golden")
+        .join(kind)
+}
+
+/// Checks every sample against its golden file, and that no file is left without a sample
+fn assert_golden<T>(kind: &str, samples: &[(&str, T)])
+where
+    T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let dir = golden_dir(kind);
+    let update = std::env::var_os(UPDATE_ENV_VAR).is_some();
+    if update {
+        fs::create_dir_all(&dir).unwrap();
+    }
+    let mut failures = Vec::new();
+    for (name, sample) in samples {
+        let path = dir.join(format!("{name}.json"));
+        let written = serde_json::to_value(sample).unwrap();
+        if update {
+            let text = serde_json::to_string_pretty(&written).unwrap();
+            fs::write(&path, text + "\n").unwrap();
+            continue;
+        }
+        let golden: Value = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap(),
+            Err(_) => {
+                failures.push(format!("{name} has no golden file"));
+                continue;
+            }
+        };
+        if written != golden {
+            failures.push(format!("{name} is written as {written}, not {golden}"));
+        }
+        match serde_json::from_value::<T>(golden) {
+            Ok(read) if read == *sample => {}
+            Ok(read) => failures.push(format!("{name}'s golden file is read as {read:?}")),
+            Err(e) => failures.push(format!("{name}'s golden file can't be read: {e}")),
+        }
+    }
+
+    let names: BTreeSet<_> = samples
+        .iter()
+        .map(|(name, _)| format!("{name}.json"))
+        .collect();
+    for file in fs::read_dir(&dir).unwrap() {
+        let file = file.unwrap().file_name().to_string_lossy().into_owned();
+        if !names.contains(&file) {
+            if update {
+                fs::remove_file(dir.join(&file)).unwrap();
+            } else {
+                failures.push(format!(
+                    "{file} has no sample, the message it pins was removed"
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "The wire format of {kind} messages changed, rerun with {UPDATE_ENV_VAR}=1 if that's \
+         intended:\n{}",
+        failures.join("\n")
+    );
+}
+
+/// Every variant must have a sample below, which matching on them here is a reminder of
+fn client_message_type(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Broadcast { .. } => "broadcast",
+        ClientMessage::CloseRoom => "close_room",
+        ClientMessage::BanPlayer { .. } => "ban_player",
+        ClientMessage::UpdateSettings { .. } => "update_settings",
+        ClientMessage::CreateInvite { .. } => "create_invite",
+        ClientMessage::Chat { .. } => "chat",
+        ClientMessage::MutePlayer { .. } => "mute_player",
+        ClientMessage::UnmutePlayer { .. } => "unmute_player",
+        ClientMessage::KickPlayer { .. } => "kick_player",
+        ClientMessage::React { .. } => "react",
+        ClientMessage::AnswerInvitation { .. } => "answer_invitation",
+        ClientMessage::SetCustomData { .. } => "set_custom_data",
+        ClientMessage::VoteKick { .. } => "vote_kick",
+        ClientMessage::BalanceTeams { .. } => "balance_teams",
+    }
+}
+
+/// Every variant must have a sample below, which matching on them here is a reminder of
+fn server_message_type(message: &ServerMessage) -> &'static str {
+    match message {
+        ServerMessage::Welcome { .. } => "welcome",
+        ServerMessage::Broadcast { .. } => "broadcast",
+        ServerMessage::EncodedBroadcast { .. } => "encoded_broadcast",
+        ServerMessage::Invite(_) => "invite",
+        ServerMessage::Chat(_) => "chat",
+        ServerMessage::ChatHistory { .. } => "chat_history",
+        ServerMessage::Reaction { .. } => "reaction",
+        ServerMessage::Invitation(_) => "invitation",
+        ServerMessage::InvitationAnswered { .. } => "invitation_answered",
+        ServerMessage::Error { .. } => "error",
+        ServerMessage::Batch { .. } => "batch",
+        ServerMessage::Migrate { .. } => "migrate",
+        ServerMessage::Announcement { .. } => "announcement",
+        ServerMessage::ReplayUnavailable { .. } => "replay_unavailable",
+        ServerMessage::CustomData { .. } => "custom_data",
+        ServerMessage::KickVote { .. } => "kick_vote",
+        ServerMessage::KickVoteEnded { .. } => "kick_vote_ended",
+        ServerMessage::TeamsBalanced { .. } => "teams_balanced",
+    }
+}
+
+fn client_samples() -> Vec<(&'static str, ClientEnvelope)> {
+    let player_id = PlayerId::from(2);
+    let messages = [
+        (
+            "broadcast",
+            ClientMessage::Broadcast {
+                payload: json!({ "move": "e4", "clock": [300, 295] }),
+            },
+        ),
+        ("close_room", ClientMessage::CloseRoom),
+        (
+            "ban_player",
+            ClientMessage::BanPlayer {
+                player_id,
+                reason: "spam".to_owned(),
+                duration_secs: Some(3600),
+            },
+        ),
+        (
+            "ban_player_permanently",
+            ClientMessage::BanPlayer {
+                player_id,
+                reason: "cheating".to_owned(),
+                duration_secs: None,
+            },
+        ),
+        (
+            "update_settings",
+            ClientMessage::UpdateSettings {
+                private: true,
+                name: Some("Friday night".to_owned()),
+                visibility: None,
+            },
+        ),
+        (
+            "update_settings_unlisted",
+            ClientMessage::UpdateSettings {
+                private: false,
+                name: None,
+                visibility: Some(Visibility::Unlisted),
+            },
+        ),
+        (
+            "create_invite",
+            ClientMessage::CreateInvite {
+                ttl_secs: Some(600),
+                max_uses: Some(3),
+            },
+        ),
+        (
+            "chat",
+            ClientMessage::Chat {
+                text: "good luck".to_owned(),
+                channel: ChatChannel::Room,
+            },
+        ),
+        (
+            "chat_to_team",
+            ClientMessage::Chat {
+                text: "flank left".to_owned(),
+                channel: ChatChannel::Team,
+            },
+        ),
+        (
+            "chat_whisper",
+            ClientMessage::Chat {
+                text: "rematch?".to_owned(),
+                channel: ChatChannel::Whisper(player_id),
+            },
+        ),
+        (
+            "mute_player",
+            ClientMessage::MutePlayer {
+                player_id,
+                duration_secs: Some(300),
+            },
+        ),
+        ("unmute_player", ClientMessage::UnmutePlayer { player_id }),
+        (
+            "kick_player",
+            ClientMessage::KickPlayer {
+                player_id,
+                ban: true,
+            },
+        ),
+        (
+            "react",
+            ClientMessage::React {
+                emote: "gg".to_owned(),
+            },
+        ),
+        (
+            "answer_invitation",
+            ClientMessage::AnswerInvitation {
+                invitation_id: InvitationId::from(3),
+                accept: true,
+            },
+        ),
+        (
+            "set_custom_data",
+            ClientMessage::SetCustomData {
+                data: json!({ "map": "de_dust2", "mods": ["no_fog", "fast_reload"] })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            },
+        ),
+        ("vote_kick", ClientMessage::VoteKick { player_id }),
+        (
+            "balance_teams",
+            ClientMessage::BalanceTeams {
+                teams: vec!["red".to_owned(), "blue".to_owned()],
+            },
+        ),
+    ];
+    let mut samples: Vec<_> = messages
+        .into_iter()
+        .map(|(name, message)| (name, ClientEnvelope::from(message)))
+        .collect();
+    samples.push((
+        "sender",
+        ClientEnvelope {
+            sender: Some(PlayerId::from(1)),
+            message: ClientMessage::React {
+                emote: "gg".to_owned(),
+            },
+        },
+    ));
+    samples
+}
+
+fn server_samples() -> Vec<(&'static str, ServerMessage<'static>)> {
+    let player_id = PlayerId::from(1);
+    let invite = Invite {
+        token: "invite-token".to_owned(),
+        expires_at: 1_700_003_600,
+    };
+    let chat = ChatMessage {
+        from: ConnectionId::from(1),
+        from_player: Some(player_id),
+        text: "good luck".to_owned(),
+        channel: ChatChannel::Room,
+        sent_at: 1_700_000_000_000,
+    };
+    let broadcast = ServerMessage::Broadcast {
+        from: ConnectionId::from(1),
+        from_player: Some(player_id),
+        payload: Cow::Owned(json!({ "move": "e4", "clock": [300, 295] })),
+        seq: None,
+    };
+    vec![
+        (
+            "welcome",
+            ServerMessage::Welcome {
+                connection_id: ConnectionId::from(1),
+                protocol_version: 5,
+                player_id: Some(player_id),
+            },
+        ),
+        (
+            "welcome_anonymous",
+            ServerMessage::Welcome {
+                connection_id: ConnectionId::from(2),
+                protocol_version: 5,
+                player_id: None,
+            },
+        ),
+        ("broadcast", broadcast.clone()),
+        (
+            "broadcast_sequenced",
+            ServerMessage::Broadcast {
+                from: ConnectionId::from(2),
+                from_player: None,
+                payload: Cow::Owned(json!({ "move": "e5" })),
+                seq: Some(42),
+            },
+        ),
+        (
+            "encoded_broadcast",
+            ServerMessage::EncodedBroadcast {
+                from: ConnectionId::from(2),
+                from_player: None,
+                codec: "msgpack".to_owned(),
+                // { "move": "e5" } in MessagePack
+                payload: Cow::Owned(vec![0x81, 0xa4, b'm', b'o', b'v', b'e', 0xa2, b'e', b'5']),
+                seq: Some(42),
+            },
+        ),
+        ("invite", ServerMessage::Invite(invite.clone())),
+        ("chat", ServerMessage::Chat(chat.clone())),
+        (
+            "chat_to_party",
+            ServerMessage::Chat(ChatMessage {
+                from_player: None,
+                channel: ChatChannel::Party,
+                ..chat.clone()
+            }),
+        ),
+        (
+            "chat_history",
+            ServerMessage::ChatHistory {
+                messages: vec![chat],
+            },
+        ),
+        (
+            "reaction",
+            ServerMessage::Reaction {
+                from: ConnectionId::from(2),
+                from_player: None,
+                emote: "gg".to_owned(),
+            },
+        ),
+        (
+            "invitation",
+            ServerMessage::Invitation(RoomInvitation {
+                id: InvitationId::from(3),
+                from: player_id,
+                room_id: RoomId::from(4),
+                invite: invite.clone(),
+            }),
+        ),
+        (
+            "invitation_answered",
+            ServerMessage::InvitationAnswered {
+                invitation_id: InvitationId::from(3),
+                player_id: PlayerId::from(2),
+                accepted: false,
+            },
+        ),
+        (
+            "error",
+            ServerMessage::Error {
+                reason: "You are not allowed to close this room".to_owned(),
+            },
+        ),
+        (
+            "batch",
+            ServerMessage::Batch {
+                messages: vec![
+                    broadcast,
+                    ServerMessage::Reaction {
+                        from: ConnectionId::from(2),
+                        from_player: None,
+                        emote: "gg".to_owned(),
+                    },
+                ],
+            },
+        ),
+        (
+            "migrate",
+            ServerMessage::Migrate {
+                url: "wss://eu-2.example.com/ws/00000000-0000-0000-0000-000000000004".to_owned(),
+                invite,
+            },
+        ),
+        (
+            "announcement",
+            ServerMessage::Announcement {
+                text: "The server restarts for maintenance in 10 minutes".to_owned(),
+            },
+        ),
+        (
+            "replay_unavailable",
+            ServerMessage::ReplayUnavailable { latest_seq: 42 },
+        ),
+        (
+            "custom_data",
+            ServerMessage::CustomData {
+                data: json!({ "map": "de_dust2", "mods": ["no_fog", "fast_reload"] })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            },
+        ),
+        (
+            "kick_vote",
+            ServerMessage::KickVote {
+                player_id,
+                votes: 2,
+                votes_needed: 3,
+                ends_at: 1_700_000_030,
+            },
+        ),
+        (
+            "kick_vote_ended",
+            ServerMessage::KickVoteEnded {
+                player_id,
+                kicked: true,
+            },
+        ),
+        (
+            "teams_balanced",
+            ServerMessage::TeamsBalanced {
+                teams: [(player_id, "red"), (PlayerId::from(2), "blue")]
+                    .into_iter()
+                    .map(|(player_id, team)| (player_id, team.to_owned()))
+                    .collect(),
+            },
+        ),
+    ]
+}
+
+#[test]
+fn client_messages_match_their_golden_files() {
+    let samples = client_samples();
+    let types: BTreeSet<_> = samples
+        .iter()
+        .map(|(_, envelope)| client_message_type(&envelope.message))
+        .collect();
+    assert_eq!(types.len(), 14, "Every client message needs a sample");
+
+    assert_golden("client", &samples);
+}
+
+#[test]
+fn server_messages_match_their_golden_files() {
+    let samples = server_samples();
+    let types: BTreeSet<_> = samples
+        .iter()
+        .map(|(_, message)| server_message_type(message))
+        .collect();
+    assert_eq!(types.len(), 18, "Every server message needs a sample");
+
+    assert_golden("server", &samples);
+}
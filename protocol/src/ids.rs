@@ -0,0 +1,246 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// An ID that uniquely identifies a room on a server
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+pub struct RoomId(u128);
+
+impl std::fmt::Display for RoomId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Uuid::from_u128(self.0).fmt(f)
+    }
+}
+
+impl From<u128> for RoomId {
+    fn from(value: u128) -> Self {
+        RoomId(value)
+    }
+}
+
+/// Room ids are represented as hyphenated UUIDs on the wire
+impl Serialize for RoomId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Uuid::from_u128(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RoomId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Uuid::deserialize(deserializer)?.as_u128().into())
+    }
+}
+
+impl std::str::FromStr for RoomId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Uuid::parse_str(s)?.as_u128().into())
+    }
+}
+
+/// An ID that identifies a player across connections, rooms and server restarts
+#[derive(Debug, Hash, PartialOrd, Ord, Eq, PartialEq, Copy, Clone)]
+pub struct PlayerId(u128);
+
+impl From<u128> for PlayerId {
+    fn from(value: u128) -> Self {
+        PlayerId(value)
+    }
+}
+
+impl PlayerId {
+    /// Derives the ID of the player an identity provider knows as `subject`,
+    /// so the same account always maps to the same player
+    pub fn from_subject(issuer: &str, subject: &str) -> Self {
+        let digest = Sha256::new()
+            .chain_update(issuer.as_bytes())
+            .chain_update([0])
+            .chain_update(subject.as_bytes())
+            .finalize();
+        let mut id = [0; 16];
+        id.copy_from_slice(&digest[..16]);
+        PlayerId(u128::from_be_bytes(id))
+    }
+}
+
+impl std::str::FromStr for PlayerId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Uuid::parse_str(s)?.as_u128().into())
+    }
+}
+
+impl std::fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Uuid::from_u128(self.0).fmt(f)
+    }
+}
+
+/// Player ids are represented as hyphenated UUIDs on the wire
+impl Serialize for PlayerId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Uuid::from_u128(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PlayerId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Uuid::deserialize(deserializer)?.as_u128().into())
+    }
+}
+
+/// An ID that identifies an [invitation][crate::RoomInvitation] from one player to another
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct InvitationId(u128);
+
+impl From<u128> for InvitationId {
+    fn from(value: u128) -> Self {
+        InvitationId(value)
+    }
+}
+
+impl std::str::FromStr for InvitationId {
+    type Err = uuid::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Uuid::parse_str(s)?.as_u128().into())
+    }
+}
+
+impl std::fmt::Display for InvitationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Uuid::from_u128(self.0).fmt(f)
+    }
+}
+
+/// Invitation ids are represented as hyphenated UUIDs on the wire
+impl Serialize for InvitationId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Uuid::from_u128(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for InvitationId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Uuid::deserialize(deserializer)?.as_u128().into())
+    }
+}
+
+/// An ID that uniquely identifies a connection to a room
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(transparent)]
+pub struct ConnectionId(u64);
+
+impl From<u64> for ConnectionId {
+    fn from(value: u64) -> Self {
+        ConnectionId(value)
+    }
+}
+
+impl From<ConnectionId> for u64 {
+    fn from(id: ConnectionId) -> Self {
+        id.0
+    }
+}
+
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Room, player and invitation IDs are documented as the UUIDs they're serialized as
+#[cfg(feature = "utoipa")]
+mod schema {
+    use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, SchemaFormat, Type};
+    use utoipa::openapi::{RefOr, Schema};
+    use utoipa::{PartialSchema, ToSchema};
+
+    use super::{InvitationId, PlayerId, RoomId};
+
+    fn uuid_schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::Uuid)))
+            .into()
+    }
+
+    impl PartialSchema for RoomId {
+        fn schema() -> RefOr<Schema> {
+            uuid_schema()
+        }
+    }
+
+    impl ToSchema for RoomId {}
+
+    impl PartialSchema for PlayerId {
+        fn schema() -> RefOr<Schema> {
+            uuid_schema()
+        }
+    }
+
+    impl ToSchema for PlayerId {}
+
+    impl PartialSchema for InvitationId {
+        fn schema() -> RefOr<Schema> {
+            uuid_schema()
+        }
+    }
+
+    impl ToSchema for InvitationId {}
+}
+
+#[cfg(test)]
+mod room_id_serde {
+    use super::*;
+
+    #[test]
+    fn serializes_as_hyphenated_uuid() {
+        let id = RoomId::from(0x67e5504410b1426f9247bb680e5fe0c8_u128);
+        assert_eq!(
+            serde_json::to_string(&id).unwrap(),
+            "\"67e55044-10b1-426f-9247-bb680e5fe0c8\""
+        );
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let id = RoomId::from(0x67e5504410b1426f9247bb680e5fe0c8_u128);
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(serde_json::from_str::<RoomId>(&json).unwrap(), id);
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert!(serde_json::from_str::<RoomId>("\"not-a-room\"").is_err());
+    }
+}
+
+#[cfg(test)]
+mod from_subject {
+    use super::*;
+
+    #[test]
+    fn is_stable_for_the_same_subject() {
+        assert_eq!(
+            PlayerId::from_subject("https://id.example.com", "user-1"),
+            PlayerId::from_subject("https://id.example.com", "user-1")
+        );
+    }
+
+    #[test]
+    fn differs_between_subjects_and_issuers() {
+        let id = PlayerId::from_subject("https://id.example.com", "user-1");
+        assert_ne!(
+            id,
+            PlayerId::from_subject("https://id.example.com", "user-2")
+        );
+        assert_ne!(
+            id,
+            PlayerId::from_subject("https://other.example.com", "user-1")
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! The wire format spoken between wormhole servers and game clients
+//!
+//! Clients connect to a room's WebSocket at `/ws/{room_id}`, send [client envelopes][ClientEnvelope]
+//! as JSON text frames, and receive [server messages][ServerMessage] in return. Game clients
+//! written in Rust can depend on this crate directly so both sides agree on the format.
+//!
+//! Clients say which version of the protocol they speak in the `protocol_version` query
+//! parameter when connecting, and are told the version the server settled on when they're
+//! [welcomed][ServerMessage::Welcome]. Servers keep serving a window of older versions, and
+//! close connections asking for versions outside it with a reason asking the client to update.
+//!
+//! Messages are JSON by default. Clients joining with `format=protobuf` exchange protobuf
+//! encoded binary frames instead, which this crate reads and writes with the `protobuf` feature.
+
+#[cfg(test)]
+mod golden;
+mod ids;
+mod messages;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+/// The version of the protocol described by this crate
+///
+/// Version 2 added [batch][ServerMessage::Batch] frames, version 3 added
+/// [chat][ClientMessage::Chat], version 4 added [reactions][ClientMessage::React], version 5
+/// added [invitations][ServerMessage::Invitation] between players, version 6 added
+/// [announcements][ServerMessage::Announcement], version 7 added replaying the
+/// [broadcasts][ServerMessage::Broadcast] a client missed when it rejoins, version 8 added
+/// rooms' [custom data][ServerMessage::CustomData], version 9 added
+/// [votes to kick][ClientMessage::VoteKick], version 10 added
+/// [balancing teams][ClientMessage::BalanceTeams], and version 11 added
+/// [encoded broadcasts][ServerMessage::EncodedBroadcast].
+pub const PROTOCOL_VERSION: u32 = 11;
+
+pub use ids::*;
+pub use messages::*;
@@ -0,0 +1,602 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{ConnectionId, InvitationId, PlayerId, RoomId};
+
+/// Messages sent by clients to the room they're connected to
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Relays an arbitrary payload to every connection in the room
+    Broadcast { payload: Value },
+    /// Disconnects everyone from the room and removes it, only allowed for moderators and admins
+    CloseRoom,
+    /// Bans a player from the whole server, only allowed for moderators and admins
+    BanPlayer {
+        player_id: PlayerId,
+        reason: String,
+        /// How long the ban lasts, omitted for a permanent ban
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    /// Changes whether joining the room requires an invite and what the room is called, only
+    /// allowed for the room's owner
+    UpdateSettings {
+        #[serde(default)]
+        private: bool,
+        /// The room's display name, left unchanged if omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        /// Who can find and join the room, overriding `private` if given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        visibility: Option<Visibility>,
+    },
+    /// Mints an invite to the room, only allowed for the room's owner
+    CreateInvite {
+        /// How long the invite lasts, an hour if omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl_secs: Option<u64>,
+        /// How many times the invite can be used, unlimited if omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_uses: Option<u32>,
+    },
+    /// Sends a line of text to everyone in the room, or only to some of them
+    Chat {
+        text: String,
+        /// Who the line is for, everyone in the room if omitted
+        #[serde(default, skip_serializing_if = "ChatChannel::is_room")]
+        channel: ChatChannel,
+    },
+    /// Stops a player from chatting in the room, only allowed for the room's owner, moderators
+    /// and admins
+    MutePlayer {
+        player_id: PlayerId,
+        /// How long the mute lasts, omitted to mute the player for as long as the room exists
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        duration_secs: Option<u64>,
+    },
+    /// Lets a muted player chat in the room again, only allowed for the room's owner,
+    /// moderators and admins
+    UnmutePlayer { player_id: PlayerId },
+    /// Disconnects a player from the room, only allowed for the room's owner, moderators and
+    /// admins
+    KickPlayer {
+        player_id: PlayerId,
+        /// Whether to also keep the player out of the room and every rematch of it
+        #[serde(default)]
+        ban: bool,
+    },
+    /// Shows an emote from the server's catalog to everyone in the room
+    React { emote: String },
+    /// Accepts or declines an [invitation][ServerMessage::Invitation] sent to the client's
+    /// player, letting whoever sent it know
+    AnswerInvitation {
+        invitation_id: InvitationId,
+        accept: bool,
+    },
+    /// Replaces the room's custom data, such as its map or the mods it's played with, only
+    /// allowed for the room's owner
+    ///
+    /// The data is any JSON object within the server's size limit, an empty one clearing it.
+    SetCustomData {
+        #[cfg_attr(feature = "utoipa", schema(value_type = Object))]
+        data: Map<String, Value>,
+    },
+    /// Votes to kick another player out of the room, starting a vote if none is running
+    /// against them, only allowed for signed in players taking part in the game
+    VoteKick { player_id: PlayerId },
+    /// Spreads the room's players evenly across teams, keeping parties together and evening
+    /// out the teams' ratings, only allowed for the room's owner
+    BalanceTeams {
+        /// The teams to spread the players across, the teams they're already on if omitted
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        teams: Vec<String>,
+    },
+}
+
+/// A [client message][ClientMessage] along with the identity the client claims to be sending it as
+///
+/// The server never acts on a claimed identity, it only checks that it matches the player the
+/// connection authenticated as, so tampered clients can be told apart from honest ones.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ClientEnvelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<PlayerId>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+impl From<ClientMessage> for ClientEnvelope {
+    fn from(message: ClientMessage) -> Self {
+        Self {
+            sender: None,
+            message,
+        }
+    }
+}
+
+/// Messages sent by the server to connected clients
+///
+/// Broadcast payloads are borrowed when the server sends them, so they can be serialized
+/// without being copied, and owned when a client reads them.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage<'a> {
+    /// Sent to a connection once it has joined a room
+    Welcome {
+        connection_id: ConnectionId,
+        /// The [version][crate::PROTOCOL_VERSION] of the protocol spoken over the connection
+        protocol_version: u32,
+        /// The authenticated player behind the connection, absent for anonymous connections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        player_id: Option<PlayerId>,
+    },
+    /// A payload broadcast by a connection in the room
+    Broadcast {
+        from: ConnectionId,
+        /// The authenticated player who sent the payload, absent for anonymous connections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_player: Option<PlayerId>,
+        #[cfg_attr(feature = "utoipa", schema(value_type = Value))]
+        payload: Cow<'a, Value>,
+        /// The broadcast's place in the room's sequence of broadcasts, counting up from 1,
+        /// which clients rejoin with to catch up on what they missed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// A payload broadcast by a connection in a room whose game type has its payloads encoded
+    /// by a codec other than JSON, sent in place of a [broadcast][ServerMessage::Broadcast]
+    ///
+    /// The payload is written in base64 in JSON frames, and as it is in protobuf frames.
+    EncodedBroadcast {
+        from: ConnectionId,
+        /// The authenticated player who sent the payload, absent for anonymous connections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_player: Option<PlayerId>,
+        /// The name of the codec that encoded the payload, such as `msgpack`
+        codec: String,
+        #[serde(with = "base64_bytes")]
+        #[cfg_attr(feature = "utoipa", schema(value_type = String, format = Byte))]
+        payload: Cow<'a, [u8]>,
+        /// The broadcast's place in the room's sequence of broadcasts, as for a
+        /// [broadcast][ServerMessage::Broadcast]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+    },
+    /// An invite minted at the client's request
+    Invite(Invite),
+    /// A line of chat sent by a connection in the room
+    Chat(ChatMessage),
+    /// The room's most recent chat, oldest first, sent to a connection once it has joined
+    ChatHistory { messages: Vec<ChatMessage> },
+    /// An emote shown by a connection in the room
+    Reaction {
+        from: ConnectionId,
+        /// The authenticated player who reacted, absent for anonymous connections
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_player: Option<PlayerId>,
+        emote: String,
+    },
+    /// Another player has invited the client's player to a room, wherever they're connected
+    Invitation(RoomInvitation),
+    /// A player has answered an invitation the client's player sent them
+    InvitationAnswered {
+        invitation_id: InvitationId,
+        player_id: PlayerId,
+        accepted: bool,
+    },
+    /// Sent when a message from the client couldn't be processed
+    Error { reason: String },
+    /// Several messages sent as one frame, in the order they were sent
+    Batch {
+        #[cfg_attr(feature = "utoipa", schema(no_recursion))]
+        messages: Vec<ServerMessage<'a>>,
+    },
+    /// The room has moved to another server, the connection closing right after
+    ///
+    /// Clients reconnect to `url`, passing the invite's token in the `invite` query
+    /// parameter so private rooms let them back in.
+    Migrate { url: String, invite: Invite },
+    /// A notice from the server's operators, such as a warning of upcoming maintenance, sent
+    /// to every connection it's meant for
+    Announcement { text: String },
+    /// The broadcasts since the one the client rejoined after are no longer all kept, so it
+    /// has to catch up on the game some other way, such as asking another player for its
+    /// state
+    ///
+    /// `latest_seq` is the last broadcast sent before the client rejoined, every broadcast
+    /// after it being sent as usual.
+    ReplayUnavailable { latest_seq: u64 },
+    /// The room's custom data, sent to every connection when the owner changes it and to each
+    /// connection as it joins, if the room has any
+    CustomData {
+        #[cfg_attr(feature = "utoipa", schema(value_type = Object))]
+        data: Map<String, Value>,
+    },
+    /// A vote to kick a player out of the room has started, or another player has voted in it
+    KickVote {
+        /// The player the vote would kick
+        player_id: PlayerId,
+        votes: u32,
+        /// How many votes it takes to kick the player
+        votes_needed: u32,
+        /// When the vote fails unless it has passed by then, in seconds since the Unix epoch
+        ends_at: u64,
+    },
+    /// A vote to kick a player out of the room has ended, kicking them if it passed
+    KickVoteEnded { player_id: PlayerId, kicked: bool },
+    /// The room's players have been spread across teams, giving the team each of them is now
+    /// on
+    TeamsBalanced { teams: BTreeMap<PlayerId, String> },
+}
+
+/// Writes bytes as base64, so they can be sent in JSON frames
+mod base64_bytes {
+    use std::borrow::Cow;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, 'a, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Cow<'a, [u8]>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(encoded)
+            .map(Cow::Owned)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Who a line of chat is for
+///
+/// Written as `"team"`, `"party"`, or `{ "whisper": "<player id>" }`.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ChatChannel {
+    /// Everyone in the room
+    #[default]
+    Room,
+    /// The players on the sender's team
+    Team,
+    /// The members of the sender's party who are in the room
+    Party,
+    /// A single player, along with the sender
+    Whisper(PlayerId),
+}
+
+impl ChatChannel {
+    pub fn is_room(&self) -> bool {
+        *self == Self::Room
+    }
+}
+
+/// A line of chat sent to a room
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct ChatMessage {
+    pub from: ConnectionId,
+    /// The authenticated player who sent the message, absent for anonymous connections
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_player: Option<PlayerId>,
+    pub text: String,
+    /// Who the message was sent to, everyone in the room if absent
+    #[serde(default, skip_serializing_if = "ChatChannel::is_room")]
+    pub channel: ChatChannel,
+    /// Milliseconds since the Unix epoch
+    pub sent_at: u64,
+}
+
+/// An invite to a private room
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct Invite {
+    /// Passed in the `invite` query parameter when joining the room
+    pub token: String,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// An invitation from one player to another to join a room
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct RoomInvitation {
+    pub id: InvitationId,
+    /// The player who sent the invitation
+    pub from: PlayerId,
+    pub room_id: RoomId,
+    /// The seat held in the room for the invited player, which lets them in even if the room
+    /// is private
+    pub invite: Invite,
+}
+
+/// Who can find and join a room
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Listed in the lobby, and anyone can join
+    #[default]
+    Public,
+    /// Left out of the lobby, but anyone with the room's ID or an invite can join
+    Unlisted,
+    /// Listed in the lobby, but joining requires an invite
+    Private,
+}
+
+impl Visibility {
+    /// Whether rooms with the visibility are listed in the lobby
+    pub fn is_listed(self) -> bool {
+        self != Self::Unlisted
+    }
+}
+
+/// Where a listed room is in its life
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RoomStatus {
+    /// Waiting to open to players at its `starts_at`
+    Scheduled,
+    /// Letting players in
+    #[default]
+    Open,
+    /// Every seat is taken
+    Full,
+    /// Everyone has left, and the room is deleted at its `deletes_at` unless someone joins
+    ///
+    /// Rooms waiting for their first player are open, with a `deletes_at` all the same.
+    Closing,
+}
+
+/// A lightweight description of a room suitable for listing to clients
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct RoomSummary {
+    pub id: RoomId,
+    /// What the room's owner has called it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The kind of game played in the room, if the server or the match it was created for
+    /// says
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_type: Option<String>,
+    /// How many distinct authenticated players are connected
+    #[serde(default)]
+    pub player_count: usize,
+    /// How many connections are watching the room rather than playing in it
+    #[serde(default)]
+    pub spectator_count: usize,
+    /// How many players the room can hold, absent for no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub state: RoomStatus,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// The region of the instance hosting the room, if it has been configured with one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// When the room opens, in seconds since the Unix epoch, if it's scheduled to open later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<u64>,
+    /// When the room is deleted unless someone joins it, in seconds since the Unix epoch, if
+    /// it's waiting for players
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletes_at: Option<u64>,
+    /// When something was last broadcast in the room, in seconds since the Unix epoch and
+    /// accurate to the minute, if anything has been
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_active_at: Option<u64>,
+}
+
+#[cfg(test)]
+mod client_envelope {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_claimed_sender_alongside_message() {
+        let sender = "00000000-0000-0000-0000-000000000001";
+        let envelope: ClientEnvelope = serde_json::from_value(json!({
+            "type": "broadcast",
+            "sender": sender,
+            "payload": { "x": 1 },
+        }))
+        .unwrap();
+
+        assert_eq!(envelope.sender, Some(PlayerId::from(1)));
+        assert_eq!(
+            envelope.message,
+            ClientMessage::Broadcast {
+                payload: json!({ "x": 1 })
+            }
+        );
+    }
+
+    #[test]
+    fn leaves_message_fields_to_the_message() {
+        let envelope: ClientEnvelope = serde_json::from_value(json!({
+            "type": "ban_player",
+            "player_id": "00000000-0000-0000-0000-000000000002",
+            "reason": "spam",
+        }))
+        .unwrap();
+
+        assert_eq!(envelope.sender, None);
+        assert_eq!(
+            envelope.message,
+            ClientMessage::BanPlayer {
+                player_id: PlayerId::from(2),
+                reason: "spam".to_owned(),
+                duration_secs: None,
+            }
+        );
+    }
+
+    #[test]
+    fn serializes_without_absent_fields() {
+        let envelope = ClientEnvelope::from(ClientMessage::CreateInvite {
+            ttl_secs: None,
+            max_uses: Some(1),
+        });
+
+        assert_eq!(
+            serde_json::to_value(&envelope).unwrap(),
+            json!({ "type": "create_invite", "max_uses": 1 })
+        );
+    }
+}
+
+#[cfg(test)]
+mod chat_channel {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_team_and_whisper_channels() {
+        let team: ClientMessage =
+            serde_json::from_value(json!({ "type": "chat", "text": "go", "channel": "team" }))
+                .unwrap();
+        let whisper: ClientMessage = serde_json::from_value(json!({
+            "type": "chat",
+            "text": "psst",
+            "channel": { "whisper": "00000000-0000-0000-0000-000000000002" },
+        }))
+        .unwrap();
+
+        assert_eq!(
+            team,
+            ClientMessage::Chat {
+                text: "go".to_owned(),
+                channel: ChatChannel::Team
+            }
+        );
+        assert_eq!(
+            whisper,
+            ClientMessage::Chat {
+                text: "psst".to_owned(),
+                channel: ChatChannel::Whisper(PlayerId::from(2))
+            }
+        );
+    }
+
+    #[test]
+    fn defaults_to_the_room() {
+        let message: ClientMessage =
+            serde_json::from_value(json!({ "type": "chat", "text": "hi" })).unwrap();
+
+        assert_eq!(
+            message,
+            ClientMessage::Chat {
+                text: "hi".to_owned(),
+                channel: ChatChannel::Room
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod server_message {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_batches_of_messages() {
+        let frame = json!({
+            "type": "batch",
+            "messages": [
+                { "type": "welcome", "connection_id": 1, "protocol_version": 2 },
+                { "type": "broadcast", "from": 2, "payload": { "x": 1 } },
+            ]
+        });
+
+        assert_eq!(
+            serde_json::from_value::<ServerMessage>(frame).unwrap(),
+            ServerMessage::Batch {
+                messages: vec![
+                    ServerMessage::Welcome {
+                        connection_id: 1.into(),
+                        protocol_version: 2,
+                        player_id: None,
+                    },
+                    ServerMessage::Broadcast {
+                        from: 2.into(),
+                        from_player: None,
+                        payload: Cow::Owned(json!({ "x": 1 })),
+                        seq: None,
+                    },
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn puts_chat_fields_alongside_the_type() {
+        let message = ServerMessage::Chat(ChatMessage {
+            from: 1.into(),
+            from_player: None,
+            text: "gg".to_owned(),
+            channel: ChatChannel::Room,
+            sent_at: 2,
+        });
+
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({ "type": "chat", "from": 1, "text": "gg", "sent_at": 2 })
+        );
+    }
+
+    #[test]
+    fn puts_invitation_fields_alongside_the_type() {
+        let message = ServerMessage::Invitation(RoomInvitation {
+            id: InvitationId::from(1),
+            from: PlayerId::from(2),
+            room_id: RoomId::from(3),
+            invite: Invite {
+                token: "seat".to_owned(),
+                expires_at: 4,
+            },
+        });
+
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            json!({
+                "type": "invitation",
+                "id": "00000000-0000-0000-0000-000000000001",
+                "from": "00000000-0000-0000-0000-000000000002",
+                "room_id": "00000000-0000-0000-0000-000000000003",
+                "invite": { "token": "seat", "expires_at": 4 },
+            })
+        );
+    }
+
+    #[test]
+    fn round_trips_borrowed_payloads() {
+        let payload = json!({ "x": 1 });
+        let message = ServerMessage::Broadcast {
+            from: 1.into(),
+            from_player: Some(PlayerId::from(3)),
+            payload: Cow::Borrowed(&payload),
+            seq: None,
+        };
+
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(&json).unwrap(),
+            message
+        );
+    }
+}
@@ -0,0 +1,854 @@
+//! The protobuf encoding of the messages, defined in `proto/wormhole/v1/messages.proto`
+//!
+//! Messages are converted to and from the generated [pb] types, so the rest of the protocol
+//! only ever deals with one set of types whichever encoding a client speaks.
+
+use std::borrow::Cow;
+
+use prost::Message as _;
+use prost_types::value::Kind;
+use prost_types::{ListValue, Struct};
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    ChatChannel, ChatMessage, ClientEnvelope, ClientMessage, InvitationId, Invite, PlayerId,
+    RoomId, RoomInvitation, ServerMessage, Visibility,
+};
+
+/// The types generated from the protobuf definitions
+#[allow(clippy::all)]
+pub mod pb {
+    include!(concat!(env!("OUT_DIR"), "/wormhole.v1.rs"));
+}
+
+use pb::client_envelope::Message as ClientKind;
+use pb::server_message::Message as ServerKind;
+
+/// Why a protobuf message couldn't be read
+#[derive(Debug, PartialEq)]
+pub enum ProtobufError {
+    Decode(prost::DecodeError),
+    /// The message's `oneof` was left unset
+    MissingMessage,
+    InvalidPlayerId(String),
+    InvalidRoomId(String),
+    InvalidInvitationId(String),
+    InvalidVisibility(i32),
+    /// A payload held a number JSON can't represent, such as NaN
+    InvalidNumber(f64),
+}
+
+impl std::fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Decode(e) => write!(f, "The message is not valid protobuf: {e}"),
+            Self::MissingMessage => f.write_str("The message is empty"),
+            Self::InvalidPlayerId(id) => write!(f, "{id:?} is not a player ID"),
+            Self::InvalidRoomId(id) => write!(f, "{id:?} is not a room ID"),
+            Self::InvalidInvitationId(id) => write!(f, "{id:?} is not an invitation ID"),
+            Self::InvalidVisibility(visibility) => {
+                write!(f, "{visibility} is not a room visibility")
+            }
+            Self::InvalidNumber(number) => write!(f, "{number} can't be represented in JSON"),
+        }
+    }
+}
+
+impl std::error::Error for ProtobufError {}
+
+impl From<prost::DecodeError> for ProtobufError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+fn to_pb_value(value: &Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(value) => Kind::BoolValue(*value),
+        Value::Number(number) => Kind::NumberValue(number.as_f64().unwrap_or_default()),
+        Value::String(value) => Kind::StringValue(value.clone()),
+        Value::Array(values) => Kind::ListValue(ListValue {
+            values: values.iter().map(to_pb_value).collect(),
+        }),
+        Value::Object(fields) => Kind::StructValue(to_pb_struct(fields)),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn to_pb_struct(fields: &Map<String, Value>) -> Struct {
+    Struct {
+        fields: fields
+            .iter()
+            .map(|(key, value)| (key.clone(), to_pb_value(value)))
+            .collect(),
+    }
+}
+
+/// Protobuf only has doubles, so whole numbers are turned back into JSON integers
+fn to_json_number(number: f64) -> Result<Number, ProtobufError> {
+    if number.fract() == 0.0 && number.abs() < 2_f64.powi(53) {
+        Ok(Number::from(number as i64))
+    } else {
+        Number::from_f64(number).ok_or(ProtobufError::InvalidNumber(number))
+    }
+}
+
+fn to_json_value(value: prost_types::Value) -> Result<Value, ProtobufError> {
+    Ok(match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(value)) => Value::Bool(value),
+        Some(Kind::NumberValue(number)) => Value::Number(to_json_number(number)?),
+        Some(Kind::StringValue(value)) => Value::String(value),
+        Some(Kind::ListValue(list)) => Value::Array(
+            list.values
+                .into_iter()
+                .map(to_json_value)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some(Kind::StructValue(fields)) => Value::Object(to_json_object(fields)?),
+    })
+}
+
+fn to_json_object(fields: Struct) -> Result<Map<String, Value>, ProtobufError> {
+    fields
+        .fields
+        .into_iter()
+        .map(|(key, value)| Ok((key, to_json_value(value)?)))
+        .collect()
+}
+
+fn to_player_id(id: String) -> Result<PlayerId, ProtobufError> {
+    id.parse().map_err(|_| ProtobufError::InvalidPlayerId(id))
+}
+
+fn to_room_id(id: String) -> Result<RoomId, ProtobufError> {
+    id.parse().map_err(|_| ProtobufError::InvalidRoomId(id))
+}
+
+fn to_invitation_id(id: String) -> Result<InvitationId, ProtobufError> {
+    id.parse()
+        .map_err(|_| ProtobufError::InvalidInvitationId(id))
+}
+
+fn to_pb_visibility(visibility: Visibility) -> pb::Visibility {
+    match visibility {
+        Visibility::Public => pb::Visibility::Public,
+        Visibility::Unlisted => pb::Visibility::Unlisted,
+        Visibility::Private => pb::Visibility::Private,
+    }
+}
+
+fn to_visibility(visibility: i32) -> Result<Visibility, ProtobufError> {
+    match pb::Visibility::try_from(visibility) {
+        Ok(pb::Visibility::Public) => Ok(Visibility::Public),
+        Ok(pb::Visibility::Unlisted) => Ok(Visibility::Unlisted),
+        Ok(pb::Visibility::Private) => Ok(Visibility::Private),
+        Err(_) => Err(ProtobufError::InvalidVisibility(visibility)),
+    }
+}
+
+fn to_pb_channel(channel: &ChatChannel) -> Option<pb::ChatChannel> {
+    use pb::chat_channel::{Channel, Party, Team};
+
+    let channel = match channel {
+        ChatChannel::Room => return None,
+        ChatChannel::Team => Channel::Team(Team {}),
+        ChatChannel::Party => Channel::Party(Party {}),
+        ChatChannel::Whisper(player_id) => Channel::Whisper(player_id.to_string()),
+    };
+    Some(pb::ChatChannel {
+        channel: Some(channel),
+    })
+}
+
+fn to_channel(channel: Option<pb::ChatChannel>) -> Result<ChatChannel, ProtobufError> {
+    use pb::chat_channel::Channel;
+
+    Ok(match channel.and_then(|channel| channel.channel) {
+        None => ChatChannel::Room,
+        Some(Channel::Team(_)) => ChatChannel::Team,
+        Some(Channel::Party(_)) => ChatChannel::Party,
+        Some(Channel::Whisper(player_id)) => ChatChannel::Whisper(to_player_id(player_id)?),
+    })
+}
+
+fn to_pb_chat(message: &ChatMessage) -> pb::server_message::ChatMessage {
+    pb::server_message::ChatMessage {
+        from: message.from.into(),
+        from_player: message.from_player.map(|id| id.to_string()),
+        text: message.text.clone(),
+        sent_at: message.sent_at,
+        channel: to_pb_channel(&message.channel),
+    }
+}
+
+fn to_chat(message: pb::server_message::ChatMessage) -> Result<ChatMessage, ProtobufError> {
+    Ok(ChatMessage {
+        from: message.from.into(),
+        from_player: message.from_player.map(to_player_id).transpose()?,
+        text: message.text,
+        channel: to_channel(message.channel)?,
+        sent_at: message.sent_at,
+    })
+}
+
+impl From<&ClientEnvelope> for pb::ClientEnvelope {
+    fn from(envelope: &ClientEnvelope) -> Self {
+        use pb::client_envelope::{
+            AnswerInvitation, BalanceTeams, BanPlayer, Broadcast, Chat, CloseRoom, CreateInvite,
+            KickPlayer, MutePlayer, React, SetCustomData, UnmutePlayer, UpdateSettings, VoteKick,
+        };
+
+        let message = match &envelope.message {
+            ClientMessage::Broadcast { payload } => ClientKind::Broadcast(Broadcast {
+                payload: Some(to_pb_value(payload)),
+            }),
+            ClientMessage::CloseRoom => ClientKind::CloseRoom(CloseRoom {}),
+            ClientMessage::BanPlayer {
+                player_id,
+                reason,
+                duration_secs,
+            } => ClientKind::BanPlayer(BanPlayer {
+                player_id: player_id.to_string(),
+                reason: reason.clone(),
+                duration_secs: *duration_secs,
+            }),
+            ClientMessage::UpdateSettings {
+                private,
+                name,
+                visibility,
+            } => ClientKind::UpdateSettings(UpdateSettings {
+                private: *private,
+                name: name.clone(),
+                visibility: visibility.map(|visibility| to_pb_visibility(visibility) as i32),
+            }),
+            ClientMessage::CreateInvite { ttl_secs, max_uses } => {
+                ClientKind::CreateInvite(CreateInvite {
+                    ttl_secs: *ttl_secs,
+                    max_uses: *max_uses,
+                })
+            }
+            ClientMessage::Chat { text, channel } => ClientKind::Chat(Chat {
+                text: text.clone(),
+                channel: to_pb_channel(channel),
+            }),
+            ClientMessage::MutePlayer {
+                player_id,
+                duration_secs,
+            } => ClientKind::MutePlayer(MutePlayer {
+                player_id: player_id.to_string(),
+                duration_secs: *duration_secs,
+            }),
+            ClientMessage::UnmutePlayer { player_id } => ClientKind::UnmutePlayer(UnmutePlayer {
+                player_id: player_id.to_string(),
+            }),
+            ClientMessage::KickPlayer { player_id, ban } => ClientKind::KickPlayer(KickPlayer {
+                player_id: player_id.to_string(),
+                ban: *ban,
+            }),
+            ClientMessage::React { emote } => ClientKind::React(React {
+                emote: emote.clone(),
+            }),
+            ClientMessage::AnswerInvitation {
+                invitation_id,
+                accept,
+            } => ClientKind::AnswerInvitation(AnswerInvitation {
+                invitation_id: invitation_id.to_string(),
+                accept: *accept,
+            }),
+            ClientMessage::SetCustomData { data } => ClientKind::SetCustomData(SetCustomData {
+                data: Some(to_pb_struct(data)),
+            }),
+            ClientMessage::VoteKick { player_id } => ClientKind::VoteKick(VoteKick {
+                player_id: player_id.to_string(),
+            }),
+            ClientMessage::BalanceTeams { teams } => ClientKind::BalanceTeams(BalanceTeams {
+                teams: teams.clone(),
+            }),
+        };
+        Self {
+            sender: envelope.sender.map(|sender| sender.to_string()),
+            message: Some(message),
+        }
+    }
+}
+
+impl TryFrom<pb::ClientEnvelope> for ClientEnvelope {
+    type Error = ProtobufError;
+
+    fn try_from(envelope: pb::ClientEnvelope) -> Result<Self, Self::Error> {
+        let message = match envelope.message.ok_or(ProtobufError::MissingMessage)? {
+            ClientKind::Broadcast(broadcast) => ClientMessage::Broadcast {
+                payload: broadcast
+                    .payload
+                    .map(to_json_value)
+                    .transpose()?
+                    .unwrap_or_default(),
+            },
+            ClientKind::CloseRoom(_) => ClientMessage::CloseRoom,
+            ClientKind::BanPlayer(ban) => ClientMessage::BanPlayer {
+                player_id: to_player_id(ban.player_id)?,
+                reason: ban.reason,
+                duration_secs: ban.duration_secs,
+            },
+            ClientKind::UpdateSettings(settings) => ClientMessage::UpdateSettings {
+                private: settings.private,
+                name: settings.name,
+                visibility: settings.visibility.map(to_visibility).transpose()?,
+            },
+            ClientKind::CreateInvite(invite) => ClientMessage::CreateInvite {
+                ttl_secs: invite.ttl_secs,
+                max_uses: invite.max_uses,
+            },
+            ClientKind::Chat(chat) => ClientMessage::Chat {
+                text: chat.text,
+                channel: to_channel(chat.channel)?,
+            },
+            ClientKind::MutePlayer(mute) => ClientMessage::MutePlayer {
+                player_id: to_player_id(mute.player_id)?,
+                duration_secs: mute.duration_secs,
+            },
+            ClientKind::UnmutePlayer(unmute) => ClientMessage::UnmutePlayer {
+                player_id: to_player_id(unmute.player_id)?,
+            },
+            ClientKind::KickPlayer(kick) => ClientMessage::KickPlayer {
+                player_id: to_player_id(kick.player_id)?,
+                ban: kick.ban,
+            },
+            ClientKind::React(react) => ClientMessage::React { emote: react.emote },
+            ClientKind::AnswerInvitation(answer) => ClientMessage::AnswerInvitation {
+                invitation_id: to_invitation_id(answer.invitation_id)?,
+                accept: answer.accept,
+            },
+            ClientKind::SetCustomData(custom) => ClientMessage::SetCustomData {
+                data: custom
+                    .data
+                    .map(to_json_object)
+                    .transpose()?
+                    .unwrap_or_default(),
+            },
+            ClientKind::VoteKick(vote) => ClientMessage::VoteKick {
+                player_id: to_player_id(vote.player_id)?,
+            },
+            ClientKind::BalanceTeams(balance) => ClientMessage::BalanceTeams {
+                teams: balance.teams,
+            },
+        };
+        Ok(Self {
+            sender: envelope.sender.map(to_player_id).transpose()?,
+            message,
+        })
+    }
+}
+
+impl From<&ServerMessage<'_>> for pb::ServerMessage {
+    fn from(message: &ServerMessage<'_>) -> Self {
+        use pb::server_message::{
+            Announcement, Batch, Broadcast, ChatHistory, CustomData, EncodedBroadcast, Error,
+            Invitation, InvitationAnswered, KickVote, KickVoteEnded, Migrate, Reaction,
+            ReplayUnavailable, TeamsBalanced, Welcome,
+        };
+
+        let message = match message {
+            ServerMessage::Welcome {
+                connection_id,
+                protocol_version,
+                player_id,
+            } => ServerKind::Welcome(Welcome {
+                connection_id: (*connection_id).into(),
+                protocol_version: *protocol_version,
+                player_id: player_id.map(|id| id.to_string()),
+            }),
+            ServerMessage::Broadcast {
+                from,
+                from_player,
+                payload,
+                seq,
+            } => ServerKind::Broadcast(Broadcast {
+                from: (*from).into(),
+                from_player: from_player.map(|id| id.to_string()),
+                payload: Some(to_pb_value(payload)),
+                seq: *seq,
+            }),
+            ServerMessage::EncodedBroadcast {
+                from,
+                from_player,
+                codec,
+                payload,
+                seq,
+            } => ServerKind::EncodedBroadcast(EncodedBroadcast {
+                from: (*from).into(),
+                from_player: from_player.map(|id| id.to_string()),
+                codec: codec.clone(),
+                payload: payload.to_vec(),
+                seq: *seq,
+            }),
+            ServerMessage::Invite(invite) => ServerKind::Invite(pb::server_message::Invite {
+                token: invite.token.clone(),
+                expires_at: invite.expires_at,
+            }),
+            ServerMessage::Error { reason } => ServerKind::Error(Error {
+                reason: reason.clone(),
+            }),
+            ServerMessage::Batch { messages } => ServerKind::Batch(Batch {
+                messages: messages.iter().map(Into::into).collect(),
+            }),
+            ServerMessage::Migrate { url, invite } => ServerKind::Migrate(Migrate {
+                url: url.clone(),
+                invite: Some(pb::server_message::Invite {
+                    token: invite.token.clone(),
+                    expires_at: invite.expires_at,
+                }),
+            }),
+            ServerMessage::Chat(chat) => ServerKind::Chat(to_pb_chat(chat)),
+            ServerMessage::ChatHistory { messages } => ServerKind::ChatHistory(ChatHistory {
+                messages: messages.iter().map(to_pb_chat).collect(),
+            }),
+            ServerMessage::Reaction {
+                from,
+                from_player,
+                emote,
+            } => ServerKind::Reaction(Reaction {
+                from: (*from).into(),
+                from_player: from_player.map(|id| id.to_string()),
+                emote: emote.clone(),
+            }),
+            ServerMessage::Invitation(invitation) => ServerKind::Invitation(Invitation {
+                id: invitation.id.to_string(),
+                from: invitation.from.to_string(),
+                room_id: invitation.room_id.to_string(),
+                invite: Some(pb::server_message::Invite {
+                    token: invitation.invite.token.clone(),
+                    expires_at: invitation.invite.expires_at,
+                }),
+            }),
+            ServerMessage::InvitationAnswered {
+                invitation_id,
+                player_id,
+                accepted,
+            } => ServerKind::InvitationAnswered(InvitationAnswered {
+                invitation_id: invitation_id.to_string(),
+                player_id: player_id.to_string(),
+                accepted: *accepted,
+            }),
+            ServerMessage::Announcement { text } => {
+                ServerKind::Announcement(Announcement { text: text.clone() })
+            }
+            ServerMessage::ReplayUnavailable { latest_seq } => {
+                ServerKind::ReplayUnavailable(ReplayUnavailable {
+                    latest_seq: *latest_seq,
+                })
+            }
+            ServerMessage::CustomData { data } => ServerKind::CustomData(CustomData {
+                data: Some(to_pb_struct(data)),
+            }),
+            ServerMessage::KickVote {
+                player_id,
+                votes,
+                votes_needed,
+                ends_at,
+            } => ServerKind::KickVote(KickVote {
+                player_id: player_id.to_string(),
+                votes: *votes,
+                votes_needed: *votes_needed,
+                ends_at: *ends_at,
+            }),
+            ServerMessage::KickVoteEnded { player_id, kicked } => {
+                ServerKind::KickVoteEnded(KickVoteEnded {
+                    player_id: player_id.to_string(),
+                    kicked: *kicked,
+                })
+            }
+            ServerMessage::TeamsBalanced { teams } => ServerKind::TeamsBalanced(TeamsBalanced {
+                teams: teams
+                    .iter()
+                    .map(|(player_id, team)| (player_id.to_string(), team.clone()))
+                    .collect(),
+            }),
+        };
+        Self {
+            message: Some(message),
+        }
+    }
+}
+
+impl TryFrom<pb::ServerMessage> for ServerMessage<'static> {
+    type Error = ProtobufError;
+
+    fn try_from(message: pb::ServerMessage) -> Result<Self, ProtobufError> {
+        Ok(
+            match message.message.ok_or(ProtobufError::MissingMessage)? {
+                ServerKind::Welcome(welcome) => ServerMessage::Welcome {
+                    connection_id: welcome.connection_id.into(),
+                    protocol_version: welcome.protocol_version,
+                    player_id: welcome.player_id.map(to_player_id).transpose()?,
+                },
+                ServerKind::Broadcast(broadcast) => ServerMessage::Broadcast {
+                    from: broadcast.from.into(),
+                    from_player: broadcast.from_player.map(to_player_id).transpose()?,
+                    payload: Cow::Owned(
+                        broadcast
+                            .payload
+                            .map(to_json_value)
+                            .transpose()?
+                            .unwrap_or_default(),
+                    ),
+                    seq: broadcast.seq,
+                },
+                ServerKind::EncodedBroadcast(broadcast) => ServerMessage::EncodedBroadcast {
+                    from: broadcast.from.into(),
+                    from_player: broadcast.from_player.map(to_player_id).transpose()?,
+                    codec: broadcast.codec,
+                    payload: Cow::Owned(broadcast.payload),
+                    seq: broadcast.seq,
+                },
+                ServerKind::Invite(invite) => ServerMessage::Invite(Invite {
+                    token: invite.token,
+                    expires_at: invite.expires_at,
+                }),
+                ServerKind::Error(error) => ServerMessage::Error {
+                    reason: error.reason,
+                },
+                ServerKind::Batch(batch) => ServerMessage::Batch {
+                    messages: batch
+                        .messages
+                        .into_iter()
+                        .map(TryInto::try_into)
+                        .collect::<Result<_, _>>()?,
+                },
+                ServerKind::Migrate(migrate) => {
+                    let invite = migrate.invite.unwrap_or_default();
+                    ServerMessage::Migrate {
+                        url: migrate.url,
+                        invite: Invite {
+                            token: invite.token,
+                            expires_at: invite.expires_at,
+                        },
+                    }
+                }
+                ServerKind::Chat(chat) => ServerMessage::Chat(to_chat(chat)?),
+                ServerKind::ChatHistory(history) => ServerMessage::ChatHistory {
+                    messages: history
+                        .messages
+                        .into_iter()
+                        .map(to_chat)
+                        .collect::<Result<_, _>>()?,
+                },
+                ServerKind::Reaction(reaction) => ServerMessage::Reaction {
+                    from: reaction.from.into(),
+                    from_player: reaction.from_player.map(to_player_id).transpose()?,
+                    emote: reaction.emote,
+                },
+                ServerKind::Invitation(invitation) => {
+                    let invite = invitation.invite.unwrap_or_default();
+                    ServerMessage::Invitation(RoomInvitation {
+                        id: to_invitation_id(invitation.id)?,
+                        from: to_player_id(invitation.from)?,
+                        room_id: to_room_id(invitation.room_id)?,
+                        invite: Invite {
+                            token: invite.token,
+                            expires_at: invite.expires_at,
+                        },
+                    })
+                }
+                ServerKind::InvitationAnswered(answered) => ServerMessage::InvitationAnswered {
+                    invitation_id: to_invitation_id(answered.invitation_id)?,
+                    player_id: to_player_id(answered.player_id)?,
+                    accepted: answered.accepted,
+                },
+                ServerKind::Announcement(announcement) => ServerMessage::Announcement {
+                    text: announcement.text,
+                },
+                ServerKind::ReplayUnavailable(unavailable) => ServerMessage::ReplayUnavailable {
+                    latest_seq: unavailable.latest_seq,
+                },
+                ServerKind::CustomData(custom) => ServerMessage::CustomData {
+                    data: custom
+                        .data
+                        .map(to_json_object)
+                        .transpose()?
+                        .unwrap_or_default(),
+                },
+                ServerKind::KickVote(vote) => ServerMessage::KickVote {
+                    player_id: to_player_id(vote.player_id)?,
+                    votes: vote.votes,
+                    votes_needed: vote.votes_needed,
+                    ends_at: vote.ends_at,
+                },
+                ServerKind::KickVoteEnded(ended) => ServerMessage::KickVoteEnded {
+                    player_id: to_player_id(ended.player_id)?,
+                    kicked: ended.kicked,
+                },
+                ServerKind::TeamsBalanced(balanced) => ServerMessage::TeamsBalanced {
+                    teams: balanced
+                        .teams
+                        .into_iter()
+                        .map(|(player_id, team)| Ok((to_player_id(player_id)?, team)))
+                        .collect::<Result<_, ProtobufError>>()?,
+                },
+            },
+        )
+    }
+}
+
+impl ClientEnvelope {
+    pub fn encode_protobuf(&self) -> Vec<u8> {
+        pb::ClientEnvelope::from(self).encode_to_vec()
+    }
+
+    pub fn decode_protobuf(bytes: &[u8]) -> Result<Self, ProtobufError> {
+        pb::ClientEnvelope::decode(bytes)?.try_into()
+    }
+}
+
+impl ServerMessage<'_> {
+    pub fn encode_protobuf(&self) -> Vec<u8> {
+        pb::ServerMessage::from(self).encode_to_vec()
+    }
+}
+
+impl ServerMessage<'static> {
+    pub fn decode_protobuf(bytes: &[u8]) -> Result<Self, ProtobufError> {
+        pb::ServerMessage::decode(bytes)?.try_into()
+    }
+}
+
+#[cfg(test)]
+mod client_envelope {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_every_message() {
+        let messages = [
+            ClientMessage::Broadcast {
+                payload: json!({ "x": 1, "y": [true, null, "z", 1.5] }),
+            },
+            ClientMessage::CloseRoom,
+            ClientMessage::BanPlayer {
+                player_id: PlayerId::from(2),
+                reason: "spam".to_owned(),
+                duration_secs: Some(60),
+            },
+            ClientMessage::UpdateSettings {
+                private: true,
+                name: Some("Friday night".to_owned()),
+                visibility: None,
+            },
+            ClientMessage::UpdateSettings {
+                private: false,
+                name: None,
+                visibility: Some(Visibility::Unlisted),
+            },
+            ClientMessage::CreateInvite {
+                ttl_secs: None,
+                max_uses: Some(3),
+            },
+            ClientMessage::Chat {
+                text: "gg".to_owned(),
+                channel: ChatChannel::Room,
+            },
+            ClientMessage::Chat {
+                text: "go left".to_owned(),
+                channel: ChatChannel::Team,
+            },
+            ClientMessage::Chat {
+                text: "psst".to_owned(),
+                channel: ChatChannel::Whisper(PlayerId::from(3)),
+            },
+            ClientMessage::MutePlayer {
+                player_id: PlayerId::from(2),
+                duration_secs: Some(300),
+            },
+            ClientMessage::UnmutePlayer {
+                player_id: PlayerId::from(2),
+            },
+            ClientMessage::KickPlayer {
+                player_id: PlayerId::from(2),
+                ban: true,
+            },
+            ClientMessage::React {
+                emote: "thumbs_up".to_owned(),
+            },
+            ClientMessage::AnswerInvitation {
+                invitation_id: InvitationId::from(4),
+                accept: true,
+            },
+            ClientMessage::SetCustomData {
+                data: json!({ "map": "de_dust2", "mods": ["no_fog"], "rounds": 12 })
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            },
+            ClientMessage::VoteKick {
+                player_id: PlayerId::from(2),
+            },
+            ClientMessage::BalanceTeams {
+                teams: vec!["red".to_owned(), "blue".to_owned()],
+            },
+        ];
+
+        for message in messages {
+            let envelope = ClientEnvelope {
+                sender: Some(PlayerId::from(1)),
+                message,
+            };
+            assert_eq!(
+                ClientEnvelope::decode_protobuf(&envelope.encode_protobuf()),
+                Ok(envelope)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_messages() {
+        assert_eq!(
+            ClientEnvelope::decode_protobuf(&[]),
+            Err(ProtobufError::MissingMessage)
+        );
+        assert!(matches!(
+            ClientEnvelope::decode_protobuf(&[0xff]),
+            Err(ProtobufError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_player_ids() {
+        let envelope = pb::ClientEnvelope {
+            sender: Some("someone".to_owned()),
+            message: Some(ClientKind::CloseRoom(pb::client_envelope::CloseRoom {})),
+        };
+
+        assert_eq!(
+            ClientEnvelope::decode_protobuf(&envelope.encode_to_vec()),
+            Err(ProtobufError::InvalidPlayerId("someone".to_owned()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod server_message {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_batches() {
+        let payload = json!({ "scores": [1, -2, 3.25], "name": "x" });
+        let message = ServerMessage::Batch {
+            messages: vec![
+                ServerMessage::Welcome {
+                    connection_id: 1.into(),
+                    protocol_version: 2,
+                    player_id: Some(PlayerId::from(3)),
+                },
+                ServerMessage::Broadcast {
+                    from: 4.into(),
+                    from_player: None,
+                    payload: Cow::Borrowed(&payload),
+                    seq: Some(16),
+                },
+                ServerMessage::EncodedBroadcast {
+                    from: 4.into(),
+                    from_player: Some(PlayerId::from(3)),
+                    codec: "msgpack".to_owned(),
+                    payload: Cow::Borrowed(&[0x81, 0xa1, 0x78, 0x01]),
+                    seq: Some(17),
+                },
+                ServerMessage::Invite(Invite {
+                    token: "token".to_owned(),
+                    expires_at: 5,
+                }),
+                ServerMessage::Error {
+                    reason: "nope".to_owned(),
+                },
+                ServerMessage::Announcement {
+                    text: "Restarting soon".to_owned(),
+                },
+                ServerMessage::ReplayUnavailable { latest_seq: 17 },
+                ServerMessage::CustomData {
+                    data: json!({ "map": "arena", "teams": 2 })
+                        .as_object()
+                        .unwrap()
+                        .clone(),
+                },
+                ServerMessage::Migrate {
+                    url: "https://b.example.com/ws/1".to_owned(),
+                    invite: Invite {
+                        token: "resume".to_owned(),
+                        expires_at: 6,
+                    },
+                },
+                ServerMessage::ChatHistory {
+                    messages: vec![ChatMessage {
+                        from: 7.into(),
+                        from_player: Some(PlayerId::from(8)),
+                        text: "gg".to_owned(),
+                        channel: ChatChannel::Whisper(PlayerId::from(1)),
+                        sent_at: 9,
+                    }],
+                },
+                ServerMessage::Reaction {
+                    from: 10.into(),
+                    from_player: None,
+                    emote: "thumbs_up".to_owned(),
+                },
+                ServerMessage::Invitation(RoomInvitation {
+                    id: InvitationId::from(11),
+                    from: PlayerId::from(12),
+                    room_id: RoomId::from(13),
+                    invite: Invite {
+                        token: "seat".to_owned(),
+                        expires_at: 14,
+                    },
+                }),
+                ServerMessage::InvitationAnswered {
+                    invitation_id: InvitationId::from(11),
+                    player_id: PlayerId::from(15),
+                    accepted: false,
+                },
+                ServerMessage::KickVote {
+                    player_id: PlayerId::from(16),
+                    votes: 1,
+                    votes_needed: 3,
+                    ends_at: 17,
+                },
+                ServerMessage::KickVoteEnded {
+                    player_id: PlayerId::from(16),
+                    kicked: true,
+                },
+                ServerMessage::TeamsBalanced {
+                    teams: [(PlayerId::from(1), "red"), (PlayerId::from(2), "blue")]
+                        .into_iter()
+                        .map(|(player_id, team)| (player_id, team.to_owned()))
+                        .collect(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            ServerMessage::decode_protobuf(&message.encode_protobuf()),
+            Ok(message)
+        );
+    }
+
+    #[test]
+    fn keeps_whole_numbers_as_integers() {
+        assert_eq!(
+            to_json_value(to_pb_value(&json!([1, 2.5]))),
+            Ok(json!([1, 2.5]))
+        );
+        assert!(to_json_value(to_pb_value(&json!(1))).unwrap().is_i64());
+    }
+
+    #[test]
+    fn rejects_numbers_json_cant_hold() {
+        let value = prost_types::Value {
+            kind: Some(Kind::NumberValue(f64::NAN)),
+        };
+        assert!(matches!(
+            to_json_value(value),
+            Err(ProtobufError::InvalidNumber(_))
+        ));
+    }
+}
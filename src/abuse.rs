@@ -0,0 +1,320 @@
+//! Scoring how much each connection abuses the server, and what's done about it
+//!
+//! Every [offence][Offence] a connection commits, whether an invalid action, an oversized
+//! frame or hitting a rate limit, adds to its abuse score, and stops counting towards it once
+//! it's older than the rules' [window][AbuseRules]. As the score climbs it crosses each of the
+//! rules' thresholds in turn: the connection is warned, then refused any action for a while,
+//! then disconnected, with a report of its player made for moderators to review if it's
+//! signed in. Scores are kept by [who the client is][ClientKey], its player or else its
+//! address, rather than by connection, so reconnecting doesn't wipe the slate clean.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::rate_limit::ClientKey;
+
+/// How many clients' scores are kept before those with nothing left against them start being
+/// forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Something a connection did that counts towards its abuse score
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Offence {
+    /// A message that couldn't be read, claimed someone else's identity or asked for
+    /// something the connection isn't permitted to do
+    InvalidAction,
+    /// A frame bigger than the rules allow
+    OversizedFrame,
+    /// A message sent once the connection's budget had been used up, or while it was locked
+    RateLimited,
+}
+
+impl Offence {
+    const ALL: [Offence; 3] = [
+        Offence::InvalidAction,
+        Offence::OversizedFrame,
+        Offence::RateLimited,
+    ];
+
+    /// How much the offence adds to the score
+    pub fn points(self) -> u32 {
+        match self {
+            Offence::InvalidAction => 2,
+            Offence::OversizedFrame => 3,
+            Offence::RateLimited => 1,
+        }
+    }
+}
+
+impl Display for Offence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Offence::InvalidAction => "invalid actions",
+            Offence::OversizedFrame => "oversized frames",
+            Offence::RateLimited => "rate limit hits",
+        })
+    }
+}
+
+/// When a connection's abuse score is acted on, and how
+#[derive(Debug, PartialEq, Clone)]
+pub struct AbuseRules {
+    /// How long each offence counts towards the score
+    pub window: Duration,
+    /// The score at which the connection is warned
+    pub warn_at: u32,
+    /// The score at which the connection is refused any action for the lock duration
+    pub lock_at: u32,
+    pub lock_duration: Duration,
+    /// The score at which the connection is disconnected and its player reported
+    pub disconnect_at: u32,
+    /// The biggest frame a client may send, in bytes, bigger ones being refused unread
+    pub max_frame_bytes: usize,
+}
+
+impl Default for AbuseRules {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            warn_at: 4,
+            lock_at: 6,
+            lock_duration: Duration::from_secs(10),
+            disconnect_at: 10,
+            max_frame_bytes: 32 * 1024,
+        }
+    }
+}
+
+/// What's done about a connection once it has committed an offence
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum AbuseVerdict {
+    /// Nothing yet
+    Tolerated,
+    /// The connection should be told to behave
+    Warned,
+    /// The connection is refused any action for the duration
+    Locked(Duration),
+    /// The connection should be closed, and its player reported
+    Disconnected,
+}
+
+/// The offences a single client has committed lately
+#[derive(Debug, Default)]
+pub struct AbuseScore {
+    /// Each offence and when it was committed, oldest first
+    offences: VecDeque<(Instant, Offence)>,
+    /// When the connection may take actions again, if it has been locked
+    locked_until: Option<Instant>,
+}
+
+impl AbuseScore {
+    fn forget_old_offences(&mut self, rules: &AbuseRules, now: Instant) {
+        while let Some(&(committed_at, _)) = self.offences.front() {
+            if now.duration_since(committed_at) < rules.window {
+                break;
+            }
+            self.offences.pop_front();
+        }
+    }
+
+    /// The connection's score, counting the offences within the window
+    pub fn score(&self) -> u32 {
+        self.offences
+            .iter()
+            .map(|(_, offence)| offence.points())
+            .sum()
+    }
+
+    /// Scores the offence committed at `now`, returning what's to be done about it
+    pub fn record(&mut self, rules: &AbuseRules, offence: Offence, now: Instant) -> AbuseVerdict {
+        self.forget_old_offences(rules, now);
+        self.offences.push_back((now, offence));
+        let score = self.score();
+        if score >= rules.disconnect_at {
+            AbuseVerdict::Disconnected
+        } else if score >= rules.lock_at {
+            self.locked_until = Some(now + rules.lock_duration);
+            AbuseVerdict::Locked(rules.lock_duration)
+        } else if score >= rules.warn_at {
+            AbuseVerdict::Warned
+        } else {
+            AbuseVerdict::Tolerated
+        }
+    }
+
+    /// How much longer the connection is refused any action at `now`, if it's locked
+    pub fn locked_for(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|&locked_until| locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+
+    /// Sums up the offences within the window, for the report made when the connection is
+    /// disconnected
+    pub fn summary(&self) -> String {
+        let counts: Vec<_> = Offence::ALL
+            .into_iter()
+            .filter_map(|kind| {
+                let count = self
+                    .offences
+                    .iter()
+                    .filter(|(_, offence)| *offence == kind)
+                    .count();
+                (count > 0).then(|| format!("{count} {kind}"))
+            })
+            .collect();
+        format!(
+            "Disconnected automatically for abuse, with a score of {} from {}",
+            self.score(),
+            counts.join(", ")
+        )
+    }
+}
+
+/// Every client's [abuse score][AbuseScore], shared by all of its connections to this
+/// instance
+#[derive(Debug, Default)]
+pub struct AbuseScores {
+    scores: Mutex<HashMap<ClientKey, AbuseScore>>,
+}
+
+impl AbuseScores {
+    /// Scores the offence the client committed at `now`, returning what's to be done about it
+    /// and the client's score
+    pub fn record(
+        &self,
+        key: &ClientKey,
+        rules: &AbuseRules,
+        offence: Offence,
+        now: Instant,
+    ) -> (AbuseVerdict, u32) {
+        let mut scores = self.scores.lock().unwrap();
+        if scores.len() > MAX_TRACKED_CLIENTS {
+            scores.retain(|_, score| {
+                score.forget_old_offences(rules, now);
+                !score.offences.is_empty() || score.locked_for(now).is_some()
+            });
+        }
+        let score = scores.entry(key.clone()).or_default();
+        let verdict = score.record(rules, offence, now);
+        (verdict, score.score())
+    }
+
+    /// How much longer the client is refused any action at `now`, if it's locked
+    pub fn locked_for(&self, key: &ClientKey, now: Instant) -> Option<Duration> {
+        self.scores.lock().unwrap().get(key)?.locked_for(now)
+    }
+
+    /// [Sums up][AbuseScore::summary] the client's offences within the window
+    pub fn summary(&self, key: &ClientKey) -> String {
+        self.scores
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(AbuseScore::summary)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod record {
+    use super::*;
+
+    fn rules() -> AbuseRules {
+        AbuseRules {
+            window: Duration::from_secs(60),
+            warn_at: 2,
+            lock_at: 4,
+            lock_duration: Duration::from_secs(10),
+            disconnect_at: 6,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn escalates_as_the_score_climbs() {
+        let rules = rules();
+        let mut score = AbuseScore::default();
+        let now = Instant::now();
+
+        let verdicts: Vec<_> = (0..4)
+            .map(|_| score.record(&rules, Offence::InvalidAction, now))
+            .collect();
+
+        assert_eq!(
+            verdicts,
+            [
+                AbuseVerdict::Warned,
+                AbuseVerdict::Locked(Duration::from_secs(10)),
+                AbuseVerdict::Disconnected,
+                AbuseVerdict::Disconnected,
+            ]
+        );
+        assert_eq!(
+            score.locked_for(now + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(score.locked_for(now + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn forgets_offences_older_than_the_window() {
+        let rules = rules();
+        let mut score = AbuseScore::default();
+        let now = Instant::now();
+
+        score.record(&rules, Offence::RateLimited, now);
+        let verdict = score.record(&rules, Offence::RateLimited, now + Duration::from_secs(60));
+
+        assert_eq!(verdict, AbuseVerdict::Tolerated);
+        assert_eq!(score.score(), 1);
+    }
+
+    #[test]
+    fn sums_up_the_offences() {
+        let rules = rules();
+        let mut score = AbuseScore::default();
+        let now = Instant::now();
+        score.record(&rules, Offence::RateLimited, now);
+        score.record(&rules, Offence::RateLimited, now);
+        score.record(&rules, Offence::OversizedFrame, now);
+
+        assert_eq!(
+            score.summary(),
+            "Disconnected automatically for abuse, with a score of 5 from 1 oversized frames, 2 rate limit hits"
+        );
+    }
+}
+
+#[cfg(test)]
+mod abuse_scores {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn keeps_scoring_clients_across_connections() {
+        let rules = AbuseRules {
+            warn_at: 2,
+            lock_at: 4,
+            disconnect_at: 6,
+            ..Default::default()
+        };
+        let scores = AbuseScores::default();
+        let client = ClientKey::Player(1_u128.into());
+        let other = ClientKey::Address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            scores.record(&client, &rules, Offence::InvalidAction, now);
+        }
+        // As though the client had reconnected, and someone else misbehaved meanwhile
+        scores.record(&other, &rules, Offence::RateLimited, now);
+        let verdict = scores.record(&client, &rules, Offence::InvalidAction, now);
+
+        assert_eq!(verdict, (AbuseVerdict::Disconnected, 6));
+        assert!(scores.locked_for(&client, now).is_some());
+        assert_eq!(scores.locked_for(&other, now), None);
+    }
+}
@@ -0,0 +1,53 @@
+//! The global allocator selected through cargo features, and the statistics it reports
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("The jemalloc and mimalloc features are mutually exclusive");
+
+#[cfg(feature = "jemalloc")]
+pub type GlobalAllocator = tikv_jemallocator::Jemalloc;
+
+#[cfg(feature = "mimalloc")]
+pub type GlobalAllocator = mimalloc::MiMalloc;
+
+/// The name of the allocator the server is running with
+pub const NAME: &str = if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else {
+    "system"
+};
+
+/// A point in time view of the allocator's memory usage, in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    /// Bytes handed out to the application
+    pub allocated: usize,
+    /// Bytes in pages the allocator is actively using for allocations
+    pub active: usize,
+    /// Bytes of physical memory backing the allocator's pages
+    pub resident: usize,
+    /// Bytes mapped from the operating system
+    pub mapped: usize,
+}
+
+/// Reads the allocator's current statistics, if the selected allocator reports any
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Option<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc caches its statistics until the epoch is advanced
+    epoch::advance().ok()?;
+    Some(AllocatorStats {
+        allocated: stats::allocated::read().ok()?,
+        active: stats::active::read().ok()?,
+        resident: stats::resident::read().ok()?,
+        mapped: stats::mapped::read().ok()?,
+    })
+}
+
+/// Reads the allocator's current statistics, if the selected allocator reports any
+#[cfg(not(feature = "jemalloc"))]
+pub fn stats() -> Option<AllocatorStats> {
+    None
+}
@@ -0,0 +1,171 @@
+//! Announcements from the server's operators, such as warnings of upcoming maintenance, pushed
+//! to the connections in every room or only some of them
+//!
+//! An announcement is delivered to the rooms on the instance it's made on and, when instances
+//! share their lobby, [relayed][crate::cluster::RedisBridge::announce] to every other instance
+//! to deliver to theirs. Clients speaking protocol versions older than announcements are left
+//! out, and aren't counted among the connections reached.
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::game::{RoomId, RoomRegistry};
+use crate::protocol::ServerMessage;
+use crate::ws::ANNOUNCEMENTS_VERSION;
+
+/// The most characters an announcement may be
+pub const MAX_ANNOUNCEMENT_CHARS: usize = 1000;
+
+/// Why an announcement can't be made
+pub fn check_announcement_text(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        Err("Announcements can't be empty".to_owned())
+    } else if text.chars().count() > MAX_ANNOUNCEMENT_CHARS {
+        Err(format!(
+            "Announcements can be at most {MAX_ANNOUNCEMENT_CHARS} characters"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Which rooms an announcement is for, every room when neither is given
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AnnouncementScope {
+    /// Only these rooms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rooms: Option<Vec<RoomId>>,
+    /// Only rooms hosted by instances in these regions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regions: Option<Vec<String>>,
+}
+
+impl AnnouncementScope {
+    fn includes_region(&self, region: Option<&str>) -> bool {
+        self.regions.as_ref().is_none_or(|regions| {
+            region.is_some_and(|region| regions.iter().any(|wanted| wanted == region))
+        })
+    }
+
+    fn includes_room(&self, id: RoomId) -> bool {
+        self.rooms.as_ref().is_none_or(|rooms| rooms.contains(&id))
+    }
+}
+
+/// How far an announcement reached on one instance
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct AnnouncementReport {
+    pub rooms: usize,
+    /// The connections the announcement was queued for that speak a protocol version new
+    /// enough to be shown it
+    pub connections: usize,
+}
+
+/// Queues the announcement for every connection in the registry's rooms within `scope`
+pub fn announce(
+    registry: &RoomRegistry,
+    text: &str,
+    scope: &AnnouncementScope,
+) -> AnnouncementReport {
+    let mut report = AnnouncementReport::default();
+    if !scope.includes_region(registry.region()) {
+        return report;
+    }
+    let message = ServerMessage::Announcement {
+        text: text.to_owned(),
+    };
+    for summary in registry.list_active_rooms().iter() {
+        if !scope.includes_room(summary.id) {
+            continue;
+        }
+        let Some(room) = registry.get_room_for_id(summary.id) else {
+            continue;
+        };
+        let broadcaster = room.broadcaster();
+        match broadcaster.broadcast(&message) {
+            Ok(connections) => {
+                let outdated = broadcaster.subscribers_below(ANNOUNCEMENTS_VERSION);
+                report.rooms += 1;
+                report.connections += connections.saturating_sub(outdated);
+            }
+            Err(e) => warn!(event = "announcement_not_queued", room_id = %summary.id, error = %e),
+        }
+    }
+    info!(
+        event = "announcement_sent",
+        rooms = report.rooms,
+        connections = report.connections
+    );
+    report
+}
+
+#[cfg(test)]
+mod announce {
+    use super::*;
+    use crate::game::MessageQueue;
+
+    /// A registry holding two rooms with a connection each, whose queues are returned to keep
+    /// them connected
+    fn registry_with_connections(region: &str) -> (RoomRegistry, [RoomId; 2], Vec<MessageQueue>) {
+        let registry = RoomRegistry::new(4).with_region(region);
+        let rooms = [(); 2].map(|_| registry.create_room().unwrap());
+        let queues = rooms
+            .iter()
+            .map(|&id| {
+                registry
+                    .get_room_for_id(id)
+                    .unwrap()
+                    .broadcaster()
+                    .subscribe()
+                    .1
+            })
+            .collect();
+        (registry, rooms, queues)
+    }
+
+    #[test]
+    fn reaches_every_room_by_default() {
+        let (registry, _, _queues) = registry_with_connections("eu");
+
+        let report = announce(&registry, "Restarting soon", &Default::default());
+
+        assert_eq!(
+            report,
+            AnnouncementReport {
+                rooms: 2,
+                connections: 2
+            }
+        );
+    }
+
+    #[test]
+    fn leaves_out_connections_too_old_for_announcements() {
+        let (registry, [room, _], _queues) = registry_with_connections("eu");
+        let room = registry.get_room_for_id(room).unwrap();
+        let (outdated, _queue) = room.broadcaster().subscribe();
+        room.broadcaster()
+            .set_protocol_version(outdated, ANNOUNCEMENTS_VERSION - 1);
+
+        let report = announce(&registry, "Restarting soon", &Default::default());
+
+        assert_eq!(report.connections, 2);
+    }
+
+    #[test]
+    fn only_reaches_the_rooms_in_scope() {
+        let (registry, [room, _], _queues) = registry_with_connections("eu");
+
+        let scope = AnnouncementScope {
+            rooms: Some(vec![room]),
+            regions: Some(vec!["eu".to_owned()]),
+        };
+        assert_eq!(announce(&registry, "hi", &scope).rooms, 1);
+
+        let scope = AnnouncementScope {
+            rooms: None,
+            regions: Some(vec!["us".to_owned()]),
+        };
+        assert_eq!(announce(&registry, "hi", &scope).rooms, 0);
+    }
+}
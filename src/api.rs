@@ -0,0 +1,4801 @@
+//! Handlers for the REST API served under `api/v1`
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{Accept, Header, VARY};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::ResponseError;
+use actix_web::{body::BoxBody, mime, web, HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::{self, Sender};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream, ReceiverStream};
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::announcements::{self, check_announcement_text, AnnouncementScope};
+use crate::bans::{AuditAction, AuditEntry, Ban, BanId, BanList, BanTarget};
+use crate::cluster::{DrainReport, RoomPlacement};
+use crate::game::{
+    CreationStatus, CreationTicket, LobbyEvent, PlayerId, Room, RoomId, RoomState, RoomSummary,
+    SubmitCreationError, Turn, Visibility, ADMIN_CLOSED_REASON,
+};
+use crate::identity::PlayerAuthError;
+use crate::invitations::{deliver, deliver_answer, InvitationError, InvitationId, RoomInvitation};
+use crate::invites::{Invite, DEFAULT_INVITE_TTL};
+use crate::leaderboards::{LeaderboardEntry, LeaderboardPeriod, MAX_LEADERBOARD_PAGE};
+use crate::load::{CapacityReport, LoadReport};
+use crate::matchmaking::{EnqueueError, QueueTicket, TicketStatus};
+use crate::notifications::{is_online, PushNotification, PushReason};
+use crate::parties::{Party, PartyError, PartyId, PARTY_SEAT_TTL};
+use crate::problem::Problem;
+use crate::protocol::{ClientEnvelope, ServerMessage};
+use crate::ratings::{GameResult, GameResultError, Rating};
+use crate::reports::{
+    self, ModerationAction, NewReport, Report, ReportError, ReportId, ReportStatus,
+};
+use crate::request_body::OptionalJson;
+use crate::room_bans::RoomBan;
+use crate::scheduling::ScheduleError;
+use crate::sessions::{PlayerSessions, ADMIN_DISCONNECTED_REASON, BANNED_REASON};
+use crate::tournaments::{Tournament, TournamentError, TournamentId};
+use crate::turn_based::{DEFAULT_TURN_TIMEOUT, MAX_TURN_TIMEOUT};
+use crate::waiting_lists::{WaitingListUpdate, WAITING_SEAT_HOLD};
+use crate::webhooks::RoomWebhook;
+use crate::{metrics, SharedAppState};
+
+/// Body describing the outcome of a queued room creation request
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum CreationStatusBody {
+    Pending,
+    Created { room_id: RoomId },
+    Failed { reason: String },
+    Expired,
+}
+
+/// Query narrowing a request down to one region
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RegionQuery {
+    /// The region of the instances hosting the rooms, as configured on each instance
+    region: Option<String>,
+}
+
+impl RegionQuery {
+    fn matches(&self, room: &RoomSummary) -> bool {
+        self.region
+            .as_ref()
+            .is_none_or(|region| room.region.as_ref() == Some(region))
+    }
+}
+
+/// How rooms are ordered when listed
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum RoomSort {
+    /// Rooms with the most spectators first, then those with the most players, then those
+    /// most recently active, so lobbies can point people at the matches worth watching
+    MostWatched,
+}
+
+impl RoomSort {
+    fn sort(self, rooms: &mut [RoomSummary]) {
+        match self {
+            Self::MostWatched => rooms.sort_by(|a, b| {
+                b.spectator_count
+                    .cmp(&a.spectator_count)
+                    .then(b.player_count.cmp(&a.player_count))
+                    .then(b.last_active_at.cmp(&a.last_active_at))
+            }),
+        }
+    }
+}
+
+/// Query ordering a list of rooms
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RoomSortQuery {
+    /// The order rooms are in, as the registry keeps them if omitted
+    sort: Option<RoomSort>,
+}
+
+fn unknown_region() -> HttpResponse {
+    Problem::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "/problems/unknown-region",
+        "No instance runs in the region",
+    )
+    .error_response()
+}
+
+/// Body of a request to ban a player or network
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct BanRequest {
+    target: BanTarget,
+    reason: String,
+    /// How long the ban lasts, omitted for a permanent ban
+    duration_secs: Option<u64>,
+}
+
+/// Body of a request to invite players to a room
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct InviteRequest {
+    /// How long the invite lasts, an hour if omitted
+    ttl_secs: Option<u64>,
+    /// How many times the invite can be used, unlimited if omitted
+    max_uses: Option<u32>,
+}
+
+/// Body of a request from a matchmaker to place players in a room of their own
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct MatchRequest {
+    /// The players to reserve seats for
+    players: Vec<PlayerId>,
+    /// The team each player is on, keyed by player, for matches played in teams
+    #[serde(default)]
+    teams: HashMap<PlayerId, String>,
+    /// How long the players have to take their seats, an hour if omitted
+    ttl_secs: Option<u64>,
+    /// Where to deliver the room's events, on top of the endpoints subscribed to every room's
+    webhook: Option<RoomWebhook>,
+}
+
+/// The longest name a team in a match can have
+const MAX_TEAM_NAME_CHARS: usize = 32;
+
+/// A seat reserved for a player in a match's room
+#[derive(Debug, Serialize, ToSchema)]
+struct SeatBody {
+    player_id: PlayerId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    team: Option<String>,
+    /// Passed in the `invite` query parameter when the player joins the room
+    token: String,
+    /// The URL the player joins the room with, token included
+    url: String,
+    /// Seconds since the Unix epoch
+    expires_at: u64,
+}
+
+/// Body of a request to schedule a room to open later
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct ScheduleRequest {
+    /// When the room opens, in seconds since the Unix epoch
+    starts_at: u64,
+    /// The players to reserve seats for, the room is private to them unless there are none
+    #[serde(default)]
+    invited: Vec<PlayerId>,
+}
+
+/// Body of a request to create a room
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct CreateRoomRequest {
+    /// Where to deliver the room's events, on top of the endpoints subscribed to every room's
+    webhook: Option<RoomWebhook>,
+}
+
+/// Body of a request to create a turn-based room
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct TurnBasedRoomRequest {
+    /// How long each turn may take before the room is deleted, a day if omitted
+    #[serde(default)]
+    turn_timeout_secs: Option<u64>,
+    /// Whether joining requires an invite
+    #[serde(default)]
+    private: bool,
+    /// Where to deliver the room's events, on top of the endpoints subscribed to every room's
+    webhook: Option<RoomWebhook>,
+}
+
+/// Body describing a room scheduled to open later
+#[derive(Debug, Serialize, ToSchema)]
+struct ScheduledRoomBody {
+    room_id: RoomId,
+    /// Seconds since the Unix epoch
+    starts_at: u64,
+    /// The seats reserved for the invited players, which last until an hour after the room
+    /// opens
+    seats: Vec<SeatBody>,
+}
+
+/// Body describing the room created for a match
+#[derive(Debug, Serialize, ToSchema)]
+struct MatchBody {
+    room_id: RoomId,
+    seats: Vec<SeatBody>,
+}
+
+/// Body of a request to queue a party to be matched with others
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct QueueRequest {
+    /// The kind of game the party wants to play, parties are only matched with others
+    /// queued for the same
+    game_type: String,
+    /// The players in the party, who are always put in the same match
+    players: Vec<PlayerId>,
+}
+
+/// Body describing where a queued party stands
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum QueueStatusBody {
+    Waiting {
+        game_type: String,
+        waited_secs: u64,
+    },
+    /// The party's seats in the room its match is played in
+    Matched {
+        room_id: RoomId,
+        seats: Vec<SeatBody>,
+    },
+}
+
+/// Body of a request to create a party
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PartyRequest {
+    /// The player creating and leading the party
+    leader: PlayerId,
+}
+
+/// Body naming the player invited to or accepting an invite to a party
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PartyMemberRequest {
+    player_id: PlayerId,
+}
+
+/// Body of a request to queue a party to be matched with others
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PartyQueueRequest {
+    game_type: String,
+}
+
+/// Body of a request for seats in a room for every member of a party
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PartySeatsRequest {
+    room_id: RoomId,
+}
+
+/// Body of a request to hold a tournament
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct TournamentRequest {
+    game_type: String,
+    /// The players registered for the tournament, seeded by their rating in the game type
+    players: Vec<PlayerId>,
+}
+
+/// Body reporting who won a tournament match
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct MatchResultRequest {
+    winner: PlayerId,
+}
+
+/// Query picking a page of a leaderboard
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct LeaderboardQuery {
+    /// The stretch of time the leaderboard covers, all time if omitted
+    #[serde(default)]
+    period: LeaderboardPeriod,
+    /// How many of the top entries to skip
+    #[serde(default)]
+    offset: usize,
+    /// How many entries to return, at most 100
+    #[serde(default = "default_leaderboard_limit")]
+    limit: usize,
+}
+
+fn default_leaderboard_limit() -> usize {
+    20
+}
+
+/// Body holding a page of a leaderboard
+#[derive(Debug, Serialize, ToSchema)]
+struct LeaderboardBody {
+    game_type: String,
+    period: LeaderboardPeriod,
+    /// How many entries the whole leaderboard has
+    total: usize,
+    entries: Vec<LeaderboardEntry>,
+}
+
+/// Body describing a single room
+#[derive(Debug, Serialize, ToSchema)]
+struct RoomBody {
+    id: RoomId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    private: bool,
+    /// Whether the room is listed in the lobby, and whether joining it takes an invite
+    visibility: Visibility,
+    /// Whatever the room's owner has set for game clients to read, such as its map
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    #[schema(value_type = Object)]
+    custom_data: Map<String, Value>,
+    /// The first authenticated player to join the room
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<PlayerId>,
+    /// How many distinct authenticated players are connected
+    player_count: usize,
+    /// How many connections are watching the room rather than playing in it
+    spectator_count: usize,
+    /// How many connections are open, anonymous ones and spectators included
+    connection_count: usize,
+    /// How many seconds are left until the room is deleted unless someone joins it, if it's
+    /// waiting for players, or unless the turn is passed on, if it's turn-based
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deletes_in_secs: Option<u64>,
+    /// How long each turn may take, if the room is turn-based
+    #[serde(skip_serializing_if = "Option::is_none")]
+    turn_timeout_secs: Option<u64>,
+    /// Whose turn it is, once a turn-based room's turn has been passed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    turn: Option<Turn>,
+}
+
+/// Body describing or changing whether the server is in maintenance mode
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct MaintenanceBody {
+    enabled: bool,
+}
+
+/// Body describing how busy the instances are
+#[derive(Debug, Serialize, ToSchema)]
+struct LoadBody {
+    local: LoadReport,
+    /// Every instance sharing the lobby, this one included, empty when running on its own
+    nodes: Vec<NodeLoadBody>,
+}
+
+/// Body describing how busy an instance was as of its last heartbeat
+#[derive(Debug, Serialize, ToSchema)]
+struct NodeLoadBody {
+    id: Uuid,
+    public_url: Option<String>,
+    /// How many rooms the instance says it can host
+    capacity: usize,
+    rooms: usize,
+    score: f64,
+}
+
+/// Body describing the outcome of revoking a player's sessions
+#[derive(Debug, Serialize, ToSchema)]
+struct RevocationBody {
+    /// How many live connections were closed
+    closed_connections: usize,
+}
+
+/// Body of a request to make an announcement
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct AnnouncementRequest {
+    text: String,
+    /// Only these rooms
+    rooms: Option<Vec<RoomId>>,
+    /// Only rooms hosted by instances in these regions
+    regions: Option<Vec<String>>,
+}
+
+/// Body describing how far an announcement reached
+#[derive(Debug, Serialize, ToSchema)]
+struct AnnouncementBody {
+    /// How many rooms on this instance it was sent to
+    rooms: usize,
+    /// How many connections on this instance it was sent to, leaving out those speaking a
+    /// protocol version too old to be shown it
+    connections: usize,
+    /// Whether it was relayed to the other instances sharing the lobby, `false` when running
+    /// on its own
+    relayed: bool,
+}
+
+/// Body describing the outcome of force closing a room
+#[derive(Debug, Serialize, ToSchema)]
+struct ForceCloseBody {
+    /// How many live connections were closed
+    closed_connections: usize,
+}
+
+/// Query finding players by their display name
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PlayerSearchQuery {
+    /// Part of the display name, matched ignoring case
+    display_name: String,
+}
+
+/// Body describing a player, for operators
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerBody {
+    #[serde(flatten)]
+    sessions: PlayerSessions,
+    /// Whether the player has a live connection to this instance
+    online: bool,
+    /// The ban keeping the player off the server, if there is one
+    ban: Option<Ban>,
+}
+
+impl PlayerBody {
+    fn new(sessions: PlayerSessions, state: &SharedAppState) -> Self {
+        Self {
+            online: !sessions.connections.is_empty(),
+            ban: state.ban_list.find(Some(sessions.player_id), None),
+            sessions,
+        }
+    }
+}
+
+/// Body of a request to ban a player from the server and disconnect them
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct PlayerBanRequest {
+    reason: String,
+    /// How long the ban lasts, omitted for a permanent ban
+    duration_secs: Option<u64>,
+}
+
+/// Body describing the outcome of banning a player
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerBanBody {
+    ban: Ban,
+    /// How many live connections were closed
+    closed_connections: usize,
+}
+
+/// Body of a player's report of another player
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct ReportRequest {
+    reporter: PlayerId,
+    reported: PlayerId,
+    /// The room the reported player was in
+    room_id: RoomId,
+    reason: String,
+    /// Whether to capture the reported player's most recent chat in the room, if it's hosted
+    /// by this instance
+    #[serde(default)]
+    include_chat: bool,
+}
+
+/// Query narrowing the moderation queue down to reports with a status
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReportStatusQuery {
+    /// Every report if omitted
+    status: Option<ReportStatus>,
+}
+
+/// Body of a request to act against a reported player
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ReportActionRequest {
+    /// Closes the player's live connections to this instance
+    Disconnect,
+    /// Bans the player from the server and closes their live connections to this instance
+    Ban {
+        /// The report's reason if omitted
+        reason: Option<String>,
+        /// How long the ban lasts, omitted for a permanent ban
+        duration_secs: Option<u64>,
+    },
+    /// Stops showing the player's display name
+    WipeDisplayName,
+}
+
+/// Body of a request to resolve a report
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct ResolutionRequest {
+    /// What was done about the report, for other moderators
+    note: Option<String>,
+}
+
+/// Body describing the outcome of disconnecting a player
+#[derive(Debug, Serialize, ToSchema)]
+struct DisconnectBody {
+    /// How many live connections were closed
+    closed_connections: usize,
+}
+
+/// Body describing where a player is connected, on any instance
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerPresenceBody {
+    /// Whether the player has a live connection to any room
+    online: bool,
+    rooms: Vec<PlayerRoomBody>,
+}
+
+/// Body of a request to invite a player to a room
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct InvitationRequest {
+    /// The player sending the invitation, who's told when it's answered
+    from: PlayerId,
+    room_id: RoomId,
+}
+
+/// Body describing an invitation sent to a player
+#[derive(Debug, Serialize, ToSchema)]
+struct InvitationBody {
+    invitation: RoomInvitation,
+    /// Whether the player was connected to a room anywhere and sent the invitation straight
+    /// away, rather than having it kept in their inbox
+    delivered: bool,
+}
+
+/// Body of a player's answer to an invitation
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct InvitationAnswerRequest {
+    accept: bool,
+}
+
+/// Body of a request to hand the turn in a room's game to a player
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct TurnRequest {
+    player_id: PlayerId,
+}
+
+/// Body describing how a player was told it's their turn
+#[derive(Debug, Serialize, ToSchema)]
+struct TurnBody {
+    /// Whether the player wasn't connected to a room anywhere and was sent a push
+    /// notification, which is never the case unless a push gateway is configured
+    pushed: bool,
+    /// When the room is deleted unless the turn is passed on by then, in seconds since the
+    /// Unix epoch, if the room is turn-based and hosted by this instance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deadline: Option<u64>,
+}
+
+/// Body describing a player's standing in the game types they've played
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerStatsBody {
+    /// The player's rating, keyed by game type
+    ratings: HashMap<String, Rating>,
+}
+
+/// A player's rating after a game
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerRatingBody {
+    player_id: PlayerId,
+    #[serde(flatten)]
+    rating: Rating,
+}
+
+/// A room a player is connected to
+#[derive(Debug, Serialize, ToSchema)]
+struct PlayerRoomBody {
+    room_id: RoomId,
+    /// The instance hosting the room, absent when this instance runs on its own
+    node: Option<Uuid>,
+    /// The room's WebSocket URL, absolute when the hosting instance has a public URL
+    url: String,
+}
+
+impl PlayerRoomBody {
+    fn new(room_id: RoomId, node: Option<Uuid>, public_url: Option<&str>) -> Self {
+        Self {
+            room_id,
+            node,
+            url: format!("{}/ws/{room_id}", public_url.unwrap_or_default()),
+        }
+    }
+}
+
+/// Body of the event telling a lobby subscriber it fell behind and missed some events
+#[derive(Debug, Serialize)]
+struct ResyncBody {
+    missed: u64,
+}
+
+/// How often the lobby event stream sends a comment while idle, so proxies keep it open
+const LOBBY_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Bans and revocations made through the REST API are attributed to it in the logs
+const API_ACTOR: &str = "api";
+
+/// How long clients are asked to wait before retrying room creation when every instance is
+/// at capacity, about as long as it takes instances to report their loads again
+const SATURATED_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+fn room_location(room_id: RoomId) -> (&'static str, String) {
+    ("LOCATION", format!("/ws/{room_id}"))
+}
+
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// Serializes the value as MessagePack shaped like its JSON, with field names and with IDs
+/// as strings rather than raw bytes
+fn to_msgpack(value: &impl Serialize) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut serializer = rmp_serde::Serializer::new(Vec::new())
+        .with_struct_map()
+        .with_human_readable();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_inner())
+}
+
+/// The formats responses describing rooms can be serialized in, using the same serde
+/// representations as the WebSocket protocol
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum ResponseFormat {
+    Json,
+    MessagePack,
+}
+
+impl ResponseFormat {
+    /// The format the client ranks highest in its `Accept` header, JSON if it accepts neither
+    fn negotiate(req: &HttpRequest) -> Self {
+        let Ok(accept) = Accept::parse(req) else {
+            return Self::Json;
+        };
+        accept
+            .ranked()
+            .iter()
+            .find_map(|mime| match (mime.type_(), mime.subtype().as_str()) {
+                (mime::APPLICATION, "msgpack" | "x-msgpack") => Some(Self::MessagePack),
+                (mime::APPLICATION, "json" | "*") | (mime::STAR, _) => Some(Self::Json),
+                _ => None,
+            })
+            .unwrap_or(Self::Json)
+    }
+
+    /// Completes the response with the body serialized in this format
+    fn respond(self, mut response: HttpResponseBuilder, body: &impl Serialize) -> HttpResponse {
+        response.insert_header((VARY, "Accept"));
+        match self {
+            Self::Json => response.json(body),
+            Self::MessagePack => match to_msgpack(body) {
+                Ok(bytes) => response.content_type(MSGPACK_CONTENT_TYPE).body(bytes),
+                Err(e) => HttpResponse::InternalServerError()
+                    .message_body(BoxBody::new(e.to_string()))
+                    .unwrap(),
+            },
+        }
+    }
+}
+
+/// Why the webhook a room was asked to be created with can't be, if it can't
+fn check_webhook(state: &SharedAppState, webhook: Option<&RoomWebhook>) -> Result<(), Problem> {
+    let Some(webhook) = webhook else {
+        return Ok(());
+    };
+    if !state.room_registry.delivers_room_webhooks() {
+        return Err(Problem::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "/problems/webhooks-disabled",
+            "The server doesn't deliver webhooks",
+        ));
+    }
+    webhook.subscription().map(|_| ()).map_err(|e| {
+        Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-webhook",
+            "The room's webhook is invalid",
+        )
+        .with_detail(e.to_string())
+    })
+}
+
+fn creation_location(ticket: CreationTicket) -> (&'static str, String) {
+    ("LOCATION", format!("/api/v1/rooms/creations/{ticket}"))
+}
+
+fn queue_location(ticket: QueueTicket) -> (&'static str, String) {
+    ("LOCATION", format!("/api/v1/matchmaking/queue/{ticket}"))
+}
+
+/// Mints the invite holding the player's seat in the room
+fn mint_seat_body(
+    state: &SharedAppState,
+    room_id: RoomId,
+    player_id: PlayerId,
+    team: Option<String>,
+    ttl: Duration,
+) -> SeatBody {
+    let Invite { token, expires_at } =
+        state
+            .invite_signer
+            .mint_seat(room_id, player_id, team.clone(), ttl);
+    SeatBody {
+        player_id,
+        team,
+        url: format!("{}?invite={token}", room_location(room_id).1),
+        token,
+        expires_at,
+    }
+}
+
+/// Creates a room, waiting briefly for the creation queue before telling the client to poll
+///
+/// When instances share the lobby the room may be placed on another one, in which case the
+/// `Location` header holds that instance's absolute WebSocket URL, and is turned away with a
+/// `503` when every instance is at capacity. Asking for a region keeps the room to the
+/// instances in it.
+///
+/// A room created with a webhook has its events delivered to it as well as to the endpoints
+/// subscribed to every room's, so a service creating rooms can follow its own rooms alone.
+#[utoipa::path(
+    post,
+    path = "/rooms/",
+    tag = "rooms",
+    params(RegionQuery),
+    request_body(content = Option<CreateRoomRequest>, description = "Omit for a room without a webhook of its own"),
+    responses(
+        (status = 201, description = "The room was created, its WebSocket URL is in the `Location` header"),
+        (status = 202, description = "The request is still queued, poll the URL in the `Location` header", body = CreationStatusBody),
+        (status = 400, description = "The webhook is invalid", body = Problem),
+        (status = 422, description = "No instance runs in the region, or the server doesn't deliver webhooks", body = Problem),
+        (status = 500, description = "The room could not be created"),
+        (status = 503, description = "The server is in maintenance mode, every instance is at capacity, or the creation queue is full or the request expired, retry later", body = CreationStatusBody),
+    )
+)]
+async fn create_room(
+    query: web::Query<RegionQuery>,
+    body: OptionalJson<CreateRoomRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let CreateRoomRequest { webhook } = body.0.unwrap_or_default();
+    if let Err(problem) = check_webhook(&state, webhook.as_ref()) {
+        return problem.error_response();
+    }
+    let room_state = RoomState {
+        webhook,
+        ..Default::default()
+    };
+    let region = query.region.as_deref();
+    if let Some(cluster) = &state.cluster {
+        match cluster.place_room(region, room_state.clone()).await {
+            RoomPlacement::Here => {}
+            RoomPlacement::Elsewhere(url) => {
+                return HttpResponse::Created()
+                    .insert_header(("LOCATION", url))
+                    .finish()
+            }
+            RoomPlacement::Saturated => {
+                return Problem::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "/problems/cluster-saturated",
+                    "Every instance is at capacity",
+                )
+                .with_retry_after(SATURATED_RETRY_AFTER)
+                .error_response()
+            }
+            RoomPlacement::UnknownRegion => return unknown_region(),
+        }
+    } else if region.is_some_and(|region| state.room_registry.region() != Some(region)) {
+        return unknown_region();
+    }
+    let ticket = match state.room_creation_queue.submit_with(room_state) {
+        Ok(ticket) => ticket,
+        Err(e @ SubmitCreationError::QueueFull) => {
+            return HttpResponse::ServiceUnavailable()
+                .insert_header(("RETRY-AFTER", "1"))
+                .message_body(BoxBody::new(e.to_string()))
+                .unwrap()
+        }
+        Err(e @ SubmitCreationError::WorkerStopped) => {
+            return HttpResponse::ServiceUnavailable()
+                .message_body(BoxBody::new(e.to_string()))
+                .unwrap()
+        }
+    };
+
+    let status = state
+        .room_creation_queue
+        .wait(ticket, state.room_creation_wait)
+        .await;
+
+    match status {
+        Some(CreationStatus::Created(room_id)) => HttpResponse::Created()
+            .insert_header(room_location(room_id))
+            .finish(),
+        Some(CreationStatus::Failed(e)) => HttpResponse::InternalServerError()
+            .message_body(BoxBody::new(format!("{e:?}")))
+            .unwrap(),
+        Some(CreationStatus::Pending) => HttpResponse::Accepted()
+            .insert_header(creation_location(ticket))
+            .json(CreationStatusBody::Pending),
+        Some(CreationStatus::Expired) | None => HttpResponse::ServiceUnavailable()
+            .insert_header(("RETRY-AFTER", "1"))
+            .json(CreationStatusBody::Expired),
+    }
+}
+
+/// Schedules a room to open at a later time, reserving a seat in it for each invited player
+///
+/// The room is listed straight away with the time it starts at, but players trying to join
+/// before then are turned away with a `409`. Once it opens it's deleted if nobody joins, like
+/// any other room, and a `room_opened` webhook event names its invited players so they can be
+/// told. Scheduled rooms are always hosted by the instance they're scheduled on.
+#[utoipa::path(
+    post,
+    path = "/rooms/scheduled",
+    tag = "rooms",
+    request_body = ScheduleRequest,
+    responses(
+        (status = 201, description = "The room was scheduled, its WebSocket URL is in the `Location` header", body = ScheduledRoomBody),
+        (status = 400, description = "The room would open in the past or more than a week ahead", body = Problem),
+        (status = 500, description = "The room could not be created"),
+        (status = 503, description = "The server is in maintenance mode", body = Problem),
+    )
+)]
+async fn schedule_room(
+    body: web::Json<ScheduleRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let ScheduleRequest { starts_at, invited } = body.into_inner();
+    match state.room_scheduler.schedule(starts_at, invited.clone()) {
+        Ok((room_id, lead)) => {
+            let seats = invited
+                .into_iter()
+                .map(|player_id| {
+                    mint_seat_body(&state, room_id, player_id, None, lead + DEFAULT_INVITE_TTL)
+                })
+                .collect();
+            HttpResponse::Created()
+                .insert_header(room_location(room_id))
+                .json(ScheduledRoomBody {
+                    room_id,
+                    starts_at,
+                    seats,
+                })
+        }
+        Err(ScheduleError::Creation(e)) => HttpResponse::InternalServerError()
+            .message_body(BoxBody::new(format!("{e:?}")))
+            .unwrap(),
+        Err(e) => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-schedule",
+            "The room can't be scheduled for that time",
+        )
+        .with_detail(e.to_string())
+        .error_response(),
+    }
+}
+
+/// Creates a turn-based room, for correspondence-style games played a turn at a time
+///
+/// The room isn't deleted for sitting empty, only once a turn has gone on for longer than
+/// the turn timeout, counting from when the room was created until the turn is first passed.
+/// The last payload broadcast in the room is sent to each connection as it joins, so players
+/// can connect just long enough to take their turn. Turn-based rooms are always hosted by
+/// the instance they're created on, and turns must be passed through it.
+#[utoipa::path(
+    post,
+    path = "/rooms/turn-based",
+    tag = "rooms",
+    request_body = TurnBasedRoomRequest,
+    responses(
+        (status = 201, description = "The room was created, its WebSocket URL is in the `Location` header"),
+        (status = 400, description = "The turn timeout is zero or longer than 30 days, or the webhook is invalid", body = Problem),
+        (status = 422, description = "The server doesn't deliver webhooks", body = Problem),
+        (status = 500, description = "The room could not be created"),
+        (status = 503, description = "The server is in maintenance mode, or the creation queue is full or the request expired, retry later"),
+    )
+)]
+async fn create_turn_based_room(
+    body: web::Json<TurnBasedRoomRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let TurnBasedRoomRequest {
+        turn_timeout_secs,
+        private,
+        webhook,
+    } = body.into_inner();
+    if let Err(problem) = check_webhook(&state, webhook.as_ref()) {
+        return problem.error_response();
+    }
+    let turn_timeout = turn_timeout_secs.map_or(DEFAULT_TURN_TIMEOUT, Duration::from_secs);
+    if turn_timeout.is_zero() || turn_timeout > MAX_TURN_TIMEOUT {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-turn-timeout",
+            "Turns can't last that long",
+        )
+        .with_detail(format!(
+            "The turn timeout must be between 1 and {} seconds",
+            MAX_TURN_TIMEOUT.as_secs()
+        ))
+        .error_response();
+    }
+    let room_state = RoomState {
+        private,
+        turn_timeout_secs: Some(turn_timeout.as_secs()),
+        webhook,
+        ..Default::default()
+    };
+    let room_id = match create_room_awaited(&state, room_state).await {
+        Ok(room_id) => room_id,
+        Err(response) => return response,
+    };
+    state.turn_based_rooms.mark_changed();
+    info!(event = "turn_based_room_created", %room_id, ?turn_timeout);
+    HttpResponse::Created()
+        .insert_header(room_location(room_id))
+        .finish()
+}
+
+/// The outcome of a queued room creation request, as JSON or as MessagePack if the client
+/// prefers `application/msgpack`
+#[utoipa::path(
+    get,
+    path = "/rooms/creations/{ticket}",
+    tag = "rooms",
+    params(("ticket" = CreationTicket, Path, description = "The ticket returned when the request was queued")),
+    responses(
+        (status = 200, description = "The room was created, its WebSocket URL is in the `Location` header", content(
+            (CreationStatusBody = "application/json"),
+            (CreationStatusBody = "application/msgpack"),
+        )),
+        (status = 202, description = "The request is still queued", body = CreationStatusBody),
+        (status = 404, description = "No request has the ticket"),
+        (status = 500, description = "The room could not be created", body = CreationStatusBody),
+        (status = 503, description = "The request expired before it was processed", body = CreationStatusBody),
+    )
+)]
+async fn get_room_creation(
+    req: HttpRequest,
+    path: web::Path<CreationTicket>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let ticket = path.into_inner();
+    let format = ResponseFormat::negotiate(&req);
+    match state.room_creation_queue.status(ticket) {
+        None => HttpResponse::NotFound().finish(),
+        Some(CreationStatus::Pending) => {
+            let mut response = HttpResponse::Accepted();
+            response.insert_header(creation_location(ticket));
+            format.respond(response, &CreationStatusBody::Pending)
+        }
+        Some(CreationStatus::Created(room_id)) => {
+            let mut response = HttpResponse::Ok();
+            response.insert_header(room_location(room_id));
+            format.respond(response, &CreationStatusBody::Created { room_id })
+        }
+        Some(CreationStatus::Failed(e)) => format.respond(
+            HttpResponse::InternalServerError(),
+            &CreationStatusBody::Failed {
+                reason: e.to_string(),
+            },
+        ),
+        Some(CreationStatus::Expired) => format.respond(
+            HttpResponse::ServiceUnavailable(),
+            &CreationStatusBody::Expired,
+        ),
+    }
+}
+
+/// Every active room, including those hosted by other instances sharing the lobby, as JSON or
+/// as MessagePack if the client prefers `application/msgpack`
+///
+/// Unlisted rooms are left out, they can only be joined by their ID or an invite.
+///
+/// `sort=most_watched` puts the rooms with the most spectators first, for lobbies to surface
+/// popular matches to spectate.
+#[utoipa::path(
+    get,
+    path = "/rooms/",
+    tag = "rooms",
+    params(RegionQuery, RoomSortQuery),
+    responses((status = 200, content(
+        ([RoomSummary] = "application/json"),
+        ([RoomSummary] = "application/msgpack"),
+    )))
+)]
+async fn get_rooms(
+    req: HttpRequest,
+    query: web::Query<RegionQuery>,
+    sort: web::Query<RoomSortQuery>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let format = ResponseFormat::negotiate(&req);
+    let rooms = state.room_registry.list_lobby_rooms();
+    let remote_rooms = state.remote_rooms.list();
+    if remote_rooms.is_empty() && query.region.is_none() && sort.sort.is_none() {
+        return format.respond(HttpResponse::Ok(), &rooms);
+    }
+    let mut rooms: Vec<_> = rooms
+        .into_iter()
+        .chain(remote_rooms)
+        .filter(|room| room.visibility.is_listed() && query.matches(room))
+        .collect();
+    if let Some(sort) = sort.sort {
+        sort.sort(&mut rooms);
+    }
+    format.respond(HttpResponse::Ok(), &rooms)
+}
+
+/// A single room, as JSON or as MessagePack if the client prefers `application/msgpack`
+#[utoipa::path(
+    get,
+    path = "/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (status = 200, content(
+            (RoomBody = "application/json"),
+            (RoomBody = "application/msgpack"),
+        )),
+        (status = 404, description = "No room has the ID"),
+    )
+)]
+async fn get_room(
+    req: HttpRequest,
+    path: web::Path<RoomId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let room = match state.room_registry.get_room(id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    ResponseFormat::negotiate(&req).respond(
+        HttpResponse::Ok(),
+        &RoomBody {
+            id,
+            name: room.name(),
+            private: room.is_private(),
+            visibility: room.visibility(),
+            custom_data: room.custom_data(),
+            owner: room.owner(),
+            player_count: room.player_count(),
+            spectator_count: room.spectator_count(),
+            connection_count: room.broadcaster().subscriber_count(),
+            deletes_in_secs: room
+                .time_until_deletion()
+                .map(|remaining| remaining.as_secs()),
+            turn_timeout_secs: room.turn_timeout().map(|timeout| timeout.as_secs()),
+            turn: room.turn(),
+        },
+    )
+}
+
+/// Disconnects everyone from a room and removes it
+#[utoipa::path(
+    delete,
+    path = "/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (status = 204, description = "The room was closed"),
+        (status = 404, description = "No room has the ID"),
+    )
+)]
+async fn delete_room(path: web::Path<RoomId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    let id = path.into_inner();
+    let room = match state.room_registry.get_room(id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    room.close();
+    state.room_deletion_queue.request_deletion(id);
+    HttpResponse::NoContent().finish()
+}
+
+/// Formats a single Server-Sent Event
+fn sse_event(name: &str, data: &impl Serialize) -> Bytes {
+    let data = serde_json::to_string(data).expect("Lobby events always serialize");
+    Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
+
+/// A stream of changes to the set of rooms, including those hosted by other instances sharing
+/// the lobby, for lobby browsers to follow
+///
+/// Each event's data is the room's summary, or just its ID once it's deleted. Only changes
+/// made after subscribing are sent, so clients list the rooms once they're subscribed. A
+/// `resync` event means the client fell behind and missed events, and should list the
+/// rooms again. Asking for a region leaves out rooms created in others, though deletions
+/// are sent for every room since only their IDs are known. Unlisted rooms are left out too,
+/// and a room being made unlisted is sent as deleted.
+#[utoipa::path(
+    get,
+    path = "/rooms/events",
+    tag = "rooms",
+    params(RegionQuery),
+    responses((
+        status = 200,
+        description = "`room_created`, `room_updated`, `room_deleted` and `resync` events",
+        content_type = "text/event-stream",
+    ))
+)]
+async fn get_room_events(
+    query: web::Query<RegionQuery>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let local = BroadcastStream::new(state.room_registry.lobby().subscribe_listed());
+    let remote = BroadcastStream::new(state.remote_rooms.lobby().subscribe_listed());
+    let query = query.into_inner();
+    let events = local.merge(remote).filter(move |event| match event {
+        Ok(LobbyEvent::RoomCreated(room) | LobbyEvent::RoomUpdated(room)) => query.matches(room),
+        _ => true,
+    });
+    let events = events.map(|event| {
+        Ok::<_, Infallible>(match event {
+            Ok(event) => sse_event(event.name(), &event),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                sse_event("resync", &ResyncBody { missed })
+            }
+        })
+    });
+    let heartbeats = IntervalStream::new(tokio::time::interval(LOBBY_HEARTBEAT_INTERVAL))
+        .map(|_| Ok(Bytes::from_static(b": heartbeat\n\n")));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("CACHE-CONTROL", "no-cache"))
+        .streaming(events.merge(heartbeats))
+}
+
+/// Mints an invite to a private room
+#[utoipa::path(
+    post,
+    path = "/rooms/{room_id}/invites",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    request_body(content = Option<InviteRequest>, description = "Omit for an hour long invite with unlimited uses"),
+    responses(
+        (status = 201, description = "The URL to join with is in the `Location` header", body = Invite),
+        (status = 404, description = "No room has the ID"),
+    )
+)]
+async fn create_invite(
+    path: web::Path<RoomId>,
+    body: OptionalJson<InviteRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let room_id = path.into_inner();
+    match state.room_registry.get_room(room_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    }
+
+    let InviteRequest { ttl_secs, max_uses } = body.0.unwrap_or_default();
+    let invite = state.invite_signer.mint(
+        room_id,
+        ttl_secs.map_or(DEFAULT_INVITE_TTL, Duration::from_secs),
+        max_uses,
+    );
+    HttpResponse::Created()
+        .insert_header((
+            "LOCATION",
+            format!("{}?invite={}", room_location(room_id).1, invite.token),
+        ))
+        .json(invite)
+}
+
+/// Takes a player off a room's waiting list as the stream of their place on it is dropped,
+/// unless a seat was held for them first
+struct WaitingListPlace {
+    state: web::Data<SharedAppState>,
+    room_id: RoomId,
+    player_id: PlayerId,
+}
+
+impl Drop for WaitingListPlace {
+    fn drop(&mut self) {
+        self.state.waiting_lists.leave(self.room_id, self.player_id);
+    }
+}
+
+/// Body of the event telling a waiting player a seat is being held for them
+#[derive(Debug, Serialize, ToSchema)]
+struct HeldSeatBody {
+    /// The room's WebSocket URL
+    url: String,
+    /// How long the seat is held for, in seconds
+    held_for_secs: u64,
+}
+
+/// Puts the player signed in with the request's identity token on a full room's waiting list
+/// for as long as they follow this stream of their place on it
+///
+/// The token is passed as a bearer token or, for clients such as browsers that can't set
+/// headers, in the `access_token` query parameter. A `position` event is sent when
+/// subscribing and again whenever the player's place on the list or the estimated wait
+/// changes. Once a player leaves the room the seat they freed is held for whoever has waited
+/// longest, who is sent a `seat` event and has a minute to join, after which their stream
+/// ends and the seat goes to whoever is next if they didn't take it. The stream also ends
+/// with a `room_deleted` event if the room is deleted first. Closing the stream takes the
+/// player off the list.
+#[utoipa::path(
+    get,
+    path = "/rooms/{room_id}/waiting-list",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (status = 200, description = "`position`, `seat` and `room_deleted` events", content_type = "text/event-stream"),
+        (status = 401, description = "The request has no valid identity token, or players can't sign in", body = Problem),
+        (status = 404, description = "No room on this instance has the ID"),
+        (status = 409, description = "The room has a seat for the player already", body = Problem),
+    )
+)]
+async fn follow_waiting_list(
+    req: HttpRequest,
+    path: web::Path<RoomId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let room_id = path.into_inner();
+    let Some(authenticator) = &state.player_authenticator else {
+        return Problem::unauthorized("Players can't sign in to this server to wait for a seat")
+            .error_response();
+    };
+    let player = match authenticator.authenticate_request(&req).await {
+        Ok(player) if state.sessions.is_revoked(player.id, player.issued_at) => {
+            return Problem::from(PlayerAuthError::Revoked).error_response()
+        }
+        Ok(player) => player,
+        Err(e) => return Problem::from(e).error_response(),
+    };
+    let player_id = player.id;
+    let updates = state.waiting_lists.subscribe();
+    let lobby = state.room_registry.lobby().subscribe();
+    let room = match state.room_registry.get_room(room_id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    if room.has_seat_for(Some(player_id)) {
+        return Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/seat-free",
+            "The room has a seat for the player",
+        )
+        .error_response();
+    }
+    state.waiting_lists.join(room_id, player_id);
+    let (events, received) = mpsc::channel(WAITING_LIST_EVENT_CAPACITY);
+    tokio::spawn(stream_waiting_list(
+        WaitingListPlace {
+            state: state.clone(),
+            room_id,
+            player_id,
+        },
+        room,
+        (updates, lobby),
+        events,
+    ));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("CACHE-CONTROL", "no-cache"))
+        .streaming(ReceiverStream::new(received).map(Ok::<_, Infallible>))
+}
+
+/// How many events for a waiting player can be held while they're slow to read them
+const WAITING_LIST_EVENT_CAPACITY: usize = 8;
+
+/// Sends the waiting player's place on the list as it changes, until a seat is held for
+/// them, the room is deleted or they stop reading
+async fn stream_waiting_list(
+    place: WaitingListPlace,
+    room: Arc<Room>,
+    (mut updates, mut lobby): (Receiver<WaitingListUpdate>, Receiver<LobbyEvent>),
+    events: Sender<Bytes>,
+) {
+    let WaitingListPlace {
+        ref state,
+        room_id,
+        player_id,
+    } = place;
+    let mut heartbeats = tokio::time::interval(LOBBY_HEARTBEAT_INTERVAL);
+    heartbeats.reset();
+    let mut last_position = None;
+    let mut seated = false;
+    loop {
+        let position = state.waiting_lists.position(room_id, player_id);
+        let event = if seated {
+            Some(sse_event(
+                "seat",
+                &HeldSeatBody {
+                    url: room_location(room_id).1,
+                    held_for_secs: WAITING_SEAT_HOLD.as_secs(),
+                },
+            ))
+        } else if position != last_position {
+            last_position = position;
+            position.map(|position| sse_event("position", &position))
+        } else {
+            None
+        };
+        if let Some(event) = event {
+            let sent = events.send(event).await.is_ok();
+            if seated {
+                tokio::spawn(reseat_unless_taken(state.clone(), room, room_id, player_id));
+                return;
+            }
+            if !sent {
+                return;
+            }
+        }
+
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => seated = update.room_id == room_id && update.seated == Some(player_id),
+                // A seat might have been held for the player among the updates missed
+                Err(RecvError::Lagged(_)) => seated = position.is_some() && room.has_seat_for(Some(player_id)),
+                Err(RecvError::Closed) => return,
+            },
+            event = lobby.recv() => match event {
+                Ok(event @ LobbyEvent::RoomDeleted { id }) if id == room_id => {
+                    let _ = events.send(sse_event(event.name(), &event)).await;
+                    return;
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            _ = heartbeats.tick() => {
+                if events.send(Bytes::from_static(b": heartbeat\n\n")).await.is_err() {
+                    return;
+                }
+            }
+            () = events.closed() => return,
+        }
+    }
+}
+
+/// Holds the seat held for the waiting player for whoever waited longest next once the hold
+/// runs out, unless the player took it
+async fn reseat_unless_taken(
+    state: web::Data<SharedAppState>,
+    room: Arc<Room>,
+    room_id: RoomId,
+    player_id: PlayerId,
+) {
+    tokio::time::sleep(WAITING_SEAT_HOLD).await;
+    if !room.has_player(player_id) {
+        info!(event = "held_seat_unused", %room_id, %player_id);
+        state
+            .waiting_lists
+            .seat_waiting(room_id, &room, Instant::now());
+    }
+}
+
+/// Why a match's players can't be seated on their teams, if they can't
+fn check_match_players(
+    players: &[PlayerId],
+    teams: &HashMap<PlayerId, String>,
+) -> Result<(), &'static str> {
+    if players.is_empty() {
+        return Err("A match needs at least one player");
+    }
+    let seated: HashSet<_> = players.iter().collect();
+    if seated.len() != players.len() {
+        return Err("A player can only hold one seat in a match");
+    }
+    if !teams.keys().all(|player| seated.contains(player)) {
+        return Err("Only players in the match can be put on a team");
+    }
+    if teams
+        .values()
+        .any(|team| team.is_empty() || team.chars().count() > MAX_TEAM_NAME_CHARS)
+    {
+        return Err("Team names must be between 1 and 32 characters");
+    }
+    Ok(())
+}
+
+/// Creates a private room for a match and reserves a seat in it for each player, answering
+/// with the token each player joins with
+///
+/// Players given a team are put on it when they take their seat, and can then chat with
+/// their team alone.
+///
+/// A seat's token only admits the player it was minted for. Without an identity provider
+/// the server can't tell players apart, so anyone holding the token can take the seat.
+#[utoipa::path(
+    post,
+    path = "/matches",
+    tag = "matchmaking",
+    request_body = MatchRequest,
+    responses(
+        (status = 201, description = "The room was created, its WebSocket URL is in the `Location` header", body = MatchBody),
+        (status = 400, description = "No players were given, a player was given twice, a team was given for someone outside the match, or the webhook is invalid", body = Problem),
+        (status = 422, description = "The server doesn't deliver webhooks", body = Problem),
+        (status = 500, description = "The room could not be created"),
+        (status = 503, description = "The server is in maintenance mode, or the creation queue is full or the request expired, retry later"),
+    )
+)]
+async fn create_match(
+    body: web::Json<MatchRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let MatchRequest {
+        players,
+        mut teams,
+        ttl_secs,
+        webhook,
+    } = body.into_inner();
+    if let Err(problem) = check_webhook(&state, webhook.as_ref()) {
+        return problem.error_response();
+    }
+    if let Err(detail) = check_match_players(&players, &teams) {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-match",
+            "The match's players can't be seated",
+        )
+        .with_detail(detail)
+        .error_response();
+    }
+
+    // There's no ticket to hand back here since the seats are minted once the room exists
+    let private = RoomState {
+        private: true,
+        webhook,
+        ..Default::default()
+    };
+    let room_id = match create_room_awaited(&state, private).await {
+        Ok(room_id) => room_id,
+        Err(response) => return response,
+    };
+
+    let ttl = ttl_secs.map_or(DEFAULT_INVITE_TTL, Duration::from_secs);
+    let seats = players
+        .into_iter()
+        .map(|player_id| {
+            let team = teams.remove(&player_id);
+            mint_seat_body(&state, room_id, player_id, team, ttl)
+        })
+        .collect();
+    HttpResponse::Created()
+        .insert_header(room_location(room_id))
+        .json(MatchBody { room_id, seats })
+}
+
+/// Creates a room with the state through the creation queue, waiting for as long as the
+/// queue's deadline allows, or answers with why it couldn't be
+async fn create_room_awaited(
+    state: &SharedAppState,
+    room_state: RoomState,
+) -> Result<RoomId, HttpResponse> {
+    let ticket = match state.room_creation_queue.submit_with(room_state) {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            return Err(HttpResponse::ServiceUnavailable()
+                .insert_header(("RETRY-AFTER", "1"))
+                .message_body(BoxBody::new(e.to_string()))
+                .unwrap())
+        }
+    };
+    match state.room_creation_queue.wait(ticket, Duration::MAX).await {
+        Some(CreationStatus::Created(room_id)) => Ok(room_id),
+        Some(CreationStatus::Failed(e)) => Err(HttpResponse::InternalServerError()
+            .message_body(BoxBody::new(format!("{e:?}")))
+            .unwrap()),
+        Some(CreationStatus::Pending | CreationStatus::Expired) | None => {
+            Err(HttpResponse::ServiceUnavailable()
+                .insert_header(("RETRY-AFTER", "1"))
+                .json(CreationStatusBody::Expired))
+        }
+    }
+}
+
+/// Creates a rematch of a room, with the same owner, settings and teams
+///
+/// Players banned from the room are kept out of the rematch, and of any rematch of it in
+/// turn.
+#[utoipa::path(
+    post,
+    path = "/rooms/{room_id}/rematch",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (status = 201, description = "The rematch was created, its WebSocket URL is in the `Location` header"),
+        (status = 404, description = "No room has the ID"),
+        (status = 500, description = "The room could not be created"),
+        (status = 503, description = "The server is in maintenance mode, or the creation queue is full or the request expired, retry later"),
+    )
+)]
+async fn rematch_room(path: web::Path<RoomId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let id = path.into_inner();
+    let room = match state.room_registry.get_room(id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    let rematch_id = match create_room_awaited(&state, room.rematch_state(id)).await {
+        Ok(rematch_id) => rematch_id,
+        Err(response) => return response,
+    };
+    info!(event = "room_rematched", room_id = %id, %rematch_id);
+    HttpResponse::Created()
+        .insert_header(room_location(rematch_id))
+        .finish()
+}
+
+/// Queues a party to be matched with others for a game
+///
+/// The party is seated in a private room created for its match once enough players rated
+/// close to it have queued for the same kind of game, which the ticket in the `Location`
+/// header tells. How close their ratings must be loosens the longer the party waits.
+#[utoipa::path(
+    post,
+    path = "/matchmaking/queue",
+    tag = "matchmaking",
+    request_body = QueueRequest,
+    responses(
+        (status = 202, description = "The party was queued, poll the URL in the `Location` header", body = QueueTicket),
+        (status = 400, description = "The game type is blank or too long, the party is empty or too large, or a player was given twice", body = Problem),
+        (status = 409, description = "A player in the party is already queued", body = Problem),
+        (status = 503, description = "The server is in maintenance mode", body = Problem),
+    )
+)]
+async fn enqueue_party(
+    body: web::Json<QueueRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let QueueRequest { game_type, players } = body.into_inner();
+    queue_players(&state, game_type, players)
+}
+
+fn queue_players(
+    state: &SharedAppState,
+    game_type: String,
+    players: Vec<PlayerId>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let rating = state.ratings.party_rating(&game_type, &players);
+    match state.matchmaker.enqueue(game_type, players, rating) {
+        Ok(ticket) => HttpResponse::Accepted()
+            .insert_header(queue_location(ticket))
+            .json(ticket),
+        Err(e @ EnqueueError::AlreadyQueued(_)) => Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/already-queued",
+            "A player in the party is already queued",
+        )
+        .with_detail(e.to_string())
+        .error_response(),
+        Err(e) => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-party",
+            "The party can't be queued",
+        )
+        .with_detail(e.to_string())
+        .error_response(),
+    }
+}
+
+/// Where a queued party stands, with the seats its players join the room with once it has
+/// been matched
+///
+/// Matched tickets are remembered for five minutes.
+#[utoipa::path(
+    get,
+    path = "/matchmaking/queue/{ticket}",
+    tag = "matchmaking",
+    params(("ticket" = QueueTicket, Path, description = "The ticket returned when the party was queued")),
+    responses(
+        (status = 200, body = QueueStatusBody),
+        (status = 404, description = "No queued party has the ticket"),
+    )
+)]
+async fn get_queue_ticket(
+    path: web::Path<QueueTicket>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state.matchmaker.status(path.into_inner()) {
+        None => HttpResponse::NotFound().finish(),
+        Some(TicketStatus::Waiting { game_type, waited }) => {
+            HttpResponse::Ok().json(QueueStatusBody::Waiting {
+                game_type,
+                waited_secs: waited.as_secs(),
+            })
+        }
+        Some(TicketStatus::Matched { room_id, players }) => {
+            let seats = players
+                .into_iter()
+                .map(|player_id| {
+                    mint_seat_body(&state, room_id, player_id, None, DEFAULT_INVITE_TTL)
+                })
+                .collect();
+            HttpResponse::Ok()
+                .insert_header(room_location(room_id))
+                .json(QueueStatusBody::Matched { room_id, seats })
+        }
+    }
+}
+
+/// Takes a party out of its queue
+#[utoipa::path(
+    delete,
+    path = "/matchmaking/queue/{ticket}",
+    tag = "matchmaking",
+    params(("ticket" = QueueTicket, Path)),
+    responses(
+        (status = 204, description = "The party left the queue"),
+        (status = 404, description = "No party waiting in a queue has the ticket"),
+    )
+)]
+async fn leave_queue(
+    path: web::Path<QueueTicket>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if state.matchmaker.leave(path.into_inner()) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+fn party_problem(e: PartyError) -> HttpResponse {
+    let (status, problem_type, title) = match e {
+        PartyError::NotFound | PartyError::NotAMember(_) => (
+            StatusCode::NOT_FOUND,
+            "/problems/not-in-party",
+            "The party or member doesn't exist",
+        ),
+        PartyError::NotInvited(_) => (
+            StatusCode::FORBIDDEN,
+            "/problems/not-invited",
+            "Only invited players can join the party",
+        ),
+        PartyError::AlreadyInParty(_) | PartyError::Full => (
+            StatusCode::CONFLICT,
+            "/problems/party-conflict",
+            "The player can't be added to the party",
+        ),
+    };
+    Problem::new(status, problem_type, title)
+        .with_detail(e.to_string())
+        .error_response()
+}
+
+/// Creates a party led by a player who isn't in one already
+#[utoipa::path(
+    post,
+    path = "/parties/",
+    tag = "parties",
+    request_body = PartyRequest,
+    responses(
+        (status = 201, description = "The party's URL is in the `Location` header", body = Party),
+        (status = 409, description = "The leader is already in a party", body = Problem),
+    )
+)]
+async fn create_party(
+    body: web::Json<PartyRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state.parties.create(body.leader) {
+        Ok(party) => HttpResponse::Created()
+            .insert_header(("LOCATION", format!("/api/v1/parties/{}", party.id)))
+            .json(party),
+        Err(e) => party_problem(e),
+    }
+}
+
+/// A party's members and the players invited to it
+#[utoipa::path(
+    get,
+    path = "/parties/{party_id}",
+    tag = "parties",
+    params(("party_id" = PartyId, Path)),
+    responses(
+        (status = 200, body = Party),
+        (status = 404, description = "No party has the ID"),
+    )
+)]
+async fn get_party(path: web::Path<PartyId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    match state.parties.get(path.into_inner()) {
+        Some(party) => HttpResponse::Ok().json(party),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Invites a player to a party
+#[utoipa::path(
+    post,
+    path = "/parties/{party_id}/invites",
+    tag = "parties",
+    params(("party_id" = PartyId, Path)),
+    request_body = PartyMemberRequest,
+    responses(
+        (status = 200, body = Party),
+        (status = 404, description = "No party has the ID", body = Problem),
+        (status = 409, description = "The player is already a member or the party is full", body = Problem),
+    )
+)]
+async fn invite_to_party(
+    path: web::Path<PartyId>,
+    body: web::Json<PartyMemberRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state.parties.invite(path.into_inner(), body.player_id) {
+        Ok(party) => HttpResponse::Ok().json(party),
+        Err(e) => party_problem(e),
+    }
+}
+
+/// Accepts a player's invite to a party, making them a member
+#[utoipa::path(
+    post,
+    path = "/parties/{party_id}/members",
+    tag = "parties",
+    params(("party_id" = PartyId, Path)),
+    request_body = PartyMemberRequest,
+    responses(
+        (status = 200, body = Party),
+        (status = 403, description = "The player wasn't invited", body = Problem),
+        (status = 404, description = "No party has the ID", body = Problem),
+        (status = 409, description = "The player is already in a party", body = Problem),
+    )
+)]
+async fn join_party(
+    path: web::Path<PartyId>,
+    body: web::Json<PartyMemberRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state.parties.accept(path.into_inner(), body.player_id) {
+        Ok(party) => HttpResponse::Ok().json(party),
+        Err(e) => party_problem(e),
+    }
+}
+
+/// Takes a player out of a party, or withdraws their invite
+///
+/// The lead passes to the longest standing member when the leader leaves, and the party is
+/// disbanded once its last member has.
+#[utoipa::path(
+    delete,
+    path = "/parties/{party_id}/members/{player_id}",
+    tag = "parties",
+    params(("party_id" = PartyId, Path), ("player_id" = PlayerId, Path)),
+    responses(
+        (status = 204, description = "The player left the party"),
+        (status = 404, description = "No party has the ID, or the player isn't in it", body = Problem),
+    )
+)]
+async fn leave_party(
+    path: web::Path<(PartyId, PlayerId)>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let (party_id, player_id) = path.into_inner();
+    match state.parties.leave(party_id, player_id) {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => party_problem(e),
+    }
+}
+
+/// Queues every member of a party to be matched with others, together
+#[utoipa::path(
+    post,
+    path = "/parties/{party_id}/queue",
+    tag = "parties",
+    params(("party_id" = PartyId, Path)),
+    request_body = PartyQueueRequest,
+    responses(
+        (status = 202, description = "The party was queued, poll the URL in the `Location` header", body = QueueTicket),
+        (status = 400, description = "The game type is blank or too long, or the party is too large for a match", body = Problem),
+        (status = 404, description = "No party has the ID"),
+        (status = 409, description = "A member is already queued", body = Problem),
+        (status = 503, description = "The server is in maintenance mode", body = Problem),
+    )
+)]
+async fn queue_party(
+    path: web::Path<PartyId>,
+    body: web::Json<PartyQueueRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let Some(party) = state.parties.get(path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    queue_players(&state, body.into_inner().game_type, party.members)
+}
+
+/// Holds a seat in a room for every member of a party, answering with the token each member
+/// joins with, but only if there are enough free seats for all of them
+///
+/// Seats are held for five minutes, and so are the tokens valid.
+#[utoipa::path(
+    post,
+    path = "/parties/{party_id}/seats",
+    tag = "parties",
+    params(("party_id" = PartyId, Path)),
+    request_body = PartySeatsRequest,
+    responses(
+        (status = 201, description = "The seats are held, the room's WebSocket URL is in the `Location` header", body = MatchBody),
+        (status = 404, description = "No party or room has the ID"),
+        (status = 409, description = "The room doesn't have a free seat for every member", body = Problem),
+    )
+)]
+async fn seat_party(
+    path: web::Path<PartyId>,
+    body: web::Json<PartySeatsRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let room_id = body.room_id;
+    let Some(party) = state.parties.get(path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let room = match state.room_registry.get_room(room_id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    if !room.hold_seats(&party.members, Instant::now() + PARTY_SEAT_TTL) {
+        return Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/room-full",
+            "The room has no free seats",
+        )
+        .with_detail(format!(
+            "The room doesn't have {} free seats",
+            party.members.len()
+        ))
+        .error_response();
+    }
+    let seats = party
+        .members
+        .into_iter()
+        .map(|player_id| mint_seat_body(&state, room_id, player_id, None, PARTY_SEAT_TTL))
+        .collect();
+    HttpResponse::Created()
+        .insert_header(room_location(room_id))
+        .json(MatchBody { room_id, seats })
+}
+
+fn tournament_problem(e: TournamentError) -> HttpResponse {
+    let (status, problem_type, title) = match e {
+        TournamentError::InvalidGameType
+        | TournamentError::InvalidPlayerCount
+        | TournamentError::DuplicatePlayer(_) => (
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-tournament",
+            "The tournament can't be held",
+        ),
+        TournamentError::NotFound | TournamentError::NoSuchMatch => (
+            StatusCode::NOT_FOUND,
+            "/problems/no-such-match",
+            "The tournament or match doesn't exist",
+        ),
+        TournamentError::NotPlayable | TournamentError::NotInMatch(_) => (
+            StatusCode::CONFLICT,
+            "/problems/match-not-playable",
+            "The match can't be played as asked",
+        ),
+    };
+    Problem::new(status, problem_type, title)
+        .with_detail(e.to_string())
+        .error_response()
+}
+
+/// Starts a single-elimination tournament between the registered players
+///
+/// Players are seeded by their rating in the game type, and the top seeds get byes when the
+/// number of players isn't a power of two. Each match gets a private room once both of its
+/// players are known.
+#[utoipa::path(
+    post,
+    path = "/tournaments/",
+    tag = "tournaments",
+    request_body = TournamentRequest,
+    responses(
+        (status = 201, description = "The tournament's URL is in the `Location` header", body = Tournament),
+        (status = 400, description = "The game type is blank or too long, there are fewer than 2 or more than 256 players, or a player is registered twice", body = Problem),
+        (status = 503, description = "The server is in maintenance mode", body = Problem),
+    )
+)]
+async fn create_tournament(
+    body: web::Json<TournamentRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if let Err(problem) = state.maintenance.check_room_creation() {
+        return problem.error_response();
+    }
+    let TournamentRequest {
+        game_type,
+        mut players,
+    } = body.into_inner();
+    let rating = |player_id| state.ratings.rating(&game_type, player_id).rating;
+    players.sort_by(|&a, &b| rating(b).total_cmp(&rating(a)));
+    match state
+        .tournaments
+        .create(game_type.clone(), &players, state.room_registry.registry())
+    {
+        Ok(tournament) => HttpResponse::Created()
+            .insert_header(("LOCATION", format!("/api/v1/tournaments/{}", tournament.id)))
+            .json(tournament),
+        Err(e) => tournament_problem(e),
+    }
+}
+
+/// A tournament's bracket
+#[utoipa::path(
+    get,
+    path = "/tournaments/{tournament_id}",
+    tag = "tournaments",
+    params(("tournament_id" = TournamentId, Path)),
+    responses(
+        (status = 200, body = Tournament),
+        (status = 404, description = "No tournament has the ID"),
+    )
+)]
+async fn get_tournament(
+    path: web::Path<TournamentId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state.tournaments.get(path.into_inner()) {
+        Some(tournament) => HttpResponse::Ok().json(tournament),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// A stream of a tournament's bracket, sent when subscribing and again whenever it changes
+///
+/// Each `bracket` event holds the whole bracket, so one missed by a client that fell behind
+/// is made up for by the next.
+#[utoipa::path(
+    get,
+    path = "/tournaments/{tournament_id}/events",
+    tag = "tournaments",
+    params(("tournament_id" = TournamentId, Path)),
+    responses(
+        (status = 200, description = "`bracket` events", content_type = "text/event-stream"),
+        (status = 404, description = "No tournament has the ID"),
+    )
+)]
+async fn get_tournament_events(
+    path: web::Path<TournamentId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let updates = BroadcastStream::new(state.tournaments.subscribe());
+    let Some(tournament) = state.tournaments.get(id) else {
+        return HttpResponse::NotFound().finish();
+    };
+    let current = tokio_stream::once(Ok(sse_event("bracket", &tournament)));
+    let updates = updates.filter_map(move |update| match update {
+        Ok(tournament) if tournament.id == id => Some(Ok(sse_event("bracket", &tournament))),
+        _ => None,
+    });
+    let heartbeats = IntervalStream::new(tokio::time::interval(LOBBY_HEARTBEAT_INTERVAL))
+        .map(|_| Ok::<_, Infallible>(Bytes::from_static(b": heartbeat\n\n")));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("CACHE-CONTROL", "no-cache"))
+        .streaming(current.chain(updates).merge(heartbeats))
+}
+
+/// Mints the seats the two players of a tournament match join its room with
+///
+/// A new room is opened if the match's room has been deleted, such as after everyone left it.
+#[utoipa::path(
+    post,
+    path = "/tournaments/{tournament_id}/matches/{round}/{index}/seats",
+    tag = "tournaments",
+    params(
+        ("tournament_id" = TournamentId, Path),
+        ("round" = usize, Path, description = "The match's round, from 0 for the first"),
+        ("index" = usize, Path, description = "The match's place in its round, from 0"),
+    ),
+    responses(
+        (status = 201, description = "The room's WebSocket URL is in the `Location` header", body = MatchBody),
+        (status = 404, description = "No tournament or match has the ID", body = Problem),
+        (status = 409, description = "The match has been decided or is waiting for its players", body = Problem),
+    )
+)]
+async fn seat_tournament_match(
+    path: web::Path<(TournamentId, usize, usize)>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let (id, round, index) = path.into_inner();
+    match state
+        .tournaments
+        .match_room(id, round, index, state.room_registry.registry())
+    {
+        Ok((room_id, players)) => {
+            let seats = players
+                .into_iter()
+                .map(|player_id| {
+                    mint_seat_body(&state, room_id, player_id, None, DEFAULT_INVITE_TTL)
+                })
+                .collect();
+            HttpResponse::Created()
+                .insert_header(room_location(room_id))
+                .json(MatchBody { room_id, seats })
+        }
+        Err(e) => tournament_problem(e),
+    }
+}
+
+/// Reports the winner of a tournament match, who moves on to their next match
+///
+/// The match's room is closed, and the result is rated like any other game.
+#[utoipa::path(
+    post,
+    path = "/tournaments/{tournament_id}/matches/{round}/{index}/result",
+    tag = "tournaments",
+    params(
+        ("tournament_id" = TournamentId, Path),
+        ("round" = usize, Path, description = "The match's round, from 0 for the first"),
+        ("index" = usize, Path, description = "The match's place in its round, from 0"),
+    ),
+    request_body = MatchResultRequest,
+    responses(
+        (status = 200, body = Tournament),
+        (status = 404, description = "No tournament or match has the ID", body = Problem),
+        (status = 409, description = "The match has been decided or is waiting for its players, or the winner isn't playing in it", body = Problem),
+    )
+)]
+async fn report_tournament_match(
+    path: web::Path<(TournamentId, usize, usize)>,
+    body: web::Json<MatchResultRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let (id, round, index) = path.into_inner();
+    let winner = body.winner;
+    match state.tournaments.report(
+        id,
+        round,
+        index,
+        winner,
+        state.room_registry.registry(),
+        &state.room_deletion_queue,
+    ) {
+        Ok((tournament, loser)) => {
+            let result = GameResult {
+                game_type: tournament.game_type.clone(),
+                standings: vec![vec![winner], vec![loser]],
+            };
+            if let Err(e) = rate_game(&state, &result) {
+                warn!(event = "tournament_match_not_rated", error = %e);
+            }
+            HttpResponse::Ok().json(tournament)
+        }
+        Err(e) => tournament_problem(e),
+    }
+}
+
+/// Calls a tournament off, closing the rooms of its undecided matches
+#[utoipa::path(
+    delete,
+    path = "/tournaments/{tournament_id}",
+    tag = "tournaments",
+    params(("tournament_id" = TournamentId, Path)),
+    responses(
+        (status = 204, description = "The tournament was called off"),
+        (status = 404, description = "No tournament has the ID"),
+    )
+)]
+async fn delete_tournament(
+    path: web::Path<TournamentId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    if state
+        .tournaments
+        .cancel(path.into_inner(), &state.room_deletion_queue)
+    {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+/// Rates a finished game and logs it for the leaderboards
+fn rate_game(
+    state: &SharedAppState,
+    result: &GameResult,
+) -> Result<Vec<(PlayerId, Rating)>, GameResultError> {
+    let updated = state.ratings.record(result)?;
+    state.leaderboards.record(result, &updated);
+    Ok(updated)
+}
+
+/// A page of the best rated players in a game type who played in the period, then by their
+/// wins
+///
+/// Leaderboards are rebuilt on a schedule, so the latest results may take a while to show.
+#[utoipa::path(
+    get,
+    path = "/leaderboards/{game_type}",
+    tag = "players",
+    params(("game_type" = String, Path), LeaderboardQuery),
+    responses(
+        (status = 200, body = LeaderboardBody),
+        (status = 400, description = "The limit is over 100", body = Problem),
+    )
+)]
+async fn get_leaderboard(
+    path: web::Path<String>,
+    query: web::Query<LeaderboardQuery>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let game_type = path.into_inner();
+    let LeaderboardQuery {
+        period,
+        offset,
+        limit,
+    } = query.into_inner();
+    if limit > MAX_LEADERBOARD_PAGE {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/page-too-large",
+            "The page is too large",
+        )
+        .with_detail(format!(
+            "Pages can hold at most {MAX_LEADERBOARD_PAGE} entries"
+        ))
+        .error_response();
+    }
+    let (total, entries) = state.leaderboards.page(&game_type, period, offset, limit);
+    HttpResponse::Ok().json(LeaderboardBody {
+        game_type,
+        period,
+        total,
+        entries,
+    })
+}
+
+/// Rates the players of a finished game, answering with their new ratings in the order they
+/// placed
+#[utoipa::path(
+    post,
+    path = "/results",
+    tag = "players",
+    request_body = GameResult,
+    responses(
+        (status = 200, body = [PlayerRatingBody]),
+        (status = 400, description = "The game type is blank or too long, fewer than two places have players, or a player is in more than one place", body = Problem),
+    )
+)]
+async fn record_game_result(
+    body: web::Json<GameResult>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match rate_game(&state, &body) {
+        Ok(updated) => HttpResponse::Ok().json(
+            updated
+                .into_iter()
+                .map(|(player_id, rating)| PlayerRatingBody { player_id, rating })
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-game-result",
+            "The game's result can't be rated",
+        )
+        .with_detail(e.to_string())
+        .error_response(),
+    }
+}
+
+/// A player's skill rating in each game type they've played
+#[utoipa::path(
+    get,
+    path = "/players/{player_id}/stats",
+    tag = "players",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 200, body = PlayerStatsBody))
+)]
+async fn get_player_stats(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(PlayerStatsBody {
+        ratings: state.ratings.ratings_of(path.into_inner()),
+    })
+}
+
+/// Every ban in force
+#[utoipa::path(get, path = "/bans/", tag = "admin", responses((status = 200, body = [Ban])))]
+async fn get_bans(state: web::Data<SharedAppState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.ban_list.active_bans())
+}
+
+/// Bans a player or network from the server
+#[utoipa::path(
+    post,
+    path = "/bans/",
+    tag = "admin",
+    request_body = BanRequest,
+    responses((status = 201, description = "The ban's URL is in the `Location` header", body = Ban))
+)]
+async fn create_ban(body: web::Json<BanRequest>, state: web::Data<SharedAppState>) -> HttpResponse {
+    let BanRequest {
+        target,
+        reason,
+        duration_secs,
+    } = body.into_inner();
+    let ban = state.ban_list.ban(
+        target,
+        reason,
+        duration_secs.map(Duration::from_secs),
+        API_ACTOR.to_owned(),
+    );
+    HttpResponse::Created()
+        .insert_header(("LOCATION", format!("/api/v1/bans/{}", ban.id)))
+        .json(ban)
+}
+
+/// Lifts a ban
+#[utoipa::path(
+    delete,
+    path = "/bans/{ban_id}",
+    tag = "admin",
+    params(("ban_id" = BanId, Path)),
+    responses(
+        (status = 204, description = "The ban was lifted"),
+        (status = 404, description = "No ban has the ID"),
+    )
+)]
+async fn delete_ban(path: web::Path<BanId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    match state.ban_list.lift(path.into_inner(), API_ACTOR.to_owned()) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// The most recent bans issued and lifted, oldest first
+#[utoipa::path(get, path = "/bans/audit", tag = "admin", responses((status = 200, body = [AuditEntry])))]
+async fn get_ban_audit_log(state: web::Data<SharedAppState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.ban_list.audit_log())
+}
+
+/// Revokes every token issued to the player so far and closes their live connections
+#[utoipa::path(
+    delete,
+    path = "/players/{player_id}/sessions",
+    tag = "admin",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 200, body = RevocationBody))
+)]
+async fn delete_player_sessions(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let closed_connections = state.sessions.revoke(path.into_inner(), API_ACTOR);
+    HttpResponse::Ok().json(RevocationBody { closed_connections })
+}
+
+/// Where a player is connected, on this instance or any other sharing the lobby, for
+/// inviting them or joining their room
+///
+/// Other instances report their players with their heartbeat, so a player who connected to
+/// one in the last few seconds may not be found yet.
+#[utoipa::path(
+    get,
+    path = "/players/{player_id}/presence",
+    tag = "players",
+    params(("player_id" = PlayerId, Path)),
+    responses(
+        (status = 200, body = PlayerPresenceBody),
+        (status = 503, description = "Redis couldn't be reached", body = Problem),
+    )
+)]
+async fn get_player_presence(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let player_id = path.into_inner();
+    let rooms: Vec<_> = match &state.cluster {
+        Some(cluster) => match cluster.locate_player(player_id).await {
+            Ok(locations) => locations
+                .into_iter()
+                .map(|location| {
+                    PlayerRoomBody::new(
+                        location.room_id,
+                        Some(location.node),
+                        location.public_url.as_deref(),
+                    )
+                })
+                .collect(),
+            Err(e) => {
+                return Problem::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "/problems/cluster-unavailable",
+                    "The other instances couldn't be reached",
+                )
+                .with_detail(e.to_string())
+                .error_response()
+            }
+        },
+        None => state
+            .sessions
+            .rooms_of(player_id)
+            .into_iter()
+            .map(|room_id| PlayerRoomBody::new(room_id, None, None))
+            .collect(),
+    };
+    HttpResponse::Ok().json(PlayerPresenceBody {
+        online: !rooms.is_empty(),
+        rooms,
+    })
+}
+
+fn invitation_problem(e: InvitationError) -> HttpResponse {
+    let (status, problem_type, title) = match e {
+        InvitationError::SelfInvite => (
+            StatusCode::BAD_REQUEST,
+            "/problems/self-invite",
+            "Players can't invite themselves",
+        ),
+        InvitationError::InboxFull(_) => (
+            StatusCode::CONFLICT,
+            "/problems/inbox-full",
+            "The player has too many unanswered invitations",
+        ),
+        InvitationError::NotFound => (
+            StatusCode::NOT_FOUND,
+            "/problems/no-invitation",
+            "The player has no such invitation",
+        ),
+    };
+    Problem::new(status, problem_type, title)
+        .with_detail(e.to_string())
+        .error_response()
+}
+
+/// Hands the turn in the room's game to a player, sending them a push notification if they
+/// aren't connected to a room anywhere
+///
+/// Players who are connected are left to hear about it from the game itself. In a
+/// turn-based room hosted by this instance the player then has the room's turn timeout to
+/// take their turn before the room is deleted.
+#[utoipa::path(
+    post,
+    path = "/rooms/{room_id}/turn",
+    tag = "rooms",
+    params(("room_id" = RoomId, Path)),
+    request_body = TurnRequest,
+    responses(
+        (status = 200, body = TurnBody),
+        (status = 404, description = "No instance sharing the lobby hosts the room"),
+    )
+)]
+async fn pass_turn(
+    path: web::Path<RoomId>,
+    body: web::Json<TurnRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let room_id = path.into_inner();
+    let TurnRequest { player_id } = body.into_inner();
+    let turn = match state.room_registry.get_room(room_id).await {
+        Ok(Some(room)) => room.pass_turn(room_id, player_id, state.room_deletion_queue.clone()),
+        Ok(None)
+            if state
+                .remote_rooms
+                .list()
+                .iter()
+                .any(|room| room.id == room_id) =>
+        {
+            None
+        }
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    if turn.is_some() {
+        state.room_registry.registry().room_updated(room_id);
+        state.turn_based_rooms.mark_changed();
+    }
+    let pushed = match &state.push {
+        Some(push) if !is_online(&state.sessions, state.cluster.as_deref(), player_id).await => {
+            push.push(PushNotification {
+                player_id,
+                room_id,
+                reason: PushReason::YourTurn,
+            });
+            true
+        }
+        _ => false,
+    };
+    let deadline = turn.map(|turn| turn.deadline);
+    info!(event = "turn_passed", %room_id, %player_id, pushed, deadline);
+    HttpResponse::Ok().json(TurnBody { pushed, deadline })
+}
+
+/// Invites a player to a room, holding a seat in it for them
+///
+/// In a room hosted by this instance the seat is held for as long as the invitation lasts,
+/// rooms hosted by other instances only take the seat if they have one free when the player
+/// joins. The invitation is sent straight away to the player's connections to any room, on this
+/// instance or any other sharing the lobby, and is otherwise kept in their inbox until they
+/// next connect to this instance, and sent to them as a push notification if a push gateway
+/// is configured. The player who sent it is sent the answer the same way.
+#[utoipa::path(
+    post,
+    path = "/players/{player_id}/invite",
+    tag = "players",
+    params(("player_id" = PlayerId, Path, description = "The player being invited")),
+    request_body = InvitationRequest,
+    responses(
+        (status = 201, body = InvitationBody),
+        (status = 400, description = "The player invited themselves", body = Problem),
+        (status = 404, description = "No instance sharing the lobby hosts the room"),
+        (status = 409, description = "The player has too many unanswered invitations, or the room no free seats", body = Problem),
+    )
+)]
+async fn invite_player(
+    path: web::Path<PlayerId>,
+    body: web::Json<InvitationRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let player_id = path.into_inner();
+    let InvitationRequest { from, room_id } = body.into_inner();
+    let room = match state.room_registry.get_room(room_id).await {
+        Ok(room) => room,
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    if room.is_none()
+        && !state
+            .remote_rooms
+            .list()
+            .iter()
+            .any(|room| room.id == room_id)
+    {
+        return HttpResponse::NotFound().finish();
+    }
+    let seat = state
+        .invite_signer
+        .mint_seat(room_id, player_id, None, DEFAULT_INVITE_TTL);
+    let invitation = match state.invitations.send(from, player_id, room_id, seat) {
+        Ok(invitation) => invitation,
+        Err(e) => return invitation_problem(e),
+    };
+    if let Some(room) = &room {
+        if !room.hold_seats(&[player_id], Instant::now() + DEFAULT_INVITE_TTL) {
+            state.invitations.forget(invitation.id);
+            return Problem::new(
+                StatusCode::CONFLICT,
+                "/problems/room-full",
+                "The room has no free seats",
+            )
+            .error_response();
+        }
+    }
+    let delivered = deliver(
+        &state.sessions,
+        state.cluster.as_deref(),
+        player_id,
+        ServerMessage::Invitation(invitation.clone()),
+    )
+    .await;
+    if delivered {
+        state.invitations.delivered(player_id, &invitation);
+    } else if let Some(push) = &state.push {
+        push.push(PushNotification {
+            player_id,
+            room_id,
+            reason: PushReason::Invited { from },
+        });
+    }
+    HttpResponse::Created().json(InvitationBody {
+        invitation,
+        delivered,
+    })
+}
+
+/// The player's unanswered invitations, oldest first
+#[utoipa::path(
+    get,
+    path = "/players/{player_id}/invitations",
+    tag = "players",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 200, body = [RoomInvitation]))
+)]
+async fn get_player_invitations(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(state.invitations.inbox(path.into_inner()))
+}
+
+/// Accepts or declines an invitation on the player's behalf, telling the player who sent it
+///
+/// Accepting doesn't put the player in the room, they join it with the invitation's seat.
+#[utoipa::path(
+    post,
+    path = "/players/{player_id}/invitations/{invitation_id}",
+    tag = "players",
+    params(("player_id" = PlayerId, Path), ("invitation_id" = InvitationId, Path)),
+    request_body = InvitationAnswerRequest,
+    responses(
+        (status = 204, description = "The invitation was answered"),
+        (status = 404, description = "The player has no unanswered invitation with the ID", body = Problem),
+    )
+)]
+async fn answer_invitation(
+    path: web::Path<(PlayerId, InvitationId)>,
+    body: web::Json<InvitationAnswerRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let (player_id, invitation_id) = path.into_inner();
+    let accept = body.accept;
+    match state.invitations.answer(invitation_id, player_id, accept) {
+        Ok(invitation) => {
+            deliver_answer(
+                &state.sessions,
+                state.cluster.as_deref(),
+                &invitation,
+                player_id,
+                accept,
+            )
+            .await;
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => invitation_problem(e),
+    }
+}
+
+/// Whether the server is in maintenance mode
+#[utoipa::path(get, path = "/maintenance", tag = "admin", responses((status = 200, body = MaintenanceBody)))]
+async fn get_maintenance(state: web::Data<SharedAppState>) -> HttpResponse {
+    HttpResponse::Ok().json(MaintenanceBody {
+        enabled: state.maintenance.is_enabled(),
+    })
+}
+
+/// Turns maintenance mode on or off
+///
+/// While it's on, rooms can't be created but players can still join the rooms that exist,
+/// so games under way can finish.
+#[utoipa::path(
+    put,
+    path = "/maintenance",
+    tag = "admin",
+    request_body = MaintenanceBody,
+    responses((status = 200, body = MaintenanceBody))
+)]
+async fn put_maintenance(
+    body: web::Json<MaintenanceBody>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    state.maintenance.set_enabled(body.enabled, API_ACTOR);
+    HttpResponse::Ok().json(body.into_inner())
+}
+
+/// How busy this instance is, and how busy every instance sharing the lobby was as of their
+/// last heartbeats
+#[utoipa::path(get, path = "/load", tag = "admin", responses((status = 200, body = LoadBody)))]
+async fn get_load(state: web::Data<SharedAppState>) -> HttpResponse {
+    let nodes = state.cluster.as_ref().map_or_else(Vec::new, |cluster| {
+        cluster
+            .nodes()
+            .iter()
+            .map(|node| NodeLoadBody {
+                id: node.id,
+                public_url: node.public_url.clone(),
+                capacity: node.capacity,
+                rooms: node.rooms.len(),
+                score: node.load,
+            })
+            .collect()
+    });
+    HttpResponse::Ok().json(LoadBody {
+        local: LoadReport::measure(state.room_registry.registry(), state.load_limits),
+        nodes,
+    })
+}
+
+/// What this instance has left to give: its rooms and connections against its capacity,
+/// how much of each is left, which are used up, and whether it should be sent new rooms
+///
+/// Meant for autoscalers and load balancers to poll, rather than the Prometheus metrics.
+#[utoipa::path(
+    get,
+    path = "/admin/capacity",
+    tag = "admin",
+    responses((status = 200, body = CapacityReport))
+)]
+async fn get_capacity(state: web::Data<SharedAppState>) -> HttpResponse {
+    let load = LoadReport::measure(state.room_registry.registry(), state.load_limits);
+    HttpResponse::Ok().json(CapacityReport::new(
+        &load,
+        state.load_limits,
+        state.maintenance.is_enabled(),
+    ))
+}
+
+/// Hands every room over to the other instances ahead of taking this one down
+///
+/// Maintenance mode is turned on first so no rooms are created in the meantime. Players in
+/// rooms another instance takes over are told to reconnect there, the rest stay here.
+#[utoipa::path(
+    post,
+    path = "/drain",
+    tag = "admin",
+    responses(
+        (status = 200, body = DrainReport),
+        (status = 409, description = "The instance isn't sharing its lobby with others", body = Problem),
+        (status = 503, description = "Redis couldn't be reached", body = Problem),
+    )
+)]
+async fn drain(state: web::Data<SharedAppState>) -> HttpResponse {
+    let Some(cluster) = &state.cluster else {
+        return Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/not-clustered",
+            "There are no other instances to hand rooms over to",
+        )
+        .error_response();
+    };
+    state.maintenance.set_enabled(true, API_ACTOR);
+    match cluster.drain(&state.invite_signer).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => Problem::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "/problems/cluster-unavailable",
+            "The other instances couldn't be reached",
+        )
+        .with_detail(e.to_string())
+        .error_response(),
+    }
+}
+
+/// Pushes an announcement, such as a warning of upcoming maintenance, to every connection
+/// in the rooms it's scoped to, on every instance sharing the lobby
+///
+/// Without `rooms` or `regions` every room is reached. Clients speaking protocol versions
+/// older than 6 aren't sent it.
+#[utoipa::path(
+    post,
+    path = "/admin/announcements",
+    tag = "admin",
+    request_body = AnnouncementRequest,
+    responses(
+        (status = 200, body = AnnouncementBody),
+        (status = 400, description = "The announcement is empty or too long", body = Problem),
+    )
+)]
+async fn create_announcement(
+    body: web::Json<AnnouncementRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let AnnouncementRequest {
+        text,
+        rooms,
+        regions,
+    } = body.into_inner();
+    let scope = AnnouncementScope { rooms, regions };
+    if let Err(detail) = check_announcement_text(&text) {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-announcement",
+            "The announcement can't be made",
+        )
+        .with_detail(detail)
+        .error_response();
+    }
+    let report = announcements::announce(state.room_registry.registry(), &text, &scope);
+    let relayed = match &state.cluster {
+        Some(cluster) => match cluster.announce(&text, &scope).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(event = "announcement_relay_failed", error = %e);
+                false
+            }
+        },
+        None => false,
+    };
+    info!(event = "announcement_made", actor = API_ACTOR, relayed);
+    HttpResponse::Ok().json(AnnouncementBody {
+        rooms: report.rooms,
+        connections: report.connections,
+        relayed,
+    })
+}
+
+/// Removes a room at once, for rooms stuck open
+///
+/// Unlike deleting a room, nothing waits on its deletion timer or the deletion queue: the
+/// timer is aborted, every connection is closed with the `admin_closed` reason and the room
+/// is removed from the registry before the response is sent.
+#[utoipa::path(
+    post,
+    path = "/admin/rooms/{room_id}/force-close",
+    tag = "admin",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (status = 200, body = ForceCloseBody),
+        (status = 404, description = "No room has the ID"),
+    )
+)]
+async fn force_close_room(
+    path: web::Path<RoomId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let room = match state.room_registry.get_room(id).await {
+        Ok(Some(room)) => room,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    };
+    if let Err(busy) = state.room_registry.delete_room(id).await {
+        return Problem::from(busy).error_response();
+    }
+    let closed_connections = room.broadcaster().subscriber_count();
+    room.cancel_deletion();
+    room.close_with_reason(ADMIN_CLOSED_REASON);
+    info!(
+        event = "room_force_closed",
+        room_id = %id,
+        closed_connections,
+        actor = API_ACTOR
+    );
+    HttpResponse::Ok().json(ForceCloseBody { closed_connections })
+}
+
+/// The room the line of rematches the room belongs to started with, which is the room itself
+/// once it's gone
+async fn lineage_of(state: &SharedAppState, room_id: RoomId) -> Result<RoomId, Problem> {
+    Ok(state
+        .room_registry
+        .get_room(room_id)
+        .await?
+        .map_or(room_id, |room| room.lineage(room_id)))
+}
+
+/// The players kept out of a room and its rematches, oldest ban first
+///
+/// The room can be any in the line of rematches while it's open, or the room the line
+/// started with at any time.
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}/bans",
+    tag = "admin",
+    params(("room_id" = RoomId, Path)),
+    responses((status = 200, body = [RoomBan]))
+)]
+async fn get_room_bans(path: web::Path<RoomId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    let lineage = match lineage_of(&state, path.into_inner()).await {
+        Ok(lineage) => lineage,
+        Err(problem) => return problem.error_response(),
+    };
+    HttpResponse::Ok().json(state.room_bans.list(lineage))
+}
+
+/// Lets a player back into a room and its rematches
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/bans/{player_id}",
+    tag = "admin",
+    params(("room_id" = RoomId, Path), ("player_id" = PlayerId, Path)),
+    responses(
+        (status = 204, description = "The ban was lifted"),
+        (status = 404, description = "The player isn't banned from the room"),
+    )
+)]
+async fn delete_room_ban(
+    path: web::Path<(RoomId, PlayerId)>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let (room_id, player_id) = path.into_inner();
+    let lineage = match lineage_of(&state, room_id).await {
+        Ok(lineage) => lineage,
+        Err(problem) => return problem.error_response(),
+    };
+    match state.room_bans.lift(lineage, player_id, API_ACTOR) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn report_problem(e: ReportError) -> HttpResponse {
+    let (status, problem_type, title) = match e {
+        ReportError::SelfReport | ReportError::MissingReason | ReportError::ReasonTooLong => (
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-report",
+            "The report can't be made",
+        ),
+        ReportError::Duplicate(_) => (
+            StatusCode::CONFLICT,
+            "/problems/duplicate-report",
+            "The player has already been reported",
+        ),
+        ReportError::NotFound => (
+            StatusCode::NOT_FOUND,
+            "/problems/no-report",
+            "No report has the ID",
+        ),
+        ReportError::AlreadyResolved => (
+            StatusCode::CONFLICT,
+            "/problems/report-resolved",
+            "The report has already been resolved",
+        ),
+    };
+    Problem::new(status, problem_type, title)
+        .with_detail(e.to_string())
+        .error_response()
+}
+
+/// Reports a player to the moderators, on behalf of another player
+///
+/// A player can only have one unresolved report of another player in a room.
+#[utoipa::path(
+    post,
+    path = "/reports",
+    tag = "players",
+    request_body = ReportRequest,
+    responses(
+        (status = 201, description = "The report's URL is in the `Location` header", body = Report),
+        (status = 400, description = "The player reported themselves, or the reason is empty or too long", body = Problem),
+        (status = 409, description = "The player already reported the other in the room", body = Problem),
+    )
+)]
+async fn create_report(
+    body: web::Json<ReportRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let ReportRequest {
+        reporter,
+        reported,
+        room_id,
+        reason,
+        include_chat,
+    } = body.into_inner();
+    // A report is worth taking even without the chat leading up to it
+    let chat_excerpt = match state.room_registry.get_room(room_id).await {
+        Ok(Some(room)) if include_chat => reports::chat_excerpt(room.chat_history(), reported),
+        _ => Vec::new(),
+    };
+    match state.reports.submit(NewReport {
+        reporter: Some(reporter),
+        reported,
+        room_id,
+        reason,
+        chat_excerpt,
+    }) {
+        Ok(report) => HttpResponse::Created()
+            .insert_header(("LOCATION", format!("/api/v1/admin/reports/{}", report.id)))
+            .json(report),
+        Err(e) => report_problem(e),
+    }
+}
+
+/// The moderation queue, oldest report first
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    tag = "admin",
+    params(ReportStatusQuery),
+    responses((status = 200, body = [Report]))
+)]
+async fn get_reports(
+    query: web::Query<ReportStatusQuery>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    HttpResponse::Ok().json(state.reports.list(query.status))
+}
+
+/// A single report
+#[utoipa::path(
+    get,
+    path = "/admin/reports/{report_id}",
+    tag = "admin",
+    params(("report_id" = ReportId, Path)),
+    responses(
+        (status = 200, body = Report),
+        (status = 404, description = "No report has the ID"),
+    )
+)]
+async fn get_report(path: web::Path<ReportId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    match state.reports.get(path.into_inner()) {
+        Some(report) => HttpResponse::Ok().json(report),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Acts against the reported player and records the action on the report
+#[utoipa::path(
+    post,
+    path = "/admin/reports/{report_id}/actions",
+    tag = "admin",
+    params(("report_id" = ReportId, Path)),
+    request_body = ReportActionRequest,
+    responses(
+        (status = 200, body = Report),
+        (status = 404, description = "No report has the ID", body = Problem),
+        (status = 409, description = "The report has already been resolved", body = Problem),
+    )
+)]
+async fn act_on_report(
+    path: web::Path<ReportId>,
+    body: web::Json<ReportActionRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let report = match state.reports.get(id) {
+        Some(report) if report.status == ReportStatus::Resolved => {
+            return report_problem(ReportError::AlreadyResolved)
+        }
+        Some(report) => report,
+        None => return report_problem(ReportError::NotFound),
+    };
+    let action = match body.into_inner() {
+        ReportActionRequest::Disconnect => ModerationAction::Disconnected {
+            closed_connections: state.sessions.disconnect(
+                report.reported,
+                ADMIN_DISCONNECTED_REASON,
+                API_ACTOR,
+            ),
+        },
+        ReportActionRequest::Ban {
+            reason,
+            duration_secs,
+        } => {
+            let ban = state.ban_list.ban(
+                BanTarget::Player {
+                    player_id: report.reported,
+                },
+                reason.unwrap_or(report.reason),
+                duration_secs.map(Duration::from_secs),
+                API_ACTOR.to_owned(),
+            );
+            state
+                .sessions
+                .disconnect(report.reported, BANNED_REASON, API_ACTOR);
+            ModerationAction::Banned { ban_id: ban.id }
+        }
+        ReportActionRequest::WipeDisplayName => {
+            state.sessions.wipe_display_name(report.reported, API_ACTOR);
+            ModerationAction::DisplayNameWiped
+        }
+    };
+    match state.reports.record_action(id, action, API_ACTOR) {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => report_problem(e),
+    }
+}
+
+/// Takes a report off the queue, whether or not anything was done about it
+#[utoipa::path(
+    post,
+    path = "/admin/reports/{report_id}/resolution",
+    tag = "admin",
+    params(("report_id" = ReportId, Path)),
+    request_body = ResolutionRequest,
+    responses(
+        (status = 200, body = Report),
+        (status = 404, description = "No report has the ID", body = Problem),
+        (status = 409, description = "The report has already been resolved", body = Problem),
+    )
+)]
+async fn resolve_report(
+    path: web::Path<ReportId>,
+    body: web::Json<ResolutionRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    match state
+        .reports
+        .resolve(path.into_inner(), body.into_inner().note, API_ACTOR)
+    {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => report_problem(e),
+    }
+}
+
+/// Logs that an operator stopped watching a room as the room's event stream is dropped
+/// An operator watching a room, recorded in the audit trail as they start and once the
+/// stream is dropped, however it ended
+struct RoomWatch {
+    room_id: RoomId,
+    ban_list: Arc<BanList>,
+    /// Whether the event ending the stream has been sent
+    ended: bool,
+}
+
+impl RoomWatch {
+    fn start(room_id: RoomId, ban_list: Arc<BanList>) -> Self {
+        info!(event = "room_watch_started", room_id = %room_id, actor = API_ACTOR);
+        ban_list.audit(
+            API_ACTOR.to_owned(),
+            AuditAction::RoomWatchStarted { room_id },
+        );
+        Self {
+            room_id,
+            ban_list,
+            ended: false,
+        }
+    }
+}
+
+impl Drop for RoomWatch {
+    fn drop(&mut self) {
+        let room_id = self.room_id;
+        info!(event = "room_watch_ended", room_id = %room_id, actor = API_ACTOR);
+        self.ban_list.audit(
+            API_ACTOR.to_owned(),
+            AuditAction::RoomWatchEnded { room_id },
+        );
+    }
+}
+
+/// A live stream of what happens in a room hosted by this instance, for moderating or
+/// debugging it
+///
+/// Connections joining and leaving are sent as `joined` and `left` events, and every
+/// message a connection sends, whether or not it was allowed to, as a `message` event.
+/// Changes to the room's settings and players are sent as `room_updated` events, and the
+/// stream ends with a `room_deleted` event once the room is gone. A `resync` event means the
+/// stream fell behind and missed events. Who watches which room is kept in the audit trail.
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}/watch",
+    tag = "admin",
+    params(("room_id" = RoomId, Path)),
+    responses(
+        (
+            status = 200,
+            description = "`joined`, `left`, `message`, `room_updated`, `room_deleted` and \
+                `resync` events",
+            content_type = "text/event-stream",
+        ),
+        (status = 404, description = "No room on this instance has the ID"),
+    )
+)]
+async fn watch_room(path: web::Path<RoomId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    let room_id = path.into_inner();
+    let feed = BroadcastStream::new(state.room_watchers.watch(room_id));
+    let lobby = BroadcastStream::new(state.room_registry.lobby().subscribe());
+    match state.room_registry.get_room(room_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(busy) => return Problem::from(busy).error_response(),
+    }
+    let watch = RoomWatch::start(room_id, state.ban_list.clone());
+
+    let events = feed.map(|event| match event {
+        Ok(event) => (sse_event(event.name(), &event), false),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            (sse_event("resync", &ResyncBody { missed }), false)
+        }
+    });
+    let transitions = lobby.filter_map(move |event| {
+        let event = event.ok()?;
+        let (LobbyEvent::RoomUpdated(RoomSummary { id, .. }) | LobbyEvent::RoomDeleted { id }) =
+            &event
+        else {
+            return None;
+        };
+        let deleted = matches!(event, LobbyEvent::RoomDeleted { .. });
+        (*id == room_id).then(|| (sse_event(event.name(), &event), deleted))
+    });
+    let heartbeats = IntervalStream::new(tokio::time::interval(LOBBY_HEARTBEAT_INTERVAL))
+        .map(|_| (Bytes::from_static(b": heartbeat\n\n"), false));
+    let events = events.merge(transitions).merge(heartbeats);
+    // Ends the stream as soon as the event ending it is sent, the watch being dropped with it
+    let stream =
+        futures_util::stream::unfold((events, watch), |(mut events, mut watch)| async move {
+            if watch.ended {
+                return None;
+            }
+            let (event, last) = events.next().await?;
+            watch.ended = last;
+            Some((Ok::<_, Infallible>(event), (events, watch)))
+        });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("CACHE-CONTROL", "no-cache"))
+        .streaming(stream)
+}
+
+/// The players connected to this instance whose display name contains the query
+#[utoipa::path(
+    get,
+    path = "/admin/players",
+    tag = "admin",
+    params(PlayerSearchQuery),
+    responses((status = 200, body = [PlayerBody]))
+)]
+async fn find_players(
+    query: web::Query<PlayerSearchQuery>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let players: Vec<_> = state
+        .sessions
+        .find_by_display_name(&query.display_name)
+        .into_iter()
+        .map(|sessions| PlayerBody::new(sessions, &state))
+        .collect();
+    HttpResponse::Ok().json(players)
+}
+
+/// A player's display name, live connections to this instance, revocation and ban
+///
+/// Every player ID can be looked up, a player who never connected having no connections.
+#[utoipa::path(
+    get,
+    path = "/admin/players/{player_id}",
+    tag = "admin",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 200, body = PlayerBody))
+)]
+async fn get_player(path: web::Path<PlayerId>, state: web::Data<SharedAppState>) -> HttpResponse {
+    let sessions = state.sessions.player(path.into_inner());
+    HttpResponse::Ok().json(PlayerBody::new(sessions, &state))
+}
+
+/// Closes the player's live connections to this instance with the `admin_disconnected`
+/// reason, leaving them free to connect again
+#[utoipa::path(
+    post,
+    path = "/admin/players/{player_id}/disconnect",
+    tag = "admin",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 200, body = DisconnectBody))
+)]
+async fn disconnect_player(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let closed_connections =
+        state
+            .sessions
+            .disconnect(path.into_inner(), ADMIN_DISCONNECTED_REASON, API_ACTOR);
+    HttpResponse::Ok().json(DisconnectBody { closed_connections })
+}
+
+/// Bans the player from the server and closes their live connections to this instance with
+/// the `banned` reason
+#[utoipa::path(
+    post,
+    path = "/admin/players/{player_id}/ban",
+    tag = "admin",
+    params(("player_id" = PlayerId, Path)),
+    request_body = PlayerBanRequest,
+    responses((status = 201, description = "The ban's URL is in the `Location` header", body = PlayerBanBody))
+)]
+async fn ban_player(
+    path: web::Path<PlayerId>,
+    body: web::Json<PlayerBanRequest>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    let player_id = path.into_inner();
+    let PlayerBanRequest {
+        reason,
+        duration_secs,
+    } = body.into_inner();
+    let ban = state.ban_list.ban(
+        BanTarget::Player { player_id },
+        reason,
+        duration_secs.map(Duration::from_secs),
+        API_ACTOR.to_owned(),
+    );
+    let closed_connections = state
+        .sessions
+        .disconnect(player_id, BANNED_REASON, API_ACTOR);
+    HttpResponse::Created()
+        .insert_header(("LOCATION", format!("/api/v1/bans/{}", ban.id)))
+        .json(PlayerBanBody {
+            ban,
+            closed_connections,
+        })
+}
+
+/// Stops showing the display name the player's tokens give them, until the server restarts
+#[utoipa::path(
+    delete,
+    path = "/admin/players/{player_id}/display-name",
+    tag = "admin",
+    params(("player_id" = PlayerId, Path)),
+    responses((status = 204, description = "The display name was wiped"))
+)]
+async fn wipe_display_name(
+    path: web::Path<PlayerId>,
+    state: web::Data<SharedAppState>,
+) -> HttpResponse {
+    state
+        .sessions
+        .wipe_display_name(path.into_inner(), API_ACTOR);
+    HttpResponse::NoContent().finish()
+}
+
+/// Describes the API's bearer token authentication
+struct ApiTokenSecurity;
+
+impl Modify for ApiTokenSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+/// The OpenAPI document describing the REST API
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Wormhole",
+        description = "Every endpoint answers with an `application/problem+json` body when it turns a \
+            request away: `401` without a valid API token, `403` when a read-only token asks to \
+            change something, and `429` with a `Retry-After` header when the client's request \
+            budget is used up. The `admin` endpoints are served on the admin listener instead \
+            when one is configured."
+    ),
+    servers((url = "/api/v1")),
+    paths(
+        create_room,
+        schedule_room,
+        create_turn_based_room,
+        get_rooms,
+        get_room_events,
+        get_room_creation,
+        get_room,
+        delete_room,
+        create_invite,
+        follow_waiting_list,
+        rematch_room,
+        pass_turn,
+        create_match,
+        enqueue_party,
+        get_queue_ticket,
+        leave_queue,
+        create_party,
+        get_party,
+        invite_to_party,
+        join_party,
+        leave_party,
+        queue_party,
+        seat_party,
+        create_tournament,
+        get_tournament,
+        get_tournament_events,
+        seat_tournament_match,
+        report_tournament_match,
+        delete_tournament,
+        record_game_result,
+        get_leaderboard,
+        get_player_presence,
+        invite_player,
+        get_player_invitations,
+        answer_invitation,
+        get_player_stats,
+        get_bans,
+        create_ban,
+        get_ban_audit_log,
+        delete_ban,
+        delete_player_sessions,
+        get_maintenance,
+        put_maintenance,
+        get_load,
+        get_capacity,
+        drain,
+        force_close_room,
+        watch_room,
+        get_room_bans,
+        delete_room_ban,
+        create_announcement,
+        find_players,
+        get_player,
+        disconnect_player,
+        ban_player,
+        wipe_display_name,
+        create_report,
+        get_reports,
+        get_report,
+        act_on_report,
+        resolve_report,
+    ),
+    components(schemas(Problem)),
+    modifiers(&ApiTokenSecurity),
+    security(("api_token" = [])),
+    tags(
+        (name = "rooms", description = "Creating and joining rooms"),
+        (name = "matchmaking", description = "Matching queued players and placing them in rooms"),
+        (name = "parties", description = "Grouping players who play together"),
+        (name = "tournaments", description = "Running single-elimination tournaments"),
+        (name = "players", description = "Finding and inviting players wherever they're connected, rating their skill and reporting them"),
+        (name = "admin", description = "Operating the server"),
+    )
+)]
+pub struct ApiDoc;
+
+async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// The messages exchanged over room WebSockets, which OpenAPI has no way to describe
+#[derive(OpenApi)]
+#[openapi(components(schemas(ClientEnvelope, ServerMessage)))]
+struct ProtocolDoc;
+
+/// Points references to other schemas at `$defs`, where they live in the JSON Schema document
+fn rewrite_refs(schema: &mut serde_json::Value) {
+    match schema {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                match value {
+                    serde_json::Value::String(reference) if key == "$ref" => {
+                        if let Some(name) = reference.strip_prefix("#/components/schemas/") {
+                            *reference = format!("#/$defs/{name}");
+                        }
+                    }
+                    value => rewrite_refs(value),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+/// A JSON Schema document defining every type used by the REST API and the WebSocket
+/// protocol under `$defs`, for clients in other languages to generate models from
+fn json_schemas() -> serde_json::Value {
+    let mut doc = ApiDoc::openapi();
+    doc.merge(ProtocolDoc::openapi());
+    let schemas = doc
+        .components
+        .map(|components| components.schemas)
+        .unwrap_or_default();
+    let mut defs = serde_json::to_value(schemas).expect("Schemas always serialize");
+    rewrite_refs(&mut defs);
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$defs": defs,
+    })
+}
+
+async fn get_schemas() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/schema+json")
+        .body(json_schemas().to_string())
+}
+
+/// Serves the [OpenAPI document][ApiDoc] at `/api/v1/openapi.json`, JSON Schemas for every
+/// API and protocol type at `/api/v1/schemas`, and Swagger UI at `/api/v1/docs/` when built
+/// with the `swagger-ui` feature
+///
+/// These are registered outside the `api/v1` scope so they don't require a token,
+/// since Swagger UI can't send one when loading the document.
+pub fn configure_docs(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/api/v1/openapi.json").route(web::get().to(get_openapi)))
+        .service(web::resource("/api/v1/schemas").route(web::get().to(get_schemas)));
+    #[cfg(feature = "swagger-ui")]
+    cfg.service(
+        utoipa_swagger_ui::SwaggerUi::new("/api/v1/docs/{_:.*}")
+            .config(utoipa_swagger_ui::Config::from("/api/v1/openapi.json")),
+    );
+}
+
+/// Serves the routes players call with their identity token at `/api/v1`
+///
+/// These are registered outside the `api/v1` scope, like `/ws`, since a player's bearer token
+/// is their identity token rather than an API token.
+pub fn configure_player_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/api/v1/rooms/{room_id}/waiting-list")
+            .route(web::get().to(follow_waiting_list)),
+    );
+}
+
+pub async fn get_metrics(state: web::Data<SharedAppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(&state))
+}
+
+pub fn configure_api_scope(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/rooms/")
+            .route(web::post().to(create_room))
+            .route(web::get().to(get_rooms)),
+    )
+    .service(web::resource("/rooms/events").route(web::get().to(get_room_events)))
+    .service(web::resource("/rooms/scheduled").route(web::post().to(schedule_room)))
+    .service(web::resource("/rooms/turn-based").route(web::post().to(create_turn_based_room)))
+    .service(web::resource("/rooms/creations/{ticket}").route(web::get().to(get_room_creation)))
+    .service(
+        web::resource("/rooms/{room_id}")
+            .route(web::get().to(get_room))
+            .route(web::delete().to(delete_room)),
+    )
+    .service(web::resource("/rooms/{room_id}/invites").route(web::post().to(create_invite)))
+    .service(web::resource("/rooms/{room_id}/rematch").route(web::post().to(rematch_room)))
+    .service(web::resource("/rooms/{room_id}/turn").route(web::post().to(pass_turn)))
+    .service(web::resource("/matches").route(web::post().to(create_match)))
+    .service(web::resource("/matchmaking/queue").route(web::post().to(enqueue_party)))
+    .service(
+        web::resource("/matchmaking/queue/{ticket}")
+            .route(web::get().to(get_queue_ticket))
+            .route(web::delete().to(leave_queue)),
+    )
+    .service(web::resource("/parties/").route(web::post().to(create_party)))
+    .service(web::resource("/parties/{party_id}").route(web::get().to(get_party)))
+    .service(web::resource("/parties/{party_id}/invites").route(web::post().to(invite_to_party)))
+    .service(web::resource("/parties/{party_id}/members").route(web::post().to(join_party)))
+    .service(
+        web::resource("/parties/{party_id}/members/{player_id}")
+            .route(web::delete().to(leave_party)),
+    )
+    .service(web::resource("/parties/{party_id}/queue").route(web::post().to(queue_party)))
+    .service(web::resource("/parties/{party_id}/seats").route(web::post().to(seat_party)))
+    .service(web::resource("/tournaments/").route(web::post().to(create_tournament)))
+    .service(
+        web::resource("/tournaments/{tournament_id}")
+            .route(web::get().to(get_tournament))
+            .route(web::delete().to(delete_tournament)),
+    )
+    .service(
+        web::resource("/tournaments/{tournament_id}/events")
+            .route(web::get().to(get_tournament_events)),
+    )
+    .service(
+        web::resource("/tournaments/{tournament_id}/matches/{round}/{index}/seats")
+            .route(web::post().to(seat_tournament_match)),
+    )
+    .service(
+        web::resource("/tournaments/{tournament_id}/matches/{round}/{index}/result")
+            .route(web::post().to(report_tournament_match)),
+    )
+    .service(web::resource("/results").route(web::post().to(record_game_result)))
+    .service(web::resource("/reports").route(web::post().to(create_report)))
+    .service(web::resource("/leaderboards/{game_type}").route(web::get().to(get_leaderboard)))
+    .service(web::resource("/players/{player_id}/stats").route(web::get().to(get_player_stats)))
+    .service(
+        web::resource("/players/{player_id}/presence").route(web::get().to(get_player_presence)),
+    )
+    .service(web::resource("/players/{player_id}/invite").route(web::post().to(invite_player)))
+    .service(
+        web::resource("/players/{player_id}/invitations")
+            .route(web::get().to(get_player_invitations)),
+    )
+    .service(
+        web::resource("/players/{player_id}/invitations/{invitation_id}")
+            .route(web::post().to(answer_invitation)),
+    );
+}
+
+/// Endpoints for operating the server, served under `api/v1` either alongside the rest of
+/// the API or on the admin listener
+pub fn configure_admin_scope(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/bans/")
+            .route(web::post().to(create_ban))
+            .route(web::get().to(get_bans)),
+    )
+    .service(web::resource("/bans/audit").route(web::get().to(get_ban_audit_log)))
+    .service(web::resource("/bans/{ban_id}").route(web::delete().to(delete_ban)))
+    .service(
+        web::resource("/players/{player_id}/sessions")
+            .route(web::delete().to(delete_player_sessions)),
+    )
+    .service(
+        web::resource("/maintenance")
+            .route(web::get().to(get_maintenance))
+            .route(web::put().to(put_maintenance)),
+    )
+    .service(web::resource("/load").route(web::get().to(get_load)))
+    .service(web::resource("/drain").route(web::post().to(drain)))
+    .service(web::resource("/admin/capacity").route(web::get().to(get_capacity)))
+    .service(web::resource("/admin/announcements").route(web::post().to(create_announcement)))
+    .service(
+        web::resource("/admin/rooms/{room_id}/force-close").route(web::post().to(force_close_room)),
+    )
+    .service(web::resource("/admin/rooms/{room_id}/watch").route(web::get().to(watch_room)))
+    .service(web::resource("/admin/rooms/{room_id}/bans").route(web::get().to(get_room_bans)))
+    .service(
+        web::resource("/admin/rooms/{room_id}/bans/{player_id}")
+            .route(web::delete().to(delete_room_ban)),
+    )
+    .service(web::resource("/admin/reports").route(web::get().to(get_reports)))
+    .service(web::resource("/admin/reports/{report_id}").route(web::get().to(get_report)))
+    .service(
+        web::resource("/admin/reports/{report_id}/actions").route(web::post().to(act_on_report)),
+    )
+    .service(
+        web::resource("/admin/reports/{report_id}/resolution")
+            .route(web::post().to(resolve_report)),
+    )
+    .service(web::resource("/admin/players").route(web::get().to(find_players)))
+    .service(web::resource("/admin/players/{player_id}").route(web::get().to(get_player)))
+    .service(
+        web::resource("/admin/players/{player_id}/disconnect")
+            .route(web::post().to(disconnect_player)),
+    )
+    .service(web::resource("/admin/players/{player_id}/ban").route(web::post().to(ban_player)))
+    .service(
+        web::resource("/admin/players/{player_id}/display-name")
+            .route(web::delete().to(wipe_display_name)),
+    );
+}
+
+#[cfg(test)]
+mod response_format {
+    use super::*;
+    use actix_web::http::header::ACCEPT;
+    use actix_web::test::TestRequest;
+
+    fn negotiate(accept: &str) -> ResponseFormat {
+        ResponseFormat::negotiate(
+            &TestRequest::default()
+                .insert_header((ACCEPT, accept))
+                .to_http_request(),
+        )
+    }
+
+    #[test]
+    fn defaults_to_json() {
+        assert_eq!(
+            ResponseFormat::negotiate(&TestRequest::default().to_http_request()),
+            ResponseFormat::Json
+        );
+        assert_eq!(negotiate("*/*"), ResponseFormat::Json);
+        assert_eq!(negotiate("text/html"), ResponseFormat::Json);
+    }
+
+    #[test]
+    fn honours_the_clients_preference() {
+        assert_eq!(
+            negotiate("application/msgpack"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate("application/json;q=0.5, application/msgpack"),
+            ResponseFormat::MessagePack
+        );
+        assert_eq!(
+            negotiate("application/msgpack;q=0.5, application/json"),
+            ResponseFormat::Json
+        );
+    }
+
+    #[actix_web::test]
+    async fn serializes_messagepack_with_field_names() {
+        let response = ResponseFormat::MessagePack.respond(
+            HttpResponse::Ok(),
+            &CreationStatusBody::Created {
+                room_id: RoomId::from(1),
+            },
+        );
+
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            MSGPACK_CONTENT_TYPE
+        );
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::json!({
+                "status": "created",
+                "room_id": "00000000-0000-0000-0000-000000000001",
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod check_match_players {
+    use super::*;
+
+    #[test]
+    fn accepts_distinct_players() {
+        assert_eq!(
+            check_match_players(&[PlayerId::from(1), PlayerId::from(2)], &HashMap::new()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_empty_matches() {
+        assert!(check_match_players(&[], &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_players_given_twice() {
+        assert!(
+            check_match_players(&[PlayerId::from(1), PlayerId::from(1)], &HashMap::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn accepts_teams_of_players_in_the_match() {
+        let teams = HashMap::from([(PlayerId::from(1), "red".to_owned())]);
+
+        assert_eq!(
+            check_match_players(&[PlayerId::from(1), PlayerId::from(2)], &teams),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_teams_for_players_outside_the_match() {
+        let teams = HashMap::from([(PlayerId::from(3), "red".to_owned())]);
+
+        assert!(check_match_players(&[PlayerId::from(1)], &teams).is_err());
+    }
+
+    #[test]
+    fn rejects_blank_team_names() {
+        let teams = HashMap::from([(PlayerId::from(1), String::new())]);
+
+        assert!(check_match_players(&[PlayerId::from(1)], &teams).is_err());
+    }
+}
+
+#[cfg(test)]
+mod region_query {
+    use super::*;
+
+    fn room(region: Option<&str>) -> RoomSummary {
+        RoomSummary {
+            id: 1_u128.into(),
+            name: None,
+            game_type: None,
+            player_count: 0,
+            spectator_count: 0,
+            capacity: None,
+            state: crate::game::RoomStatus::Open,
+            visibility: crate::game::Visibility::Public,
+            region: region.map(str::to_owned),
+            starts_at: None,
+            deletes_at: None,
+            last_active_at: None,
+        }
+    }
+
+    #[test]
+    fn matches_every_room_without_a_region() {
+        let query = RegionQuery::default();
+
+        assert!(query.matches(&room(None)));
+        assert!(query.matches(&room(Some("eu-west"))));
+    }
+
+    #[test]
+    fn matches_only_rooms_in_the_region() {
+        let query = RegionQuery {
+            region: Some("eu-west".to_owned()),
+        };
+
+        assert!(query.matches(&room(Some("eu-west"))));
+        assert!(!query.matches(&room(Some("us-east"))));
+        assert!(!query.matches(&room(None)));
+    }
+}
+
+#[cfg(test)]
+mod room_sort {
+    use super::*;
+
+    fn room(id: u128, spectator_count: usize, player_count: usize) -> RoomSummary {
+        RoomSummary {
+            id: id.into(),
+            name: None,
+            game_type: None,
+            player_count,
+            spectator_count,
+            capacity: None,
+            state: crate::game::RoomStatus::Open,
+            visibility: crate::game::Visibility::Public,
+            region: None,
+            starts_at: None,
+            deletes_at: None,
+            last_active_at: None,
+        }
+    }
+
+    #[test]
+    fn puts_spectators_before_players() {
+        let mut rooms = [room(1, 0, 4), room(2, 3, 2), room(3, 3, 0)];
+
+        RoomSort::MostWatched.sort(&mut rooms);
+
+        let ids: Vec<_> = rooms.iter().map(|room| room.id).collect();
+        assert_eq!(ids, [2_u128.into(), 3_u128.into(), 1_u128.into()]);
+    }
+}
+
+#[cfg(test)]
+mod player_room_body {
+    use super::*;
+
+    #[test]
+    fn points_at_the_hosting_instance() {
+        let room_id = RoomId::from(1_u128);
+
+        assert_eq!(
+            PlayerRoomBody::new(room_id, None, Some("https://eu-1.example.com")).url,
+            "https://eu-1.example.com/ws/00000000-0000-0000-0000-000000000001"
+        );
+        assert_eq!(
+            PlayerRoomBody::new(room_id, None, None).url,
+            "/ws/00000000-0000-0000-0000-000000000001"
+        );
+    }
+}
+
+#[cfg(test)]
+mod api_doc {
+    use super::*;
+
+    #[test]
+    fn documents_every_route() {
+        let doc = ApiDoc::openapi();
+        let mut paths: Vec<_> = doc.paths.paths.keys().map(String::as_str).collect();
+        paths.sort_unstable();
+
+        assert_eq!(
+            paths,
+            [
+                "/admin/announcements",
+                "/admin/capacity",
+                "/admin/players",
+                "/admin/players/{player_id}",
+                "/admin/players/{player_id}/ban",
+                "/admin/players/{player_id}/disconnect",
+                "/admin/players/{player_id}/display-name",
+                "/admin/reports",
+                "/admin/reports/{report_id}",
+                "/admin/reports/{report_id}/actions",
+                "/admin/reports/{report_id}/resolution",
+                "/admin/rooms/{room_id}/bans",
+                "/admin/rooms/{room_id}/bans/{player_id}",
+                "/admin/rooms/{room_id}/force-close",
+                "/admin/rooms/{room_id}/watch",
+                "/bans/",
+                "/bans/audit",
+                "/bans/{ban_id}",
+                "/drain",
+                "/leaderboards/{game_type}",
+                "/load",
+                "/maintenance",
+                "/matches",
+                "/matchmaking/queue",
+                "/matchmaking/queue/{ticket}",
+                "/parties/",
+                "/parties/{party_id}",
+                "/parties/{party_id}/invites",
+                "/parties/{party_id}/members",
+                "/parties/{party_id}/members/{player_id}",
+                "/parties/{party_id}/queue",
+                "/parties/{party_id}/seats",
+                "/players/{player_id}/invitations",
+                "/players/{player_id}/invitations/{invitation_id}",
+                "/players/{player_id}/invite",
+                "/players/{player_id}/presence",
+                "/players/{player_id}/sessions",
+                "/players/{player_id}/stats",
+                "/reports",
+                "/results",
+                "/rooms/",
+                "/rooms/creations/{ticket}",
+                "/rooms/events",
+                "/rooms/scheduled",
+                "/rooms/turn-based",
+                "/rooms/{room_id}",
+                "/rooms/{room_id}/invites",
+                "/rooms/{room_id}/rematch",
+                "/rooms/{room_id}/turn",
+                "/rooms/{room_id}/waiting-list",
+                "/tournaments/",
+                "/tournaments/{tournament_id}",
+                "/tournaments/{tournament_id}/events",
+                "/tournaments/{tournament_id}/matches/{round}/{index}/result",
+                "/tournaments/{tournament_id}/matches/{round}/{index}/seats",
+            ]
+        );
+        let schemas = doc.components.unwrap().schemas;
+        for schema in [
+            "Ban",
+            "BanTarget",
+            "CreationStatusBody",
+            "Invite",
+            "LeaderboardBody",
+            "MatchBody",
+            "Party",
+            "Problem",
+            "QueueStatusBody",
+            "RoomInvitation",
+            "RoomSummary",
+            "Tournament",
+        ] {
+            assert!(schemas.contains_key(schema), "{schema} is missing");
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_schemas {
+    use super::*;
+
+    #[test]
+    fn defines_api_and_protocol_types() {
+        let schemas = json_schemas();
+        let defs = schemas["$defs"].as_object().unwrap();
+
+        for schema in [
+            "Ban",
+            "ClientEnvelope",
+            "ClientMessage",
+            "Invite",
+            "RoomSummary",
+            "ServerMessage",
+        ] {
+            assert!(defs.contains_key(schema), "{schema} is missing");
+        }
+    }
+
+    #[test]
+    fn only_refers_to_defined_schemas() {
+        let schemas = json_schemas();
+        let text = schemas.to_string();
+
+        assert!(!text.contains("#/components/schemas/"));
+        for (start, _) in text.match_indices("\"#/$defs/") {
+            let name = text[start + 9..].split('"').next().unwrap();
+            assert!(
+                schemas["$defs"].get(name).is_some(),
+                "{name} is not defined"
+            );
+        }
+    }
+
+    /// Drops the descriptions taken from doc comments, which can change without changing what
+    /// goes over the wire
+    fn without_descriptions(schema: &mut serde_json::Value) {
+        match schema {
+            serde_json::Value::Object(fields) => {
+                fields.retain(|key, value| key != "description" || !value.is_string());
+                fields.values_mut().for_each(without_descriptions);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(without_descriptions),
+            _ => {}
+        }
+    }
+
+    /// Rerun with `WORMHOLE_UPDATE_GOLDEN=1` to accept an intended change, rewriting the file
+    #[test]
+    fn match_the_golden_file() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/schemas.json");
+        let mut schemas = json_schemas();
+        without_descriptions(&mut schemas);
+        if std::env::var_os("WORMHOLE_UPDATE_GOLDEN").is_some() {
+            let text = serde_json::to_string_pretty(&schemas).unwrap();
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, text + "\n").unwrap();
+            return;
+        }
+        let golden: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert!(
+            schemas == golden,
+            "The API's schemas changed, rerun with WORMHOLE_UPDATE_GOLDEN=1 if that's intended"
+        );
+    }
+}
+
+#[cfg(test)]
+mod sse_event {
+    use super::*;
+    use crate::game::LobbyEvent;
+
+    #[test]
+    fn names_the_event_and_ends_with_a_blank_line() {
+        let event = LobbyEvent::RoomDeleted { id: 1_u128.into() };
+
+        assert_eq!(
+            sse_event(event.name(), &event),
+            "event: room_deleted\ndata: {\"id\":\"00000000-0000-0000-0000-000000000001\"}\n\n"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod force_close_room {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn disconnects_everyone_and_removes_the_room_at_once() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+        let url = server.url(&format!("/api/v1/admin/rooms/{room_id}/force-close"));
+
+        let response = server.http().post(&url).send().await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["closed_connections"], 1);
+        assert!(server
+            .state()
+            .room_registry
+            .registry()
+            .get_room_for_id(room_id)
+            .is_none());
+        assert_eq!(
+            client.close_reason().await.as_deref(),
+            Some(ADMIN_CLOSED_REASON)
+        );
+        let response = server.http().post(&url).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod rematch_room {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn keeps_players_banned_from_the_original_out() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        server
+            .state()
+            .room_bans
+            .ban(room_id, 7.into(), "player:1".to_owned(), None);
+        let rematch = |room_id: RoomId| {
+            server
+                .http()
+                .post(server.url(&format!("/api/v1/rooms/{room_id}/rematch")))
+                .send()
+        };
+
+        let response = rematch(room_id).await.unwrap();
+        assert_eq!(response.status(), 201);
+        let location = response.headers()[reqwest::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let rematch_id: RoomId = location.rsplit('/').next().unwrap().parse().unwrap();
+        // The original may be gone by the time its rematch is rematched
+        server
+            .state()
+            .room_registry
+            .delete_room(room_id)
+            .await
+            .unwrap();
+        let response = rematch(rematch_id).await.unwrap();
+        let location = response.headers()[reqwest::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let second_id: RoomId = location.rsplit('/').next().unwrap().parse().unwrap();
+
+        let second = server
+            .state()
+            .room_registry
+            .registry()
+            .get_room_for_id(second_id)
+            .unwrap();
+        assert_eq!(second.lineage(second_id), room_id);
+        let bans: Vec<serde_json::Value> = server
+            .http()
+            .get(server.url(&format!("/api/v1/admin/rooms/{second_id}/bans")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0]["player_id"], serde_json::json!(PlayerId::from(7)));
+        let response = rematch(RoomId::from(u128::MAX)).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod create_announcement {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn reaches_clients_that_understand_announcements() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut current = server.connect(room_id).await;
+        let mut outdated = server
+            .connect_with_query(room_id, "protocol_version=5")
+            .await;
+
+        let response = server
+            .http()
+            .post(server.url("/api/v1/admin/announcements"))
+            .json(&json!({ "text": "Restarting soon" }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            body,
+            json!({ "rooms": 1, "connections": 1, "relayed": false })
+        );
+        assert_eq!(
+            current.recv().await,
+            ServerMessage::Announcement {
+                text: "Restarting soon".to_owned()
+            }
+        );
+        outdated
+            .expect_silence(std::time::Duration::from_millis(50))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn turns_empty_announcements_away() {
+        let server = TestServer::start().await;
+
+        let response = server
+            .http()
+            .post(server.url("/api/v1/admin/announcements"))
+            .json(&json!({ "text": " " }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 400);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod get_player {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn shows_the_actions_taken_against_the_player() {
+        let server = TestServer::start().await;
+        let player_id = PlayerId::from(7);
+        let url = |path: &str| server.url(&format!("/api/v1/admin/players/{player_id}{path}"));
+
+        let response = server
+            .http()
+            .post(url("/ban"))
+            .json(&json!({ "reason": "cheating" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let banned: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(banned["closed_connections"], 0);
+        let response = server
+            .http()
+            .delete(url("/display-name"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+
+        let player: serde_json::Value = server
+            .http()
+            .get(url(""))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        assert_eq!(player["online"], false);
+        assert_eq!(player["display_name_wiped"], true);
+        assert_eq!(player["connections"], json!([]));
+        assert_eq!(player["ban"]["id"], banned["ban"]["id"]);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod watch_room {
+    use super::*;
+    use crate::protocol::{ChatChannel, ClientMessage};
+    use crate::test_support::TestServer;
+
+    /// Reads the stream until an event with the name has arrived, returning what was read
+    async fn read_until(response: &mut reqwest::Response, event: &str) -> String {
+        let mut read = String::new();
+        while !read.contains(&format!("event: {event}\n")) {
+            let chunk = response.chunk().await.unwrap().expect("The stream ended");
+            read.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn streams_the_rooms_events_until_it_is_deleted() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut watch = server
+            .http()
+            .get(server.url(&format!("/api/v1/admin/rooms/{room_id}/watch")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(watch.status(), 200);
+
+        let mut client = server.connect(room_id).await;
+        client
+            .send(ClientMessage::Chat {
+                text: "hello".to_owned(),
+                channel: ChatChannel::Room,
+            })
+            .await;
+
+        let read = read_until(&mut watch, "message").await;
+        assert!(read.contains("event: joined\n"));
+        assert!(read.contains(r#""text":"hello""#));
+
+        server
+            .http()
+            .post(server.url(&format!("/api/v1/admin/rooms/{room_id}/force-close")))
+            .send()
+            .await
+            .unwrap();
+        read_until(&mut watch, "room_deleted").await;
+        assert_eq!(watch.chunk().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_who_watched_the_room_in_the_audit_trail() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let watch = server
+            .http()
+            .get(server.url(&format!("/api/v1/admin/rooms/{room_id}/watch")))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(watch.status(), 200);
+        let actions = || {
+            server
+                .state()
+                .ban_list
+                .audit_log()
+                .into_iter()
+                .map(|entry| entry.action)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(actions(), vec![AuditAction::RoomWatchStarted { room_id }]);
+
+        server
+            .http()
+            .post(server.url(&format!("/api/v1/admin/rooms/{room_id}/force-close")))
+            .send()
+            .await
+            .unwrap();
+        assert!(watch
+            .text()
+            .await
+            .unwrap()
+            .contains("event: room_deleted\n"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while actions().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The watch wasn't recorded as ended");
+        assert_eq!(
+            actions(),
+            vec![
+                AuditAction::RoomWatchStarted { room_id },
+                AuditAction::RoomWatchEnded { room_id },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn turns_unknown_rooms_away() {
+        let server = TestServer::start().await;
+
+        let response = server
+            .http()
+            .get(server.url(&format!("/api/v1/admin/rooms/{}/watch", RoomId::from(1))))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 404);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod create_report {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn queues_the_report_for_moderators_to_act_on_and_resolve() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let reported = PlayerId::from(2);
+        let report = json!({
+            "reporter": PlayerId::from(1),
+            "reported": reported,
+            "room_id": room_id,
+            "reason": "cheating",
+            "include_chat": true,
+        });
+
+        let response = server
+            .http()
+            .post(server.url("/api/v1/reports"))
+            .json(&report)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let id = response.json::<Value>().await.unwrap()["id"].clone();
+        let id = id.as_str().unwrap();
+        let response = server
+            .http()
+            .post(server.url("/api/v1/reports"))
+            .json(&report)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+
+        let queue: Value = server
+            .http()
+            .get(server.url("/api/v1/admin/reports?status=open"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(queue.as_array().unwrap().len(), 1);
+
+        let actioned: Value = server
+            .http()
+            .post(server.url(&format!("/api/v1/admin/reports/{id}/actions")))
+            .json(&json!({ "type": "ban", "duration_secs": 3600 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(actioned["status"], "actioned");
+        assert_eq!(actioned["actions"][0]["type"], "banned");
+        let ban = server.state().ban_list.find(Some(reported), None).unwrap();
+        assert_eq!(ban.reason, "cheating");
+
+        let resolve = || {
+            server
+                .http()
+                .post(server.url(&format!("/api/v1/admin/reports/{id}/resolution")))
+                .json(&json!({ "note": "Banned for an hour" }))
+                .send()
+        };
+        let resolved: Value = resolve().await.unwrap().json().await.unwrap();
+        assert_eq!(resolved["status"], "resolved");
+        assert_eq!(resolve().await.unwrap().status(), 409);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod pass_turn {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn only_pushes_through_a_configured_gateway() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let pass_turn = |room_id: RoomId| {
+            server
+                .http()
+                .post(server.url(&format!("/api/v1/rooms/{room_id}/turn")))
+                .json(&serde_json::json!({ "player_id": PlayerId::from(1) }))
+                .send()
+        };
+
+        let response = pass_turn(room_id).await.unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["pushed"], false);
+        let response = pass_turn(RoomId::from(u128::MAX)).await.unwrap();
+        assert_eq!(response.status(), 404);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod invite_player {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn holds_a_seat_for_the_invited_player() {
+        let server = TestServer::builder().with_room_capacity(1).start().await;
+        let room_id = server.create_room().await;
+        let invite = |player_id: u128| {
+            server
+                .http()
+                .post(server.url(&format!(
+                    "/api/v1/players/{}/invite",
+                    PlayerId::from(player_id)
+                )))
+                .json(&serde_json::json!({ "from": PlayerId::from(1), "room_id": room_id }))
+                .send()
+        };
+
+        assert_eq!(invite(2).await.unwrap().status(), 201);
+        let room = server
+            .state()
+            .room_registry
+            .get_room(room_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(room.has_seat_for(Some(2.into())));
+        assert!(!room.has_seat_for(Some(3.into())));
+
+        let response = invite(3).await.unwrap();
+        assert_eq!(response.status(), 409);
+        assert!(server.state().invitations.inbox(3.into()).is_empty());
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod create_turn_based_room {
+    use std::borrow::Cow;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::protocol::ClientMessage;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn keeps_the_game_for_players_taking_their_turn_later() {
+        let server = TestServer::start().await;
+        let create = |body: serde_json::Value| {
+            server
+                .http()
+                .post(server.url("/api/v1/rooms/turn-based"))
+                .json(&body)
+                .send()
+        };
+
+        let response = create(json!({ "turn_timeout_secs": 0 })).await.unwrap();
+        assert_eq!(response.status(), 400);
+        let response = create(json!({ "turn_timeout_secs": 7200 })).await.unwrap();
+        assert_eq!(response.status(), 201);
+        let location = response.headers()[reqwest::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let room_id: RoomId = location.rsplit('/').next().unwrap().parse().unwrap();
+
+        let mut alice = server.connect(room_id).await;
+        let from = alice.connection_id();
+        alice
+            .send(ClientMessage::Broadcast {
+                payload: json!({ "board": "e4" }),
+            })
+            .await;
+        alice.recv().await;
+        alice.close().await;
+        let body: serde_json::Value = server
+            .http()
+            .post(server.url(&format!("/api/v1/rooms/{room_id}/turn")))
+            .json(&json!({ "player_id": PlayerId::from(2) }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(body["deadline"].is_u64());
+
+        let mut bob = server.connect(room_id).await;
+        assert_eq!(
+            bob.recv().await,
+            ServerMessage::Broadcast {
+                from,
+                from_player: None,
+                payload: Cow::Owned(json!({ "board": "e4" })),
+                seq: None,
+            }
+        );
+        let room: serde_json::Value = server
+            .http()
+            .get(server.url(&format!("/api/v1/rooms/{room_id}")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(room["turn_timeout_secs"], 7200);
+        assert_eq!(room["turn"]["player_id"], json!(PlayerId::from(2)));
+        assert!(room["deletes_in_secs"].as_u64().unwrap() > 7000);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod get_rooms {
+    use serde_json::json;
+
+    use super::*;
+    use crate::protocol::{ClientMessage, ServerMessage};
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn puts_the_most_watched_rooms_first() {
+        let server = TestServer::start().await;
+        let quiet = server.create_room().await;
+        let watched = server.create_room().await;
+        let _player = server.connect(quiet).await;
+        let mut spectator = server.connect_with_query(watched, "spectate=true").await;
+        spectator
+            .send(ClientMessage::Broadcast {
+                payload: json!({ "move": "e4" }),
+            })
+            .await;
+        assert!(matches!(
+            spectator.recv().await,
+            ServerMessage::Error { .. }
+        ));
+        server.state().room_registry.registry().publish_updates();
+
+        let rooms: Vec<RoomSummary> = server
+            .http()
+            .get(server.url("/api/v1/rooms/?sort=most_watched"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = rooms.iter().map(|room| room.id).collect();
+        assert_eq!(ids, [watched, quiet]);
+        assert_eq!(rooms[0].spectator_count, 1);
+        assert_eq!(rooms[1].spectator_count, 0);
+    }
+
+    #[tokio::test]
+    async fn leaves_unlisted_rooms_out() {
+        let server = TestServer::start().await;
+        let listed = server.create_room().await;
+        let unlisted = server.create_room().await;
+        let registry = server.state().room_registry.registry();
+        registry
+            .get_room_for_id(unlisted)
+            .unwrap()
+            .set_visibility(Visibility::Unlisted);
+        registry.room_updated(unlisted);
+        registry.publish_updates();
+
+        let rooms: Vec<RoomSummary> = server
+            .http()
+            .get(server.url("/api/v1/rooms/"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let room: serde_json::Value = server
+            .http()
+            .get(server.url(&format!("/api/v1/rooms/{unlisted}")))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = rooms.iter().map(|room| room.id).collect();
+        assert_eq!(ids, [listed]);
+        assert_eq!(room["visibility"], "unlisted");
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod get_capacity {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn reports_the_headroom_left() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let _client = server.connect(room_id).await;
+        server.state().maintenance.set_enabled(true, API_ACTOR);
+
+        let capacity: Value = server
+            .http()
+            .get(server.url("/api/v1/admin/capacity"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+
+        let limits = server.state().load_limits;
+        assert_eq!(capacity["rooms"], 1);
+        assert_eq!(capacity["room_headroom"], limits.rooms - 1);
+        assert_eq!(capacity["connections"], 1);
+        assert_eq!(capacity["connection_headroom"], limits.connections - 1);
+        assert_eq!(capacity["saturated"]["rooms"], false);
+        assert_eq!(capacity["accepting_rooms"], false);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod create_room {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_support::TestServer;
+    use crate::webhooks::{RoomWebhooks, WebhookEvent, WebhookEventKind};
+
+    fn webhook(events: &[&str]) -> serde_json::Value {
+        json!({ "webhook": { "url": "https://tournaments.example/rooms", "events": events } })
+    }
+
+    #[tokio::test]
+    async fn registers_the_rooms_own_webhook() {
+        let room_webhooks = Arc::new(RoomWebhooks::default());
+        let server = TestServer::builder()
+            .with_room_webhooks(room_webhooks.clone())
+            .start()
+            .await;
+
+        let response = server
+            .http()
+            .post(server.url("/api/v1/rooms/"))
+            .json(&webhook(&["game_finished"]))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 201);
+        let location = response.headers()[reqwest::header::LOCATION]
+            .to_str()
+            .unwrap();
+        let room_id: RoomId = location.rsplit('/').next().unwrap().parse().unwrap();
+        let room = server
+            .state()
+            .room_registry
+            .get_room(room_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            room.webhook().unwrap().events,
+            [WebhookEventKind::GameFinished]
+        );
+        let finished = WebhookEvent::GameFinished { room_id };
+        assert!(room_webhooks.subscription_for(&finished).is_some());
+    }
+
+    #[tokio::test]
+    async fn refuses_webhooks_the_server_cant_deliver() {
+        let server = TestServer::start().await;
+        let delivering = TestServer::builder()
+            .with_room_webhooks(Default::default())
+            .start()
+            .await;
+
+        let undelivered = server
+            .http()
+            .post(server.url("/api/v1/rooms/"))
+            .json(&webhook(&["room_created"]))
+            .send()
+            .await
+            .unwrap();
+        let invalid = delivering
+            .http()
+            .post(delivering.url("/api/v1/rooms/"))
+            .json(&webhook(&["player_banned"]))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(undelivered.status(), 422);
+        assert_eq!(invalid.status(), 400);
+        assert_eq!(server.state().room_registry.registry().room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_with_fields_it_doesnt_take() {
+        let server = TestServer::start().await;
+
+        let response = server
+            .http()
+            .post(server.url("/api/v1/rooms/"))
+            .json(&json!({ "webhok": { "url": "https://tournaments.example/rooms" } }))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 400);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["type"], "/problems/unknown-field");
+        assert_eq!(server.state().room_registry.registry().room_count(), 0);
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod follow_waiting_list {
+    use super::*;
+    use crate::test_support::TestServer;
+
+    /// Reads the stream until an event with the name has arrived, returning what was read
+    async fn read_until(response: &mut reqwest::Response, event: &str) -> String {
+        let mut read = String::new();
+        while !read.contains(&format!("event: {event}\n")) {
+            let chunk = response.chunk().await.unwrap().expect("The stream ended");
+            read.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        read
+    }
+
+    #[tokio::test]
+    async fn holds_the_next_free_seat_for_the_player() {
+        let server = TestServer::builder()
+            .with_room_capacity(1)
+            .with_signed_in_players()
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let room = server
+            .state()
+            .room_registry
+            .get_room(room_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let now = Instant::now();
+        room.hold_seats(&[1.into()], now + Duration::from_millis(100));
+        let follow = |subject: &str| {
+            server
+                .http()
+                .get(server.url(&format!("/api/v1/rooms/{room_id}/waiting-list")))
+                .bearer_auth(TestServer::player_token(subject))
+                .send()
+        };
+
+        let mut first = follow("bob").await.unwrap();
+        assert!(read_until(&mut first, "position")
+            .await
+            .contains(r#""position":1"#));
+        let mut second = follow("carol").await.unwrap();
+        assert!(read_until(&mut second, "position")
+            .await
+            .contains(r#""position":2"#));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server
+            .state()
+            .waiting_lists
+            .seat_freed(room_id, &room, Instant::now());
+
+        read_until(&mut first, "seat").await;
+        assert_eq!(first.chunk().await.unwrap(), None);
+        assert!(room.has_seat_for(Some(TestServer::player_id("bob"))));
+        assert!(read_until(&mut second, "position")
+            .await
+            .contains(r#""position":1"#));
+    }
+
+    #[tokio::test]
+    async fn takes_identity_tokens_when_api_tokens_are_configured() {
+        use sha2::{Digest, Sha256};
+
+        let api_tokens = hex::encode(Sha256::digest(b"api-token")).parse().unwrap();
+        let server = TestServer::builder()
+            .with_room_capacity(1)
+            .with_signed_in_players()
+            .with_api_tokens(api_tokens)
+            .start()
+            .await;
+        let registry = server.state().room_registry.registry();
+        let room_id = registry.create_room().unwrap();
+        registry
+            .get_room_for_id(room_id)
+            .unwrap()
+            .hold_seats(&[1.into()], Instant::now() + Duration::from_secs(60));
+
+        let mut response = server
+            .http()
+            .get(server.url(&format!("/api/v1/rooms/{room_id}/waiting-list")))
+            .bearer_auth(TestServer::player_token("bob"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert!(read_until(&mut response, "position")
+            .await
+            .contains(r#""position":1"#));
+    }
+
+    #[tokio::test]
+    async fn turns_away_players_the_room_has_a_seat_for() {
+        let server = TestServer::builder()
+            .with_room_capacity(1)
+            .with_signed_in_players()
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+
+        let response = server
+            .http()
+            .get(server.url(&format!("/api/v1/rooms/{room_id}/waiting-list")))
+            .bearer_auth(TestServer::player_token("bob"))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 409);
+    }
+
+    #[tokio::test]
+    async fn turns_away_players_without_an_identity_token() {
+        let server = TestServer::builder()
+            .with_room_capacity(1)
+            .with_signed_in_players()
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+
+        let response = server
+            .http()
+            .get(server.url(&format!("/api/v1/rooms/{room_id}/waiting-list")))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,247 @@
+//! Bearer token authentication for the REST API
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::config::secrets::Reloadable;
+use crate::problem::Problem;
+
+type TokenHash = [u8; 32];
+
+/// What a holder of an API token is allowed to do
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TokenScope {
+    /// Only safe methods such as `GET` may be used
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ApiTokenParseError {
+    #[error("{0:?} is not a hex encoded SHA-256 hash")]
+    InvalidHash(String),
+    #[error("{0:?} is not a known token scope, expected \"read\" or \"write\"")]
+    InvalidScope(String),
+}
+
+/// The set of tokens accepted by the REST API, held as SHA-256 hashes so the
+/// tokens themselves never have to be stored in configuration
+#[derive(Debug, Default, Clone)]
+pub struct ApiTokens {
+    tokens: HashMap<TokenHash, TokenScope>,
+}
+
+/// Parses a comma separated list of hex encoded SHA-256 token hashes,
+/// each optionally followed by `:read` or `:write` (the default)
+impl FromStr for ApiTokens {
+    type Err = ApiTokenParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = HashMap::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (hash, scope) = entry.split_once(':').unwrap_or((entry, "write"));
+            let scope = match scope {
+                "read" => TokenScope::ReadOnly,
+                "write" => TokenScope::ReadWrite,
+                _ => return Err(ApiTokenParseError::InvalidScope(scope.to_owned())),
+            };
+            let mut digest = TokenHash::default();
+            hex::decode_to_slice(hash, &mut digest)
+                .map_err(|_| ApiTokenParseError::InvalidHash(hash.to_owned()))?;
+            tokens.insert(digest, scope);
+        }
+        Ok(Self { tokens })
+    }
+}
+
+impl ApiTokens {
+    /// Whether no tokens are configured, in which case the API is left open
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// The scope granted to `token`, or `None` if it isn't accepted
+    pub fn scope_of(&self, token: &str) -> Option<TokenScope> {
+        let digest: TokenHash = Sha256::digest(token.as_bytes()).into();
+        self.tokens.get(&digest).copied()
+    }
+}
+
+pub(crate) fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn is_read_only(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Rejects requests that don't carry a bearer token from the [configured tokens][ApiTokens]
+/// with a `401`, and requests that need more than the token's scope allows with a `403`
+pub async fn require_api_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(tokens) = req
+        .app_data::<web::Data<Reloadable<ApiTokens>>>()
+        .map(|tokens| tokens.get())
+    else {
+        warn!(event = "api_tokens_not_configured");
+        return Err(Problem::unauthorized("The server has no API tokens configured").into());
+    };
+
+    if !tokens.is_empty() {
+        let Some(token) = bearer_token(&req) else {
+            return Err(Problem::unauthorized("Expected a bearer token").into());
+        };
+        match tokens.scope_of(token) {
+            None => {
+                debug!(event = "api_token_rejected", path = req.path());
+                return Err(Problem::unauthorized("The bearer token is not valid").into());
+            }
+            Some(TokenScope::ReadOnly) if !is_read_only(req.method()) => {
+                return Err(Problem::forbidden("The bearer token is read-only").into());
+            }
+            Some(_) => {}
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod from_str {
+    use super::*;
+
+    #[test]
+    fn parses_hashes_with_and_without_scopes() {
+        let tokens: ApiTokens = format!("{}, {}:read", hash("writer"), hash("reader"))
+            .parse()
+            .unwrap();
+
+        assert_eq!(tokens.scope_of("writer"), Some(TokenScope::ReadWrite));
+        assert_eq!(tokens.scope_of("reader"), Some(TokenScope::ReadOnly));
+        assert_eq!(tokens.scope_of("stranger"), None);
+    }
+
+    #[test]
+    fn parses_empty_string_as_no_tokens() {
+        assert!("".parse::<ApiTokens>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert_eq!(
+            "abc".parse::<ApiTokens>().unwrap_err(),
+            ApiTokenParseError::InvalidHash("abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_scopes() {
+        assert_eq!(
+            format!("{}:admin", hash("token"))
+                .parse::<ApiTokens>()
+                .unwrap_err(),
+            ApiTokenParseError::InvalidScope("admin".to_owned())
+        );
+    }
+}
+
+#[cfg(test)]
+mod require_api_token {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App, HttpResponse};
+
+    async fn call(tokens: &str, method: Method, token: Option<&str>) -> StatusCode {
+        let tokens: ApiTokens = tokens.parse().unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(Reloadable::fixed(tokens)))
+                .service(
+                    web::scope("")
+                        .wrap(from_fn(require_api_token))
+                        .default_service(web::to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let mut req = test::TestRequest::default().method(method);
+        if let Some(token) = token {
+            req = req.insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+        }
+        match test::try_call_service(&app, req.to_request()).await {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn allows_everything_when_no_tokens_are_configured() {
+        assert_eq!(call("", Method::POST, None).await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rejects_missing_token() {
+        let tokens = hash("secret");
+        assert_eq!(
+            call(&tokens, Method::GET, None).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_unknown_token() {
+        let tokens = hash("secret");
+        assert_eq!(
+            call(&tokens, Method::GET, Some("guess")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_web::test]
+    async fn accepts_known_token() {
+        let tokens = hash("secret");
+        assert_eq!(
+            call(&tokens, Method::POST, Some("secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[actix_web::test]
+    async fn forbids_writes_with_read_only_token() {
+        let tokens = format!("{}:read", hash("secret"));
+        assert_eq!(
+            call(&tokens, Method::GET, Some("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(&tokens, Method::POST, Some("secret")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
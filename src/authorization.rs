@@ -0,0 +1,193 @@
+//! Roles held by players and the permissions they grant
+
+use crate::protocol::ClientMessage;
+
+/// A role held by a player, either server-wide through their token or within a single room
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Role {
+    Player,
+    /// Held by the first authenticated player to join a room, only within that room
+    RoomOwner,
+    Moderator,
+    Admin,
+}
+
+/// An action that only some [roles][Role] may take
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Permission {
+    ChangeRoomSettings,
+    /// Mint invites to a room
+    InvitePlayers,
+    /// Disconnect everyone from a room and remove it
+    CloseRoom,
+    /// Ban players from the whole server
+    BanPlayers,
+    /// Stop players from chatting in a room
+    MutePlayers,
+    /// Disconnect players from a room, and keep them out of it and its rematches
+    KickPlayers,
+}
+
+impl Permission {
+    /// The permission needed to send the message, if any
+    pub fn required_to_send(message: &ClientMessage) -> Option<Permission> {
+        match message {
+            ClientMessage::Broadcast { .. }
+            | ClientMessage::Chat { .. }
+            | ClientMessage::React { .. }
+            | ClientMessage::AnswerInvitation { .. }
+            | ClientMessage::VoteKick { .. } => None,
+            ClientMessage::CloseRoom => Some(Permission::CloseRoom),
+            ClientMessage::BanPlayer { .. } => Some(Permission::BanPlayers),
+            ClientMessage::UpdateSettings { .. }
+            | ClientMessage::SetCustomData { .. }
+            | ClientMessage::BalanceTeams { .. } => Some(Permission::ChangeRoomSettings),
+            ClientMessage::CreateInvite { .. } => Some(Permission::InvitePlayers),
+            ClientMessage::MutePlayer { .. } | ClientMessage::UnmutePlayer { .. } => {
+                Some(Permission::MutePlayers)
+            }
+            ClientMessage::KickPlayer { .. } => Some(Permission::KickPlayers),
+        }
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Permission::ChangeRoomSettings => "change room settings",
+            Permission::InvitePlayers => "invite players",
+            Permission::CloseRoom => "close rooms",
+            Permission::BanPlayers => "ban players",
+            Permission::MutePlayers => "mute players",
+            Permission::KickPlayers => "kick players",
+        })
+    }
+}
+
+impl Role {
+    pub fn grants(self, permission: Permission) -> bool {
+        match self {
+            Role::Player => false,
+            Role::RoomOwner => matches!(
+                permission,
+                Permission::ChangeRoomSettings
+                    | Permission::InvitePlayers
+                    | Permission::MutePlayers
+                    | Permission::KickPlayers
+            ),
+            Role::Moderator => matches!(
+                permission,
+                Permission::CloseRoom
+                    | Permission::BanPlayers
+                    | Permission::MutePlayers
+                    | Permission::KickPlayers
+            ),
+            Role::Admin => true,
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// A set of [roles][Role]
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct Roles(u8);
+
+impl Roles {
+    pub fn with(mut self, role: Role) -> Self {
+        self.0 |= role.bit();
+        self
+    }
+
+    pub fn contains(self, role: Role) -> bool {
+        self.0 & role.bit() != 0
+    }
+
+    /// Whether any of the roles grants `permission`
+    pub fn allow(self, permission: Permission) -> bool {
+        [Role::Player, Role::RoomOwner, Role::Moderator, Role::Admin]
+            .into_iter()
+            .any(|role| self.contains(role) && role.grants(permission))
+    }
+}
+
+impl FromIterator<Role> for Roles {
+    fn from_iter<I: IntoIterator<Item = Role>>(roles: I) -> Self {
+        roles.into_iter().fold(Roles::default(), Roles::with)
+    }
+}
+
+#[cfg(test)]
+mod allow {
+    use super::*;
+
+    #[test]
+    fn players_have_no_permissions() {
+        let roles = Roles::default().with(Role::Player);
+        assert!(!roles.allow(Permission::ChangeRoomSettings));
+        assert!(!roles.allow(Permission::InvitePlayers));
+        assert!(!roles.allow(Permission::CloseRoom));
+        assert!(!roles.allow(Permission::BanPlayers));
+        assert!(!roles.allow(Permission::MutePlayers));
+        assert!(!roles.allow(Permission::KickPlayers));
+    }
+
+    #[test]
+    fn owners_moderators_and_admins_mute_players() {
+        for role in [Role::RoomOwner, Role::Moderator, Role::Admin] {
+            assert!(Roles::default().with(role).allow(Permission::MutePlayers));
+        }
+    }
+
+    #[test]
+    fn owners_moderators_and_admins_kick_players() {
+        for role in [Role::RoomOwner, Role::Moderator, Role::Admin] {
+            assert!(Roles::default().with(role).allow(Permission::KickPlayers));
+        }
+    }
+
+    #[test]
+    fn only_owners_and_admins_change_settings() {
+        assert!(Roles::default()
+            .with(Role::RoomOwner)
+            .allow(Permission::ChangeRoomSettings));
+        assert!(!Roles::default()
+            .with(Role::Moderator)
+            .allow(Permission::ChangeRoomSettings));
+        assert!(Roles::default()
+            .with(Role::Admin)
+            .allow(Permission::ChangeRoomSettings));
+    }
+
+    #[test]
+    fn only_owners_and_admins_invite_players() {
+        assert!(Roles::default()
+            .with(Role::RoomOwner)
+            .allow(Permission::InvitePlayers));
+        assert!(!Roles::default()
+            .with(Role::Moderator)
+            .allow(Permission::InvitePlayers));
+    }
+
+    #[test]
+    fn only_moderators_and_admins_close_rooms_and_ban() {
+        let owner = Roles::default().with(Role::RoomOwner);
+        assert!(!owner.allow(Permission::CloseRoom));
+        assert!(!owner.allow(Permission::BanPlayers));
+
+        for role in [Role::Moderator, Role::Admin] {
+            let roles = Roles::default().with(role);
+            assert!(roles.allow(Permission::CloseRoom));
+            assert!(roles.allow(Permission::BanPlayers));
+        }
+    }
+
+    #[test]
+    fn combines_permissions_of_every_role() {
+        let roles: Roles = [Role::RoomOwner, Role::Moderator].into_iter().collect();
+        assert!(roles.allow(Permission::ChangeRoomSettings));
+        assert!(roles.allow(Permission::CloseRoom));
+    }
+}
@@ -0,0 +1,445 @@
+//! Server-wide bans of players and IP networks, along with the admin audit trail of who issued
+//! and lifted them, and who watched which rooms
+//!
+//! Bans and their audit trail are held in memory and, when a bans file is configured, saved
+//! to it by [BanList::run] soon after every change and read back from it on start, so they
+//! survive restarts.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::unix_time;
+use crate::files::write_atomically;
+use crate::game::{PlayerId, RoomId};
+
+/// How many audit entries are kept before the oldest are forgotten
+const AUDIT_LOG_CAPACITY: usize = 1000;
+
+/// How many issued bans a subscriber may fall behind by before it misses some
+const ISSUED_BANS_CAPACITY: usize = 64;
+
+/// An ID that identifies a [ban][Ban] so it can be lifted
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct BanId(Uuid);
+
+impl std::fmt::Display for BanId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Who or what a [ban][Ban] keeps out
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BanTarget {
+    Player {
+        player_id: PlayerId,
+    },
+    /// Every address in the network, given in CIDR notation
+    Network {
+        #[schema(value_type = String, example = "203.0.113.0/24")]
+        network: IpNet,
+    },
+}
+
+impl BanTarget {
+    fn matches(&self, player_id: Option<PlayerId>, ip: Option<IpAddr>) -> bool {
+        match self {
+            BanTarget::Player { player_id: banned } => player_id == Some(*banned),
+            BanTarget::Network { network } => ip.is_some_and(|ip| network.contains(&ip)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Ban {
+    pub id: BanId,
+    pub target: BanTarget,
+    pub reason: String,
+    /// Who issued the ban, such as `api` or the moderator's player id
+    pub issued_by: String,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    /// Seconds since the Unix epoch, `None` for permanent bans
+    pub expires_at: Option<u64>,
+}
+
+impl Ban {
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    Banned {
+        ban_id: BanId,
+        target: BanTarget,
+    },
+    Lifted {
+        ban_id: BanId,
+        target: BanTarget,
+    },
+    /// An operator started following the room's [live feed][crate::room_watch]
+    RoomWatchStarted {
+        room_id: RoomId,
+    },
+    RoomWatchEnded {
+        room_id: RoomId,
+    },
+}
+
+/// A record of a change to the [ban list][BanList], or of something else an operator did
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub at: u64,
+    pub actor: String,
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+/// The bans and audit trail as written to the file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedBans {
+    bans: Vec<Ban>,
+    audit_log: VecDeque<AuditEntry>,
+}
+
+/// The bans in force across the server
+#[derive(Debug)]
+pub struct BanList {
+    bans: RwLock<HashMap<BanId, Ban>>,
+    audit_log: Mutex<VecDeque<AuditEntry>>,
+    issued: Sender<Ban>,
+    /// Where the bans are written to, `None` to keep them in memory only
+    file: Option<PathBuf>,
+    /// Woken when the bans changed and should be saved
+    changed: Notify,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        Self {
+            bans: Default::default(),
+            audit_log: Default::default(),
+            issued: broadcast::channel(ISSUED_BANS_CAPACITY).0,
+            file: None,
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl BanList {
+    /// Creates a list kept in the file, starting with the bans in it that haven't expired and
+    /// its audit trail
+    pub fn with_file(file: PathBuf) -> io::Result<Self> {
+        let saved: SavedBans = match fs::read(&file) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SavedBans::default(),
+            Err(e) => return Err(e),
+        };
+        let now = unix_time();
+        Ok(Self {
+            bans: RwLock::new(
+                saved
+                    .bans
+                    .into_iter()
+                    .filter(|ban| ban.is_active(now))
+                    .map(|ban| (ban.id, ban))
+                    .collect(),
+            ),
+            audit_log: Mutex::new(saved.audit_log),
+            file: Some(file),
+            ..Default::default()
+        })
+    }
+
+    /// Bans the target for `duration`, or permanently if it is `None`
+    pub fn ban(
+        &self,
+        target: BanTarget,
+        reason: String,
+        duration: Option<Duration>,
+        issued_by: String,
+    ) -> Ban {
+        let now = unix_time();
+        let ban = Ban {
+            id: BanId(Uuid::new_v4()),
+            target,
+            reason,
+            issued_by,
+            issued_at: now,
+            expires_at: duration.map(|duration| now.saturating_add(duration.as_secs())),
+        };
+
+        let mut bans = self.bans.write().unwrap();
+        bans.retain(|_, ban| ban.is_active(now));
+        bans.insert(ban.id, ban.clone());
+        drop(bans);
+
+        info!(event = "ban_issued", ban_id = %ban.id, target = ?ban.target, issued_by = ban.issued_by);
+        self.audit(
+            ban.issued_by.clone(),
+            AuditAction::Banned {
+                ban_id: ban.id,
+                target,
+            },
+        );
+        self.changed.notify_one();
+        // Having nobody following the bans is not an error
+        let _ = self.issued.send(ban.clone());
+        ban
+    }
+
+    /// Receives every ban issued from now on
+    pub fn subscribe(&self) -> Receiver<Ban> {
+        self.issued.subscribe()
+    }
+
+    /// Lifts the ban, returning it if it existed
+    pub fn lift(&self, id: BanId, actor: String) -> Option<Ban> {
+        let ban = self.bans.write().unwrap().remove(&id)?;
+        info!(event = "ban_lifted", ban_id = %id, actor);
+        self.audit(
+            actor,
+            AuditAction::Lifted {
+                ban_id: id,
+                target: ban.target,
+            },
+        );
+        self.changed.notify_one();
+        Some(ban)
+    }
+
+    /// The active ban covering the player or address, if there is one
+    pub fn find(&self, player_id: Option<PlayerId>, ip: Option<IpAddr>) -> Option<Ban> {
+        let now = unix_time();
+        self.bans
+            .read()
+            .unwrap()
+            .values()
+            .find(|ban| ban.is_active(now) && ban.target.matches(player_id, ip))
+            .cloned()
+    }
+
+    /// Every ban that hasn't expired
+    pub fn active_bans(&self) -> Vec<Ban> {
+        let now = unix_time();
+        self.bans
+            .read()
+            .unwrap()
+            .values()
+            .filter(|ban| ban.is_active(now))
+            .cloned()
+            .collect()
+    }
+
+    /// The most recent changes to the ban list, oldest first
+    pub fn audit_log(&self) -> Vec<AuditEntry> {
+        self.audit_log.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Adds an entry to the audit trail, forgetting the oldest once it's full
+    pub fn audit(&self, actor: String, action: AuditAction) {
+        let mut audit_log = self.audit_log.lock().unwrap();
+        if audit_log.len() == AUDIT_LOG_CAPACITY {
+            audit_log.pop_front();
+        }
+        audit_log.push_back(AuditEntry {
+            at: unix_time(),
+            actor,
+            action,
+        });
+        drop(audit_log);
+        self.changed.notify_one();
+    }
+
+    /// Writes the bans that haven't expired and the audit trail to the file, if there is one
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let saved = SavedBans {
+            bans: self.active_bans(),
+            audit_log: self.audit_log.lock().unwrap().clone(),
+        };
+        if let Err(e) = write_atomically(file, &saved) {
+            warn!(event = "bans_not_saved", file = %file.display(), error = %e);
+        }
+    }
+
+    /// Writes the bans to the file whenever they change until shut down, then writes them once
+    /// more on the way out
+    pub async fn run(self: Arc<Self>, shutdown: CancellationToken) {
+        if self.file.is_none() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                _ = self.changed.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            self.save_in_background().await;
+        }
+        self.save_in_background().await;
+    }
+
+    async fn save_in_background(self: &Arc<Self>) {
+        let bans = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || bans.save()).await {
+            warn!(event = "bans_not_saved", error = %e);
+        }
+    }
+}
+
+#[cfg(test)]
+fn player_ban(bans: &BanList, player_id: u128, duration: Option<Duration>) -> Ban {
+    bans.ban(
+        BanTarget::Player {
+            player_id: player_id.into(),
+        },
+        "cheating".to_owned(),
+        duration,
+        "api".to_owned(),
+    )
+}
+
+#[cfg(test)]
+mod find {
+    use super::*;
+
+    #[test]
+    fn matches_banned_players() {
+        let bans = BanList::default();
+        let ban = player_ban(&bans, 1, None);
+
+        assert_eq!(bans.find(Some(1.into()), None), Some(ban));
+        assert_eq!(bans.find(Some(2.into()), None), None);
+    }
+
+    #[test]
+    fn matches_addresses_within_banned_networks() {
+        let bans = BanList::default();
+        bans.ban(
+            BanTarget::Network {
+                network: "10.0.0.0/8".parse().unwrap(),
+            },
+            "abuse".to_owned(),
+            None,
+            "api".to_owned(),
+        );
+
+        assert!(bans.find(None, Some("10.1.2.3".parse().unwrap())).is_some());
+        assert!(bans
+            .find(None, Some("192.168.0.1".parse().unwrap()))
+            .is_none());
+    }
+
+    #[test]
+    fn ignores_expired_bans() {
+        let bans = BanList::default();
+        player_ban(&bans, 1, Some(Duration::ZERO));
+
+        assert_eq!(bans.find(Some(1.into()), None), None);
+        assert!(bans.active_bans().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod subscribe {
+    use super::*;
+
+    #[test]
+    fn receives_bans_issued_after_subscribing() {
+        let bans = BanList::default();
+        player_ban(&bans, 1, None);
+        let mut issued = bans.subscribe();
+
+        let ban = player_ban(&bans, 2, None);
+
+        assert_eq!(issued.try_recv(), Ok(ban));
+        assert!(issued.try_recv().is_err());
+    }
+}
+
+#[cfg(test)]
+mod lift {
+    use super::*;
+
+    #[test]
+    fn removes_ban_and_records_audit_entries() {
+        let bans = BanList::default();
+        let ban = player_ban(&bans, 1, None);
+
+        assert_eq!(bans.lift(ban.id, "moderator".to_owned()), Some(ban.clone()));
+        assert_eq!(bans.find(Some(1.into()), None), None);
+
+        let actions: Vec<_> = bans
+            .audit_log()
+            .into_iter()
+            .map(|entry| (entry.actor, entry.action))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (
+                    "api".to_owned(),
+                    AuditAction::Banned {
+                        ban_id: ban.id,
+                        target: ban.target
+                    }
+                ),
+                (
+                    "moderator".to_owned(),
+                    AuditAction::Lifted {
+                        ban_id: ban.id,
+                        target: ban.target
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let file =
+            std::env::temp_dir().join(format!("wormhole-bans-{}.json", uuid::Uuid::new_v4()));
+        let bans = BanList::with_file(file.clone()).unwrap();
+        let kept = player_ban(&bans, 1, None);
+        let lifted = player_ban(&bans, 2, None);
+        player_ban(&bans, 3, Some(Duration::ZERO));
+        bans.lift(lifted.id, "moderator".to_owned());
+        bans.save();
+
+        let restarted = BanList::with_file(file.clone()).unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(restarted.active_bans(), vec![kept]);
+        assert_eq!(restarted.audit_log(), bans.audit_log());
+        assert_eq!(restarted.audit_log().len(), 4);
+    }
+
+    #[test]
+    fn returns_none_for_unknown_bans() {
+        let bans = BanList::default();
+        assert_eq!(bans.lift(BanId(Uuid::new_v4()), "api".to_owned()), None);
+        assert!(bans.audit_log().is_empty());
+    }
+}
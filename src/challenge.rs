@@ -0,0 +1,299 @@
+//! Challenges guests must solve before joining a room anonymously, so that farming
+//! anonymous connections for spam costs the farmer something
+//!
+//! How a challenge is posed and checked is up to the [verifier][ChallengeVerifier], so a
+//! CAPTCHA service can be plugged in. The built-in [ProofOfWork] verifier needs no outside
+//! service: clients fetch a challenge from `/challenge` and search for a solution whose
+//! SHA-256 hash, taken over `<challenge>:<solution>`, starts with enough zero bits.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::clock::unix_time;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long a proof-of-work challenge can be solved and redeemed for
+pub const DEFAULT_CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A client's answer to a challenge, taken from the `challenge` and `solution` query parameters
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ChallengeResponse {
+    /// The challenge as it was issued, for verifiers that issue their own
+    pub challenge: Option<String>,
+    pub solution: Option<String>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ChallengeError {
+    #[error("A challenge must be solved before joining as a guest")]
+    Missing,
+    #[error("The challenge is malformed")]
+    Malformed,
+    #[error("The challenge was not issued by this server")]
+    InvalidSignature,
+    #[error("The challenge has expired")]
+    Expired,
+    #[error("The solution is not correct")]
+    WrongSolution,
+    #[error("The challenge has already been used")]
+    AlreadyUsed,
+    #[error("The challenge could not be checked: {0}")]
+    Unavailable(String),
+}
+
+pub type VerifyFuture<'a> = Pin<Box<dyn Future<Output = Result<(), ChallengeError>> + Send + 'a>>;
+
+/// Poses challenges to guests and checks their answers
+pub trait ChallengeVerifier: std::fmt::Debug + Send + Sync {
+    /// A new challenge, sent as is to the client asking for one
+    fn issue(&self) -> serde_json::Value;
+
+    /// Checks the client's answer, which may mean asking an outside service
+    fn verify<'a>(&'a self, response: &'a ChallengeResponse) -> VerifyFuture<'a>;
+}
+
+/// A proof-of-work challenge as sent to clients
+#[derive(Debug, PartialEq, Serialize)]
+struct ProofOfWorkChallenge {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    challenge: String,
+    /// How many leading zero bits the hash of a solution must have
+    difficulty: u8,
+    /// Seconds since the Unix epoch
+    expires_at: u64,
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Issues signed proof-of-work challenges, so they can be checked without being stored until
+/// they're redeemed, after which they're remembered until they expire so they can't be reused
+pub struct ProofOfWork {
+    key: Zeroizing<[u8; 32]>,
+    difficulty: u8,
+    ttl: Duration,
+    /// The challenges already redeemed, along with when they expire
+    redeemed: Mutex<HashMap<String, u64>>,
+}
+
+impl std::fmt::Debug for ProofOfWork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProofOfWork")
+            .field("difficulty", &self.difficulty)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProofOfWork {
+    /// Creates a verifier requiring `difficulty` leading zero bits, signing challenges
+    /// with a random key
+    pub fn new(difficulty: u8, ttl: Duration) -> Self {
+        let mut key = Zeroizing::new([0; 32]);
+        key[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+        key[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+        Self {
+            key,
+            difficulty,
+            ttl,
+            redeemed: Default::default(),
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(self.key.as_slice()).expect("HMAC accepts keys of any length")
+    }
+
+    fn issue_challenge(&self) -> ProofOfWorkChallenge {
+        let expires_at = unix_time() + self.ttl.as_secs();
+        let payload = format!(
+            "{}.{expires_at}.{}",
+            Uuid::new_v4().simple(),
+            self.difficulty
+        );
+        let signature = URL_SAFE_NO_PAD.encode(
+            self.mac()
+                .chain_update(payload.as_bytes())
+                .finalize()
+                .into_bytes(),
+        );
+        ProofOfWorkChallenge {
+            kind: "proof_of_work",
+            challenge: format!("{payload}.{signature}"),
+            difficulty: self.difficulty,
+            expires_at,
+        }
+    }
+
+    fn check(&self, challenge: &str, solution: &str) -> Result<(), ChallengeError> {
+        let (payload, signature) = challenge
+            .rsplit_once('.')
+            .ok_or(ChallengeError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| ChallengeError::Malformed)?;
+        self.mac()
+            .chain_update(payload.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| ChallengeError::InvalidSignature)?;
+
+        let mut fields = payload.split('.').skip(1);
+        let (Some(Ok(expires_at)), Some(Ok(difficulty))) = (
+            fields.next().map(str::parse::<u64>),
+            fields.next().map(str::parse::<u32>),
+        ) else {
+            return Err(ChallengeError::Malformed);
+        };
+        let now = unix_time();
+        if now >= expires_at {
+            return Err(ChallengeError::Expired);
+        }
+        let hash = Sha256::new()
+            .chain_update(challenge)
+            .chain_update(b":")
+            .chain_update(solution)
+            .finalize();
+        if leading_zero_bits(&hash) < difficulty {
+            return Err(ChallengeError::WrongSolution);
+        }
+
+        let mut redeemed = self.redeemed.lock().unwrap();
+        redeemed.retain(|_, expires_at| now < *expires_at);
+        if redeemed.insert(challenge.to_owned(), expires_at).is_some() {
+            return Err(ChallengeError::AlreadyUsed);
+        }
+        Ok(())
+    }
+}
+
+impl ChallengeVerifier for ProofOfWork {
+    fn issue(&self) -> serde_json::Value {
+        serde_json::to_value(self.issue_challenge()).expect("Challenges always serialize")
+    }
+
+    fn verify<'a>(&'a self, response: &'a ChallengeResponse) -> VerifyFuture<'a> {
+        Box::pin(async move {
+            match (&response.challenge, &response.solution) {
+                (Some(challenge), Some(solution)) => self.check(challenge, solution),
+                _ => Err(ChallengeError::Missing),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+fn solve(challenge: &str, difficulty: u8) -> String {
+    (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| {
+            let hash = Sha256::new()
+                .chain_update(challenge)
+                .chain_update(b":")
+                .chain_update(solution)
+                .finalize();
+            leading_zero_bits(&hash) >= u32::from(difficulty)
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod check {
+    use super::*;
+
+    #[test]
+    fn accepts_solutions_once() {
+        let verifier = ProofOfWork::new(8, DEFAULT_CHALLENGE_TTL);
+        let challenge = verifier.issue_challenge().challenge;
+        let solution = solve(&challenge, 8);
+
+        assert_eq!(verifier.check(&challenge, &solution), Ok(()));
+        assert_eq!(
+            verifier.check(&challenge, &solution),
+            Err(ChallengeError::AlreadyUsed)
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_solutions() {
+        let verifier = ProofOfWork::new(32, DEFAULT_CHALLENGE_TTL);
+        let challenge = verifier.issue_challenge().challenge;
+
+        assert_eq!(
+            verifier.check(&challenge, "0"),
+            Err(ChallengeError::WrongSolution)
+        );
+    }
+
+    #[test]
+    fn rejects_expired_challenges() {
+        let verifier = ProofOfWork::new(0, Duration::ZERO);
+        let challenge = verifier.issue_challenge().challenge;
+
+        assert_eq!(
+            verifier.check(&challenge, "0"),
+            Err(ChallengeError::Expired)
+        );
+    }
+
+    #[test]
+    fn rejects_challenges_from_other_servers() {
+        let challenge = ProofOfWork::new(0, DEFAULT_CHALLENGE_TTL)
+            .issue_challenge()
+            .challenge;
+
+        assert_eq!(
+            ProofOfWork::new(0, DEFAULT_CHALLENGE_TTL).check(&challenge, "0"),
+            Err(ChallengeError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_lowered_difficulty() {
+        let verifier = ProofOfWork::new(32, DEFAULT_CHALLENGE_TTL);
+        let challenge = verifier.issue_challenge().challenge;
+        let (payload, signature) = challenge.rsplit_once('.').unwrap();
+        let easier = format!(
+            "{}.0.{signature}",
+            payload.trim_end_matches("32").trim_end_matches('.')
+        );
+
+        assert_eq!(
+            verifier.check(&easier, "0"),
+            Err(ChallengeError::InvalidSignature)
+        );
+    }
+}
+
+#[cfg(test)]
+mod leading_zero_bits {
+    use super::*;
+
+    #[test]
+    fn counts_across_bytes() {
+        assert_eq!(leading_zero_bits(&[0, 0b0001_0000, 0]), 11);
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0, 0]), 16);
+    }
+}
@@ -0,0 +1,122 @@
+//! Text chat between the connections in a room
+//!
+//! Chat is sent with [ClientMessage::Chat][crate::protocol::ClientMessage::Chat], charged to
+//! the sender's chat [budget][crate::rate_limit::RouteBudget::Chat], turned away from players
+//! [muted][crate::game::Room::mute] in the room, passed through the server's
+//! [content filters][crate::content_filter], and delivered on its [channel][crate::protocol::ChatChannel]: to the whole
+//! room, the sender's team, the members of the sender's [party][crate::parties] in the room,
+//! or whispered to a single player. Teams come from the seats players join matches with, which
+//! the server signs, so clients can't put themselves on another team.
+//! Rooms keep their most recent room-wide messages so players joining later can catch up.
+//!
+//! [Reactions][crate::protocol::ClientMessage::React] are a cheaper way to express
+//! something: players pick an emote from the server's [EmoteCatalog], so there's nothing to
+//! filter, mute or keep, and they're charged to a separate, more generous
+//! [budget][crate::rate_limit::RouteBudget::Reactions].
+
+use thiserror::Error;
+
+use crate::game::PlayerId;
+
+/// The longest chat message a client may send
+pub const MAX_CHAT_MESSAGE_CHARS: usize = 500;
+
+/// How many of a room's most recent chat messages are kept for players joining later
+pub const CHAT_SCROLLBACK_LEN: usize = 50;
+
+/// Why a line of chat can't be delivered on the channel it was sent on
+#[derive(Error, Debug, PartialEq)]
+pub enum ChatRoutingError {
+    #[error("You aren't on a team in this room")]
+    NoTeam,
+    #[error("You aren't in a party")]
+    NoParty,
+    #[error("{0} isn't in this room")]
+    NotInRoom(PlayerId),
+}
+
+/// Why a chat message can't be sent
+pub fn check_chat_text(text: &str) -> Result<(), String> {
+    if text.trim().is_empty() {
+        Err("Chat messages can't be empty".to_owned())
+    } else if text.chars().count() > MAX_CHAT_MESSAGE_CHARS {
+        Err(format!(
+            "Chat messages can be at most {MAX_CHAT_MESSAGE_CHARS} characters"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The emotes players can react with unless the server is configured with its own
+pub const DEFAULT_EMOTES: [&str; 6] = ["thumbs_up", "thumbs_down", "laugh", "wow", "gg", "heart"];
+
+/// The longest name an emote can have
+const MAX_EMOTE_NAME_LEN: usize = 32;
+
+/// Whether `name` can be used as an emote, which must be made of lowercase letters, digits and
+/// underscores
+pub fn is_valid_emote_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_EMOTE_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// The emotes players can react with
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmoteCatalog {
+    emotes: Vec<String>,
+}
+
+impl EmoteCatalog {
+    pub fn new(emotes: Vec<String>) -> Self {
+        Self { emotes }
+    }
+
+    pub fn contains(&self, emote: &str) -> bool {
+        self.emotes.iter().any(|known| known == emote)
+    }
+}
+
+impl Default for EmoteCatalog {
+    fn default() -> Self {
+        Self::new(DEFAULT_EMOTES.map(str::to_owned).to_vec())
+    }
+}
+
+#[cfg(test)]
+mod check_chat_text {
+    use super::*;
+
+    #[test]
+    fn rejects_blank_and_overlong_messages() {
+        assert!(check_chat_text(" ").is_err());
+        assert!(check_chat_text(&"a".repeat(MAX_CHAT_MESSAGE_CHARS + 1)).is_err());
+        assert!(check_chat_text(&"a".repeat(MAX_CHAT_MESSAGE_CHARS)).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod is_valid_emote_name {
+    use super::*;
+
+    #[test]
+    fn accepts_the_default_emotes() {
+        assert!(DEFAULT_EMOTES.into_iter().all(is_valid_emote_name));
+    }
+
+    #[test]
+    fn rejects_anything_but_lowercase_letters_digits_and_underscores() {
+        for name in [
+            "",
+            "Thumbs",
+            "thumbs up",
+            "<b>",
+            &"a".repeat(MAX_EMOTE_NAME_LEN + 1),
+        ] {
+            assert!(!is_valid_emote_name(name), "{name:?}");
+        }
+    }
+}
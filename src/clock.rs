@@ -0,0 +1,197 @@
+//! The time the server's timers run on
+//!
+//! Timers that decide when something happens, such as [room deletion][crate::game::Room::schedule_deletion],
+//! [scheduled rooms][crate::scheduling] opening and [matchmaking][crate::matchmaking] rounds,
+//! read the time and sleep through a [Clock] rather than calling into tokio directly. The
+//! server runs on the [SystemClock], while tests can swap in a [MockClock] and
+//! [advance][MockClock::advance] it by hand, so timeouts fire instantly and in the same order
+//! every run instead of being slept through.
+//!
+//! Timestamps kept in files and the store or sent to clients are read from the system's
+//! wall clock through [unix_time] and [unix_time_ms].
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::oneshot;
+
+/// A future that completes once a [Clock] has slept for the requested time
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A source of the current time, and of timers that wait on it
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time, which timestamps sent to clients are given in, moving along with
+    /// [now][Self::now]
+    fn system_time(&self) -> SystemTime;
+
+    /// The [wall-clock time][Self::system_time] in seconds since the Unix epoch
+    fn unix_time(&self) -> u64 {
+        self.system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+
+    /// Waits until `duration` has passed on this clock
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// The clock the server runs on, which follows tokio's
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The [SystemClock], shared
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// The time in seconds since the Unix epoch, as timestamps are kept in files and the store
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The time in milliseconds since the Unix epoch, as chat messages and recordings are
+/// timestamped
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[derive(Debug, Default)]
+struct MockTime {
+    elapsed: Duration,
+    /// The pending sleeps along with when they're due
+    sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+/// A clock that stands still until it's [advanced][MockClock::advance]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    started_at: SystemTime,
+    time: Mutex<MockTime>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            started_at: SystemTime::now(),
+            time: Default::default(),
+        }
+    }
+
+    /// Moves the clock forward by `by`, waking every sleep that has come due
+    ///
+    /// The tasks waiting on them still need the runtime to poll them before they carry on.
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.elapsed += by;
+        let elapsed = time.elapsed;
+        let (due, pending) = std::mem::take(&mut time.sleepers)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= elapsed);
+        time.sleepers = pending;
+        drop(time);
+        for (_, sleeper) in due {
+            // Sleeps that were dropped before coming due have nobody to wake
+            let _ = sleeper.send(());
+        }
+    }
+
+    /// How long the clock has been advanced by in total
+    pub fn elapsed(&self) -> Duration {
+        self.time.lock().unwrap().elapsed
+    }
+
+    /// How many sleeps are still waiting to come due
+    pub fn sleeper_count(&self) -> usize {
+        let mut time = self.time.lock().unwrap();
+        time.sleepers.retain(|(_, sleeper)| !sleeper.is_closed());
+        time.sleepers.len()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.started_at + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        let mut time = self.time.lock().unwrap();
+        if duration.is_zero() {
+            return Box::pin(std::future::ready(()));
+        }
+        let (sender, receiver) = oneshot::channel();
+        let deadline = time.elapsed + duration;
+        time.sleepers.push((deadline, sender));
+        Box::pin(async move {
+            // The clock was dropped, so it will never come due
+            if receiver.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod advance {
+    use super::*;
+
+    #[tokio::test]
+    async fn wakes_sleeps_once_they_come_due() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut short = tokio::spawn(clock.sleep(Duration::from_secs(10)));
+        let mut long = tokio::spawn(clock.sleep(Duration::from_secs(60)));
+        tokio::task::yield_now().await;
+
+        clock.advance(Duration::from_secs(30));
+        (&mut short).await.unwrap();
+        assert!(!long.is_finished());
+        assert_eq!(clock.sleeper_count(), 1);
+
+        clock.advance(Duration::from_secs(30));
+        (&mut long).await.unwrap();
+        assert_eq!(clock.now() - start, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn forgets_dropped_sleeps() {
+        let clock = MockClock::new();
+        drop(clock.sleep(Duration::from_secs(1)));
+
+        assert_eq!(clock.sleeper_count(), 0);
+    }
+}
@@ -0,0 +1,1280 @@
+//! Sharing the lobby between server instances through Redis pub/sub, so each instance lists
+//! the rooms hosted by every other instance alongside its own
+//!
+//! Every instance publishes its lobby events to [LOBBY_CHANNEL], along with a snapshot of
+//! its rooms every [SNAPSHOT_INTERVAL] so instances that start later, or miss messages
+//! while disconnected, catch up. Instances that go [INSTANCE_TIMEOUT] without being heard
+//! from are assumed to be gone and their rooms are forgotten.
+//!
+//! Players join a room through the instance hosting it. Instances configured with the URL
+//! they're reachable at share it along with their events, so the others can send players
+//! joining its rooms their way.
+//!
+//! Instances also register themselves in the [NODES_KEY] hash, refreshing their [NodeInfo]
+//! as a heartbeat every [SNAPSHOT_INTERVAL]. The [janitor][Janitor] removes nodes whose
+//! heartbeat is older than [INSTANCE_TIMEOUT] and records their rooms in the
+//! [ORPHANED_ROOMS_KEY] hash.
+//!
+//! Before an instance is taken down it can be [drained][RedisBridge::drain], handing each of
+//! its rooms over to the live instance with the most room to spare through
+//! [MIGRATION_CHANNEL]. Once the new host lists the room, its players are sent a
+//! [ServerMessage::Migrate] telling them where to reconnect, with an invite to get back in.
+//! Instances must share the invite secret for those invites to be accepted.
+//!
+//! New rooms are [placed][RedisBridge::place_room] on the instance the [HashRing] built from
+//! the live registrations picks for them, handed over the same way.
+//!
+//! Along with its heartbeat each instance replaces its [players hash][players_key], holding
+//! the rooms each player connected to it is in, so any instance can
+//! [locate][RedisBridge::locate_player] a player wherever they're connected. These hashes
+//! expire with [INSTANCE_TIMEOUT] so dead instances' players aren't reported. Messages meant
+//! for a player rather than a room, such as [invitations][crate::invitations], are
+//! [relayed][RedisBridge::notify_player] through [NOTICES_CHANNEL] to the instances they're
+//! connected to, and answers to invitations to every instance so they all forget them. [Announcements][crate::announcements] are relayed to every instance through
+//! [ANNOUNCEMENTS_CHANNEL].
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use redis::{AsyncCommands, ErrorKind, RedisError, RedisResult};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::announcements::{self, AnnouncementScope};
+use crate::clock::unix_time;
+use crate::faults;
+use crate::game::{
+    LobbyEvent, LobbyFeed, PlayerId, RoomDeletionQueue, RoomId, RoomRegistry, RoomState,
+    RoomSummary,
+};
+use crate::invitations::InvitationBook;
+use crate::invites::InviteSigner;
+use crate::janitor::Janitor;
+use crate::load::{LoadLimits, LoadReport};
+use crate::placement::{HashRing, Placement};
+use crate::protocol::ServerMessage;
+use crate::sessions::SessionRegistry;
+
+/// The Redis channel lobby events are published on
+pub const LOBBY_CHANNEL: &str = "wormhole:lobby";
+/// The Redis channel rooms are handed over to other instances on
+pub const MIGRATION_CHANNEL: &str = "wormhole:migrations";
+/// The Redis channel messages for players connected to other instances are relayed on
+pub const NOTICES_CHANNEL: &str = "wormhole:notices";
+/// The Redis channel announcements are relayed to the other instances on
+pub const ANNOUNCEMENTS_CHANNEL: &str = "wormhole:announcements";
+/// How long a draining instance waits for its rooms to be taken over
+pub const MIGRATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a new room placed on another instance has to show up in its lobby before it's
+/// created here instead
+pub const PLACEMENT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long the invites players reconnect to migrated rooms with last
+const MIGRATION_INVITE_TTL: Duration = Duration::from_secs(5 * 60);
+/// The Redis hash holding each instance's [NodeInfo], keyed by its ID
+pub const NODES_KEY: &str = "wormhole:nodes";
+/// The Redis hash holding the [OrphanedRoom]s left behind by dead instances, keyed by the
+/// room's ID
+pub const ORPHANED_ROOMS_KEY: &str = "wormhole:orphaned_rooms";
+/// The prefix of the Redis hashes holding the rooms of each player connected to an instance,
+/// keyed by the player's ID
+pub const PLAYERS_KEY_PREFIX: &str = "wormhole:players:";
+/// How often each instance publishes every room it hosts
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long an instance can go without being heard from before its rooms are forgotten
+pub const INSTANCE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait before reconnecting to Redis after losing the connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClusterEvent {
+    RoomCreated {
+        room: RoomSummary,
+    },
+    RoomUpdated {
+        room: RoomSummary,
+    },
+    RoomDeleted {
+        id: RoomId,
+    },
+    /// Every room the instance hosts
+    Snapshot {
+        rooms: Vec<RoomSummary>,
+    },
+}
+
+impl From<LobbyEvent> for ClusterEvent {
+    fn from(event: LobbyEvent) -> Self {
+        match event {
+            LobbyEvent::RoomCreated(room) => Self::RoomCreated { room },
+            LobbyEvent::RoomUpdated(room) => Self::RoomUpdated { room },
+            LobbyEvent::RoomDeleted { id } => Self::RoomDeleted { id },
+        }
+    }
+}
+
+/// A lobby event along with the instance it happened on, as published to Redis
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct ClusterMessage {
+    instance: Uuid,
+    /// The URL players reach the instance at, if it has been configured with one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_url: Option<String>,
+    #[serde(flatten)]
+    event: ClusterEvent,
+}
+
+/// A message for a player connected to another instance, as published to Redis
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct Notice {
+    /// The instance the message was sent from, which has already delivered it locally
+    instance: Uuid,
+    player_id: PlayerId,
+    message: ServerMessage<'static>,
+}
+
+/// An announcement for the rooms on every instance, as published to Redis
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct RelayedAnnouncement {
+    /// The instance the announcement was made on, which has already delivered it locally
+    instance: Uuid,
+    text: String,
+    #[serde(flatten)]
+    scope: AnnouncementScope,
+}
+
+/// An instance as registered in the shared store
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub id: Uuid,
+    /// The URL players reach the instance at, if it has been configured with one
+    pub public_url: Option<String>,
+    /// How many rooms the instance says it can host
+    pub capacity: usize,
+    /// The instance's [load score][LoadReport::score] as of its last heartbeat
+    #[serde(default)]
+    pub load: f64,
+    /// The region the instance runs in, if it has been configured with one
+    #[serde(default)]
+    pub region: Option<String>,
+    /// The rooms the instance hosted as of its last heartbeat
+    pub rooms: Vec<RoomId>,
+    /// When the instance last refreshed its registration, in seconds since the Unix epoch
+    pub heartbeat_at: u64,
+}
+
+impl NodeInfo {
+    /// Whether the instance has refreshed its registration recently enough, as of `now` in
+    /// seconds since the Unix epoch
+    pub fn is_alive(&self, now: u64) -> bool {
+        now.saturating_sub(self.heartbeat_at) < INSTANCE_TIMEOUT.as_secs()
+    }
+}
+
+/// A room whose instance died while hosting it
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct OrphanedRoom {
+    pub id: RoomId,
+    /// The instance that was hosting the room
+    pub node: Uuid,
+    /// When the instance was found dead, in seconds since the Unix epoch
+    pub orphaned_at: u64,
+}
+
+/// A room handed over to another instance, either moving there or being placed there as
+/// it's created
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct Migration {
+    /// The instance taking the room over
+    target: Uuid,
+    room_id: RoomId,
+    state: RoomState,
+}
+
+/// How many rooms were handed over when draining an instance
+#[derive(Debug, Default, PartialEq, Clone, Serialize, ToSchema)]
+pub struct DrainReport {
+    pub migrated: usize,
+    /// Rooms no other instance took over, which stay where they are
+    pub failed: usize,
+}
+
+/// Every registered node, skipping registrations that can't be read
+pub(crate) fn registered_nodes(registrations: &HashMap<String, String>) -> Vec<NodeInfo> {
+    registrations
+        .values()
+        .filter_map(
+            |registration| match serde_json::from_str::<NodeInfo>(registration) {
+                Ok(node) => Some(node),
+                Err(e) => {
+                    debug!(event = "cluster_node_malformed", error = %e);
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// The nodes among the registered ones that have stopped sending heartbeats, as of `now`
+/// in seconds since the Unix epoch
+pub(crate) fn dead_nodes(registrations: &HashMap<String, String>, now: u64) -> Vec<NodeInfo> {
+    registered_nodes(registrations)
+        .into_iter()
+        .filter(|node| !node.is_alive(now))
+        .collect()
+}
+
+/// The live node other than `instance` with the most room to spare that players can be sent
+/// to, counting the rooms already `assigned` to each node since its last heartbeat, preferring
+/// nodes in the same region as `instance` so players stay close to their rooms
+fn choose_target<'a>(
+    nodes: &'a [NodeInfo],
+    instance: Uuid,
+    now: u64,
+    assigned: &HashMap<Uuid, usize>,
+) -> Option<&'a NodeInfo> {
+    let region = nodes
+        .iter()
+        .find(|node| node.id == instance)
+        .and_then(|node| node.region.as_ref());
+    let headroom = |node: &NodeInfo| {
+        node.capacity
+            .saturating_sub(node.rooms.len())
+            .saturating_sub(assigned.get(&node.id).copied().unwrap_or_default())
+    };
+    nodes
+        .iter()
+        .filter(|node| node.id != instance && node.public_url.is_some() && node.is_alive(now))
+        .filter(|node| headroom(node) > 0)
+        .max_by_key(|node| {
+            (
+                region.is_some() && node.region.as_ref() == region,
+                headroom(node),
+            )
+        })
+}
+
+/// The Redis hash holding the rooms of each player connected to the instance
+pub fn players_key(instance: Uuid) -> String {
+    format!("{PLAYERS_KEY_PREFIX}{instance}")
+}
+
+/// A room a player is connected to
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlayerLocation {
+    pub room_id: RoomId,
+    /// The instance hosting the room
+    pub node: Uuid,
+    /// The URL players reach the instance at, if it has been configured with one
+    pub public_url: Option<String>,
+}
+
+/// Fails a write to Redis as though it were unreachable, if a fault injector says to
+fn check_write_fault() -> RedisResult<()> {
+    if faults::fail_storage_write("redis") {
+        return Err(RedisError::from((
+            ErrorKind::IoError,
+            "Injected storage write failure",
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct RemoteInstance {
+    rooms: HashMap<RoomId, RoomSummary>,
+    public_url: Option<String>,
+    last_seen: Instant,
+}
+
+/// The rooms hosted by other instances, as last heard from them
+#[derive(Debug, Default)]
+pub struct RemoteRooms {
+    instances: Mutex<HashMap<Uuid, RemoteInstance>>,
+    lobby: LobbyFeed,
+}
+
+impl RemoteRooms {
+    /// Every room hosted by another instance
+    pub fn list(&self) -> Vec<RoomSummary> {
+        self.instances
+            .lock()
+            .unwrap()
+            .values()
+            .flat_map(|instance| instance.rooms.values().cloned())
+            .collect()
+    }
+
+    /// Changes to the rooms hosted by other instances
+    pub fn lobby(&self) -> &LobbyFeed {
+        &self.lobby
+    }
+
+    /// The URL of the instance hosting the room, if another instance hosts it and has said
+    /// where it can be reached
+    pub fn host_of(&self, id: RoomId) -> Option<String> {
+        self.instances
+            .lock()
+            .unwrap()
+            .values()
+            .find(|instance| instance.rooms.contains_key(&id))
+            .and_then(|instance| instance.public_url.clone())
+    }
+
+    fn apply(&self, message: ClusterMessage, now: Instant) {
+        let mut instances = self.instances.lock().unwrap();
+        let instance = instances
+            .entry(message.instance)
+            .or_insert_with(|| RemoteInstance {
+                rooms: HashMap::new(),
+                public_url: None,
+                last_seen: now,
+            });
+        instance.last_seen = now;
+        instance.public_url = message.public_url;
+
+        match message.event {
+            ClusterEvent::RoomCreated { room } | ClusterEvent::RoomUpdated { room } => {
+                match instance.rooms.insert(room.id, room.clone()) {
+                    None => self.lobby.publish(LobbyEvent::RoomCreated(room), false),
+                    Some(previous) if previous == room => {}
+                    Some(previous) => self.lobby.publish(
+                        LobbyEvent::RoomUpdated(room),
+                        previous.visibility.is_listed(),
+                    ),
+                }
+            }
+            ClusterEvent::RoomDeleted { id } => {
+                if let Some(previous) = instance.rooms.remove(&id) {
+                    self.lobby.publish(
+                        LobbyEvent::RoomDeleted { id },
+                        previous.visibility.is_listed(),
+                    );
+                }
+            }
+            ClusterEvent::Snapshot { rooms } => {
+                let rooms: HashMap<_, _> = rooms.into_iter().map(|room| (room.id, room)).collect();
+                for (&id, previous) in &instance.rooms {
+                    if !rooms.contains_key(&id) {
+                        self.lobby.publish(
+                            LobbyEvent::RoomDeleted { id },
+                            previous.visibility.is_listed(),
+                        );
+                    }
+                }
+                for (id, room) in &rooms {
+                    match instance.rooms.get(id) {
+                        None => self
+                            .lobby
+                            .publish(LobbyEvent::RoomCreated(room.clone()), false),
+                        Some(previous) if previous != room => self.lobby.publish(
+                            LobbyEvent::RoomUpdated(room.clone()),
+                            previous.visibility.is_listed(),
+                        ),
+                        Some(_) => {}
+                    }
+                }
+                instance.rooms = rooms;
+            }
+        }
+    }
+
+    /// Forgets the instances that haven't been heard from in time, along with their rooms
+    fn prune(&self, now: Instant) {
+        self.instances.lock().unwrap().retain(|id, instance| {
+            if now.duration_since(instance.last_seen) < INSTANCE_TIMEOUT {
+                return true;
+            }
+            info!(event = "cluster_instance_lost", instance = %id, rooms = instance.rooms.len());
+            for (&id, room) in &instance.rooms {
+                self.lobby
+                    .publish(LobbyEvent::RoomDeleted { id }, room.visibility.is_listed());
+            }
+            false
+        });
+    }
+}
+
+/// Where [RedisBridge::place_room] put a new room
+#[derive(Debug, PartialEq, Clone)]
+pub enum RoomPlacement {
+    /// The room is to be created on this instance
+    Here,
+    /// The room was created on another instance, reachable at this WebSocket URL
+    Elsewhere(String),
+    /// Every instance has used up some of its capacity
+    Saturated,
+    /// No instance runs in the region the room was asked for in
+    UnknownRegion,
+}
+
+/// Relays lobby events between this instance and every other instance sharing the Redis
+/// server
+#[derive(Debug)]
+pub struct RedisBridge {
+    client: redis::Client,
+    instance: Uuid,
+    public_url: Option<String>,
+    limits: LoadLimits,
+    /// Where new rooms go, as of the last heartbeat
+    ring: ArcSwap<HashRing>,
+    /// The live instances, as of the last heartbeat
+    nodes: ArcSwap<Vec<NodeInfo>>,
+    registry: Arc<RoomRegistry>,
+    deletion_queue: RoomDeletionQueue,
+    /// How long rooms taken over from other instances wait for someone to join them
+    unjoined_room_timeout: Duration,
+    remote: Arc<RemoteRooms>,
+    sessions: Arc<SessionRegistry>,
+    /// The invitations to forget once they're answered through other instances
+    invitations: Option<Arc<InvitationBook>>,
+    janitor: Janitor,
+}
+
+impl RedisBridge {
+    pub fn new(
+        client: redis::Client,
+        registry: Arc<RoomRegistry>,
+        deletion_queue: RoomDeletionQueue,
+        unjoined_room_timeout: Duration,
+        remote: Arc<RemoteRooms>,
+        sessions: Arc<SessionRegistry>,
+    ) -> Self {
+        let instance = Uuid::new_v4();
+        Self {
+            janitor: Janitor::new(client.clone(), instance),
+            client,
+            instance,
+            public_url: None,
+            limits: LoadLimits::default(),
+            ring: Default::default(),
+            nodes: Default::default(),
+            registry,
+            deletion_queue,
+            unjoined_room_timeout,
+            remote,
+            sessions,
+            invitations: None,
+        }
+    }
+
+    /// Forgets the invitations in the book once they're answered through other instances
+    pub fn with_invitations(mut self, invitations: Arc<InvitationBook>) -> Self {
+        self.invitations = Some(invitations);
+        self
+    }
+
+    /// Advertises how much this instance can handle, and measures its load against it
+    pub fn with_limits(mut self, limits: LoadLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sweeps up after dead instances whenever this one is elected to
+    pub fn janitor(&self) -> &Janitor {
+        &self.janitor
+    }
+
+    /// The live instances sharing the lobby, this one included, as of the last heartbeat
+    pub fn nodes(&self) -> Arc<Vec<NodeInfo>> {
+        self.nodes.load_full()
+    }
+
+    /// The rooms the player is connected to on every live instance, as of each one's last
+    /// heartbeat, and on this one as of now
+    pub async fn locate_player(&self, player_id: PlayerId) -> RedisResult<Vec<PlayerLocation>> {
+        let mut locations: Vec<_> = self
+            .sessions
+            .rooms_of(player_id)
+            .into_iter()
+            .map(|room_id| PlayerLocation {
+                room_id,
+                node: self.instance,
+                public_url: self.public_url.clone(),
+            })
+            .collect();
+
+        let nodes = self.nodes();
+        let others: Vec<_> = nodes
+            .iter()
+            .filter(|node| node.id != self.instance)
+            .collect();
+        if others.is_empty() {
+            return Ok(locations);
+        }
+        let mut pipe = redis::pipe();
+        for node in &others {
+            pipe.hget(players_key(node.id), player_id.to_string());
+        }
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let rooms: Vec<Option<String>> = pipe.query_async(&mut connection).await?;
+        for (node, rooms) in others.into_iter().zip(rooms) {
+            let Some(rooms) = rooms else {
+                continue;
+            };
+            let rooms: Vec<RoomId> = match serde_json::from_str(&rooms) {
+                Ok(rooms) => rooms,
+                Err(e) => {
+                    debug!(event = "cluster_player_malformed", node = %node.id, error = %e);
+                    continue;
+                }
+            };
+            locations.extend(rooms.into_iter().map(|room_id| PlayerLocation {
+                room_id,
+                node: node.id,
+                public_url: node.public_url.clone(),
+            }));
+        }
+        Ok(locations)
+    }
+
+    /// Relays the message to the player's live connections on the other instances, returning
+    /// whether they're connected to any as of each one's last heartbeat
+    pub async fn notify_player(
+        &self,
+        player_id: PlayerId,
+        message: &ServerMessage<'static>,
+    ) -> RedisResult<bool> {
+        let elsewhere = self.is_elsewhere(player_id).await?;
+        if elsewhere {
+            self.publish_notice(player_id, message).await?;
+        }
+        Ok(elsewhere)
+    }
+
+    /// Relays an answer to an invitation to every other instance, for the player who sent it
+    /// if they're connected there and so every instance forgets the invitation, returning
+    /// whether the player is connected to any as of each one's last heartbeat
+    pub async fn relay_answer(
+        &self,
+        player_id: PlayerId,
+        message: &ServerMessage<'static>,
+    ) -> RedisResult<bool> {
+        self.publish_notice(player_id, message).await?;
+        self.is_elsewhere(player_id).await
+    }
+
+    async fn is_elsewhere(&self, player_id: PlayerId) -> RedisResult<bool> {
+        Ok(self
+            .locate_player(player_id)
+            .await?
+            .iter()
+            .any(|location| location.node != self.instance))
+    }
+
+    async fn publish_notice(
+        &self,
+        player_id: PlayerId,
+        message: &ServerMessage<'static>,
+    ) -> RedisResult<()> {
+        let notice = Notice {
+            instance: self.instance,
+            player_id,
+            message: message.clone(),
+        };
+        let notice = serde_json::to_string(&notice).expect("Notices always serialize");
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        connection
+            .publish::<_, _, ()>(NOTICES_CHANNEL, notice)
+            .await
+    }
+
+    /// Relays the announcement to the other instances, to deliver to their rooms within
+    /// `scope`
+    pub async fn announce(&self, text: &str, scope: &AnnouncementScope) -> RedisResult<()> {
+        let announcement = RelayedAnnouncement {
+            instance: self.instance,
+            text: text.to_owned(),
+            scope: scope.clone(),
+        };
+        let announcement =
+            serde_json::to_string(&announcement).expect("Announcements always serialize");
+        check_write_fault()?;
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        connection
+            .publish::<_, _, ()>(ANNOUNCEMENTS_CHANNEL, announcement)
+            .await
+    }
+
+    /// Tells the other instances to send players joining this instance's rooms to `url`
+    pub fn with_public_url(mut self, url: String) -> Self {
+        self.public_url = Some(url);
+        self
+    }
+
+    /// Relays events until the process exits, reconnecting whenever the connection to Redis
+    /// is lost
+    pub async fn run(self: Arc<Self>) {
+        info!(event = "cluster_bridge_started", instance = %self.instance);
+        tokio::join!(
+            self.publish_forever(),
+            self.subscribe_forever(),
+            self.prune_forever(),
+            self.heartbeat_forever(),
+            self.janitor.run()
+        );
+    }
+
+    async fn publish_forever(&self) {
+        loop {
+            match self.publish().await {
+                Ok(()) => return,
+                Err(e) => warn!(event = "cluster_publish_failed", error = %e),
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Publishes this instance's lobby events and snapshots until the lobby closes
+    async fn publish(&self) -> RedisResult<()> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let mut lobby = self.registry.lobby().subscribe();
+        let mut snapshots = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                _ = snapshots.tick() => ClusterEvent::Snapshot {
+                    rooms: Vec::clone(&self.registry.list_active_rooms()),
+                },
+                event = lobby.recv() => match event {
+                    Ok(event) => event.into(),
+                    // The next snapshot makes up for whatever was missed
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            };
+            let message = ClusterMessage {
+                instance: self.instance,
+                public_url: self.public_url.clone(),
+                event,
+            };
+            let message =
+                serde_json::to_string(&message).expect("Cluster messages always serialize");
+            check_write_fault()?;
+            connection
+                .publish::<_, _, ()>(LOBBY_CHANNEL, message)
+                .await?;
+        }
+    }
+
+    async fn subscribe_forever(&self) {
+        loop {
+            if let Err(e) = self.subscribe().await {
+                warn!(event = "cluster_subscription_lost", error = %e);
+            }
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    /// Applies other instances' lobby events, takes over the rooms handed to this instance,
+    /// and delivers notices to the players connected to it and announcements to its rooms,
+    /// until the connection is lost
+    async fn subscribe(&self) -> RedisResult<()> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub
+            .subscribe(&[
+                LOBBY_CHANNEL,
+                MIGRATION_CHANNEL,
+                NOTICES_CHANNEL,
+                ANNOUNCEMENTS_CHANNEL,
+            ])
+            .await?;
+        info!(event = "cluster_subscribed", channel = LOBBY_CHANNEL);
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload: String = message.get_payload()?;
+            if message.get_channel_name() == MIGRATION_CHANNEL {
+                match serde_json::from_str::<Migration>(&payload) {
+                    Ok(migration) if migration.target == self.instance => self.adopt(migration),
+                    Ok(_) => {}
+                    Err(e) => debug!(event = "cluster_migration_malformed", error = %e),
+                }
+                continue;
+            }
+            if message.get_channel_name() == NOTICES_CHANNEL {
+                match serde_json::from_str::<Notice>(&payload) {
+                    Ok(notice) if notice.instance == self.instance => {}
+                    Ok(notice) => {
+                        if let (
+                            ServerMessage::InvitationAnswered { invitation_id, .. },
+                            Some(invitations),
+                        ) = (&notice.message, &self.invitations)
+                        {
+                            invitations.forget(*invitation_id);
+                        }
+                        self.sessions.notify(notice.player_id, notice.message);
+                    }
+                    Err(e) => debug!(event = "cluster_notice_malformed", error = %e),
+                }
+                continue;
+            }
+            if message.get_channel_name() == ANNOUNCEMENTS_CHANNEL {
+                match serde_json::from_str::<RelayedAnnouncement>(&payload) {
+                    Ok(announcement) if announcement.instance == self.instance => {}
+                    Ok(announcement) => {
+                        announcements::announce(
+                            &self.registry,
+                            &announcement.text,
+                            &announcement.scope,
+                        );
+                    }
+                    Err(e) => debug!(event = "cluster_announcement_malformed", error = %e),
+                }
+                continue;
+            }
+            match serde_json::from_str::<ClusterMessage>(&payload) {
+                Ok(message) if message.instance == self.instance => {}
+                Ok(message) => self.remote.apply(message, Instant::now()),
+                Err(e) => debug!(event = "cluster_message_malformed", error = %e),
+            }
+        }
+        Err(RedisError::from((
+            ErrorKind::IoError,
+            "The subscription was closed",
+        )))
+    }
+
+    fn adopt(&self, migration: Migration) {
+        let Migration { room_id, state, .. } = migration;
+        match self
+            .registry
+            .adopt_room(room_id, |room| room.restore(state))
+        {
+            Ok(()) => {
+                if let Some(room) = self.registry.get_room_for_id(room_id) {
+                    room.schedule_unjoined_deletion(
+                        room_id,
+                        self.unjoined_room_timeout,
+                        self.deletion_queue.clone(),
+                    );
+                    self.registry.room_updated(room_id);
+                }
+                info!(event = "cluster_room_adopted", %room_id)
+            }
+            Err(e) => warn!(event = "cluster_room_adoption_failed", %room_id, error = %e),
+        }
+    }
+
+    /// Hands every room over to the other instances, sending the players in each room taken
+    /// over within [MIGRATION_TIMEOUT] to its new host and forgetting the room here
+    pub async fn drain(&self, invite_signer: &InviteSigner) -> RedisResult<DrainReport> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let registrations: HashMap<String, String> = connection.hgetall(NODES_KEY).await?;
+        let nodes = registered_nodes(&registrations);
+        let now = unix_time();
+        let mut report = DrainReport::default();
+
+        // Listening before handing anything over so no takeover goes unnoticed
+        let mut lobby = self.remote.lobby().subscribe();
+        let mut assigned = HashMap::new();
+        let mut pending = HashMap::new();
+        for summary in self.registry.list_active_rooms().iter() {
+            let (Some(room), Some(target)) = (
+                self.registry.get_room_for_id(summary.id),
+                choose_target(&nodes, self.instance, now, &assigned),
+            ) else {
+                warn!(event = "cluster_room_not_migrated", room_id = %summary.id);
+                report.failed += 1;
+                continue;
+            };
+            let migration = Migration {
+                target: target.id,
+                room_id: summary.id,
+                state: room.state(),
+            };
+            let migration = serde_json::to_string(&migration).expect("Migrations always serialize");
+            connection
+                .publish::<_, _, ()>(MIGRATION_CHANNEL, migration)
+                .await?;
+            *assigned.entry(target.id).or_default() += 1;
+            pending.insert(summary.id, target.public_url.clone().unwrap_or_default());
+        }
+
+        let deadline = tokio::time::Instant::now() + MIGRATION_TIMEOUT;
+        loop {
+            pending.retain(|&id, url: &mut String| {
+                if self.remote.host_of(id).as_deref() != Some(url.as_str()) {
+                    return true;
+                }
+                self.hand_over(id, url, invite_signer);
+                report.migrated += 1;
+                false
+            });
+            if pending.is_empty() {
+                break;
+            }
+            match tokio::time::timeout_at(deadline, lobby.recv()).await {
+                Ok(Ok(_) | Err(RecvError::Lagged(_))) => {}
+                Ok(Err(RecvError::Closed)) | Err(_) => break,
+            }
+        }
+        for id in pending.keys() {
+            warn!(event = "cluster_room_not_migrated", room_id = %id);
+        }
+        report.failed += pending.len();
+        info!(
+            event = "cluster_drained",
+            migrated = report.migrated,
+            failed = report.failed
+        );
+        Ok(report)
+    }
+
+    /// Creates a room with the state on the instance the ring places it on, out of those in
+    /// `region` if given, when that's another instance, falling back to this one if the other
+    /// didn't take it over within [PLACEMENT_TIMEOUT]
+    pub async fn place_room(&self, region: Option<&str>, state: RoomState) -> RoomPlacement {
+        let room_id = RoomId::from(Uuid::new_v4().as_u128());
+        let local = LoadReport::measure(&self.registry, self.limits).score;
+        let target = match self
+            .ring
+            .load()
+            .place(room_id, region, Some((self.instance, local)))
+        {
+            Placement::Node(node) if node != self.instance => node,
+            Placement::Node(_) => return RoomPlacement::Here,
+            // Before the first heartbeat not even this instance is on the ring
+            Placement::Unknown
+                if region.is_none_or(|region| self.registry.region() == Some(region)) =>
+            {
+                return RoomPlacement::Here
+            }
+            Placement::Unknown => return RoomPlacement::UnknownRegion,
+            Placement::Saturated => {
+                warn!(event = "cluster_saturated");
+                return RoomPlacement::Saturated;
+            }
+        };
+        let mut lobby = self.remote.lobby().subscribe();
+        let migration = Migration {
+            target,
+            room_id,
+            state,
+        };
+        let migration = serde_json::to_string(&migration).expect("Migrations always serialize");
+        let published = async {
+            let mut connection = self.client.get_multiplexed_async_connection().await?;
+            connection
+                .publish::<_, _, ()>(MIGRATION_CHANNEL, migration)
+                .await
+        };
+        if let Err(e) = published.await {
+            warn!(event = "cluster_placement_failed", %room_id, error = %e);
+            return RoomPlacement::Here;
+        }
+
+        let placed = tokio::time::timeout(PLACEMENT_TIMEOUT, async {
+            loop {
+                if let Some(host) = self.remote.host_of(room_id) {
+                    return Some(host);
+                }
+                if let Err(RecvError::Closed) = lobby.recv().await {
+                    return None;
+                }
+            }
+        });
+        match placed.await {
+            Ok(Some(host)) => {
+                info!(event = "cluster_room_placed", %room_id, %host);
+                RoomPlacement::Elsewhere(format!("{host}/ws/{room_id}"))
+            }
+            Ok(None) | Err(_) => {
+                warn!(event = "cluster_placement_timed_out", %room_id, node = %target);
+                RoomPlacement::Here
+            }
+        }
+    }
+
+    /// Sends the players in a room another instance has taken over to its new host at `url`
+    fn hand_over(&self, id: RoomId, url: &str, invite_signer: &InviteSigner) {
+        // The new host delivers the room's events to its webhook, and its game isn't finished
+        self.registry.unregister_webhook(id);
+        let Some(room) = self.registry.delete_room(id) else {
+            return;
+        };
+        let message = ServerMessage::Migrate {
+            url: format!("{url}/ws/{id}"),
+            invite: invite_signer.mint(id, MIGRATION_INVITE_TTL, None),
+        };
+        if let Err(e) = room.broadcaster().broadcast(&message) {
+            warn!(event = "cluster_migration_notice_failed", room_id = %id, error = %e);
+        }
+        room.close();
+        info!(event = "cluster_room_migrated", room_id = %id, host = url);
+    }
+
+    async fn prune_forever(&self) {
+        let mut ticks = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            ticks.tick().await;
+            self.remote.prune(Instant::now());
+        }
+    }
+
+    async fn heartbeat_forever(&self) {
+        let mut ticks = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            ticks.tick().await;
+            if let Err(e) = self.heartbeat().await {
+                warn!(event = "cluster_heartbeat_failed", error = %e);
+            }
+        }
+    }
+
+    /// Refreshes this instance's registration, places new rooms on the instances that are
+    /// still refreshing theirs and orphans the rooms of those that aren't
+    async fn heartbeat(&self) -> RedisResult<()> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let now = unix_time();
+        let node = NodeInfo {
+            id: self.instance,
+            public_url: self.public_url.clone(),
+            capacity: self.limits.rooms,
+            load: LoadReport::measure(&self.registry, self.limits).score,
+            region: self.registry.region().map(str::to_owned),
+            rooms: self
+                .registry
+                .list_active_rooms()
+                .iter()
+                .map(|room| room.id)
+                .collect(),
+            heartbeat_at: now,
+        };
+        let node = serde_json::to_string(&node).expect("Node registrations always serialize");
+        check_write_fault()?;
+        connection
+            .hset::<_, _, _, ()>(NODES_KEY, self.instance.to_string(), node)
+            .await?;
+
+        let players: Vec<(String, String)> = self
+            .sessions
+            .online_players()
+            .into_iter()
+            .map(|(player_id, rooms)| {
+                let rooms = serde_json::to_string(&rooms).expect("Room IDs always serialize");
+                (player_id.to_string(), rooms)
+            })
+            .collect();
+        let players_key = players_key(self.instance);
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&players_key).ignore();
+        if !players.is_empty() {
+            pipe.hset_multiple(&players_key, &players)
+                .ignore()
+                .expire(&players_key, INSTANCE_TIMEOUT.as_secs() as i64)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut connection).await?;
+
+        let registrations: HashMap<String, String> = connection.hgetall(NODES_KEY).await?;
+        let hosts: Vec<_> = registered_nodes(&registrations)
+            .into_iter()
+            .filter(|node| node.id == self.instance || node.public_url.is_some())
+            .filter(|node| node.is_alive(now))
+            .collect();
+        self.ring.store(Arc::new(HashRing::new(&hosts)));
+        self.nodes.store(Arc::new(hosts));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn message(instance: u128, event: ClusterEvent) -> ClusterMessage {
+    ClusterMessage {
+        instance: Uuid::from_u128(instance),
+        public_url: None,
+        event,
+    }
+}
+
+#[cfg(test)]
+fn room(id: u128) -> RoomSummary {
+    RoomSummary {
+        id: id.into(),
+        name: None,
+        game_type: None,
+        player_count: 0,
+        spectator_count: 0,
+        capacity: None,
+        state: crate::game::RoomStatus::Open,
+        visibility: crate::game::Visibility::Public,
+        region: None,
+        starts_at: None,
+        deletes_at: None,
+        last_active_at: None,
+    }
+}
+
+#[cfg(test)]
+mod apply {
+    use super::*;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[test]
+    fn tracks_rooms_created_and_deleted_elsewhere() {
+        let remote = RemoteRooms::default();
+        let mut lobby = remote.lobby().subscribe();
+        let now = Instant::now();
+
+        remote.apply(message(1, ClusterEvent::RoomCreated { room: room(1) }), now);
+        remote.apply(message(2, ClusterEvent::RoomCreated { room: room(2) }), now);
+        remote.apply(
+            message(1, ClusterEvent::RoomDeleted { id: 1_u128.into() }),
+            now,
+        );
+
+        assert_eq!(remote.list(), [room(2)]);
+        assert_eq!(lobby.try_recv(), Ok(LobbyEvent::RoomCreated(room(1))));
+        assert_eq!(lobby.try_recv(), Ok(LobbyEvent::RoomCreated(room(2))));
+        assert_eq!(
+            lobby.try_recv(),
+            Ok(LobbyEvent::RoomDeleted { id: 1_u128.into() })
+        );
+    }
+
+    #[test]
+    fn reconciles_snapshots_with_what_was_heard() {
+        let remote = RemoteRooms::default();
+        let now = Instant::now();
+        remote.apply(message(1, ClusterEvent::RoomCreated { room: room(1) }), now);
+        remote.apply(message(1, ClusterEvent::RoomCreated { room: room(2) }), now);
+        let mut lobby = remote.lobby().subscribe();
+
+        remote.apply(
+            message(
+                1,
+                ClusterEvent::Snapshot {
+                    rooms: vec![room(2), room(3)],
+                },
+            ),
+            now,
+        );
+
+        let mut rooms = remote.list();
+        rooms.sort_by(|a, b| a.id.partial_cmp(&b.id).unwrap());
+        assert_eq!(rooms, [room(2), room(3)]);
+        assert_eq!(
+            lobby.try_recv(),
+            Ok(LobbyEvent::RoomDeleted { id: 1_u128.into() })
+        );
+        assert_eq!(lobby.try_recv(), Ok(LobbyEvent::RoomCreated(room(3))));
+        assert_eq!(lobby.try_recv(), Err(TryRecvError::Empty));
+    }
+}
+
+#[cfg(test)]
+mod host_of {
+    use super::*;
+
+    #[test]
+    fn finds_the_instance_hosting_the_room() {
+        let remote = RemoteRooms::default();
+        let now = Instant::now();
+        remote.apply(
+            ClusterMessage {
+                public_url: Some("https://b.example.com".to_owned()),
+                ..message(1, ClusterEvent::RoomCreated { room: room(1) })
+            },
+            now,
+        );
+        remote.apply(message(2, ClusterEvent::RoomCreated { room: room(2) }), now);
+
+        assert_eq!(
+            remote.host_of(1_u128.into()).as_deref(),
+            Some("https://b.example.com")
+        );
+        assert_eq!(remote.host_of(2_u128.into()), None);
+        assert_eq!(remote.host_of(3_u128.into()), None);
+    }
+}
+
+#[cfg(test)]
+mod prune {
+    use super::*;
+
+    #[test]
+    fn forgets_instances_that_went_quiet() {
+        let remote = RemoteRooms::default();
+        let start = Instant::now();
+        remote.apply(
+            message(1, ClusterEvent::RoomCreated { room: room(1) }),
+            start,
+        );
+        remote.apply(
+            message(2, ClusterEvent::RoomCreated { room: room(2) }),
+            start + INSTANCE_TIMEOUT,
+        );
+        let mut lobby = remote.lobby().subscribe();
+
+        remote.prune(start + INSTANCE_TIMEOUT);
+
+        assert_eq!(remote.list(), [room(2)]);
+        assert_eq!(
+            lobby.try_recv(),
+            Ok(LobbyEvent::RoomDeleted { id: 1_u128.into() })
+        );
+    }
+}
+
+#[cfg(test)]
+mod cluster_message {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn names_the_instance_alongside_the_event() {
+        assert_eq!(
+            serde_json::to_value(message(1, ClusterEvent::RoomDeleted { id: 2_u128.into() }))
+                .unwrap(),
+            json!({
+                "instance": "00000000-0000-0000-0000-000000000001",
+                "type": "room_deleted",
+                "id": "00000000-0000-0000-0000-000000000002",
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod relayed_announcement {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn leaves_the_scope_out_unless_narrowed() {
+        let announcement = RelayedAnnouncement {
+            instance: Uuid::from_u128(1),
+            text: "Restarting soon".to_owned(),
+            scope: AnnouncementScope {
+                rooms: None,
+                regions: Some(vec!["eu".to_owned()]),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(announcement).unwrap(),
+            json!({
+                "instance": "00000000-0000-0000-0000-000000000001",
+                "text": "Restarting soon",
+                "regions": ["eu"],
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod dead_nodes {
+    use super::*;
+
+    fn registration(instance: u128, heartbeat_at: u64) -> (String, String) {
+        let node = NodeInfo {
+            id: Uuid::from_u128(instance),
+            public_url: None,
+            capacity: crate::load::DEFAULT_ROOM_CAPACITY,
+            load: 0.0,
+            region: None,
+            rooms: vec![1_u128.into()],
+            heartbeat_at,
+        };
+        (node.id.to_string(), serde_json::to_string(&node).unwrap())
+    }
+
+    #[test]
+    fn finds_nodes_without_a_recent_heartbeat() {
+        let now = 1_000;
+        let registrations = HashMap::from([
+            registration(1, now - 5),
+            registration(2, now - INSTANCE_TIMEOUT.as_secs()),
+        ]);
+
+        let dead = dead_nodes(&registrations, now);
+
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, Uuid::from_u128(2));
+        assert_eq!(dead[0].rooms, [RoomId::from(1_u128)]);
+    }
+
+    #[test]
+    fn skips_malformed_registrations() {
+        let registrations = HashMap::from([("garbage".to_owned(), "{".to_owned())]);
+
+        assert!(dead_nodes(&registrations, 1_000).is_empty());
+    }
+
+    #[test]
+    fn tolerates_clocks_running_behind() {
+        let registrations = HashMap::from([registration(1, 1_010)]);
+
+        assert!(dead_nodes(&registrations, 1_000).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod choose_target {
+    use super::*;
+
+    fn node(instance: u128, capacity: usize, rooms: u128) -> NodeInfo {
+        NodeInfo {
+            id: Uuid::from_u128(instance),
+            public_url: Some(format!("https://{instance}.example.com")),
+            capacity,
+            load: 0.0,
+            region: None,
+            rooms: (0..rooms).map(RoomId::from).collect(),
+            heartbeat_at: 1_000,
+        }
+    }
+
+    #[test]
+    fn prefers_the_node_with_the_most_room_to_spare() {
+        let nodes = [node(1, 10, 8), node(2, 10, 5), node(3, 100, 0)];
+        let mut assigned = HashMap::new();
+
+        let target = choose_target(&nodes, Uuid::from_u128(3), 1_000, &assigned).unwrap();
+        assert_eq!(target.id, Uuid::from_u128(2));
+
+        assigned.insert(Uuid::from_u128(2), 4);
+        let target = choose_target(&nodes, Uuid::from_u128(3), 1_000, &assigned).unwrap();
+        assert_eq!(target.id, Uuid::from_u128(1));
+    }
+
+    #[test]
+    fn skips_dead_full_and_unreachable_nodes() {
+        let dead = NodeInfo {
+            heartbeat_at: 0,
+            ..node(1, 10, 0)
+        };
+        let unreachable = NodeInfo {
+            public_url: None,
+            ..node(2, 10, 0)
+        };
+        let nodes = [dead, unreachable, node(3, 10, 10)];
+
+        assert_eq!(
+            choose_target(&nodes, Uuid::from_u128(4), 1_000, &HashMap::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn prefers_nodes_in_the_same_region() {
+        let in_region = |instance, rooms, region: &str| NodeInfo {
+            region: Some(region.to_owned()),
+            ..node(instance, 10, rooms)
+        };
+        let nodes = [
+            in_region(1, 8, "eu-west"),
+            in_region(2, 0, "us-east"),
+            in_region(3, 0, "eu-west"),
+        ];
+
+        let target = choose_target(&nodes, Uuid::from_u128(3), 1_000, &HashMap::new()).unwrap();
+        assert_eq!(target.id, Uuid::from_u128(1));
+    }
+}
@@ -0,0 +1,120 @@
+use sha2::{Digest, Sha256};
+use std::env::var;
+use tracing::{info, warn};
+
+use super::secrets::{read_secret, try_read_secret, Reloadable};
+use crate::auth::ApiTokens;
+use crate::challenge::{ChallengeVerifier, ProofOfWork, DEFAULT_CHALLENGE_TTL};
+use crate::identity::IdentityProvider;
+use crate::invites::InviteSigner;
+
+const API_TOKENS_ENV_VAR: &str = "WORMHOLE_API_TOKENS";
+
+fn load_api_tokens() -> Result<ApiTokens, String> {
+    match try_read_secret(API_TOKENS_ENV_VAR)? {
+        Some(tokens) => tokens
+            .expose()
+            .parse()
+            .map_err(|e| format!("The secret {API_TOKENS_ENV_VAR} is invalid: {e}")),
+        None => Ok(Default::default()),
+    }
+}
+
+/// The tokens accepted by the REST API, configured as a comma separated list of
+/// hex encoded SHA-256 hashes of each token, optionally suffixed with `:read`
+///
+/// The list may be read from the file named by `WORMHOLE_API_TOKENS_FILE` instead, in which
+/// case it is read again on every reload so tokens can be rotated without a restart.
+///
+/// # Panics
+/// Panics if the tokens can't be read or parsed
+pub fn get_api_tokens() -> Reloadable<ApiTokens> {
+    let tokens = load_api_tokens().unwrap_or_else(|e| panic!("{e}"));
+
+    if tokens.is_empty() {
+        warn!(
+            "No API tokens are configured, the REST API is open to anyone. Set {} to require them",
+            API_TOKENS_ENV_VAR
+        );
+    } else {
+        info!("Requiring API tokens since {} is set", API_TOKENS_ENV_VAR);
+    }
+    Reloadable::new(tokens, load_api_tokens)
+}
+
+const INVITE_SECRET_ENV_VAR: &str = "WORMHOLE_INVITE_SECRET";
+
+/// The signer for room invites, keyed with the SHA-256 hash of `WORMHOLE_INVITE_SECRET` (or
+/// of the file named by `WORMHOLE_INVITE_SECRET_FILE`) so invites outlive restarts and are
+/// accepted by every server sharing the secret, or with a random key if it isn't set
+pub fn get_invite_signer() -> InviteSigner {
+    match read_secret(INVITE_SECRET_ENV_VAR) {
+        Some(secret) => {
+            info!(
+                "Signing invites with the secret in {} since it is set",
+                INVITE_SECRET_ENV_VAR
+            );
+            InviteSigner::new(Sha256::digest(secret.expose().as_bytes()).into())
+        }
+        None => {
+            info!(
+                "Signing invites with a random key, set {} to keep invites valid across restarts",
+                INVITE_SECRET_ENV_VAR
+            );
+            InviteSigner::random()
+        }
+    }
+}
+
+const JWT_ISSUER_ENV_VAR: &str = "WORMHOLE_JWT_ISSUER";
+const JWT_AUDIENCE_ENV_VAR: &str = "WORMHOLE_JWT_AUDIENCE";
+const JWT_JWKS_URL_ENV_VAR: &str = "WORMHOLE_JWT_JWKS_URL";
+
+/// The identity provider players authenticate with, or `None` if players may join rooms anonymously
+///
+/// # Panics
+/// Panics if only some of the issuer, audience and JWKS URL are set
+pub fn get_identity_provider() -> Option<IdentityProvider> {
+    match (
+        var(JWT_ISSUER_ENV_VAR),
+        var(JWT_AUDIENCE_ENV_VAR),
+        var(JWT_JWKS_URL_ENV_VAR),
+    ) {
+        (Ok(issuer), Ok(audience), Ok(jwks_url)) => {
+            info!(
+                "Requiring player tokens issued by {} since {} is set",
+                issuer, JWT_ISSUER_ENV_VAR
+            );
+            Some(IdentityProvider {
+                issuer,
+                audience,
+                jwks_url,
+            })
+        }
+        (Err(_), Err(_), Err(_)) => {
+            warn!(
+                "No identity provider is configured, players join rooms anonymously. Set {}, {} and {} to require tokens",
+                JWT_ISSUER_ENV_VAR, JWT_AUDIENCE_ENV_VAR, JWT_JWKS_URL_ENV_VAR
+            );
+            None
+        }
+        _ => panic!(
+            "The environment variables {JWT_ISSUER_ENV_VAR}, {JWT_AUDIENCE_ENV_VAR} and {JWT_JWKS_URL_ENV_VAR} must be set together, please fix or delete them"
+        ),
+    }
+}
+
+const GUEST_CHALLENGE_DIFFICULTY_ENV_VAR: &str = "WORMHOLE_GUEST_CHALLENGE_DIFFICULTY";
+
+/// The proof-of-work challenge guests must solve before joining a room anonymously, requiring
+/// `WORMHOLE_GUEST_CHALLENGE_DIFFICULTY` leading zero bits, or `None` if it is zero or unset
+pub fn get_guest_challenge() -> Option<Box<dyn ChallengeVerifier>> {
+    let difficulty: u8 = super::parse_env_var(
+        GUEST_CHALLENGE_DIFFICULTY_ENV_VAR,
+        0,
+        "guest challenge difficulty in bits",
+    );
+    (difficulty > 0).then(|| {
+        Box::new(ProofOfWork::new(difficulty, DEFAULT_CHALLENGE_TTL)) as Box<dyn ChallengeVerifier>
+    })
+}
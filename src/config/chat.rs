@@ -0,0 +1,25 @@
+use tracing::info;
+
+use crate::chat::{is_valid_emote_name, EmoteCatalog};
+
+const EMOTES_ENV_VAR: &str = "WORMHOLE_EMOTES";
+
+/// The emotes players can react with, from the comma separated names in `WORMHOLE_EMOTES`
+///
+/// # Panics
+/// Panics if any name isn't made of lowercase letters, digits and underscores
+pub fn get_emote_catalog() -> EmoteCatalog {
+    let Ok(emotes) = std::env::var(EMOTES_ENV_VAR) else {
+        info!("Using the default emotes");
+        return EmoteCatalog::default();
+    };
+    let emotes: Vec<String> = emotes
+        .split(',')
+        .map(|emote| emote.trim().to_owned())
+        .collect();
+    if !emotes.iter().all(|emote| is_valid_emote_name(emote)) {
+        panic!("The environment variable {EMOTES_ENV_VAR} contains an invalid emote name, please fix or delete it")
+    }
+    info!("Using the emotes {}", emotes.join(", "));
+    EmoteCatalog::new(emotes)
+}
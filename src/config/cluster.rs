@@ -0,0 +1,96 @@
+use reqwest::Url;
+use tracing::info;
+
+use super::secrets::read_secret;
+use crate::load::{LoadLimits, DEFAULT_CONNECTION_CAPACITY, DEFAULT_ROOM_CAPACITY};
+
+const REDIS_URL_ENV_VAR: &str = "WORMHOLE_REDIS_URL";
+
+/// The Redis server instances share the lobby through, from `WORMHOLE_REDIS_URL` (or the
+/// file named by `WORMHOLE_REDIS_URL_FILE`, since the URL may hold a password), or `None`
+/// to keep the lobby to this instance
+///
+/// # Panics
+/// Panics if the URL is invalid
+pub fn get_redis_client() -> Option<redis::Client> {
+    let Some(url) = read_secret(REDIS_URL_ENV_VAR) else {
+        info!(
+            "Not sharing the lobby with other instances, set {} to do so",
+            REDIS_URL_ENV_VAR
+        );
+        return None;
+    };
+    let client = redis::Client::open(url.expose()).unwrap_or_else(|e| {
+        panic!("The environment variable {REDIS_URL_ENV_VAR} is not a valid Redis URL: {e}, please fix or delete it")
+    });
+    info!(
+        "Sharing the lobby with other instances through Redis since {} is set",
+        REDIS_URL_ENV_VAR
+    );
+    Some(client)
+}
+
+const PUBLIC_URL_ENV_VAR: &str = "WORMHOLE_PUBLIC_URL";
+
+/// The URL players reach this instance at, such as `https://eu-1.example.com`, so other
+/// instances can redirect players joining its rooms, from `WORMHOLE_PUBLIC_URL`
+///
+/// # Panics
+/// Panics if the URL is invalid
+pub fn get_public_url() -> Option<String> {
+    let Ok(url) = std::env::var(PUBLIC_URL_ENV_VAR) else {
+        info!(
+            "Not redirecting players from other instances, set {} to do so",
+            PUBLIC_URL_ENV_VAR
+        );
+        return None;
+    };
+    if Url::parse(&url).is_err() {
+        panic!("The environment variable {PUBLIC_URL_ENV_VAR} is not a valid URL, please fix or delete it")
+    }
+    info!("Telling other instances this one is reachable at {}", url);
+    Some(url.trim_end_matches('/').to_owned())
+}
+
+const REGION_ENV_VAR: &str = "WORMHOLE_REGION";
+
+/// The region this instance runs in, such as `eu-west`, from `WORMHOLE_REGION`, which its
+/// rooms are tagged with so clients can keep to the regions closest to them
+///
+/// # Panics
+/// Panics if the region is blank
+pub fn get_region() -> Option<String> {
+    let Ok(region) = std::env::var(REGION_ENV_VAR) else {
+        info!(
+            "Not tagging rooms with a region, set {} to do so",
+            REGION_ENV_VAR
+        );
+        return None;
+    };
+    let region = region.trim();
+    if region.is_empty() {
+        panic!("The environment variable {REGION_ENV_VAR} is blank, please fix or delete it")
+    }
+    info!("Tagging rooms with the region {}", region);
+    Some(region.to_owned())
+}
+
+const NODE_CAPACITY_ENV_VAR: &str = "WORMHOLE_NODE_CAPACITY";
+const NODE_CONNECTION_CAPACITY_ENV_VAR: &str = "WORMHOLE_NODE_CONNECTION_CAPACITY";
+
+/// How many rooms and connections this instance says it can handle, from
+/// `WORMHOLE_NODE_CAPACITY` and `WORMHOLE_NODE_CONNECTION_CAPACITY`
+pub fn get_load_limits() -> LoadLimits {
+    LoadLimits {
+        rooms: super::parse_env_var(
+            NODE_CAPACITY_ENV_VAR,
+            DEFAULT_ROOM_CAPACITY,
+            "node capacity",
+        ),
+        connections: super::parse_env_var(
+            NODE_CONNECTION_CAPACITY_ENV_VAR,
+            DEFAULT_CONNECTION_CAPACITY,
+            "node connection capacity",
+        ),
+    }
+}
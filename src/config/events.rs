@@ -0,0 +1,75 @@
+use tracing::info;
+
+use super::secrets::read_secret;
+use crate::events::{is_valid_subject_prefix, DEFAULT_NATS_SUBJECT_PREFIX};
+
+const NATS_URL_ENV_VAR: &str = "WORMHOLE_NATS_URL";
+const NATS_SUBJECT_PREFIX_ENV_VAR: &str = "WORMHOLE_NATS_SUBJECT_PREFIX";
+#[cfg(feature = "kafka")]
+const KAFKA_BROKERS_ENV_VAR: &str = "WORMHOLE_KAFKA_BROKERS";
+#[cfg(feature = "kafka")]
+const KAFKA_TOPIC_ENV_VAR: &str = "WORMHOLE_KAFKA_TOPIC";
+
+/// The NATS server events are published to, from `WORMHOLE_NATS_URL` (or the file named by
+/// `WORMHOLE_NATS_URL_FILE`, since the URL may hold credentials), or `None` to not publish
+/// them
+pub fn get_nats_url() -> Option<String> {
+    let Some(url) = read_secret(NATS_URL_ENV_VAR) else {
+        info!(
+            "Not publishing events to NATS, set {} to do so",
+            NATS_URL_ENV_VAR
+        );
+        return None;
+    };
+    info!(
+        "Publishing events to NATS since {} is set",
+        NATS_URL_ENV_VAR
+    );
+    Some(url.expose().to_owned())
+}
+
+/// What subjects events are published to NATS under, from `WORMHOLE_NATS_SUBJECT_PREFIX`
+///
+/// # Panics
+/// Panics if the prefix isn't a valid subject
+pub fn get_nats_subject_prefix() -> String {
+    let Ok(prefix) = std::env::var(NATS_SUBJECT_PREFIX_ENV_VAR) else {
+        return DEFAULT_NATS_SUBJECT_PREFIX.to_owned();
+    };
+    if !is_valid_subject_prefix(&prefix) {
+        panic!("The environment variable {NATS_SUBJECT_PREFIX_ENV_VAR} is not a valid NATS subject, please fix or delete it")
+    }
+    info!("Publishing events to NATS under {}", prefix);
+    prefix
+}
+
+/// The comma separated Kafka brokers game telemetry is published to, from
+/// `WORMHOLE_KAFKA_BROKERS`, or `None` to not publish it
+#[cfg(feature = "kafka")]
+pub fn get_kafka_brokers() -> Option<String> {
+    let Ok(brokers) = std::env::var(KAFKA_BROKERS_ENV_VAR) else {
+        info!(
+            "Not publishing telemetry to Kafka, set {} to do so",
+            KAFKA_BROKERS_ENV_VAR
+        );
+        return None;
+    };
+    info!("Publishing telemetry to Kafka brokers {}", brokers);
+    Some(brokers)
+}
+
+/// The Kafka topic game telemetry is published to, from `WORMHOLE_KAFKA_TOPIC`
+///
+/// # Panics
+/// Panics if the topic is empty
+#[cfg(feature = "kafka")]
+pub fn get_kafka_topic() -> String {
+    let Ok(topic) = std::env::var(KAFKA_TOPIC_ENV_VAR) else {
+        return crate::events::DEFAULT_KAFKA_TOPIC.to_owned();
+    };
+    if topic.is_empty() {
+        panic!("The environment variable {KAFKA_TOPIC_ENV_VAR} is empty, please fix or delete it")
+    }
+    info!("Publishing telemetry to the Kafka topic {}", topic);
+    topic
+}
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::leaderboards::Leaderboards;
+
+const SIZE_ENV_VAR: &str = "WORMHOLE_LEADERBOARD_SIZE";
+const DEFAULT_SIZE: usize = 100;
+const REFRESH_ENV_VAR: &str = "WORMHOLE_LEADERBOARD_REFRESH_SECS";
+const DEFAULT_REFRESH_SECS: u64 = 60;
+const RESULTS_FILE_ENV_VAR: &str = "WORMHOLE_LEADERBOARD_RESULTS_FILE";
+
+/// The leaderboards, with the results they're rolled up from kept in the file named by
+/// `WORMHOLE_LEADERBOARD_RESULTS_FILE` or in memory only if it isn't set
+///
+/// # Panics
+/// Panics if the file exists but can't be read as results
+pub fn get_leaderboards() -> Leaderboards {
+    let size = get_leaderboard_size();
+    let Ok(path) = std::env::var(RESULTS_FILE_ENV_VAR) else {
+        info!(
+            "Keeping leaderboard results in memory only, set {} to keep them in a file",
+            RESULTS_FILE_ENV_VAR
+        );
+        return Leaderboards::new(size);
+    };
+    let leaderboards = Leaderboards::with_file(size, PathBuf::from(&path)).unwrap_or_else(|e| {
+        panic!("The file {path} named by the environment variable {RESULTS_FILE_ENV_VAR} can't be read: {e}, please fix or delete it")
+    });
+    info!("Keeping leaderboard results in {}", path);
+    leaderboards
+}
+
+/// How many of the top players each leaderboard keeps
+pub fn get_leaderboard_size() -> usize {
+    super::parse_env_var(
+        SIZE_ENV_VAR,
+        DEFAULT_SIZE,
+        "number of players on each leaderboard",
+    )
+}
+
+/// How often the leaderboards are rebuilt from the latest results
+///
+/// # Panics
+/// Panics if the interval is zero
+pub fn get_leaderboard_refresh_interval() -> Duration {
+    let secs = super::parse_env_var(
+        REFRESH_ENV_VAR,
+        DEFAULT_REFRESH_SECS,
+        "time in seconds between leaderboard refreshes",
+    );
+    if secs == 0 {
+        panic!("The environment variable {REFRESH_ENV_VAR} must be a positive integer, please fix or delete it")
+    }
+    Duration::from_secs(secs)
+}
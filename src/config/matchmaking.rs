@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use crate::matchmaking::MatchmakingRules;
+
+const MATCH_SIZE_ENV_VAR: &str = "WORMHOLE_MATCH_SIZE";
+const DEFAULT_MATCH_SIZE: usize = 2;
+const MATCH_MIN_PLAYERS_ENV_VAR: &str = "WORMHOLE_MATCH_MIN_PLAYERS";
+const RELAX_AFTER_ENV_VAR: &str = "WORMHOLE_MATCHMAKING_RELAX_AFTER_SECS";
+const DEFAULT_RELAX_AFTER_SECS: u64 = 30;
+const RATING_BAND_ENV_VAR: &str = "WORMHOLE_MATCHMAKING_RATING_BAND";
+const DEFAULT_RATING_BAND: f64 = 100.0;
+const BAND_GROWTH_ENV_VAR: &str = "WORMHOLE_MATCHMAKING_BAND_GROWTH_PER_SEC";
+const DEFAULT_BAND_GROWTH_PER_SEC: f64 = 10.0;
+
+/// How parties are grouped into matches: `WORMHOLE_MATCH_SIZE` players to a match, which can
+/// start with as few as `WORMHOLE_MATCH_MIN_PLAYERS` once its oldest party has waited
+/// `WORMHOLE_MATCHMAKING_RELAX_AFTER_SECS`, and only with parties rated within
+/// `WORMHOLE_MATCHMAKING_RATING_BAND` of it, widening by
+/// `WORMHOLE_MATCHMAKING_BAND_GROWTH_PER_SEC` for each second it waits
+///
+/// # Panics
+/// Panics if the match size is zero
+pub fn get_matchmaking_rules() -> MatchmakingRules {
+    let match_size = super::parse_env_var(MATCH_SIZE_ENV_VAR, DEFAULT_MATCH_SIZE, "match size");
+    if match_size == 0 {
+        panic!("The environment variable {MATCH_SIZE_ENV_VAR} must be a positive integer, please fix or delete it")
+    }
+    let min_players = super::parse_env_var(
+        MATCH_MIN_PLAYERS_ENV_VAR,
+        match_size,
+        "fewest players a match can start with",
+    );
+    let relax_after = Duration::from_secs(super::parse_env_var(
+        RELAX_AFTER_ENV_VAR,
+        DEFAULT_RELAX_AFTER_SECS,
+        "time in seconds before matches can start short of players",
+    ));
+    let rating_band = super::parse_env_var(
+        RATING_BAND_ENV_VAR,
+        DEFAULT_RATING_BAND,
+        "rating band of newly queued parties",
+    );
+    let band_growth = super::parse_env_var(
+        BAND_GROWTH_ENV_VAR,
+        DEFAULT_BAND_GROWTH_PER_SEC,
+        "rating band growth per second",
+    );
+    MatchmakingRules::new(match_size, min_players, relax_after)
+        .with_rating_band(rating_band, band_growth)
+}
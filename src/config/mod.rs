@@ -1,2 +1,40 @@
+use std::{env::var, fmt::Display, str::FromStr};
+use tracing::info;
+
+pub mod auth;
+pub mod chat;
+pub mod cluster;
+pub mod events;
+pub mod leaderboards;
 pub mod logging;
+pub mod matchmaking;
+pub mod moderation;
+pub mod notifications;
+pub mod rate_limit;
+pub mod registry;
+pub mod room;
+pub mod secrets;
 pub mod server;
+pub mod webhooks;
+
+/// Reads `env_var` as a `T`, falling back to `default` when it isn't set
+///
+/// # Panics
+/// Panics if the variable is set to something that can't be parsed as a `T`
+fn parse_env_var<T: FromStr + Display>(env_var: &str, default: T, description: &str) -> T {
+    match var(env_var) {
+        Ok(value) => {
+            info!(
+                "Using {} as the {} since {} is set",
+                value, description, env_var
+            );
+            value.parse().unwrap_or_else(|_| {
+                panic!("The environment variable {env_var} contains an invalid {description}, please fix or delete it")
+            })
+        }
+        _ => {
+            info!("Using {} as the {}", default, description);
+            default
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::info;
+
+use super::secrets::read_secret;
+use crate::abuse::AbuseRules;
+use crate::bans::BanList;
+use crate::content_filter::{ContentFilters, ModerationApiFilter, WordListFilter};
+use crate::kick_votes::KickVoteRules;
+use crate::reports::ReportQueue;
+use crate::room_bans::RoomBans;
+use crate::spam::SpamRules;
+
+const BANS_FILE_ENV_VAR: &str = "WORMHOLE_BANS_FILE";
+const REPORTS_FILE_ENV_VAR: &str = "WORMHOLE_REPORTS_FILE";
+const ROOM_BANS_FILE_ENV_VAR: &str = "WORMHOLE_ROOM_BANS_FILE";
+const SPAM_WINDOW_ENV_VAR: &str = "WORMHOLE_SPAM_WINDOW_SECS";
+const SPAM_MAX_REPEATS_ENV_VAR: &str = "WORMHOLE_SPAM_MAX_REPEATS";
+const SPAM_MAX_LINES_ENV_VAR: &str = "WORMHOLE_SPAM_MAX_LINES";
+const SPAM_MUTES_ENV_VAR: &str = "WORMHOLE_SPAM_MUTE_SECS";
+const KICK_VOTE_TIMEOUT_ENV_VAR: &str = "WORMHOLE_KICK_VOTE_TIMEOUT_SECS";
+const KICK_VOTE_THRESHOLD_ENV_VAR: &str = "WORMHOLE_KICK_VOTE_THRESHOLD_PERCENT";
+const KICK_VOTE_MIN_VOTES_ENV_VAR: &str = "WORMHOLE_KICK_VOTE_MIN_VOTES";
+const KICK_VOTE_COOLDOWN_ENV_VAR: &str = "WORMHOLE_KICK_VOTE_COOLDOWN_SECS";
+const ABUSE_WINDOW_ENV_VAR: &str = "WORMHOLE_ABUSE_WINDOW_SECS";
+const ABUSE_WARN_SCORE_ENV_VAR: &str = "WORMHOLE_ABUSE_WARN_SCORE";
+const ABUSE_LOCK_SCORE_ENV_VAR: &str = "WORMHOLE_ABUSE_LOCK_SCORE";
+const ABUSE_LOCK_ENV_VAR: &str = "WORMHOLE_ABUSE_LOCK_SECS";
+const ABUSE_DISCONNECT_SCORE_ENV_VAR: &str = "WORMHOLE_ABUSE_DISCONNECT_SCORE";
+const MAX_FRAME_BYTES_ENV_VAR: &str = "WORMHOLE_MAX_FRAME_BYTES";
+const BLOCKED_WORDS_FILES_ENV_VAR: &str = "WORMHOLE_CHAT_BLOCKED_WORDS_FILE";
+const LEETSPEAK_ENV_VAR: &str = "WORMHOLE_BLOCKED_WORDS_LEETSPEAK";
+const MODERATION_API_URL_ENV_VAR: &str = "WORMHOLE_MODERATION_API_URL";
+const MODERATION_API_TOKEN_ENV_VAR: &str = "WORMHOLE_MODERATION_API_TOKEN";
+const MODERATION_API_TIMEOUT_ENV_VAR: &str = "WORMHOLE_MODERATION_API_TIMEOUT_MS";
+
+/// The server-wide bans and their audit trail, kept in the file named by `WORMHOLE_BANS_FILE`
+/// or in memory only if it isn't set
+///
+/// # Panics
+/// Panics if the file exists but can't be read as bans
+pub fn get_ban_list() -> BanList {
+    let Ok(path) = std::env::var(BANS_FILE_ENV_VAR) else {
+        info!(
+            "Keeping bans in memory only, set {} to keep them in a file",
+            BANS_FILE_ENV_VAR
+        );
+        return BanList::default();
+    };
+    let bans = BanList::with_file(PathBuf::from(&path)).unwrap_or_else(|e| {
+        panic!("The file {path} named by the environment variable {BANS_FILE_ENV_VAR} can't be read: {e}, please fix or delete it")
+    });
+    info!("Keeping bans in {}", path);
+    bans
+}
+
+/// The queue of players' reports, kept in the file named by `WORMHOLE_REPORTS_FILE` or in
+/// memory only if it isn't set
+///
+/// # Panics
+/// Panics if the file exists but can't be read as reports
+pub fn get_report_queue() -> ReportQueue {
+    let Ok(path) = std::env::var(REPORTS_FILE_ENV_VAR) else {
+        info!(
+            "Keeping reports in memory only, set {} to keep them in a file",
+            REPORTS_FILE_ENV_VAR
+        );
+        return ReportQueue::default();
+    };
+    let queue = ReportQueue::with_file(PathBuf::from(&path)).unwrap_or_else(|e| {
+        panic!("The file {path} named by the environment variable {REPORTS_FILE_ENV_VAR} can't be read: {e}, please fix or delete it")
+    });
+    info!("Keeping reports in {}", path);
+    queue
+}
+
+/// The players kept out of rooms and their rematches, kept in the file named by
+/// `WORMHOLE_ROOM_BANS_FILE` or in memory only if it isn't set
+///
+/// # Panics
+/// Panics if the file exists but can't be read as room bans
+pub fn get_room_bans() -> RoomBans {
+    let Ok(path) = std::env::var(ROOM_BANS_FILE_ENV_VAR) else {
+        info!(
+            "Keeping room bans in memory only, set {} to keep them in a file",
+            ROOM_BANS_FILE_ENV_VAR
+        );
+        return RoomBans::default();
+    };
+    let bans = RoomBans::with_file(PathBuf::from(&path)).unwrap_or_else(|e| {
+        panic!("The file {path} named by the environment variable {ROOM_BANS_FILE_ENV_VAR} can't be read: {e}, please fix or delete it")
+    });
+    info!("Keeping room bans in {}", path);
+    bans
+}
+
+/// The filters players' chat and display names are passed through
+///
+/// The words listed one per line in each of the comma separated files named by
+/// `WORMHOLE_CHAT_BLOCKED_WORDS_FILE` are masked, seeing through leetspeak unless
+/// `WORMHOLE_BLOCKED_WORDS_LEETSPEAK` is `false`. The text is then sent to the moderation
+/// service at `WORMHOLE_MODERATION_API_URL` if it's set, with the bearer token in
+/// `WORMHOLE_MODERATION_API_TOKEN` if that is, waiting at most
+/// `WORMHOLE_MODERATION_API_TIMEOUT_MS` for an answer.
+///
+/// # Panics
+/// Panics if any of the files can't be read or any of the settings is invalid
+pub fn get_content_filters() -> ContentFilters {
+    let mut filters = ContentFilters::default();
+    match std::env::var(BLOCKED_WORDS_FILES_ENV_VAR) {
+        Ok(paths) => {
+            let mut words = Vec::new();
+            for path in paths.split(',').map(str::trim) {
+                let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                    panic!("The file {path} named by the environment variable {BLOCKED_WORDS_FILES_ENV_VAR} can't be read: {e}, please fix or delete it")
+                });
+                words.extend(contents.lines().map(str::to_owned));
+            }
+            let leetspeak = super::parse_env_var(
+                LEETSPEAK_ENV_VAR,
+                true,
+                "setting for seeing through leetspeak in blocked words",
+            );
+            info!("Masking the words listed in {}", paths);
+            filters = filters.with_filter(WordListFilter::new(words).with_leetspeak(leetspeak));
+        }
+        Err(_) => info!(
+            "Not masking any words, set {} to do so",
+            BLOCKED_WORDS_FILES_ENV_VAR
+        ),
+    }
+    match std::env::var(MODERATION_API_URL_ENV_VAR) {
+        Ok(url) => {
+            let timeout = Duration::from_millis(super::parse_env_var(
+                MODERATION_API_TIMEOUT_ENV_VAR,
+                500,
+                "moderation API timeout in milliseconds",
+            ));
+            info!("Moderating chat and display names with {}", url);
+            let mut filter = ModerationApiFilter::new(url, timeout);
+            if let Some(token) = read_secret(MODERATION_API_TOKEN_ENV_VAR) {
+                filter = filter.with_token(token);
+            }
+            filters = filters.with_filter(filter);
+        }
+        Err(_) => info!(
+            "Not using a moderation service, set {} to do so",
+            MODERATION_API_URL_ENV_VAR
+        ),
+    }
+    filters
+}
+
+/// When chat counts as spam: saying the same thing more than `WORMHOLE_SPAM_MAX_REPEATS`
+/// times, or more than `WORMHOLE_SPAM_MAX_LINES` lines at all, within
+/// `WORMHOLE_SPAM_WINDOW_SECS`, and how long spammers are muted for, from the comma separated
+/// seconds in `WORMHOLE_SPAM_MUTE_SECS` for their first mute, their second and so on
+///
+/// # Panics
+/// Panics if any setting is invalid
+pub fn get_spam_rules() -> SpamRules {
+    let defaults = SpamRules::default();
+    let window = Duration::from_secs(super::parse_env_var(
+        SPAM_WINDOW_ENV_VAR,
+        defaults.window.as_secs(),
+        "spam detection window in seconds",
+    ));
+    let max_repeats = super::parse_env_var(
+        SPAM_MAX_REPEATS_ENV_VAR,
+        defaults.max_repeats,
+        "most times a player can repeat a line of chat",
+    );
+    let max_lines = super::parse_env_var(
+        SPAM_MAX_LINES_ENV_VAR,
+        defaults.max_lines,
+        "most lines of chat a player can send in the spam detection window",
+    );
+    let mute_durations = match std::env::var(SPAM_MUTES_ENV_VAR) {
+        Ok(mutes) => {
+            info!("Muting spammers for {} seconds", mutes);
+            let durations: Option<Vec<_>> = mutes
+                .split(',')
+                .map(|secs| secs.trim().parse().ok().map(Duration::from_secs))
+                .collect();
+            match durations {
+                Some(durations) if !durations.is_empty() => durations,
+                _ => panic!("The environment variable {SPAM_MUTES_ENV_VAR} must be a comma separated list of seconds, please fix or delete it"),
+            }
+        }
+        Err(_) => {
+            info!("Muting spammers for the default durations");
+            defaults.mute_durations
+        }
+    };
+    SpamRules {
+        window,
+        max_repeats,
+        max_lines,
+        mute_durations,
+    }
+}
+
+/// When connections are acted on for abusing the server: offences count towards a
+/// connection's score for `WORMHOLE_ABUSE_WINDOW_SECS`, and it's warned once its score
+/// reaches `WORMHOLE_ABUSE_WARN_SCORE`, refused any action for `WORMHOLE_ABUSE_LOCK_SECS` at
+/// `WORMHOLE_ABUSE_LOCK_SCORE`, and disconnected at `WORMHOLE_ABUSE_DISCONNECT_SCORE`, while
+/// frames bigger than `WORMHOLE_MAX_FRAME_BYTES` are refused
+///
+/// # Panics
+/// Panics if any setting is invalid, or the disconnect score is zero
+pub fn get_abuse_rules() -> AbuseRules {
+    let defaults = AbuseRules::default();
+    let disconnect_at = super::parse_env_var(
+        ABUSE_DISCONNECT_SCORE_ENV_VAR,
+        defaults.disconnect_at,
+        "abuse score at which connections are disconnected",
+    );
+    if disconnect_at == 0 {
+        panic!("The environment variable {ABUSE_DISCONNECT_SCORE_ENV_VAR} must be at least 1, please fix or delete it");
+    }
+    AbuseRules {
+        window: Duration::from_secs(super::parse_env_var(
+            ABUSE_WINDOW_ENV_VAR,
+            defaults.window.as_secs(),
+            "abuse scoring window in seconds",
+        )),
+        warn_at: super::parse_env_var(
+            ABUSE_WARN_SCORE_ENV_VAR,
+            defaults.warn_at,
+            "abuse score at which connections are warned",
+        ),
+        lock_at: super::parse_env_var(
+            ABUSE_LOCK_SCORE_ENV_VAR,
+            defaults.lock_at,
+            "abuse score at which connections are locked",
+        ),
+        lock_duration: Duration::from_secs(super::parse_env_var(
+            ABUSE_LOCK_ENV_VAR,
+            defaults.lock_duration.as_secs(),
+            "abuse lock duration in seconds",
+        )),
+        disconnect_at,
+        max_frame_bytes: super::parse_env_var(
+            MAX_FRAME_BYTES_ENV_VAR,
+            defaults.max_frame_bytes,
+            "biggest frame a client may send in bytes",
+        ),
+    }
+}
+
+/// How votes to kick are run: each lasts `WORMHOLE_KICK_VOTE_TIMEOUT_SECS`, passes once
+/// `WORMHOLE_KICK_VOTE_THRESHOLD_PERCENT` of the players who can vote have, and never with
+/// fewer than `WORMHOLE_KICK_VOTE_MIN_VOTES` votes, and another can't be started against the
+/// same player in the room for `WORMHOLE_KICK_VOTE_COOLDOWN_SECS` after it ends
+///
+/// # Panics
+/// Panics if any setting is invalid, or the threshold isn't between 1 and 100
+pub fn get_kick_vote_rules() -> KickVoteRules {
+    let defaults = KickVoteRules::default();
+    let threshold_percent = super::parse_env_var(
+        KICK_VOTE_THRESHOLD_ENV_VAR,
+        defaults.threshold_percent,
+        "percentage of players who have to vote to kick",
+    );
+    if !(1..=100).contains(&threshold_percent) {
+        panic!("The environment variable {KICK_VOTE_THRESHOLD_ENV_VAR} must be between 1 and 100, please fix or delete it");
+    }
+    KickVoteRules {
+        timeout: Duration::from_secs(super::parse_env_var(
+            KICK_VOTE_TIMEOUT_ENV_VAR,
+            defaults.timeout.as_secs(),
+            "kick vote timeout in seconds",
+        )),
+        threshold_percent,
+        min_votes: super::parse_env_var(
+            KICK_VOTE_MIN_VOTES_ENV_VAR,
+            defaults.min_votes,
+            "fewest votes that can kick a player",
+        ),
+        cooldown: Duration::from_secs(super::parse_env_var(
+            KICK_VOTE_COOLDOWN_ENV_VAR,
+            defaults.cooldown.as_secs(),
+            "kick vote cooldown in seconds",
+        )),
+    }
+}
@@ -0,0 +1,59 @@
+use reqwest::Url;
+use tracing::info;
+
+use super::secrets::read_secret;
+use crate::notifications::{PushDispatcher, PushTemplate};
+use crate::webhooks::RetryPolicy;
+
+const PUSH_GATEWAY_ENV_VAR: &str = "WORMHOLE_PUSH_GATEWAY_URL";
+const PUSH_GATEWAY_TOKEN_ENV_VAR: &str = "WORMHOLE_PUSH_GATEWAY_TOKEN";
+const PUSH_TEMPLATE_ENV_VAR: &str = "WORMHOLE_PUSH_TEMPLATE";
+const PUSH_MAX_ATTEMPTS_ENV_VAR: &str = "WORMHOLE_PUSH_MAX_ATTEMPTS";
+
+/// The dispatcher pushing notifications to offline players through the gateway at
+/// `WORMHOLE_PUSH_GATEWAY_URL`, or `None` if no gateway is set
+///
+/// The gateway is posted `WORMHOLE_PUSH_TEMPLATE` with its placeholders filled in, sent
+/// `WORMHOLE_PUSH_GATEWAY_TOKEN` (or the file named by `WORMHOLE_PUSH_GATEWAY_TOKEN_FILE`) as
+/// a bearer token if it's set, and each push is attempted up to `WORMHOLE_PUSH_MAX_ATTEMPTS`
+/// times.
+///
+/// # Panics
+/// Panics if the gateway URL, template or attempts are invalid
+pub fn get_push_dispatcher() -> Option<PushDispatcher> {
+    let Ok(gateway) = std::env::var(PUSH_GATEWAY_ENV_VAR) else {
+        info!(
+            "Not pushing notifications to offline players, set {} to the push gateway's URL",
+            PUSH_GATEWAY_ENV_VAR
+        );
+        return None;
+    };
+    let gateway = Url::parse(&gateway).unwrap_or_else(|_| {
+        panic!("The environment variable {PUSH_GATEWAY_ENV_VAR} is not a valid URL, please fix or delete it")
+    });
+    let template: PushTemplate = match std::env::var(PUSH_TEMPLATE_ENV_VAR) {
+        Ok(template) => template.parse().unwrap_or_else(|e| {
+            panic!("The environment variable {PUSH_TEMPLATE_ENV_VAR} is invalid: {e}, please fix or delete it")
+        }),
+        Err(_) => Default::default(),
+    };
+    let retry = RetryPolicy {
+        max_attempts: super::parse_env_var(
+            PUSH_MAX_ATTEMPTS_ENV_VAR,
+            RetryPolicy::default().max_attempts,
+            "maximum number of push attempts",
+        ),
+        ..Default::default()
+    };
+    info!(
+        "Pushing notifications to offline players through {} since {} is set",
+        gateway.host_str().unwrap_or_default(),
+        PUSH_GATEWAY_ENV_VAR
+    );
+    Some(PushDispatcher::new(
+        gateway,
+        read_secret(PUSH_GATEWAY_TOKEN_ENV_VAR),
+        template,
+        retry,
+    ))
+}
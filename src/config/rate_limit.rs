@@ -0,0 +1,46 @@
+const ROOM_CREATION_ENV_VAR: &str = "WORMHOLE_RATE_LIMIT_ROOM_CREATION_PER_MIN";
+const DEFAULT_ROOM_CREATION_PER_MIN: u32 = 60;
+const GENERAL_ENV_VAR: &str = "WORMHOLE_RATE_LIMIT_GENERAL_PER_MIN";
+const DEFAULT_GENERAL_PER_MIN: u32 = 600;
+const CHAT_ENV_VAR: &str = "WORMHOLE_RATE_LIMIT_CHAT_PER_MIN";
+const DEFAULT_CHAT_PER_MIN: u32 = 30;
+const REACTIONS_ENV_VAR: &str = "WORMHOLE_RATE_LIMIT_REACTIONS_PER_MIN";
+const DEFAULT_REACTIONS_PER_MIN: u32 = 120;
+
+/// How many rooms each client may create per minute, zero for no limit
+pub fn get_room_creation_per_minute() -> u32 {
+    super::parse_env_var(
+        ROOM_CREATION_ENV_VAR,
+        DEFAULT_ROOM_CREATION_PER_MIN,
+        "room creation rate limit per minute",
+    )
+}
+
+/// How many requests to every other REST route each client may make per minute, zero for no limit
+pub fn get_general_per_minute() -> u32 {
+    super::parse_env_var(
+        GENERAL_ENV_VAR,
+        DEFAULT_GENERAL_PER_MIN,
+        "general rate limit per minute",
+    )
+}
+
+/// How many chat messages each player, or each address for anonymous connections, may send
+/// per minute, zero for no limit
+pub fn get_chat_per_minute() -> u32 {
+    super::parse_env_var(
+        CHAT_ENV_VAR,
+        DEFAULT_CHAT_PER_MIN,
+        "chat rate limit per minute",
+    )
+}
+
+/// How many reactions each player, or each address for anonymous connections, may send per
+/// minute, zero for no limit
+pub fn get_reactions_per_minute() -> u32 {
+    super::parse_env_var(
+        REACTIONS_ENV_VAR,
+        DEFAULT_REACTIONS_PER_MIN,
+        "reaction rate limit per minute",
+    )
+}
@@ -0,0 +1,122 @@
+use std::{env::var, sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+use crate::game::{
+    DeletionOverflowPolicy, RoomIdProvider, SeededRoomIds, UuidRoomIds,
+    DEFAULT_REGISTRY_LOCK_TIMEOUT,
+};
+
+const SHARD_COUNT_ENV_VAR: &str = "WORMHOLE_REGISTRY_SHARDS";
+const DEFAULT_SHARD_COUNT: usize = 16;
+
+/// # Panics
+/// Panics if the shard count is zero
+pub fn get_shard_count() -> usize {
+    let shards = super::parse_env_var(
+        SHARD_COUNT_ENV_VAR,
+        DEFAULT_SHARD_COUNT,
+        "registry shard count",
+    );
+    if shards == 0 {
+        panic!("The environment variable {SHARD_COUNT_ENV_VAR} must be a positive integer, please fix or delete it")
+    }
+    shards
+}
+
+const LOCK_TIMEOUT_ENV_VAR: &str = "WORMHOLE_REGISTRY_LOCK_TIMEOUT_MS";
+
+/// How long request handlers wait for a registry shard's lock before answering with a `503`
+pub fn get_lock_timeout() -> Duration {
+    Duration::from_millis(super::parse_env_var(
+        LOCK_TIMEOUT_ENV_VAR,
+        DEFAULT_REGISTRY_LOCK_TIMEOUT.as_millis() as u64,
+        "registry lock timeout in milliseconds",
+    ))
+}
+
+const ROOM_ID_SEED_ENV_VAR: &str = "WORMHOLE_ROOM_ID_SEED";
+
+/// Random UUIDs, unless rooms should be given the same identifiers every run for testing
+///
+/// # Panics
+/// Panics if the seed isn't an unsigned 64-bit integer
+pub fn get_room_id_provider() -> Arc<dyn RoomIdProvider> {
+    let Ok(seed) = var(ROOM_ID_SEED_ENV_VAR) else {
+        info!("Using random UUIDs as room identifiers");
+        return Arc::new(UuidRoomIds);
+    };
+    let seed = seed.parse().unwrap_or_else(|_| {
+        panic!("The environment variable {ROOM_ID_SEED_ENV_VAR} must be an unsigned 64-bit integer, please fix or delete it")
+    });
+    warn!(
+        "Using room identifiers seeded with {} since {} is set, anyone who knows the seed can guess them",
+        seed, ROOM_ID_SEED_ENV_VAR
+    );
+    Arc::new(SeededRoomIds::new(seed))
+}
+
+const DELETION_QUEUE_CAPACITY_ENV_VAR: &str = "WORMHOLE_DELETION_QUEUE_CAPACITY";
+const DEFAULT_DELETION_QUEUE_CAPACITY: usize = 1024;
+const DELETION_BATCH_SIZE_ENV_VAR: &str = "WORMHOLE_DELETION_BATCH_SIZE";
+const DEFAULT_DELETION_BATCH_SIZE: usize = 64;
+const DELETION_OVERFLOW_POLICY_ENV_VAR: &str = "WORMHOLE_DELETION_OVERFLOW_POLICY";
+
+pub fn get_deletion_queue_capacity() -> usize {
+    super::parse_env_var(
+        DELETION_QUEUE_CAPACITY_ENV_VAR,
+        DEFAULT_DELETION_QUEUE_CAPACITY,
+        "deletion queue capacity",
+    )
+    .max(1)
+}
+
+pub fn get_deletion_batch_size() -> usize {
+    super::parse_env_var(
+        DELETION_BATCH_SIZE_ENV_VAR,
+        DEFAULT_DELETION_BATCH_SIZE,
+        "deletion batch size",
+    )
+}
+
+/// Either `grow` or `direct_delete`, see [DeletionOverflowPolicy]
+pub fn get_deletion_overflow_policy() -> DeletionOverflowPolicy {
+    super::parse_env_var(
+        DELETION_OVERFLOW_POLICY_ENV_VAR,
+        DeletionOverflowPolicy::GrowAndWarn,
+        "deletion overflow policy",
+    )
+}
+
+const CREATION_QUEUE_CAPACITY_ENV_VAR: &str = "WORMHOLE_CREATION_QUEUE_CAPACITY";
+const DEFAULT_CREATION_QUEUE_CAPACITY: usize = 256;
+const CREATION_DEADLINE_ENV_VAR: &str = "WORMHOLE_CREATION_DEADLINE_MS";
+const DEFAULT_CREATION_DEADLINE_MS: u64 = 2000;
+const CREATION_WAIT_ENV_VAR: &str = "WORMHOLE_CREATION_WAIT_MS";
+const DEFAULT_CREATION_WAIT_MS: u64 = 250;
+
+pub fn get_creation_queue_capacity() -> usize {
+    super::parse_env_var(
+        CREATION_QUEUE_CAPACITY_ENV_VAR,
+        DEFAULT_CREATION_QUEUE_CAPACITY,
+        "creation queue capacity",
+    )
+    .max(1)
+}
+
+/// How long a room creation request may sit in the queue before it is abandoned
+pub fn get_creation_deadline() -> Duration {
+    Duration::from_millis(super::parse_env_var(
+        CREATION_DEADLINE_ENV_VAR,
+        DEFAULT_CREATION_DEADLINE_MS,
+        "creation deadline in milliseconds",
+    ))
+}
+
+/// How long a room creation request waits to be handled before the client is told to poll for it
+pub fn get_creation_wait() -> Duration {
+    Duration::from_millis(super::parse_env_var(
+        CREATION_WAIT_ENV_VAR,
+        DEFAULT_CREATION_WAIT_MS,
+        "creation wait in milliseconds",
+    ))
+}
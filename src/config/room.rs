@@ -0,0 +1,218 @@
+use std::{env::var, path::PathBuf, time::Duration};
+use tracing::info;
+
+use crate::game::{
+    built_in_codec, StateCodecs, DEFAULT_REPLAY_BUFFER_LEN, DEFAULT_ROOM_MEMORY_LIMIT_BYTES,
+};
+use crate::turn_based::{TurnBasedRooms, DEFAULT_RESTORE_PARALLELISM};
+
+const MEMORY_LIMIT_ENV_VAR: &str = "WORMHOLE_ROOM_MEMORY_LIMIT_BYTES";
+
+/// # Panics
+/// Panics if the limit is zero
+pub fn get_memory_limit_bytes() -> usize {
+    let limit = super::parse_env_var(
+        MEMORY_LIMIT_ENV_VAR,
+        DEFAULT_ROOM_MEMORY_LIMIT_BYTES,
+        "room memory limit in bytes",
+    );
+    if limit == 0 {
+        panic!("The environment variable {MEMORY_LIMIT_ENV_VAR} must be a positive integer, please fix or delete it")
+    }
+    limit
+}
+
+const GAME_TYPE_ENV_VAR: &str = "WORMHOLE_GAME_TYPE";
+
+/// The kind of game the server hosts, as shown by rich presence integrations
+pub fn get_game_type() -> Option<String> {
+    match var(GAME_TYPE_ENV_VAR) {
+        Ok(game_type) => {
+            info!("Describing rooms as {} games in rich presence", game_type);
+            Some(game_type)
+        }
+        _ => {
+            info!(
+                "Not describing the game type in rich presence, set {} to do so",
+                GAME_TYPE_ENV_VAR
+            );
+            None
+        }
+    }
+}
+
+const MAX_PLAYERS_ENV_VAR: &str = "WORMHOLE_ROOM_MAX_PLAYERS";
+
+/// How many players each room can hold, or `None` for no limit
+pub fn get_max_players() -> Option<usize> {
+    match var(MAX_PLAYERS_ENV_VAR) {
+        Ok(max_players) => {
+            info!(
+                "Limiting rooms to {} players since {} is set",
+                max_players, MAX_PLAYERS_ENV_VAR
+            );
+            let max_players = max_players
+                .parse()
+                .ok()
+                .filter(|&max_players| max_players > 0)
+                .unwrap_or_else(|| panic!("The environment variable {MAX_PLAYERS_ENV_VAR} must be a positive integer, please fix or delete it"));
+            Some(max_players)
+        }
+        _ => {
+            info!(
+                "Not limiting how many players rooms hold, set {} to do so",
+                MAX_PLAYERS_ENV_VAR
+            );
+            None
+        }
+    }
+}
+
+const UNJOINED_TIMEOUT_ENV_VAR: &str = "WORMHOLE_ROOM_UNJOINED_TIMEOUT_SECS";
+const DEFAULT_UNJOINED_TIMEOUT_SECS: u64 = 60;
+
+/// How long a newly created room may go without anyone joining before it is deleted
+pub fn get_unjoined_timeout() -> Duration {
+    Duration::from_secs(super::parse_env_var(
+        UNJOINED_TIMEOUT_ENV_VAR,
+        DEFAULT_UNJOINED_TIMEOUT_SECS,
+        "unjoined room timeout in seconds",
+    ))
+}
+
+const EMPTY_TIMEOUT_ENV_VAR: &str = "WORMHOLE_ROOM_EMPTY_TIMEOUT_SECS";
+const DEFAULT_EMPTY_TIMEOUT_SECS: u64 = 300;
+
+/// How long a room may stay empty after the last connection to it closes before it is deleted
+pub fn get_empty_timeout() -> Duration {
+    Duration::from_secs(super::parse_env_var(
+        EMPTY_TIMEOUT_ENV_VAR,
+        DEFAULT_EMPTY_TIMEOUT_SECS,
+        "empty room timeout in seconds",
+    ))
+}
+
+const BROADCAST_FLUSH_INTERVAL_ENV_VAR: &str = "WORMHOLE_BROADCAST_FLUSH_INTERVAL_MS";
+const DEFAULT_BROADCAST_FLUSH_INTERVAL_MS: u64 = 0;
+
+/// How long each connection holds broadcasts back so it can write them out as a single
+/// batch frame, zero to write every broadcast immediately
+pub fn get_broadcast_flush_interval() -> Duration {
+    Duration::from_millis(super::parse_env_var(
+        BROADCAST_FLUSH_INTERVAL_ENV_VAR,
+        DEFAULT_BROADCAST_FLUSH_INTERVAL_MS,
+        "broadcast flush interval in milliseconds",
+    ))
+}
+
+const SPECTATOR_DELAY_ENV_VAR: &str = "WORMHOLE_SPECTATOR_DELAY_SECS";
+const DEFAULT_SPECTATOR_DELAY_SECS: u64 = 0;
+
+/// How far behind the players spectators are kept, so a match being streamed can't be used to
+/// tell its players what their opponents are doing, zero to let spectators watch live
+///
+/// Everything broadcast while a spectator is held behind counts against the room's memory
+/// budget until it's sent to them, so rooms with long delays and many spectators may need a
+/// bigger budget.
+pub fn get_spectator_delay() -> Duration {
+    Duration::from_secs(super::parse_env_var(
+        SPECTATOR_DELAY_ENV_VAR,
+        DEFAULT_SPECTATOR_DELAY_SECS,
+        "spectator delay in seconds",
+    ))
+}
+
+const STATE_CODEC_ENV_VAR: &str = "WORMHOLE_STATE_CODEC";
+
+/// The codec broadcasts and turn-based rooms' game states are encoded with, `json` or the more
+/// compact `msgpack`, JSON unless set
+///
+/// States saved with another codec can still be read, each being decoded by the codec that
+/// encoded it, so the codec can be changed without losing the rooms already saved.
+pub fn get_state_codecs() -> StateCodecs {
+    match var(STATE_CODEC_ENV_VAR) {
+        Ok(name) => {
+            let codec = built_in_codec(&name).unwrap_or_else(|| panic!("The environment variable {STATE_CODEC_ENV_VAR} names an unknown codec, please fix or delete it"));
+            info!(
+                "Encoding game states as {} since {} is set",
+                name, STATE_CODEC_ENV_VAR
+            );
+            StateCodecs::new(codec)
+        }
+        _ => StateCodecs::default(),
+    }
+}
+
+const REPLAY_BUFFER_LEN_ENV_VAR: &str = "WORMHOLE_REPLAY_BUFFER_LEN";
+
+/// How many of each room's latest broadcasts are kept to replay to connections catching up,
+/// zero to keep none
+pub fn get_replay_buffer_len() -> usize {
+    super::parse_env_var(
+        REPLAY_BUFFER_LEN_ENV_VAR,
+        DEFAULT_REPLAY_BUFFER_LEN,
+        "number of broadcasts kept to replay",
+    )
+}
+
+const RECORDING_DIR_ENV_VAR: &str = "WORMHOLE_RECORDING_DIR";
+
+/// The directory the traffic in every room is recorded to, or `None` to record nothing
+pub fn get_recording_dir() -> Option<PathBuf> {
+    match var(RECORDING_DIR_ENV_VAR) {
+        Ok(dir) => {
+            info!(
+                "Recording the traffic in every room to {} since {} is set",
+                dir, RECORDING_DIR_ENV_VAR
+            );
+            Some(PathBuf::from(dir))
+        }
+        _ => {
+            info!(
+                "Not recording traffic, set {} to a directory to do so",
+                RECORDING_DIR_ENV_VAR
+            );
+            None
+        }
+    }
+}
+
+const RESTORE_PARALLELISM_ENV_VAR: &str = "WORMHOLE_TURN_BASED_RESTORE_PARALLELISM";
+
+/// How many turn-based rooms are registered at once when they're restored on start
+///
+/// # Panics
+/// Panics if the parallelism is zero
+fn get_restore_parallelism() -> usize {
+    let parallelism = super::parse_env_var(
+        RESTORE_PARALLELISM_ENV_VAR,
+        DEFAULT_RESTORE_PARALLELISM,
+        "turn-based room restore parallelism",
+    );
+    if parallelism == 0 {
+        panic!("The environment variable {RESTORE_PARALLELISM_ENV_VAR} must be a positive integer, please fix or delete it")
+    }
+    parallelism
+}
+
+const TURN_BASED_ROOMS_FILE_ENV_VAR: &str = "WORMHOLE_TURN_BASED_ROOMS_FILE";
+
+/// Where turn-based rooms are kept across restarts, the file named by
+/// `WORMHOLE_TURN_BASED_ROOMS_FILE` along with a log of the changes since it was written next
+/// to it, with a `.log` extension, or nowhere if it isn't set
+pub fn get_turn_based_rooms() -> TurnBasedRooms {
+    match var(TURN_BASED_ROOMS_FILE_ENV_VAR) {
+        Ok(path) => {
+            info!("Keeping turn-based rooms in {}", path);
+            TurnBasedRooms::with_file(PathBuf::from(path))
+                .with_restore_parallelism(get_restore_parallelism())
+        }
+        _ => {
+            info!(
+                "Keeping turn-based rooms in memory only, set {} to keep them across restarts",
+                TURN_BASED_ROOMS_FILE_ENV_VAR
+            );
+            TurnBasedRooms::default()
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Loading of secrets from the environment or from files such as Docker and Kubernetes secret mounts
+
+use std::env::var;
+use std::fs;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+/// Suffix of the environment variable naming a file to read a secret from instead
+const FILE_SUFFIX: &str = "_FILE";
+
+/// A secret value that is wiped from memory when dropped and never printed
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(Zeroizing::new(value))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Reads the secret from the file named by `<env_var>_FILE` if it is set, or from `env_var` itself
+///
+/// Trailing newlines are stripped from files, since most tools that write secrets add one.
+pub fn try_read_secret(env_var: &str) -> Result<Option<Secret>, String> {
+    try_read_secret_from(env_var, |name| var(name).ok())
+}
+
+/// Like [try_read_secret], looking environment variables up with `lookup`
+fn try_read_secret_from(
+    env_var: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Secret>, String> {
+    let file_env_var = format!("{env_var}{FILE_SUFFIX}");
+    if let Some(path) = lookup(&file_env_var) {
+        let mut contents =
+            Zeroizing::new(fs::read_to_string(&path).map_err(|e| {
+                format!("The file {path} named by {file_env_var} can't be read: {e}")
+            })?);
+        let length = contents.trim_end_matches(['\r', '\n']).len();
+        contents.truncate(length);
+        return Ok(Some(Secret(contents)));
+    }
+    Ok(lookup(env_var).map(Secret::new))
+}
+
+/// Like [try_read_secret], for secrets read once at startup
+///
+/// # Panics
+/// Panics if `<env_var>_FILE` names a file that can't be read
+pub fn read_secret(env_var: &str) -> Option<Secret> {
+    try_read_secret(env_var).unwrap_or_else(|e| panic!("{e}"))
+}
+
+type Loader<T> = Box<dyn Fn() -> Result<T, String> + Send + Sync>;
+
+/// A value built from secrets that can be rebuilt while the server runs, so secrets
+/// mounted as files can be rotated by replacing the files and [reloading][Reloadable::reload]
+pub struct Reloadable<T> {
+    current: ArcSwap<T>,
+    load: Option<Loader<T>>,
+}
+
+impl<T> std::fmt::Debug for Reloadable<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Reloadable").finish_non_exhaustive()
+    }
+}
+
+impl<T> Reloadable<T> {
+    /// Starts out holding `initial`, replacing it with the result of `load` on every reload
+    pub fn new(initial: T, load: impl Fn() -> Result<T, String> + Send + Sync + 'static) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(initial),
+            load: Some(Box::new(load)),
+        }
+    }
+
+    /// Holds `value` for good, ignoring reloads
+    pub fn fixed(value: T) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(value),
+            load: None,
+        }
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Rebuilds the value, keeping the current one if that fails
+    pub fn reload(&self) {
+        let Some(load) = &self.load else {
+            return;
+        };
+        match load() {
+            Ok(value) => self.current.store(Arc::new(value)),
+            Err(e) => warn!(event = "secret_reload_failed", error = e),
+        }
+    }
+}
+
+/// Calls `reload` every time the process receives `SIGHUP`
+#[cfg(unix)]
+pub async fn reload_on_hangup(reload: impl Fn()) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!(event = "sighup_handler_unavailable", error = %e);
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!(event = "reloading_secrets");
+        reload();
+    }
+}
+
+/// Secrets can't be reloaded by signal on platforms without `SIGHUP`
+#[cfg(not(unix))]
+pub async fn reload_on_hangup(_reload: impl Fn()) {}
+
+#[cfg(test)]
+mod reload {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn replaces_value_with_reloaded_one() {
+        let loads = Arc::new(AtomicU32::new(0));
+        let counter = loads.clone();
+        let value = Reloadable::new(0, move || Ok(counter.fetch_add(1, Ordering::Relaxed) + 1));
+
+        value.reload();
+
+        assert_eq!(*value.get(), 1);
+    }
+
+    #[test]
+    fn keeps_current_value_when_reload_fails() {
+        let value = Reloadable::new(1, || Err("unreadable".to_owned()));
+
+        value.reload();
+
+        assert_eq!(*value.get(), 1);
+    }
+}
+
+#[cfg(test)]
+mod try_read_secret {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Looks environment variables up in `vars` rather than in the process's environment
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn prefers_file_and_strips_trailing_newline() {
+        let path = std::env::temp_dir().join(format!("wormhole-secret-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, "from-file\n").unwrap();
+        let lookup = env(&[
+            ("WORMHOLE_TEST_SECRET_FILE", path.to_str().unwrap()),
+            ("WORMHOLE_TEST_SECRET", "from-env"),
+        ]);
+
+        let secret = try_read_secret_from("WORMHOLE_TEST_SECRET", lookup)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(secret.expose(), "from-file");
+        assert_eq!(format!("{secret:?}"), "Secret(<redacted>)");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let lookup = env(&[("WORMHOLE_MISSING_SECRET_FILE", "/nonexistent/secret")]);
+        assert!(try_read_secret_from("WORMHOLE_MISSING_SECRET", lookup).is_err());
+    }
+
+    #[test]
+    fn falls_back_to_the_variable_itself() {
+        let lookup = env(&[("WORMHOLE_TEST_SECRET", "from-env")]);
+
+        let secret = try_read_secret_from("WORMHOLE_TEST_SECRET", lookup).unwrap();
+
+        assert_eq!(secret.unwrap().expose(), "from-env");
+        assert!(try_read_secret_from("WORMHOLE_TEST_SECRET", env(&[]))
+            .unwrap()
+            .is_none());
+    }
+}
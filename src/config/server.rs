@@ -1,5 +1,9 @@
-use std::{borrow::Cow, env::var};
-use tracing::info;
+use std::{borrow::Cow, env::var, time::Duration};
+use tracing::{info, warn};
+
+use crate::request_body::{BodyLimits, DEFAULT_BODY_LIMIT};
+use crate::request_timeout::{RequestTimeout, DEFAULT_REQUEST_TIMEOUT};
+use crate::tls::AdminTls;
 
 const HOST_ENV_VAR: &str = "WORMHOLE_HOST";
 const PORT_ENV_VAR: &str = "WORMHOLE_PORT";
@@ -31,3 +35,139 @@ pub fn get_port() -> u16 {
         }
     }
 }
+
+const ADMIN_PORT_ENV_VAR: &str = "WORMHOLE_ADMIN_PORT";
+const ADMIN_TLS_CERT_ENV_VAR: &str = "WORMHOLE_ADMIN_TLS_CERT";
+const ADMIN_TLS_KEY_ENV_VAR: &str = "WORMHOLE_ADMIN_TLS_KEY";
+const ADMIN_CLIENT_CA_ENV_VAR: &str = "WORMHOLE_ADMIN_CLIENT_CA";
+
+/// The separate listener serving the admin endpoints
+#[derive(Debug, Clone)]
+pub struct AdminListener {
+    pub port: u16,
+    /// How the listener is secured, `None` to serve plain HTTP
+    pub tls: Option<AdminTls>,
+}
+
+/// The listener admin endpoints such as bans and session revocation are moved to when
+/// `WORMHOLE_ADMIN_PORT` is set, or `None` to serve them alongside the rest of the API
+///
+/// The listener serves TLS when `WORMHOLE_ADMIN_TLS_CERT` and `WORMHOLE_ADMIN_TLS_KEY` are set,
+/// and also requires client certificates issued by `WORMHOLE_ADMIN_CLIENT_CA` when it is set.
+///
+/// # Panics
+/// Panics if the port is invalid, if only one of the certificate and key is set, or if
+/// TLS is configured without the admin port
+pub fn get_admin_listener() -> Option<AdminListener> {
+    let tls = match (
+        var(ADMIN_TLS_CERT_ENV_VAR),
+        var(ADMIN_TLS_KEY_ENV_VAR),
+        var(ADMIN_CLIENT_CA_ENV_VAR),
+    ) {
+        (Ok(cert_path), Ok(key_path), client_ca_path) => Some(AdminTls {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: client_ca_path.ok().map(Into::into),
+        }),
+        (Err(_), Err(_), Err(_)) => None,
+        (_, _, Err(_)) => panic!(
+            "The environment variables {ADMIN_TLS_CERT_ENV_VAR} and {ADMIN_TLS_KEY_ENV_VAR} must be set together, please fix or delete them"
+        ),
+        _ => panic!(
+            "The environment variable {ADMIN_CLIENT_CA_ENV_VAR} requires {ADMIN_TLS_CERT_ENV_VAR} and {ADMIN_TLS_KEY_ENV_VAR}, please set them or delete it"
+        ),
+    };
+
+    let Ok(port) = var(ADMIN_PORT_ENV_VAR) else {
+        if tls.is_some() {
+            panic!(
+                "The admin TLS settings require {ADMIN_PORT_ENV_VAR}, please set it or delete them"
+            );
+        }
+        info!(
+            "Serving admin endpoints alongside the API, set {} to move them to their own listener",
+            ADMIN_PORT_ENV_VAR
+        );
+        return None;
+    };
+    let port = port.parse().unwrap_or_else(|_| panic!("The environment variable {ADMIN_PORT_ENV_VAR} contains an invalid port, please fix or delete it"));
+    match &tls {
+        Some(AdminTls {
+            client_ca_path: Some(_),
+            ..
+        }) => info!(
+            "Serving admin endpoints on port {} over TLS, requiring client certificates issued by {}",
+            port, ADMIN_CLIENT_CA_ENV_VAR
+        ),
+        Some(_) => info!("Serving admin endpoints on port {} over TLS", port),
+        None => warn!(
+            "Serving admin endpoints on port {} without TLS, set {} and {} to secure it",
+            port, ADMIN_TLS_CERT_ENV_VAR, ADMIN_TLS_KEY_ENV_VAR
+        ),
+    }
+    Some(AdminListener { port, tls })
+}
+
+const GRPC_PORT_ENV_VAR: &str = "WORMHOLE_GRPC_PORT";
+
+/// The port the gRPC service is served on when `WORMHOLE_GRPC_PORT` is set, or `None`
+/// to not serve it
+///
+/// # Panics
+/// Panics if the port is invalid
+pub fn get_grpc_port() -> Option<u16> {
+    let Ok(port) = var(GRPC_PORT_ENV_VAR) else {
+        info!(
+            "Not serving gRPC, set {} to serve it on its own port",
+            GRPC_PORT_ENV_VAR
+        );
+        return None;
+    };
+    let port = port.parse().unwrap_or_else(|_| panic!("The environment variable {GRPC_PORT_ENV_VAR} contains an invalid port, please fix or delete it"));
+    info!("Serving gRPC on port {}", port);
+    Some(port)
+}
+
+const REQUEST_TIMEOUT_ENV_VAR: &str = "WORMHOLE_REQUEST_TIMEOUT_MS";
+
+/// How long a REST request may take before it's answered with a `504`
+pub fn get_request_timeout() -> RequestTimeout {
+    RequestTimeout(Duration::from_millis(super::parse_env_var(
+        REQUEST_TIMEOUT_ENV_VAR,
+        DEFAULT_REQUEST_TIMEOUT.as_millis() as u64,
+        "request timeout in milliseconds",
+    )))
+}
+
+const BODY_LIMIT_ENV_VAR: &str = "WORMHOLE_BODY_LIMIT_BYTES";
+const ENDPOINT_BODY_LIMITS_ENV_VAR: &str = "WORMHOLE_ENDPOINT_BODY_LIMITS";
+
+/// How large REST request bodies may be, `WORMHOLE_BODY_LIMIT_BYTES` unless the endpoint is
+/// given its own limit in `WORMHOLE_ENDPOINT_BODY_LIMITS`, as comma separated
+/// `<path>=<bytes>` pairs such as `/tournaments/=65536`
+///
+/// # Panics
+/// Panics if either setting is invalid
+pub fn get_body_limits() -> BodyLimits {
+    let mut limits = BodyLimits::new(super::parse_env_var(
+        BODY_LIMIT_ENV_VAR,
+        DEFAULT_BODY_LIMIT,
+        "request body limit in bytes",
+    ));
+    let Ok(endpoints) = var(ENDPOINT_BODY_LIMITS_ENV_VAR) else {
+        return limits;
+    };
+    for entry in endpoints
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+    {
+        let (path, limit) = entry
+            .split_once('=')
+            .and_then(|(path, limit)| Some((path.trim(), limit.trim().parse().ok()?)))
+            .unwrap_or_else(|| panic!("The environment variable {ENDPOINT_BODY_LIMITS_ENV_VAR} contains an invalid limit {entry:?}, please fix or delete it"));
+        info!("Limiting bodies sent to {} to {} bytes", path, limit);
+        limits = limits.with_endpoint_limit(path, limit);
+    }
+    limits
+}
@@ -0,0 +1,56 @@
+use tracing::info;
+
+use super::secrets::read_secret;
+use crate::webhooks::{RetryPolicy, WebhookDispatcher, WebhookSigner, WebhookSubscriptions};
+
+const WEBHOOKS_ENV_VAR: &str = "WORMHOLE_WEBHOOKS";
+const WEBHOOK_SECRET_ENV_VAR: &str = "WORMHOLE_WEBHOOK_SECRET";
+const WEBHOOK_MAX_ATTEMPTS_ENV_VAR: &str = "WORMHOLE_WEBHOOK_MAX_ATTEMPTS";
+
+/// The dispatcher delivering events to the endpoints subscribed in `WORMHOLE_WEBHOOKS`, as
+/// `;` separated `<events>=<url>` entries, and to the webhooks rooms are created with, or
+/// `None` if there's no secret to sign deliveries with
+///
+/// Deliveries are signed with `WORMHOLE_WEBHOOK_SECRET` (or the file named by
+/// `WORMHOLE_WEBHOOK_SECRET_FILE`), and attempted up to `WORMHOLE_WEBHOOK_MAX_ATTEMPTS` times.
+/// With a secret but no subscriptions, only rooms' own webhooks are delivered.
+///
+/// # Panics
+/// Panics if the subscriptions or attempts are invalid, or if endpoints are subscribed
+/// without a secret
+pub fn get_webhook_dispatcher() -> Option<WebhookDispatcher> {
+    let subscriptions: WebhookSubscriptions = match std::env::var(WEBHOOKS_ENV_VAR) {
+        Ok(subscriptions) => subscriptions.parse().unwrap_or_else(|e| {
+            panic!("The environment variable {WEBHOOKS_ENV_VAR} is invalid: {e}, please fix or delete it")
+        }),
+        Err(_) => Default::default(),
+    };
+    let Some(secret) = read_secret(WEBHOOK_SECRET_ENV_VAR) else {
+        if !subscriptions.is_empty() {
+            panic!("Webhooks are subscribed in {WEBHOOKS_ENV_VAR} without a secret to sign them with, please set {WEBHOOK_SECRET_ENV_VAR}")
+        }
+        info!(
+            "Not delivering webhooks, set {} and {} to subscribe endpoints",
+            WEBHOOK_SECRET_ENV_VAR, WEBHOOKS_ENV_VAR
+        );
+        return None;
+    };
+    let retry = RetryPolicy {
+        max_attempts: super::parse_env_var(
+            WEBHOOK_MAX_ATTEMPTS_ENV_VAR,
+            RetryPolicy::default().max_attempts,
+            "maximum number of webhook delivery attempts",
+        ),
+        ..Default::default()
+    };
+    info!(
+        "Delivering webhooks to rooms' own endpoints and to {} endpoints subscribed in {}",
+        subscriptions.0.len(),
+        WEBHOOKS_ENV_VAR
+    );
+    Some(WebhookDispatcher::new(
+        subscriptions,
+        WebhookSigner::new(secret.expose().as_bytes()),
+        retry,
+    ))
+}
@@ -0,0 +1,377 @@
+//! Filtering of the text players show each other, their chat and display names
+//!
+//! Text is passed through each [ContentFilter] of the [ContentFilters] in turn, each seeing
+//! what the one before it let through. The built-in [WordListFilter] masks blocked words,
+//! seeing through leetspeak, and the [ModerationApiFilter] asks an outside moderation service,
+//! which may take a while, so filtering never blocks the connection's actor.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::secrets::Secret;
+
+/// What the text being filtered is
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Chat,
+    DisplayName,
+}
+
+/// What a filter made of a piece of text
+#[derive(Debug, PartialEq, Clone)]
+pub enum Verdict {
+    /// The text can be shown, possibly changed, such as with words masked
+    Allow(String),
+    /// The text can't be shown at all
+    Reject { reason: String },
+}
+
+pub type FilterFuture<'a> = Pin<Box<dyn Future<Output = Verdict> + Send + 'a>>;
+
+/// Decides whether text can be shown to other players, and how
+pub trait ContentFilter: std::fmt::Debug + Send + Sync {
+    fn filter<'a>(&'a self, text: String, kind: ContentKind) -> FilterFuture<'a>;
+}
+
+/// The filters every piece of text goes through, in order
+#[derive(Debug, Default)]
+pub struct ContentFilters {
+    filters: Vec<Box<dyn ContentFilter>>,
+}
+
+impl ContentFilters {
+    pub fn with_filter(mut self, filter: impl ContentFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Passes the text through every filter, stopping at the first to reject it
+    pub async fn filter(&self, mut text: String, kind: ContentKind) -> Verdict {
+        for filter in &self.filters {
+            match filter.filter(text, kind).await {
+                Verdict::Allow(allowed) => text = allowed,
+                rejected @ Verdict::Reject { .. } => return rejected,
+            }
+        }
+        Verdict::Allow(text)
+    }
+}
+
+/// The letter each character commonly stands in for in leetspeak
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c,
+    }
+}
+
+/// Masks blocked words with asterisks
+///
+/// Words are matched whole and ignoring case, so blocking "ass" leaves "class" alone. Unless
+/// leetspeak is turned off, digits and symbols standing in for letters are read as those
+/// letters, so blocking "heck" masks "h3ck" too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordListFilter {
+    blocked: Vec<String>,
+    leetspeak: bool,
+}
+
+impl WordListFilter {
+    /// Creates a filter masking each of `words`, ignoring blank ones
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(words: I) -> Self {
+        Self {
+            blocked: words
+                .into_iter()
+                .map(|word| word.as_ref().trim().to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect(),
+            leetspeak: true,
+        }
+    }
+
+    /// Whether digits and symbols are read as the letters they stand in for
+    pub fn with_leetspeak(mut self, leetspeak: bool) -> Self {
+        self.leetspeak = leetspeak;
+        self
+    }
+
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || (self.leetspeak && unleet(c) != c)
+    }
+
+    /// The text with every blocked word masked
+    pub fn censor(&self, text: &str) -> String {
+        let mut censored = String::with_capacity(text.len());
+        let mut word_start = None;
+        for (i, c) in text.char_indices() {
+            match (self.is_word_char(c), word_start) {
+                (true, None) => word_start = Some(i),
+                (false, Some(start)) => {
+                    self.push_word(&mut censored, &text[start..i]);
+                    word_start = None;
+                    censored.push(c);
+                }
+                (false, None) => censored.push(c),
+                (true, Some(_)) => {}
+            }
+        }
+        if let Some(start) = word_start {
+            self.push_word(&mut censored, &text[start..]);
+        }
+        censored
+    }
+
+    fn is_blocked(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        if self.blocked.contains(&word) {
+            return true;
+        }
+        if !self.leetspeak {
+            return false;
+        }
+        self.blocked
+            .contains(&word.chars().map(unleet).collect::<String>())
+    }
+
+    fn push_word(&self, out: &mut String, word: &str) {
+        if self.is_blocked(word) {
+            out.extend(word.chars().map(|_| '*'));
+        } else {
+            out.push_str(word);
+        }
+    }
+}
+
+impl<F: ContentFilter + ?Sized> ContentFilter for Arc<F> {
+    fn filter<'a>(&'a self, text: String, kind: ContentKind) -> FilterFuture<'a> {
+        (**self).filter(text, kind)
+    }
+}
+
+impl ContentFilter for WordListFilter {
+    fn filter<'a>(&'a self, text: String, _kind: ContentKind) -> FilterFuture<'a> {
+        Box::pin(std::future::ready(Verdict::Allow(self.censor(&text))))
+    }
+}
+
+/// What's sent to the moderation service
+#[derive(Debug, Serialize)]
+struct ModerationRequest<'a> {
+    text: &'a str,
+    kind: ContentKind,
+}
+
+/// What the moderation service answers with
+#[derive(Debug, PartialEq, Deserialize)]
+struct ModerationResponse {
+    allowed: bool,
+    /// The text to show instead, such as with words masked
+    text: Option<String>,
+    /// Why the text isn't allowed
+    reason: Option<String>,
+}
+
+impl ModerationResponse {
+    fn verdict(self, text: String) -> Verdict {
+        match self {
+            ModerationResponse {
+                allowed: true,
+                text: rewritten,
+                ..
+            } => Verdict::Allow(rewritten.unwrap_or(text)),
+            ModerationResponse { reason, .. } => Verdict::Reject {
+                reason: reason.unwrap_or_else(|| "The text isn't allowed".to_owned()),
+            },
+        }
+    }
+}
+
+/// Asks an outside moderation service about every piece of text
+///
+/// The text is POSTed to the service as `{"text": ..., "kind": "chat" | "display_name"}`,
+/// which answers with `{"allowed": bool, "text": ..., "reason": ...}`, `text` replacing the
+/// text when it's allowed and `reason` saying why when it isn't. Text is let through as is
+/// when the service can't be reached or doesn't answer in time, so an outage doesn't silence
+/// every room.
+#[derive(Debug)]
+pub struct ModerationApiFilter {
+    url: String,
+    token: Option<Secret>,
+    client: reqwest::Client,
+}
+
+impl ModerationApiFilter {
+    /// Creates a filter asking the service at `url`, giving up on it after `timeout`
+    pub fn new(url: String, timeout: Duration) -> Self {
+        Self {
+            url,
+            token: None,
+            client: reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("The moderation API client can always be built"),
+        }
+    }
+
+    /// Sends the token as a bearer token with every request
+    pub fn with_token(mut self, token: Secret) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    async fn ask(&self, text: &str, kind: ContentKind) -> reqwest::Result<ModerationResponse> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .json(&ModerationRequest { text, kind });
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose());
+        }
+        request.send().await?.error_for_status()?.json().await
+    }
+}
+
+impl ContentFilter for ModerationApiFilter {
+    fn filter<'a>(&'a self, text: String, kind: ContentKind) -> FilterFuture<'a> {
+        Box::pin(async move {
+            match self.ask(&text, kind).await {
+                Ok(response) => {
+                    let verdict = response.verdict(text);
+                    debug!(event = "content_moderated", ?kind, ?verdict);
+                    verdict
+                }
+                Err(e) => {
+                    warn!(event = "moderation_api_unavailable", error = %e);
+                    Verdict::Allow(text)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod censor {
+    use super::*;
+
+    #[test]
+    fn masks_whole_words_ignoring_case() {
+        let filter = WordListFilter::new(["darn", " heck "]);
+
+        assert_eq!(
+            filter.censor("Darn it, what the HECK!"),
+            "**** it, what the ****!"
+        );
+    }
+
+    #[test]
+    fn leaves_words_containing_blocked_ones_alone() {
+        let filter = WordListFilter::new(["ass"]);
+
+        assert_eq!(filter.censor("first class pass"), "first class pass");
+        assert_eq!(filter.censor("ass"), "***");
+    }
+
+    #[test]
+    fn sees_through_leetspeak() {
+        let filter = WordListFilter::new(["heck", "ass"]);
+
+        assert_eq!(filter.censor("h3ck, @$$!"), "****, ***!");
+        assert_eq!(filter.censor("1337 4 life"), "1337 4 life");
+        assert_eq!(
+            filter.with_leetspeak(false).censor("h3ck, @$$!"),
+            "h3ck, @$$!"
+        );
+    }
+}
+
+#[cfg(test)]
+mod content_filters {
+    use super::*;
+
+    #[derive(Debug)]
+    struct RejectAll;
+
+    impl ContentFilter for RejectAll {
+        fn filter<'a>(&'a self, _text: String, _kind: ContentKind) -> FilterFuture<'a> {
+            Box::pin(std::future::ready(Verdict::Reject {
+                reason: "No".to_owned(),
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_the_text_through_each_filter_in_turn() {
+        let filters = ContentFilters::default()
+            .with_filter(WordListFilter::new(["darn"]))
+            .with_filter(WordListFilter::new(["heck"]));
+
+        assert_eq!(
+            filters
+                .filter("darn heck".to_owned(), ContentKind::Chat)
+                .await,
+            Verdict::Allow("**** ****".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_at_the_first_rejection() {
+        let filters = ContentFilters::default().with_filter(RejectAll);
+
+        assert!(matches!(
+            filters
+                .filter("hi".to_owned(), ContentKind::DisplayName)
+                .await,
+            Verdict::Reject { .. }
+        ));
+    }
+}
+
+#[cfg(test)]
+mod moderation_api_filter {
+    use super::*;
+
+    #[test]
+    fn follows_the_services_verdict() {
+        let response = |json| serde_json::from_value::<ModerationResponse>(json).unwrap();
+
+        assert_eq!(
+            response(serde_json::json!({ "allowed": true })).verdict("hi".to_owned()),
+            Verdict::Allow("hi".to_owned())
+        );
+        assert_eq!(
+            response(serde_json::json!({ "allowed": true, "text": "h*" })).verdict("hi".to_owned()),
+            Verdict::Allow("h*".to_owned())
+        );
+        assert_eq!(
+            response(serde_json::json!({ "allowed": false, "reason": "Spam" }))
+                .verdict("hi".to_owned()),
+            Verdict::Reject {
+                reason: "Spam".to_owned()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn lets_text_through_when_the_service_is_down() {
+        let filter = ModerationApiFilter::new(
+            "http://127.0.0.1:9/moderate".to_owned(),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(
+            filter.filter("hi".to_owned(), ContentKind::Chat).await,
+            Verdict::Allow("hi".to_owned())
+        );
+    }
+}
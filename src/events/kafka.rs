@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use serde::Serialize;
+use tracing::warn;
+
+use super::EventSink;
+use crate::clock::unix_time;
+use crate::webhooks::{WebhookEvent, WebhookEventKind};
+
+/// What topic telemetry is published to unless configured otherwise
+pub const DEFAULT_KAFKA_TOPIC: &str = "wormhole.telemetry";
+
+/// The version of the telemetry payloads, bumped whenever a field is changed or removed
+pub const TELEMETRY_SCHEMA_VERSION: u32 = 1;
+
+/// How long a record may wait in the producer's queue for the broker before it's dropped
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The schema records of the kind are tagged with, such as `wormhole.game_finished.v1`
+pub fn telemetry_schema(kind: WebhookEventKind) -> String {
+    format!("wormhole.{kind}.v{TELEMETRY_SCHEMA_VERSION}")
+}
+
+/// The room a game telemetry event happened in, which keys its record so a room's events stay
+/// in order on one partition, or `None` for moderation and social activity which is left to
+/// the other sinks
+fn telemetry_key(event: &WebhookEvent) -> Option<String> {
+    match event {
+        WebhookEvent::RoomCreated { room_id } | WebhookEvent::GameFinished { room_id } => {
+            Some(room_id.to_string())
+        }
+        WebhookEvent::PlayerBanned { .. }
+        | WebhookEvent::PresenceUpdated(_)
+        | WebhookEvent::RoomOpened(_)
+        | WebhookEvent::PlayerAutoMuted(_) => None,
+    }
+}
+
+/// The value of a record, the event's webhook body tagged with [its schema][telemetry_schema]
+#[derive(Debug, Serialize)]
+struct TelemetryRecord<'a> {
+    schema: String,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// Seconds since the Unix epoch
+    occurred_at: u64,
+}
+
+impl<'a> TelemetryRecord<'a> {
+    fn new(event: &'a WebhookEvent) -> Self {
+        Self {
+            schema: telemetry_schema(event.kind()),
+            event,
+            occurred_at: unix_time(),
+        }
+    }
+}
+
+/// Publishes rooms being created and games finishing to a Kafka topic as
+/// [schema tagged][telemetry_schema] JSON, for data pipelines to consume without the server
+/// knowing where they lead
+///
+/// Publishing is fire and forget, records are queued by the producer while the brokers are
+/// unreachable and dropped if they can't be delivered within 30 seconds.
+#[derive(Clone)]
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl std::fmt::Debug for KafkaSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KafkaSink {
+    /// Creates a producer for the comma separated `brokers`, which connects to them in the
+    /// background
+    pub fn new(brokers: &str, topic: String) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("client.id", "wormhole")
+            .set(
+                "message.timeout.ms",
+                DELIVERY_TIMEOUT.as_millis().to_string(),
+            )
+            .create()?;
+        Ok(Self { producer, topic })
+    }
+}
+
+impl EventSink for KafkaSink {
+    fn publish(&self, event: &WebhookEvent) {
+        let Some(key) = telemetry_key(event) else {
+            return;
+        };
+        let payload = serde_json::to_vec(&TelemetryRecord::new(event))
+            .expect("Telemetry records always serialize");
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        tokio::spawn(async move {
+            let record = FutureRecord::to(&topic).key(&key).payload(&payload);
+            if let Err((e, _)) = producer.send(record, DELIVERY_TIMEOUT).await {
+                warn!(event = "kafka_publish_failed", topic, error = %e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod telemetry_schema {
+    use super::*;
+
+    #[test]
+    fn names_the_kind_and_version() {
+        assert_eq!(
+            telemetry_schema(WebhookEventKind::GameFinished),
+            "wormhole.game_finished.v1"
+        );
+    }
+}
+
+#[cfg(test)]
+mod telemetry_record {
+    use super::*;
+    use crate::game::RoomId;
+
+    #[test]
+    fn tags_the_webhook_body_with_its_schema() {
+        let room_id = RoomId::from(7);
+        let event = WebhookEvent::RoomCreated { room_id };
+        let record = serde_json::to_value(TelemetryRecord::new(&event)).unwrap();
+
+        assert_eq!(record["schema"], "wormhole.room_created.v1");
+        assert_eq!(record["event"], "room_created");
+        assert_eq!(record["room_id"], room_id.to_string());
+        assert!(record["occurred_at"].as_u64().unwrap() > 0);
+    }
+}
+
+#[cfg(test)]
+mod telemetry_key {
+    use super::*;
+    use crate::game::RoomId;
+
+    #[test]
+    fn keys_games_by_their_room() {
+        let room_id = RoomId::from(7);
+        let event = WebhookEvent::GameFinished { room_id };
+
+        assert_eq!(telemetry_key(&event), Some(room_id.to_string()));
+    }
+}
@@ -0,0 +1,118 @@
+//! The bus carrying events on the server, such as rooms being created and games finishing,
+//! out to the services around it
+//!
+//! Lobby changes, issued bans, automatic mutes, presence updates and scheduled rooms opening
+//! are turned into
+//! [events][WebhookEvent]
+//! and [forwarded][forward_events] to every configured [EventSink]: the
+//! [webhook dispatcher][crate::webhooks::WebhookDispatcher] delivering them to subscribed
+//! endpoints, the [NATS sink][NatsSink] publishing them for any service on the platform
+//! to subscribe to, and with the `kafka` feature the `KafkaSink` publishing game telemetry for
+//! data pipelines.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+mod nats;
+
+#[cfg(feature = "kafka")]
+pub use kafka::*;
+pub use nats::*;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tracing::{info, warn};
+
+use crate::bans::Ban;
+use crate::game::LobbyEvent;
+use crate::presence::Presence;
+use crate::scheduling::RoomOpened;
+use crate::spam::AutoMute;
+use crate::webhooks::WebhookEvent;
+
+/// Somewhere events are sent as they happen
+pub trait EventSink: Send + Sync {
+    /// Starts sending the event on, without waiting for it to arrive
+    fn publish(&self, event: &WebhookEvent);
+}
+
+/// Publishes lobby changes, issued bans, presence updates, scheduled rooms opening and
+/// automatic mutes to every sink until every feed closes
+pub async fn forward_events(
+    sinks: Vec<Box<dyn EventSink>>,
+    mut lobby: Receiver<LobbyEvent>,
+    mut bans: Receiver<Ban>,
+    mut presence: Receiver<Presence>,
+    mut opened: Receiver<RoomOpened>,
+    mut muted: Receiver<AutoMute>,
+) {
+    let mut lobby_open = true;
+    let mut bans_open = true;
+    let mut presence_open = true;
+    let mut opened_open = true;
+    let mut muted_open = true;
+    while lobby_open || bans_open || presence_open || opened_open || muted_open {
+        let event = tokio::select! {
+            event = lobby.recv(), if lobby_open => match event {
+                Ok(event) => WebhookEvent::from_lobby(event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(event = "events_missed", feed = "lobby", missed);
+                    None
+                }
+                Err(RecvError::Closed) => {
+                    lobby_open = false;
+                    None
+                }
+            },
+            ban = bans.recv(), if bans_open => match ban {
+                Ok(ban) => Some(WebhookEvent::PlayerBanned { ban }),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(event = "events_missed", feed = "bans", missed);
+                    None
+                }
+                Err(RecvError::Closed) => {
+                    bans_open = false;
+                    None
+                }
+            },
+            presence = presence.recv(), if presence_open => match presence {
+                Ok(presence) => Some(WebhookEvent::PresenceUpdated(presence)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(event = "events_missed", feed = "presence", missed);
+                    None
+                }
+                Err(RecvError::Closed) => {
+                    presence_open = false;
+                    None
+                }
+            },
+            room = opened.recv(), if opened_open => match room {
+                Ok(room) => Some(WebhookEvent::RoomOpened(room)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(event = "events_missed", feed = "opened_rooms", missed);
+                    None
+                }
+                Err(RecvError::Closed) => {
+                    opened_open = false;
+                    None
+                }
+            },
+            mute = muted.recv(), if muted_open => match mute {
+                Ok(mute) => Some(WebhookEvent::PlayerAutoMuted(mute)),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(event = "events_missed", feed = "auto_mutes", missed);
+                    None
+                }
+                Err(RecvError::Closed) => {
+                    muted_open = false;
+                    None
+                }
+            },
+        };
+        if let Some(event) = event {
+            for sink in &sinks {
+                sink.publish(&event);
+            }
+        }
+    }
+    info!(event = "event_forwarding_stopped");
+}
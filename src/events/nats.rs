@@ -0,0 +1,92 @@
+use async_nats::{Client, ConnectError, ConnectOptions};
+use tracing::warn;
+
+use super::EventSink;
+use crate::webhooks::{WebhookEvent, WebhookEventKind};
+
+/// What subjects events are published under unless configured otherwise
+pub const DEFAULT_NATS_SUBJECT_PREFIX: &str = "wormhole.events";
+
+/// The subject events of the kind are published to, such as `wormhole.events.room_created`
+pub fn nats_subject(prefix: &str, kind: WebhookEventKind) -> String {
+    format!("{prefix}.{kind}")
+}
+
+/// Whether events can be published under the prefix, which must be dot separated tokens
+/// without whitespace or wildcards
+pub fn is_valid_subject_prefix(prefix: &str) -> bool {
+    prefix.split('.').all(|token| {
+        !token.is_empty()
+            && !token
+                .chars()
+                .any(|c| c.is_whitespace() || c == '*' || c == '>')
+    })
+}
+
+/// Publishes events to NATS as the same JSON bodies webhook endpoints receive, each kind to
+/// [its own subject][nats_subject]
+///
+/// Publishing is fire and forget, events that happen while the server is unreachable are
+/// buffered by the client until it reconnects or its buffer fills up.
+#[derive(Debug, Clone)]
+pub struct NatsSink {
+    client: Client,
+    prefix: String,
+}
+
+impl NatsSink {
+    /// Connects to the NATS server at `url`, retrying in the background if it can't be
+    /// reached yet
+    pub async fn connect(url: &str, prefix: String) -> Result<Self, ConnectError> {
+        let client = ConnectOptions::new()
+            .name("wormhole")
+            .retry_on_initial_connect()
+            .connect(url)
+            .await?;
+        Ok(Self { client, prefix })
+    }
+}
+
+impl EventSink for NatsSink {
+    fn publish(&self, event: &WebhookEvent) {
+        let subject = nats_subject(&self.prefix, event.kind());
+        let client = self.client.clone();
+        let body = event.to_json();
+        tokio::spawn(async move {
+            if let Err(e) = client.publish(subject.clone(), body.into()).await {
+                warn!(event = "nats_publish_failed", subject, error = %e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod nats_subject {
+    use super::*;
+
+    #[test]
+    fn puts_the_kind_after_the_prefix() {
+        assert_eq!(
+            nats_subject(DEFAULT_NATS_SUBJECT_PREFIX, WebhookEventKind::GameFinished),
+            "wormhole.events.game_finished"
+        );
+    }
+}
+
+#[cfg(test)]
+mod is_valid_subject_prefix {
+    use super::*;
+
+    #[test]
+    fn accepts_dot_separated_tokens() {
+        assert!(is_valid_subject_prefix(DEFAULT_NATS_SUBJECT_PREFIX));
+        assert!(is_valid_subject_prefix("games"));
+    }
+
+    #[test]
+    fn rejects_empty_tokens_whitespace_and_wildcards() {
+        for prefix in ["", "wormhole.", "a..b", "a b", "wormhole.*", "wormhole.>"] {
+            assert!(!is_valid_subject_prefix(prefix), "{prefix:?}");
+        }
+    }
+}
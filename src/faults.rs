@@ -0,0 +1,334 @@
+//! Hooks for injecting faults into the server, so tests can exercise how it recovers
+//!
+//! With the `fault-injection` feature, a [FaultInjector] [installed][install] for the whole
+//! process is asked at each hook whether to fail there: before a room's deletion request is
+//! sent, before a registry shard's lock is taken, before a write to Redis and before a
+//! connection handles a frame from its client. Without the feature the hooks compile to
+//! nothing.
+//!
+//! Injectors apply to every server in the process, so tests that run alongside others should
+//! only fail the rooms and connections they created, matching their IDs, rather than install a
+//! [RandomFaults].
+
+#[cfg(feature = "fault-injection")]
+pub use injection::*;
+
+#[cfg(feature = "fault-injection")]
+use tracing::warn;
+
+use crate::game::{ConnectionId, RoomId};
+
+/// Whether the room's deletion request should be dropped instead of sent to the handler
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn drop_deletion_request(room_id: RoomId) -> bool {
+    #[cfg(feature = "fault-injection")]
+    if injector().is_some_and(|injector| injector.drop_deletion_request(room_id)) {
+        warn!(event = "fault_injected", fault = "dropped_deletion_request", %room_id);
+        return true;
+    }
+    false
+}
+
+/// Stalls the calling thread before it takes the lock named `lock`, if the injector says to
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn delay_lock(lock: &'static str) {
+    #[cfg(feature = "fault-injection")]
+    if let Some(delay) = injector().and_then(|injector| injector.lock_delay(lock)) {
+        warn!(event = "fault_injected", fault = "lock_delay", lock, ?delay);
+        std::thread::sleep(delay);
+    }
+}
+
+/// Whether the write to `store` should fail as though the store were unreachable
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn fail_storage_write(store: &'static str) -> bool {
+    #[cfg(feature = "fault-injection")]
+    if injector().is_some_and(|injector| injector.fail_storage_write(store)) {
+        warn!(
+            event = "fault_injected",
+            fault = "storage_write_failed",
+            store
+        );
+        return true;
+    }
+    false
+}
+
+/// Whether the connection to the room should be dropped, without a close frame, instead of
+/// handling the frame it just received
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn disconnect(room_id: RoomId, connection_id: ConnectionId) -> bool {
+    #[cfg(feature = "fault-injection")]
+    if injector().is_some_and(|injector| injector.disconnect(room_id, connection_id)) {
+        warn!(event = "fault_injected", fault = "disconnect", %room_id, %connection_id);
+        return true;
+    }
+    false
+}
+
+#[cfg(feature = "fault-injection")]
+mod injection {
+    use std::fmt::Debug;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+
+    use tracing::warn;
+
+    use crate::game::{ConnectionId, RoomId};
+
+    static INJECTOR: RwLock<Option<Arc<dyn FaultInjector>>> = RwLock::new(None);
+
+    /// Decides where the server fails, every fault being off unless overridden
+    pub trait FaultInjector: Debug + Send + Sync {
+        /// Whether the room's deletion request should be dropped instead of sent to the
+        /// handler, leaving the room in the registry until it's requested again
+        fn drop_deletion_request(&self, room_id: RoomId) -> bool {
+            let _ = room_id;
+            false
+        }
+
+        /// How long to stall before taking the lock named `lock`, such as `registry_shard`
+        fn lock_delay(&self, lock: &'static str) -> Option<Duration> {
+            let _ = lock;
+            None
+        }
+
+        /// Whether the write to `store`, such as `redis`, should fail
+        fn fail_storage_write(&self, store: &'static str) -> bool {
+            let _ = store;
+            false
+        }
+
+        /// Whether the connection to the room should be dropped instead of handling its
+        /// client's frame
+        fn disconnect(&self, room_id: RoomId, connection_id: ConnectionId) -> bool {
+            let _ = (room_id, connection_id);
+            false
+        }
+    }
+
+    /// Uninstalls the injector it was returned with when dropped
+    #[must_use = "The injector is uninstalled as soon as the guard is dropped"]
+    #[derive(Debug)]
+    pub struct FaultGuard(());
+
+    impl Drop for FaultGuard {
+        fn drop(&mut self) {
+            *INJECTOR.write().unwrap() = None;
+        }
+    }
+
+    /// Has the hooks ask `injector` whether to fail until the returned guard is dropped,
+    /// replacing any injector already installed
+    pub fn install(injector: Arc<dyn FaultInjector>) -> FaultGuard {
+        warn!(event = "fault_injector_installed", ?injector);
+        *INJECTOR.write().unwrap() = Some(injector);
+        FaultGuard(())
+    }
+
+    pub(super) fn injector() -> Option<Arc<dyn FaultInjector>> {
+        INJECTOR.read().unwrap().clone()
+    }
+
+    /// Injects each fault at random with its own probability, drawing from a seeded sequence
+    /// so a failing run can be repeated
+    #[derive(Debug)]
+    pub struct RandomFaults {
+        state: AtomicU64,
+        deletion_drop_rate: f64,
+        lock_delay_rate: f64,
+        lock_delay: Duration,
+        storage_failure_rate: f64,
+        disconnect_rate: f64,
+    }
+
+    impl RandomFaults {
+        /// Injects nothing until given a rate for each fault
+        pub fn new(seed: u64) -> Self {
+            Self {
+                state: AtomicU64::new(seed),
+                deletion_drop_rate: 0.0,
+                lock_delay_rate: 0.0,
+                lock_delay: Duration::ZERO,
+                storage_failure_rate: 0.0,
+                disconnect_rate: 0.0,
+            }
+        }
+
+        /// Drops each deletion request with probability `rate`
+        pub fn with_deletion_drop_rate(mut self, rate: f64) -> Self {
+            self.deletion_drop_rate = rate;
+            self
+        }
+
+        /// Stalls for `delay` before taking each lock with probability `rate`
+        pub fn with_lock_delay(mut self, rate: f64, delay: Duration) -> Self {
+            self.lock_delay_rate = rate;
+            self.lock_delay = delay;
+            self
+        }
+
+        /// Fails each storage write with probability `rate`
+        pub fn with_storage_failure_rate(mut self, rate: f64) -> Self {
+            self.storage_failure_rate = rate;
+            self
+        }
+
+        /// Drops each connection with probability `rate` as it receives a frame
+        pub fn with_disconnect_rate(mut self, rate: f64) -> Self {
+            self.disconnect_rate = rate;
+            self
+        }
+
+        /// Whether an event with probability `rate` happens, drawing the next number from the
+        /// seed's [SplitMix64](https://prng.di.unimi.it/splitmix64.c) sequence
+        fn happens(&self, rate: f64) -> bool {
+            if rate <= 0.0 {
+                return false;
+            }
+            let mut z = self
+                .state
+                .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+                .wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            ((z >> 11) as f64 / (1_u64 << 53) as f64) < rate
+        }
+    }
+
+    impl FaultInjector for RandomFaults {
+        fn drop_deletion_request(&self, _: RoomId) -> bool {
+            self.happens(self.deletion_drop_rate)
+        }
+
+        fn lock_delay(&self, _: &'static str) -> Option<Duration> {
+            self.happens(self.lock_delay_rate)
+                .then_some(self.lock_delay)
+        }
+
+        fn fail_storage_write(&self, _: &'static str) -> bool {
+            self.happens(self.storage_failure_rate)
+        }
+
+        fn disconnect(&self, _: RoomId, _: ConnectionId) -> bool {
+            self.happens(self.disconnect_rate)
+        }
+    }
+}
+
+/// Keeps tests that install an injector from replacing each other's
+#[cfg(all(test, feature = "fault-injection"))]
+static INSTALLING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[cfg(all(test, feature = "fault-injection"))]
+mod drop_deletion_request {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy, RoomRegistry};
+
+    #[derive(Debug)]
+    struct DropRequestsFor(RoomId);
+
+    impl FaultInjector for DropRequestsFor {
+        fn drop_deletion_request(&self, room_id: RoomId) -> bool {
+            room_id == self.0
+        }
+    }
+
+    #[test]
+    fn leaves_the_room_until_its_deletion_is_requested_again() {
+        let _installing = INSTALLING.blocking_lock();
+        let registry = Arc::new(RoomRegistry::new(4));
+        let [dropped, deleted] = [(); 2].map(|_| registry.create_room().unwrap());
+        // Without a handler the queue deletes rooms as they're requested
+        let (queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+
+        let faults = install(Arc::new(DropRequestsFor(dropped)));
+        queue.request_deletion(dropped);
+        queue.request_deletion(deleted);
+        assert!(registry.get_room_for_id(dropped).is_some());
+        assert!(registry.get_room_for_id(deleted).is_none());
+
+        drop(faults);
+        queue.request_deletion(dropped);
+        assert_eq!(registry.room_count(), 0);
+    }
+}
+
+#[cfg(all(test, feature = "fault-injection"))]
+mod random_faults {
+    use super::*;
+
+    #[test]
+    fn repeats_the_same_faults_for_the_same_seed() {
+        let draws = |seed| {
+            let faults = RandomFaults::new(seed).with_storage_failure_rate(0.5);
+            (0..64)
+                .map(|_| faults.fail_storage_write("redis"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(draws(1), draws(1));
+        assert_ne!(draws(1), draws(2));
+        assert!(draws(1).contains(&true) && draws(1).contains(&false));
+    }
+
+    #[test]
+    fn injects_nothing_by_default() {
+        let faults = RandomFaults::new(1);
+
+        assert!(!faults.drop_deletion_request(0_u128.into()));
+        assert_eq!(faults.lock_delay("registry_shard"), None);
+        assert!(!faults.disconnect(0_u128.into(), 0.into()));
+    }
+}
+
+#[cfg(all(test, feature = "fault-injection", feature = "test-support"))]
+mod disconnect {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::protocol::{ClientMessage, ServerMessage};
+    use crate::test_support::TestServer;
+
+    #[derive(Debug)]
+    struct Disconnect(RoomId, ConnectionId);
+
+    impl FaultInjector for Disconnect {
+        fn disconnect(&self, room_id: RoomId, connection_id: ConnectionId) -> bool {
+            (room_id, connection_id) == (self.0, self.1)
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_only_the_faulty_connection() {
+        let _installing = INSTALLING.lock().await;
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut dropped = server.connect(room_id).await;
+        let mut peer = server.connect(room_id).await;
+        let _faults = install(Arc::new(Disconnect(room_id, dropped.connection_id())));
+
+        dropped
+            .send(ClientMessage::Broadcast { payload: json!(1) })
+            .await;
+        dropped.expect_closed().await;
+
+        peer.send(ClientMessage::Broadcast { payload: json!(2) })
+            .await;
+        assert!(matches!(
+            peer.recv().await,
+            ServerMessage::Broadcast { payload, .. } if *payload == json!(2)
+        ));
+    }
+}
@@ -0,0 +1,15 @@
+//! Files state is kept in across restarts
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Writes `contents` to `file` as JSON by way of a temporary file next to it, so that a write
+/// cut short leaves the last one in place rather than half of each
+pub(crate) fn write_atomically<T: Serialize + ?Sized>(file: &Path, contents: &T) -> io::Result<()> {
+    let temporary = file.with_extension("tmp");
+    fs::write(&temporary, serde_json::to_vec_pretty(contents)?)?;
+    fs::rename(temporary, file)
+}
@@ -0,0 +1,811 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, Instant, Sleep};
+use tokio_stream::Stream;
+use tracing::{debug, instrument, warn};
+
+pub use wormhole_protocol::ConnectionId;
+
+use crate::game::{MemoryBudget, MemoryBudgetExceeded};
+
+/// How many of a room's latest broadcasts are kept for connections catching up, unless
+/// configured otherwise
+pub const DEFAULT_REPLAY_BUFFER_LEN: usize = 128;
+
+/// Enumerates the errors that can occur while broadcasting a message
+#[derive(Error, Debug)]
+pub enum BroadcastError {
+    #[error("Unable to serialize the message: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("The room is holding too many undelivered messages: {0}")]
+    MemoryBudgetExceeded(#[from] MemoryBudgetExceeded),
+}
+
+/// A message waiting in a connection's queue
+#[derive(Debug)]
+struct Queued {
+    payload: Bytes,
+    queued_at: Instant,
+}
+
+/// The queue of messages waiting to be written out to a single connection
+///
+/// Every queued message counts against the [memory budget][MemoryBudget] of
+/// the room it came from until it is taken off the queue. Messages a
+/// [delayed][Broadcaster::subscribe_delayed] queue is holding back count against a budget of
+/// their own instead, so delayed queues can't crowd out everyone else.
+#[derive(Debug)]
+pub struct MessageQueue {
+    receiver: UnboundedReceiver<Queued>,
+    budget: Arc<MemoryBudget>,
+    /// How long each message is held back after it was queued, zero to hand it out at once
+    delay: Duration,
+    /// The next message, taken off the channel but not due yet
+    held: Option<Queued>,
+    /// Wakes the queue once the held message is due
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl MessageQueue {
+    fn due_at(&self, queued: &Queued) -> Instant {
+        queued.queued_at + self.delay
+    }
+
+    pub fn try_recv(&mut self) -> Result<Bytes, TryRecvError> {
+        let queued = match self.held.take() {
+            Some(queued) => queued,
+            None => self.receiver.try_recv()?,
+        };
+        if self.due_at(&queued) > Instant::now() {
+            self.held = Some(queued);
+            return Err(TryRecvError::Empty);
+        }
+        self.budget.release(queued.payload.len());
+        Ok(queued.payload)
+    }
+}
+
+impl Stream for MessageQueue {
+    type Item = Bytes;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queue = self.get_mut();
+        let queued = match queue.held.take() {
+            Some(queued) => queued,
+            None => match ready!(queue.receiver.poll_recv(cx)) {
+                Some(queued) => queued,
+                None => return Poll::Ready(None),
+            },
+        };
+        let due_at = queue.due_at(&queued);
+        if !queue.delay.is_zero() && due_at > Instant::now() {
+            let sleep = queue
+                .sleep
+                .get_or_insert_with(|| Box::pin(sleep_until(due_at)));
+            sleep.as_mut().reset(due_at);
+            if sleep.as_mut().poll(cx).is_pending() {
+                queue.held = Some(queued);
+                return Poll::Pending;
+            }
+        }
+        queue.budget.release(queued.payload.len());
+        Poll::Ready(Some(queued.payload))
+    }
+}
+
+impl Drop for MessageQueue {
+    fn drop(&mut self) {
+        // Messages that were never delivered no longer occupy the room's budget
+        self.receiver.close();
+        if let Some(held) = self.held.take() {
+            self.budget.release(held.payload.len());
+        }
+        while let Ok(queued) = self.receiver.try_recv() {
+            self.budget.release(queued.payload.len());
+        }
+    }
+}
+
+/// What a connection missed since the last sequenced broadcast it saw, as
+/// [replayed][Broadcaster::subscribe_since] to it
+#[derive(Debug, PartialEq)]
+pub enum Replay {
+    /// Every sequenced broadcast since, oldest first
+    Complete(Vec<Bytes>),
+    /// Some of the broadcasts since are no longer kept, or the connection claims to have seen
+    /// broadcasts that were never sent, `latest_seq` being the last one sent before it
+    /// subscribed
+    Incomplete { latest_seq: u64 },
+}
+
+/// A connection's end of its [MessageQueue]
+#[derive(Debug)]
+struct Subscriber {
+    sender: UnboundedSender<Queued>,
+    /// Whether the queue holds messages back, and is charged to the delayed budget
+    delayed: bool,
+    /// The protocol version the connection speaks, if it was [given][Broadcaster::set_protocol_version]
+    protocol_version: Option<u32>,
+}
+
+/// The latest [sequenced][Broadcaster::broadcast_sequenced] broadcasts, kept so connections
+/// can catch up on what they missed
+///
+/// The kept broadcasts are charged to the room's [memory budget][MemoryBudget] by the
+/// [Broadcaster], which is handed back the bytes of those dropped to make room.
+#[derive(Debug, Default)]
+struct ReplayBuffer {
+    /// The sequence number of the latest broadcast, zero before the first
+    latest_seq: u64,
+    /// The latest broadcasts, oldest first, their sequence numbers running up to `latest_seq`
+    broadcasts: VecDeque<Bytes>,
+    /// How many broadcasts are kept, zero to keep none
+    len: usize,
+    /// The number of bytes held by the kept broadcasts
+    bytes: usize,
+}
+
+impl ReplayBuffer {
+    /// The number of bytes keeping `payload` would take
+    fn cost(&self, payload: &Bytes) -> usize {
+        if self.len == 0 {
+            0
+        } else {
+            payload.len()
+        }
+    }
+
+    /// Numbers the broadcast and keeps it, returning the number of bytes of the oldest one if
+    /// it was dropped to make room
+    fn push(&mut self, payload: Bytes) -> usize {
+        self.latest_seq += 1;
+        if self.len == 0 {
+            return 0;
+        }
+        let dropped = if self.broadcasts.len() == self.len {
+            self.drop_oldest()
+        } else {
+            0
+        };
+        self.bytes += payload.len();
+        self.broadcasts.push_back(payload);
+        dropped
+    }
+
+    /// Drops the oldest broadcast kept, returning its number of bytes
+    fn drop_oldest(&mut self) -> usize {
+        let dropped = self.broadcasts.pop_front().map_or(0, |oldest| oldest.len());
+        self.bytes -= dropped;
+        dropped
+    }
+
+    /// Drops every broadcast kept, returning their number of bytes
+    fn clear(&mut self) -> usize {
+        self.broadcasts.clear();
+        std::mem::take(&mut self.bytes)
+    }
+
+    fn since(&self, seq: u64) -> Replay {
+        let oldest_seq = self.latest_seq + 1 - self.broadcasts.len() as u64;
+        if seq > self.latest_seq || seq + 1 < oldest_seq {
+            return Replay::Incomplete {
+                latest_seq: self.latest_seq,
+            };
+        }
+        let skipped = (seq + 1 - oldest_seq) as usize;
+        Replay::Complete(self.broadcasts.iter().skip(skipped).cloned().collect())
+    }
+}
+
+/// Fans messages out to the send queues of every connection subscribed to it
+///
+/// Each message is serialized exactly once and the resulting [Bytes] are shared
+/// by reference count across every queue, so the cost of a broadcast doesn't
+/// grow with the size of the payload times the number of recipients.
+///
+/// Queued messages are charged against a [memory budget][MemoryBudget] per
+/// recipient, so slow connections can't make a room grow without bound.
+///
+/// [Sequenced][Self::broadcast_sequenced] broadcasts are numbered, and the latest of them
+/// kept so connections that join late or reconnect can be
+/// [replayed][Self::subscribe_since] what they missed. Those kept are charged to the budget
+/// too, and the oldest dropped when a broadcast wouldn't fit otherwise.
+///
+/// Connections can also be [held behind][Self::subscribe_delayed] everyone else, each of
+/// their messages handed out only once it has waited in their queue for the delay. Their
+/// queues are charged to a budget of their own, and a delayed connection whose message
+/// wouldn't fit in it is dropped rather than the message being rejected, so they can never
+/// keep the others from being sent anything.
+#[derive(Debug, Default)]
+pub struct Broadcaster {
+    next_connection_id: AtomicU64,
+    subscribers: Mutex<HashMap<ConnectionId, Subscriber>>,
+    budget: Arc<MemoryBudget>,
+    /// What the queues of delayed subscribers are charged to
+    delayed_budget: Arc<MemoryBudget>,
+    /// The delayed subscribers dropped for going over their budget, until they're
+    /// [told][Self::take_overflowed]
+    overflowed: Mutex<HashSet<ConnectionId>>,
+    /// Locked after `subscribers` whenever both are held
+    replay: Mutex<ReplayBuffer>,
+}
+
+impl Broadcaster {
+    /// Creates a broadcaster whose undelivered messages may occupy at most `limit` bytes
+    pub fn with_memory_limit(limit: usize) -> Self {
+        Self {
+            budget: Arc::new(MemoryBudget::new(limit)),
+            delayed_budget: Arc::new(MemoryBudget::new(limit)),
+            ..Default::default()
+        }
+    }
+
+    /// Keeps the latest `len` [sequenced][Self::broadcast_sequenced] broadcasts to replay,
+    /// none if zero
+    pub fn with_replay_len(self, len: usize) -> Self {
+        self.replay.lock().unwrap().len = len;
+        self
+    }
+
+    /// Registers a new connection, returning its id and the queue its messages arrive on
+    pub fn subscribe(&self) -> (ConnectionId, MessageQueue) {
+        self.subscribe_delayed(Duration::ZERO)
+    }
+
+    /// Registers a new connection like [subscribe][Self::subscribe], each message only coming
+    /// off its queue once `delay` has passed since it was queued
+    ///
+    /// The connection is dropped, its queue ending once it has handed out what it holds, if a
+    /// message for it doesn't fit in the budget delayed queues share.
+    pub fn subscribe_delayed(&self, delay: Duration) -> (ConnectionId, MessageQueue) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        self.subscribe_locked(&mut subscribers, delay)
+    }
+
+    /// Registers a new connection like [subscribe][Self::subscribe], along with the
+    /// sequenced broadcasts that came after the one numbered `seq`
+    ///
+    /// Nothing broadcast once the connection is registered is replayed, it's queued instead,
+    /// so the replay and the queue together miss nothing and repeat nothing.
+    pub fn subscribe_since(&self, seq: u64) -> (ConnectionId, MessageQueue, Replay) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let (id, queue) = self.subscribe_locked(&mut subscribers, Duration::ZERO);
+        let replay = self.replay.lock().unwrap().since(seq);
+        (id, queue, replay)
+    }
+
+    fn subscribe_locked(
+        &self,
+        subscribers: &mut HashMap<ConnectionId, Subscriber>,
+        delay: Duration,
+    ) -> (ConnectionId, MessageQueue) {
+        let id = ConnectionId::from(self.next_connection_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = unbounded_channel();
+        let delayed = !delay.is_zero();
+        subscribers.insert(
+            id,
+            Subscriber {
+                sender,
+                delayed,
+                protocol_version: None,
+            },
+        );
+        let budget = if delayed {
+            &self.delayed_budget
+        } else {
+            &self.budget
+        };
+        let queue = MessageQueue {
+            receiver,
+            budget: budget.clone(),
+            delay,
+            held: None,
+            sleep: None,
+        };
+        (id, queue)
+    }
+
+    pub fn unsubscribe(&self, id: ConnectionId) {
+        self.subscribers.lock().unwrap().remove(&id);
+        self.overflowed.lock().unwrap().remove(&id);
+    }
+
+    /// Whether the delayed connection was dropped for going over the budget delayed queues
+    /// share, forgetting it was
+    pub fn take_overflowed(&self, id: ConnectionId) -> bool {
+        self.overflowed.lock().unwrap().remove(&id)
+    }
+
+    /// Unsubscribes every connection, ending their queues once they've taken the messages
+    /// already queued for them
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    /// Notes the protocol version the connection speaks, so those too old to be shown a
+    /// message can be [counted][Self::subscribers_below]
+    pub fn set_protocol_version(&self, id: ConnectionId, protocol_version: u32) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&id) {
+            subscriber.protocol_version = Some(protocol_version);
+        }
+    }
+
+    /// How many connections speak a protocol version older than `protocol_version`, leaving
+    /// out those whose version wasn't given
+    pub fn subscribers_below(&self, protocol_version: u32) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|subscriber| {
+                subscriber
+                    .protocol_version
+                    .is_some_and(|v| v < protocol_version)
+            })
+            .count()
+    }
+
+    /// The approximate number of bytes held by messages that haven't been delivered yet
+    pub fn queued_bytes(&self) -> usize {
+        self.budget.used().saturating_sub(self.replay_bytes()) + self.delayed_budget.used()
+    }
+
+    /// The number of bytes held by the broadcasts kept to replay, which are shared with any
+    /// queues they're still waiting in
+    pub fn replay_bytes(&self) -> usize {
+        self.replay.lock().unwrap().bytes
+    }
+
+    /// The sequence number of the latest sequenced broadcast, zero before the first
+    pub fn latest_seq(&self) -> u64 {
+        self.replay.lock().unwrap().latest_seq
+    }
+
+    /// Numbers sequenced broadcasts on from `latest_seq`, as when the room they're sent in
+    /// moved here from elsewhere, dropping those kept to replay since they were numbered
+    /// differently
+    ///
+    /// Connections that saw broadcasts up to `latest_seq` then miss nothing, and those that
+    /// saw fewer are told their replay is incomplete.
+    pub fn resume_from(&self, latest_seq: u64) {
+        let mut replay = self.replay.lock().unwrap();
+        replay.latest_seq = latest_seq;
+        self.budget.release(replay.clear());
+    }
+
+    /// Serializes `message` once and queues it for every subscriber,
+    /// returning the number of subscribers it was queued for
+    ///
+    /// The message is rejected if queueing it would take the room over its memory budget, even
+    /// once the broadcasts kept to replay are dropped to make room.
+    #[instrument(skip_all)]
+    pub fn broadcast<M: Serialize>(&self, message: &M) -> Result<usize, BroadcastError> {
+        let payload = Bytes::from(serde_json::to_vec(message)?);
+        self.broadcast_bytes(payload)
+    }
+
+    /// Numbers the message built by `message` with the next sequence number, then
+    /// [broadcasts][Self::broadcast] it and keeps it to replay
+    ///
+    /// A message rejected for taking the room over its memory budget isn't numbered.
+    #[instrument(skip_all)]
+    pub fn broadcast_sequenced<M: Serialize>(
+        &self,
+        message: impl FnOnce(u64) -> M,
+    ) -> Result<usize, BroadcastError> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut replay = self.replay.lock().unwrap();
+        let payload = Bytes::from(serde_json::to_vec(&message(replay.latest_seq + 1))?);
+        let bytes = payload.len() * undelayed_count(subscribers.values()) + replay.cost(&payload);
+        self.reserve(&mut replay, bytes)?;
+        let delivered = self.queue_reserved(&mut subscribers, payload.clone());
+        self.budget.release(replay.push(payload));
+        Ok(delivered)
+    }
+
+    /// Serializes `message` once and queues it for those of `recipients` still subscribed,
+    /// returning the number of subscribers it was queued for
+    ///
+    /// The message is rejected if queueing it would take the room over its memory budget, even
+    /// once the broadcasts kept to replay are dropped to make room.
+    #[instrument(skip_all)]
+    pub fn send_to<M: Serialize>(
+        &self,
+        recipients: &[ConnectionId],
+        message: &M,
+    ) -> Result<usize, BroadcastError> {
+        let payload = Bytes::from(serde_json::to_vec(message)?);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let recipients: Vec<ConnectionId> = recipients
+            .iter()
+            .copied()
+            .filter(|id| subscribers.contains_key(id))
+            .collect();
+        self.reserve(
+            &mut self.replay.lock().unwrap(),
+            payload.len() * undelayed_count(recipients.iter().map(|id| &subscribers[id])),
+        )?;
+
+        let queued_at = Instant::now();
+        let mut delivered = 0;
+        for id in recipients {
+            if self.queue(id, &subscribers[&id], &payload, queued_at) {
+                delivered += 1;
+            } else {
+                subscribers.remove(&id);
+            }
+        }
+        Ok(delivered)
+    }
+
+    fn broadcast_bytes(&self, payload: Bytes) -> Result<usize, BroadcastError> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        self.reserve(
+            &mut self.replay.lock().unwrap(),
+            payload.len() * undelayed_count(subscribers.values()),
+        )?;
+        Ok(self.queue_reserved(&mut subscribers, payload))
+    }
+
+    /// Claims `bytes` from the memory budget, dropping the oldest broadcasts kept to replay
+    /// until they fit
+    ///
+    /// Nothing is dropped if dropping every broadcast kept still wouldn't make room.
+    fn reserve(&self, replay: &mut ReplayBuffer, bytes: usize) -> Result<(), BroadcastError> {
+        while let Err(e) = self.budget.try_reserve(bytes) {
+            if replay.bytes == 0 || e.used.saturating_sub(replay.bytes) + bytes > e.limit {
+                warn!(event = "room_memory_budget_exceeded", error = %e);
+                return Err(e.into());
+            }
+            self.budget.release(replay.drop_oldest());
+        }
+        Ok(())
+    }
+
+    /// Queues the payload for every subscriber, the budget for those that aren't delayed
+    /// having been reserved already
+    fn queue_reserved(
+        &self,
+        subscribers: &mut HashMap<ConnectionId, Subscriber>,
+        payload: Bytes,
+    ) -> usize {
+        let queued_at = Instant::now();
+        subscribers.retain(|id, subscriber| self.queue(*id, subscriber, &payload, queued_at));
+        subscribers.len()
+    }
+
+    /// Queues the payload for the subscriber, returning whether it should be kept
+    ///
+    /// The budget for subscribers that aren't delayed has been reserved already, while delayed
+    /// ones are charged here, and dropped if the payload doesn't fit.
+    fn queue(
+        &self,
+        id: ConnectionId,
+        subscriber: &Subscriber,
+        payload: &Bytes,
+        queued_at: Instant,
+    ) -> bool {
+        let budget = if subscriber.delayed {
+            if let Err(e) = self.delayed_budget.try_reserve(payload.len()) {
+                warn!(event = "delayed_subscriber_overflowed", connection_id = %id, error = %e);
+                self.overflowed.lock().unwrap().insert(id);
+                return false;
+            }
+            &self.delayed_budget
+        } else {
+            &self.budget
+        };
+        let queued = Queued {
+            payload: payload.clone(),
+            queued_at,
+        };
+        // A failed send means the receiving connection has gone away without
+        // unsubscribing, so it can be dropped from the room
+        let delivered = subscriber.sender.send(queued).is_ok();
+        if !delivered {
+            budget.release(payload.len());
+            debug!(event = "dropped_closed_subscriber", connection_id = %id);
+        }
+        delivered
+    }
+}
+
+/// The number of subscribers whose queues are charged to the room's own budget
+fn undelayed_count<'a>(subscribers: impl Iterator<Item = &'a Subscriber>) -> usize {
+    subscribers.filter(|subscriber| !subscriber.delayed).count()
+}
+
+#[cfg(test)]
+mod broadcast {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn delivers_message_to_every_subscriber() {
+        let broadcaster = Broadcaster::default();
+        let (_, mut first) = broadcaster.subscribe();
+        let (_, mut second) = broadcaster.subscribe();
+
+        let delivered = broadcaster.broadcast(&"hello").unwrap();
+
+        assert_eq!(delivered, 2);
+        assert_eq!(first.try_recv().unwrap(), Bytes::from_static(b"\"hello\""));
+        assert_eq!(second.try_recv().unwrap(), Bytes::from_static(b"\"hello\""));
+    }
+
+    #[test]
+    fn shares_one_allocation_across_subscribers() {
+        let broadcaster = Broadcaster::default();
+        let (_, mut first) = broadcaster.subscribe();
+        let (_, mut second) = broadcaster.subscribe();
+
+        broadcaster.broadcast(&vec![1, 2, 3]).unwrap();
+
+        let first = first.try_recv().unwrap();
+        let second = second.try_recv().unwrap();
+        assert_eq!(first.as_ptr(), second.as_ptr());
+    }
+
+    #[test]
+    fn skips_unsubscribed_connections() {
+        let broadcaster = Broadcaster::default();
+        let (id, mut receiver) = broadcaster.subscribe();
+        broadcaster.unsubscribe(id);
+
+        assert_eq!(broadcaster.broadcast(&"hello").unwrap(), 0);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn rejects_messages_over_the_memory_budget() {
+        let broadcaster = Broadcaster::with_memory_limit(16);
+        let (_, mut queue) = broadcaster.subscribe();
+
+        broadcaster.broadcast(&"0123456789").unwrap();
+        assert!(matches!(
+            broadcaster.broadcast(&"0123456789"),
+            Err(BroadcastError::MemoryBudgetExceeded(_))
+        ));
+
+        queue.try_recv().unwrap();
+        assert!(queue.try_recv().is_err());
+    }
+
+    #[test]
+    fn releases_budget_once_messages_are_received() {
+        let broadcaster = Broadcaster::with_memory_limit(16);
+        let (_, mut queue) = broadcaster.subscribe();
+
+        broadcaster.broadcast(&"0123456789").unwrap();
+        assert_eq!(broadcaster.queued_bytes(), 12);
+
+        queue.try_recv().unwrap();
+        assert_eq!(broadcaster.queued_bytes(), 0);
+        broadcaster.broadcast(&"0123456789").unwrap();
+    }
+
+    #[test]
+    fn releases_budget_of_dropped_queues() {
+        let broadcaster = Broadcaster::default();
+        let (id, queue) = broadcaster.subscribe();
+        broadcaster.broadcast(&"hello").unwrap();
+        broadcaster.unsubscribe(id);
+        drop(queue);
+
+        assert_eq!(broadcaster.queued_bytes(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn holds_delayed_subscribers_behind() {
+        let broadcaster = Broadcaster::default();
+        let (_, mut live) = broadcaster.subscribe();
+        let (_, mut delayed) = broadcaster.subscribe_delayed(Duration::from_secs(60));
+        broadcaster.broadcast(&"hello").unwrap();
+
+        assert!(live.try_recv().is_ok());
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(delayed.try_recv().is_err());
+        assert_eq!(broadcaster.queued_bytes(), 7);
+        let waited_from = Instant::now();
+        assert_eq!(delayed.next().await, Some(Bytes::from_static(b"\"hello\"")));
+        assert_eq!(waited_from.elapsed(), Duration::from_secs(1));
+        assert_eq!(broadcaster.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn releases_budget_of_delayed_queues_when_dropped() {
+        let broadcaster = Broadcaster::default();
+        let (_, mut queue) = broadcaster.subscribe_delayed(Duration::from_secs(60));
+        broadcaster.broadcast(&"hello").unwrap();
+        broadcaster.broadcast(&"world").unwrap();
+        assert!(queue.try_recv().is_err());
+
+        drop(queue);
+
+        assert_eq!(broadcaster.queued_bytes(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_delayed_subscribers_rather_than_rejecting_messages() {
+        let broadcaster = Broadcaster::with_memory_limit(16);
+        let (_, mut live) = broadcaster.subscribe();
+        let (delayed_id, mut delayed) = broadcaster.subscribe_delayed(Duration::from_secs(60));
+
+        for _ in 0..3 {
+            broadcaster.broadcast(&"0123456789").unwrap();
+            live.try_recv().unwrap();
+        }
+
+        assert_eq!(broadcaster.subscriber_count(), 1);
+        assert!(broadcaster.take_overflowed(delayed_id));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(delayed.next().await.is_some());
+        assert_eq!(delayed.next().await, None);
+        assert_eq!(broadcaster.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn drops_subscribers_whose_queue_has_closed() {
+        let broadcaster = Broadcaster::default();
+        let (_, receiver) = broadcaster.subscribe();
+        drop(receiver);
+
+        assert_eq!(broadcaster.broadcast(&"hello").unwrap(), 0);
+        assert_eq!(broadcaster.subscriber_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod send_to {
+    use super::*;
+
+    #[test]
+    fn delivers_message_only_to_the_recipients() {
+        let broadcaster = Broadcaster::default();
+        let (first_id, mut first) = broadcaster.subscribe();
+        let (_, mut second) = broadcaster.subscribe();
+        let (third_id, _) = broadcaster.subscribe();
+        broadcaster.unsubscribe(third_id);
+
+        let delivered = broadcaster.send_to(&[first_id, third_id], &"psst").unwrap();
+
+        assert_eq!(delivered, 1);
+        assert_eq!(first.try_recv().unwrap(), Bytes::from_static(b"\"psst\""));
+        assert!(second.try_recv().is_err());
+        assert_eq!(broadcaster.queued_bytes(), 0);
+    }
+}
+
+#[cfg(test)]
+mod close {
+    use super::*;
+
+    #[test]
+    fn ends_queues_after_queued_messages() {
+        let broadcaster = Broadcaster::default();
+        let (_, mut receiver) = broadcaster.subscribe();
+        broadcaster.broadcast(&"goodbye").unwrap();
+
+        broadcaster.close();
+
+        assert_eq!(broadcaster.subscriber_count(), 0);
+        assert!(receiver.try_recv().is_ok());
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
+
+#[cfg(test)]
+mod subscribe_since {
+    use super::*;
+
+    fn broadcast_numbers(broadcaster: &Broadcaster, count: u64) {
+        for _ in 0..count {
+            broadcaster.broadcast_sequenced(|seq| seq).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_what_came_after_the_last_broadcast_seen() {
+        let broadcaster = Broadcaster::default().with_replay_len(4);
+        broadcast_numbers(&broadcaster, 3);
+
+        let (_, mut queue, replay) = broadcaster.subscribe_since(1);
+        broadcast_numbers(&broadcaster, 1);
+
+        assert_eq!(
+            replay,
+            Replay::Complete(vec![Bytes::from_static(b"2"), Bytes::from_static(b"3")])
+        );
+        assert_eq!(queue.try_recv().unwrap(), Bytes::from_static(b"4"));
+        assert!(queue.try_recv().is_err());
+        assert_eq!(broadcaster.latest_seq(), 4);
+    }
+
+    #[test]
+    fn owns_up_to_broadcasts_it_no_longer_keeps() {
+        let broadcaster = Broadcaster::default().with_replay_len(2);
+        broadcast_numbers(&broadcaster, 5);
+
+        let (_, _, replay) = broadcaster.subscribe_since(2);
+        assert_eq!(replay, Replay::Incomplete { latest_seq: 5 });
+        let (_, _, replay) = broadcaster.subscribe_since(3);
+        assert!(matches!(replay, Replay::Complete(broadcasts) if broadcasts.len() == 2));
+        assert_eq!(broadcaster.replay_bytes(), 2);
+    }
+
+    #[test]
+    fn owns_up_to_broadcasts_it_never_sent() {
+        let broadcaster = Broadcaster::default().with_replay_len(2);
+        broadcast_numbers(&broadcaster, 1);
+
+        let (_, _, replay) = broadcaster.subscribe_since(7);
+
+        assert_eq!(replay, Replay::Incomplete { latest_seq: 1 });
+    }
+
+    #[test]
+    fn carries_on_numbering_where_it_resumed_from() {
+        let broadcaster = Broadcaster::default().with_replay_len(2);
+        broadcast_numbers(&broadcaster, 2);
+
+        broadcaster.resume_from(7);
+        let (_, _, caught_up) = broadcaster.subscribe_since(7);
+        let (_, _, behind) = broadcaster.subscribe_since(6);
+        broadcast_numbers(&broadcaster, 1);
+
+        assert_eq!(caught_up, Replay::Complete(Vec::new()));
+        assert_eq!(behind, Replay::Incomplete { latest_seq: 7 });
+        assert_eq!(broadcaster.latest_seq(), 8);
+        assert_eq!(broadcaster.replay_bytes(), 1);
+        assert_eq!(broadcaster.queued_bytes(), 0);
+        assert_eq!(broadcaster.budget.used(), 1);
+    }
+
+    #[test]
+    fn keeps_large_broadcasts_within_the_memory_budget() {
+        let broadcaster =
+            Broadcaster::with_memory_limit(10_000).with_replay_len(DEFAULT_REPLAY_BUFFER_LEN);
+        let frame = "x".repeat(998);
+
+        for _ in 0..DEFAULT_REPLAY_BUFFER_LEN {
+            broadcaster.broadcast_sequenced(|_| &frame).unwrap();
+        }
+
+        assert_eq!(broadcaster.replay_bytes(), 10_000);
+        assert_eq!(broadcaster.budget.used(), 10_000);
+        let (_, _, replay) = broadcaster.subscribe_since(DEFAULT_REPLAY_BUFFER_LEN as u64 - 10);
+        assert!(matches!(replay, Replay::Complete(broadcasts) if broadcasts.len() == 10));
+        let (_, _, replay) = broadcaster.subscribe_since(DEFAULT_REPLAY_BUFFER_LEN as u64 - 11);
+        assert!(matches!(replay, Replay::Incomplete { .. }));
+    }
+
+    #[test]
+    fn turns_away_broadcasts_that_dropping_the_replay_wouldnt_make_room_for() {
+        let broadcaster = Broadcaster::with_memory_limit(100).with_replay_len(4);
+        let (_, _queue) = broadcaster.subscribe();
+        broadcaster.broadcast_sequenced(|_| "x".repeat(18)).unwrap();
+        broadcaster.broadcast(&"x".repeat(38)).unwrap();
+
+        assert!(matches!(
+            broadcaster.broadcast_sequenced(|_| "x".repeat(28)),
+            Err(BroadcastError::MemoryBudgetExceeded(_))
+        ));
+        assert_eq!(broadcaster.replay_bytes(), 20);
+        assert_eq!(broadcaster.latest_seq(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::game::{RoomId, RoomSummary};
+
+/// How many events a lobby subscriber may fall behind by before it misses some
+pub const LOBBY_EVENT_CAPACITY: usize = 256;
+
+/// A change to the set of rooms listed in the lobby
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(untagged)]
+pub enum LobbyEvent {
+    RoomCreated(RoomSummary),
+    /// The room's settings changed
+    RoomUpdated(RoomSummary),
+    RoomDeleted {
+        id: RoomId,
+    },
+}
+
+impl LobbyEvent {
+    /// The name the event is sent under, as the `event` field of a Server-Sent Event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RoomCreated(_) => "room_created",
+            Self::RoomUpdated(_) => "room_updated",
+            Self::RoomDeleted { .. } => "room_deleted",
+        }
+    }
+
+    /// The event as lobby browsers see it, given whether the room was listed before it,
+    /// `None` if they shouldn't see it at all
+    ///
+    /// Unlisted rooms are never shown, so a room being made unlisted looks like it was
+    /// deleted and one being listed looks like it was created, while nothing else about
+    /// unlisted rooms gets out, not even their ids.
+    pub fn for_lobby(self, was_listed: bool) -> Option<Self> {
+        match self {
+            Self::RoomCreated(room) => room
+                .visibility
+                .is_listed()
+                .then_some(Self::RoomCreated(room)),
+            Self::RoomUpdated(room) => match (was_listed, room.visibility.is_listed()) {
+                (true, true) => Some(Self::RoomUpdated(room)),
+                (true, false) => Some(Self::RoomDeleted { id: room.id }),
+                (false, true) => Some(Self::RoomCreated(room)),
+                (false, false) => None,
+            },
+            Self::RoomDeleted { id } => was_listed.then_some(Self::RoomDeleted { id }),
+        }
+    }
+}
+
+/// Fans lobby events out to everyone watching the lobby
+///
+/// Subscribers that fall more than [LOBBY_EVENT_CAPACITY] events behind miss the oldest
+/// ones, and are told so the next time they receive, rather than holding events back
+/// for everyone else.
+#[derive(Debug)]
+pub struct LobbyFeed {
+    sender: Sender<LobbyEvent>,
+    /// The same events [as lobby browsers see them][LobbyEvent::for_lobby]
+    listed: Sender<LobbyEvent>,
+}
+
+impl Default for LobbyFeed {
+    fn default() -> Self {
+        Self::with_capacity(LOBBY_EVENT_CAPACITY)
+    }
+}
+
+impl LobbyFeed {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+            listed: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Sends the event to every current subscriber, and to those watching only listed rooms
+    /// as they should see it, given whether the room was listed before the event
+    pub fn publish(&self, event: LobbyEvent, was_listed: bool) {
+        // Having nobody watching the lobby is not an error
+        if let Some(listed) = event.clone().for_lobby(was_listed) {
+            let _ = self.listed.send(listed);
+        }
+        let _ = self.sender.send(event);
+    }
+
+    /// Receives every event published from now on
+    pub fn subscribe(&self) -> Receiver<LobbyEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Receives the events published from now on as lobby browsers see them, leaving out
+    /// unlisted rooms
+    pub fn subscribe_listed(&self) -> Receiver<LobbyEvent> {
+        self.listed.subscribe()
+    }
+
+    /// The number of subscribers currently watching the lobby
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count() + self.listed.receiver_count()
+    }
+}
+
+#[cfg(test)]
+mod publish {
+    use super::*;
+    use tokio::sync::broadcast::error::TryRecvError;
+
+    #[test]
+    fn reaches_every_subscriber() {
+        let feed = LobbyFeed::default();
+        let mut first = feed.subscribe();
+        let mut second = feed.subscribe();
+        let event = LobbyEvent::RoomDeleted { id: 1_u128.into() };
+
+        feed.publish(event.clone(), true);
+
+        assert_eq!(first.try_recv(), Ok(event.clone()));
+        assert_eq!(second.try_recv(), Ok(event));
+    }
+
+    #[test]
+    fn is_not_seen_by_later_subscribers() {
+        let feed = LobbyFeed::default();
+        feed.publish(LobbyEvent::RoomDeleted { id: 1_u128.into() }, true);
+
+        assert_eq!(feed.subscribe().try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn tells_lagging_subscribers_what_they_missed() {
+        let feed = LobbyFeed::with_capacity(1);
+        let mut subscriber = feed.subscribe();
+        feed.publish(LobbyEvent::RoomDeleted { id: 1_u128.into() }, true);
+        feed.publish(LobbyEvent::RoomDeleted { id: 2_u128.into() }, true);
+
+        assert_eq!(subscriber.try_recv(), Err(TryRecvError::Lagged(1)));
+        assert_eq!(
+            subscriber.try_recv(),
+            Ok(LobbyEvent::RoomDeleted { id: 2_u128.into() })
+        );
+    }
+}
+
+#[cfg(test)]
+mod for_lobby {
+    use super::*;
+    use crate::game::{RoomStatus, Visibility};
+
+    fn room(visibility: Visibility) -> RoomSummary {
+        RoomSummary {
+            id: 1_u128.into(),
+            name: None,
+            game_type: None,
+            player_count: 0,
+            spectator_count: 0,
+            capacity: None,
+            state: RoomStatus::Open,
+            visibility,
+            region: None,
+            starts_at: None,
+            deletes_at: None,
+            last_active_at: None,
+        }
+    }
+
+    #[test]
+    fn passes_listed_rooms_through() {
+        let created = LobbyEvent::RoomCreated(room(Visibility::Private));
+        let updated = LobbyEvent::RoomUpdated(room(Visibility::Public));
+        let deleted = LobbyEvent::RoomDeleted { id: 1_u128.into() };
+
+        assert_eq!(created.clone().for_lobby(false), Some(created));
+        assert_eq!(updated.clone().for_lobby(true), Some(updated));
+        assert_eq!(deleted.clone().for_lobby(true), Some(deleted));
+    }
+
+    #[test]
+    fn hides_unlisted_rooms() {
+        assert_eq!(
+            LobbyEvent::RoomCreated(room(Visibility::Unlisted)).for_lobby(false),
+            None
+        );
+        assert_eq!(
+            LobbyEvent::RoomUpdated(room(Visibility::Unlisted)).for_lobby(false),
+            None
+        );
+        assert_eq!(
+            LobbyEvent::RoomDeleted { id: 1_u128.into() }.for_lobby(false),
+            None
+        );
+    }
+
+    #[test]
+    fn deletes_rooms_made_unlisted() {
+        assert_eq!(
+            LobbyEvent::RoomUpdated(room(Visibility::Unlisted)).for_lobby(true),
+            Some(LobbyEvent::RoomDeleted { id: 1_u128.into() })
+        );
+    }
+
+    #[test]
+    fn creates_rooms_made_listed() {
+        let listed = room(Visibility::Public);
+
+        assert_eq!(
+            LobbyEvent::RoomUpdated(listed.clone()).for_lobby(false),
+            Some(LobbyEvent::RoomCreated(listed))
+        );
+    }
+}
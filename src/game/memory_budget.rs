@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use thiserror::Error;
+
+/// The default cap on the memory a single room may hold, 8 MiB
+pub const DEFAULT_ROOM_MEMORY_LIMIT_BYTES: usize = 8 * 1024 * 1024;
+
+/// Raised when reserving memory would push a [budget][MemoryBudget] over its limit
+#[derive(Error, Debug, PartialEq)]
+#[error(
+    "Reserving {requested} bytes would exceed the memory budget ({used} of {limit} bytes in use)"
+)]
+pub struct MemoryBudgetExceeded {
+    pub requested: usize,
+    pub used: usize,
+    pub limit: usize,
+}
+
+/// Tracks the approximate number of bytes a room is holding on to and
+/// refuses reservations that would take it over a fixed limit
+#[derive(Debug)]
+pub struct MemoryBudget {
+    used: AtomicUsize,
+    limit: usize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(DEFAULT_ROOM_MEMORY_LIMIT_BYTES)
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            used: AtomicUsize::new(0),
+            limit,
+        }
+    }
+
+    /// Claims `bytes` from the budget, failing without claiming anything if
+    /// doing so would exceed the limit
+    pub fn try_reserve(&self, bytes: usize) -> Result<(), MemoryBudgetExceeded> {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes).filter(|&total| total <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|used| MemoryBudgetExceeded {
+                requested: bytes,
+                used,
+                limit: self.limit,
+            })
+    }
+
+    /// Returns `bytes` previously claimed with [try_reserve][MemoryBudget::try_reserve]
+    pub fn release(&self, bytes: usize) {
+        let _ = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod try_reserve {
+    use super::*;
+
+    #[test]
+    fn succeeds_up_to_the_limit() {
+        let budget = MemoryBudget::new(100);
+        assert_eq!(budget.try_reserve(60), Ok(()));
+        assert_eq!(budget.try_reserve(40), Ok(()));
+        assert_eq!(budget.used(), 100);
+    }
+
+    #[test]
+    fn fails_without_claiming_when_over_the_limit() {
+        let budget = MemoryBudget::new(100);
+        budget.try_reserve(60).unwrap();
+
+        assert_eq!(
+            budget.try_reserve(41),
+            Err(MemoryBudgetExceeded {
+                requested: 41,
+                used: 60,
+                limit: 100
+            })
+        );
+        assert_eq!(budget.used(), 60);
+    }
+}
+
+#[cfg(test)]
+mod release {
+    use super::*;
+
+    #[test]
+    fn frees_space_for_new_reservations() {
+        let budget = MemoryBudget::new(100);
+        budget.try_reserve(100).unwrap();
+        budget.release(50);
+
+        assert_eq!(budget.try_reserve(50), Ok(()));
+    }
+
+    #[test]
+    fn never_drops_below_zero() {
+        let budget = MemoryBudget::new(100);
+        budget.release(10);
+        assert_eq!(budget.used(), 0);
+    }
+}
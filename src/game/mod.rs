@@ -1,7 +1,21 @@
+mod broadcaster;
+mod lobby;
+mod memory_budget;
 mod player;
+mod registry_handle;
 mod room;
+mod room_creation;
+mod room_deletion;
 mod room_registry;
+mod state_codec;
 
-pub(crate) use player::*;
-pub(crate) use room::*;
-pub(crate) use room_registry::*;
+pub use broadcaster::*;
+pub use lobby::*;
+pub use memory_budget::*;
+pub use player::*;
+pub use registry_handle::*;
+pub use room::*;
+pub use room_creation::*;
+pub use room_deletion::*;
+pub use room_registry::*;
+pub use state_codec::*;
@@ -1,15 +1,9 @@
 use std::hash::Hash;
-#[derive(Debug, Hash, PartialOrd, Eq, PartialEq, Copy, Clone)]
-pub(crate) struct PlayerId(u128);
 
-impl From<u128> for PlayerId {
-    fn from(value: u128) -> Self {
-        PlayerId(value)
-    }
-}
+pub use wormhole_protocol::PlayerId;
 
 #[derive(Debug, Eq)]
-pub(crate) struct Player {
+pub struct Player {
     id: PlayerId,
 }
 
@@ -31,4 +25,12 @@ impl Hash for Player {
     }
 }
 
-impl Player {}
+impl Player {
+    pub fn new(id: PlayerId) -> Self {
+        Self { id }
+    }
+
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::game::{LobbyFeed, RegistryBusy, Room, RoomId, RoomRegistry, RoomSummary, ShardLocked};
+use crate::problem::Problem;
+
+/// How long request handlers wait for a registry shard's lock unless configured otherwise
+pub const DEFAULT_REGISTRY_LOCK_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How long clients turned away by a busy registry are asked to wait before retrying
+const REGISTRY_BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// How long to pause between attempts at taking a contended shard's lock, at first and at most
+const MIN_LOCK_PAUSE: Duration = Duration::from_micros(50);
+const MAX_LOCK_PAUSE: Duration = Duration::from_millis(5);
+
+/// The [registry][RoomRegistry] as request handlers see it
+///
+/// Looking rooms up and deleting them through the handle gives up on a shard whose lock is
+/// held for longer than the lock timeout, so one stuck request can't hold up every request
+/// for rooms in the same shard. The wait is async, so it doesn't hold up the other requests
+/// on the thread either. What else handlers need from the registry doesn't take a shard's
+/// lock, and the registry itself is only handed to background tasks.
+#[derive(Debug, Clone)]
+pub struct RegistryHandle {
+    registry: Arc<RoomRegistry>,
+    lock_timeout: Duration,
+}
+
+impl RegistryHandle {
+    pub fn new(registry: Arc<RoomRegistry>, lock_timeout: Duration) -> Self {
+        Self {
+            registry,
+            lock_timeout,
+        }
+    }
+
+    /// The registry itself, for handing to whatever needs to keep hold of it
+    pub fn registry(&self) -> &Arc<RoomRegistry> {
+        &self.registry
+    }
+
+    pub fn lobby(&self) -> &LobbyFeed {
+        self.registry.lobby()
+    }
+
+    pub fn list_active_rooms(&self) -> Arc<Vec<RoomSummary>> {
+        self.registry.list_active_rooms()
+    }
+
+    pub fn list_lobby_rooms(&self) -> Vec<RoomSummary> {
+        self.registry.list_lobby_rooms()
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.registry.region()
+    }
+
+    pub fn game_type_of(&self, room: &Room) -> Option<String> {
+        self.registry.game_type_of(room)
+    }
+
+    pub fn delivers_room_webhooks(&self) -> bool {
+        self.registry.delivers_room_webhooks()
+    }
+
+    pub async fn get_room(&self, id: RoomId) -> Result<Option<Arc<Room>>, RegistryBusy> {
+        self.within_lock_timeout(|| self.registry.try_get_room_for_id(id))
+            .await
+    }
+
+    /// Removes the room with the given id, returning it if it was registered
+    pub async fn delete_room(&self, id: RoomId) -> Result<Option<Arc<Room>>, RegistryBusy> {
+        self.within_lock_timeout(|| self.registry.try_delete_room(id))
+            .await
+    }
+
+    /// Retries `attempt` while the shard it needs is locked, giving up after the lock timeout
+    async fn within_lock_timeout<T>(
+        &self,
+        attempt: impl Fn() -> Result<T, ShardLocked>,
+    ) -> Result<T, RegistryBusy> {
+        let timeout = self.lock_timeout;
+        let deadline = Instant::now() + timeout;
+        let mut pause = MIN_LOCK_PAUSE;
+        loop {
+            if let Ok(done) = attempt() {
+                return Ok(done);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(event = "registry_shard_contended", ?timeout);
+                return Err(RegistryBusy(timeout));
+            }
+            tokio::time::sleep(pause.min(deadline - now)).await;
+            pause = (pause * 2).min(MAX_LOCK_PAUSE);
+        }
+    }
+}
+
+impl From<RegistryBusy> for Problem {
+    fn from(busy: RegistryBusy) -> Self {
+        Problem::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "/problems/registry-busy",
+            "The server is too busy to look up rooms",
+        )
+        .with_detail(busy.to_string())
+        .with_retry_after(REGISTRY_BUSY_RETRY_AFTER)
+    }
+}
+
+#[cfg(test)]
+mod problem {
+    use super::*;
+
+    #[test]
+    fn asks_clients_to_retry_shortly() {
+        let problem = Problem::from(RegistryBusy(Duration::from_millis(10)));
+
+        assert_eq!(problem.status, 503);
+        assert_eq!(problem.problem_type, "/problems/registry-busy");
+        assert_eq!(problem.retry_after, Some(REGISTRY_BUSY_RETRY_AFTER));
+    }
+}
+
+#[cfg(test)]
+mod get_room {
+    use super::*;
+
+    #[tokio::test]
+    async fn gives_up_on_a_shard_locked_for_too_long() {
+        let registry = Arc::new(RoomRegistry::new(1));
+        let id = registry.create_room().unwrap();
+        let handle = RegistryHandle::new(registry.clone(), Duration::from_millis(20));
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let holder = std::thread::spawn(move || {
+            registry.delete_room_if(id, |_| {
+                locked_tx.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+                false
+            })
+        });
+        locked_rx.recv().unwrap();
+
+        assert_eq!(
+            handle.get_room(id).await.unwrap_err(),
+            RegistryBusy(Duration::from_millis(20))
+        );
+        holder.join().unwrap();
+        assert!(handle.get_room(id).await.unwrap().is_some());
+    }
+}
@@ -1,18 +1,1455 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::game::Player;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::task::JoinHandle;
+use tracing::{debug, instrument};
+use utoipa::ToSchema;
+
+use crate::chat::{ChatRoutingError, CHAT_SCROLLBACK_LEN};
+use crate::clock::{unix_time, Clock};
+use crate::game::{
+    encoded_or_plain, Broadcaster, ConnectionId, EncodedState, Player, PlayerId, RoomDeletionQueue,
+    RoomId, RoomStatus, Visibility,
+};
+use crate::invites::{InviteClaims, InviteError, InviteId};
+use crate::protocol::{ChatChannel, ChatMessage};
+use crate::webhooks::RoomWebhook;
+
+/// The reason connections are given when an admin force closes their room
+pub const ADMIN_CLOSED_REASON: &str = "admin_closed";
 
 /// A room is an entity that maintains a collection of [players][Player]
 /// and is responsible for orchestrating their interactions
-#[derive(Debug, PartialEq)]
-pub(crate) struct Room {
-    players: HashSet<Player>,
+#[derive(Debug, Default)]
+pub struct Room {
+    /// The players in the room along with the connections each of them has open
+    players: Mutex<HashMap<Player, Vec<ConnectionId>>>,
+    /// How many players the room can hold, `None` for no limit
+    capacity: Option<usize>,
+    /// How long the room is kept once everyone has left, `None` to keep it until something
+    /// else deletes it
+    empty_timeout: Option<Duration>,
+    /// The players seats are held for until they join, along with when the hold runs out
+    held_seats: Mutex<HashMap<PlayerId, Instant>>,
+    /// The first authenticated player to join the room
+    owner: Mutex<Option<PlayerId>>,
+    /// Who can find and join the room
+    visibility: Mutex<Visibility>,
+    /// When the room opens to players, in seconds since the Unix epoch, if it's scheduled to
+    /// open later
+    starts_at: Mutex<Option<u64>>,
+    /// What the room's owner has called it
+    name: Mutex<Option<String>>,
+    /// Whatever the room's owner has set for game clients to read, such as its map
+    custom_data: Mutex<Map<String, Value>>,
+    /// The kind of game played in the room, if the match it was created for says
+    game_type: Mutex<Option<String>>,
+    /// How many times each invite to the room has been used
+    invite_uses: Mutex<HashMap<InviteId, u32>>,
+    /// The team each player is on, for matches played in teams
+    teams: Mutex<HashMap<PlayerId, String>>,
+    /// The players who can't chat, along with when their mutes run out
+    mutes: Mutex<HashMap<PlayerId, Option<Instant>>>,
+    /// The most recent chat messages, oldest first
+    chat_history: Mutex<VecDeque<ChatMessage>>,
+    broadcaster: Broadcaster,
+    deletion: Mutex<Option<ScheduledDeletion>>,
+    /// Bumped every time the room's deletion is scheduled or cancelled, so a deletion request
+    /// sent just before it was cancelled can be told apart from a current one, shared with
+    /// the scheduled deletion's task
+    deletion_generation: Arc<AtomicU64>,
+    /// Why the room was closed, given to its connections as they're disconnected
+    close_reason: Mutex<Option<&'static str>>,
+    /// The room the line of rematches this room belongs to started with, `None` if it's this
+    /// one
+    lineage: Mutex<Option<RoomId>>,
+    /// How long each turn may take, `None` unless the room is turn-based
+    turn_timeout: Mutex<Option<Duration>>,
+    /// Whose turn it is in a turn-based room, once the turn has first been passed
+    turn: Mutex<Option<Turn>>,
+    /// The last payload broadcast in a turn-based room
+    game_state: Mutex<Option<GameState>>,
+    /// The endpoint the room's creator asked to be told about its events at
+    webhook: Mutex<Option<RoomWebhook>>,
+    /// How many connections are watching the room rather than playing in it
+    spectators: AtomicUsize,
+    /// When something was last broadcast in the room, in seconds since the Unix epoch, zero
+    /// if nothing has been
+    last_active_at: AtomicU64,
+}
+
+/// Whose turn it is in a [turn-based][Room::is_turn_based] room, and until when
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Turn {
+    pub player_id: PlayerId,
+    /// When the room is deleted unless the turn is passed on by then, in seconds since the
+    /// Unix epoch
+    pub deadline: u64,
+}
+
+/// The last payload broadcast in a turn-based room, which stands for the state of its game
+/// so players joining between turns can pick the game up where it was left
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct GameState {
+    pub from: ConnectionId,
+    pub from_player: Option<PlayerId>,
+    /// The payload, as encoded by its game type's [codec][crate::game::StateCodecs]
+    #[serde(deserialize_with = "encoded_or_plain")]
+    pub payload: EncodedState,
+}
+
+/// A room's pending [deletion][Room::schedule_deletion]
+#[derive(Debug)]
+struct ScheduledDeletion {
+    task: JoinHandle<()>,
+    /// When the deletion comes due, on `clock`
+    deadline: Instant,
+    clock: Arc<dyn Clock>,
+    /// Whether it was scheduled because everyone left, rather than because nobody has
+    /// joined yet or the turn is running out
+    emptied: bool,
+}
+
+/// Everything about a room that carries over when it moves to another instance
+///
+/// Connections don't carry over, players reconnect to the room's new home and are counted
+/// again as they do. Neither do mutes and chat history.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct RoomState {
+    pub owner: Option<PlayerId>,
+    pub private: bool,
+    /// Whether the room is left out of lobby listings, which private rooms never are
+    #[serde(default)]
+    pub unlisted: bool,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub custom_data: Map<String, Value>,
+    pub invite_uses: Vec<(InviteId, u32)>,
+    #[serde(default)]
+    pub teams: Vec<(PlayerId, String)>,
+    #[serde(default)]
+    pub starts_at: Option<u64>,
+    #[serde(default)]
+    pub lineage: Option<RoomId>,
+    #[serde(default)]
+    pub game_type: Option<String>,
+    /// How long each turn may take, `None` unless the room is turn-based
+    #[serde(default)]
+    pub turn_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub turn: Option<Turn>,
+    #[serde(default)]
+    pub game_state: Option<GameState>,
+    #[serde(default)]
+    pub webhook: Option<RoomWebhook>,
+    /// The sequence number of the latest sequenced broadcast, for connections to keep
+    /// replaying from wherever the room moves to
+    #[serde(default)]
+    pub latest_seq: u64,
 }
 
 impl Room {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a room whose undelivered messages may occupy at most `limit` bytes
+    pub fn with_memory_limit(limit: usize) -> Self {
         Self {
-            players: Default::default(),
+            broadcaster: Broadcaster::with_memory_limit(limit),
+            ..Default::default()
+        }
+    }
+
+    /// Limits the room to `capacity` players, or lets any number in if `None`
+    ///
+    /// Only authenticated players take up seats, anonymous connections can't be told apart.
+    pub fn with_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Has the room deleted once it's been empty for `timeout` after the last connection to
+    /// it closes, or kept until something else deletes it if `None`
+    pub fn with_empty_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.empty_timeout = timeout;
+        self
+    }
+
+    /// Has the room keep its latest `len` broadcasts so connections joining late or
+    /// reconnecting can catch up on them, none if zero
+    pub fn with_replay_len(mut self, len: usize) -> Self {
+        self.broadcaster = std::mem::take(&mut self.broadcaster).with_replay_len(len);
+        self
+    }
+
+    /// Requests the room's deletion once `after` has elapsed on the queue's
+    /// [clock][RoomDeletionQueue::clock], replacing any deletion that was already scheduled
+    #[instrument(skip(self, queue))]
+    pub fn schedule_deletion(&self, id: RoomId, after: Duration, queue: RoomDeletionQueue) {
+        self.schedule(id, after, queue, false);
+    }
+
+    fn schedule(&self, id: RoomId, after: Duration, queue: RoomDeletionQueue, emptied: bool) {
+        let generation = self.deletion_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let current_generation = self.deletion_generation.clone();
+        let clock = queue.clock().clone();
+        let deadline = clock.now() + after;
+        let sleep = clock.sleep(after);
+        let task = tokio::spawn(async move {
+            sleep.await;
+            // Aborting the task doesn't stop it if it's already running, so it checks for
+            // itself that it hasn't been superseded, and the deletion handler checks again in
+            // case it's superseded once the request is sent
+            if current_generation.load(Ordering::SeqCst) == generation {
+                queue.request_idle_deletion(id, generation);
+            }
+        });
+        let scheduled = ScheduledDeletion {
+            task,
+            deadline,
+            clock,
+            emptied,
+        };
+        if let Some(previous) = self.deletion.lock().unwrap().replace(scheduled) {
+            previous.task.abort();
+        }
+    }
+
+    /// Cancels the room's scheduled deletion, if it has one, including one whose request is
+    /// already on its way to the deletion handler, returning whether it had one
+    pub fn cancel_deletion(&self) -> bool {
+        self.deletion_generation.fetch_add(1, Ordering::SeqCst);
+        let Some(scheduled) = self.deletion.lock().unwrap().take() else {
+            return false;
+        };
+        debug!(event = "room_deletion_cancelled");
+        scheduled.task.abort();
+        true
+    }
+
+    /// When the room's scheduled deletion comes due, on the [clock][RoomDeletionQueue::clock]
+    /// of the queue it was scheduled through, if it's scheduled to be deleted
+    pub fn deletion_deadline(&self) -> Option<Instant> {
+        self.deletion
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|scheduled| scheduled.deadline)
+    }
+
+    /// How long until the room's scheduled deletion comes due, if it's scheduled to be
+    /// deleted, zero once it's due
+    pub fn time_until_deletion(&self) -> Option<Duration> {
+        self.deletion.lock().unwrap().as_ref().map(|scheduled| {
+            scheduled
+                .deadline
+                .saturating_duration_since(scheduled.clock.now())
+        })
+    }
+
+    /// When the room's scheduled deletion comes due, in seconds since the Unix epoch, if it's
+    /// scheduled to be deleted
+    pub fn deletes_at(&self) -> Option<u64> {
+        self.time_until_deletion()
+            .map(|remaining| unix_time() + remaining.as_secs())
+    }
+
+    /// How many times the room's deletion has been scheduled or cancelled
+    pub fn deletion_generation(&self) -> u64 {
+        self.deletion_generation.load(Ordering::SeqCst)
+    }
+
+    /// Records another connection to the room for the player, making them the
+    /// room's owner if it doesn't have one yet
+    pub fn add_player(&self, id: PlayerId, connection: ConnectionId) {
+        self.owner.lock().unwrap().get_or_insert(id);
+        self.held_seats.lock().unwrap().remove(&id);
+        self.players
+            .lock()
+            .unwrap()
+            .entry(Player::new(id))
+            .or_default()
+            .push(connection);
+    }
+
+    /// Records that a connection to the room has closed, removing its player, if it had
+    /// one, from the room once they have none left
+    ///
+    /// The connection must already have been unsubscribed from the room's broadcasts. If it
+    /// was the last one, the room's deletion is scheduled for after its
+    /// [empty timeout][Self::with_empty_timeout], as when the room was created, and `true`
+    /// returned.
+    pub fn remove_player(
+        &self,
+        room_id: RoomId,
+        id: Option<PlayerId>,
+        connection: ConnectionId,
+        queue: &RoomDeletionQueue,
+    ) -> bool {
+        if let Some(id) = id {
+            let mut players = self.players.lock().unwrap();
+            let player = Player::new(id);
+            if let Some(connections) = players.get_mut(&player) {
+                connections.retain(|&open| open != connection);
+                if connections.is_empty() {
+                    players.remove(&player);
+                }
+            }
         }
+        if self.broadcaster.subscriber_count() > 0 {
+            return false;
+        }
+        // Someone joining in the meantime has the deletion skipped when it comes due, their
+        // connection keeping the room from being idle
+        let timeout = if self.is_turn_based() {
+            // Turn-based rooms are kept for as long as the turn lasts, and deleted as soon as
+            // they're left if the turn ran out while someone was still connected
+            if self.time_until_deletion() != Some(Duration::ZERO) {
+                return false;
+            }
+            Duration::ZERO
+        } else {
+            let Some(timeout) = self.empty_timeout else {
+                return false;
+            };
+            timeout
+        };
+        debug!(event = "empty_room_deletion_scheduled", ?timeout);
+        self.schedule(room_id, timeout, queue.clone(), true);
+        true
+    }
+
+    /// Holds a seat until `until` for each of the players who isn't in the room already, but
+    /// only if there are enough free seats for all of them, returning whether there were
+    pub fn hold_seats(&self, players: &[PlayerId], until: Instant) -> bool {
+        let in_room = self.players.lock().unwrap();
+        let mut held = self.held_seats.lock().unwrap();
+        let now = Instant::now();
+        held.retain(|_, expires| *expires > now);
+        let needed: Vec<_> = players
+            .iter()
+            .filter(|&&id| !in_room.contains_key(&Player::new(id)) && !held.contains_key(&id))
+            .collect();
+        if let Some(capacity) = self.capacity {
+            let taken = in_room.len() + held.len();
+            if taken + needed.len() > capacity {
+                return false;
+            }
+        }
+        for &id in players {
+            if !in_room.contains_key(&Player::new(id)) {
+                held.insert(id, until);
+            }
+        }
+        true
+    }
+
+    /// Whether the player can join without going over the room's capacity, because there's a
+    /// free seat, one is held for them, or they're already in the room
+    pub fn has_seat_for(&self, id: Option<PlayerId>) -> bool {
+        let Some(capacity) = self.capacity else {
+            return true;
+        };
+        let Some(id) = id else {
+            return true;
+        };
+        let in_room = self.players.lock().unwrap();
+        let mut held = self.held_seats.lock().unwrap();
+        let now = Instant::now();
+        held.retain(|_, expires| *expires > now);
+        in_room.contains_key(&Player::new(id))
+            || held.contains_key(&id)
+            || in_room.len() + held.len() < capacity
+    }
+
+    /// Records another connection watching the room without taking a seat in it
+    pub fn add_spectator(&self) {
+        self.spectators.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a connection watching the room has closed, before it's
+    /// [removed][Self::remove_player] like any other
+    pub fn remove_spectator(&self) {
+        self.spectators.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn spectator_count(&self) -> usize {
+        self.spectators.load(Ordering::Relaxed)
+    }
+
+    /// Records that something was broadcast in the room, returning whether it's the first
+    /// time this minute, so listings only need refreshing once a minute however busy it is
+    pub fn record_activity(&self) -> bool {
+        let now = unix_time();
+        let previous = self.last_active_at.swap(now, Ordering::Relaxed);
+        previous / 60 != now / 60
+    }
+
+    /// When something was last broadcast in the room, in seconds since the Unix epoch, if
+    /// anything has been
+    pub fn last_active_at(&self) -> Option<u64> {
+        Some(self.last_active_at.load(Ordering::Relaxed)).filter(|&at| at > 0)
+    }
+
+    /// Puts the player on a team, as their seat in a match says
+    pub fn set_team(&self, id: PlayerId, team: String) {
+        self.teams.lock().unwrap().insert(id, team);
+    }
+
+    pub fn team_of(&self, id: PlayerId) -> Option<String> {
+        self.teams.lock().unwrap().get(&id).cloned()
+    }
+
+    /// The distinct teams the room's players are on, in order
+    pub fn team_names(&self) -> Vec<String> {
+        let teams = self.teams.lock().unwrap();
+        let names: BTreeSet<_> = teams.values().cloned().collect();
+        names.into_iter().collect()
+    }
+
+    /// The connections a line of chat sent on `channel` by the `sender` connection of
+    /// `sender_player`, whose party has `party` as its members, is delivered to, `None` for
+    /// every connection in the room
+    pub fn chat_recipients(
+        &self,
+        sender: ConnectionId,
+        sender_player: Option<PlayerId>,
+        party: &[PlayerId],
+        channel: &ChatChannel,
+    ) -> Result<Option<Vec<ConnectionId>>, ChatRoutingError> {
+        let players = self.players.lock().unwrap();
+        let connections_of =
+            |id: PlayerId| players.get(&Player::new(id)).cloned().unwrap_or_default();
+        match channel {
+            ChatChannel::Room => Ok(None),
+            ChatChannel::Team => {
+                let team = sender_player
+                    .and_then(|id| self.team_of(id))
+                    .ok_or(ChatRoutingError::NoTeam)?;
+                let teams = self.teams.lock().unwrap();
+                Ok(Some(
+                    teams
+                        .iter()
+                        .filter(|(_, their_team)| **their_team == team)
+                        .flat_map(|(&id, _)| connections_of(id))
+                        .collect(),
+                ))
+            }
+            ChatChannel::Party => {
+                if sender_player.is_none_or(|id| !party.contains(&id)) {
+                    return Err(ChatRoutingError::NoParty);
+                }
+                Ok(Some(
+                    party.iter().flat_map(|&id| connections_of(id)).collect(),
+                ))
+            }
+            ChatChannel::Whisper(recipient) => {
+                let mut recipients = connections_of(*recipient);
+                if recipients.is_empty() {
+                    return Err(ChatRoutingError::NotInRoom(*recipient));
+                }
+                match sender_player {
+                    Some(id) if id != *recipient => recipients.extend(connections_of(id)),
+                    Some(_) => {}
+                    None => recipients.push(sender),
+                }
+                Ok(Some(recipients))
+            }
+        }
+    }
+
+    pub fn owner(&self) -> Option<PlayerId> {
+        *self.owner.lock().unwrap()
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        *self.visibility.lock().unwrap()
+    }
+
+    pub fn set_visibility(&self, visibility: Visibility) {
+        *self.visibility.lock().unwrap() = visibility;
+    }
+
+    /// Whether joining requires an invite
+    pub fn is_private(&self) -> bool {
+        self.visibility() == Visibility::Private
+    }
+
+    /// Makes joining require an invite, or makes the room public
+    pub fn set_private(&self, private: bool) {
+        self.set_visibility(if private {
+            Visibility::Private
+        } else {
+            Visibility::Public
+        });
+    }
+
+    /// What the room's owner has set for game clients to read, empty if they haven't
+    pub fn custom_data(&self) -> Map<String, Value> {
+        self.custom_data.lock().unwrap().clone()
+    }
+
+    pub fn set_custom_data(&self, data: Map<String, Value>) {
+        *self.custom_data.lock().unwrap() = data;
+    }
+
+    pub fn starts_at(&self) -> Option<u64> {
+        *self.starts_at.lock().unwrap()
+    }
+
+    /// Schedules the room to open at `starts_at`, in seconds since the Unix epoch, or opens it
+    /// right away if `None`
+    pub fn set_starts_at(&self, starts_at: Option<u64>) {
+        *self.starts_at.lock().unwrap() = starts_at;
+    }
+
+    /// Whether players can join the room, which they can once the time it's scheduled to open
+    /// at has come
+    pub fn is_open(&self) -> bool {
+        self.starts_at()
+            .is_none_or(|starts_at| starts_at <= unix_time())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name.lock().unwrap().clone()
+    }
+
+    pub fn set_name(&self, name: String) {
+        *self.name.lock().unwrap() = Some(name);
+    }
+
+    pub fn game_type(&self) -> Option<String> {
+        self.game_type.lock().unwrap().clone()
+    }
+
+    pub fn set_game_type(&self, game_type: String) {
+        *self.game_type.lock().unwrap() = Some(game_type);
+    }
+
+    /// How many players the room can hold, `None` for no limit
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Where the room is in its life, as listed in the lobby
+    ///
+    /// Only rooms everyone has left are closing. Those waiting for their first player, or
+    /// for the player whose turn it is, are open though they're due to be deleted too.
+    pub fn status(&self) -> RoomStatus {
+        if !self.is_open() {
+            RoomStatus::Scheduled
+        } else if self
+            .deletion
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|scheduled| scheduled.emptied)
+        {
+            RoomStatus::Closing
+        } else if self
+            .capacity
+            .is_some_and(|capacity| self.player_count() >= capacity)
+        {
+            RoomStatus::Full
+        } else {
+            RoomStatus::Open
+        }
+    }
+
+    /// Counts a use of an already verified invite by the player joining with it, failing if
+    /// it has been used up or holds a seat for someone else
+    pub fn redeem_invite(
+        &self,
+        invite: &InviteClaims,
+        player: Option<PlayerId>,
+    ) -> Result<(), InviteError> {
+        if invite
+            .player_id
+            .is_some_and(|seat| player.is_some_and(|player| player != seat))
+        {
+            return Err(InviteError::WrongPlayer);
+        }
+        let mut invite_uses = self.invite_uses.lock().unwrap();
+        let uses = invite_uses.entry(invite.id).or_default();
+        if invite.max_uses.is_some_and(|max_uses| *uses >= max_uses) {
+            return Err(InviteError::UsedUp);
+        }
+        *uses += 1;
+        Ok(())
+    }
+
+    /// Stops the player from chatting for `duration`, or for as long as the room exists if
+    /// `None` or too long to tell when it would end, replacing any mute they already have
+    pub fn mute(&self, id: PlayerId, duration: Option<Duration>) {
+        let until = duration.and_then(|duration| Instant::now().checked_add(duration));
+        self.mutes.lock().unwrap().insert(id, until);
+    }
+
+    pub fn unmute(&self, id: PlayerId) {
+        self.mutes.lock().unwrap().remove(&id);
+    }
+
+    pub fn is_muted(&self, id: PlayerId) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        match mutes.get(&id) {
+            Some(Some(until)) if *until <= Instant::now() => {
+                mutes.remove(&id);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Keeps a chat message for players joining later, forgetting the oldest one once
+    /// [CHAT_SCROLLBACK_LEN] are kept
+    pub fn record_chat(&self, message: ChatMessage) {
+        let mut history = self.chat_history.lock().unwrap();
+        if history.len() == CHAT_SCROLLBACK_LEN {
+            history.pop_front();
+        }
+        history.push_back(message);
+    }
+
+    /// The room's most recent chat messages, oldest first
+    pub fn chat_history(&self) -> Vec<ChatMessage> {
+        self.chat_history.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Whether the room is turn-based, kept for as long as its players take over each turn
+    /// rather than deleted for sitting empty
+    pub fn is_turn_based(&self) -> bool {
+        self.turn_timeout().is_some()
+    }
+
+    /// How long each turn may take, if the room is turn-based
+    pub fn turn_timeout(&self) -> Option<Duration> {
+        *self.turn_timeout.lock().unwrap()
+    }
+
+    /// Whose turn it is, if the room is turn-based and the turn has been passed
+    pub fn turn(&self) -> Option<Turn> {
+        *self.turn.lock().unwrap()
+    }
+
+    /// Hands the turn to the player, giving them the [turn timeout][Self::turn_timeout] to
+    /// take it before the room is deleted, or returns `None` if the room isn't turn-based.
+    /// The turn's deadline is timed on the queue's [clock][RoomDeletionQueue::clock], like
+    /// the deletion
+    pub fn pass_turn(
+        &self,
+        id: RoomId,
+        player_id: PlayerId,
+        queue: RoomDeletionQueue,
+    ) -> Option<Turn> {
+        let timeout = self.turn_timeout()?;
+        let turn = Turn {
+            player_id,
+            deadline: queue.clock().unix_time() + timeout.as_secs(),
+        };
+        *self.turn.lock().unwrap() = Some(turn);
+        self.schedule_deletion(id, timeout, queue);
+        Some(turn)
+    }
+
+    /// The endpoint the room's events are delivered to on top of those subscribed to every
+    /// room's, if its creator gave one
+    pub fn webhook(&self) -> Option<RoomWebhook> {
+        self.webhook.lock().unwrap().clone()
+    }
+
+    /// The last payload broadcast in the room, if it's turn-based
+    pub fn game_state(&self) -> Option<GameState> {
+        self.game_state.lock().unwrap().clone()
+    }
+
+    /// Keeps the payload as the state of the room's game, if the room is turn-based,
+    /// returning whether it is
+    pub fn set_game_state(&self, state: GameState) -> bool {
+        if !self.is_turn_based() {
+            return false;
+        }
+        *self.game_state.lock().unwrap() = Some(state);
+        true
+    }
+
+    /// Schedules the deletion of the room in case nobody joins it, after `unjoined_timeout`,
+    /// or once the current turn runs out if the room is turn-based
+    pub fn schedule_unjoined_deletion(
+        &self,
+        id: RoomId,
+        unjoined_timeout: Duration,
+        queue: RoomDeletionQueue,
+    ) {
+        let timeout = match (self.turn_timeout(), self.turn()) {
+            (Some(_), Some(turn)) => {
+                Duration::from_secs(turn.deadline.saturating_sub(queue.clock().unix_time()))
+            }
+            (Some(turn_timeout), None) => turn_timeout,
+            (None, _) => unjoined_timeout,
+        };
+        self.schedule_deletion(id, timeout, queue);
+    }
+
+    /// The room's state, for it to be [restored][Room::restore] elsewhere
+    pub fn state(&self) -> RoomState {
+        RoomState {
+            owner: self.owner(),
+            private: self.is_private(),
+            unlisted: self.visibility() == Visibility::Unlisted,
+            name: self.name(),
+            custom_data: self.custom_data(),
+            starts_at: self.starts_at(),
+            lineage: *self.lineage.lock().unwrap(),
+            game_type: self.game_type(),
+            turn_timeout_secs: self.turn_timeout().map(|timeout| timeout.as_secs()),
+            turn: self.turn(),
+            game_state: self.game_state(),
+            webhook: self.webhook(),
+            latest_seq: self.broadcaster.latest_seq(),
+            invite_uses: self
+                .invite_uses
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(&id, &uses)| (id, uses))
+                .collect(),
+            teams: self
+                .teams
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(&id, team)| (id, team.clone()))
+                .collect(),
+        }
+    }
+
+    /// Takes on the state of a room moved from another instance
+    pub fn restore(&self, state: RoomState) {
+        *self.owner.lock().unwrap() = state.owner;
+        self.set_visibility(match (state.private, state.unlisted) {
+            (true, _) => Visibility::Private,
+            (false, true) => Visibility::Unlisted,
+            (false, false) => Visibility::Public,
+        });
+        *self.name.lock().unwrap() = state.name;
+        self.set_custom_data(state.custom_data);
+        self.set_starts_at(state.starts_at);
+        *self.invite_uses.lock().unwrap() = state.invite_uses.into_iter().collect();
+        *self.teams.lock().unwrap() = state.teams.into_iter().collect();
+        *self.lineage.lock().unwrap() = state.lineage;
+        *self.game_type.lock().unwrap() = state.game_type;
+        *self.turn_timeout.lock().unwrap() = state.turn_timeout_secs.map(Duration::from_secs);
+        *self.turn.lock().unwrap() = state.turn;
+        *self.game_state.lock().unwrap() = state.game_state;
+        *self.webhook.lock().unwrap() = state.webhook;
+        self.broadcaster.resume_from(state.latest_seq);
+    }
+
+    /// The room the line of rematches the room with the given id belongs to started with,
+    /// which bans from the room are kept under
+    pub fn lineage(&self, id: RoomId) -> RoomId {
+        self.lineage.lock().unwrap().unwrap_or(id)
+    }
+
+    /// The state a rematch of the room with the given id starts with: the same owner,
+    /// settings and teams, in the same line of rematches, with none of its invites or
+    /// broadcasts and, if it's turn-based, a game that has yet to start
+    pub fn rematch_state(&self, id: RoomId) -> RoomState {
+        RoomState {
+            invite_uses: Vec::new(),
+            starts_at: None,
+            lineage: Some(self.lineage(id)),
+            turn: None,
+            game_state: None,
+            latest_seq: 0,
+            ..self.state()
+        }
+    }
+
+    /// Disconnects every connection from the room
+    pub fn close(&self) {
+        self.broadcaster.close();
+    }
+
+    /// Disconnects every connection from the room, telling them `reason`
+    pub fn close_with_reason(&self, reason: &'static str) {
+        *self.close_reason.lock().unwrap() = Some(reason);
+        self.close();
+    }
+
+    /// Why the room was closed, if it was given a reason
+    pub fn close_reason(&self) -> Option<&'static str> {
+        *self.close_reason.lock().unwrap()
+    }
+
+    /// Whether the player has a connection to the room
+    pub fn has_player(&self, id: PlayerId) -> bool {
+        self.players.lock().unwrap().contains_key(&Player::new(id))
+    }
+
+    /// The distinct players connected to the room
+    pub fn player_ids(&self) -> Vec<PlayerId> {
+        self.players
+            .lock()
+            .unwrap()
+            .keys()
+            .map(Player::id)
+            .collect()
+    }
+
+    /// The number of distinct players connected to the room
+    pub fn player_count(&self) -> usize {
+        self.players.lock().unwrap().len()
+    }
+
+    /// The approximate number of bytes the room is holding on to
+    pub fn memory_usage(&self) -> usize {
+        self.broadcaster.queued_bytes() + self.broadcaster.replay_bytes()
+    }
+
+    /// The [broadcaster][Broadcaster] that fans messages out to every connection in the room
+    pub fn broadcaster(&self) -> &Broadcaster {
+        &self.broadcaster
+    }
+}
+
+/// A deletion queue for rooms that aren't registered anywhere
+#[cfg(test)]
+fn deletion_queue() -> RoomDeletionQueue {
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy, RoomRegistry};
+
+    let registry = Arc::new(RoomRegistry::new(1));
+    room_deletion_channel(registry, 8, 8, DeletionOverflowPolicy::DirectDelete).0
+}
+
+#[cfg(test)]
+mod add_player {
+    use super::*;
+
+    #[tokio::test]
+    async fn first_player_becomes_owner() {
+        let room = Room::new();
+        let queue = deletion_queue();
+
+        room.add_player(PlayerId::from(1), 1.into());
+        room.add_player(PlayerId::from(2), 2.into());
+        room.remove_player(1_u128.into(), Some(PlayerId::from(1)), 1.into(), &queue);
+
+        assert_eq!(room.owner(), Some(PlayerId::from(1)));
+    }
+}
+
+#[cfg(test)]
+mod schedule_deletion {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy, RoomRegistry};
+
+    /// A registry with one room, whose deletions wait on the returned clock and happen on
+    /// the scheduling task since the queue has no handler
+    fn setup() -> (Arc<RoomRegistry>, RoomId, RoomDeletionQueue, Arc<MockClock>) {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let id = registry.create_room().unwrap();
+        let clock = Arc::new(MockClock::new());
+        let (queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        (registry, id, queue.with_clock(clock.clone()), clock)
+    }
+
+    #[tokio::test]
+    async fn deletes_the_room_once_the_clock_reaches_the_timeout() {
+        let (registry, id, queue, clock) = setup();
+        registry
+            .get_room_for_id(id)
+            .unwrap()
+            .schedule_deletion(id, Duration::from_secs(60), queue);
+
+        clock.advance(Duration::from_secs(59));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelled_deletions_never_happen() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        room.schedule_deletion(id, Duration::from_secs(60), queue);
+        assert!(room.cancel_deletion());
+
+        clock.advance(Duration::from_secs(60));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn tracks_the_time_left_until_deletion() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        assert_eq!(room.time_until_deletion(), None);
+
+        room.schedule_deletion(id, Duration::from_secs(60), queue);
+        clock.advance(Duration::from_secs(15));
+        assert_eq!(room.time_until_deletion(), Some(Duration::from_secs(45)));
+
+        room.cancel_deletion();
+        assert_eq!(room.time_until_deletion(), None);
+    }
+
+    #[tokio::test]
+    async fn times_turns_on_the_queues_clock() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        room.restore(RoomState {
+            turn_timeout_secs: Some(60),
+            ..Default::default()
+        });
+        clock.advance(Duration::from_secs(86400));
+
+        let turn = room
+            .pass_turn(id, PlayerId::from(1), queue.clone())
+            .unwrap();
+        assert_eq!(turn.deadline, clock.unix_time() + 60);
+
+        clock.advance(Duration::from_secs(20));
+        room.schedule_unjoined_deletion(id, Duration::from_secs(600), queue);
+        assert_eq!(room.time_until_deletion(), Some(Duration::from_secs(40)));
+    }
+
+    #[tokio::test]
+    async fn rescheduling_supersedes_a_deletion_already_requested() {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let id = registry.create_room().unwrap();
+        let clock = Arc::new(MockClock::new());
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let queue = queue.with_clock(clock.clone());
+        let room = registry.get_room_for_id(id).unwrap();
+
+        room.schedule_deletion(id, Duration::from_secs(60), queue.clone());
+        clock.advance(Duration::from_secs(60));
+        tokio::task::yield_now().await;
+        assert_eq!(queue.depth(), 1);
+        room.schedule_deletion(id, Duration::from_secs(60), queue.clone());
+        tokio::spawn(handler.watch());
+        while queue.depth() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(registry.room_count(), 1);
+        assert_eq!(queue.stale_count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod chat_recipients {
+    use super::*;
+
+    /// A room with players 1 and 2 on the red team and 3 on blue, each player `n` connected
+    /// as connection `n`
+    fn room_with_teams() -> Room {
+        let room = Room::new();
+        for (id, team) in [(1, "red"), (2, "red"), (3, "blue")] {
+            room.add_player(PlayerId::from(id), (id as u64).into());
+            room.set_team(PlayerId::from(id), team.to_owned());
+        }
+        room
+    }
+
+    fn sorted(recipients: Option<Vec<ConnectionId>>) -> Vec<ConnectionId> {
+        let mut recipients = recipients.unwrap();
+        recipients.sort();
+        recipients
+    }
+
+    #[test]
+    fn sends_room_chat_to_everyone() {
+        let room = room_with_teams();
+
+        assert_eq!(
+            room.chat_recipients(1.into(), Some(PlayerId::from(1)), &[], &ChatChannel::Room),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn sends_team_chat_to_the_senders_team() {
+        let room = room_with_teams();
+
+        let recipients = room
+            .chat_recipients(1.into(), Some(PlayerId::from(1)), &[], &ChatChannel::Team)
+            .unwrap();
+        assert_eq!(sorted(recipients), [1.into(), 2.into()]);
+    }
+
+    #[test]
+    fn refuses_team_chat_from_players_without_a_team() {
+        let room = room_with_teams();
+        room.add_player(PlayerId::from(4), 4.into());
+
+        assert_eq!(
+            room.chat_recipients(4.into(), Some(PlayerId::from(4)), &[], &ChatChannel::Team),
+            Err(ChatRoutingError::NoTeam)
+        );
+        assert_eq!(
+            room.chat_recipients(5.into(), None, &[], &ChatChannel::Team),
+            Err(ChatRoutingError::NoTeam)
+        );
+    }
+
+    #[test]
+    fn sends_party_chat_to_the_members_in_the_room() {
+        let room = room_with_teams();
+        let party = [PlayerId::from(1), PlayerId::from(3), PlayerId::from(9)];
+
+        let recipients = room
+            .chat_recipients(
+                1.into(),
+                Some(PlayerId::from(1)),
+                &party,
+                &ChatChannel::Party,
+            )
+            .unwrap();
+        assert_eq!(sorted(recipients), [1.into(), 3.into()]);
+        assert_eq!(
+            room.chat_recipients(
+                2.into(),
+                Some(PlayerId::from(2)),
+                &party,
+                &ChatChannel::Party
+            ),
+            Err(ChatRoutingError::NoParty)
+        );
+    }
+
+    #[test]
+    fn sends_whispers_to_the_recipient_and_sender() {
+        let room = room_with_teams();
+        room.add_player(PlayerId::from(3), 5.into());
+
+        let recipients = room
+            .chat_recipients(
+                1.into(),
+                Some(PlayerId::from(1)),
+                &[],
+                &ChatChannel::Whisper(PlayerId::from(3)),
+            )
+            .unwrap();
+        assert_eq!(sorted(recipients), [1.into(), 3.into(), 5.into()]);
+    }
+
+    #[test]
+    fn refuses_whispers_to_players_who_arent_in_the_room() {
+        let room = room_with_teams();
+
+        assert_eq!(
+            room.chat_recipients(
+                1.into(),
+                Some(PlayerId::from(1)),
+                &[],
+                &ChatChannel::Whisper(PlayerId::from(9))
+            ),
+            Err(ChatRoutingError::NotInRoom(PlayerId::from(9)))
+        );
+    }
+}
+
+#[cfg(test)]
+mod hold_seats {
+    use super::*;
+
+    #[test]
+    fn holds_seats_only_if_every_player_fits() {
+        let room = Room::new().with_capacity(Some(3));
+        room.add_player(PlayerId::from(1), 1.into());
+        let until = Instant::now() + Duration::from_secs(60);
+
+        assert!(!room.hold_seats(&[1, 2, 3, 4].map(PlayerId::from), until));
+        assert!(room.hold_seats(&[1, 2, 3].map(PlayerId::from), until));
+
+        assert!(room.has_seat_for(Some(PlayerId::from(2))));
+        assert!(!room.has_seat_for(Some(PlayerId::from(4))));
+        assert!(room.has_seat_for(None));
+    }
+
+    #[test]
+    fn frees_seats_once_their_hold_runs_out() {
+        let room = Room::new().with_capacity(Some(1));
+
+        assert!(room.hold_seats(&[PlayerId::from(1)], Instant::now()));
+        assert!(room.has_seat_for(Some(PlayerId::from(2))));
+    }
+}
+
+#[cfg(test)]
+mod is_muted {
+    use super::*;
+
+    #[test]
+    fn lifts_mutes_once_they_run_out() {
+        let room = Room::new();
+
+        room.mute(PlayerId::from(1), None);
+        room.mute(PlayerId::from(2), Some(Duration::ZERO));
+
+        assert!(room.is_muted(PlayerId::from(1)));
+        assert!(!room.is_muted(PlayerId::from(2)));
+        assert!(!room.is_muted(PlayerId::from(3)));
+
+        room.unmute(PlayerId::from(1));
+        assert!(!room.is_muted(PlayerId::from(1)));
+    }
+
+    #[test]
+    fn keeps_mutes_too_long_to_end_for_good() {
+        let room = Room::new();
+
+        room.mute(PlayerId::from(1), Some(Duration::from_secs(u64::MAX)));
+
+        assert!(room.is_muted(PlayerId::from(1)));
+    }
+}
+
+#[cfg(test)]
+mod record_chat {
+    use super::*;
+
+    #[test]
+    fn keeps_only_the_most_recent_messages() {
+        let room = Room::new();
+        for sent_at in 0..CHAT_SCROLLBACK_LEN as u64 + 2 {
+            room.record_chat(ChatMessage {
+                from: 1.into(),
+                from_player: None,
+                text: "gg".to_owned(),
+                channel: ChatChannel::Room,
+                sent_at,
+            });
+        }
+
+        let history = room.chat_history();
+        assert_eq!(history.len(), CHAT_SCROLLBACK_LEN);
+        assert_eq!(history[0].sent_at, 2);
+    }
+}
+
+#[cfg(test)]
+mod redeem_invite {
+    use super::*;
+    use crate::invites::{InviteSigner, DEFAULT_INVITE_TTL};
+
+    #[test]
+    fn allows_invites_up_to_their_maximum_uses() {
+        let room = Room::new();
+        let signer = InviteSigner::random();
+        let room_id = RoomId::from(1);
+        let invite = signer.mint(room_id, DEFAULT_INVITE_TTL, Some(2));
+        let claims = signer.verify(&invite.token, room_id).unwrap();
+
+        assert_eq!(room.redeem_invite(&claims, None), Ok(()));
+        assert_eq!(room.redeem_invite(&claims, None), Ok(()));
+        assert_eq!(room.redeem_invite(&claims, None), Err(InviteError::UsedUp));
+    }
+
+    #[test]
+    fn holds_seats_for_the_invited_player() {
+        let room = Room::new();
+        let signer = InviteSigner::random();
+        let room_id = RoomId::from(1);
+        let invite = signer.mint_seat(room_id, PlayerId::from(1), None, DEFAULT_INVITE_TTL);
+        let claims = signer.verify(&invite.token, room_id).unwrap();
+
+        assert_eq!(room.redeem_invite(&claims, Some(PlayerId::from(1))), Ok(()));
+        assert_eq!(room.redeem_invite(&claims, Some(PlayerId::from(1))), Ok(()));
+        assert_eq!(
+            room.redeem_invite(&claims, Some(PlayerId::from(2))),
+            Err(InviteError::WrongPlayer)
+        );
+    }
+}
+
+#[cfg(test)]
+mod remove_player {
+    use super::*;
+
+    use crate::clock::MockClock;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy, RoomRegistry};
+
+    #[tokio::test]
+    async fn keeps_player_until_last_connection_closes() {
+        let room = Room::new();
+        let queue = deletion_queue();
+        let id = PlayerId::from(1);
+
+        room.add_player(id, 1.into());
+        room.add_player(id, 2.into());
+        room.remove_player(1_u128.into(), Some(id), 1.into(), &queue);
+        assert_eq!(room.player_count(), 1);
+
+        room.remove_player(1_u128.into(), Some(id), 2.into(), &queue);
+        assert_eq!(room.player_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn ignores_unknown_players() {
+        let room = Room::new();
+        room.remove_player(
+            1_u128.into(),
+            Some(PlayerId::from(1)),
+            1.into(),
+            &deletion_queue(),
+        );
+        assert_eq!(room.player_count(), 0);
+    }
+
+    fn setup() -> (Arc<RoomRegistry>, RoomId, RoomDeletionQueue, Arc<MockClock>) {
+        let registry =
+            Arc::new(RoomRegistry::new(4).with_room_empty_timeout(Some(Duration::from_secs(300))));
+        let id = registry.create_room().unwrap();
+        let clock = Arc::new(MockClock::new());
+        let (queue, _handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        (registry, id, queue.with_clock(clock.clone()), clock)
+    }
+
+    #[tokio::test]
+    async fn deletes_the_room_once_it_has_been_empty_for_the_timeout() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        let (connection, _outbound) = room.broadcaster().subscribe();
+        room.add_player(PlayerId::from(1), connection);
+
+        room.broadcaster().unsubscribe(connection);
+        room.remove_player(id, Some(PlayerId::from(1)), connection, &queue);
+        clock.advance(Duration::from_secs(299));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_room_while_anyone_is_connected() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        let (anonymous, _outbound) = room.broadcaster().subscribe();
+        let (connection, _outbound) = room.broadcaster().subscribe();
+        room.add_player(PlayerId::from(1), connection);
+
+        room.broadcaster().unsubscribe(connection);
+        room.remove_player(id, Some(PlayerId::from(1)), connection, &queue);
+        clock.advance(Duration::from_secs(300));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+
+        room.broadcaster().unsubscribe(anonymous);
+        room.remove_player(id, None, anonymous, &queue);
+        clock.advance(Duration::from_secs(300));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_turn_based_rooms_until_the_turn_runs_out() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        room.restore(RoomState {
+            turn_timeout_secs: Some(3600),
+            ..Default::default()
+        });
+        room.pass_turn(id, PlayerId::from(1), queue.clone());
+        let (connection, _outbound) = room.broadcaster().subscribe();
+        room.add_player(PlayerId::from(1), connection);
+        clock.advance(Duration::from_secs(3600));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+
+        room.broadcaster().unsubscribe(connection);
+        assert!(room.remove_player(id, Some(PlayerId::from(1)), connection, &queue));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn leaves_turn_based_rooms_alone_while_the_turn_lasts() {
+        let (registry, id, queue, clock) = setup();
+        let room = registry.get_room_for_id(id).unwrap();
+        room.restore(RoomState {
+            turn_timeout_secs: Some(3600),
+            ..Default::default()
+        });
+        room.pass_turn(id, PlayerId::from(1), queue.clone());
+        let (connection, _outbound) = room.broadcaster().subscribe();
+
+        room.broadcaster().unsubscribe(connection);
+        assert!(!room.remove_player(id, None, connection, &queue));
+        clock.advance(Duration::from_secs(3599));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod pass_turn {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_takes_turns_in_turn_based_rooms() {
+        let room = Room::new();
+        assert_eq!(
+            room.pass_turn(1_u128.into(), PlayerId::from(1), deletion_queue()),
+            None
+        );
+
+        room.restore(RoomState {
+            turn_timeout_secs: Some(86400),
+            ..Default::default()
+        });
+        let turn = room
+            .pass_turn(1_u128.into(), PlayerId::from(1), deletion_queue())
+            .unwrap();
+        assert_eq!(turn.player_id, PlayerId::from(1));
+        assert!(turn.deadline >= unix_time() + 86400);
+        assert_eq!(room.turn(), Some(turn));
+        assert_eq!(room.rematch_state(1_u128.into()).turn, None);
+    }
+}
+
+#[cfg(test)]
+mod record_activity {
+    use super::*;
+
+    #[test]
+    fn asks_for_a_refresh_once_a_minute() {
+        let room = Room::new();
+        assert_eq!(room.last_active_at(), None);
+
+        assert!(room.record_activity());
+        let active_at = room.last_active_at().unwrap();
+        assert!(active_at.abs_diff(unix_time()) <= 1);
+
+        room.last_active_at.store(active_at - 60, Ordering::Relaxed);
+        assert!(room.record_activity());
+    }
+}
+
+#[cfg(test)]
+mod status {
+    use super::*;
+
+    #[test]
+    fn rooms_yet_to_open_are_scheduled() {
+        let room = Room::new();
+        room.set_starts_at(Some(unix_time() + 60));
+
+        assert_eq!(room.status(), RoomStatus::Scheduled);
+    }
+
+    #[test]
+    fn rooms_with_every_seat_taken_are_full() {
+        let room = Room::new().with_capacity(Some(2));
+        room.add_player(PlayerId::from(1), 1.into());
+        assert_eq!(room.status(), RoomStatus::Open);
+
+        room.add_player(PlayerId::from(2), 2.into());
+        assert_eq!(room.status(), RoomStatus::Full);
+    }
+
+    #[tokio::test]
+    async fn only_rooms_everyone_left_are_closing() {
+        let room = Room::new().with_empty_timeout(Some(Duration::from_secs(60)));
+        let id = RoomId::from(1_u128);
+        room.schedule_unjoined_deletion(id, Duration::from_secs(60), deletion_queue());
+        assert_eq!(room.status(), RoomStatus::Open);
+        assert!(room.deletes_at().is_some());
+
+        let connection = ConnectionId::from(1);
+        room.add_player(PlayerId::from(1), connection);
+        room.cancel_deletion();
+        room.remove_player(id, Some(PlayerId::from(1)), connection, &deletion_queue());
+
+        assert_eq!(room.status(), RoomStatus::Closing);
+    }
+}
+
+#[cfg(test)]
+mod restore {
+    use super::*;
+    use crate::invites::InviteSigner;
+
+    #[test]
+    fn carries_the_state_over() {
+        let signer = InviteSigner::random();
+        let room_id = RoomId::from(1_u128);
+        let token = signer.mint(room_id, Duration::from_secs(60), Some(1)).token;
+        let invite = signer.verify(&token, room_id).unwrap();
+        let room = Room::new();
+        room.add_player(PlayerId::from(1), 1.into());
+        room.set_team(PlayerId::from(1), "red".to_owned());
+        room.set_private(true);
+        room.set_name("Friday night".to_owned());
+        room.set_custom_data(
+            serde_json::json!({ "map": "de_dust2" })
+                .as_object()
+                .unwrap()
+                .clone(),
+        );
+        room.redeem_invite(&invite, None).unwrap();
+        room.broadcaster().broadcast_sequenced(|seq| seq).unwrap();
+
+        let moved = Room::new();
+        moved.restore(room.state());
+
+        assert_eq!(moved.state(), room.state());
+        assert_eq!(moved.broadcaster().latest_seq(), 1);
+        assert_eq!(moved.player_count(), 0);
+        assert_eq!(moved.redeem_invite(&invite, None), Err(InviteError::UsedUp));
+    }
+
+    #[test]
+    fn keeps_unlisted_rooms_unlisted() {
+        let room = Room::new();
+        room.set_visibility(Visibility::Unlisted);
+
+        let moved = Room::new();
+        moved.restore(room.state());
+
+        assert_eq!(moved.visibility(), Visibility::Unlisted);
+        assert!(!moved.is_private());
+    }
+}
+
+#[cfg(test)]
+mod rematch_state {
+    use super::*;
+
+    #[test]
+    fn keeps_rematches_in_the_original_rooms_lineage() {
+        let original = Room::new();
+        original.set_name("Friday night".to_owned());
+
+        let rematch = Room::new();
+        rematch.restore(original.rematch_state(1_u128.into()));
+        let second_rematch = Room::new();
+        second_rematch.restore(rematch.rematch_state(2_u128.into()));
+
+        assert_eq!(rematch.name().as_deref(), Some("Friday night"));
+        assert_eq!(original.lineage(1_u128.into()), RoomId::from(1_u128));
+        assert_eq!(second_rematch.lineage(3_u128.into()), RoomId::from(1_u128));
     }
 }
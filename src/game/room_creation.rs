@@ -0,0 +1,334 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tracing::{info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::game::{RoomCreationError, RoomDeletionQueue, RoomId, RoomRegistry, RoomState};
+
+/// How long the outcome of a creation request can be polled for once it has been submitted
+const TICKET_RETENTION: Duration = Duration::from_secs(300);
+
+/// An ID that identifies a queued [room creation][RoomCreationQueue] request so its outcome can be polled
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct CreationTicket(Uuid);
+
+impl std::fmt::Display for CreationTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The outcome of a room creation request
+#[derive(Debug, PartialEq, Clone)]
+pub enum CreationStatus {
+    Pending,
+    Created(RoomId),
+    Failed(RoomCreationError),
+    /// The request's deadline passed before the queue got to it
+    Expired,
+}
+
+/// Raised when a creation request can't be queued
+#[derive(Error, Debug, PartialEq)]
+pub enum SubmitCreationError {
+    #[error("The room creation queue is full")]
+    QueueFull,
+    #[error("The room creation worker has stopped")]
+    WorkerStopped,
+}
+
+#[derive(Debug)]
+struct CreationJob {
+    /// What the room starts out as, such as private so only invited players can join
+    state: RoomState,
+    deadline: Instant,
+    status: watch::Sender<CreationStatus>,
+}
+
+#[derive(Debug, Default)]
+struct Tickets {
+    statuses: HashMap<CreationTicket, watch::Receiver<CreationStatus>>,
+    submitted: VecDeque<(Instant, CreationTicket)>,
+}
+
+impl Tickets {
+    fn prune(&mut self, now: Instant) {
+        while let Some(&(submitted_at, ticket)) = self.submitted.front() {
+            if now.duration_since(submitted_at) < TICKET_RETENTION {
+                break;
+            }
+            self.submitted.pop_front();
+            self.statuses.remove(&ticket);
+        }
+    }
+}
+
+/// A bounded queue of room creation requests, each of which must be handled before its deadline
+///
+/// Callers can wait a short time for the outcome of a request and fall back to
+/// polling it by [ticket][CreationTicket], so they never wait on a contended registry
+/// for longer than they are willing to.
+#[derive(Debug, Clone)]
+pub struct RoomCreationQueue {
+    sender: Sender<CreationJob>,
+    tickets: Arc<Mutex<Tickets>>,
+    deadline: Duration,
+}
+
+/// Creates rooms as requests arrive on its [queue][RoomCreationQueue]
+#[derive(Debug)]
+pub struct RoomCreationWorker {
+    receiver: Receiver<CreationJob>,
+    registry: Arc<RoomRegistry>,
+    deletion_queue: RoomDeletionQueue,
+    unjoined_room_timeout: Duration,
+}
+
+/// Creates a creation queue holding up to `capacity` requests that each expire after `deadline`,
+/// along with the worker that serves it
+pub fn room_creation_channel(
+    registry: Arc<RoomRegistry>,
+    deletion_queue: RoomDeletionQueue,
+    unjoined_room_timeout: Duration,
+    capacity: usize,
+    deadline: Duration,
+) -> (RoomCreationQueue, RoomCreationWorker) {
+    let (sender, receiver) = channel(capacity);
+    let queue = RoomCreationQueue {
+        sender,
+        tickets: Default::default(),
+        deadline,
+    };
+    let worker = RoomCreationWorker {
+        receiver,
+        registry,
+        deletion_queue,
+        unjoined_room_timeout,
+    };
+    (queue, worker)
+}
+
+impl RoomCreationQueue {
+    /// Queues a request to create a room, returning the ticket its outcome can be polled with
+    pub fn submit(&self) -> Result<CreationTicket, SubmitCreationError> {
+        self.submit_with(RoomState::default())
+    }
+
+    /// Queues a request to create a room that is private from the moment it exists
+    pub fn submit_private(&self) -> Result<CreationTicket, SubmitCreationError> {
+        self.submit_with(RoomState {
+            private: true,
+            ..Default::default()
+        })
+    }
+
+    /// Queues a request to create a room that has the state from the moment it exists, such
+    /// as a rematch of another room
+    #[instrument(skip(self))]
+    pub fn submit_with(&self, state: RoomState) -> Result<CreationTicket, SubmitCreationError> {
+        let (status, receiver) = watch::channel(CreationStatus::Pending);
+        let now = Instant::now();
+        let job = CreationJob {
+            state,
+            deadline: now + self.deadline,
+            status,
+        };
+
+        match self.sender.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(event = "room_creation_queue_full");
+                return Err(SubmitCreationError::QueueFull);
+            }
+            Err(TrySendError::Closed(_)) => return Err(SubmitCreationError::WorkerStopped),
+        }
+
+        let ticket = CreationTicket(Uuid::new_v4());
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.prune(now);
+        tickets.statuses.insert(ticket, receiver);
+        tickets.submitted.push_back((now, ticket));
+        Ok(ticket)
+    }
+
+    /// The current outcome of the request, or `None` if the ticket is unknown or has been forgotten
+    pub fn status(&self, ticket: CreationTicket) -> Option<CreationStatus> {
+        let tickets = self.tickets.lock().unwrap();
+        let status = tickets.statuses.get(&ticket)?;
+        let status = status.borrow().clone();
+        Some(status)
+    }
+
+    /// Waits up to `timeout` for the request to be handled, returning its outcome at that point
+    pub async fn wait(&self, ticket: CreationTicket, timeout: Duration) -> Option<CreationStatus> {
+        let mut receiver = self.tickets.lock().unwrap().statuses.get(&ticket)?.clone();
+        let _ = tokio::time::timeout(
+            timeout,
+            receiver.wait_for(|status| *status != CreationStatus::Pending),
+        )
+        .await;
+
+        let status = receiver.borrow().clone();
+        Some(status)
+    }
+
+    /// The number of requests waiting to be handled
+    pub fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+}
+
+impl RoomCreationWorker {
+    /// Creates rooms as requests arrive until every [queue][RoomCreationQueue] has been dropped
+    pub async fn watch(mut self) {
+        while let Some(job) = self.receiver.recv().await {
+            self.handle(job);
+        }
+        info!(event = "room_creation_worker_stopped");
+    }
+
+    #[instrument(skip_all)]
+    fn handle(&self, job: CreationJob) {
+        if Instant::now() > job.deadline {
+            warn!(event = "room_creation_request_expired");
+            job.status.send_replace(CreationStatus::Expired);
+            return;
+        }
+
+        let status = match self
+            .registry
+            .create_room_with(|room| room.restore(job.state))
+        {
+            Ok(id) => {
+                if let Some(room) = self.registry.get_room_for_id(id) {
+                    room.schedule_unjoined_deletion(
+                        id,
+                        self.unjoined_room_timeout,
+                        self.deletion_queue.clone(),
+                    );
+                    self.registry.room_updated(id);
+                }
+                CreationStatus::Created(id)
+            }
+            Err(e) => CreationStatus::Failed(e),
+        };
+        job.status.send_replace(status);
+    }
+}
+
+#[cfg(test)]
+fn creation_channel(
+    capacity: usize,
+    deadline: Duration,
+) -> (Arc<RoomRegistry>, RoomCreationQueue, RoomCreationWorker) {
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy};
+
+    let registry = Arc::new(RoomRegistry::new(4));
+    let (deletion_queue, _) =
+        room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+    let (queue, worker) = room_creation_channel(
+        registry.clone(),
+        deletion_queue,
+        Duration::from_secs(60),
+        capacity,
+        deadline,
+    );
+    (registry, queue, worker)
+}
+
+#[cfg(test)]
+mod submit {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_room_once_worker_runs() {
+        let (registry, queue, worker) = creation_channel(4, Duration::from_secs(1));
+        tokio::spawn(worker.watch());
+
+        let ticket = queue.submit().unwrap();
+        let status = queue.wait(ticket, Duration::from_secs(1)).await;
+
+        let Some(CreationStatus::Created(id)) = status else {
+            panic!("Expected the room to be created, got {status:?}");
+        };
+        assert!(registry.get_room_for_id(id).is_some());
+    }
+
+    #[tokio::test]
+    async fn creates_private_rooms_on_request() {
+        let (registry, queue, worker) = creation_channel(4, Duration::from_secs(1));
+        tokio::spawn(worker.watch());
+
+        let ticket = queue.submit_private().unwrap();
+        let status = queue.wait(ticket, Duration::from_secs(1)).await;
+
+        let Some(CreationStatus::Created(id)) = status else {
+            panic!("Expected the room to be created, got {status:?}");
+        };
+        assert!(registry.get_room_for_id(id).unwrap().is_private());
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_when_queue_is_full() {
+        let (_registry, queue, _worker) = creation_channel(1, Duration::from_secs(1));
+
+        queue.submit().unwrap();
+        assert_eq!(queue.submit(), Err(SubmitCreationError::QueueFull));
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_once_worker_has_stopped() {
+        let (_registry, queue, worker) = creation_channel(1, Duration::from_secs(1));
+        drop(worker);
+
+        assert_eq!(queue.submit(), Err(SubmitCreationError::WorkerStopped));
+    }
+}
+
+#[cfg(test)]
+mod wait {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_pending_if_not_handled_in_time() {
+        let (_registry, queue, _worker) = creation_channel(1, Duration::from_secs(1));
+
+        let ticket = queue.submit().unwrap();
+        let status = queue.wait(ticket, Duration::from_millis(10)).await;
+
+        assert_eq!(status, Some(CreationStatus::Pending));
+        assert_eq!(queue.depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn expires_requests_handled_after_their_deadline() {
+        let (registry, queue, worker) = creation_channel(1, Duration::ZERO);
+
+        let ticket = queue.submit().unwrap();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        tokio::spawn(worker.watch());
+
+        let status = queue.wait(ticket, Duration::from_secs(1)).await;
+        assert_eq!(status, Some(CreationStatus::Expired));
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn returns_none_for_unknown_tickets() {
+        let (_registry, queue, _worker) = creation_channel(1, Duration::from_secs(1));
+
+        let status = queue
+            .wait(CreationTicket(Uuid::new_v4()), Duration::ZERO)
+            .await;
+        assert_eq!(status, None);
+    }
+}
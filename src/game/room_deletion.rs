@@ -0,0 +1,645 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::clock::{system_clock, Clock};
+use crate::faults;
+use crate::game::{Room, RoomId, RoomRegistry};
+
+/// What a [deletion queue][RoomDeletionQueue] does with a request that arrives while it is full
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DeletionOverflowPolicy {
+    /// Park the request in an unbounded overflow buffer the handler drains with its next batch
+    GrowAndWarn,
+    /// Bypass the handler and remove the room from the registry on the caller's task
+    DirectDelete,
+}
+
+impl std::fmt::Display for DeletionOverflowPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GrowAndWarn => f.write_str("grow"),
+            Self::DirectDelete => f.write_str("direct_delete"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("Unknown deletion overflow policy {0:?}, expected \"grow\" or \"direct_delete\"")]
+pub struct UnknownOverflowPolicy(String);
+
+impl FromStr for DeletionOverflowPolicy {
+    type Err = UnknownOverflowPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grow" => Ok(Self::GrowAndWarn),
+            "direct_delete" => Ok(Self::DirectDelete),
+            _ => Err(UnknownOverflowPolicy(s.to_owned())),
+        }
+    }
+}
+
+/// How long a [supervised][RoomDeletionHandler::supervise] handler that panicked is left
+/// stopped before it's restarted, so one that panics on every request doesn't spin
+const HANDLER_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// When a requested deletion goes ahead
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum DeletionCondition {
+    /// Whatever state the room is in, such as when it's been closed
+    Always,
+    /// Only if nobody is connected to the room and its deletion hasn't been scheduled or
+    /// cancelled since, its [deletion generation][Room::deletion_generation] still being the
+    /// one the request was made with
+    Idle { generation: u64 },
+}
+
+impl DeletionCondition {
+    /// Why the room should be kept despite the request, if it should
+    fn stale_because(self, room: &Room) -> Option<&'static str> {
+        let Self::Idle { generation } = self else {
+            return None;
+        };
+        if room.deletion_generation() != generation {
+            Some("rescheduled")
+        } else if room.broadcaster().subscriber_count() > 0 {
+            Some("occupied")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DeletionQueueState {
+    capacity: usize,
+    policy: DeletionOverflowPolicy,
+    registry: Arc<RoomRegistry>,
+    /// Rooms with a deletion request that hasn't been handled yet, along with when it goes
+    /// ahead, used to coalesce duplicates
+    pending: Mutex<HashMap<RoomId, DeletionCondition>>,
+    overflow: Mutex<Vec<RoomId>>,
+    overflow_count: AtomicU64,
+    /// How many requests were for rooms no longer idle by the time they were handled
+    stale_count: AtomicU64,
+    /// How many times the handler has been restarted after panicking
+    restart_count: AtomicU64,
+}
+
+/// The sending half of the channel through which rooms ask to be removed from the registry
+///
+/// Requesting a deletion never blocks: duplicate requests for a room are coalesced,
+/// and requests that arrive while the channel is full are handled according to the
+/// queue's [overflow policy][DeletionOverflowPolicy].
+#[derive(Debug, Clone)]
+pub struct RoomDeletionQueue {
+    sender: Sender<RoomId>,
+    state: Arc<DeletionQueueState>,
+    /// The clock deletions scheduled through the queue wait on
+    clock: Arc<dyn Clock>,
+}
+
+/// Removes rooms from the registry as their deletion requests arrive
+#[derive(Debug)]
+pub struct RoomDeletionHandler {
+    inbox: tokio::sync::Mutex<Inbox>,
+    state: Arc<DeletionQueueState>,
+    batch_size: usize,
+}
+
+/// The requests a handler has received, kept apart from the task handling them so a handler
+/// restarted after a panic picks up where it left off
+#[derive(Debug)]
+struct Inbox {
+    receiver: Receiver<RoomId>,
+    /// Requests received but not yet handled
+    batch: Vec<RoomId>,
+    /// The room whose request is being handled, left behind if handling it panics
+    handling: Option<RoomId>,
+}
+
+/// Creates a deletion queue holding up to `capacity` requests along with the handler that drains it
+/// in batches of up to `batch_size`
+pub fn room_deletion_channel(
+    registry: Arc<RoomRegistry>,
+    capacity: usize,
+    batch_size: usize,
+    policy: DeletionOverflowPolicy,
+) -> (RoomDeletionQueue, RoomDeletionHandler) {
+    let (sender, receiver) = channel(capacity);
+    let state = Arc::new(DeletionQueueState {
+        capacity,
+        policy,
+        registry,
+        pending: Default::default(),
+        overflow: Default::default(),
+        overflow_count: AtomicU64::new(0),
+        stale_count: AtomicU64::new(0),
+        restart_count: AtomicU64::new(0),
+    });
+    let queue = RoomDeletionQueue {
+        sender,
+        state: state.clone(),
+        clock: system_clock(),
+    };
+    let batch_size = batch_size.max(1);
+    let handler = RoomDeletionHandler {
+        inbox: tokio::sync::Mutex::new(Inbox {
+            receiver,
+            batch: Vec::with_capacity(batch_size),
+            handling: None,
+        }),
+        state,
+        batch_size,
+    };
+    (queue, handler)
+}
+
+impl DeletionQueueState {
+    fn delete(&self, id: RoomId) {
+        let condition = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .unwrap_or(DeletionCondition::Always);
+        self.registry.delete_room_if(id, |room| {
+            let Some(reason) = condition.stale_because(room) else {
+                return true;
+            };
+            info!(event = "stale_room_deletion_skipped", room_id = %id, reason);
+            self.stale_count.fetch_add(1, Ordering::Relaxed);
+            false
+        });
+    }
+
+    /// Forgets the request for the room a handler panicked on, so the room's next request
+    /// isn't coalesced into it and dropped
+    fn abandon(&self, id: RoomId) {
+        // Panicking while the lock was held poisons it without leaving the map half-changed
+        self.pending.clear_poison();
+        self.pending.lock().unwrap().remove(&id);
+        warn!(event = "room_deletion_abandoned", room_id = %id);
+    }
+
+    fn take_overflow(&self) -> Vec<RoomId> {
+        std::mem::take(&mut *self.overflow.lock().unwrap())
+    }
+}
+
+impl RoomDeletionQueue {
+    /// Has deletions [scheduled][crate::game::Room::schedule_deletion] through the queue wait
+    /// on `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Asks for the room to be removed from the registry, however busy it is
+    #[instrument(skip(self))]
+    pub fn request_deletion(&self, id: RoomId) {
+        self.request(id, DeletionCondition::Always);
+    }
+
+    /// Asks for the room to be removed from the registry if it's still idle when the request
+    /// is handled, nobody having joined and its deletion not having been scheduled or
+    /// cancelled since it was at `generation`
+    #[instrument(skip(self))]
+    pub fn request_idle_deletion(&self, id: RoomId, generation: u64) {
+        self.request(id, DeletionCondition::Idle { generation });
+    }
+
+    fn request(&self, id: RoomId, condition: DeletionCondition) {
+        if faults::drop_deletion_request(id) {
+            return;
+        }
+        match self.state.pending.lock().unwrap().entry(id) {
+            // The room is already on its way out, though an unconditional request for it
+            // mustn't be held back by a conditional one
+            Entry::Occupied(mut pending) => {
+                if *pending.get() != DeletionCondition::Always {
+                    pending.insert(condition);
+                }
+                return;
+            }
+            Entry::Vacant(pending) => {
+                pending.insert(condition);
+            }
+        }
+
+        match self.sender.try_send(id) {
+            Ok(()) => {}
+            Err(TrySendError::Full(id)) => {
+                self.state.overflow_count.fetch_add(1, Ordering::Relaxed);
+                match self.state.policy {
+                    DeletionOverflowPolicy::GrowAndWarn => {
+                        let mut overflow = self.state.overflow.lock().unwrap();
+                        overflow.push(id);
+                        warn!(
+                            event = "deletion_queue_overflowed",
+                            capacity = self.state.capacity,
+                            overflow_depth = overflow.len()
+                        );
+                    }
+                    DeletionOverflowPolicy::DirectDelete => {
+                        warn!(
+                            event = "deletion_queue_overflowed",
+                            capacity = self.state.capacity
+                        );
+                        self.state.delete(id);
+                    }
+                }
+            }
+            Err(TrySendError::Closed(id)) => {
+                warn!(event = "deletion_handler_unavailable");
+                self.state.delete(id);
+            }
+        }
+    }
+
+    /// The number of requests waiting to be handled, including any held in the overflow buffer
+    pub fn depth(&self) -> usize {
+        let queued = self.state.capacity - self.sender.capacity();
+        queued + self.state.overflow.lock().unwrap().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.state.capacity
+    }
+
+    /// The number of requests that have arrived while the queue was full
+    pub fn overflow_count(&self) -> u64 {
+        self.state.overflow_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests skipped because their room was no longer idle when they were
+    /// handled
+    pub fn stale_count(&self) -> u64 {
+        self.state.stale_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of times the handler has been restarted after panicking
+    pub fn restart_count(&self) -> u64 {
+        self.state.restart_count.load(Ordering::Relaxed)
+    }
+}
+
+impl RoomDeletionHandler {
+    /// Deletes rooms as requests arrive until every [queue][RoomDeletionQueue] has been dropped
+    pub async fn watch(self) {
+        self.run(&CancellationToken::new()).await;
+    }
+
+    /// Deletes rooms as requests arrive until `shutdown` is cancelled or every
+    /// [queue][RoomDeletionQueue] has been dropped, restarting the handler if it panics
+    ///
+    /// Once `shutdown` is cancelled the handler deletes the rooms already requested and stops,
+    /// and rooms requested after that are deleted on the requester's task.
+    pub async fn supervise(self, shutdown: CancellationToken) {
+        let handler = Arc::new(self);
+        loop {
+            let task = tokio::spawn({
+                let handler = handler.clone();
+                let shutdown = shutdown.clone();
+                async move { handler.run(&shutdown).await }
+            });
+            match task.await {
+                Ok(()) => return,
+                Err(e) if e.is_panic() => {
+                    handler.state.restart_count.fetch_add(1, Ordering::Relaxed);
+                    error!(
+                        event = "room_deletion_handler_panicked",
+                        restart_delay = ?HANDLER_RESTART_DELAY,
+                        "Rooms won't be deleted until the handler restarts"
+                    );
+                }
+                // The runtime is shutting down
+                Err(_) => return,
+            }
+            // A handler restarted after shutdown stops as soon as it's handled what's left
+            tokio::select! {
+                _ = tokio::time::sleep(HANDLER_RESTART_DELAY) => {}
+                _ = shutdown.cancelled() => {}
+            }
+        }
+    }
+
+    async fn run(&self, shutdown: &CancellationToken) {
+        let mut inbox = self.inbox.lock().await;
+        let Inbox {
+            receiver,
+            batch,
+            handling,
+        } = &mut *inbox;
+        if let Some(id) = handling.take() {
+            self.state.abandon(id);
+        }
+        self.delete_batch(batch, handling);
+        let mut closed = false;
+        loop {
+            tokio::select! {
+                received = receiver.recv_many(batch, self.batch_size) => {
+                    if received == 0 {
+                        break;
+                    }
+                    batch.extend(self.state.take_overflow());
+                    self.delete_batch(batch, handling);
+                }
+                // Closing the channel lets the requests already in it be received, while
+                // new ones are deleted by whoever makes them
+                _ = shutdown.cancelled(), if !closed => {
+                    receiver.close();
+                    closed = true;
+                }
+            }
+        }
+        self.delete_batch(&mut self.state.take_overflow(), handling);
+        info!(event = "room_deletion_handler_stopped");
+    }
+
+    #[instrument(skip_all, fields(batch_size = batch.len()))]
+    fn delete_batch(&self, batch: &mut Vec<RoomId>, handling: &mut Option<RoomId>) {
+        // Each request is taken off the batch before it's handled, so a handler restarted
+        // after a panic neither retries the request it panicked on nor loses the rest
+        while let Some(id) = batch.pop() {
+            *handling = Some(id);
+            self.state.delete(id);
+            *handling = None;
+        }
+    }
+}
+
+#[cfg(test)]
+fn registry_with_rooms(count: usize) -> (Arc<RoomRegistry>, Vec<RoomId>) {
+    let registry = Arc::new(RoomRegistry::new(4));
+    let ids = (0..count)
+        .map(|_| registry.create_room().unwrap())
+        .collect();
+    (registry, ids)
+}
+
+#[cfg(test)]
+mod request_deletion {
+    use super::*;
+
+    #[tokio::test]
+    async fn handler_removes_requested_rooms() {
+        let (registry, ids) = registry_with_rooms(3);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+
+        for &id in &ids {
+            queue.request_deletion(id);
+        }
+        drop(queue);
+        handler.watch().await;
+
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn coalesces_duplicate_requests() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, _handler) =
+            room_deletion_channel(registry, 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+
+        queue.request_deletion(ids[0]);
+        queue.request_deletion(ids[0]);
+
+        assert_eq!(queue.depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn grows_into_overflow_buffer_when_full() {
+        let (registry, ids) = registry_with_rooms(4);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 2, 8, DeletionOverflowPolicy::GrowAndWarn);
+
+        for &id in &ids {
+            queue.request_deletion(id);
+        }
+        assert_eq!(queue.depth(), 4);
+        assert_eq!(queue.overflow_count(), 2);
+        assert_eq!(registry.room_count(), 4);
+
+        drop(queue);
+        handler.watch().await;
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn deletes_directly_when_full() {
+        let (registry, ids) = registry_with_rooms(4);
+        let (queue, _handler) =
+            room_deletion_channel(registry.clone(), 2, 8, DeletionOverflowPolicy::DirectDelete);
+
+        for &id in &ids {
+            queue.request_deletion(id);
+        }
+
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(queue.overflow_count(), 2);
+        assert_eq!(registry.room_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn deletes_directly_once_handler_is_gone() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 2, 8, DeletionOverflowPolicy::GrowAndWarn);
+        drop(handler);
+
+        queue.request_deletion(ids[0]);
+
+        assert_eq!(registry.room_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod request_idle_deletion {
+    use super::*;
+
+    /// Handles every request made through the queue, once it's dropped
+    async fn handle(queue: RoomDeletionQueue, handler: RoomDeletionHandler) {
+        drop(queue);
+        handler.watch().await;
+    }
+
+    #[tokio::test]
+    async fn deletes_rooms_still_idle() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let room = registry.get_room_for_id(ids[0]).unwrap();
+
+        queue.request_idle_deletion(ids[0], room.deletion_generation());
+        handle(queue, handler).await;
+
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_rooms_whose_deletion_was_cancelled_after_the_request() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let room = registry.get_room_for_id(ids[0]).unwrap();
+
+        queue.request_idle_deletion(ids[0], room.deletion_generation());
+        room.cancel_deletion();
+        handle(queue, handler).await;
+
+        assert_eq!(registry.room_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_rooms_someone_is_connected_to() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        let room = registry.get_room_for_id(ids[0]).unwrap();
+        let _connection = room.broadcaster().subscribe();
+
+        queue.request_idle_deletion(ids[0], room.deletion_generation());
+        handle(queue, handler).await;
+
+        assert_eq!(registry.room_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn never_holds_back_unconditional_requests() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let room = registry.get_room_for_id(ids[0]).unwrap();
+
+        queue.request_idle_deletion(ids[0], room.deletion_generation());
+        queue.request_deletion(ids[0]);
+        room.cancel_deletion();
+        queue.request_idle_deletion(ids[0], room.deletion_generation());
+        handle(queue, handler).await;
+
+        assert_eq!(registry.room_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod watch {
+    use super::*;
+
+    #[tokio::test]
+    async fn allows_rooms_to_be_requested_again_after_deletion() {
+        let (registry, ids) = registry_with_rooms(1);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let watcher = tokio::spawn(handler.watch());
+
+        queue.request_deletion(ids[0]);
+        while registry.room_count() > 0 {
+            tokio::task::yield_now().await;
+        }
+        queue.request_deletion(ids[0]);
+        assert_eq!(queue.depth(), 1);
+
+        drop(queue);
+        watcher.await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod supervise {
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_once_shut_down() {
+        let (registry, ids) = registry_with_rooms(2);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        let shutdown = CancellationToken::new();
+        queue.request_deletion(ids[0]);
+
+        shutdown.cancel();
+        handler.supervise(shutdown).await;
+        assert_eq!(registry.room_count(), 1);
+        queue.request_deletion(ids[1]);
+
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restarts_handlers_that_panic() {
+        let (registry, ids) = registry_with_rooms(2);
+        let (queue, handler) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::GrowAndWarn);
+        for &id in &ids {
+            queue.request_deletion(id);
+        }
+        // Handling a request panics while the pending requests' lock is poisoned, until the
+        // handler is restarted
+        let state = queue.state.clone();
+        std::thread::spawn(move || {
+            let _pending = state.pending.lock().unwrap();
+            panic!("poisoning the pending requests");
+        })
+        .join()
+        .unwrap_err();
+
+        let shutdown = CancellationToken::new();
+        let supervisor = tokio::spawn(handler.supervise(shutdown.clone()));
+        while registry.room_count() > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(queue.restart_count(), 1);
+
+        // The request the handler panicked on doesn't swallow the room's next one
+        let abandoned = ids
+            .iter()
+            .copied()
+            .find(|&id| registry.get_room_for_id(id).is_some())
+            .unwrap();
+        queue.request_deletion(abandoned);
+        while registry.room_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        shutdown.cancel();
+        supervisor.await.unwrap();
+        assert_eq!(queue.restart_count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod from_str {
+    use super::*;
+
+    #[test]
+    fn parses_known_policies() {
+        assert_eq!("grow".parse(), Ok(DeletionOverflowPolicy::GrowAndWarn));
+        assert_eq!(
+            "direct_delete".parse(),
+            Ok(DeletionOverflowPolicy::DirectDelete)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_policies() {
+        assert_eq!(
+            "drop".parse::<DeletionOverflowPolicy>(),
+            Err(UnknownOverflowPolicy("drop".to_owned()))
+        );
+    }
+}
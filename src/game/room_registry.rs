@@ -1,94 +1,680 @@
-use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{
+    Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, TryLockResult,
+};
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
 use thiserror::Error;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
-use crate::game::Room;
+pub use wormhole_protocol::{RoomId, RoomStatus, RoomSummary, Visibility};
+
+use crate::faults;
+use crate::game::{
+    LobbyEvent, LobbyFeed, Room, DEFAULT_REPLAY_BUFFER_LEN, DEFAULT_ROOM_MEMORY_LIMIT_BYTES,
+};
+use crate::webhooks::RoomWebhooks;
 
 const MAX_CREATE_ROOM_ID_ATTEMPTS: u8 = 5;
 
-pub(crate) trait ProvideRoomId {
-    fn provide_id() -> RoomId;
+/// How long after telling the lobby about changed rooms the registry waits before telling it
+/// about more, so that a room filling up is one update rather than one per player
+pub const LOBBY_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Chooses the identifiers of the rooms a [registry][RoomRegistry] creates
+///
+/// Identifiers only need to be unlikely to repeat, the registry retries with another when
+/// one is already taken.
+pub trait RoomIdProvider: Debug + Send + Sync {
+    fn provide_id(&self) -> RoomId;
 }
 
-impl ProvideRoomId for Uuid {
-    fn provide_id() -> RoomId {
+/// Provides random UUIDs, which is what registries use unless they're given another provider
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UuidRoomIds;
+
+impl RoomIdProvider for UuidRoomIds {
+    fn provide_id(&self) -> RoomId {
         Uuid::new_v4().as_u128().into()
     }
 }
 
-/// An ID that uniquely identifies a [room][Room] within a [registry][RoomRegistry]
-#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Copy, Clone)]
-pub(crate) struct RoomId(u128);
+/// Provides the same sequence of identifiers for the same seed, so tests can know the rooms
+/// they'll create ahead of time
+#[derive(Debug)]
+pub struct SeededRoomIds {
+    state: AtomicU64,
+}
 
-impl std::fmt::Display for RoomId {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Uuid::from_u128(self.0).fmt(f)
+impl SeededRoomIds {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
     }
+
+    /// The next number in the seed's [SplitMix64](https://prng.di.unimi.it/splitmix64.c)
+    /// sequence
+    fn next(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl RoomIdProvider for SeededRoomIds {
+    fn provide_id(&self) -> RoomId {
+        ((u128::from(self.next()) << 64) | u128::from(self.next())).into()
+    }
+}
+
+type RoomShard = RwLock<HashMap<RoomId, Arc<Room>>>;
+
+/// Takes the guard out of a poisoned shard's lock, clearing the poison so it's only reported
+/// once
+///
+/// A shard is never left half changed, as rooms are added and removed in a single step, so a
+/// thread panicking while holding its lock doesn't make the rooms in it any less usable.
+fn recover<G>(shard: &RoomShard, guard: G) -> G {
+    warn!(event = "registry_shard_poisoned");
+    shard.clear_poison();
+    guard
+}
+
+fn read(shard: &RoomShard) -> RwLockReadGuard<'_, HashMap<RoomId, Arc<Room>>> {
+    shard
+        .read()
+        .unwrap_or_else(|poisoned| recover(shard, poisoned.into_inner()))
+}
+
+fn write(shard: &RoomShard) -> RwLockWriteGuard<'_, HashMap<RoomId, Arc<Room>>> {
+    shard
+        .write()
+        .unwrap_or_else(|poisoned| recover(shard, poisoned.into_inner()))
 }
 
-impl From<u128> for RoomId {
-    fn from(value: u128) -> Self {
-        RoomId(value)
+/// Takes one of the shard's locks with `try_lock`, without waiting if it's held
+fn lock_now<'a, G>(
+    shard: &'a RoomShard,
+    try_lock: impl Fn(&'a RoomShard) -> TryLockResult<G>,
+) -> Result<G, ShardLocked> {
+    match try_lock(shard) {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Ok(recover(shard, poisoned.into_inner())),
+        Err(TryLockError::WouldBlock) => Err(ShardLocked),
     }
 }
 
 /// RoomRegistry maintains a list of [rooms][Room]
+///
+/// Rooms are partitioned into shards keyed by a hash of their [id][RoomId], each
+/// guarded by its own lock, so operations on rooms in different shards never contend.
+/// Each shard also has an immutable snapshot of its rooms' summaries, swapped in under the
+/// shard's lock on every change so a listing never outlives its room, and merged into a
+/// listing of every room when one is asked for after a change. Listing rooms never has to
+/// take any of the shard locks, and a change only copies the summaries of its own shard.
+///
+/// Every change to the set of rooms is also published to the [lobby feed][LobbyFeed], so
+/// lobby browsers can follow along without polling the list.
 #[derive(Debug)]
-pub(crate) struct RoomRegistry<T: ProvideRoomId = Uuid> {
-    rooms: HashMap<RoomId, Room>,
-    _provider: std::marker::PhantomData<T>,
+pub struct RoomRegistry {
+    shards: Box<[RoomShard]>,
+    /// The summaries of each shard's rooms, indexed like the shards
+    listings: Box<[ArcSwap<Vec<ListedRoom>>]>,
+    /// Bumped after every change to the listings
+    listings_version: AtomicU64,
+    /// Every shard's listing merged, along with the version of the listings it was merged at
+    merged_listing: ArcSwap<(u64, Arc<Vec<RoomSummary>>)>,
+    next_listing_order: AtomicU64,
+    lobby: LobbyFeed,
+    /// Rooms [updated][Self::room_updated] since the lobby was last told
+    pending_updates: Mutex<BTreeSet<RoomId>>,
+    updated: Notify,
+    room_memory_limit: usize,
+    room_capacity: Option<usize>,
+    room_empty_timeout: Option<Duration>,
+    room_replay_len: usize,
+    /// The kind of game rooms are listed as hosting unless they say otherwise
+    game_type: Option<String>,
+    region: Option<String>,
+    id_provider: Arc<dyn RoomIdProvider>,
+    /// Where the webhooks rooms are created with are registered for delivery, `None` when
+    /// webhooks aren't delivered
+    room_webhooks: Option<Arc<RoomWebhooks>>,
+}
+
+/// A room's summary as its shard lists it
+#[derive(Debug, Clone)]
+struct ListedRoom {
+    /// Orders the room among every shard's, by when it was added
+    order: u64,
+    summary: RoomSummary,
 }
 
 /// Enumerates the errors that can occur within the context of [room][Room] creation
-#[derive(Error, Debug, PartialEq)]
-pub(crate) enum RoomCreationError {
+#[derive(Error, Debug, PartialEq, Clone)]
+pub enum RoomCreationError {
     #[error("Unable to create a unique room identifier after {0} attempts")]
     UnableToCreateIdentifier(u8),
+    #[error("A room with the identifier {0} already exists")]
+    IdentifierTaken(RoomId),
 }
 
-impl RoomRegistry<Uuid> {
-    pub fn new() -> Self {
+/// Raised when a shard's lock is held for longer than the caller is willing to wait for it
+#[derive(Error, Debug, PartialEq, Clone, Copy)]
+#[error("Gave up on the room registry after waiting {0:?} for a shard's lock")]
+pub struct RegistryBusy(pub Duration);
+
+/// Raised instead of waiting when a room's shard is locked
+#[derive(Error, Debug, PartialEq, Clone, Copy)]
+#[error("The room's shard of the registry is locked")]
+pub struct ShardLocked;
+
+impl RoomRegistry {
+    pub fn new(shard_count: usize) -> Self {
+        Self::with_shard_count(shard_count)
+    }
+
+    /// Creates an empty registry split into `shard_count` shards
+    ///
+    /// # Panics
+    /// Panics if `shard_count` is zero
+    pub fn with_shard_count(shard_count: usize) -> Self {
+        assert!(shard_count > 0, "A room registry needs at least one shard");
         Self {
-            rooms: Default::default(),
-            _provider: std::marker::PhantomData,
+            shards: (0..shard_count).map(|_| Default::default()).collect(),
+            listings: (0..shard_count).map(|_| Default::default()).collect(),
+            listings_version: AtomicU64::new(0),
+            merged_listing: Default::default(),
+            next_listing_order: AtomicU64::new(0),
+            lobby: Default::default(),
+            pending_updates: Default::default(),
+            updated: Notify::new(),
+            room_memory_limit: DEFAULT_ROOM_MEMORY_LIMIT_BYTES,
+            room_capacity: None,
+            room_empty_timeout: None,
+            room_replay_len: DEFAULT_REPLAY_BUFFER_LEN,
+            game_type: None,
+            region: None,
+            id_provider: Arc::new(UuidRoomIds),
+            room_webhooks: None,
         }
     }
-}
 
-impl<T: ProvideRoomId> RoomRegistry<T> {
+    /// Has the registry choose the identifiers of the rooms it creates with `provider`
+    /// instead of [random UUIDs][UuidRoomIds]
+    pub fn with_room_id_provider(mut self, provider: Arc<dyn RoomIdProvider>) -> Self {
+        self.id_provider = provider;
+        self
+    }
+
+    /// Caps the memory each room created from now on may hold, in bytes
+    pub fn with_room_memory_limit(mut self, limit: usize) -> Self {
+        self.room_memory_limit = limit;
+        self
+    }
+
+    /// Caps how many players each room created from now on may hold, see [Room::with_capacity]
+    pub fn with_room_capacity(mut self, capacity: Option<usize>) -> Self {
+        self.room_capacity = capacity;
+        self
+    }
+
+    /// Has each room created from now on deleted once it's been empty for `timeout`, see
+    /// [Room::with_empty_timeout]
+    pub fn with_room_empty_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.room_empty_timeout = timeout;
+        self
+    }
+
+    /// Has each room created from now on keep its latest `len` broadcasts to replay, see
+    /// [Room::with_replay_len]
+    pub fn with_room_replay_len(mut self, len: usize) -> Self {
+        self.room_replay_len = len;
+        self
+    }
+
+    /// Lists the registry's rooms as hosting `game_type` unless they were given one of their
+    /// own, such as by the match they were created for
+    pub fn with_game_type(mut self, game_type: Option<String>) -> Self {
+        self.game_type = game_type;
+        self
+    }
+
+    /// Tags the registry's rooms with the region the instance runs in
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Registers the webhook of each room created or adopted from now on in `room_webhooks`,
+    /// for its events to be delivered to it
+    pub fn with_room_webhooks(mut self, room_webhooks: Arc<RoomWebhooks>) -> Self {
+        self.room_webhooks = Some(room_webhooks);
+        self
+    }
+
+    /// Whether rooms can be created with webhooks of their own
+    pub fn delivers_room_webhooks(&self) -> bool {
+        self.room_webhooks.is_some()
+    }
+
+    fn register_webhook(&self, id: RoomId, room: &Room) {
+        let Some(webhook) = room.webhook() else {
+            return;
+        };
+        match &self.room_webhooks {
+            Some(room_webhooks) => room_webhooks.register(id, &webhook),
+            None => warn!(event = "room_webhook_not_delivered", room_id = %id),
+        }
+    }
+
+    /// Stops delivering the room's events to the webhook it was created with, as when it's
+    /// handed over to another instance
+    pub fn unregister_webhook(&self, id: RoomId) {
+        if let Some(room_webhooks) = &self.room_webhooks {
+            room_webhooks.unregister(id);
+        }
+    }
+
+    /// The region the instance runs in, if it has been configured with one
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// The game the room hosts, its own if it was given one or else the registry's
+    pub fn game_type_of(&self, room: &Room) -> Option<String> {
+        room.game_type().or_else(|| self.game_type.clone())
+    }
+
+    fn summary(&self, id: RoomId, room: &Room) -> RoomSummary {
+        RoomSummary {
+            id,
+            name: room.name(),
+            game_type: self.game_type_of(room),
+            player_count: room.player_count(),
+            spectator_count: room.spectator_count(),
+            capacity: room.capacity(),
+            state: room.status(),
+            visibility: room.visibility(),
+            region: self.region.clone(),
+            starts_at: room.starts_at(),
+            deletes_at: room.deletes_at(),
+            last_active_at: room.last_active_at(),
+        }
+    }
+
+    fn shard_index(&self, id: &RoomId) -> usize {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    fn shard_for(&self, id: &RoomId) -> &RoomShard {
+        &self.shards[self.shard_index(id)]
+    }
+
     #[instrument(skip_all)]
-    pub fn get_room_for_id(&self, id: impl Into<RoomId>) -> Option<&Room> {
+    pub fn get_room_for_id(&self, id: impl Into<RoomId>) -> Option<Arc<Room>> {
         info!(event = "room_registry.get_room_for_id");
-        return self.rooms.get(&id.into());
+        let id = id.into();
+        read(self.shard_for(&id)).get(&id).cloned()
+    }
+
+    /// Like [get_room_for_id][Self::get_room_for_id], but giving up straight away if the
+    /// room's shard is locked
+    pub fn try_get_room_for_id(&self, id: RoomId) -> Result<Option<Arc<Room>>, ShardLocked> {
+        let shard = lock_now(self.shard_for(&id), RwLock::try_read)?;
+        Ok(shard.get(&id).cloned())
+    }
+
+    pub fn create_room(&self) -> Result<RoomId, RoomCreationError> {
+        self.create_room_with(|_| {})
     }
 
-    #[instrument(skip(self))]
-    pub fn create_room(&mut self) -> Result<RoomId, RoomCreationError> {
+    /// Creates a room, letting `configure` set it up before anyone else can see it
+    #[instrument(skip_all)]
+    pub fn create_room_with(
+        &self,
+        configure: impl FnOnce(&Room),
+    ) -> Result<RoomId, RoomCreationError> {
         info!(event = "start");
-        let mut id = T::provide_id();
+        let room = Room::with_memory_limit(self.room_memory_limit)
+            .with_capacity(self.room_capacity)
+            .with_empty_timeout(self.room_empty_timeout)
+            .with_replay_len(self.room_replay_len);
+        configure(&room);
         let mut attempts = 0;
-        while self.rooms.contains_key(&id) {
+        loop {
+            let id = self.id_provider.provide_id();
+            faults::delay_lock("registry_shard");
+            let mut shard = write(self.shard_for(&id));
+            if let Entry::Vacant(entry) = shard.entry(id) {
+                let summary = self.summary(id, &room);
+                self.register_webhook(id, &room);
+                entry.insert(Arc::new(room));
+                self.room_added(summary);
+                drop(shard);
+                info!(event = "room_created_successfully", id = format!("{}", id));
+                return Ok(id);
+            }
+            drop(shard);
+
             if attempts >= MAX_CREATE_ROOM_ID_ATTEMPTS {
                 warn!(
                     event = "room_creation_error",
-                    current_room_count = self.rooms.len()
+                    current_room_count = self.room_count()
                 );
                 return Err(RoomCreationError::UnableToCreateIdentifier(
                     MAX_CREATE_ROOM_ID_ATTEMPTS,
                 ));
             }
             attempts += 1;
-            id = T::provide_id();
         }
+    }
+
+    /// Registers a room under an identifier chosen elsewhere, such as a room moving here
+    /// from another instance, letting `configure` set it up before anyone else can see it
+    #[instrument(skip_all)]
+    pub fn adopt_room(
+        &self,
+        id: RoomId,
+        configure: impl FnOnce(&Room),
+    ) -> Result<(), RoomCreationError> {
+        let room = Room::with_memory_limit(self.room_memory_limit)
+            .with_capacity(self.room_capacity)
+            .with_empty_timeout(self.room_empty_timeout)
+            .with_replay_len(self.room_replay_len);
+        configure(&room);
+        faults::delay_lock("registry_shard");
+        let mut shard = write(self.shard_for(&id));
+        let Entry::Vacant(entry) = shard.entry(id) else {
+            return Err(RoomCreationError::IdentifierTaken(id));
+        };
+        let summary = self.summary(id, &room);
+        self.register_webhook(id, &room);
+        entry.insert(Arc::new(room));
+        self.room_added(summary);
+        drop(shard);
+        info!(event = "room_adopted", id = format!("{}", id));
+        Ok(())
+    }
+
+    /// Lists a room just added to its shard, to be called while still holding the shard's
+    /// lock
+    fn room_added(&self, summary: RoomSummary) {
+        let listed = ListedRoom {
+            order: self.next_listing_order.fetch_add(1, Ordering::Relaxed),
+            summary: summary.clone(),
+        };
+        self.change_listing(self.shard_index(&summary.id), |rooms| {
+            let mut rooms = Vec::clone(rooms);
+            rooms.push(listed.clone());
+            rooms
+        });
+        self.lobby.publish(LobbyEvent::RoomCreated(summary), false);
+    }
+
+    fn change_listing(
+        &self,
+        shard: usize,
+        change: impl FnMut(&Arc<Vec<ListedRoom>>) -> Vec<ListedRoom>,
+    ) {
+        self.listings[shard].rcu(change);
+        self.listings_version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Removes the room with the given id, returning it if it was registered
+    #[instrument(skip_all)]
+    pub fn delete_room(&self, id: impl Into<RoomId>) -> Option<Arc<Room>> {
+        let id = id.into();
+        faults::delay_lock("registry_shard");
+        let mut shard = write(self.shard_for(&id));
+        let removed = shard.remove(&id);
+        if removed.is_some() {
+            self.room_removed(id);
+        }
+        removed
+    }
+
+    /// Removes the room with the given id if `should_delete` says to, deciding while nothing can
+    /// look the room up, and returns it if it was removed
+    pub fn delete_room_if(
+        &self,
+        id: RoomId,
+        should_delete: impl FnOnce(&Room) -> bool,
+    ) -> Option<Arc<Room>> {
+        faults::delay_lock("registry_shard");
+        let mut shard = write(self.shard_for(&id));
+        if !should_delete(shard.get(&id)?) {
+            return None;
+        }
+        let removed = shard.remove(&id);
+        self.room_removed(id);
+        removed
+    }
+
+    /// Like [delete_room][Self::delete_room], but giving up straight away if the room's shard
+    /// is locked
+    pub fn try_delete_room(&self, id: RoomId) -> Result<Option<Arc<Room>>, ShardLocked> {
+        faults::delay_lock("registry_shard");
+        let mut shard = lock_now(self.shard_for(&id), RwLock::try_write)?;
+        let removed = shard.remove(&id);
+        if removed.is_some() {
+            self.room_removed(id);
+        }
+        Ok(removed)
+    }
+
+    /// Unlists a room just removed from its shard, to be called while still holding the
+    /// shard's lock
+    fn room_removed(&self, id: RoomId) {
+        let shard = self.shard_index(&id);
+        let was_listed = self.listings[shard]
+            .load()
+            .iter()
+            .any(|room| room.summary.id == id && room.summary.visibility.is_listed());
+        self.change_listing(shard, |rooms| {
+            let mut rooms = Vec::clone(rooms);
+            rooms.retain(|room| room.summary.id != id);
+            rooms
+        });
+        if let Some(room_webhooks) = &self.room_webhooks {
+            room_webhooks.room_deleted(id, Instant::now());
+        }
+        self.lobby
+            .publish(LobbyEvent::RoomDeleted { id }, was_listed);
+        info!(event = "room_deleted", id = format!("{}", id));
+    }
+
+    /// Has the lobby told that the settings or players of the room with the given id changed,
+    /// along with every other room changed by the time [Self::publish_updates] runs
+    pub fn room_updated(&self, id: impl Into<RoomId>) {
+        self.pending_updates.lock().unwrap().insert(id.into());
+        self.updated.notify_one();
+    }
+
+    /// Lists the rooms [updated][Self::room_updated] since the last call as they are now, and
+    /// tells the lobby about each
+    pub fn publish_updates(&self) {
+        let updated = std::mem::take(&mut *self.pending_updates.lock().unwrap());
+        let summaries: Vec<_> = updated
+            .into_iter()
+            .filter_map(|id| {
+                let room = self.get_room_for_id(id)?;
+                Some(self.summary(id, &room))
+            })
+            .collect();
+        if summaries.is_empty() {
+            return;
+        }
+        let mut by_shard: BTreeMap<usize, HashMap<RoomId, &RoomSummary>> = BTreeMap::new();
+        for summary in &summaries {
+            by_shard
+                .entry(self.shard_index(&summary.id))
+                .or_default()
+                .insert(summary.id, summary);
+        }
+        // Each shard stays locked while its rooms are relisted, so the visibility they were
+        // last listed with is the one lobby browsers were last told about. Rooms deleted
+        // meanwhile are no longer listed, so they're left out rather than listed again
+        for (shard, by_id) in by_shard {
+            faults::delay_lock("registry_shard");
+            let _shard = write(&self.shards[shard]);
+            let mut was_listed = HashMap::new();
+            self.change_listing(shard, |rooms| {
+                was_listed.clear();
+                rooms
+                    .iter()
+                    .map(|listed| match by_id.get(&listed.summary.id) {
+                        Some(&summary) => {
+                            was_listed.insert(summary.id, listed.summary.visibility.is_listed());
+                            ListedRoom {
+                                order: listed.order,
+                                summary: summary.clone(),
+                            }
+                        }
+                        None => listed.clone(),
+                    })
+                    .collect()
+            });
+            for (id, was_listed) in was_listed {
+                self.lobby
+                    .publish(LobbyEvent::RoomUpdated(by_id[&id].clone()), was_listed);
+            }
+        }
+    }
+
+    /// [Publishes][Self::publish_updates] rooms as they're updated until shut down, at most
+    /// once every [LOBBY_UPDATE_INTERVAL]
+    pub async fn run_updates(self: Arc<Self>, shutdown: CancellationToken) {
+        loop {
+            tokio::select! {
+                _ = self.updated.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            self.publish_updates();
+            tokio::select! {
+                _ = tokio::time::sleep(LOBBY_UPDATE_INTERVAL) => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+    }
+
+    /// The feed of changes to the set of rooms
+    pub fn lobby(&self) -> &LobbyFeed {
+        &self.lobby
+    }
+
+    /// Summaries of every registered room in the order they were added, read from the latest
+    /// snapshots without locking
+    pub fn list_active_rooms(&self) -> Arc<Vec<RoomSummary>> {
+        let version = self.listings_version.load(Ordering::Acquire);
+        let merged = self.merged_listing.load();
+        if merged.0 == version {
+            return merged.1.clone();
+        }
+        let mut rooms = Vec::new();
+        for listing in &self.listings {
+            rooms.extend(listing.load().iter().cloned());
+        }
+        // Each shard's listing is already in order, which the sort takes advantage of
+        rooms.sort_by_key(|room| room.order);
+        let rooms = Arc::new(
+            rooms
+                .into_iter()
+                .map(|room| room.summary)
+                .collect::<Vec<_>>(),
+        );
+        self.merged_listing
+            .store(Arc::new((version, rooms.clone())));
+        rooms
+    }
+
+    /// Summaries of the rooms listed in the lobby, leaving out unlisted ones
+    pub fn list_lobby_rooms(&self) -> Vec<RoomSummary> {
+        self.list_active_rooms()
+            .iter()
+            .filter(|room| room.visibility.is_listed())
+            .cloned()
+            .collect()
+    }
+
+    /// The total number of rooms across every shard
+    pub fn room_count(&self) -> usize {
+        self.shard_occupancy().into_iter().sum()
+    }
+
+    fn sum_over_rooms(&self, f: impl Fn(&Room) -> usize) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| read(shard).values().map(|room| f(room)).sum::<usize>())
+            .sum()
+    }
 
-        let room = Room::new();
-        self.rooms.insert(id, room);
-        info!(event = "room_created_successfully", id = format!("{}", id));
-        Ok(id)
+    /// The total number of connections across every room
+    pub fn connection_count(&self) -> usize {
+        self.sum_over_rooms(|room| room.broadcaster().subscriber_count())
+    }
+
+    /// The approximate number of bytes held across every room
+    pub fn memory_usage(&self) -> usize {
+        self.sum_over_rooms(Room::memory_usage)
+    }
+
+    /// The number of rooms held by each shard, indexed by shard
+    pub fn shard_occupancy(&self) -> Vec<usize> {
+        self.shards.iter().map(|shard| read(shard).len()).collect()
     }
 }
 
+#[cfg(test)]
+fn summary(id: impl Into<RoomId>) -> RoomSummary {
+    RoomSummary {
+        id: id.into(),
+        name: None,
+        game_type: None,
+        player_count: 0,
+        spectator_count: 0,
+        capacity: None,
+        state: RoomStatus::Open,
+        visibility: Visibility::Public,
+        region: None,
+        starts_at: None,
+        deletes_at: None,
+        last_active_at: None,
+    }
+}
+
+#[cfg(test)]
+fn registry_with_rooms(ids: &[u128]) -> RoomRegistry {
+    let registry = RoomRegistry::new(4);
+    for &id in ids {
+        let id = RoomId::from(id);
+        registry
+            .shard_for(&id)
+            .write()
+            .unwrap()
+            .insert(id, Arc::new(Room::new()));
+    }
+    for &id in ids {
+        registry.room_added(summary(id));
+    }
+    registry
+}
+
 #[cfg(test)]
 mod get_room_for_id {
     use super::*;
@@ -96,28 +682,59 @@ mod get_room_for_id {
     #[test]
     fn returns_room_if_one_exists() {
         let room_id = 1234_u128;
-        let rooms = HashMap::from([(room_id.into(), Room::new())]);
+        let registry = registry_with_rooms(&[room_id]);
 
-        let registry: RoomRegistry<Uuid> = RoomRegistry {
-            rooms,
-            _provider: std::marker::PhantomData,
-        };
         let room = registry.get_room_for_id(room_id);
-        assert_eq!(room, Some(&Room::new()));
+        assert!(room.is_some());
     }
 
     #[test]
     fn returns_none_if_no_rooms_exist() {
         let room_id = 1234_u128;
         let bad_room_id = 0_u128;
-        let rooms = HashMap::from([(room_id.into(), Room::new())]);
+        let registry = registry_with_rooms(&[room_id]);
 
-        let registry: RoomRegistry<Uuid> = RoomRegistry {
-            rooms,
-            _provider: std::marker::PhantomData,
-        };
         let room = registry.get_room_for_id(bad_room_id);
-        assert_eq!(room, None);
+        assert!(room.is_none());
+    }
+
+    #[test]
+    fn recovers_from_a_poisoned_shard() {
+        let room_id = RoomId::from(1234);
+        let registry = registry_with_rooms(&[1234]);
+
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _shard = registry.shard_for(&room_id).write().unwrap();
+                    panic!("Poisoning the shard");
+                })
+                .join()
+                .unwrap_err();
+        });
+
+        assert!(registry.get_room_for_id(room_id).is_some());
+        assert!(!registry.shard_for(&room_id).is_poisoned());
+    }
+}
+
+#[cfg(test)]
+mod try_get_room_for_id {
+    use super::*;
+
+    #[test]
+    fn gives_up_on_a_locked_shard() {
+        let room_id = RoomId::from(1234);
+        let registry = registry_with_rooms(&[1234]);
+
+        let shard = registry.shard_for(&room_id).write().unwrap();
+        assert_eq!(
+            registry.try_get_room_for_id(room_id).unwrap_err(),
+            ShardLocked
+        );
+        drop(shard);
+
+        assert!(registry.try_get_room_for_id(room_id).unwrap().is_some());
     }
 }
 
@@ -127,25 +744,23 @@ mod create_room {
 
     #[test]
     fn adds_room_to_registry_on_creation() {
-        let mut registry = RoomRegistry::new();
+        let registry = RoomRegistry::new(4);
         let id = registry.create_room().unwrap();
         let room = registry.get_room_for_id(id);
-        assert_ne!(room, Option::None);
+        assert!(room.is_some());
     }
 
     #[test]
     fn fails_if_new_room_cant_be_created_after_max_attempts() {
+        #[derive(Debug)]
         struct BadIdProvider;
-        impl ProvideRoomId for BadIdProvider {
-            fn provide_id() -> RoomId {
-                0_128.into()
+        impl RoomIdProvider for BadIdProvider {
+            fn provide_id(&self) -> RoomId {
+                0_u128.into()
             }
         }
 
-        let mut registry: RoomRegistry<BadIdProvider> = RoomRegistry {
-            rooms: Default::default(),
-            _provider: std::marker::PhantomData,
-        };
+        let registry = RoomRegistry::new(4).with_room_id_provider(Arc::new(BadIdProvider));
         // Bad room id provider only returns 0 so after the first room is created
         // we should be unable to create another one
         let _ = registry.create_room();
@@ -158,4 +773,317 @@ mod create_room {
             ))
         );
     }
+
+    #[test]
+    fn seeded_providers_repeat_their_identifiers() {
+        let registries = [7, 7, 8].map(|seed| {
+            RoomRegistry::new(4).with_room_id_provider(Arc::new(SeededRoomIds::new(seed)))
+        });
+        let ids = registries.map(|registry| [(); 3].map(|_| registry.create_room().unwrap()));
+
+        assert_eq!(ids[0], ids[1]);
+        assert_ne!(ids[0], ids[2]);
+        assert_ne!(ids[0][0], ids[0][1]);
+    }
+}
+
+#[cfg(test)]
+mod adopt_room {
+    use super::*;
+
+    #[test]
+    fn registers_the_room_under_its_id() {
+        let registry = RoomRegistry::new(4);
+        let mut lobby = registry.lobby().subscribe();
+
+        registry
+            .adopt_room(1234_u128.into(), |room| room.set_private(true))
+            .unwrap();
+
+        let adopted = RoomSummary {
+            visibility: Visibility::Private,
+            ..summary(1234_u128)
+        };
+        assert!(registry.get_room_for_id(1234_u128).unwrap().is_private());
+        assert_eq!(*registry.list_active_rooms(), vec![adopted.clone()]);
+        assert_eq!(lobby.try_recv(), Ok(LobbyEvent::RoomCreated(adopted)));
+    }
+
+    #[test]
+    fn refuses_ids_already_taken() {
+        let registry = registry_with_rooms(&[1234]);
+
+        assert_eq!(
+            registry.adopt_room(1234_u128.into(), |_| {}),
+            Err(RoomCreationError::IdentifierTaken(1234_u128.into()))
+        );
+    }
+}
+
+#[cfg(test)]
+mod delete_room {
+    use super::*;
+
+    #[test]
+    fn removes_room_from_registry() {
+        let room_id = 1234_u128;
+        let registry = registry_with_rooms(&[room_id]);
+
+        assert!(registry.delete_room(room_id).is_some());
+        assert!(registry.get_room_for_id(room_id).is_none());
+    }
+
+    #[test]
+    fn returns_none_if_room_does_not_exist() {
+        let registry = registry_with_rooms(&[1234]);
+        assert!(registry.delete_room(0_u128).is_none());
+        assert_eq!(registry.room_count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod list_active_rooms {
+    use super::*;
+
+    #[test]
+    fn is_empty_for_new_registry() {
+        let registry = RoomRegistry::new(4);
+        assert!(registry.list_active_rooms().is_empty());
+    }
+
+    #[test]
+    fn includes_created_rooms() {
+        let registry = RoomRegistry::new(4);
+        let first = registry.create_room().unwrap();
+        let second = registry.create_room().unwrap();
+
+        assert_eq!(
+            *registry.list_active_rooms(),
+            vec![summary(first), summary(second)]
+        );
+    }
+
+    #[test]
+    fn excludes_deleted_rooms() {
+        let registry = registry_with_rooms(&[1, 2, 3]);
+        registry.delete_room(2_u128);
+
+        assert_eq!(*registry.list_active_rooms(), vec![summary(1), summary(3)]);
+    }
+
+    #[test]
+    fn leaves_unlisted_rooms_out_of_the_lobby() {
+        let registry = registry_with_rooms(&[1, 2, 3]);
+        registry
+            .get_room_for_id(2_u128)
+            .unwrap()
+            .set_visibility(Visibility::Unlisted);
+        registry
+            .get_room_for_id(3_u128)
+            .unwrap()
+            .set_visibility(Visibility::Private);
+        registry.room_updated(2_u128);
+        registry.room_updated(3_u128);
+        registry.publish_updates();
+
+        let listed: Vec<_> = registry
+            .list_lobby_rooms()
+            .into_iter()
+            .map(|room| room.id)
+            .collect();
+        assert_eq!(listed, [1_u128.into(), 3_u128.into()]);
+        assert_eq!(registry.list_active_rooms().len(), 3);
+    }
+
+    #[test]
+    fn keeps_the_order_rooms_were_added_in_across_shards() {
+        let registry = RoomRegistry::new(4);
+        let mut ids: Vec<_> = (0..20).map(|_| registry.create_room().unwrap()).collect();
+        for id in ids.iter().step_by(3) {
+            registry.delete_room(*id);
+        }
+        ids = ids
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .map(|(_, id)| id)
+            .collect();
+
+        let listed: Vec<_> = registry
+            .list_active_rooms()
+            .iter()
+            .map(|room| room.id)
+            .collect();
+        assert_eq!(listed, ids);
+    }
+
+    #[test]
+    fn earlier_snapshots_are_unaffected_by_changes() {
+        let registry = registry_with_rooms(&[1]);
+        let snapshot = registry.list_active_rooms();
+        registry.create_room().unwrap();
+
+        assert_eq!(*snapshot, vec![summary(1)]);
+    }
+}
+
+#[cfg(test)]
+mod lobby {
+    use super::*;
+
+    #[test]
+    fn lists_rooms_with_their_own_game_type_or_the_servers() {
+        let registry = RoomRegistry::new(4).with_game_type(Some("chess".to_owned()));
+
+        registry.create_room().unwrap();
+        registry
+            .create_room_with(|room| room.set_game_type("go".to_owned()))
+            .unwrap();
+
+        let mut game_types: Vec<_> = registry
+            .list_active_rooms()
+            .iter()
+            .map(|room| room.game_type.clone().unwrap())
+            .collect();
+        game_types.sort();
+        assert_eq!(game_types, ["chess", "go"]);
+    }
+
+    #[test]
+    fn publishes_created_updated_and_deleted_rooms() {
+        let registry = RoomRegistry::new(4);
+        let mut events = registry.lobby().subscribe();
+
+        let id = registry.create_room().unwrap();
+        registry.room_updated(id);
+        registry.publish_updates();
+        registry.delete_room(id);
+
+        assert_eq!(events.try_recv(), Ok(LobbyEvent::RoomCreated(summary(id))));
+        assert_eq!(events.try_recv(), Ok(LobbyEvent::RoomUpdated(summary(id))));
+        assert_eq!(events.try_recv(), Ok(LobbyEvent::RoomDeleted { id }));
+    }
+
+    #[test]
+    fn publishes_each_room_once_however_often_it_was_updated() {
+        let registry = RoomRegistry::new(4);
+        let id = registry.create_room().unwrap();
+        let mut events = registry.lobby().subscribe();
+
+        for name in ["Opening", "Middlegame", "Endgame"] {
+            registry
+                .get_room_for_id(id)
+                .unwrap()
+                .set_name(name.to_owned());
+            registry.room_updated(id);
+        }
+        registry.publish_updates();
+
+        let Ok(LobbyEvent::RoomUpdated(updated)) = events.try_recv() else {
+            panic!("The room's update wasn't published");
+        };
+        assert_eq!(updated.name.as_deref(), Some("Endgame"));
+        assert!(events.try_recv().is_err());
+        assert_eq!(registry.list_active_rooms()[0], updated);
+    }
+
+    #[test]
+    fn shows_lobby_browsers_rooms_made_unlisted_as_deleted() {
+        let registry = RoomRegistry::new(4);
+        let id = registry.create_room().unwrap();
+        let mut events = registry.lobby().subscribe_listed();
+        let room = registry.get_room_for_id(id).unwrap();
+
+        room.set_visibility(Visibility::Unlisted);
+        registry.room_updated(id);
+        registry.publish_updates();
+        room.set_name("Secret".to_owned());
+        registry.room_updated(id);
+        registry.publish_updates();
+        registry.delete_room(id);
+
+        assert_eq!(events.try_recv(), Ok(LobbyEvent::RoomDeleted { id }));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn shows_lobby_browsers_rooms_made_listed_as_created() {
+        let registry = RoomRegistry::new(4);
+        let id = registry
+            .create_room_with(|room| room.set_visibility(Visibility::Unlisted))
+            .unwrap();
+        let mut events = registry.lobby().subscribe_listed();
+        let room = registry.get_room_for_id(id).unwrap();
+
+        room.set_name("Secret".to_owned());
+        registry.room_updated(id);
+        registry.publish_updates();
+        room.set_visibility(Visibility::Public);
+        registry.room_updated(id);
+        registry.publish_updates();
+
+        let Ok(LobbyEvent::RoomCreated(created)) = events.try_recv() else {
+            panic!("The room wasn't shown once listed");
+        };
+        assert_eq!(created.id, id);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn ignores_rooms_that_are_not_registered() {
+        let registry = registry_with_rooms(&[1]);
+        let mut events = registry.lobby().subscribe();
+
+        registry.room_updated(2_u128);
+        registry.publish_updates();
+        registry.delete_room(2_u128);
+
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn lists_when_rooms_waiting_for_players_are_deleted() {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let (queue, _handler) = crate::game::room_deletion_channel(
+            registry.clone(),
+            8,
+            8,
+            crate::game::DeletionOverflowPolicy::DirectDelete,
+        );
+        let id = registry.create_room().unwrap();
+
+        registry
+            .get_room_for_id(id)
+            .unwrap()
+            .schedule_deletion(id, Duration::from_secs(60), queue);
+        registry.room_updated(id);
+        registry.publish_updates();
+
+        let deletes_at = registry.list_active_rooms()[0].deletes_at.unwrap();
+        assert!(deletes_at.abs_diff(crate::clock::unix_time() + 60) <= 1);
+    }
+}
+
+#[cfg(test)]
+mod shard_occupancy {
+    use super::*;
+
+    #[test]
+    fn reports_one_entry_per_shard() {
+        let registry = RoomRegistry::new(8);
+        assert_eq!(registry.shard_occupancy(), vec![0; 8]);
+    }
+
+    #[test]
+    fn sums_to_total_room_count() {
+        let ids: Vec<u128> = (0..100).collect();
+        let registry = registry_with_rooms(&ids);
+
+        let occupancy = registry.shard_occupancy();
+        assert_eq!(occupancy.iter().sum::<usize>(), 100);
+        assert!(
+            occupancy.iter().all(|&rooms| rooms > 0),
+            "Expected rooms to be spread across every shard, got {occupancy:?}"
+        );
+    }
 }
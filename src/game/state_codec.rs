@@ -0,0 +1,311 @@
+//! How the state of a room's game is encoded while the server holds on to it and sends it on
+//!
+//! Broadcasts are encoded by the codec of the room's game type once, as they're received, and
+//! sent to clients encoded unless the codec is JSON or the client speaks a protocol version from
+//! before encoded broadcasts. A turn-based room's [game state][crate::game::GameState] is the
+//! last broadcast kept encoded the same way, in memory and when it's saved. Games with big
+//! boards can register a [StateCodec] with a compact encoding for their game type, without the
+//! rest of the server knowing anything about it. Each encoded state carries the name of the
+//! codec that encoded it, so it's always decoded by the same one, even if its game type has
+//! been given another codec since. States encoded as JSON are saved as they are, and only those
+//! of binary codecs are saved as base64.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StateCodecError {
+    #[error("The game's state couldn't be encoded: {0}")]
+    Encode(String),
+    #[error("The game's state couldn't be decoded: {0}")]
+    Decode(String),
+    #[error("No codec is named {0}")]
+    UnknownCodec(String),
+}
+
+/// Encodes and decodes the state of a game
+pub trait StateCodec: Debug + Send + Sync {
+    /// The name the codec is known by, kept with every state it encodes
+    fn name(&self) -> &str;
+
+    fn encode(&self, state: &Value) -> Result<Vec<u8>, StateCodecError>;
+
+    fn decode(&self, encoded: &[u8]) -> Result<Value, StateCodecError>;
+}
+
+/// Encodes states as JSON, as clients are sent them
+#[derive(Debug, Default)]
+pub struct JsonCodec;
+
+impl StateCodec for JsonCodec {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn encode(&self, state: &Value) -> Result<Vec<u8>, StateCodecError> {
+        serde_json::to_vec(state).map_err(|e| StateCodecError::Encode(e.to_string()))
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Value, StateCodecError> {
+        serde_json::from_slice(encoded).map_err(|e| StateCodecError::Decode(e.to_string()))
+    }
+}
+
+/// Encodes states as MessagePack, which takes less room than JSON for most states
+#[derive(Debug, Default)]
+pub struct MessagePackCodec;
+
+impl StateCodec for MessagePackCodec {
+    fn name(&self) -> &str {
+        "msgpack"
+    }
+
+    fn encode(&self, state: &Value) -> Result<Vec<u8>, StateCodecError> {
+        rmp_serde::to_vec(state).map_err(|e| StateCodecError::Encode(e.to_string()))
+    }
+
+    fn decode(&self, encoded: &[u8]) -> Result<Value, StateCodecError> {
+        rmp_serde::from_slice(encoded).map_err(|e| StateCodecError::Decode(e.to_string()))
+    }
+}
+
+/// The built-in codec named `name`, if there is one
+pub fn built_in_codec(name: &str) -> Option<Arc<dyn StateCodec>> {
+    match name {
+        "json" => Some(Arc::new(JsonCodec)),
+        "msgpack" => Some(Arc::new(MessagePackCodec)),
+        _ => None,
+    }
+}
+
+/// A game's state as a codec encoded it
+///
+/// It's saved with the state itself under `state` when the JSON codec encoded it, and with
+/// the encoded bytes in base64 under `bytes` otherwise.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EncodedState {
+    /// The name of the codec that encoded the state
+    pub codec: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SavedState {
+    Json { codec: String, state: Value },
+    Binary { codec: String, bytes: String },
+}
+
+impl Serialize for EncodedState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = (self.codec == JsonCodec.name())
+            .then(|| JsonCodec.decode(&self.bytes).ok())
+            .flatten();
+        let saved = match json {
+            Some(state) => SavedState::Json {
+                codec: self.codec.clone(),
+                state,
+            },
+            None => SavedState::Binary {
+                codec: self.codec.clone(),
+                bytes: STANDARD.encode(&self.bytes),
+            },
+        };
+        saved.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncodedState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match SavedState::deserialize(deserializer)? {
+            SavedState::Json { codec, state } => Ok(Self {
+                bytes: JsonCodec.encode(&state).map_err(serde::de::Error::custom)?,
+                codec,
+            }),
+            SavedState::Binary { codec, bytes } => Ok(Self {
+                bytes: STANDARD.decode(bytes).map_err(serde::de::Error::custom)?,
+                codec,
+            }),
+        }
+    }
+}
+
+/// Reads a game's state as saved, taking a payload saved before states were encoded to be
+/// encoded as JSON
+pub(crate) fn encoded_or_plain<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<EncodedState, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Encoded(EncodedState),
+        Plain(Value),
+    }
+
+    match Saved::deserialize(deserializer)? {
+        Saved::Encoded(encoded) => Ok(encoded),
+        Saved::Plain(payload) => Ok(EncodedState {
+            codec: JsonCodec.name().to_owned(),
+            bytes: JsonCodec
+                .encode(&payload)
+                .map_err(serde::de::Error::custom)?,
+        }),
+    }
+}
+
+/// The codec each game type's state is encoded with
+#[derive(Debug, Clone)]
+pub struct StateCodecs {
+    /// The codec for game types that weren't given one of their own
+    default: Arc<dyn StateCodec>,
+    by_game_type: HashMap<String, Arc<dyn StateCodec>>,
+}
+
+impl Default for StateCodecs {
+    fn default() -> Self {
+        Self::new(Arc::new(JsonCodec))
+    }
+}
+
+impl StateCodecs {
+    /// Encodes every game type's state with the `default` codec
+    pub fn new(default: Arc<dyn StateCodec>) -> Self {
+        Self {
+            default,
+            by_game_type: HashMap::new(),
+        }
+    }
+
+    /// Encodes the state of the game type's games with the codec
+    pub fn with_codec(mut self, game_type: impl Into<String>, codec: Arc<dyn StateCodec>) -> Self {
+        self.by_game_type.insert(game_type.into(), codec);
+        self
+    }
+
+    /// The codec the game type's states are encoded with, the default for rooms without a
+    /// game type
+    pub fn for_game_type(&self, game_type: Option<&str>) -> &dyn StateCodec {
+        game_type
+            .and_then(|game_type| self.by_game_type.get(game_type))
+            .unwrap_or(&self.default)
+            .as_ref()
+    }
+
+    /// Whether the game type's states are encoded as JSON, as clients send them
+    pub fn encodes_as_json(&self, game_type: Option<&str>) -> bool {
+        self.for_game_type(game_type).name() == JsonCodec.name()
+    }
+
+    pub fn encode(
+        &self,
+        game_type: Option<&str>,
+        state: &Value,
+    ) -> Result<EncodedState, StateCodecError> {
+        let codec = self.for_game_type(game_type);
+        Ok(EncodedState {
+            codec: codec.name().to_owned(),
+            bytes: codec.encode(state)?,
+        })
+    }
+
+    /// Decodes the state with the codec that encoded it, whichever game type it's for
+    pub fn decode(&self, encoded: &EncodedState) -> Result<Value, StateCodecError> {
+        let registered = std::iter::once(&self.default)
+            .chain(self.by_game_type.values())
+            .find(|codec| codec.name() == encoded.codec)
+            .cloned();
+        registered
+            .or_else(|| built_in_codec(&encoded.codec))
+            .ok_or_else(|| StateCodecError::UnknownCodec(encoded.codec.clone()))?
+            .decode(&encoded.bytes)
+    }
+}
+
+#[cfg(test)]
+mod state_codecs {
+    use serde_json::json;
+
+    use super::*;
+    use crate::game::{ConnectionId, GameState};
+
+    fn board() -> Value {
+        json!({ "board": [[0, 1, 2], [2, 1, 0]], "to_move": "white", "clock": 12.5 })
+    }
+
+    #[test]
+    fn encodes_with_each_game_types_codec() {
+        let codecs = StateCodecs::default().with_codec("go", Arc::new(MessagePackCodec));
+
+        let chess = codecs.encode(Some("chess"), &board()).unwrap();
+        let go = codecs.encode(Some("go"), &board()).unwrap();
+
+        assert_eq!(chess.codec, "json");
+        assert_eq!(go.codec, "msgpack");
+        assert!(go.bytes.len() < chess.bytes.len());
+        assert_eq!(codecs.decode(&chess).unwrap(), board());
+        assert_eq!(codecs.decode(&go).unwrap(), board());
+    }
+
+    #[test]
+    fn decodes_with_the_codec_that_encoded_the_state() {
+        let encoded = StateCodecs::default()
+            .with_codec("go", Arc::new(MessagePackCodec))
+            .encode(Some("go"), &board())
+            .unwrap();
+
+        assert_eq!(StateCodecs::default().decode(&encoded).unwrap(), board());
+        assert!(matches!(
+            StateCodecs::default().decode(&EncodedState {
+                codec: "protobuf".to_owned(),
+                bytes: Vec::new(),
+            }),
+            Err(StateCodecError::UnknownCodec(_))
+        ));
+    }
+
+    #[test]
+    fn saves_only_binary_states_as_base64() {
+        let codecs = StateCodecs::default().with_codec("go", Arc::new(MessagePackCodec));
+        let chess = codecs.encode(Some("chess"), &board()).unwrap();
+        let go = codecs.encode(Some("go"), &board()).unwrap();
+
+        let saved_chess = serde_json::to_value(&chess).unwrap();
+        let saved_go = serde_json::to_value(&go).unwrap();
+
+        assert_eq!(saved_chess, json!({ "codec": "json", "state": board() }));
+        assert_eq!(
+            saved_go,
+            json!({ "codec": "msgpack", "bytes": STANDARD.encode(&go.bytes) })
+        );
+        assert_eq!(
+            serde_json::from_value::<EncodedState>(saved_chess).unwrap(),
+            chess
+        );
+        assert_eq!(
+            serde_json::from_value::<EncodedState>(saved_go).unwrap(),
+            go
+        );
+    }
+
+    #[test]
+    fn reads_states_saved_before_they_were_encoded() {
+        let saved =
+            json!({ "from": ConnectionId::from(1), "from_player": null, "payload": board() });
+
+        let state: GameState = serde_json::from_value(saved).unwrap();
+
+        assert_eq!(
+            StateCodecs::default().decode(&state.payload).unwrap(),
+            board()
+        );
+        let resaved = serde_json::to_value(&state).unwrap();
+        assert_eq!(serde_json::from_value::<GameState>(resaved).unwrap(), state);
+    }
+}
@@ -0,0 +1,435 @@
+//! Room management over gRPC, for backend services such as matchmakers and tournament
+//! managers, served on its own port
+//!
+//! The service is defined in `proto/wormhole/v1/rooms.proto`.
+
+// Every tonic handler returns a Status as its error, boxing it would only add conversions
+#![allow(clippy::result_large_err)]
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::web;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+use tracing::{debug, info};
+
+use crate::auth::{ApiTokens, TokenScope};
+use crate::config::secrets::Reloadable;
+use crate::game::{
+    CreationStatus, LobbyEvent, RegistryBusy, RegistryHandle, Room, RoomId, SubmitCreationError,
+};
+use crate::SharedAppState;
+
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("wormhole.v1");
+}
+
+use proto::room_event::Event;
+use proto::rooms_server::{Rooms, RoomsServer};
+
+/// Whether the caller holds an API token allowing the call, `write` being whether the call
+/// changes anything
+fn authorize(tokens: &ApiTokens, metadata: &MetadataMap, write: bool) -> Result<(), Status> {
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let token = metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| Status::unauthenticated("Expected a bearer token"))?;
+    match tokens.scope_of(token) {
+        None => {
+            debug!(event = "grpc_token_rejected");
+            Err(Status::unauthenticated("The bearer token is not valid"))
+        }
+        Some(TokenScope::ReadOnly) if write => {
+            Err(Status::permission_denied("The bearer token is read-only"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+fn parse_room_id(id: &str) -> Result<RoomId, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument(format!("{id:?} is not a room ID")))
+}
+
+fn room_message(id: RoomId, room: Option<&Room>) -> proto::Room {
+    proto::Room {
+        id: id.to_string(),
+        private: room.is_some_and(Room::is_private),
+        player_count: room.map_or(0, |room| room.player_count() as u32),
+        connection_count: room.map_or(0, |room| room.broadcaster().subscriber_count() as u32),
+    }
+}
+
+fn unavailable(busy: RegistryBusy) -> Status {
+    Status::unavailable(busy.to_string())
+}
+
+async fn event_message(registry: &RegistryHandle, event: LobbyEvent) -> Result<Event, Status> {
+    Ok(match event {
+        LobbyEvent::RoomCreated(summary) => Event::Created(room_message(
+            summary.id,
+            registry
+                .get_room(summary.id)
+                .await
+                .map_err(unavailable)?
+                .as_deref(),
+        )),
+        LobbyEvent::RoomUpdated(summary) => Event::Updated(room_message(
+            summary.id,
+            registry
+                .get_room(summary.id)
+                .await
+                .map_err(unavailable)?
+                .as_deref(),
+        )),
+        LobbyEvent::RoomDeleted { id } => Event::Deleted(id.to_string()),
+    })
+}
+
+/// Serves the [Rooms] service from the state shared with the HTTP servers
+pub struct RoomService {
+    state: web::Data<SharedAppState>,
+    api_tokens: web::Data<Reloadable<ApiTokens>>,
+    /// Ends the streams of rooms being watched when the server shuts down, as they'd otherwise
+    /// hold it up forever
+    shutdown: CancellationToken,
+}
+
+impl RoomService {
+    pub fn new(
+        state: web::Data<SharedAppState>,
+        api_tokens: web::Data<Reloadable<ApiTokens>>,
+    ) -> Self {
+        Self {
+            state,
+            api_tokens,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    fn authorize<T>(&self, request: &Request<T>, write: bool) -> Result<(), Status> {
+        authorize(&self.api_tokens.get(), request.metadata(), write)
+    }
+
+    async fn room(&self, id: RoomId) -> Result<Arc<Room>, Status> {
+        self.state
+            .room_registry
+            .get_room(id)
+            .await
+            .map_err(unavailable)?
+            .ok_or_else(|| Status::not_found(format!("No room has the ID {id}")))
+    }
+}
+
+type RoomEventStream = Pin<Box<dyn Stream<Item = Result<proto::RoomEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Rooms for RoomService {
+    async fn create_room(
+        &self,
+        request: Request<proto::CreateRoomRequest>,
+    ) -> Result<Response<proto::Room>, Status> {
+        self.authorize(&request, true)?;
+        self.state
+            .maintenance
+            .check_room_creation()
+            .map_err(|problem| Status::unavailable(problem.to_string()))?;
+        let queue = &self.state.room_creation_queue;
+        let ticket = queue.submit().map_err(|e| match e {
+            SubmitCreationError::QueueFull | SubmitCreationError::WorkerStopped => {
+                Status::unavailable(e.to_string())
+            }
+        })?;
+
+        // Callers bound how long they're willing to wait with the call's deadline, unlike
+        // REST clients there's no ticket to hand back and have them poll
+        match queue.wait(ticket, Duration::MAX).await {
+            Some(CreationStatus::Created(id)) => {
+                info!(event = "grpc_room_created", room_id = %id);
+                Ok(Response::new(room_message(
+                    id,
+                    self.state
+                        .room_registry
+                        .get_room(id)
+                        .await
+                        .map_err(unavailable)?
+                        .as_deref(),
+                )))
+            }
+            Some(CreationStatus::Failed(e)) => Err(Status::internal(e.to_string())),
+            Some(CreationStatus::Pending | CreationStatus::Expired) | None => Err(
+                Status::unavailable("The request expired before it was processed"),
+            ),
+        }
+    }
+
+    async fn get_room(
+        &self,
+        request: Request<proto::GetRoomRequest>,
+    ) -> Result<Response<proto::Room>, Status> {
+        self.authorize(&request, false)?;
+        let id = parse_room_id(&request.get_ref().id)?;
+        let room = self.room(id).await?;
+        Ok(Response::new(room_message(id, Some(&room))))
+    }
+
+    async fn close_room(
+        &self,
+        request: Request<proto::CloseRoomRequest>,
+    ) -> Result<Response<proto::CloseRoomResponse>, Status> {
+        self.authorize(&request, true)?;
+        let id = parse_room_id(&request.get_ref().id)?;
+        let room = self.room(id).await?;
+        info!(event = "grpc_room_closed", room_id = %id);
+        room.close();
+        self.state.room_deletion_queue.request_deletion(id);
+        Ok(Response::new(proto::CloseRoomResponse {}))
+    }
+
+    async fn list_rooms(
+        &self,
+        request: Request<proto::ListRoomsRequest>,
+    ) -> Result<Response<proto::ListRoomsResponse>, Status> {
+        self.authorize(&request, false)?;
+        let rooms = lobby_rooms(&self.state.room_registry).await?;
+        Ok(Response::new(proto::ListRoomsResponse { rooms }))
+    }
+
+    type WatchRoomsStream = RoomEventStream;
+
+    async fn watch_rooms(
+        &self,
+        request: Request<proto::WatchRoomsRequest>,
+    ) -> Result<Response<Self::WatchRoomsStream>, Status> {
+        self.authorize(&request, false)?;
+        Ok(Response::new(lobby_events(
+            self.state.room_registry.clone(),
+            self.shutdown.clone(),
+        )))
+    }
+}
+
+/// The rooms listed in the lobby, as lobby browsers over HTTP see them
+async fn lobby_rooms(registry: &RegistryHandle) -> Result<Vec<proto::Room>, Status> {
+    let mut rooms = Vec::new();
+    for summary in registry.list_lobby_rooms() {
+        let room = registry.get_room(summary.id).await.map_err(unavailable)?;
+        rooms.push(room_message(summary.id, room.as_deref()));
+    }
+    Ok(rooms)
+}
+
+/// Changes to the lobby from now on, leaving out unlisted rooms as lobby browsers over HTTP
+/// do, until `shutdown` is cancelled
+fn lobby_events(registry: RegistryHandle, shutdown: CancellationToken) -> RoomEventStream {
+    let events = BroadcastStream::new(registry.lobby().subscribe_listed()).then(move |event| {
+        let registry = registry.clone();
+        async move {
+            let event = match event {
+                Ok(event) => event_message(&registry, event).await?,
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Event::Missed(missed),
+            };
+            Ok(proto::RoomEvent { event: Some(event) })
+        }
+    });
+    Box::pin(futures_util::StreamExt::take_until(
+        events,
+        shutdown.cancelled_owned(),
+    ))
+}
+
+/// Serves the [Rooms] service on `address` until `shutdown` is cancelled
+pub async fn serve(
+    address: SocketAddr,
+    mut service: RoomService,
+    shutdown: CancellationToken,
+) -> Result<(), tonic::transport::Error> {
+    service.shutdown = shutdown.clone();
+    info!(event = "grpc_listening", %address);
+    tonic::transport::Server::builder()
+        .add_service(RoomsServer::new(service))
+        .serve_with_shutdown(address, shutdown.cancelled_owned())
+        .await
+}
+
+#[cfg(test)]
+fn metadata_with_token(token: &str) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+    metadata
+}
+
+#[cfg(test)]
+mod authorize {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use tonic::Code;
+
+    fn tokens() -> ApiTokens {
+        let hash = |token: &str| hex::encode(Sha256::digest(token));
+        format!("{}:write,{}:read", hash("writer"), hash("reader"))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn allows_anyone_without_configured_tokens() {
+        assert!(authorize(&ApiTokens::default(), &MetadataMap::new(), true).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_tokens() {
+        let missing = authorize(&tokens(), &MetadataMap::new(), false).unwrap_err();
+        let unknown = authorize(&tokens(), &metadata_with_token("stranger"), false).unwrap_err();
+
+        assert_eq!(missing.code(), Code::Unauthenticated);
+        assert_eq!(unknown.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn limits_read_only_tokens_to_reads() {
+        let metadata = metadata_with_token("reader");
+
+        assert!(authorize(&tokens(), &metadata, false).is_ok());
+        assert_eq!(
+            authorize(&tokens(), &metadata, true).unwrap_err().code(),
+            Code::PermissionDenied
+        );
+        assert!(authorize(&tokens(), &metadata_with_token("writer"), true).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod event_message {
+    use super::*;
+    use crate::game::RoomRegistry;
+
+    fn registry() -> RegistryHandle {
+        RegistryHandle::new(Arc::new(RoomRegistry::new(4)), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn describes_rooms_as_they_are_now() {
+        let registry = registry();
+        let id = registry.registry().create_room().unwrap();
+        registry
+            .registry()
+            .get_room_for_id(id)
+            .unwrap()
+            .set_private(true);
+
+        assert_eq!(
+            event_message(
+                &registry,
+                LobbyEvent::RoomCreated(crate::game::RoomSummary {
+                    id,
+                    name: None,
+                    game_type: None,
+                    player_count: 0,
+                    spectator_count: 0,
+                    capacity: None,
+                    state: crate::game::RoomStatus::Open,
+                    visibility: crate::game::Visibility::Public,
+                    region: None,
+                    starts_at: None,
+                    deletes_at: None,
+                    last_active_at: None,
+                })
+            )
+            .await
+            .unwrap(),
+            Event::Created(proto::Room {
+                id: id.to_string(),
+                private: true,
+                player_count: 0,
+                connection_count: 0,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn identifies_deleted_rooms() {
+        let id = RoomId::from(1_u128);
+
+        assert_eq!(
+            event_message(&registry(), LobbyEvent::RoomDeleted { id })
+                .await
+                .unwrap(),
+            Event::Deleted("00000000-0000-0000-0000-000000000001".to_owned())
+        );
+    }
+}
+
+#[cfg(test)]
+mod lobby {
+    use super::*;
+    use crate::game::{RoomRegistry, Visibility};
+
+    fn registry() -> RegistryHandle {
+        RegistryHandle::new(Arc::new(RoomRegistry::new(4)), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn lists_only_rooms_listed_in_the_lobby() {
+        let registry = registry();
+        let listed = registry.registry().create_room().unwrap();
+        let unlisted = registry.registry().create_room().unwrap();
+        registry
+            .registry()
+            .get_room_for_id(unlisted)
+            .unwrap()
+            .set_visibility(Visibility::Unlisted);
+        registry.registry().room_updated(unlisted);
+        registry.registry().publish_updates();
+
+        let rooms = lobby_rooms(&registry).await.unwrap();
+
+        assert_eq!(
+            rooms.iter().map(|room| room.id.clone()).collect::<Vec<_>>(),
+            vec![listed.to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_watching_on_shutdown() {
+        let shutdown = CancellationToken::new();
+        let mut events = lobby_events(registry(), shutdown.clone());
+
+        shutdown.cancel();
+
+        assert!(events.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shows_rooms_made_unlisted_as_deleted() {
+        let registry = registry();
+        let id = registry.registry().create_room().unwrap();
+        let mut events = lobby_events(registry.clone(), CancellationToken::new());
+
+        registry
+            .registry()
+            .get_room_for_id(id)
+            .unwrap()
+            .set_visibility(Visibility::Unlisted);
+        registry.registry().room_updated(id);
+        registry.registry().publish_updates();
+
+        assert_eq!(
+            events.next().await.unwrap().unwrap().event,
+            Some(Event::Deleted(id.to_string()))
+        );
+    }
+}
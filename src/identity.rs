@@ -0,0 +1,387 @@
+//! Player authentication with JSON Web Tokens issued by an external identity provider
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest};
+use arc_swap::ArcSwap;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument, warn};
+
+use crate::authorization::{Role, Roles};
+use crate::game::PlayerId;
+use crate::problem::Problem;
+
+/// The shortest time between two fetches of the provider's signing keys, so tokens
+/// signed with unknown keys can't be used to hammer the provider
+const MIN_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The query parameter a token can be passed in by clients that can't set headers,
+/// such as browsers opening a WebSocket
+const ACCESS_TOKEN_QUERY_PARAM: &str = "access_token";
+
+/// The identity provider whose tokens players authenticate with
+#[derive(Debug, Clone)]
+pub struct IdentityProvider {
+    /// The expected `iss` claim
+    pub issuer: String,
+    /// The expected `aud` claim
+    pub audience: String,
+    /// Where the provider publishes the JSON Web Key Set its tokens are signed with
+    pub jwks_url: String,
+}
+
+#[derive(Error, Debug)]
+pub enum PlayerAuthError {
+    #[error("Expected a bearer token")]
+    MissingToken,
+    #[error("The token doesn't name the key it was signed with")]
+    MissingKeyId,
+    #[error("The token was signed with unknown key {0:?}")]
+    UnknownKey(String),
+    #[error("The token is not valid: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    #[error("The player's session has been revoked")]
+    Revoked,
+    #[error("The identity provider's signing keys could not be fetched: {0}")]
+    KeysUnavailable(#[from] reqwest::Error),
+}
+
+impl From<PlayerAuthError> for Problem {
+    fn from(e: PlayerAuthError) -> Self {
+        match e {
+            PlayerAuthError::KeysUnavailable(_) => Problem::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "/problems/identity-provider-unavailable",
+                "The identity provider could not be reached",
+            )
+            .with_detail(e.to_string()),
+            _ => Problem::unauthorized(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    /// When the token was issued, in seconds since the Unix epoch
+    iat: Option<u64>,
+    /// Server-wide roles such as `moderator` or `admin`, unknown roles are ignored
+    #[serde(default)]
+    roles: Vec<String>,
+    /// The player's display name, from the standard OpenID Connect claim
+    name: Option<String>,
+}
+
+impl Claims {
+    fn roles(&self) -> Roles {
+        self.roles
+            .iter()
+            .filter_map(|role| match role.as_str() {
+                "moderator" => Some(Role::Moderator),
+                "admin" => Some(Role::Admin),
+                _ => None,
+            })
+            .chain([Role::Player])
+            .collect()
+    }
+}
+
+/// A player whose token has been verified
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuthenticatedPlayer {
+    pub id: PlayerId,
+    /// The server-wide roles granted by the player's token
+    pub roles: Roles,
+    /// When the player's token was issued, in seconds since the Unix epoch
+    pub issued_at: Option<u64>,
+    /// The name the player's token gives them, if any
+    pub display_name: Option<String>,
+}
+
+/// Verifies player tokens against the signing keys published by an [identity provider][IdentityProvider]
+///
+/// Keys are fetched on first use and fetched again whenever a token names a key
+/// that hasn't been seen yet, so the provider can rotate its keys freely.
+#[derive(Debug)]
+pub struct PlayerAuthenticator {
+    provider: IdentityProvider,
+    client: reqwest::Client,
+    keys: ArcSwap<JwkSet>,
+    /// When the keys were last fetched, held across the fetch so concurrent misses share it
+    last_refresh: Mutex<Option<Instant>>,
+}
+
+impl PlayerAuthenticator {
+    pub fn new(provider: IdentityProvider) -> Self {
+        Self {
+            provider,
+            client: reqwest::Client::new(),
+            keys: ArcSwap::from_pointee(JwkSet { keys: Vec::new() }),
+            last_refresh: Mutex::new(None),
+        }
+    }
+
+    /// Authenticates the player presenting the request's bearer token
+    pub async fn authenticate_request(
+        &self,
+        req: &HttpRequest,
+    ) -> Result<AuthenticatedPlayer, PlayerAuthError> {
+        let token = request_token(req).ok_or(PlayerAuthError::MissingToken)?;
+        self.authenticate(&token).await
+    }
+
+    /// Verifies the token, mapping its subject to the player's [id][PlayerId] and reading their roles
+    #[instrument(skip_all)]
+    pub async fn authenticate(&self, token: &str) -> Result<AuthenticatedPlayer, PlayerAuthError> {
+        let header = decode_header(token)?;
+        let key_id = header.kid.ok_or(PlayerAuthError::MissingKeyId)?;
+        let (key, algorithm) = match self.decoding_key(&key_id, header.alg)? {
+            Some(key) => key,
+            None => {
+                self.refresh_keys().await?;
+                self.decoding_key(&key_id, header.alg)?
+                    .ok_or_else(|| PlayerAuthError::UnknownKey(key_id.clone()))?
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&self.provider.issuer]);
+        validation.set_audience(&[&self.provider.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<Claims>(token, &key, &validation)?.claims;
+
+        let player = AuthenticatedPlayer {
+            id: PlayerId::from_subject(&self.provider.issuer, &claims.sub),
+            roles: claims.roles(),
+            issued_at: claims.iat,
+            display_name: claims.name,
+        };
+        debug!(event = "player_authenticated", player_id = %player.id, roles = ?player.roles);
+        Ok(player)
+    }
+
+    /// The key named `key_id` along with the algorithm it signs with, falling back to
+    /// the token's algorithm for keys that don't specify one
+    fn decoding_key(
+        &self,
+        key_id: &str,
+        token_algorithm: Algorithm,
+    ) -> Result<Option<(DecodingKey, Algorithm)>, PlayerAuthError> {
+        let keys = self.keys.load();
+        let Some(jwk) = keys.find(key_id) else {
+            return Ok(None);
+        };
+
+        let algorithm = match jwk.common.key_algorithm {
+            Some(algorithm) => Algorithm::from_str(&algorithm.to_string())?,
+            None => token_algorithm,
+        };
+        if algorithm != token_algorithm {
+            return Err(jsonwebtoken::errors::Error::from(ErrorKind::InvalidAlgorithm).into());
+        }
+        Ok(Some((DecodingKey::from_jwk(jwk)?, algorithm)))
+    }
+
+    async fn refresh_keys(&self) -> Result<(), PlayerAuthError> {
+        let mut last_refresh = self.last_refresh.lock().await;
+        if last_refresh.is_some_and(|at| at.elapsed() < MIN_KEY_REFRESH_INTERVAL) {
+            return Ok(());
+        }
+        *last_refresh = Some(Instant::now());
+
+        let keys: JwkSet = self
+            .client
+            .get(&self.provider.jwks_url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| {
+                warn!(event = "jwks_fetch_failed", error = %e);
+                e
+            })?
+            .json()
+            .await?;
+        info!(event = "jwks_refreshed", key_count = keys.keys.len());
+        self.keys.store(Arc::new(keys));
+        Ok(())
+    }
+}
+
+/// The token from the request's `Authorization` header, or its `access_token` query parameter
+fn request_token(req: &HttpRequest) -> Option<String> {
+    if let Some(token) = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        return Some(token.trim().to_owned());
+    }
+
+    web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .ok()?
+        .remove(ACCESS_TOKEN_QUERY_PARAM)
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) const TEST_ISSUER: &str = "https://id.example.com";
+#[cfg(any(test, feature = "test-support"))]
+const TEST_SECRET: &[u8] = b"a-test-secret-that-is-long-enough";
+
+/// An authenticator that already holds an HS256 key named `test-key` signing with [TEST_SECRET]
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) fn test_authenticator() -> PlayerAuthenticator {
+    let keys = serde_json::from_value(serde_json::json!({
+        "keys": [{
+            "kty": "oct",
+            "kid": "test-key",
+            "alg": "HS256",
+            "k": "YS10ZXN0LXNlY3JldC10aGF0LWlzLWxvbmctZW5vdWdo",
+        }]
+    }))
+    .unwrap();
+
+    PlayerAuthenticator {
+        provider: IdentityProvider {
+            issuer: TEST_ISSUER.to_owned(),
+            audience: "wormhole".to_owned(),
+            jwks_url: "http://127.0.0.1:9/jwks.json".to_owned(),
+        },
+        client: reqwest::Client::new(),
+        keys: ArcSwap::from_pointee(keys),
+        last_refresh: Mutex::new(Some(Instant::now())),
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub(crate) fn token(key_id: &str, claims: serde_json::Value) -> String {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    let header = Header {
+        kid: Some(key_id.to_owned()),
+        ..Header::new(Algorithm::HS256)
+    };
+    encode(&header, &claims, &EncodingKey::from_secret(TEST_SECRET)).unwrap()
+}
+
+#[cfg(test)]
+fn claims(audience: &str) -> serde_json::Value {
+    serde_json::json!({
+        "sub": "player-1",
+        "iss": TEST_ISSUER,
+        "aud": audience,
+        "exp": jsonwebtoken::get_current_timestamp() + 60,
+    })
+}
+
+#[cfg(test)]
+mod authenticate {
+    use super::*;
+
+    #[tokio::test]
+    async fn maps_subject_to_player_id() {
+        let authenticator = test_authenticator();
+
+        let player = authenticator
+            .authenticate(&token("test-key", claims("wormhole")))
+            .await
+            .unwrap();
+
+        assert_eq!(player.id, PlayerId::from_subject(TEST_ISSUER, "player-1"));
+        assert_eq!(player.roles, Roles::default().with(Role::Player));
+        assert_eq!(player.display_name, None);
+    }
+
+    #[tokio::test]
+    async fn reads_the_display_name() {
+        let authenticator = test_authenticator();
+        let mut claims = claims("wormhole");
+        claims["name"] = serde_json::json!("Ada");
+
+        let player = authenticator
+            .authenticate(&token("test-key", claims))
+            .await
+            .unwrap();
+
+        assert_eq!(player.display_name.as_deref(), Some("Ada"));
+    }
+
+    #[tokio::test]
+    async fn reads_server_wide_roles() {
+        let authenticator = test_authenticator();
+        let mut claims = claims("wormhole");
+        claims["roles"] = serde_json::json!(["moderator", "room_owner", "unknown"]);
+
+        let player = authenticator
+            .authenticate(&token("test-key", claims))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            player.roles,
+            [Role::Player, Role::Moderator].into_iter().collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_for_other_audiences() {
+        let authenticator = test_authenticator();
+
+        let result = authenticator
+            .authenticate(&token("test-key", claims("someone-else")))
+            .await;
+
+        assert!(matches!(result, Err(PlayerAuthError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_tokens() {
+        let authenticator = test_authenticator();
+        let mut claims = claims("wormhole");
+        claims["exp"] = serde_json::json!(1);
+
+        let result = authenticator.authenticate(&token("test-key", claims)).await;
+
+        assert!(matches!(result, Err(PlayerAuthError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_signed_with_unknown_keys() {
+        let authenticator = test_authenticator();
+
+        let result = authenticator
+            .authenticate(&token("rotated-key", claims("wormhole")))
+            .await;
+
+        assert!(matches!(result, Err(PlayerAuthError::UnknownKey(kid)) if kid == "rotated-key"));
+    }
+}
+
+#[cfg(test)]
+mod request_token {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn reads_authorization_header() {
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer abc"))
+            .to_http_request();
+        assert_eq!(request_token(&req), Some("abc".to_owned()));
+    }
+
+    #[test]
+    fn falls_back_to_query_parameter() {
+        let req = TestRequest::with_uri("/ws/room?access_token=abc").to_http_request();
+        assert_eq!(request_token(&req), Some("abc".to_owned()));
+    }
+}
@@ -0,0 +1,359 @@
+//! Invitations from one player to another to join a room
+//!
+//! An invitation holds a seat in the room for the invited player, and is
+//! [delivered][deliver] as a [ServerMessage::Invitation] to each of their live connections,
+//! on this instance or any other sharing the lobby. Players who aren't connected anywhere
+//! find it in their inbox, and are sent it when they next connect. Once the invited player
+//! answers, whether they accepted is delivered to the player who invited them the same way.
+//!
+//! Invitations are kept by the instance they're sent through and by the instances they're
+//! delivered to, so a player who was offline is only sent them on connecting to the one they
+//! were sent through. They're forgotten once answered or once their seat runs out. Answers
+//! are [relayed][deliver_answer] through the same channel invitations are delivered through
+//! to every instance, whether or not the player who sent it is connected to one, so the
+//! instance it was sent through and any it was delivered to forget it too.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use thiserror::Error;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::clock::unix_time;
+use crate::cluster::RedisBridge;
+use crate::game::{PlayerId, RoomId};
+use crate::invites::Invite;
+use crate::protocol::ServerMessage;
+use crate::sessions::SessionRegistry;
+
+pub use wormhole_protocol::{InvitationId, RoomInvitation};
+
+/// The most unanswered invitations a player can have waiting for them
+pub const MAX_PENDING_INVITATIONS: usize = 50;
+
+/// Raised when an invitation can't be sent or answered
+#[derive(Error, Debug, PartialEq)]
+pub enum InvitationError {
+    #[error("Players can't invite themselves")]
+    SelfInvite,
+    #[error("{0} has too many unanswered invitations")]
+    InboxFull(PlayerId),
+    #[error("No invitation to the player has the ID")]
+    NotFound,
+}
+
+#[derive(Debug)]
+struct Pending {
+    invitation: RoomInvitation,
+    to: PlayerId,
+    /// Whether the invited player has been sent the invitation over a live connection
+    delivered: bool,
+}
+
+/// The unanswered invitations this instance knows of
+#[derive(Debug, Default)]
+pub struct InvitationBook {
+    pending: Mutex<HashMap<InvitationId, Pending>>,
+}
+
+impl InvitationBook {
+    /// Records an invitation from `from` to `to` to join the room, holding the seat `invite`
+    /// for them
+    pub fn send(
+        &self,
+        from: PlayerId,
+        to: PlayerId,
+        room_id: RoomId,
+        invite: Invite,
+    ) -> Result<RoomInvitation, InvitationError> {
+        if from == to {
+            return Err(InvitationError::SelfInvite);
+        }
+        let mut pending = self.pending.lock().unwrap();
+        prune(&mut pending);
+        if pending.values().filter(|p| p.to == to).count() >= MAX_PENDING_INVITATIONS {
+            return Err(InvitationError::InboxFull(to));
+        }
+        let invitation = RoomInvitation {
+            id: Uuid::new_v4().as_u128().into(),
+            from,
+            room_id,
+            invite,
+        };
+        pending.insert(
+            invitation.id,
+            Pending {
+                invitation: invitation.clone(),
+                to,
+                delivered: false,
+            },
+        );
+        info!(event = "invitation_sent", invitation_id = %invitation.id, %from, %to, %room_id);
+        Ok(invitation)
+    }
+
+    /// Records that the invited player has been sent an invitation over a live connection,
+    /// remembering it if it was sent through another instance so it can be answered here
+    pub fn delivered(&self, to: PlayerId, invitation: &RoomInvitation) {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(invitation.id)
+            .or_insert_with(|| Pending {
+                invitation: invitation.clone(),
+                to,
+                delivered: true,
+            })
+            .delivered = true;
+    }
+
+    /// The player's unanswered invitations, oldest first
+    pub fn inbox(&self, player_id: PlayerId) -> Vec<RoomInvitation> {
+        let mut pending = self.pending.lock().unwrap();
+        prune(&mut pending);
+        sorted(
+            pending
+                .values()
+                .filter(|p| p.to == player_id)
+                .map(|p| p.invitation.clone()),
+        )
+    }
+
+    /// The player's unanswered invitations that they haven't been sent over a live
+    /// connection, oldest first, which count as delivered from now on
+    pub fn take_undelivered(&self, player_id: PlayerId) -> Vec<RoomInvitation> {
+        let mut pending = self.pending.lock().unwrap();
+        prune(&mut pending);
+        sorted(
+            pending
+                .values_mut()
+                .filter(|p| p.to == player_id && !p.delivered)
+                .map(|p| {
+                    p.delivered = true;
+                    p.invitation.clone()
+                }),
+        )
+    }
+
+    /// Forgets an invitation answered through another instance, or one whose seat couldn't be
+    /// held after all
+    pub fn forget(&self, id: InvitationId) {
+        if self.pending.lock().unwrap().remove(&id).is_some() {
+            debug!(event = "invitation_forgotten", invitation_id = %id);
+        }
+    }
+
+    /// Forgets an invitation to the player now that they've answered it, returning it so the
+    /// player who sent it can be told
+    pub fn answer(
+        &self,
+        id: InvitationId,
+        player_id: PlayerId,
+        accept: bool,
+    ) -> Result<RoomInvitation, InvitationError> {
+        let mut pending = self.pending.lock().unwrap();
+        prune(&mut pending);
+        if pending.get(&id).is_none_or(|p| p.to != player_id) {
+            return Err(InvitationError::NotFound);
+        }
+        let invitation = pending.remove(&id).unwrap().invitation;
+        info!(event = "invitation_answered", invitation_id = %id, %player_id, accept);
+        Ok(invitation)
+    }
+}
+
+/// Forgets the invitations whose seats have run out
+fn prune(pending: &mut HashMap<InvitationId, Pending>) {
+    let now = unix_time();
+    pending.retain(|_, p| p.invitation.invite.expires_at > now);
+}
+
+/// Invitations sent earlier run out earlier, since they all hold their seats as long
+fn sorted(invitations: impl Iterator<Item = RoomInvitation>) -> Vec<RoomInvitation> {
+    let mut invitations: Vec<_> = invitations.collect();
+    invitations.sort_by_key(|invitation| invitation.invite.expires_at);
+    invitations
+}
+
+/// Sends the message to each of the player's live connections, on this instance or, through
+/// the `cluster`, any other sharing the lobby, returning whether they're connected anywhere
+pub async fn deliver(
+    sessions: &SessionRegistry,
+    cluster: Option<&RedisBridge>,
+    player_id: PlayerId,
+    message: ServerMessage<'static>,
+) -> bool {
+    let local = sessions.notify(player_id, message.clone()) > 0;
+    let elsewhere = match cluster {
+        Some(cluster) => cluster
+            .notify_player(player_id, &message)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(event = "notice_relay_failed", %player_id, error = %e);
+                false
+            }),
+        None => false,
+    };
+    local || elsewhere
+}
+
+/// Tells the player who sent the invitation how it was answered, like [deliver], and relays
+/// the answer through the `cluster` to every other instance so they forget the invitation,
+/// returning whether the player who sent it is connected anywhere
+pub async fn deliver_answer(
+    sessions: &SessionRegistry,
+    cluster: Option<&RedisBridge>,
+    invitation: &RoomInvitation,
+    player_id: PlayerId,
+    accepted: bool,
+) -> bool {
+    let message = ServerMessage::InvitationAnswered {
+        invitation_id: invitation.id,
+        player_id,
+        accepted,
+    };
+    let local = sessions.notify(invitation.from, message.clone()) > 0;
+    let elsewhere = match cluster {
+        Some(cluster) => cluster
+            .relay_answer(invitation.from, &message)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(event = "notice_relay_failed", player_id = %invitation.from, error = %e);
+                false
+            }),
+        None => false,
+    };
+    local || elsewhere
+}
+
+#[cfg(test)]
+mod send {
+    use super::*;
+
+    fn seat(expires_at: u64) -> Invite {
+        Invite {
+            token: "seat".to_owned(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn keeps_invitations_in_the_invited_players_inbox() {
+        let book = InvitationBook::default();
+        let [a, b, c] = [1, 2, 3].map(PlayerId::from);
+        let later = book
+            .send(a, b, 1_u128.into(), seat(unix_time() + 120))
+            .unwrap();
+        let sooner = book
+            .send(c, b, 2_u128.into(), seat(unix_time() + 60))
+            .unwrap();
+        book.send(b, a, 1_u128.into(), seat(unix_time() + 60))
+            .unwrap();
+
+        assert_eq!(book.inbox(b), [sooner, later]);
+        assert_eq!(
+            book.send(a, a, 1_u128.into(), seat(unix_time() + 60)),
+            Err(InvitationError::SelfInvite)
+        );
+    }
+
+    #[test]
+    fn forgets_invitations_whose_seats_ran_out() {
+        let book = InvitationBook::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        book.send(a, b, 1_u128.into(), seat(unix_time() - 1))
+            .unwrap();
+
+        assert!(book.inbox(b).is_empty());
+    }
+
+    #[test]
+    fn caps_each_players_inbox() {
+        let book = InvitationBook::default();
+        let to = PlayerId::from(0);
+        for from in 1..=MAX_PENDING_INVITATIONS {
+            book.send(
+                PlayerId::from(from as u128),
+                to,
+                1_u128.into(),
+                seat(unix_time() + 60),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            book.send(
+                PlayerId::from(99),
+                to,
+                1_u128.into(),
+                seat(unix_time() + 60)
+            ),
+            Err(InvitationError::InboxFull(to))
+        );
+    }
+}
+
+#[cfg(test)]
+mod answer {
+    use super::*;
+
+    #[test]
+    fn only_lets_the_invited_player_answer_once() {
+        let book = InvitationBook::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let invite = Invite {
+            token: "seat".to_owned(),
+            expires_at: unix_time() + 60,
+        };
+        let invitation = book.send(a, b, 1_u128.into(), invite).unwrap();
+
+        assert_eq!(
+            book.answer(invitation.id, a, true),
+            Err(InvitationError::NotFound)
+        );
+        assert_eq!(book.answer(invitation.id, b, false), Ok(invitation.clone()));
+        assert_eq!(
+            book.answer(invitation.id, b, true),
+            Err(InvitationError::NotFound)
+        );
+    }
+
+    #[test]
+    fn takes_invitations_sent_through_other_instances() {
+        let book = InvitationBook::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let invitation = RoomInvitation {
+            id: 7.into(),
+            from: a,
+            room_id: 1_u128.into(),
+            invite: Invite {
+                token: "seat".to_owned(),
+                expires_at: unix_time() + 60,
+            },
+        };
+        book.delivered(b, &invitation);
+
+        assert!(book.take_undelivered(b).is_empty());
+        assert_eq!(book.answer(invitation.id, b, true), Ok(invitation));
+    }
+
+    #[test]
+    fn forgets_invitations_answered_elsewhere() {
+        let book = InvitationBook::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let invite = Invite {
+            token: "seat".to_owned(),
+            expires_at: unix_time() + 60,
+        };
+        let invitation = book.send(a, b, 1_u128.into(), invite).unwrap();
+
+        book.forget(invitation.id);
+
+        assert!(book.inbox(b).is_empty());
+        assert_eq!(
+            book.answer(invitation.id, b, true),
+            Err(InvitationError::NotFound)
+        );
+    }
+}
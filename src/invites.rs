@@ -0,0 +1,261 @@
+//! Signed, expiring invitations to join private rooms, optionally holding a seat for one
+//! player
+//!
+//! An invite token is the base64url encoded JSON of its [claims][InviteClaims] followed by
+//! a `.` and the base64url encoded HMAC-SHA256 of that encoding, so the server can check
+//! an invite without having stored it.
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+pub use wormhole_protocol::Invite;
+
+use crate::clock::unix_time;
+use crate::game::{PlayerId, RoomId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long invites last when no lifetime is asked for
+pub const DEFAULT_INVITE_TTL: Duration = Duration::from_secs(60 * 60);
+/// The longest an invite may last
+pub const MAX_INVITE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// An ID that identifies an invite so its uses can be counted
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InviteId(Uuid);
+
+/// What an invite token grants
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub id: InviteId,
+    pub room_id: RoomId,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+    /// How many times the invite can be used to join, `None` for no limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    /// The player the invite holds a seat for, `None` for invites anyone can use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player_id: Option<PlayerId>,
+    /// The team the seat is on, for seats in matches played in teams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum InviteError {
+    #[error("No invite was given")]
+    Missing,
+    #[error("The invite is malformed")]
+    Malformed,
+    #[error("The invite's signature is not valid")]
+    InvalidSignature,
+    #[error("The invite has expired")]
+    Expired,
+    #[error("The invite is for a different room")]
+    WrongRoom,
+    #[error("The invite has been used the maximum number of times")]
+    UsedUp,
+    #[error("The invite is for a different player")]
+    WrongPlayer,
+}
+
+/// Mints and verifies invite tokens with a key only the server knows
+pub struct InviteSigner {
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl std::fmt::Debug for InviteSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InviteSigner").finish_non_exhaustive()
+    }
+}
+
+impl InviteSigner {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key: Zeroizing::new(key),
+        }
+    }
+
+    /// Creates a signer with a random key, so invites stop working when the server restarts
+    /// just as the rooms they're for do
+    pub fn random() -> Self {
+        let mut key = [0; 32];
+        key[..16].copy_from_slice(Uuid::new_v4().as_bytes());
+        key[16..].copy_from_slice(Uuid::new_v4().as_bytes());
+        Self::new(key)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(self.key.as_slice()).expect("HMAC accepts keys of any length")
+    }
+
+    /// Mints an invite to the room lasting `ttl`, capped at [MAX_INVITE_TTL]
+    pub fn mint(&self, room_id: RoomId, ttl: Duration, max_uses: Option<u32>) -> Invite {
+        self.sign(InviteClaims {
+            id: InviteId(Uuid::new_v4()),
+            room_id,
+            expires_at: unix_time() + ttl.min(MAX_INVITE_TTL).as_secs(),
+            max_uses,
+            player_id: None,
+            team: None,
+        })
+    }
+
+    /// Mints an invite lasting `ttl` that only the player can use, as often as they
+    /// need to reconnect, to take the seat reserved for them in the room, on `team` if the
+    /// match is played in teams
+    pub fn mint_seat(
+        &self,
+        room_id: RoomId,
+        player_id: PlayerId,
+        team: Option<String>,
+        ttl: Duration,
+    ) -> Invite {
+        self.sign(InviteClaims {
+            id: InviteId(Uuid::new_v4()),
+            room_id,
+            expires_at: unix_time() + ttl.min(MAX_INVITE_TTL).as_secs(),
+            max_uses: None,
+            player_id: Some(player_id),
+            team,
+        })
+    }
+
+    fn sign(&self, claims: InviteClaims) -> Invite {
+        let payload = URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims).expect("Invite claims always serialize"));
+        let signature = URL_SAFE_NO_PAD.encode(
+            self.mac()
+                .chain_update(payload.as_bytes())
+                .finalize()
+                .into_bytes(),
+        );
+        Invite {
+            token: format!("{payload}.{signature}"),
+            expires_at: claims.expires_at,
+        }
+    }
+
+    /// Checks that the token was minted by this signer for the room and hasn't expired
+    pub fn verify(&self, token: &str, room_id: RoomId) -> Result<InviteClaims, InviteError> {
+        let (payload, signature) = token.split_once('.').ok_or(InviteError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| InviteError::Malformed)?;
+        self.mac()
+            .chain_update(payload.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| InviteError::InvalidSignature)?;
+
+        let claims: InviteClaims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(InviteError::Malformed)?;
+        if claims.room_id != room_id {
+            return Err(InviteError::WrongRoom);
+        }
+        if unix_time() >= claims.expires_at {
+            return Err(InviteError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod verify {
+    use super::*;
+
+    fn signer() -> InviteSigner {
+        InviteSigner::new([7; 32])
+    }
+
+    #[test]
+    fn accepts_minted_invites() {
+        let room_id = RoomId::from(1);
+        let invite = signer().mint(room_id, DEFAULT_INVITE_TTL, Some(3));
+
+        let claims = signer().verify(&invite.token, room_id).unwrap();
+
+        assert_eq!(claims.room_id, room_id);
+        assert_eq!(claims.expires_at, invite.expires_at);
+        assert_eq!(claims.max_uses, Some(3));
+    }
+
+    #[test]
+    fn accepts_minted_seats() {
+        let room_id = RoomId::from(1);
+        let invite = signer().mint_seat(
+            room_id,
+            PlayerId::from(9),
+            Some("red".to_owned()),
+            DEFAULT_INVITE_TTL,
+        );
+
+        let claims = signer().verify(&invite.token, room_id).unwrap();
+
+        assert_eq!(claims.player_id, Some(PlayerId::from(9)));
+        assert_eq!(claims.team.as_deref(), Some("red"));
+        assert_eq!(claims.max_uses, None);
+    }
+
+    #[test]
+    fn rejects_invites_for_other_rooms() {
+        let invite = signer().mint(RoomId::from(1), DEFAULT_INVITE_TTL, None);
+        assert_eq!(
+            signer().verify(&invite.token, RoomId::from(2)),
+            Err(InviteError::WrongRoom)
+        );
+    }
+
+    #[test]
+    fn rejects_expired_invites() {
+        let invite = signer().mint(RoomId::from(1), Duration::ZERO, None);
+        assert_eq!(
+            signer().verify(&invite.token, RoomId::from(1)),
+            Err(InviteError::Expired)
+        );
+    }
+
+    #[test]
+    fn rejects_invites_signed_with_another_key() {
+        let invite = InviteSigner::new([8; 32]).mint(RoomId::from(1), DEFAULT_INVITE_TTL, None);
+        assert_eq!(
+            signer().verify(&invite.token, RoomId::from(1)),
+            Err(InviteError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_claims() {
+        let invite = signer().mint(RoomId::from(1), DEFAULT_INVITE_TTL, Some(1));
+        let (_, signature) = invite.token.split_once('.').unwrap();
+        let mut claims = signer().verify(&invite.token, RoomId::from(1)).unwrap();
+        claims.max_uses = None;
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+
+        assert_eq!(
+            signer().verify(&format!("{payload}.{signature}"), RoomId::from(1)),
+            Err(InviteError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(
+            signer().verify("not-an-invite", RoomId::from(1)),
+            Err(InviteError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Cluster-wide cleanup of what instances leave behind in Redis, done by one instance at a
+//! time
+//!
+//! Every [JANITOR_INTERVAL] each instance tries to take, or renew, the lease held in
+//! [JANITOR_LEADER_KEY]. Whichever holds it sweeps the shared store:
+//!
+//! - removing the registrations of instances whose heartbeat is older than
+//!   [INSTANCE_TIMEOUT][crate::cluster::INSTANCE_TIMEOUT], recording their rooms as [orphaned][OrphanedRoom]
+//! - forgetting orphaned rooms once they've been kept for [ORPHANED_ROOM_RETENTION]
+//! - deleting the [players hashes][crate::cluster::players_key] of instances that are no longer registered,
+//!   rather than waiting for them to expire
+//! - compacting the registrations and orphaned rooms by removing entries that can't be read
+//!
+//! Invites are signed rather than stored, so there are none to expire.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncCommands, AsyncIter, RedisResult, Script};
+use serde::de::DeserializeOwned;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::clock::unix_time;
+use crate::cluster::{
+    dead_nodes, registered_nodes, NodeInfo, OrphanedRoom, NODES_KEY, ORPHANED_ROOMS_KEY,
+    PLAYERS_KEY_PREFIX,
+};
+
+/// The Redis key holding the ID of the instance currently sweeping the cluster
+pub const JANITOR_LEADER_KEY: &str = "wormhole:janitor:leader";
+/// How often the cluster is swept
+pub const JANITOR_INTERVAL: Duration = Duration::from_secs(30);
+/// How long the sweeping instance holds the lease without renewing it, so another takes over
+/// within this long of it going down
+const JANITOR_LEASE: Duration = Duration::from_secs(2 * JANITOR_INTERVAL.as_secs());
+/// How long orphaned rooms are kept for operators to look into before they're forgotten
+pub const ORPHANED_ROOM_RETENTION: Duration = Duration::from_secs(15 * 60);
+
+/// Takes the lease in `KEYS[1]` for the instance in `ARGV[1]` for `ARGV[2]` milliseconds if
+/// it's free, or extends it if the instance already holds it, returning 1 if it holds it
+const LEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+";
+
+/// The kinds of leftovers a sweep removes
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Reaped {
+    /// Registrations of instances that stopped sending heartbeats
+    DeadNodes,
+    /// Rooms of dead instances kept past [ORPHANED_ROOM_RETENTION]
+    OrphanedRooms,
+    /// Players hashes of instances that are no longer registered
+    PlayerIndexes,
+    /// Registrations and orphaned rooms that can't be read
+    MalformedEntries,
+}
+
+impl Reaped {
+    pub const ALL: [Reaped; 4] = [
+        Reaped::DeadNodes,
+        Reaped::OrphanedRooms,
+        Reaped::PlayerIndexes,
+        Reaped::MalformedEntries,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Reaped::DeadNodes => "dead_nodes",
+            Reaped::OrphanedRooms => "orphaned_rooms",
+            Reaped::PlayerIndexes => "player_indexes",
+            Reaped::MalformedEntries => "malformed_entries",
+        }
+    }
+}
+
+/// The fields of a hash whose values can't be read as a `T`
+fn malformed_fields<T: DeserializeOwned>(hash: &HashMap<String, String>) -> Vec<String> {
+    hash.iter()
+        .filter(|(_, value)| serde_json::from_str::<T>(value).is_err())
+        .map(|(field, _)| field.clone())
+        .collect()
+}
+
+/// The rooms orphaned at least [ORPHANED_ROOM_RETENTION] before `now`, in seconds since the
+/// Unix epoch
+fn expired_orphans(orphans: &HashMap<String, String>, now: u64) -> Vec<String> {
+    orphans
+        .iter()
+        .filter(|(_, orphan)| {
+            serde_json::from_str::<OrphanedRoom>(orphan).is_ok_and(|orphan| {
+                now.saturating_sub(orphan.orphaned_at) >= ORPHANED_ROOM_RETENTION.as_secs()
+            })
+        })
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+/// The players hashes among `keys` that don't belong to one of the `registered` instances
+fn stale_player_keys(keys: Vec<String>, registered: &HashSet<Uuid>) -> Vec<String> {
+    keys.into_iter()
+        .filter(|key| {
+            key.strip_prefix(PLAYERS_KEY_PREFIX)
+                .and_then(|instance| instance.parse().ok())
+                .is_none_or(|instance| !registered.contains(&instance))
+        })
+        .collect()
+}
+
+/// Sweeps the cluster's shared store while this instance holds the lease
+pub struct Janitor {
+    client: redis::Client,
+    instance: Uuid,
+    lease: Script,
+    leader: AtomicBool,
+    reaped: HashMap<Reaped, AtomicU64>,
+}
+
+impl std::fmt::Debug for Janitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Janitor")
+            .field("instance", &self.instance)
+            .field("leader", &self.leader)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Janitor {
+    pub fn new(client: redis::Client, instance: Uuid) -> Self {
+        Self {
+            client,
+            instance,
+            lease: Script::new(LEASE_SCRIPT),
+            leader: AtomicBool::new(false),
+            reaped: Reaped::ALL
+                .into_iter()
+                .map(|kind| (kind, AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Whether this instance held the lease as of its last attempt to take it
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    /// How many leftovers of the kind this instance has removed
+    pub fn reaped_count(&self, kind: Reaped) -> u64 {
+        self.reaped[&kind].load(Ordering::Relaxed)
+    }
+
+    fn count(&self, kind: Reaped, reaped: usize) {
+        self.reaped[&kind].fetch_add(reaped as u64, Ordering::Relaxed);
+    }
+
+    /// Sweeps the cluster every [JANITOR_INTERVAL] whenever this instance holds the lease
+    pub async fn run(&self) {
+        let mut sweeps = tokio::time::interval(JANITOR_INTERVAL);
+        loop {
+            sweeps.tick().await;
+            if let Err(e) = self.sweep_if_leader().await {
+                warn!(event = "janitor_sweep_failed", error = %e);
+            }
+        }
+    }
+
+    async fn sweep_if_leader(&self) -> RedisResult<()> {
+        let mut connection = self.client.get_multiplexed_async_connection().await?;
+        let leader: bool = self
+            .lease
+            .key(JANITOR_LEADER_KEY)
+            .arg(self.instance.to_string())
+            .arg(JANITOR_LEASE.as_millis() as u64)
+            .invoke_async(&mut connection)
+            .await?;
+        if leader != self.leader.swap(leader, Ordering::Relaxed) {
+            info!(event = "janitor_lease_changed", instance = %self.instance, leader);
+        }
+        if leader {
+            self.sweep(&mut connection).await?;
+        }
+        Ok(())
+    }
+
+    async fn sweep(&self, connection: &mut MultiplexedConnection) -> RedisResult<()> {
+        let now = unix_time();
+
+        let registrations: HashMap<String, String> = connection.hgetall(NODES_KEY).await?;
+        let malformed_registrations = malformed_fields::<NodeInfo>(&registrations);
+        if !malformed_registrations.is_empty() {
+            connection
+                .hdel::<_, _, ()>(NODES_KEY, &malformed_registrations)
+                .await?;
+        }
+
+        let mut dead = 0;
+        for node in dead_nodes(&registrations, now) {
+            // Instances that lost the lease mid-sweep may still be reaping the same nodes,
+            // so only the one that removes the registration orphans its rooms
+            let removed: usize = connection.hdel(NODES_KEY, node.id.to_string()).await?;
+            if removed == 0 {
+                continue;
+            }
+            dead += 1;
+            warn!(event = "cluster_node_dead", node = %node.id, rooms = node.rooms.len());
+            let orphans: Vec<(String, String)> = node
+                .rooms
+                .iter()
+                .map(|&id| {
+                    let orphan = OrphanedRoom {
+                        id,
+                        node: node.id,
+                        orphaned_at: now,
+                    };
+                    let orphan =
+                        serde_json::to_string(&orphan).expect("Orphaned rooms always serialize");
+                    (id.to_string(), orphan)
+                })
+                .collect();
+            if !orphans.is_empty() {
+                connection
+                    .hset_multiple::<_, _, _, ()>(ORPHANED_ROOMS_KEY, &orphans)
+                    .await?;
+            }
+        }
+
+        let orphans: HashMap<String, String> = connection.hgetall(ORPHANED_ROOMS_KEY).await?;
+        let malformed_orphans = malformed_fields::<OrphanedRoom>(&orphans);
+        let expired = expired_orphans(&orphans, now);
+        for fields in [&malformed_orphans, &expired] {
+            if !fields.is_empty() {
+                connection
+                    .hdel::<_, _, ()>(ORPHANED_ROOMS_KEY, fields)
+                    .await?;
+            }
+        }
+
+        let registered: HashSet<_> = registered_nodes(&registrations)
+            .into_iter()
+            .filter(|node| node.is_alive(now))
+            .map(|node| node.id)
+            .collect();
+        let mut keys: AsyncIter<String> = connection
+            .scan_match(format!("{PLAYERS_KEY_PREFIX}*"))
+            .await?;
+        let mut player_keys = Vec::new();
+        while let Some(key) = keys.next_item().await {
+            player_keys.push(key);
+        }
+        drop(keys);
+        let stale = stale_player_keys(player_keys, &registered);
+        if !stale.is_empty() {
+            connection.del::<_, ()>(&stale).await?;
+        }
+
+        let malformed = malformed_registrations.len() + malformed_orphans.len();
+        self.count(Reaped::DeadNodes, dead);
+        self.count(Reaped::OrphanedRooms, expired.len());
+        self.count(Reaped::PlayerIndexes, stale.len());
+        self.count(Reaped::MalformedEntries, malformed);
+        info!(
+            event = "janitor_swept",
+            dead_nodes = dead,
+            orphaned_rooms = expired.len(),
+            player_indexes = stale.len(),
+            malformed_entries = malformed
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn orphan(id: u128, orphaned_at: u64) -> (String, String) {
+    let orphan = OrphanedRoom {
+        id: id.into(),
+        node: Uuid::from_u128(1),
+        orphaned_at,
+    };
+    (
+        orphan.id.to_string(),
+        serde_json::to_string(&orphan).unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod expired_orphans {
+    use super::*;
+
+    #[test]
+    fn keeps_orphans_for_the_retention_period() {
+        let now = 10_000;
+        let retention = ORPHANED_ROOM_RETENTION.as_secs();
+        let orphans = HashMap::from([
+            orphan(1, now - retention),
+            orphan(2, now - retention + 1),
+            ("3".to_owned(), "not json".to_owned()),
+        ]);
+
+        assert_eq!(expired_orphans(&orphans, now), vec![orphan(1, 0).0]);
+    }
+}
+
+#[cfg(test)]
+mod malformed_fields {
+    use super::*;
+
+    #[test]
+    fn finds_entries_that_cant_be_read() {
+        let orphans = HashMap::from([orphan(1, 0), ("2".to_owned(), "{}".to_owned())]);
+
+        assert_eq!(
+            malformed_fields::<OrphanedRoom>(&orphans),
+            vec!["2".to_owned()]
+        );
+    }
+}
+
+#[cfg(test)]
+mod stale_player_keys {
+    use super::*;
+    use crate::cluster::players_key;
+
+    #[test]
+    fn finds_keys_of_unregistered_instances() {
+        let registered = HashSet::from([Uuid::from_u128(1)]);
+        let keys = vec![
+            players_key(Uuid::from_u128(1)),
+            players_key(Uuid::from_u128(2)),
+            format!("{PLAYERS_KEY_PREFIX}garbage"),
+        ];
+
+        assert_eq!(
+            stale_player_keys(keys, &registered),
+            vec![
+                players_key(Uuid::from_u128(2)),
+                format!("{PLAYERS_KEY_PREFIX}garbage")
+            ]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Players voting to kick one another out of the room they're playing in
+//!
+//! Any signed in player in a room can start a vote to kick another player in it, which counts
+//! as their own vote. The vote passes as soon as enough of the room's other players, the
+//! target left out, have voted for it, as set out in the [rules][KickVoteRules], and fails if
+//! it hasn't passed by the time it times out. Either way, no new vote can be started against
+//! the same player in the room until the cooldown has passed since the last one ended, so a
+//! player can't be hounded with one vote after another. A player kicked by a vote is also
+//! [banned][crate::room_bans] from the room and its rematches for the cooldown, so they
+//! can't walk straight back in.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tracing::info;
+
+use crate::clock::{system_clock, Clock};
+use crate::game::{PlayerId, RoomId};
+
+/// How votes to kick are run
+#[derive(Debug, PartialEq, Clone)]
+pub struct KickVoteRules {
+    /// How long a vote runs before it fails for want of votes
+    pub timeout: Duration,
+    /// The share of the players who can vote, in percent, that have to vote to kick
+    pub threshold_percent: u32,
+    /// The fewest votes that can kick a player, however few players can vote
+    pub min_votes: usize,
+    /// How long after a vote against a player ends before another can be started against
+    /// them in the same room
+    pub cooldown: Duration,
+}
+
+impl Default for KickVoteRules {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            threshold_percent: 50,
+            min_votes: 2,
+            cooldown: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl KickVoteRules {
+    /// How many votes it takes to kick a player when `voters` players can vote
+    pub fn votes_needed(&self, voters: usize) -> usize {
+        (voters * self.threshold_percent as usize)
+            .div_ceil(100)
+            .max(self.min_votes)
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum KickVoteError {
+    #[error("You can't vote to kick yourself")]
+    OwnTarget,
+    #[error("It takes {needed} votes to kick a player, but only {voters} players can vote")]
+    NotEnoughVoters { voters: usize, needed: usize },
+    #[error("A vote to kick the player ended recently, another can be started in {} seconds", .0.as_secs().max(1))]
+    CoolingDown(Duration),
+    #[error("You have already voted to kick the player")]
+    AlreadyVoted,
+}
+
+/// Where a vote stands once a player has voted in it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct KickVoteTally {
+    pub votes: usize,
+    pub needed: usize,
+    /// Whether this was the vote that started it
+    pub first: bool,
+    /// When the vote started, which tells it apart from other votes against the same player
+    pub started_at: Instant,
+    /// How long is left until the vote fails, unless it passes first
+    pub ends_in: Duration,
+}
+
+impl KickVoteTally {
+    pub fn passed(&self) -> bool {
+        self.votes >= self.needed
+    }
+}
+
+#[derive(Debug)]
+struct Vote {
+    voters: HashSet<PlayerId>,
+    started_at: Instant,
+    /// When the vote passed, `None` while it's running or once it has timed out
+    passed_at: Option<Instant>,
+}
+
+/// The votes to kick running in every room, along with those that ended too recently for
+/// another to be started against the same player
+#[derive(Debug)]
+pub struct KickVotes {
+    rules: KickVoteRules,
+    votes: Mutex<HashMap<(RoomId, PlayerId), Vote>>,
+    /// The clock votes are timed on
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for KickVotes {
+    fn default() -> Self {
+        Self::new(KickVoteRules::default())
+    }
+}
+
+impl KickVotes {
+    pub fn new(rules: KickVoteRules) -> Self {
+        Self {
+            rules,
+            votes: Default::default(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Times votes on `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock votes are timed on, which their `now` should be read from
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    pub fn rules(&self) -> &KickVoteRules {
+        &self.rules
+    }
+
+    /// When the vote ended, if it has by `now`
+    fn ended_at(&self, vote: &Vote, now: Instant) -> Option<Instant> {
+        let deadline = vote.started_at + self.rules.timeout;
+        vote.passed_at.or((now >= deadline).then_some(deadline))
+    }
+
+    /// Casts the voter's vote to kick the target out of the room at `now`, starting a vote if
+    /// none is running against them, when `voters` players in the room can vote
+    pub fn vote(
+        &self,
+        room_id: RoomId,
+        target: PlayerId,
+        voter: PlayerId,
+        voters: usize,
+        now: Instant,
+    ) -> Result<KickVoteTally, KickVoteError> {
+        if voter == target {
+            return Err(KickVoteError::OwnTarget);
+        }
+        let mut votes = self.votes.lock().unwrap();
+        // Votes that ended longer ago than the cooldown have nothing worth keeping
+        votes.retain(|_, vote| {
+            self.ended_at(vote, now)
+                .is_none_or(|ended_at| now.duration_since(ended_at) < self.rules.cooldown)
+        });
+        let needed = self.rules.votes_needed(voters);
+        let vote = match votes.get(&(room_id, target)) {
+            Some(vote) => match self.ended_at(vote, now) {
+                Some(ended_at) => {
+                    let cooled_down_at = ended_at + self.rules.cooldown;
+                    return Err(KickVoteError::CoolingDown(cooled_down_at - now));
+                }
+                None if vote.voters.contains(&voter) => return Err(KickVoteError::AlreadyVoted),
+                None => votes.get_mut(&(room_id, target)).unwrap(),
+            },
+            None if voters < needed => {
+                return Err(KickVoteError::NotEnoughVoters { voters, needed })
+            }
+            None => {
+                info!(event = "kick_vote_started", %room_id, %target, %voter);
+                votes.entry((room_id, target)).or_insert(Vote {
+                    voters: HashSet::new(),
+                    started_at: now,
+                    passed_at: None,
+                })
+            }
+        };
+        vote.voters.insert(voter);
+        let tally = KickVoteTally {
+            votes: vote.voters.len(),
+            needed,
+            first: vote.voters.len() == 1,
+            started_at: vote.started_at,
+            ends_in: (vote.started_at + self.rules.timeout).saturating_duration_since(now),
+        };
+        if tally.passed() {
+            vote.passed_at = Some(now);
+            info!(event = "kick_vote_passed", %room_id, %target, votes = tally.votes);
+        }
+        Ok(tally)
+    }
+
+    /// Whether the vote against the target that started at `started_at` has failed by `now`
+    pub fn has_failed(
+        &self,
+        room_id: RoomId,
+        target: PlayerId,
+        started_at: Instant,
+        now: Instant,
+    ) -> bool {
+        let votes = self.votes.lock().unwrap();
+        let failed = votes.get(&(room_id, target)).is_some_and(|vote| {
+            vote.started_at == started_at
+                && vote.passed_at.is_none()
+                && self.ended_at(vote, now).is_some()
+        });
+        if failed {
+            info!(event = "kick_vote_failed", %room_id, %target);
+        }
+        failed
+    }
+}
+
+#[cfg(test)]
+mod vote {
+    use super::*;
+
+    fn votes() -> KickVotes {
+        KickVotes::new(KickVoteRules {
+            timeout: Duration::from_secs(30),
+            threshold_percent: 60,
+            min_votes: 2,
+            cooldown: Duration::from_secs(300),
+        })
+    }
+
+    fn vote(votes: &KickVotes, voter: u128, at: Instant) -> Result<KickVoteTally, KickVoteError> {
+        votes.vote(1_u128.into(), 1.into(), voter.into(), 4, at)
+    }
+
+    #[test]
+    fn passes_once_enough_players_vote() {
+        let votes = votes();
+        let now = Instant::now();
+
+        let started = vote(&votes, 2, now).unwrap();
+        assert!(started.first);
+        assert_eq!((started.votes, started.needed), (1, 3));
+        assert!(!vote(&votes, 3, now).unwrap().passed());
+        assert!(vote(&votes, 4, now).unwrap().passed());
+    }
+
+    #[test]
+    fn counts_each_player_once() {
+        let votes = votes();
+        let now = Instant::now();
+
+        vote(&votes, 2, now).unwrap();
+        assert_eq!(vote(&votes, 2, now), Err(KickVoteError::AlreadyVoted));
+        assert_eq!(
+            votes.vote(1_u128.into(), 1.into(), 1.into(), 4, now),
+            Err(KickVoteError::OwnTarget)
+        );
+    }
+
+    #[test]
+    fn needs_enough_players_to_start() {
+        let votes = votes();
+
+        assert_eq!(
+            votes.vote(1_u128.into(), 1.into(), 2.into(), 1, Instant::now()),
+            Err(KickVoteError::NotEnoughVoters {
+                voters: 1,
+                needed: 2
+            })
+        );
+    }
+
+    #[test]
+    fn cools_down_after_a_vote_fails() {
+        let votes = votes();
+        let now = Instant::now();
+        let started = vote(&votes, 2, now).unwrap();
+        let timed_out = now + Duration::from_secs(30);
+
+        assert!(!votes.has_failed(1_u128.into(), 1.into(), started.started_at, now));
+        assert!(votes.has_failed(1_u128.into(), 1.into(), started.started_at, timed_out));
+        assert_eq!(
+            vote(&votes, 3, timed_out + Duration::from_secs(60)),
+            Err(KickVoteError::CoolingDown(Duration::from_secs(240)))
+        );
+        assert!(
+            vote(&votes, 3, timed_out + Duration::from_secs(300))
+                .unwrap()
+                .first
+        );
+    }
+
+    #[test]
+    fn keeps_votes_in_each_room_apart() {
+        let votes = votes();
+        let now = Instant::now();
+        vote(&votes, 2, now).unwrap();
+
+        let elsewhere = votes
+            .vote(2_u128.into(), 1.into(), 2.into(), 4, now)
+            .unwrap();
+        assert!(elsewhere.first);
+    }
+}
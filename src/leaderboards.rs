@@ -0,0 +1,447 @@
+//! Leaderboards of the best rated players in each game type
+//!
+//! Every rated [result][GameResult] is logged, and on a schedule the log is rolled up into a
+//! leaderboard for each game type and [period][LeaderboardPeriod], ranking the players who
+//! played in it by their rating, then by their wins. Only the top players of each are kept,
+//! so reading a leaderboard never has to go through the log.
+//!
+//! A player wins a game by being in its first place, and their streak is how many games in a
+//! row they've won. The log only goes back as far as the longest period short of all time,
+//! with everything older kept as each player's all-time record. Given a file, the log is
+//! written to it as results come in and read back on start, so leaderboards carry on across
+//! restarts rather than starting over.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::clock::unix_time;
+use crate::files::write_atomically;
+use crate::game::PlayerId;
+use crate::ratings::{GameResult, Rating};
+
+/// The most entries a page of a leaderboard can hold
+pub const MAX_LEADERBOARD_PAGE: usize = 100;
+
+/// The stretch of time a leaderboard covers
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardPeriod {
+    /// The last 24 hours
+    Daily,
+    /// The last 7 days
+    Weekly,
+    #[default]
+    AllTime,
+}
+
+impl LeaderboardPeriod {
+    pub const ALL: [Self; 3] = [Self::Daily, Self::Weekly, Self::AllTime];
+
+    /// How far back the period goes, `None` for all time
+    fn window(self) -> Option<Duration> {
+        match self {
+            Self::Daily => Some(Duration::from_secs(24 * 60 * 60)),
+            Self::Weekly => Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            Self::AllTime => None,
+        }
+    }
+}
+
+/// A player's place on a leaderboard
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, ToSchema)]
+pub struct LeaderboardEntry {
+    /// The player's place, from 1 for the top
+    pub rank: usize,
+    pub player_id: PlayerId,
+    pub rating: f64,
+    /// How many games the player played in the period
+    pub games: u32,
+    pub wins: u32,
+    /// How many games in a row the player has won up to their last one
+    pub streak: u32,
+    /// The most games in a row the player won in the period
+    pub best_streak: u32,
+}
+
+/// A player's record in one game type over some period
+#[derive(Debug, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+struct Tally {
+    rating: f64,
+    games: u32,
+    wins: u32,
+    streak: u32,
+    best_streak: u32,
+}
+
+impl Tally {
+    fn add(&mut self, rating: f64, won: bool) {
+        self.rating = rating;
+        self.games += 1;
+        if won {
+            self.wins += 1;
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+        } else {
+            self.streak = 0;
+        }
+    }
+}
+
+/// A rated game, as the leaderboards need it
+#[derive(Debug, Serialize, Deserialize)]
+struct LoggedResult {
+    /// Seconds since the Unix epoch
+    at: u64,
+    game_type: String,
+    /// Each player's rating after the game, and whether they won it
+    outcomes: Vec<(PlayerId, f64, bool)>,
+}
+
+/// The results the leaderboards are rolled up from, as written to the file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ResultLog {
+    /// The results within the longest period short of all time, oldest first
+    recent: VecDeque<LoggedResult>,
+    /// Every player's record by game type
+    all_time: HashMap<String, HashMap<PlayerId, Tally>>,
+}
+
+/// A leaderboard's game type and period
+type BoardKey = (String, LeaderboardPeriod);
+
+/// The leaderboards of every game type, refreshed by [running][Self::run] them
+#[derive(Debug)]
+pub struct Leaderboards {
+    /// How many of the top players each leaderboard keeps
+    size: usize,
+    log: Mutex<ResultLog>,
+    boards: RwLock<HashMap<BoardKey, Vec<LeaderboardEntry>>>,
+    /// Where the results are written to, `None` to keep them in memory only
+    file: Option<PathBuf>,
+    /// Woken when a result was logged and should be saved
+    changed: Notify,
+}
+
+impl Leaderboards {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            log: Default::default(),
+            boards: Default::default(),
+            file: None,
+            changed: Notify::new(),
+        }
+    }
+
+    /// Creates leaderboards whose results are kept in the file, starting with those in it
+    pub fn with_file(size: usize, file: PathBuf) -> io::Result<Self> {
+        let log = match fs::read(&file) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ResultLog::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            log: Mutex::new(log),
+            file: Some(file),
+            ..Self::new(size)
+        })
+    }
+
+    /// Logs a result along with the players' ratings after it, to be ranked at the next
+    /// [rollup][Self::rollup]
+    pub fn record(&self, result: &GameResult, ratings: &[(PlayerId, Rating)]) {
+        let winners = result
+            .standings
+            .iter()
+            .find(|place| !place.is_empty())
+            .map_or(&[][..], Vec::as_slice);
+        let outcomes: Vec<_> = ratings
+            .iter()
+            .map(|&(player_id, rating)| (player_id, rating.rating, winners.contains(&player_id)))
+            .collect();
+
+        let mut log = self.log.lock().unwrap();
+        let players = log.all_time.entry(result.game_type.clone()).or_default();
+        for &(player_id, rating, won) in &outcomes {
+            players.entry(player_id).or_default().add(rating, won);
+        }
+        log.recent.push_back(LoggedResult {
+            at: unix_time(),
+            game_type: result.game_type.clone(),
+            outcomes,
+        });
+        drop(log);
+        self.changed.notify_one();
+    }
+
+    /// Rebuilds every leaderboard from the results logged up to `now`, in seconds since the
+    /// Unix epoch, forgetting those too old to be in any period short of all time
+    pub fn rollup(&self, now: u64) {
+        let mut log = self.log.lock().unwrap();
+        let longest = LeaderboardPeriod::ALL
+            .into_iter()
+            .filter_map(LeaderboardPeriod::window)
+            .max();
+        if let Some(cutoff) = longest.and_then(|window| now.checked_sub(window.as_secs())) {
+            while log.recent.front().is_some_and(|result| result.at < cutoff) {
+                log.recent.pop_front();
+            }
+        }
+
+        let mut boards = HashMap::new();
+        for period in LeaderboardPeriod::ALL {
+            let tallies = match period.window() {
+                None => log.all_time.clone(),
+                Some(window) => {
+                    let since = now.checked_sub(window.as_secs());
+                    let mut tallies: HashMap<String, HashMap<PlayerId, Tally>> = HashMap::new();
+                    for result in &log.recent {
+                        if since.is_some_and(|since| result.at < since) {
+                            continue;
+                        }
+                        let players = tallies.entry(result.game_type.clone()).or_default();
+                        for &(player_id, rating, won) in &result.outcomes {
+                            players.entry(player_id).or_default().add(rating, won);
+                        }
+                    }
+                    tallies
+                }
+            };
+            for (game_type, players) in tallies {
+                boards.insert((game_type, period), self.rank(players));
+            }
+        }
+        drop(log);
+        debug!(
+            event = "leaderboards_rolled_up",
+            leaderboards = boards.len()
+        );
+        *self.boards.write().unwrap() = boards;
+    }
+
+    fn rank(&self, players: HashMap<PlayerId, Tally>) -> Vec<LeaderboardEntry> {
+        let mut players: Vec<_> = players.into_iter().collect();
+        players.sort_by(|(a_id, a), (b_id, b)| {
+            b.rating
+                .total_cmp(&a.rating)
+                .then(b.wins.cmp(&a.wins))
+                .then_with(|| a_id.partial_cmp(b_id).unwrap_or(Ordering::Equal))
+        });
+        players
+            .into_iter()
+            .take(self.size)
+            .enumerate()
+            .map(|(i, (player_id, tally))| LeaderboardEntry {
+                rank: i + 1,
+                player_id,
+                rating: tally.rating,
+                games: tally.games,
+                wins: tally.wins,
+                streak: tally.streak,
+                best_streak: tally.best_streak,
+            })
+            .collect()
+    }
+
+    /// Up to `limit` entries of a leaderboard from its `offset`th, along with how many entries
+    /// it has, as of the last rollup
+    pub fn page(
+        &self,
+        game_type: &str,
+        period: LeaderboardPeriod,
+        offset: usize,
+        limit: usize,
+    ) -> (usize, Vec<LeaderboardEntry>) {
+        let boards = self.boards.read().unwrap();
+        match boards.get(&(game_type.to_owned(), period)) {
+            Some(board) => (
+                board.len(),
+                board.iter().skip(offset).take(limit).copied().collect(),
+            ),
+            None => (0, Vec::new()),
+        }
+    }
+
+    /// Writes the results to the file, if there is one, through a temporary file so a crash
+    /// can't leave it half written
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let saved = write_atomically(file, &*self.log.lock().unwrap());
+        if let Err(e) = saved {
+            warn!(event = "leaderboard_results_not_saved", file = %file.display(), error = %e);
+        }
+    }
+
+    /// Rolls the leaderboards up every `interval`, starting straight away from the results
+    /// read from the file, and writes the results to the file whenever one is logged until
+    /// shut down, then once more on the way out
+    pub async fn run(self: Arc<Self>, interval: Duration, shutdown: CancellationToken) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.rollup(unix_time()),
+                _ = self.changed.notified() => self.save_in_background().await,
+                _ = shutdown.cancelled() => break,
+            }
+        }
+        self.save_in_background().await;
+    }
+
+    async fn save_in_background(self: &Arc<Self>) {
+        if self.file.is_none() {
+            return;
+        }
+        let leaderboards = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || leaderboards.save()).await {
+            warn!(event = "leaderboard_results_not_saved", error = %e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod rollup {
+    use super::*;
+
+    fn duel(leaderboards: &Leaderboards, winner: PlayerId, loser: PlayerId, ratings: [f64; 2]) {
+        let result = GameResult {
+            game_type: "duel".into(),
+            standings: vec![vec![winner], vec![loser]],
+        };
+        let rating = |rating| Rating { rating, games: 1 };
+        leaderboards.record(
+            &result,
+            &[(winner, rating(ratings[0])), (loser, rating(ratings[1]))],
+        );
+    }
+
+    #[test]
+    fn ranks_players_by_rating_and_counts_their_streaks() {
+        let leaderboards = Leaderboards::new(10);
+        let [a, b] = [1, 2].map(PlayerId::from);
+        duel(&leaderboards, a, b, [1516.0, 1484.0]);
+        duel(&leaderboards, a, b, [1530.0, 1470.0]);
+        duel(&leaderboards, b, a, [1490.0, 1510.0]);
+
+        leaderboards.rollup(unix_time());
+        let (total, entries) = leaderboards.page("duel", LeaderboardPeriod::AllTime, 0, 10);
+
+        assert_eq!(total, 2);
+        assert_eq!(
+            entries[0],
+            LeaderboardEntry {
+                rank: 1,
+                player_id: a,
+                rating: 1510.0,
+                games: 3,
+                wins: 2,
+                streak: 0,
+                best_streak: 2,
+            }
+        );
+        assert_eq!((entries[1].player_id, entries[1].streak), (b, 1));
+        assert_eq!(
+            leaderboards
+                .page("duel", LeaderboardPeriod::AllTime, 1, 10)
+                .1,
+            &entries[1..]
+        );
+    }
+
+    #[test]
+    fn leaves_old_results_out_of_shorter_periods() {
+        let leaderboards = Leaderboards::new(10);
+        let [a, b] = [1, 2].map(PlayerId::from);
+        duel(&leaderboards, a, b, [1516.0, 1484.0]);
+
+        leaderboards.rollup(unix_time() + 2 * 24 * 60 * 60);
+
+        assert_eq!(
+            leaderboards.page("duel", LeaderboardPeriod::Daily, 0, 10).0,
+            0
+        );
+        assert_eq!(
+            leaderboards
+                .page("duel", LeaderboardPeriod::Weekly, 0, 10)
+                .0,
+            2
+        );
+        assert_eq!(
+            leaderboards
+                .page("duel", LeaderboardPeriod::AllTime, 0, 10)
+                .0,
+            2
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_top_players() {
+        let leaderboards = Leaderboards::new(1);
+        let [a, b] = [1, 2].map(PlayerId::from);
+        duel(&leaderboards, a, b, [1516.0, 1484.0]);
+
+        leaderboards.rollup(unix_time());
+        let (total, entries) = leaderboards.page("duel", LeaderboardPeriod::Weekly, 0, 10);
+
+        assert_eq!(total, 1);
+        assert_eq!(entries[0].player_id, a);
+    }
+}
+
+#[cfg(test)]
+mod with_file {
+    use super::*;
+
+    #[test]
+    fn rolls_up_the_results_from_before_a_restart() {
+        let file =
+            std::env::temp_dir().join(format!("wormhole-results-{}.json", uuid::Uuid::new_v4()));
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let leaderboards = Leaderboards::with_file(10, file.clone()).unwrap();
+        leaderboards.record(
+            &GameResult {
+                game_type: "duel".into(),
+                standings: vec![vec![a], vec![b]],
+            },
+            &[
+                (
+                    a,
+                    Rating {
+                        rating: 1516.0,
+                        games: 1,
+                    },
+                ),
+                (
+                    b,
+                    Rating {
+                        rating: 1484.0,
+                        games: 1,
+                    },
+                ),
+            ],
+        );
+        leaderboards.save();
+
+        let restarted = Leaderboards::with_file(10, file.clone()).unwrap();
+        restarted.rollup(unix_time());
+        fs::remove_file(&file).unwrap();
+
+        for period in LeaderboardPeriod::ALL {
+            let (total, entries) = restarted.page("duel", period, 0, 10);
+            assert_eq!(total, 2);
+            assert_eq!((entries[0].player_id, entries[0].wins), (a, 1));
+        }
+    }
+}
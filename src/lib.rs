@@ -0,0 +1,171 @@
+pub mod abuse;
+pub mod allocator;
+pub mod announcements;
+pub mod api;
+pub mod auth;
+pub mod authorization;
+pub mod bans;
+pub mod challenge;
+pub mod chat;
+pub mod clock;
+pub mod cluster;
+pub mod config;
+pub mod content_filter;
+pub mod events;
+pub mod faults;
+mod files;
+pub mod game;
+pub mod grpc;
+pub mod identity;
+pub mod invitations;
+pub mod invites;
+pub mod janitor;
+pub mod kick_votes;
+pub mod leaderboards;
+pub mod load;
+pub mod maintenance;
+pub mod matchmaking;
+pub mod metrics;
+pub mod notifications;
+pub mod panics;
+pub mod parties;
+pub mod placement;
+pub mod presence;
+pub mod problem;
+pub mod protocol;
+pub mod rate_limit;
+pub mod ratings;
+pub mod recording;
+pub mod reports;
+pub mod request_body;
+pub mod request_timeout;
+pub mod room_bans;
+pub mod room_watch;
+pub mod scheduling;
+pub mod sessions;
+pub mod spam;
+pub mod team_balance;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+pub mod tls;
+pub mod tournaments;
+pub mod turn_based;
+pub mod waiting_lists;
+#[cfg(feature = "web-ui")]
+pub mod web_ui;
+pub mod webhooks;
+pub mod ws;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::abuse::{AbuseRules, AbuseScores};
+use crate::bans::BanList;
+use crate::challenge::ChallengeVerifier;
+use crate::chat::EmoteCatalog;
+use crate::cluster::{RedisBridge, RemoteRooms};
+use crate::content_filter::ContentFilters;
+use crate::game::{RegistryHandle, RoomCreationQueue, RoomDeletionQueue, StateCodecs};
+use crate::identity::PlayerAuthenticator;
+use crate::invitations::InvitationBook;
+use crate::invites::InviteSigner;
+use crate::kick_votes::KickVotes;
+use crate::leaderboards::Leaderboards;
+use crate::load::LoadLimits;
+use crate::maintenance::MaintenanceMode;
+use crate::matchmaking::Matchmaker;
+use crate::notifications::PushDispatcher;
+use crate::panics::PanicCounters;
+use crate::parties::PartyRegistry;
+use crate::presence::PresenceFeed;
+use crate::rate_limit::RateLimiter;
+use crate::ratings::RatingBook;
+use crate::recording::Recorder;
+use crate::reports::ReportQueue;
+use crate::room_bans::RoomBans;
+use crate::room_watch::RoomWatchers;
+use crate::scheduling::RoomScheduler;
+use crate::sessions::SessionRegistry;
+use crate::spam::SpamGuard;
+use crate::tournaments::TournamentRegistry;
+use crate::turn_based::TurnBasedRooms;
+use crate::waiting_lists::WaitingLists;
+use crate::ws::AbuseCounters;
+
+/// State shared by every worker handling requests
+pub struct SharedAppState {
+    /// The rooms hosted by this instance, giving up on shards that stay locked for too long
+    pub room_registry: RegistryHandle,
+    /// The rooms hosted by other instances sharing the lobby
+    pub remote_rooms: Arc<RemoteRooms>,
+    /// The link to the other instances, `None` when this instance runs on its own
+    pub cluster: Option<Arc<RedisBridge>>,
+    pub room_deletion_queue: RoomDeletionQueue,
+    pub room_creation_queue: RoomCreationQueue,
+    /// How long a room creation request waits on the queue before being told to poll instead
+    pub room_creation_wait: Duration,
+    /// How long broadcasts are held back so each connection can write them out as one batch
+    pub broadcast_flush_interval: Duration,
+    /// How far behind the players spectators of rooms that aren't turn-based are kept, zero
+    /// to let them watch live
+    pub spectator_delay: Duration,
+    /// The codec each game type's state is kept encoded with in turn-based rooms
+    pub state_codecs: StateCodecs,
+    /// Verifies the tokens players join rooms with, `None` to let anyone join anonymously
+    pub player_authenticator: Option<PlayerAuthenticator>,
+    /// The challenge guests solve before joining anonymously, `None` to let them join freely
+    pub guest_challenge: Option<Box<dyn ChallengeVerifier>>,
+    /// Revoked sessions and the live connections they would cut off
+    pub sessions: Arc<SessionRegistry>,
+    pub ban_list: Arc<BanList>,
+    /// Request budgets for the REST API, chat and reactions
+    pub rate_limiter: RateLimiter,
+    /// What players' chat and display names are passed through before others see them
+    pub content_filters: ContentFilters,
+    /// The emotes players can react with
+    pub emotes: EmoteCatalog,
+    pub invite_signer: InviteSigner,
+    /// The invitations between players waiting to be answered
+    pub invitations: Arc<InvitationBook>,
+    /// Rich presence updates for the rooms players are in
+    pub presence: PresenceFeed,
+    /// Pushes notifications to players who aren't connected, `None` unless a push gateway is
+    /// configured
+    pub push: Option<Arc<PushDispatcher>>,
+    pub abuse_counters: AbuseCounters,
+    /// The offences each client has committed lately, across all of its connections
+    pub abuse_scores: AbuseScores,
+    /// The panics caught in request handlers and room connections
+    pub panics: PanicCounters,
+    pub maintenance: MaintenanceMode,
+    /// The queues of parties waiting to be matched
+    pub matchmaker: Arc<Matchmaker>,
+    /// Every player's skill rating in each game type, which matchmaking pairs players by
+    pub ratings: RatingBook,
+    /// The best rated players in each game type, as of the last rollup
+    pub leaderboards: Arc<Leaderboards>,
+    pub parties: PartyRegistry,
+    pub tournaments: TournamentRegistry,
+    /// Keeps the turn-based rooms this instance hosts across restarts
+    pub turn_based_rooms: Arc<TurnBasedRooms>,
+    /// Opens rooms scheduled to open later when their time comes
+    pub room_scheduler: RoomScheduler,
+    /// How much this instance says it can handle, which its load is measured against
+    pub load_limits: LoadLimits,
+    /// When connections are warned, locked and disconnected for abusing the server
+    pub abuse_rules: AbuseRules,
+    /// Records the traffic in every room, `None` unless a recording directory is configured
+    pub recorder: Option<Recorder>,
+    /// The feeds of the rooms operators are watching
+    pub room_watchers: RoomWatchers,
+    /// Players' reports of other players, waiting for moderators
+    pub reports: Arc<ReportQueue>,
+    /// The players kept out of rooms and their rematches
+    pub room_bans: Arc<RoomBans>,
+    /// Mutes players spamming chat
+    pub spam_guard: SpamGuard,
+    /// The votes players are holding to kick one another out of rooms
+    pub kick_votes: KickVotes,
+    /// The players waiting for seats in full rooms
+    pub waiting_lists: WaitingLists,
+}
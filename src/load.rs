@@ -0,0 +1,216 @@
+//! How busy an instance is, as a single score instances compare to share out new rooms
+//!
+//! An instance's [load score][LoadReport::score] is the highest of the share of its room
+//! capacity in use, the share of its connection capacity in use and its CPU load, so a score
+//! of 1 or more means at least one of them is used up. Its [capacity][CapacityReport] is the
+//! same measurements turned around into what it has left, for autoscalers and load balancers.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::game::RoomRegistry;
+
+/// How many rooms instances say they can host unless configured otherwise
+pub const DEFAULT_ROOM_CAPACITY: usize = 1000;
+/// How many connections instances say they can hold unless configured otherwise
+pub const DEFAULT_CONNECTION_CAPACITY: usize = 10_000;
+
+/// How much an instance says it can handle
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct LoadLimits {
+    pub rooms: usize,
+    pub connections: usize,
+}
+
+impl Default for LoadLimits {
+    fn default() -> Self {
+        Self {
+            rooms: DEFAULT_ROOM_CAPACITY,
+            connections: DEFAULT_CONNECTION_CAPACITY,
+        }
+    }
+}
+
+/// How busy the instance is right now
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct LoadReport {
+    pub rooms: usize,
+    pub connections: usize,
+    /// The load average over the last minute per CPU, absent where it can't be read
+    pub cpu: Option<f64>,
+    /// The highest of the share of room capacity in use, the share of connection capacity in
+    /// use and the CPU load
+    pub score: f64,
+}
+
+impl LoadReport {
+    pub fn new(rooms: usize, connections: usize, cpu: Option<f64>, limits: LoadLimits) -> Self {
+        let share = |used: usize, capacity: usize| used as f64 / capacity.max(1) as f64;
+        let score = share(rooms, limits.rooms)
+            .max(share(connections, limits.connections))
+            .max(cpu.unwrap_or_default());
+        Self {
+            rooms,
+            connections,
+            cpu,
+            score,
+        }
+    }
+
+    /// Measures the load of the instance hosting the registry's rooms
+    pub fn measure(registry: &RoomRegistry, limits: LoadLimits) -> Self {
+        Self::new(
+            registry.room_count(),
+            registry.connection_count(),
+            cpu_load(),
+            limits,
+        )
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        is_saturated(self.score)
+    }
+}
+
+/// Which of an instance's resources are used up
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, ToSchema)]
+pub struct SaturationFlags {
+    pub rooms: bool,
+    pub connections: bool,
+    /// Always `false` where the CPU load can't be read
+    pub cpu: bool,
+}
+
+/// What an instance has left to give, compact enough for autoscalers and load balancers to
+/// poll
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct CapacityReport {
+    pub rooms: usize,
+    pub room_capacity: usize,
+    /// How many more rooms the instance can host before its room capacity is used up
+    pub room_headroom: usize,
+    pub connections: usize,
+    pub connection_capacity: usize,
+    /// How many more connections the instance can hold before its connection capacity is used
+    /// up
+    pub connection_headroom: usize,
+    /// The load average over the last minute per CPU, absent where it can't be read
+    pub cpu: Option<f64>,
+    /// The share of the instance's most used resource still free, between 0 and 1
+    pub headroom: f64,
+    pub saturated: SaturationFlags,
+    /// Whether the instance should be sent new rooms, which it shouldn't once anything is
+    /// used up or while it's in maintenance mode
+    pub accepting_rooms: bool,
+}
+
+impl CapacityReport {
+    pub fn new(load: &LoadReport, limits: LoadLimits, maintenance: bool) -> Self {
+        let saturated = SaturationFlags {
+            rooms: load.rooms >= limits.rooms,
+            connections: load.connections >= limits.connections,
+            cpu: load.cpu.is_some_and(is_saturated),
+        };
+        Self {
+            rooms: load.rooms,
+            room_capacity: limits.rooms,
+            room_headroom: limits.rooms.saturating_sub(load.rooms),
+            connections: load.connections,
+            connection_capacity: limits.connections,
+            connection_headroom: limits.connections.saturating_sub(load.connections),
+            cpu: load.cpu,
+            headroom: (1.0 - load.score).clamp(0.0, 1.0),
+            saturated,
+            accepting_rooms: !load.is_saturated() && !maintenance,
+        }
+    }
+}
+
+/// Whether an instance with the load score has used up some of its capacity
+pub fn is_saturated(score: f64) -> bool {
+    score >= 1.0
+}
+
+/// The load average over the last minute divided by the number of CPUs, on Linux
+fn cpu_load() -> Option<f64> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+    let load: f64 = loadavg.split_whitespace().next()?.parse().ok()?;
+    let cpus = std::thread::available_parallelism().ok()?.get();
+    Some(load / cpus as f64)
+}
+
+#[cfg(test)]
+mod new {
+    use super::*;
+
+    const LIMITS: LoadLimits = LoadLimits {
+        rooms: 10,
+        connections: 100,
+    };
+
+    #[test]
+    fn scores_the_most_used_resource() {
+        assert_eq!(LoadReport::new(5, 20, Some(0.1), LIMITS).score, 0.5);
+        assert_eq!(LoadReport::new(1, 80, None, LIMITS).score, 0.8);
+        assert_eq!(LoadReport::new(1, 10, Some(0.9), LIMITS).score, 0.9);
+    }
+
+    #[test]
+    fn is_saturated_once_anything_is_used_up() {
+        assert!(!LoadReport::new(9, 99, Some(0.99), LIMITS).is_saturated());
+        assert!(LoadReport::new(10, 0, None, LIMITS).is_saturated());
+        assert!(LoadReport::new(0, 0, Some(1.5), LIMITS).is_saturated());
+    }
+}
+
+#[cfg(test)]
+mod capacity_report_new {
+    use super::*;
+
+    const LIMITS: LoadLimits = LoadLimits {
+        rooms: 10,
+        connections: 100,
+    };
+
+    #[test]
+    fn reports_what_is_left() {
+        let load = LoadReport::new(4, 80, Some(0.5), LIMITS);
+
+        let capacity = CapacityReport::new(&load, LIMITS, false);
+
+        assert_eq!(capacity.room_headroom, 6);
+        assert_eq!(capacity.connection_headroom, 20);
+        assert!(
+            (capacity.headroom - 0.2).abs() < 1e-9,
+            "{}",
+            capacity.headroom
+        );
+        assert!(capacity.accepting_rooms);
+    }
+
+    #[test]
+    fn flags_what_is_used_up() {
+        let load = LoadReport::new(12, 10, Some(1.2), LIMITS);
+
+        let capacity = CapacityReport::new(&load, LIMITS, false);
+
+        assert_eq!(capacity.room_headroom, 0);
+        assert_eq!(capacity.headroom, 0.0);
+        assert_eq!(
+            capacity.saturated,
+            SaturationFlags {
+                rooms: true,
+                connections: false,
+                cpu: true,
+            }
+        );
+        assert!(!capacity.accepting_rooms);
+    }
+
+    #[test]
+    fn turns_rooms_away_during_maintenance() {
+        let load = LoadReport::new(0, 0, None, LIMITS);
+
+        assert!(!CapacityReport::new(&load, LIMITS, true).accepting_rooms);
+    }
+}
@@ -1,58 +1,345 @@
-mod config;
-mod game;
+use std::sync::Arc;
 
-use std::sync::Mutex;
+use wormhole::cluster::{RedisBridge, RemoteRooms};
+use wormhole::events::{self, EventSink, NatsSink};
+use wormhole::game::{room_creation_channel, room_deletion_channel, RegistryHandle, RoomRegistry};
+use wormhole::grpc::{self, RoomService};
+use wormhole::identity::PlayerAuthenticator;
+use wormhole::invitations::InvitationBook;
+use wormhole::kick_votes::KickVotes;
+use wormhole::matchmaking::Matchmaker;
+use wormhole::presence::PresenceFeed;
+use wormhole::rate_limit::{self, RateLimiter, RedisBuckets};
+use wormhole::recording::{recording_channel, RECORDING_QUEUE_CAPACITY};
+use wormhole::scheduling::RoomScheduler;
+use wormhole::sessions::SessionRegistry;
+use wormhole::spam::SpamGuard;
+use wormhole::webhooks::RoomWebhooks;
+use wormhole::{api, auth, config, panics, request_body, request_timeout, ws, SharedAppState};
 
-use crate::game::RoomRegistry;
-
-use actix_web::{body::BoxBody, web, App, HttpResponse, HttpServer};
+use actix_web::{middleware::from_fn, web, App, HttpServer};
 use anyhow::Result as AnyhowResult;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
-async fn create_room(state: web::Data<SharedAppState>) -> HttpResponse {
-    // TODO (mitch): Graceful handling of lock acquisition
-    // https://github.com/alexrkoch/wormhole-server/issues/10
-    let mut room_registry = state.room_registry.lock().unwrap();
-    let create_room_result = room_registry.create_room();
-
-    match create_room_result {
-        Err(e) => HttpResponse::InternalServerError()
-            .message_body(BoxBody::new(format!("{e:?}")))
-            .unwrap(),
-        Ok(room_id) => HttpResponse::Created()
-            .insert_header(("LOCATION", format!("/ws/{room_id}")))
-            .finish(),
-    }
-}
-
-fn configure_api_scope(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/rooms/").route(web::post().to(create_room)));
-}
-
-struct SharedAppState {
-    room_registry: Mutex<RoomRegistry>,
-}
+#[cfg(any(feature = "jemalloc", feature = "mimalloc"))]
+#[global_allocator]
+static GLOBAL: wormhole::allocator::GlobalAllocator = wormhole::allocator::GlobalAllocator {};
 
 #[tokio::main]
 async fn main() -> AnyhowResult<()> {
     let _guard = config::logging::configure_tracing()?;
+    panics::install_hook();
+    let game_type = config::room::get_game_type();
+    let mut room_registry = RoomRegistry::new(config::registry::get_shard_count())
+        .with_room_memory_limit(config::room::get_memory_limit_bytes())
+        .with_room_capacity(config::room::get_max_players())
+        .with_room_empty_timeout(Some(config::room::get_empty_timeout()))
+        .with_room_replay_len(config::room::get_replay_buffer_len())
+        .with_game_type(game_type.clone())
+        .with_room_id_provider(config::registry::get_room_id_provider());
+    if let Some(region) = config::cluster::get_region() {
+        room_registry = room_registry.with_region(region);
+    }
+    let room_webhooks = Arc::new(RoomWebhooks::default());
+    let webhook_dispatcher = config::webhooks::get_webhook_dispatcher()
+        .map(|dispatcher| dispatcher.with_room_webhooks(room_webhooks.clone()));
+    if webhook_dispatcher.is_some() {
+        room_registry = room_registry.with_room_webhooks(room_webhooks);
+    }
+    let room_registry = Arc::new(room_registry);
+    let (room_deletion_queue, room_deletion_handler) = room_deletion_channel(
+        room_registry.clone(),
+        config::registry::get_deletion_queue_capacity(),
+        config::registry::get_deletion_batch_size(),
+        config::registry::get_deletion_overflow_policy(),
+    );
+    let shutdown = CancellationToken::new();
+    let room_deletion_supervisor = tokio::spawn(room_deletion_handler.supervise(shutdown.clone()));
+    let lobby_updates = tokio::spawn(room_registry.clone().run_updates(shutdown.clone()));
+    let turn_based_rooms = Arc::new(config::room::get_turn_based_rooms());
+    turn_based_rooms
+        .restore(&room_registry, &room_deletion_queue)
+        .await
+        .unwrap_or_else(|e| {
+            panic!("The turn-based rooms couldn't be restored: {e}, please fix or delete WORMHOLE_TURN_BASED_ROOMS_FILE")
+        });
+    let turn_based_saver = tokio::spawn(
+        turn_based_rooms
+            .clone()
+            .run(room_registry.clone(), shutdown.clone()),
+    );
+
+    let ban_list = Arc::new(config::moderation::get_ban_list());
+    let ban_list_saver = tokio::spawn(ban_list.clone().run(shutdown.clone()));
+    let reports = Arc::new(config::moderation::get_report_queue());
+    let reports_saver = tokio::spawn(reports.clone().run(shutdown.clone()));
+    let room_bans = Arc::new(config::moderation::get_room_bans());
+    let room_bans_saver = tokio::spawn(room_bans.clone().run(shutdown.clone()));
+
+    let (room_creation_queue, room_creation_worker) = room_creation_channel(
+        room_registry.clone(),
+        room_deletion_queue.clone(),
+        config::room::get_unjoined_timeout(),
+        config::registry::get_creation_queue_capacity(),
+        config::registry::get_creation_deadline(),
+    );
+    tokio::spawn(room_creation_worker.watch());
+
+    let redis_client = config::cluster::get_redis_client();
+    let mut rate_limiter = RateLimiter::new(
+        config::rate_limit::get_room_creation_per_minute(),
+        config::rate_limit::get_general_per_minute(),
+        config::rate_limit::get_chat_per_minute(),
+        config::rate_limit::get_reactions_per_minute(),
+    );
+    if let Some(client) = &redis_client {
+        rate_limiter = rate_limiter.with_store(RedisBuckets::new(client.clone()));
+    }
+
+    let remote_rooms = Arc::new(RemoteRooms::default());
+    let sessions = Arc::new(SessionRegistry::default());
+    let invitations = Arc::new(InvitationBook::default());
+    let load_limits = config::cluster::get_load_limits();
+    let cluster = redis_client.clone().map(|client| {
+        let mut bridge = RedisBridge::new(
+            client,
+            room_registry.clone(),
+            room_deletion_queue.clone(),
+            config::room::get_unjoined_timeout(),
+            remote_rooms.clone(),
+            sessions.clone(),
+        )
+        .with_limits(load_limits)
+        .with_invitations(invitations.clone());
+        if let Some(url) = config::cluster::get_public_url() {
+            bridge = bridge.with_public_url(url);
+        }
+        let bridge = Arc::new(bridge);
+        tokio::spawn(bridge.clone().run());
+        bridge
+    });
+
+    let matchmaker = Arc::new(Matchmaker::new(config::matchmaking::get_matchmaking_rules()));
+    tokio::spawn(matchmaker.clone().run(
+        room_registry.clone(),
+        room_deletion_queue.clone(),
+        config::room::get_unjoined_timeout(),
+    ));
+
+    let leaderboards = Arc::new(config::leaderboards::get_leaderboards());
+    let leaderboards_saver = tokio::spawn(leaderboards.clone().run(
+        config::leaderboards::get_leaderboard_refresh_interval(),
+        shutdown.clone(),
+    ));
+
+    let room_scheduler = RoomScheduler::new(
+        room_registry.clone(),
+        room_deletion_queue.clone(),
+        config::room::get_unjoined_timeout(),
+    );
+
+    let recorder = config::room::get_recording_dir().map(|dir| {
+        let (recorder, writer) = recording_channel(dir, RECORDING_QUEUE_CAPACITY);
+        tokio::task::spawn_blocking(move || writer.watch());
+        recorder
+    });
+
     let state = web::Data::new(SharedAppState {
-        room_registry: Mutex::new(RoomRegistry::new()),
+        room_registry: RegistryHandle::new(room_registry, config::registry::get_lock_timeout()),
+        remote_rooms,
+        cluster,
+        room_deletion_queue,
+        room_creation_queue,
+        room_creation_wait: config::registry::get_creation_wait(),
+        broadcast_flush_interval: config::room::get_broadcast_flush_interval(),
+        spectator_delay: config::room::get_spectator_delay(),
+        state_codecs: config::room::get_state_codecs(),
+        player_authenticator: config::auth::get_identity_provider().map(PlayerAuthenticator::new),
+        guest_challenge: config::auth::get_guest_challenge(),
+        sessions,
+        ban_list,
+        rate_limiter,
+        content_filters: config::moderation::get_content_filters(),
+        emotes: config::chat::get_emote_catalog(),
+        invite_signer: config::auth::get_invite_signer(),
+        invitations,
+        presence: PresenceFeed::new(game_type),
+        push: config::notifications::get_push_dispatcher().map(Arc::new),
+        abuse_counters: Default::default(),
+        abuse_scores: Default::default(),
+        panics: Default::default(),
+        maintenance: Default::default(),
+        matchmaker,
+        ratings: Default::default(),
+        leaderboards,
+        parties: Default::default(),
+        tournaments: Default::default(),
+        turn_based_rooms,
+        room_scheduler,
+        load_limits,
+        abuse_rules: config::moderation::get_abuse_rules(),
+        recorder,
+        room_watchers: Default::default(),
+        reports,
+        room_bans,
+        spam_guard: SpamGuard::new(config::moderation::get_spam_rules()),
+        kick_votes: KickVotes::new(config::moderation::get_kick_vote_rules()),
+        waiting_lists: Default::default(),
     });
 
-    HttpServer::new(move || {
-        App::new().app_data(state.clone()).service(
-            web::scope("api/v1")
-                .wrap(TracingLogger::default())
-                .configure(configure_api_scope),
-        )
+    let mut event_sinks: Vec<Box<dyn EventSink>> = Vec::new();
+    if let Some(dispatcher) = webhook_dispatcher {
+        event_sinks.push(Box::new(Arc::new(dispatcher)));
+    }
+    if let Some(url) = config::events::get_nats_url() {
+        let prefix = config::events::get_nats_subject_prefix();
+        let sink = NatsSink::connect(&url, prefix).await.unwrap_or_else(|e| {
+            panic!("Couldn't connect to NATS: {e}, please fix or delete WORMHOLE_NATS_URL")
+        });
+        event_sinks.push(Box::new(sink));
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(brokers) = config::events::get_kafka_brokers() {
+        let topic = config::events::get_kafka_topic();
+        let sink = events::KafkaSink::new(&brokers, topic).unwrap_or_else(|e| {
+            panic!("Couldn't create a Kafka producer: {e}, please fix or delete WORMHOLE_KAFKA_BROKERS")
+        });
+        event_sinks.push(Box::new(sink));
+    }
+    if !event_sinks.is_empty() {
+        tokio::spawn(events::forward_events(
+            event_sinks,
+            state.room_registry.lobby().subscribe(),
+            state.ban_list.subscribe(),
+            state.presence.subscribe(),
+            state.room_scheduler.subscribe(),
+            state.spam_guard.subscribe(),
+        ));
+    }
+
+    let api_tokens = web::Data::new(config::auth::get_api_tokens());
+    let reloaded_tokens = api_tokens.clone();
+    tokio::spawn(config::secrets::reload_on_hangup(move || {
+        reloaded_tokens.reload()
+    }));
+
+    let request_timeout = web::Data::new(config::server::get_request_timeout());
+    let body_limits = config::server::get_body_limits();
+    let json_config = body_limits.json_config();
+    let body_limits = web::Data::new(body_limits);
+    let host = config::server::get_host();
+    let admin_listener = config::server::get_admin_listener();
+    let serve_admin_endpoints = admin_listener.is_none();
+
+    let admin_server = match admin_listener {
+        Some(listener) => {
+            let state = state.clone();
+            let api_tokens = api_tokens.clone();
+            let request_timeout = request_timeout.clone();
+            let body_limits = body_limits.clone();
+            let json_config = json_config.clone();
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(state.clone())
+                    .app_data(api_tokens.clone())
+                    .app_data(request_timeout.clone())
+                    .app_data(body_limits.clone())
+                    .app_data(json_config.clone())
+                    .service(
+                        web::scope("api/v1")
+                            .wrap(from_fn(request_body::limit_request_bodies))
+                            .wrap(from_fn(auth::require_api_token))
+                            .wrap(from_fn(request_timeout::enforce_request_timeout))
+                            .wrap(from_fn(panics::catch_panics))
+                            .wrap(TracingLogger::default())
+                            .configure(api::configure_admin_scope),
+                    )
+            });
+            let address = (host.as_ref(), listener.port);
+            let server = match listener.tls {
+                Some(tls) => server.bind_rustls_0_23(address, tls.server_config()?)?,
+                None => server.bind(address)?,
+            };
+            Some(server.run())
+        }
+        None => None,
+    };
+
+    let grpc_server = match config::server::get_grpc_port() {
+        Some(port) => {
+            let address = tokio::net::lookup_host((host.as_ref(), port))
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{host} doesn't resolve to an address"))?;
+            let service = RoomService::new(state.clone(), api_tokens.clone());
+            Some(grpc::serve(address, service, shutdown.clone()))
+        }
+        None => None,
+    };
+
+    let server = HttpServer::new(move || {
+        let app = App::new()
+            .app_data(state.clone())
+            .app_data(api_tokens.clone())
+            .app_data(request_timeout.clone())
+            .app_data(body_limits.clone())
+            .app_data(json_config.clone())
+            .service(web::resource("/metrics").route(web::get().to(api::get_metrics)))
+            .configure(api::configure_docs)
+            .configure(api::configure_player_routes)
+            .service(web::resource("/ws/{room_id}").route(web::get().to(ws::join_room)))
+            .service(web::resource("/challenge").route(web::get().to(ws::get_challenge)))
+            .service(
+                web::scope("api/v1")
+                    .wrap(from_fn(request_body::limit_request_bodies))
+                    .wrap(from_fn(auth::require_api_token))
+                    .wrap(from_fn(rate_limit::limit_requests))
+                    .wrap(from_fn(request_timeout::enforce_request_timeout))
+                    .wrap(from_fn(panics::catch_panics))
+                    .wrap(TracingLogger::default())
+                    .configure(|cfg| {
+                        api::configure_api_scope(cfg);
+                        if serve_admin_endpoints {
+                            api::configure_admin_scope(cfg);
+                        }
+                    }),
+            );
+        #[cfg(feature = "web-ui")]
+        let app = app.configure(wormhole::web_ui::configure);
+        app
     })
-    .bind((
-        config::server::get_host().as_ref(),
-        config::server::get_port(),
-    ))?
-    .run()
-    .await?;
+    .bind((host.as_ref(), config::server::get_port()))?
+    .run();
+
+    // The HTTP server stops on SIGINT and SIGTERM, taking everything else down with it
+    let server = async {
+        let served = server.await;
+        shutdown.cancel();
+        served
+    };
+    let admin_server = async {
+        match admin_server {
+            Some(admin_server) => admin_server.await,
+            None => Ok(()),
+        }
+    };
+    let grpc_server = async {
+        match grpc_server {
+            Some(grpc_server) => grpc_server.await.map_err(std::io::Error::other),
+            None => Ok(()),
+        }
+    };
+    let served = tokio::try_join!(server, admin_server, grpc_server);
 
+    shutdown.cancel();
+    room_deletion_supervisor.await?;
+    turn_based_saver.await?;
+    ban_list_saver.await?;
+    reports_saver.await?;
+    room_bans_saver.await?;
+    leaderboards_saver.await?;
+    lobby_updates.await?;
+    served?;
     Ok(())
 }
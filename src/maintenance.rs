@@ -0,0 +1,63 @@
+//! Maintenance mode, which stops rooms from being created while letting the games already
+//! under way finish, so operators can drain the server before taking it down
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use tracing::info;
+
+use crate::problem::Problem;
+
+/// How long clients turned away during maintenance are asked to wait before retrying
+const MAINTENANCE_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Whether the server is in maintenance mode
+#[derive(Debug, Default)]
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+}
+
+impl MaintenanceMode {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turns maintenance mode on or off, attributing the change to `actor` in the logs
+    pub fn set_enabled(&self, enabled: bool, actor: &str) {
+        if self.enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            info!(event = "maintenance_mode_changed", enabled, actor);
+        }
+    }
+
+    /// Turns away a request to create a room while maintenance mode is on
+    pub fn check_room_creation(&self) -> Result<(), Problem> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        Err(Problem::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "/problems/maintenance",
+            "The server is undergoing maintenance",
+        )
+        .with_detail("No new rooms can be created until maintenance is over")
+        .with_retry_after(MAINTENANCE_RETRY_AFTER))
+    }
+}
+
+#[cfg(test)]
+mod check_room_creation {
+    use super::*;
+
+    #[test]
+    fn only_turns_requests_away_during_maintenance() {
+        let maintenance = MaintenanceMode::default();
+        assert_eq!(maintenance.check_room_creation(), Ok(()));
+
+        maintenance.set_enabled(true, "test");
+        assert_eq!(maintenance.check_room_creation().unwrap_err().status, 503);
+
+        maintenance.set_enabled(false, "test");
+        assert_eq!(maintenance.check_room_creation(), Ok(()));
+    }
+}
@@ -0,0 +1,647 @@
+//! Queues of players waiting to be matched with others for a game, and the matcher that
+//! seats them in rooms of their own
+//!
+//! Players [queue][Matchmaker::enqueue] as a party, one or more players who want to play
+//! together, for a game type. Every [MATCHMAKING_INTERVAL] the matcher goes through each
+//! game type's queue oldest party first, filling a match with the parties that fit in it
+//! until it holds [match_size][MatchmakingRules::match_size] players. Parties that have
+//! waited longer than [relax_after][MatchmakingRules::relax_after] may start a match with as
+//! few as [min_players][MatchmakingRules::min_players] instead, so quiet queues still
+//! get games.
+//!
+//! Parties are only matched with others whose [rating][crate::ratings] is close to theirs,
+//! within a [band][MatchmakingRules::with_rating_band] that widens the longer the oldest
+//! party in the match has waited.
+//!
+//! Each match is played in a private room created for it, which players join with seats
+//! minted for them when they poll their [ticket][QueueTicket].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::clock::{system_clock, Clock};
+use crate::game::{PlayerId, RoomDeletionQueue, RoomId, RoomRegistry};
+
+/// How often the queues are matched
+pub const MATCHMAKING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a ticket can still be polled for once its party has been matched
+const MATCHED_TICKET_RETENTION: Duration = Duration::from_secs(300);
+
+/// The longest game type name players can queue for
+const MAX_GAME_TYPE_LEN: usize = 32;
+
+/// An ID that identifies a party's place in a matchmaking queue so its outcome can be polled
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct QueueTicket(Uuid);
+
+impl std::fmt::Display for QueueTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How parties are grouped into matches
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct MatchmakingRules {
+    /// How many players a full match holds
+    pub match_size: usize,
+    /// The fewest players a match can start with once its oldest party has waited
+    /// [relax_after][Self::relax_after]
+    pub min_players: usize,
+    pub relax_after: Duration,
+    /// How far apart the ratings of parties in a match can be when its oldest party has
+    /// just queued
+    pub rating_band: f64,
+    /// How much the rating band widens each second the oldest party waits
+    pub band_growth_per_sec: f64,
+}
+
+impl MatchmakingRules {
+    /// Rules for matches of `match_size` players, which can't start short of it if
+    /// `min_players` is as large
+    ///
+    /// # Panics
+    /// Panics if `match_size` is zero
+    pub fn new(match_size: usize, min_players: usize, relax_after: Duration) -> Self {
+        assert!(match_size > 0, "A match needs at least one player");
+        Self {
+            match_size,
+            min_players: min_players.clamp(1, match_size),
+            relax_after,
+            rating_band: f64::INFINITY,
+            band_growth_per_sec: 0.0,
+        }
+    }
+
+    /// Only matches parties rated within `band` of the oldest party in the match, widening
+    /// by `growth_per_sec` for each second it has waited
+    pub fn with_rating_band(mut self, band: f64, growth_per_sec: f64) -> Self {
+        self.rating_band = band;
+        self.band_growth_per_sec = growth_per_sec;
+        self
+    }
+
+    /// How far from the oldest party's rating others in its match can be once it has waited
+    fn band_after(&self, waited: Duration) -> f64 {
+        self.rating_band + self.band_growth_per_sec * waited.as_secs_f64()
+    }
+}
+
+/// Whether players can queue for, and be rated in, a game type of this name
+pub fn is_valid_game_type(game_type: &str) -> bool {
+    !game_type.is_empty() && game_type.chars().count() <= MAX_GAME_TYPE_LEN
+}
+
+/// Raised when a party can't join a queue
+#[derive(Error, Debug, PartialEq)]
+pub enum EnqueueError {
+    #[error("Game types must be between 1 and {MAX_GAME_TYPE_LEN} characters")]
+    InvalidGameType,
+    #[error("A party needs at least one player")]
+    EmptyParty,
+    #[error("A party can hold at most {0} players")]
+    PartyTooLarge(usize),
+    #[error("A player can only be in a party once")]
+    DuplicatePlayer,
+    #[error("{0} is already queued")]
+    AlreadyQueued(PlayerId),
+}
+
+/// Where a queued party stands
+#[derive(Debug, PartialEq, Clone)]
+pub enum TicketStatus {
+    Waiting {
+        game_type: String,
+        waited: Duration,
+    },
+    /// The party was put in a match played in the room, and can take its seats
+    Matched {
+        room_id: RoomId,
+        players: Vec<PlayerId>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct QueuedParty {
+    ticket: QueueTicket,
+    players: Vec<PlayerId>,
+    /// The party's rating in the game type when it queued
+    rating: f64,
+    queued_at: Instant,
+}
+
+#[derive(Debug)]
+struct MatchedParty {
+    room_id: RoomId,
+    players: Vec<PlayerId>,
+    matched_at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Queues {
+    /// The parties waiting for each game type, oldest first
+    waiting: HashMap<String, Vec<QueuedParty>>,
+    matched: HashMap<QueueTicket, MatchedParty>,
+}
+
+/// A match the matcher has grouped, waiting for its room to be created
+#[derive(Debug)]
+struct PendingMatch {
+    game_type: String,
+    parties: Vec<QueuedParty>,
+}
+
+/// The parties to fill a match with from `waiting`, oldest first, by their indices
+///
+/// Each party, from the oldest, is tried as the oldest in a match: the parties after it that
+/// are rated within its [band][MatchmakingRules::with_rating_band] are added while they
+/// fit. The match is kept if it's full, or holds at least
+/// [min_players][MatchmakingRules::min_players] once its oldest party has waited
+/// [relax_after][MatchmakingRules::relax_after], otherwise those parties are left for the
+/// next one to try.
+fn group_parties(
+    waiting: &[QueuedParty],
+    rules: &MatchmakingRules,
+    now: Instant,
+) -> Vec<Vec<usize>> {
+    let mut taken = vec![false; waiting.len()];
+    let mut matches = Vec::new();
+    for oldest in 0..waiting.len() {
+        if taken[oldest] {
+            continue;
+        }
+        let waited = now.duration_since(waiting[oldest].queued_at);
+        let band = rules.band_after(waited);
+        let mut parties = Vec::new();
+        let mut seated = 0;
+        for (i, party) in waiting.iter().enumerate().skip(oldest) {
+            if !taken[i]
+                && seated + party.players.len() <= rules.match_size
+                && (party.rating - waiting[oldest].rating).abs() <= band
+            {
+                parties.push(i);
+                seated += party.players.len();
+            }
+        }
+        let relaxed = waited >= rules.relax_after;
+        if seated == rules.match_size || (relaxed && seated >= rules.min_players) {
+            for &i in &parties {
+                taken[i] = true;
+            }
+            matches.push(parties);
+        }
+    }
+    matches
+}
+
+/// Queues parties for games and groups them into matches
+#[derive(Debug)]
+pub struct Matchmaker {
+    rules: MatchmakingRules,
+    queues: Mutex<Queues>,
+    /// The clock waiting times and matchmaking rounds are measured on
+    clock: Arc<dyn Clock>,
+}
+
+impl Matchmaker {
+    pub fn new(rules: MatchmakingRules) -> Self {
+        Self {
+            rules,
+            queues: Default::default(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Measures how long parties wait, and runs matchmaking rounds, on `clock` rather than
+    /// the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Queues the party, [rated][crate::ratings::RatingBook::party_rating] `rating`, for a
+    /// game of `game_type`, returning the ticket its outcome can be polled with
+    pub fn enqueue(
+        &self,
+        game_type: String,
+        players: Vec<PlayerId>,
+        rating: f64,
+    ) -> Result<QueueTicket, EnqueueError> {
+        if !is_valid_game_type(&game_type) {
+            return Err(EnqueueError::InvalidGameType);
+        }
+        if players.is_empty() {
+            return Err(EnqueueError::EmptyParty);
+        }
+        if players.len() > self.rules.match_size {
+            return Err(EnqueueError::PartyTooLarge(self.rules.match_size));
+        }
+        let party: HashSet<_> = players.iter().collect();
+        if party.len() != players.len() {
+            return Err(EnqueueError::DuplicatePlayer);
+        }
+
+        let mut queues = self.queues.lock().unwrap();
+        let queued = queues
+            .waiting
+            .values()
+            .flatten()
+            .flat_map(|queued| &queued.players)
+            .find(|player| party.contains(player));
+        if let Some(&player) = queued {
+            return Err(EnqueueError::AlreadyQueued(player));
+        }
+        let ticket = QueueTicket(Uuid::new_v4());
+        info!(event = "party_queued", %ticket, game_type, size = players.len());
+        queues
+            .waiting
+            .entry(game_type)
+            .or_default()
+            .push(QueuedParty {
+                ticket,
+                players,
+                rating,
+                queued_at: self.clock.now(),
+            });
+        Ok(ticket)
+    }
+
+    /// Takes the party out of its queue, returning whether it was still waiting
+    pub fn leave(&self, ticket: QueueTicket) -> bool {
+        let mut queues = self.queues.lock().unwrap();
+        for waiting in queues.waiting.values_mut() {
+            if let Some(i) = waiting.iter().position(|party| party.ticket == ticket) {
+                waiting.remove(i);
+                info!(event = "party_left_queue", %ticket);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Where the party stands, or `None` if the ticket is unknown, has left its queue, or was
+    /// matched too long ago to be remembered
+    pub fn status(&self, ticket: QueueTicket) -> Option<TicketStatus> {
+        let queues = self.queues.lock().unwrap();
+        if let Some(matched) = queues.matched.get(&ticket) {
+            return Some(TicketStatus::Matched {
+                room_id: matched.room_id,
+                players: matched.players.clone(),
+            });
+        }
+        queues.waiting.iter().find_map(|(game_type, waiting)| {
+            let party = waiting.iter().find(|party| party.ticket == ticket)?;
+            Some(TicketStatus::Waiting {
+                game_type: game_type.clone(),
+                waited: self.clock.now().duration_since(party.queued_at),
+            })
+        })
+    }
+
+    /// How many players are waiting across every queue
+    pub fn queued_player_count(&self) -> usize {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .waiting
+            .values()
+            .flatten()
+            .map(|party| party.players.len())
+            .sum()
+    }
+
+    /// Takes the parties that can be matched out of their queues
+    fn take_matches(&self, now: Instant) -> Vec<PendingMatch> {
+        let mut queues = self.queues.lock().unwrap();
+        queues
+            .matched
+            .retain(|_, matched| now.duration_since(matched.matched_at) < MATCHED_TICKET_RETENTION);
+        let mut pending = Vec::new();
+        for (game_type, waiting) in &mut queues.waiting {
+            let groups = group_parties(waiting, &self.rules, now);
+            if groups.is_empty() {
+                continue;
+            }
+            let mut parties: Vec<_> = waiting.drain(..).map(Some).collect();
+            for group in groups {
+                pending.push(PendingMatch {
+                    game_type: game_type.clone(),
+                    parties: group
+                        .into_iter()
+                        .filter_map(|i| parties[i].take())
+                        .collect(),
+                });
+            }
+            waiting.extend(parties.into_iter().flatten());
+        }
+        queues.waiting.retain(|_, waiting| !waiting.is_empty());
+        pending
+    }
+
+    /// Puts the parties of a match whose room couldn't be created back in their queue, ahead
+    /// of everyone who queued after them
+    fn requeue(&self, pending: PendingMatch) {
+        let mut queues = self.queues.lock().unwrap();
+        let waiting = queues.waiting.entry(pending.game_type).or_default();
+        waiting.extend(pending.parties);
+        waiting.sort_by_key(|party| party.queued_at);
+    }
+
+    fn record_match(&self, room_id: RoomId, pending: PendingMatch, now: Instant) {
+        let mut queues = self.queues.lock().unwrap();
+        for party in pending.parties {
+            queues.matched.insert(
+                party.ticket,
+                MatchedParty {
+                    room_id,
+                    players: party.players,
+                    matched_at: now,
+                },
+            );
+        }
+    }
+
+    /// Creates a private room in the registry for every match that can be made, returning how
+    /// many were
+    ///
+    /// Rooms nobody joins are deleted after `unjoined_room_timeout` like any other.
+    pub fn match_parties(
+        &self,
+        registry: &RoomRegistry,
+        deletion_queue: &RoomDeletionQueue,
+        unjoined_room_timeout: Duration,
+    ) -> usize {
+        let now = self.clock.now();
+        let mut created = 0;
+        for pending in self.take_matches(now) {
+            match registry.create_room_with(|room| {
+                room.set_private(true);
+                room.set_game_type(pending.game_type.clone());
+            }) {
+                Ok(room_id) => {
+                    if let Some(room) = registry.get_room_for_id(room_id) {
+                        room.schedule_deletion(
+                            room_id,
+                            unjoined_room_timeout,
+                            deletion_queue.clone(),
+                        );
+                        registry.room_updated(room_id);
+                    }
+                    info!(
+                        event = "match_created",
+                        room_id = %room_id,
+                        game_type = pending.game_type,
+                        parties = pending.parties.len(),
+                    );
+                    self.record_match(room_id, pending, now);
+                    created += 1;
+                }
+                Err(e) => {
+                    warn!(event = "match_room_creation_failed", error = %e);
+                    self.requeue(pending);
+                }
+            }
+        }
+        created
+    }
+
+    /// Matches the queues every [MATCHMAKING_INTERVAL]
+    pub async fn run(
+        self: Arc<Self>,
+        registry: Arc<RoomRegistry>,
+        deletion_queue: RoomDeletionQueue,
+        unjoined_room_timeout: Duration,
+    ) {
+        loop {
+            self.match_parties(&registry, &deletion_queue, unjoined_room_timeout);
+            self.clock.sleep(MATCHMAKING_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod group_parties {
+    use super::*;
+    use crate::ratings::INITIAL_RATING;
+
+    fn queue(sizes: &[u128], now: Instant, waited: Duration) -> Vec<QueuedParty> {
+        let parties: Vec<_> = sizes.iter().map(|&size| (size, INITIAL_RATING)).collect();
+        rated_queue(&parties, now, waited)
+    }
+
+    fn rated_queue(parties: &[(u128, f64)], now: Instant, waited: Duration) -> Vec<QueuedParty> {
+        let mut first_player = 0;
+        parties
+            .iter()
+            .map(|&(size, rating)| {
+                let players = (first_player..first_player + size).map(PlayerId::from);
+                first_player += size;
+                QueuedParty {
+                    ticket: QueueTicket(Uuid::new_v4()),
+                    players: players.collect(),
+                    rating,
+                    queued_at: now - waited,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fills_matches_oldest_party_first() {
+        let now = Instant::now();
+        let rules = MatchmakingRules::new(4, 4, Duration::from_secs(30));
+        let waiting = queue(&[3, 2, 2, 1, 1], now, Duration::ZERO);
+
+        assert_eq!(
+            group_parties(&waiting, &rules, now),
+            [vec![0, 3], vec![1, 2]]
+        );
+    }
+
+    #[test]
+    fn waits_for_full_matches_until_relaxed() {
+        let now = Instant::now();
+        let rules = MatchmakingRules::new(4, 2, Duration::from_secs(30));
+
+        let fresh = queue(&[2, 1], now, Duration::from_secs(10));
+        assert!(group_parties(&fresh, &rules, now).is_empty());
+
+        let stale = queue(&[2, 1], now, Duration::from_secs(30));
+        assert_eq!(group_parties(&stale, &rules, now), [vec![0, 1]]);
+    }
+
+    #[test]
+    fn never_starts_below_the_minimum() {
+        let now = Instant::now();
+        let rules = MatchmakingRules::new(4, 3, Duration::ZERO);
+        let waiting = queue(&[2], now, Duration::from_secs(60));
+
+        assert!(group_parties(&waiting, &rules, now).is_empty());
+    }
+
+    #[test]
+    fn widens_the_rating_band_with_time() {
+        let now = Instant::now();
+        let rules =
+            MatchmakingRules::new(2, 2, Duration::from_secs(30)).with_rating_band(100.0, 10.0);
+
+        let fresh = rated_queue(
+            &[(1, 1500.0), (1, 1700.0), (1, 1550.0)],
+            now,
+            Duration::ZERO,
+        );
+        assert_eq!(group_parties(&fresh, &rules, now), [vec![0, 2]]);
+
+        let stale = rated_queue(&[(1, 1500.0), (1, 1700.0)], now, Duration::from_secs(10));
+        assert_eq!(group_parties(&stale, &rules, now), [vec![0, 1]]);
+    }
+}
+
+#[cfg(test)]
+mod enqueue {
+    use super::*;
+    use crate::ratings::INITIAL_RATING;
+
+    fn matchmaker() -> Matchmaker {
+        Matchmaker::new(MatchmakingRules::new(2, 2, Duration::from_secs(30)))
+    }
+
+    #[test]
+    fn turns_away_parties_that_cant_be_matched() {
+        let matchmaker = matchmaker();
+        let [a, b, c] = [1, 2, 3].map(PlayerId::from);
+
+        assert_eq!(
+            matchmaker.enqueue(String::new(), vec![a], INITIAL_RATING),
+            Err(EnqueueError::InvalidGameType)
+        );
+        assert_eq!(
+            matchmaker.enqueue("duel".into(), vec![], INITIAL_RATING),
+            Err(EnqueueError::EmptyParty)
+        );
+        assert_eq!(
+            matchmaker.enqueue("duel".into(), vec![a, b, c], INITIAL_RATING),
+            Err(EnqueueError::PartyTooLarge(2))
+        );
+        assert_eq!(
+            matchmaker.enqueue("duel".into(), vec![a, a], INITIAL_RATING),
+            Err(EnqueueError::DuplicatePlayer)
+        );
+    }
+
+    #[test]
+    fn keeps_players_in_one_queue_at_a_time() {
+        let matchmaker = matchmaker();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        matchmaker
+            .enqueue("duel".into(), vec![a], INITIAL_RATING)
+            .unwrap();
+
+        assert_eq!(
+            matchmaker.enqueue("race".into(), vec![b, a], INITIAL_RATING),
+            Err(EnqueueError::AlreadyQueued(a))
+        );
+    }
+
+    #[test]
+    fn forgets_parties_that_leave() {
+        let matchmaker = matchmaker();
+        let ticket = matchmaker
+            .enqueue("duel".into(), vec![PlayerId::from(1)], INITIAL_RATING)
+            .unwrap();
+
+        assert!(matches!(
+            matchmaker.status(ticket),
+            Some(TicketStatus::Waiting { .. })
+        ));
+        assert!(matchmaker.leave(ticket));
+        assert_eq!(matchmaker.status(ticket), None);
+        assert_eq!(matchmaker.queued_player_count(), 0);
+    }
+}
+
+#[cfg(test)]
+mod match_parties {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy};
+    use crate::ratings::INITIAL_RATING;
+
+    #[tokio::test]
+    async fn seats_matched_parties_in_a_private_room() {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let (deletion_queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        let matchmaker = Matchmaker::new(MatchmakingRules::new(2, 2, Duration::from_secs(30)));
+        let [a, b, c] = [1, 2, 3].map(PlayerId::from);
+        let first = matchmaker
+            .enqueue("duel".into(), vec![a], INITIAL_RATING)
+            .unwrap();
+        let other_game = matchmaker
+            .enqueue("race".into(), vec![b], INITIAL_RATING)
+            .unwrap();
+        let second = matchmaker
+            .enqueue("duel".into(), vec![c], INITIAL_RATING)
+            .unwrap();
+
+        let created = matchmaker.match_parties(&registry, &deletion_queue, Duration::from_secs(60));
+
+        assert_eq!(created, 1);
+        let Some(TicketStatus::Matched { room_id, players }) = matchmaker.status(first) else {
+            panic!("Expected the first party to be matched");
+        };
+        assert_eq!(players, [a]);
+        assert!(registry.get_room_for_id(room_id).unwrap().is_private());
+        assert_eq!(
+            matchmaker.status(second),
+            Some(TicketStatus::Matched {
+                room_id,
+                players: vec![c]
+            })
+        );
+        assert!(matches!(
+            matchmaker.status(other_game),
+            Some(TicketStatus::Waiting { .. })
+        ));
+        assert!(!matchmaker.leave(first));
+    }
+
+    #[tokio::test]
+    async fn relaxes_matches_once_parties_have_waited_on_its_clock() {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let (deletion_queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        let clock = Arc::new(MockClock::new());
+        let matchmaker = Matchmaker::new(MatchmakingRules::new(2, 1, Duration::from_secs(30)))
+            .with_clock(clock.clone());
+        let ticket = matchmaker
+            .enqueue("duel".into(), vec![PlayerId::from(1)], INITIAL_RATING)
+            .unwrap();
+        let timeout = Duration::from_secs(60);
+
+        assert_eq!(
+            matchmaker.match_parties(&registry, &deletion_queue, timeout),
+            0
+        );
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            matchmaker.status(ticket),
+            Some(TicketStatus::Waiting {
+                game_type: "duel".into(),
+                waited: Duration::from_secs(30)
+            })
+        );
+        assert_eq!(
+            matchmaker.match_parties(&registry, &deletion_queue, timeout),
+            1
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Rendering of server metrics in the Prometheus text exposition format
+
+use std::fmt::Write;
+
+use crate::allocator;
+use crate::game::{RoomCreationQueue, RoomDeletionQueue, RoomRegistry};
+use crate::janitor::{Janitor, Reaped};
+use crate::load::LoadReport;
+use crate::panics::PanicCounters;
+use crate::rate_limit::{RateLimiter, RouteBudget};
+use crate::ws::AbuseCounters;
+use crate::SharedAppState;
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    write_header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    write_header(out, name, help, "gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn write_registry_metrics(out: &mut String, room_registry: &RoomRegistry) {
+    let occupancy = room_registry.shard_occupancy();
+
+    write_gauge(
+        out,
+        "wormhole_rooms",
+        "Number of rooms currently registered",
+        occupancy.iter().sum::<usize>(),
+    );
+    write_gauge(
+        out,
+        "wormhole_registry_shards",
+        "Number of shards the room registry is partitioned into",
+        occupancy.len(),
+    );
+
+    write_gauge(
+        out,
+        "wormhole_connections",
+        "Number of WebSocket connections across every room",
+        room_registry.connection_count(),
+    );
+
+    write_gauge(
+        out,
+        "wormhole_room_memory_bytes",
+        "Approximate number of bytes held by undelivered messages across every room",
+        room_registry.memory_usage(),
+    );
+
+    let name = "wormhole_registry_shard_rooms";
+    write_header(
+        out,
+        name,
+        "Number of rooms held by each registry shard",
+        "gauge",
+    );
+    for (shard, rooms) in occupancy.iter().enumerate() {
+        let _ = writeln!(out, "{name}{{shard=\"{shard}\"}} {rooms}");
+    }
+}
+
+fn write_load_metrics(out: &mut String, load: &LoadReport) {
+    write_gauge(
+        out,
+        "wormhole_load_score",
+        "Highest of the shares of room and connection capacity in use and the CPU load",
+        load.score,
+    );
+}
+
+fn write_deletion_queue_metrics(out: &mut String, queue: &RoomDeletionQueue) {
+    write_gauge(
+        out,
+        "wormhole_room_deletion_queue_depth",
+        "Number of room deletion requests waiting to be handled",
+        queue.depth(),
+    );
+    write_gauge(
+        out,
+        "wormhole_room_deletion_queue_capacity",
+        "Number of room deletion requests the queue holds before overflowing",
+        queue.capacity(),
+    );
+    write_counter(
+        out,
+        "wormhole_room_deletion_queue_overflows_total",
+        "Number of room deletion requests that arrived while the queue was full",
+        queue.overflow_count(),
+    );
+    write_counter(
+        out,
+        "wormhole_room_deletion_stale_requests_total",
+        "Number of room deletion requests skipped because the room was joined or its deletion rescheduled in the meantime",
+        queue.stale_count(),
+    );
+    write_counter(
+        out,
+        "wormhole_room_deletion_handler_restarts_total",
+        "Number of times the room deletion handler has been restarted after panicking",
+        queue.restart_count(),
+    );
+}
+
+fn write_creation_queue_metrics(out: &mut String, queue: &RoomCreationQueue) {
+    write_gauge(
+        out,
+        "wormhole_room_creation_queue_depth",
+        "Number of room creation requests waiting to be handled",
+        queue.depth(),
+    );
+}
+
+fn write_rate_limit_metrics(out: &mut String, limiter: &RateLimiter) {
+    let name = "wormhole_rate_limited_requests_total";
+    write_header(
+        out,
+        name,
+        "Number of REST requests and chat messages turned away for exceeding their budget",
+        "counter",
+    );
+    for budget in RouteBudget::ALL {
+        let _ = writeln!(
+            out,
+            "{name}{{budget=\"{}\"}} {}",
+            budget.name(),
+            limiter.rejection_count(budget)
+        );
+    }
+}
+
+fn write_janitor_metrics(out: &mut String, janitor: &Janitor) {
+    write_gauge(
+        out,
+        "wormhole_janitor_leader",
+        "Whether this instance is the one sweeping up after dead instances",
+        u8::from(janitor.is_leader()),
+    );
+    let name = "wormhole_janitor_reaped_total";
+    write_header(
+        out,
+        name,
+        "Number of leftovers this instance removed from the shared store while sweeping",
+        "counter",
+    );
+    for kind in Reaped::ALL {
+        let _ = writeln!(
+            out,
+            "{name}{{kind=\"{}\"}} {}",
+            kind.name(),
+            janitor.reaped_count(kind)
+        );
+    }
+}
+
+fn write_abuse_metrics(out: &mut String, counters: &AbuseCounters) {
+    write_counter(
+        out,
+        "wormhole_protocol_violations_total",
+        "Number of client messages rejected for claiming another identity or lacking permission",
+        counters.violation_count(),
+    );
+    write_counter(
+        out,
+        "wormhole_abuse_disconnects_total",
+        "Number of connections closed for abusing the server",
+        counters.disconnect_count(),
+    );
+}
+
+fn write_panic_metrics(out: &mut String, counters: &PanicCounters) {
+    write_counter(
+        out,
+        "wormhole_request_panics_total",
+        "Number of REST requests answered with a 500 because their handler panicked",
+        counters.request_count(),
+    );
+    write_counter(
+        out,
+        "wormhole_connection_panics_total",
+        "Number of room connections closed because handling one of their frames panicked",
+        counters.connection_count(),
+    );
+}
+
+fn write_allocator_metrics(out: &mut String) {
+    let name = "wormhole_allocator_info";
+    write_header(
+        out,
+        name,
+        "The global allocator the server is running with",
+        "gauge",
+    );
+    let _ = writeln!(out, "{name}{{allocator=\"{}\"}} 1", allocator::NAME);
+
+    let Some(stats) = allocator::stats() else {
+        return;
+    };
+    write_gauge(
+        out,
+        "wormhole_allocator_allocated_bytes",
+        "Bytes handed out to the application by the allocator",
+        stats.allocated,
+    );
+    write_gauge(
+        out,
+        "wormhole_allocator_active_bytes",
+        "Bytes in pages the allocator is actively using",
+        stats.active,
+    );
+    write_gauge(
+        out,
+        "wormhole_allocator_resident_bytes",
+        "Bytes of physical memory backing the allocator's pages",
+        stats.resident,
+    );
+    write_gauge(
+        out,
+        "wormhole_allocator_mapped_bytes",
+        "Bytes the allocator has mapped from the operating system",
+        stats.mapped,
+    );
+}
+
+/// Renders every metric the server tracks
+pub fn render(state: &SharedAppState) -> String {
+    let mut out = String::new();
+    write_registry_metrics(&mut out, state.room_registry.registry());
+    write_load_metrics(
+        &mut out,
+        &LoadReport::measure(state.room_registry.registry(), state.load_limits),
+    );
+    write_deletion_queue_metrics(&mut out, &state.room_deletion_queue);
+    write_creation_queue_metrics(&mut out, &state.room_creation_queue);
+    write_rate_limit_metrics(&mut out, &state.rate_limiter);
+    if let Some(cluster) = &state.cluster {
+        write_janitor_metrics(&mut out, cluster.janitor());
+    }
+    write_abuse_metrics(&mut out, &state.abuse_counters);
+    write_panic_metrics(&mut out, &state.panics);
+    write_allocator_metrics(&mut out);
+    out
+}
@@ -0,0 +1,278 @@
+//! Push notifications bringing players back to asynchronous games
+//!
+//! When it becomes a player's turn in a room, or they're invited to one, while they aren't
+//! connected anywhere, the [PushDispatcher] asks a push gateway to notify their devices. The
+//! gateway is posted a body rendered from a [PushTemplate], so it can be shaped to whatever
+//! the gateway expects, and deliveries are retried like [webhooks][crate::webhooks] while
+//! the gateway is unreachable or failing.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::Url;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+use crate::cluster::RedisBridge;
+use crate::config::secrets::Secret;
+use crate::game::{PlayerId, RoomId};
+use crate::sessions::SessionRegistry;
+use crate::webhooks::{is_retryable, RetryPolicy};
+
+/// How long a single push attempt may take before it counts as failed
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a player is being brought back
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PushReason {
+    /// It's become the player's turn in the room's game
+    YourTurn,
+    /// Another player has invited them to the room
+    Invited { from: PlayerId },
+}
+
+impl Display for PushReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::YourTurn => f.write_str("your_turn"),
+            Self::Invited { .. } => f.write_str("invited"),
+        }
+    }
+}
+
+/// A notification for a player who isn't connected
+#[derive(Debug, PartialEq, Clone)]
+pub struct PushNotification {
+    pub player_id: PlayerId,
+    pub room_id: RoomId,
+    pub reason: PushReason,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum PushTemplateError {
+    #[error("{{{{{0}}}}} is not a known placeholder, expected {{{{player_id}}}}, {{{{room_id}}}}, {{{{reason}}}} or {{{{from}}}}")]
+    UnknownPlaceholder(String),
+    #[error("The template doesn't render as JSON: {0}")]
+    InvalidJson(String),
+}
+
+/// The JSON body posted to the push gateway, with `{{player_id}}`, `{{room_id}}`,
+/// `{{reason}}` and `{{from}}` placeholders filled in for each notification
+///
+/// `{{reason}}` is `your_turn` or `invited`, and `{{from}}` is the inviting player, empty
+/// for turns. None of them are ever rendered with characters that need escaping in JSON.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PushTemplate(String);
+
+impl PushTemplate {
+    const PLACEHOLDERS: [&'static str; 4] = ["player_id", "room_id", "reason", "from"];
+
+    pub fn render(&self, notification: &PushNotification) -> String {
+        let from = match notification.reason {
+            PushReason::Invited { from } => from.to_string(),
+            PushReason::YourTurn => String::new(),
+        };
+        self.0
+            .replace("{{player_id}}", &notification.player_id.to_string())
+            .replace("{{room_id}}", &notification.room_id.to_string())
+            .replace("{{reason}}", &notification.reason.to_string())
+            .replace("{{from}}", &from)
+    }
+}
+
+impl Default for PushTemplate {
+    fn default() -> Self {
+        Self(
+            r#"{"player_id":"{{player_id}}","room_id":"{{room_id}}","reason":"{{reason}}"}"#
+                .to_owned(),
+        )
+    }
+}
+
+/// Parses a template, checking it only uses known placeholders and renders as JSON
+impl FromStr for PushTemplate {
+    type Err = PushTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        while let Some((_, after)) = rest.split_once("{{") {
+            let Some((placeholder, after)) = after.split_once("}}") else {
+                break;
+            };
+            if !Self::PLACEHOLDERS.contains(&placeholder) {
+                return Err(PushTemplateError::UnknownPlaceholder(
+                    placeholder.to_owned(),
+                ));
+            }
+            rest = after;
+        }
+        let template = Self(s.to_owned());
+        let sample = template.render(&PushNotification {
+            player_id: PlayerId::from(1),
+            room_id: RoomId::from(1),
+            reason: PushReason::Invited {
+                from: PlayerId::from(2),
+            },
+        });
+        serde_json::from_str::<serde_json::Value>(&sample)
+            .map_err(|e| PushTemplateError::InvalidJson(e.to_string()))?;
+        Ok(template)
+    }
+}
+
+/// Whether the player has a live connection on this instance or, through the `cluster`, any
+/// other sharing the lobby
+///
+/// Players whose whereabouts can't be looked up count as offline, since a notification they
+/// didn't need does less harm than one they miss.
+pub async fn is_online(
+    sessions: &SessionRegistry,
+    cluster: Option<&RedisBridge>,
+    player_id: PlayerId,
+) -> bool {
+    if !sessions.rooms_of(player_id).is_empty() {
+        return true;
+    }
+    match cluster {
+        Some(cluster) => cluster
+            .locate_player(player_id)
+            .await
+            .map(|locations| !locations.is_empty())
+            .unwrap_or_else(|e| {
+                warn!(event = "player_lookup_failed", %player_id, error = %e);
+                false
+            }),
+        None => false,
+    }
+}
+
+/// Pushes notifications to players through the gateway
+///
+/// Each push runs on its own task, so a slow gateway never holds up whoever asked for it.
+/// The gateway URL may carry a secret of its own, so only its host is ever logged.
+#[derive(Debug)]
+pub struct PushDispatcher {
+    client: reqwest::Client,
+    gateway: Url,
+    /// Sent to the gateway as a bearer token, if it asks for one
+    token: Option<Secret>,
+    template: PushTemplate,
+    retry: RetryPolicy,
+}
+
+impl PushDispatcher {
+    pub fn new(
+        gateway: Url,
+        token: Option<Secret>,
+        template: PushTemplate,
+        retry: RetryPolicy,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(PUSH_TIMEOUT)
+                .build()
+                .expect("The push client has a valid configuration"),
+            gateway,
+            token,
+            template,
+            retry,
+        }
+    }
+
+    /// Starts pushing the notification to the gateway
+    pub fn push(self: &Arc<Self>, notification: PushNotification) {
+        let dispatcher = self.clone();
+        tokio::spawn(async move { dispatcher.deliver(notification).await });
+    }
+
+    /// Posts the notification to the gateway, retrying until it's accepted or the attempts
+    /// run out
+    async fn deliver(&self, notification: PushNotification) {
+        let host = self.gateway.host_str().unwrap_or_default();
+        let PushNotification {
+            player_id,
+            room_id,
+            reason,
+        } = notification;
+        let body = self.template.render(&notification);
+        for attempt in 1..=self.retry.max_attempts {
+            let mut request = self
+                .client
+                .post(self.gateway.clone())
+                .header("CONTENT-TYPE", "application/json")
+                .body(body.clone());
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token.expose());
+            }
+
+            let retryable = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(event = "push_delivered", host, %player_id, %room_id, %reason, attempt);
+                    return;
+                }
+                Ok(response) => {
+                    warn!(event = "push_rejected", host, %player_id, attempt, status = response.status().as_u16());
+                    is_retryable(response.status())
+                }
+                Err(e) => {
+                    warn!(event = "push_gateway_unreachable", host, %player_id, attempt, error = %e);
+                    true
+                }
+            };
+            if !retryable {
+                break;
+            }
+            if attempt < self.retry.max_attempts {
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+            }
+        }
+        warn!(event = "push_given_up", host, %player_id, %room_id, %reason);
+    }
+}
+
+#[cfg(test)]
+mod push_template_from_str {
+    use super::*;
+
+    #[test]
+    fn accepts_known_placeholders_in_json() {
+        let template: PushTemplate =
+            r#"{"to":"{{player_id}}","data":{"room":"{{room_id}}","why":"{{reason}}","by":"{{from}}"}}"#
+                .parse()
+                .unwrap();
+
+        let rendered = template.render(&PushNotification {
+            player_id: PlayerId::from(1),
+            room_id: RoomId::from(2),
+            reason: PushReason::YourTurn,
+        });
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&rendered).unwrap(),
+            serde_json::json!({
+                "to": PlayerId::from(1),
+                "data": {"room": RoomId::from(2), "why": "your_turn", "by": ""},
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders_and_invalid_json() {
+        assert_eq!(
+            r#"{"to":"{{player}}"}"#.parse::<PushTemplate>(),
+            Err(PushTemplateError::UnknownPlaceholder("player".to_owned()))
+        );
+        assert!(matches!(
+            r#"{"to":{{player_id}}}"#.parse::<PushTemplate>(),
+            Err(PushTemplateError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn defaults_to_a_valid_template() {
+        let default = PushTemplate::default();
+
+        assert_eq!(default.0.parse(), Ok(default));
+    }
+}
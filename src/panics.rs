@@ -0,0 +1,174 @@
+//! Keeping panics in one request or connection from taking anything else down with them
+//!
+//! [catch_panics] answers a REST request whose handler panicked with a `500` problem, and
+//! room connections close themselves with an internal error when handling a frame or any
+//! other message panics. Panics in the futures a connection spawns aren't caught, and one
+//! that poisons a lock on a room closes every later connection to it that takes the lock.
+//! Either way the panic is counted in [PanicCounters]. The [hook][install_hook] logs each
+//! panic's backtrace as it happens, within whatever span was current, so the log carries
+//! the request or room the panic happened in.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::future::{poll_fn, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage};
+use tracing::error;
+use tracing_actix_web::RequestId;
+
+use crate::problem::Problem;
+use crate::SharedAppState;
+
+/// Counts the panics caught across every request and connection
+#[derive(Debug, Default)]
+pub struct PanicCounters {
+    requests: AtomicU64,
+    connections: AtomicU64,
+}
+
+impl PanicCounters {
+    pub(crate) fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of REST requests answered with a `500` because their handler panicked
+    pub fn request_count(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// The number of room connections closed because handling one of their frames panicked
+    pub fn connection_count(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+}
+
+/// What a panic said, if it said it with a string
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+/// Logs every panic in the process along with its backtrace, in place of printing it
+pub fn install_hook() {
+    panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::force_capture();
+        error!(
+            event = "panicked",
+            message = panic_message(info.payload()),
+            location = info.location().map(ToString::to_string),
+            %backtrace
+        );
+    }));
+}
+
+/// Answers requests whose handler panics with a `500` problem rather than dropping them
+pub async fn catch_panics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let state = req.app_data::<web::Data<SharedAppState>>().cloned();
+    let request_id = req.extensions().get::<RequestId>().copied();
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+
+    let mut call = pin!(next.call(req));
+    let caught =
+        poll_fn(
+            |cx| match panic::catch_unwind(AssertUnwindSafe(|| call.as_mut().poll(cx))) {
+                Ok(poll) => poll.map(Ok),
+                Err(payload) => Poll::Ready(Err(payload)),
+            },
+        )
+        .await;
+    let payload = match caught {
+        Ok(response) => return response,
+        Err(payload) => payload,
+    };
+
+    if let Some(state) = state {
+        state.panics.record_request();
+    }
+    error!(
+        event = "request_panicked",
+        request_id = request_id.map(|id| id.to_string()),
+        %method,
+        path,
+        message = panic_message(&*payload)
+    );
+    Err(Problem::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "/problems/internal-error",
+        "Something went wrong handling the request",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod catch_panics {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App, HttpResponse};
+
+    #[actix_web::test]
+    async fn answers_panicking_requests_with_a_problem() {
+        let app = test::init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(from_fn(catch_panics))
+                    .route(
+                        "/panic",
+                        web::get().to(|| async {
+                            if true {
+                                panic!("the handler panicked");
+                            }
+                            HttpResponse::Ok().finish()
+                        }),
+                    )
+                    .route("/fine", web::get().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let panicked = test::TestRequest::get().uri("/panic").to_request();
+        let e = test::try_call_service(&app, panicked).await.unwrap_err();
+        assert_eq!(
+            e.as_response_error().status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let fine = test::TestRequest::get().uri("/fine").to_request();
+        assert_eq!(
+            test::call_service(&app, fine).await.status(),
+            StatusCode::OK
+        );
+    }
+}
+
+#[cfg(test)]
+mod panic_message {
+    use super::*;
+
+    #[test]
+    fn reads_static_and_formatted_messages() {
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "static");
+
+        let payload = panic::catch_unwind(|| panic!("formatted {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "formatted 1");
+    }
+}
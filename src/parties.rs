@@ -0,0 +1,253 @@
+//! Parties of players who play together
+//!
+//! A player [creates][PartyRegistry::create] a party and leads it, [invites][PartyRegistry::invite]
+//! others, who [accept][PartyRegistry::accept] to become members. A party is treated as a
+//! unit: it [queues][crate::matchmaking] for matches together, takes seats in a room only if
+//! there's one for every member, and has its own [chat channel][crate::protocol::ChatChannel::Party]
+//! in whichever room its members are in. A player can only be in one party at a time.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::game::PlayerId;
+
+/// The most players a party can hold
+pub const MAX_PARTY_SIZE: usize = 8;
+
+/// How long seats taken by a party in a room are held for its members to join
+pub const PARTY_SEAT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// An ID that identifies a [party][Party]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct PartyId(Uuid);
+
+impl std::fmt::Display for PartyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct Party {
+    pub id: PartyId,
+    /// The member who created the party, or the longest standing member once they've left
+    pub leader: PlayerId,
+    /// Every member, leader first, in the order they joined
+    pub members: Vec<PlayerId>,
+    /// The players invited who haven't accepted yet
+    pub invited: Vec<PlayerId>,
+}
+
+/// Raised when a party can't be changed as asked
+#[derive(Error, Debug, PartialEq)]
+pub enum PartyError {
+    #[error("No party has the ID")]
+    NotFound,
+    #[error("{0} is already in a party")]
+    AlreadyInParty(PlayerId),
+    #[error("{0} hasn't been invited to the party")]
+    NotInvited(PlayerId),
+    #[error("{0} isn't in the party")]
+    NotAMember(PlayerId),
+    #[error("A party can hold at most {MAX_PARTY_SIZE} players")]
+    Full,
+}
+
+#[derive(Debug, Default)]
+struct Parties {
+    parties: HashMap<PartyId, Party>,
+    party_of: HashMap<PlayerId, PartyId>,
+}
+
+/// Every party on the server
+#[derive(Debug, Default)]
+pub struct PartyRegistry {
+    parties: Mutex<Parties>,
+}
+
+impl PartyRegistry {
+    /// Creates a party led by `leader`, who must not be in another one
+    pub fn create(&self, leader: PlayerId) -> Result<Party, PartyError> {
+        let mut parties = self.parties.lock().unwrap();
+        if parties.party_of.contains_key(&leader) {
+            return Err(PartyError::AlreadyInParty(leader));
+        }
+        let party = Party {
+            id: PartyId(Uuid::new_v4()),
+            leader,
+            members: vec![leader],
+            invited: Vec::new(),
+        };
+        parties.party_of.insert(leader, party.id);
+        parties.parties.insert(party.id, party.clone());
+        info!(event = "party_created", party_id = %party.id, %leader);
+        Ok(party)
+    }
+
+    pub fn get(&self, id: PartyId) -> Option<Party> {
+        self.parties.lock().unwrap().parties.get(&id).cloned()
+    }
+
+    /// The party the player is a member of, if any
+    pub fn party_of(&self, player_id: PlayerId) -> Option<Party> {
+        let parties = self.parties.lock().unwrap();
+        let id = parties.party_of.get(&player_id)?;
+        parties.parties.get(id).cloned()
+    }
+
+    /// Invites the player to the party, which they join once they [accept][Self::accept]
+    pub fn invite(&self, id: PartyId, player_id: PlayerId) -> Result<Party, PartyError> {
+        let mut parties = self.parties.lock().unwrap();
+        let party = parties.parties.get_mut(&id).ok_or(PartyError::NotFound)?;
+        if party.members.contains(&player_id) {
+            return Err(PartyError::AlreadyInParty(player_id));
+        }
+        if party.members.len() + party.invited.len() >= MAX_PARTY_SIZE {
+            return Err(PartyError::Full);
+        }
+        if !party.invited.contains(&player_id) {
+            party.invited.push(player_id);
+        }
+        Ok(party.clone())
+    }
+
+    /// Makes an invited player a member of the party, provided they aren't in another one
+    pub fn accept(&self, id: PartyId, player_id: PlayerId) -> Result<Party, PartyError> {
+        let mut parties = self.parties.lock().unwrap();
+        if parties.party_of.contains_key(&player_id) {
+            return Err(PartyError::AlreadyInParty(player_id));
+        }
+        let party = parties.parties.get_mut(&id).ok_or(PartyError::NotFound)?;
+        let Some(i) = party
+            .invited
+            .iter()
+            .position(|&invited| invited == player_id)
+        else {
+            return Err(PartyError::NotInvited(player_id));
+        };
+        party.invited.remove(i);
+        party.members.push(player_id);
+        let party = party.clone();
+        parties.party_of.insert(player_id, id);
+        info!(event = "party_joined", party_id = %id, player_id = %player_id);
+        Ok(party)
+    }
+
+    /// Takes the player out of the party, or withdraws their invite, passing the lead to the
+    /// longest standing member if they led it and disbanding it once nobody is left
+    ///
+    /// Returns the party as it is afterwards, `None` if it was disbanded.
+    pub fn leave(&self, id: PartyId, player_id: PlayerId) -> Result<Option<Party>, PartyError> {
+        let mut parties = self.parties.lock().unwrap();
+        let party = parties.parties.get_mut(&id).ok_or(PartyError::NotFound)?;
+        if let Some(i) = party
+            .invited
+            .iter()
+            .position(|&invited| invited == player_id)
+        {
+            party.invited.remove(i);
+            return Ok(Some(party.clone()));
+        }
+        let Some(i) = party.members.iter().position(|&member| member == player_id) else {
+            return Err(PartyError::NotAMember(player_id));
+        };
+        party.members.remove(i);
+        info!(event = "party_left", party_id = %id, player_id = %player_id);
+        let party = match party.members.first() {
+            Some(&leader) => {
+                party.leader = leader;
+                Some(party.clone())
+            }
+            None => {
+                info!(event = "party_disbanded", party_id = %id);
+                parties.parties.remove(&id);
+                None
+            }
+        };
+        parties.party_of.remove(&player_id);
+        Ok(party)
+    }
+}
+
+#[cfg(test)]
+mod accept {
+    use super::*;
+
+    #[test]
+    fn only_admits_invited_players() {
+        let parties = PartyRegistry::default();
+        let [leader, invited, stranger] = [1, 2, 3].map(PlayerId::from);
+        let party = parties.create(leader).unwrap();
+        parties.invite(party.id, invited).unwrap();
+
+        assert_eq!(
+            parties.accept(party.id, stranger),
+            Err(PartyError::NotInvited(stranger))
+        );
+        let party = parties.accept(party.id, invited).unwrap();
+
+        assert_eq!(party.members, [leader, invited]);
+        assert!(party.invited.is_empty());
+        assert_eq!(parties.party_of(invited), Some(party));
+    }
+
+    #[test]
+    fn keeps_players_in_one_party_at_a_time() {
+        let parties = PartyRegistry::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let party = parties.create(a).unwrap();
+        parties.create(b).unwrap();
+        parties.invite(party.id, b).unwrap();
+
+        assert_eq!(
+            parties.accept(party.id, b),
+            Err(PartyError::AlreadyInParty(b))
+        );
+        assert_eq!(parties.create(a), Err(PartyError::AlreadyInParty(a)));
+    }
+
+    #[test]
+    fn caps_the_party_size() {
+        let parties = PartyRegistry::default();
+        let party = parties.create(PlayerId::from(0)).unwrap();
+        for id in 1..MAX_PARTY_SIZE {
+            parties
+                .invite(party.id, PlayerId::from(id as u128))
+                .unwrap();
+        }
+
+        assert_eq!(
+            parties.invite(party.id, PlayerId::from(99)),
+            Err(PartyError::Full)
+        );
+    }
+}
+
+#[cfg(test)]
+mod leave {
+    use super::*;
+
+    #[test]
+    fn passes_the_lead_on_and_disbands_empty_parties() {
+        let parties = PartyRegistry::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+        let party = parties.create(a).unwrap();
+        parties.invite(party.id, b).unwrap();
+        parties.accept(party.id, b).unwrap();
+
+        let party = parties.leave(party.id, a).unwrap().unwrap();
+        assert_eq!(party.leader, b);
+        assert_eq!(parties.party_of(a), None);
+
+        assert_eq!(parties.leave(party.id, b), Ok(None));
+        assert_eq!(parties.get(party.id), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! Choosing which instance hosts a new room when several share the lobby
+//!
+//! Rooms are placed on a [HashRing] built from the instances' registrations, each instance
+//! getting points on the ring in proportion to the capacity it advertises. Every instance
+//! builds the same ring from the same registrations, and an instance joining or leaving only
+//! moves the rooms whose points it takes or gives up.
+//!
+//! Loads are bounded as well: walking the ring from a room's point, the room goes to the first
+//! instance that isn't [saturated][is_saturated] and whose load score isn't far above the
+//! average, so busy instances pass new rooms on to the next ones along.
+//!
+//! Rooms can be asked for in a region, in which case only the instances in that region are
+//! walked past and their loads averaged.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::cluster::NodeInfo;
+use crate::game::RoomId;
+use crate::load::is_saturated;
+
+/// How many rooms of advertised capacity earn an instance another point on the ring
+pub const ROOMS_PER_POINT: usize = 10;
+/// The most points an instance gets on the ring however much capacity it advertises
+pub const MAX_POINTS_PER_NODE: usize = 1024;
+/// How far above the average load score an instance can be and still be given new rooms,
+/// as a multiple of the average
+pub const LOAD_BOUND_FACTOR: f64 = 1.25;
+/// How far above the average load score an instance can be and still be given new rooms,
+/// on top of [LOAD_BOUND_FACTOR], so nearly idle instances aren't passed over for noise
+pub const LOAD_BOUND_SLACK: f64 = 0.05;
+
+/// Where a new room should go
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Placement {
+    Node(Uuid),
+    /// Every instance has used up some of its capacity
+    Saturated,
+    /// No instances are known, in the region if one was asked for
+    Unknown,
+}
+
+fn ring_position(key: &[u8]) -> u64 {
+    let digest = Sha256::digest(key);
+    u64::from_be_bytes(
+        digest[..8]
+            .try_into()
+            .expect("SHA-256 digests are 32 bytes"),
+    )
+}
+
+/// How many points an instance advertising `capacity` rooms gets on the ring
+fn points_for(capacity: usize) -> usize {
+    (capacity / ROOMS_PER_POINT).clamp(1, MAX_POINTS_PER_NODE)
+}
+
+/// A consistent hash ring mapping rooms to the instances that should host them
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct HashRing {
+    /// Positions on the ring and the instance each belongs to, in ring order
+    points: Vec<(u64, Uuid)>,
+    /// Each instance's load score as of its last heartbeat
+    loads: HashMap<Uuid, f64>,
+    /// The region of each instance that has been configured with one
+    regions: HashMap<Uuid, String>,
+}
+
+impl HashRing {
+    pub fn new<'a>(nodes: impl IntoIterator<Item = &'a NodeInfo>) -> Self {
+        let nodes: Vec<_> = nodes.into_iter().collect();
+        let mut points: Vec<_> = nodes
+            .iter()
+            .flat_map(|node| {
+                (0..points_for(node.capacity)).map(move |point| {
+                    (
+                        ring_position(format!("{}-{point}", node.id).as_bytes()),
+                        node.id,
+                    )
+                })
+            })
+            .collect();
+        points.sort_unstable();
+        Self {
+            points,
+            loads: nodes.iter().map(|node| (node.id, node.load)).collect(),
+            regions: nodes
+                .iter()
+                .filter_map(|node| Some((node.id, node.region.clone()?)))
+                .collect(),
+        }
+    }
+
+    /// Every instance on the ring, in the order they come after the room's point
+    fn successors(&self, room_id: RoomId) -> impl Iterator<Item = Uuid> + '_ {
+        let position = ring_position(room_id.to_string().as_bytes());
+        let index = self.points.partition_point(|&(point, _)| point < position);
+        let mut seen = Vec::with_capacity(self.loads.len());
+        self.points[index..]
+            .iter()
+            .chain(&self.points[..index])
+            .map(|&(_, node)| node)
+            .filter(move |node| {
+                let first = !seen.contains(node);
+                if first {
+                    seen.push(*node);
+                }
+                first
+            })
+    }
+
+    /// The instance whose point comes first after the room's, ignoring loads, `None` if the
+    /// ring is empty
+    pub fn node_for(&self, room_id: RoomId) -> Option<Uuid> {
+        self.successors(room_id).next()
+    }
+
+    /// The instance that should host the room given how busy each one is, out of those in
+    /// `region` if given, taking `fresh` as a more recent load score for one of them, usually
+    /// the instance placing the room
+    pub fn place(
+        &self,
+        room_id: RoomId,
+        region: Option<&str>,
+        fresh: Option<(Uuid, f64)>,
+    ) -> Placement {
+        let in_region = |node: &Uuid| {
+            region.is_none_or(|region| self.regions.get(node).map(String::as_str) == Some(region))
+        };
+        let candidates = self.loads.keys().filter(|node| in_region(node)).count();
+        if candidates == 0 {
+            return Placement::Unknown;
+        }
+        let load_of = |node: &Uuid| match fresh {
+            Some((fresh_node, load)) if fresh_node == *node => load,
+            _ => self.loads[node],
+        };
+        let average = self
+            .loads
+            .keys()
+            .filter(|node| in_region(node))
+            .map(load_of)
+            .sum::<f64>()
+            / candidates as f64;
+        let bound = average * LOAD_BOUND_FACTOR + LOAD_BOUND_SLACK;
+        // The least loaded instance is never above the average, so this only comes up empty
+        // when every instance is saturated
+        self.successors(room_id)
+            .filter(in_region)
+            .find(|node| {
+                let load = load_of(node);
+                !is_saturated(load) && load <= bound
+            })
+            .map_or(Placement::Saturated, Placement::Node)
+    }
+}
+
+#[cfg(test)]
+fn node(instance: u128, capacity: usize) -> NodeInfo {
+    NodeInfo {
+        id: Uuid::from_u128(instance),
+        public_url: None,
+        capacity,
+        load: 0.0,
+        region: None,
+        rooms: Vec::new(),
+        heartbeat_at: 0,
+    }
+}
+
+#[cfg(test)]
+fn loaded(instance: u128, load: f64) -> NodeInfo {
+    NodeInfo {
+        load,
+        ..node(instance, 1_000)
+    }
+}
+
+#[cfg(test)]
+fn in_region(instance: u128, region: &str) -> NodeInfo {
+    NodeInfo {
+        region: Some(region.to_owned()),
+        ..node(instance, 1_000)
+    }
+}
+
+#[cfg(test)]
+fn rooms() -> impl Iterator<Item = RoomId> {
+    (0..2_000_u128).map(RoomId::from)
+}
+
+#[cfg(test)]
+mod node_for {
+    use super::*;
+
+    #[test]
+    fn is_none_without_nodes() {
+        assert_eq!(HashRing::default().node_for(1_u128.into()), None);
+    }
+
+    #[test]
+    fn spreads_rooms_by_capacity() {
+        let ring = HashRing::new(&[node(1, 1_000), node(2, 3_000)]);
+
+        let on_first = rooms()
+            .filter(|&room| ring.node_for(room) == Some(Uuid::from_u128(1)))
+            .count();
+
+        // A quarter of the rooms, give or take
+        assert!(
+            (350..650).contains(&on_first),
+            "{on_first} rooms on the first node"
+        );
+    }
+
+    #[test]
+    fn only_moves_rooms_to_a_joining_node() {
+        let before = HashRing::new(&[node(1, 1_000), node(2, 1_000)]);
+        let after = HashRing::new(&[node(1, 1_000), node(2, 1_000), node(3, 1_000)]);
+
+        for room in rooms() {
+            let (was, is) = (before.node_for(room), after.node_for(room));
+            assert!(was == is || is == Some(Uuid::from_u128(3)));
+        }
+    }
+
+    #[test]
+    fn agrees_whatever_order_nodes_are_given_in() {
+        let ring = HashRing::new(&[node(1, 500), node(2, 700)]);
+        let reversed = HashRing::new(&[node(2, 700), node(1, 500)]);
+
+        assert_eq!(ring, reversed);
+    }
+}
+
+#[cfg(test)]
+mod place {
+    use super::*;
+
+    #[test]
+    fn follows_the_ring_while_loads_are_even() {
+        let ring = HashRing::new(&[loaded(1, 0.3), loaded(2, 0.3), loaded(3, 0.3)]);
+
+        for room in rooms().take(100) {
+            assert_eq!(
+                ring.place(room, None, None),
+                Placement::Node(ring.node_for(room).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn passes_over_instances_far_busier_than_average() {
+        let ring = HashRing::new(&[loaded(1, 0.9), loaded(2, 0.1), loaded(3, 0.2)]);
+
+        assert!(rooms()
+            .take(100)
+            .all(|room| ring.place(room, None, None) != Placement::Node(Uuid::from_u128(1))));
+    }
+
+    #[test]
+    fn passes_over_saturated_instances() {
+        let ring = HashRing::new(&[loaded(1, 1.0), loaded(2, 0.99)]);
+
+        assert!(rooms()
+            .take(100)
+            .all(|room| ring.place(room, None, None) == Placement::Node(Uuid::from_u128(2))));
+    }
+
+    #[test]
+    fn prefers_fresh_loads_to_reported_ones() {
+        let ring = HashRing::new(&[loaded(1, 0.1), loaded(2, 0.1)]);
+        let fresh = Some((Uuid::from_u128(1), 1.0));
+
+        assert!(rooms()
+            .take(100)
+            .all(|room| ring.place(room, None, fresh) == Placement::Node(Uuid::from_u128(2))));
+    }
+
+    #[test]
+    fn is_saturated_once_every_instance_is() {
+        let ring = HashRing::new(&[loaded(1, 1.0), loaded(2, 1.4)]);
+
+        assert_eq!(ring.place(1_u128.into(), None, None), Placement::Saturated);
+    }
+
+    #[test]
+    fn is_unknown_without_instances() {
+        assert_eq!(
+            HashRing::default().place(1_u128.into(), None, None),
+            Placement::Unknown
+        );
+    }
+
+    #[test]
+    fn keeps_to_the_region_asked_for() {
+        let ring = HashRing::new(&[
+            in_region(1, "eu-west"),
+            in_region(2, "us-east"),
+            in_region(3, "eu-west"),
+            node(4, 1_000),
+        ]);
+
+        assert!(rooms().take(100).all(|room| matches!(
+            ring.place(room, Some("eu-west"), None),
+            Placement::Node(node) if node == Uuid::from_u128(1) || node == Uuid::from_u128(3)
+        )));
+    }
+
+    #[test]
+    fn averages_loads_within_the_region() {
+        let ring = HashRing::new(&[
+            NodeInfo {
+                load: 0.9,
+                ..in_region(1, "eu-west")
+            },
+            NodeInfo {
+                load: 0.1,
+                ..in_region(2, "us-east")
+            },
+        ]);
+
+        assert_eq!(
+            ring.place(1_u128.into(), Some("eu-west"), None),
+            Placement::Node(Uuid::from_u128(1))
+        );
+    }
+
+    #[test]
+    fn is_unknown_without_instances_in_the_region() {
+        let ring = HashRing::new(&[in_region(1, "eu-west"), node(2, 1_000)]);
+
+        assert_eq!(
+            ring.place(1_u128.into(), Some("ap-south"), None),
+            Placement::Unknown
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Rich presence for integrations that show what players are up to, such as a "Join my
+//! game" button on a chat profile
+//!
+//! Every time a room's party or settings change, a [Presence] describing it in a standard
+//! shape is published on the [PresenceFeed], which [webhooks][crate::webhooks] deliver as
+//! `presence_updated` events.
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::game::{Room, RoomId};
+use crate::invites::{InviteSigner, DEFAULT_INVITE_TTL};
+
+/// How many presence updates a subscriber may fall behind by before it misses some
+pub const PRESENCE_CAPACITY: usize = 256;
+
+/// A room as rich presence integrations show it
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Presence {
+    pub room_id: RoomId,
+    /// The kind of game the server hosts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_name: Option<String>,
+    /// How many players are in the room
+    pub party_size: usize,
+    /// An invite to the room for the integration to hand to whoever clicks "Join"
+    pub join_secret: String,
+    /// When the join secret stops working, in seconds since the Unix epoch
+    pub join_secret_expires_at: u64,
+}
+
+/// Fans presence updates out to everyone publishing rich presence
+#[derive(Debug)]
+pub struct PresenceFeed {
+    sender: Sender<Presence>,
+    game_type: Option<String>,
+}
+
+impl PresenceFeed {
+    /// Creates a feed describing rooms as hosting `game_type`
+    pub fn new(game_type: Option<String>) -> Self {
+        Self {
+            sender: broadcast::channel(PRESENCE_CAPACITY).0,
+            game_type,
+        }
+    }
+
+    /// The room's presence as it is now, with a freshly minted join secret
+    pub fn presence_of(&self, room_id: RoomId, room: &Room, signer: &InviteSigner) -> Presence {
+        let invite = signer.mint(room_id, DEFAULT_INVITE_TTL, None);
+        Presence {
+            room_id,
+            game_type: self.game_type.clone(),
+            room_name: room.name(),
+            party_size: room.player_count(),
+            join_secret: invite.token,
+            join_secret_expires_at: invite.expires_at,
+        }
+    }
+
+    /// Publishes the room's presence to every current subscriber
+    pub fn publish(&self, room_id: RoomId, room: &Room, signer: &InviteSigner) {
+        // Don't bother minting a join secret nobody will see
+        if self.sender.receiver_count() > 0 {
+            let _ = self.sender.send(self.presence_of(room_id, room, signer));
+        }
+    }
+
+    /// Receives every presence update published from now on
+    pub fn subscribe(&self) -> Receiver<Presence> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for PresenceFeed {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod presence_of {
+    use super::*;
+    use crate::game::PlayerId;
+
+    #[test]
+    fn describes_the_room_with_a_working_join_secret() {
+        let feed = PresenceFeed::new(Some("chess".to_owned()));
+        let signer = InviteSigner::random();
+        let room_id = RoomId::from(1);
+        let room = Room::new();
+        room.set_name("Friday night".to_owned());
+        room.add_player(PlayerId::from(1), 1.into());
+        room.add_player(PlayerId::from(2), 2.into());
+
+        let presence = feed.presence_of(room_id, &room, &signer);
+
+        assert_eq!(presence.game_type.as_deref(), Some("chess"));
+        assert_eq!(presence.room_name.as_deref(), Some("Friday night"));
+        assert_eq!(presence.party_size, 2);
+        assert!(signer.verify(&presence.join_secret, room_id).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod publish {
+    use super::*;
+
+    #[test]
+    fn reaches_subscribers() {
+        let feed = PresenceFeed::default();
+        let mut subscriber = feed.subscribe();
+
+        feed.publish(RoomId::from(1), &Room::new(), &InviteSigner::random());
+
+        assert_eq!(subscriber.try_recv().unwrap().room_id, RoomId::from(1));
+    }
+}
@@ -0,0 +1,188 @@
+//! Messages exchanged with game clients over a room's WebSocket
+//!
+//! The messages themselves live in the `wormhole-protocol` crate so clients can share them.
+
+use actix_web::web::{BufMut, Bytes, BytesMut};
+use serde::Deserialize;
+
+pub use wormhole_protocol::{
+    ChatChannel, ChatMessage, ClientEnvelope, ClientMessage, ServerMessage, PROTOCOL_VERSION,
+};
+
+/// How messages are encoded on a connection, chosen with the `format` query parameter
+/// when joining a room
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    /// JSON text frames
+    #[default]
+    Json,
+    /// Protobuf binary frames
+    Protobuf,
+}
+
+const CHAT_FRAME_PREFIX: &[u8] = br#"{"type":"chat","#;
+
+/// Whether an already serialized [server message][ServerMessage] is a line of chat
+pub fn is_chat_frame(message: &[u8]) -> bool {
+    message.starts_with(CHAT_FRAME_PREFIX)
+}
+
+const REACTION_FRAME_PREFIX: &[u8] = br#"{"type":"reaction","#;
+
+/// Whether an already serialized [server message][ServerMessage] is a reaction
+pub fn is_reaction_frame(message: &[u8]) -> bool {
+    message.starts_with(REACTION_FRAME_PREFIX)
+}
+
+const ANNOUNCEMENT_FRAME_PREFIX: &[u8] = br#"{"type":"announcement","#;
+
+/// Whether an already serialized [server message][ServerMessage] is an announcement
+pub fn is_announcement_frame(message: &[u8]) -> bool {
+    message.starts_with(ANNOUNCEMENT_FRAME_PREFIX)
+}
+
+const CUSTOM_DATA_FRAME_PREFIX: &[u8] = br#"{"type":"custom_data","#;
+
+/// Whether an already serialized [server message][ServerMessage] is a room's custom data
+pub fn is_custom_data_frame(message: &[u8]) -> bool {
+    message.starts_with(CUSTOM_DATA_FRAME_PREFIX)
+}
+
+const KICK_VOTE_FRAME_PREFIX: &[u8] = br#"{"type":"kick_vote"#;
+
+/// Whether an already serialized [server message][ServerMessage] is about a vote to kick,
+/// either its tally or its end
+pub fn is_kick_vote_frame(message: &[u8]) -> bool {
+    message.starts_with(KICK_VOTE_FRAME_PREFIX)
+}
+
+const TEAMS_BALANCED_FRAME_PREFIX: &[u8] = br#"{"type":"teams_balanced","#;
+
+/// Whether an already serialized [server message][ServerMessage] is the teams a room's
+/// players were balanced across
+pub fn is_teams_balanced_frame(message: &[u8]) -> bool {
+    message.starts_with(TEAMS_BALANCED_FRAME_PREFIX)
+}
+
+const ENCODED_BROADCAST_FRAME_PREFIX: &[u8] = br#"{"type":"encoded_broadcast","#;
+
+/// Whether an already serialized [server message][ServerMessage] is a broadcast whose payload
+/// was encoded by its game type's codec
+pub fn is_encoded_broadcast_frame(message: &[u8]) -> bool {
+    message.starts_with(ENCODED_BROADCAST_FRAME_PREFIX)
+}
+
+const BATCH_FRAME_PREFIX: &[u8] = br#"{"type":"batch","messages":["#;
+const BATCH_FRAME_SUFFIX: &[u8] = b"]}";
+
+/// Combines already serialized [server messages][ServerMessage] into a single
+/// `{"type":"batch","messages":[...]}` frame without re-serializing them
+pub fn batch_frame(messages: &[Bytes]) -> Bytes {
+    let length = BATCH_FRAME_PREFIX.len()
+        + BATCH_FRAME_SUFFIX.len()
+        + messages
+            .iter()
+            .map(|message| message.len() + 1)
+            .sum::<usize>();
+    let mut frame = BytesMut::with_capacity(length);
+
+    frame.put_slice(BATCH_FRAME_PREFIX);
+    for (i, message) in messages.iter().enumerate() {
+        if i > 0 {
+            frame.put_u8(b',');
+        }
+        frame.put_slice(message);
+    }
+    frame.put_slice(BATCH_FRAME_SUFFIX);
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod batch_frame {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn wraps_messages_in_a_batch_envelope() {
+        let messages = [
+            Bytes::from_static(br#"{"type":"welcome","connection_id":1}"#),
+            Bytes::from_static(br#"{"type":"error","reason":"nope"}"#),
+        ];
+
+        let frame: Value = serde_json::from_slice(&batch_frame(&messages)).unwrap();
+        assert_eq!(
+            frame,
+            json!({
+                "type": "batch",
+                "messages": [
+                    { "type": "welcome", "connection_id": 1 },
+                    { "type": "error", "reason": "nope" },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn is_read_by_clients_as_a_batch_message() {
+        let messages = [Bytes::from_static(br#"{"type":"error","reason":"nope"}"#)];
+
+        assert_eq!(
+            serde_json::from_slice::<ServerMessage>(&batch_frame(&messages)).unwrap(),
+            ServerMessage::Batch {
+                messages: vec![ServerMessage::Error {
+                    reason: "nope".to_owned()
+                }]
+            }
+        );
+    }
+
+    #[test]
+    fn produces_empty_batch_for_no_messages() {
+        let frame: Value = serde_json::from_slice(&batch_frame(&[])).unwrap();
+        assert_eq!(frame, json!({ "type": "batch", "messages": [] }));
+    }
+}
+
+#[cfg(test)]
+mod is_chat_frame {
+    use super::*;
+    use crate::protocol::ChatMessage;
+
+    #[test]
+    fn recognizes_serialized_chat() {
+        let chat = serde_json::to_vec(&ServerMessage::Chat(ChatMessage {
+            from: 1.into(),
+            from_player: None,
+            text: "gg".to_owned(),
+            channel: ChatChannel::Room,
+            sent_at: 2,
+        }))
+        .unwrap();
+        let error = serde_json::to_vec(&ServerMessage::Error {
+            reason: "chat".to_owned(),
+        })
+        .unwrap();
+
+        assert!(is_chat_frame(&chat));
+        assert!(!is_chat_frame(&error));
+    }
+}
+
+#[cfg(test)]
+mod is_reaction_frame {
+    use super::*;
+
+    #[test]
+    fn recognizes_serialized_reactions() {
+        let reaction = serde_json::to_vec(&ServerMessage::Reaction {
+            from: 1.into(),
+            from_player: None,
+            emote: "gg".to_owned(),
+        })
+        .unwrap();
+
+        assert!(is_reaction_frame(&reaction));
+        assert!(!is_chat_frame(&reaction));
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{BucketStore, ClientKey, RouteBudget, TakeFuture};
+
+/// How many clients are tracked before idle ones start being forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets held in memory, covering only the requests made to this instance
+#[derive(Debug, Default)]
+pub struct LocalBuckets {
+    buckets: Mutex<HashMap<(RouteBudget, ClientKey), Bucket>>,
+}
+
+impl LocalBuckets {
+    /// Takes a token from the bucket of every key as of `now`
+    pub fn take_at(
+        &self,
+        budget: RouteBudget,
+        keys: &[ClientKey],
+        requests_per_minute: u32,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let capacity = f64::from(requests_per_minute);
+        let refill_per_second = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens
+                    + now.duration_since(bucket.updated_at).as_secs_f64() * refill_per_second
+                    < capacity
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let bucket = buckets.entry((budget, key.clone())).or_insert(Bucket {
+                tokens: capacity,
+                updated_at: now,
+            });
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+            bucket.updated_at = now;
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64(
+                    (1.0 - bucket.tokens) / refill_per_second,
+                ));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(&(budget, key.clone())) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BucketStore for LocalBuckets {
+    fn take<'a>(
+        &'a self,
+        budget: RouteBudget,
+        keys: &'a [ClientKey],
+        requests_per_minute: u32,
+    ) -> TakeFuture<'a> {
+        let result = self.take_at(budget, keys, requests_per_minute, Instant::now());
+        Box::pin(std::future::ready(result))
+    }
+}
+
+#[cfg(test)]
+fn address(ip: &str) -> ClientKey {
+    ClientKey::Address(ip.parse().unwrap())
+}
+
+#[cfg(test)]
+mod take_at {
+    use super::*;
+
+    #[test]
+    fn allows_bursts_up_to_the_budget() {
+        let buckets = LocalBuckets::default();
+        let now = Instant::now();
+        let keys = [address("10.0.0.1")];
+
+        for _ in 0..3 {
+            assert_eq!(
+                buckets.take_at(RouteBudget::RoomCreation, &keys, 3, now),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            buckets.take_at(RouteBudget::RoomCreation, &keys, 3, now),
+            Err(Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let buckets = LocalBuckets::default();
+        let now = Instant::now();
+        let keys = [address("10.0.0.1")];
+        for _ in 0..60 {
+            buckets
+                .take_at(RouteBudget::RoomCreation, &keys, 60, now)
+                .unwrap();
+        }
+
+        assert!(buckets
+            .take_at(RouteBudget::RoomCreation, &keys, 60, now)
+            .is_err());
+        assert_eq!(
+            buckets.take_at(
+                RouteBudget::RoomCreation,
+                &keys,
+                60,
+                now + Duration::from_secs(1)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn keeps_separate_budgets_per_route_and_client() {
+        let buckets = LocalBuckets::default();
+        let now = Instant::now();
+
+        buckets
+            .take_at(RouteBudget::RoomCreation, &[address("10.0.0.1")], 1, now)
+            .unwrap();
+        assert!(buckets
+            .take_at(RouteBudget::General, &[address("10.0.0.1")], 1, now)
+            .is_ok());
+        assert!(buckets
+            .take_at(RouteBudget::RoomCreation, &[address("10.0.0.2")], 1, now)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_when_any_key_is_exhausted() {
+        let buckets = LocalBuckets::default();
+        let now = Instant::now();
+        let token = ClientKey::Token([0; 32]);
+
+        buckets
+            .take_at(
+                RouteBudget::RoomCreation,
+                &[address("10.0.0.1"), token.clone()],
+                1,
+                now,
+            )
+            .unwrap();
+        assert!(buckets
+            .take_at(
+                RouteBudget::RoomCreation,
+                &[address("10.0.0.2"), token],
+                1,
+                now
+            )
+            .is_err());
+    }
+}
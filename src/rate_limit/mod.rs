@@ -0,0 +1,258 @@
+//! Per-client request budgets for the REST API, room chat and reactions
+//!
+//! Each client's remaining requests are held in token buckets kept by a [BucketStore]:
+//! [LocalBuckets] in memory when an instance runs on its own, or [RedisBuckets] in the
+//! Redis server instances share, so a client's budget covers every instance it calls.
+
+mod local;
+mod shared;
+
+pub use local::*;
+pub use shared::*;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::auth::bearer_token;
+use crate::game::PlayerId;
+use crate::problem::Problem;
+use crate::SharedAppState;
+
+/// A group of routes that share a request budget
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum RouteBudget {
+    RoomCreation,
+    /// Every other route, such as listing rooms
+    General,
+    /// Chat messages sent over rooms' WebSockets
+    Chat,
+    /// Reactions sent over rooms' WebSockets
+    Reactions,
+}
+
+impl RouteBudget {
+    pub const ALL: [RouteBudget; 4] = [
+        RouteBudget::RoomCreation,
+        RouteBudget::General,
+        RouteBudget::Chat,
+        RouteBudget::Reactions,
+    ];
+
+    fn for_request(method: &Method, path: &str) -> Self {
+        if *method == Method::POST && path.trim_end_matches('/').ends_with("/rooms") {
+            RouteBudget::RoomCreation
+        } else {
+            RouteBudget::General
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RouteBudget::RoomCreation => "room_creation",
+            RouteBudget::General => "general",
+            RouteBudget::Chat => "chat",
+            RouteBudget::Reactions => "reactions",
+        }
+    }
+}
+
+/// Who a request is charged to
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum ClientKey {
+    Address(IpAddr),
+    /// The SHA-256 hash of the client's bearer token
+    Token([u8; 32]),
+    /// The authenticated player behind a WebSocket
+    Player(PlayerId),
+    /// A WebSocket with neither a player nor an address to charge it to
+    Connection(Uuid),
+}
+
+pub type TakeFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Duration>> + Send + 'a>>;
+
+/// Keeps each client's token buckets, refilled continuously so a client may make up to its
+/// budget's requests per minute in bursts
+pub trait BucketStore: std::fmt::Debug + Send + Sync {
+    /// Takes a token from the bucket of every key if each has one, or returns how long to
+    /// wait until they all do, for a budget of `requests_per_minute`
+    fn take<'a>(
+        &'a self,
+        budget: RouteBudget,
+        keys: &'a [ClientKey],
+        requests_per_minute: u32,
+    ) -> TakeFuture<'a>;
+}
+
+/// Charges requests to clients' budgets, counting the requests turned away
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_minute: HashMap<RouteBudget, u32>,
+    store: Box<dyn BucketStore>,
+    rejections: HashMap<RouteBudget, AtomicU64>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing each client the given number of requests per minute,
+    /// where zero leaves the routes unlimited, keeping budgets in memory
+    pub fn new(
+        room_creation_per_minute: u32,
+        general_per_minute: u32,
+        chat_per_minute: u32,
+        reactions_per_minute: u32,
+    ) -> Self {
+        Self {
+            requests_per_minute: HashMap::from([
+                (RouteBudget::RoomCreation, room_creation_per_minute),
+                (RouteBudget::General, general_per_minute),
+                (RouteBudget::Chat, chat_per_minute),
+                (RouteBudget::Reactions, reactions_per_minute),
+            ]),
+            store: Box::new(LocalBuckets::default()),
+            rejections: RouteBudget::ALL
+                .into_iter()
+                .map(|budget| (budget, AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Keeps budgets in `store` instead, such as one shared with other instances
+    pub fn with_store(mut self, store: impl BucketStore + 'static) -> Self {
+        self.store = Box::new(store);
+        self
+    }
+
+    /// Charges a request to every client key, or returns how long to wait before
+    /// retrying if any of them has exhausted its budget
+    pub(crate) async fn check(
+        &self,
+        budget: RouteBudget,
+        keys: &[ClientKey],
+    ) -> Result<(), Duration> {
+        let limit = self.requests_per_minute[&budget];
+        if limit == 0 {
+            return Ok(());
+        }
+        let result = self.store.take(budget, keys, limit).await;
+        if result.is_err() {
+            self.rejections[&budget].fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// The number of requests turned away for exceeding the budget
+    pub fn rejection_count(&self, budget: RouteBudget) -> u64 {
+        self.rejections[&budget].load(Ordering::Relaxed)
+    }
+}
+
+fn client_keys(req: &ServiceRequest) -> Vec<ClientKey> {
+    let mut keys = Vec::with_capacity(2);
+    if let Some(address) = req.peer_addr() {
+        keys.push(ClientKey::Address(address.ip()));
+    }
+    if let Some(token) = bearer_token(req) {
+        keys.push(ClientKey::Token(Sha256::digest(token.as_bytes()).into()));
+    }
+    keys
+}
+
+/// Turns away requests from clients that have used up the budget of the route they're calling,
+/// counting both the client's address and its bearer token, with a `429` saying when to retry
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if let Some(state) = req.app_data::<web::Data<SharedAppState>>() {
+        let budget = RouteBudget::for_request(req.method(), req.path());
+        if let Err(wait) = state.rate_limiter.check(budget, &client_keys(&req)).await {
+            debug!(
+                event = "request_rate_limited",
+                budget = budget.name(),
+                path = req.path()
+            );
+            return Err(Problem::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "/problems/rate-limited",
+                "Too many requests",
+            )
+            .with_detail(format!(
+                "The {} request budget has been used up",
+                budget.name()
+            ))
+            .with_retry_after(wait)
+            .into());
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod check {
+    use super::*;
+
+    fn address() -> [ClientKey; 1] {
+        [ClientKey::Address("10.0.0.1".parse().unwrap())]
+    }
+
+    #[actix_web::test]
+    async fn counts_rejections_per_route() {
+        let limiter = RateLimiter::new(1, 0, 0, 0);
+
+        assert_eq!(
+            limiter.check(RouteBudget::RoomCreation, &address()).await,
+            Ok(())
+        );
+        assert!(limiter
+            .check(RouteBudget::RoomCreation, &address())
+            .await
+            .is_err());
+        assert_eq!(limiter.rejection_count(RouteBudget::RoomCreation), 1);
+        assert_eq!(limiter.rejection_count(RouteBudget::General), 0);
+    }
+
+    #[actix_web::test]
+    async fn leaves_routes_with_zero_budget_unlimited() {
+        let limiter = RateLimiter::new(0, 0, 0, 0);
+        for _ in 0..100 {
+            assert!(limiter
+                .check(RouteBudget::General, &address())
+                .await
+                .is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod for_request {
+    use super::*;
+
+    #[test]
+    fn charges_room_creation_separately() {
+        assert_eq!(
+            RouteBudget::for_request(&Method::POST, "/api/v1/rooms/"),
+            RouteBudget::RoomCreation
+        );
+        assert_eq!(
+            RouteBudget::for_request(&Method::GET, "/api/v1/rooms/"),
+            RouteBudget::General
+        );
+        assert_eq!(
+            RouteBudget::for_request(&Method::POST, "/api/v1/bans/"),
+            RouteBudget::General
+        );
+    }
+}
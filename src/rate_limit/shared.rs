@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use redis::aio::ConnectionManager;
+use redis::{RedisResult, Script};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use super::{BucketStore, ClientKey, LocalBuckets, RouteBudget, TakeFuture};
+
+/// The prefix of the Redis hashes holding each client's token buckets
+pub const RATE_LIMIT_KEY_PREFIX: &str = "wormhole:rate_limit:";
+
+/// Refills and takes a token from every bucket named in `KEYS` atomically, returning 0 if
+/// they all had one, or else how many milliseconds until they all will
+///
+/// `ARGV` holds the buckets' capacity and how many tokens they gain each millisecond. Time is
+/// read from the Redis server so instances with drifting clocks agree.
+const TAKE_SCRIPT: &str = r"
+local capacity = tonumber(ARGV[1])
+local refill = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local tokens = {}
+local wait = 0
+for i, key in ipairs(KEYS) do
+    local bucket = redis.call('HMGET', key, 'tokens', 'updated_at')
+    tokens[i] = capacity
+    if bucket[1] then
+        tokens[i] = math.min(capacity, tonumber(bucket[1]) + (now - tonumber(bucket[2])) * refill)
+    end
+    if tokens[i] < 1 then
+        wait = math.max(wait, math.ceil((1 - tokens[i]) / refill))
+    end
+end
+if wait > 0 then
+    return wait
+end
+local ttl = math.ceil(capacity / refill)
+for i, key in ipairs(KEYS) do
+    redis.call('HSET', key, 'tokens', tokens[i] - 1, 'updated_at', now)
+    redis.call('PEXPIRE', key, ttl)
+end
+return 0
+";
+
+/// The Redis hash holding the client's bucket for the budget
+fn bucket_key(budget: RouteBudget, key: &ClientKey) -> String {
+    match key {
+        ClientKey::Address(address) => {
+            format!("{RATE_LIMIT_KEY_PREFIX}{}:ip:{address}", budget.name())
+        }
+        ClientKey::Token(hash) => format!(
+            "{RATE_LIMIT_KEY_PREFIX}{}:token:{}",
+            budget.name(),
+            hex::encode(hash)
+        ),
+        ClientKey::Player(id) => {
+            format!("{RATE_LIMIT_KEY_PREFIX}{}:player:{id}", budget.name())
+        }
+        ClientKey::Connection(id) => {
+            format!("{RATE_LIMIT_KEY_PREFIX}{}:connection:{id}", budget.name())
+        }
+    }
+}
+
+/// Token buckets held in the Redis server instances share, so a client's budget covers
+/// every instance it calls
+///
+/// While Redis can't be reached, requests are charged to buckets held in memory instead, so
+/// budgets are still enforced on each instance on its own.
+pub struct RedisBuckets {
+    client: redis::Client,
+    connection: OnceCell<ConnectionManager>,
+    script: Script,
+    fallback: LocalBuckets,
+}
+
+impl std::fmt::Debug for RedisBuckets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisBuckets")
+            .field("connected", &self.connection.initialized())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisBuckets {
+    pub fn new(client: redis::Client) -> Self {
+        Self {
+            client,
+            connection: OnceCell::new(),
+            script: Script::new(TAKE_SCRIPT),
+            fallback: LocalBuckets::default(),
+        }
+    }
+
+    async fn take_shared(
+        &self,
+        budget: RouteBudget,
+        keys: &[ClientKey],
+        requests_per_minute: u32,
+    ) -> RedisResult<u64> {
+        let mut connection = self
+            .connection
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?
+            .clone();
+        let capacity = f64::from(requests_per_minute);
+        let mut invocation = self.script.prepare_invoke();
+        for key in keys {
+            invocation.key(bucket_key(budget, key));
+        }
+        invocation
+            .arg(capacity)
+            .arg(capacity / 60_000.0)
+            .invoke_async(&mut connection)
+            .await
+    }
+}
+
+impl BucketStore for RedisBuckets {
+    fn take<'a>(
+        &'a self,
+        budget: RouteBudget,
+        keys: &'a [ClientKey],
+        requests_per_minute: u32,
+    ) -> TakeFuture<'a> {
+        Box::pin(async move {
+            match self.take_shared(budget, keys, requests_per_minute).await {
+                Ok(0) => Ok(()),
+                Ok(wait_ms) => Err(Duration::from_millis(wait_ms)),
+                Err(e) => {
+                    warn!(event = "rate_limit_store_unavailable", error = %e);
+                    self.fallback.take(budget, keys, requests_per_minute).await
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod bucket_key {
+    use super::*;
+
+    #[test]
+    fn names_the_budget_and_client() {
+        assert_eq!(
+            bucket_key(
+                RouteBudget::RoomCreation,
+                &ClientKey::Address("10.0.0.1".parse().unwrap())
+            ),
+            "wormhole:rate_limit:room_creation:ip:10.0.0.1"
+        );
+        assert_eq!(
+            bucket_key(RouteBudget::General, &ClientKey::Token([0xab; 32])),
+            format!("wormhole:rate_limit:general:token:{}", "ab".repeat(32))
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Skill ratings of players in each game type, kept up to date from the results of their games
+//!
+//! Ratings follow the Elo system, extended to games with more than two sides by treating a
+//! [result][GameResult] as every pair of players in different places having played each
+//! other: a player's rating moves by [ELO_K_FACTOR] times the average of how much better or
+//! worse they did against each opponent than their ratings predicted. Players on the same
+//! team, or tied, share a place and aren't compared with each other.
+//!
+//! Ratings are kept in memory, so they start over when the server restarts.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::game::PlayerId;
+use crate::matchmaking::is_valid_game_type;
+
+/// The rating players start at in every game type
+pub const INITIAL_RATING: f64 = 1500.0;
+
+/// The most a player's rating can move after one game
+pub const ELO_K_FACTOR: f64 = 32.0;
+
+/// The outcome of a game, reported once it's over
+#[derive(Debug, PartialEq, Clone, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GameResult {
+    pub game_type: String,
+    /// The players in each place, from first to last, with teammates and tied players
+    /// sharing a place
+    pub standings: Vec<Vec<PlayerId>>,
+}
+
+/// Raised when a result can't be rated
+#[derive(Error, Debug, PartialEq)]
+pub enum GameResultError {
+    #[error("Game types must be between 1 and 32 characters")]
+    InvalidGameType,
+    #[error("A game needs players in at least two places to be rated")]
+    TooFewPlaces,
+    #[error("{0} is in more than one place")]
+    DuplicatePlayer(PlayerId),
+}
+
+impl GameResult {
+    fn check(&self) -> Result<(), GameResultError> {
+        if !is_valid_game_type(&self.game_type) {
+            return Err(GameResultError::InvalidGameType);
+        }
+        if self
+            .standings
+            .iter()
+            .filter(|place| !place.is_empty())
+            .count()
+            < 2
+        {
+            return Err(GameResultError::TooFewPlaces);
+        }
+        let mut seen = HashSet::new();
+        match self
+            .standings
+            .iter()
+            .flatten()
+            .find(|&&player| !seen.insert(player))
+        {
+            Some(&player) => Err(GameResultError::DuplicatePlayer(player)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A player's rating in one game type
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, ToSchema)]
+pub struct Rating {
+    pub rating: f64,
+    /// How many rated games the rating is based on
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+/// The score a player rated `rating` is expected to get against one rated `opponent`, from 0
+/// for a certain loss to 1 for a certain win
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+/// Every player's rating in each game type they've played
+#[derive(Debug, Default)]
+pub struct RatingBook {
+    ratings: RwLock<HashMap<String, HashMap<PlayerId, Rating>>>,
+}
+
+impl RatingBook {
+    /// The player's rating in the game type, [INITIAL_RATING] if they haven't played it
+    pub fn rating(&self, game_type: &str, player_id: PlayerId) -> Rating {
+        let ratings = self.ratings.read().unwrap();
+        ratings
+            .get(game_type)
+            .and_then(|players| players.get(&player_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The average rating of the party's players in the game type
+    pub fn party_rating(&self, game_type: &str, players: &[PlayerId]) -> f64 {
+        if players.is_empty() {
+            return INITIAL_RATING;
+        }
+        let total: f64 = players
+            .iter()
+            .map(|&player_id| self.rating(game_type, player_id).rating)
+            .sum();
+        total / players.len() as f64
+    }
+
+    /// The player's rating in every game type they've played, by game type
+    pub fn ratings_of(&self, player_id: PlayerId) -> HashMap<String, Rating> {
+        let ratings = self.ratings.read().unwrap();
+        ratings
+            .iter()
+            .filter_map(|(game_type, players)| Some((game_type.clone(), *players.get(&player_id)?)))
+            .collect()
+    }
+
+    /// Updates the ratings of the game's players, returning their new ratings in the order
+    /// they placed
+    pub fn record(&self, result: &GameResult) -> Result<Vec<(PlayerId, Rating)>, GameResultError> {
+        result.check()?;
+        let mut ratings = self.ratings.write().unwrap();
+        let players = ratings.entry(result.game_type.clone()).or_default();
+
+        let before: Vec<Vec<f64>> = result
+            .standings
+            .iter()
+            .map(|place| {
+                place
+                    .iter()
+                    .map(|player| players.get(player).copied().unwrap_or_default().rating)
+                    .collect()
+            })
+            .collect();
+        let mut updated = Vec::new();
+        for (place, place_players) in result.standings.iter().enumerate() {
+            for (i, &player_id) in place_players.iter().enumerate() {
+                let rating = before[place][i];
+                let mut surprise = 0.0;
+                let mut opponents = 0;
+                for (other_place, others) in before.iter().enumerate() {
+                    if other_place == place {
+                        continue;
+                    }
+                    let score = if other_place > place { 1.0 } else { 0.0 };
+                    for &opponent in others {
+                        surprise += score - expected_score(rating, opponent);
+                        opponents += 1;
+                    }
+                }
+                let entry = players.entry(player_id).or_default();
+                entry.rating = rating + ELO_K_FACTOR * surprise / opponents as f64;
+                entry.games += 1;
+                updated.push((player_id, *entry));
+            }
+        }
+        info!(
+            event = "game_rated",
+            game_type = result.game_type,
+            players = updated.len()
+        );
+        Ok(updated)
+    }
+}
+
+#[cfg(test)]
+mod expected_score {
+    use super::*;
+
+    #[test]
+    fn favours_the_higher_rated_player() {
+        assert_eq!(expected_score(1500.0, 1500.0), 0.5);
+        assert!((expected_score(1900.0, 1500.0) - 0.909).abs() < 0.001);
+        assert!((expected_score(1500.0, 1900.0) - 0.091).abs() < 0.001);
+    }
+}
+
+#[cfg(test)]
+mod record {
+    use super::*;
+
+    fn result(standings: Vec<Vec<PlayerId>>) -> GameResult {
+        GameResult {
+            game_type: "duel".into(),
+            standings,
+        }
+    }
+
+    #[test]
+    fn moves_evenly_rated_players_by_half_the_k_factor() {
+        let book = RatingBook::default();
+        let [winner, loser] = [1, 2].map(PlayerId::from);
+
+        book.record(&result(vec![vec![winner], vec![loser]]))
+            .unwrap();
+
+        assert_eq!(
+            book.rating("duel", winner),
+            Rating {
+                rating: INITIAL_RATING + ELO_K_FACTOR / 2.0,
+                games: 1
+            }
+        );
+        assert_eq!(
+            book.rating("duel", loser).rating,
+            INITIAL_RATING - ELO_K_FACTOR / 2.0
+        );
+        assert_eq!(book.rating("race", winner), Rating::default());
+    }
+
+    #[test]
+    fn keeps_teammates_together() {
+        let book = RatingBook::default();
+        let [a, b, c, d] = [1, 2, 3, 4].map(PlayerId::from);
+
+        let updated = book.record(&result(vec![vec![a, b], vec![c, d]])).unwrap();
+
+        assert_eq!(updated.len(), 4);
+        assert_eq!(book.rating("duel", a), book.rating("duel", b));
+        assert_eq!(book.party_rating("duel", &[a, c]), INITIAL_RATING);
+    }
+
+    #[test]
+    fn rejects_games_that_cant_be_rated() {
+        let book = RatingBook::default();
+        let [a, b] = [1, 2].map(PlayerId::from);
+
+        assert_eq!(
+            book.record(&result(vec![vec![a, b], vec![]])),
+            Err(GameResultError::TooFewPlaces)
+        );
+        assert_eq!(
+            book.record(&result(vec![vec![a], vec![b, a]])),
+            Err(GameResultError::DuplicatePlayer(a))
+        );
+        assert!(book.ratings_of(a).is_empty());
+    }
+}
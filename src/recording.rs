@@ -0,0 +1,687 @@
+//! Recordings of the protocol traffic in rooms, for reproducing bugs and catching regressions
+//!
+//! When the server is given a directory to record to, every message each connection
+//! receives from its client and sends back is written there as it happens, one [frame]
+//! [RecordedFrame] per line. A recording starts when the first connection joins a room and
+//! ends once the last one leaves, so a room that empties out and fills up again is recorded
+//! twice, in files named after the room and when each recording started.
+//!
+//! Recordings are anonymized as they're made: player IDs are swapped for pseudonyms that are
+//! consistent within a recording, and invite tokens are redacted. Broadcast payloads and chat
+//! are kept as they are, since they're what reproduces a bug.
+//!
+//! With the `test-support` feature a recording can be [replayed][replay] against a
+//! [TestServer], and what each connection was sent [compared][compare] with what it was sent
+//! when it was recorded, to check that a change to the server leaves its behavior alone.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::clock::unix_time_ms;
+use crate::game::{ConnectionId, RoomId};
+
+/// How many frames can be waiting to be written before more are dropped
+pub const RECORDING_QUEUE_CAPACITY: usize = 4096;
+
+/// The fields holding player IDs, which are swapped for pseudonyms
+const PLAYER_ID_KEYS: [&str; 5] = ["player_id", "from_player", "sender", "from", "whisper"];
+
+/// The fields holding secrets, which are redacted
+const SECRET_KEYS: [&str; 1] = ["token"];
+
+/// The fields holding wall clock times, which differ between a recording and its replays
+const VOLATILE_KEYS: [&str; 2] = ["sent_at", "expires_at"];
+
+/// Something that happened on a connection to a recorded room
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Milliseconds since the recording started
+    pub at_ms: u64,
+    pub connection: ConnectionId,
+    #[serde(flatten)]
+    pub event: RecordedEvent,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Joined,
+    Left,
+    /// A message from the client, as a string if it wasn't valid JSON
+    Received {
+        message: Value,
+    },
+    /// A message, or batch of messages, to the client
+    Sent {
+        message: Value,
+    },
+}
+
+/// Swaps player IDs for pseudonyms and redacts secrets, throughout a message
+#[derive(Debug, Default)]
+struct Anonymizer {
+    pseudonyms: HashMap<String, String>,
+}
+
+impl Anonymizer {
+    fn anonymize(&mut self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    if SECRET_KEYS.contains(&key.as_str()) {
+                        *value = Value::String("redacted".to_owned());
+                    } else if let (true, Value::String(id)) =
+                        (PLAYER_ID_KEYS.contains(&key.as_str()), &value)
+                    {
+                        let next = self.pseudonyms.len() as u128 + 1;
+                        let pseudonym = self
+                            .pseudonyms
+                            .entry(id.clone())
+                            .or_insert_with(|| Uuid::from_u128(next).to_string());
+                        *value = Value::String(pseudonym.clone());
+                    } else {
+                        self.anonymize(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.anonymize(value)),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    started: Instant,
+    file_name: String,
+    connections: usize,
+    anonymizer: Anonymizer,
+}
+
+#[derive(Debug)]
+struct Line {
+    file_name: String,
+    frame: RecordedFrame,
+    /// Whether it's the last line of its recording
+    last: bool,
+}
+
+/// Records the traffic in rooms, handing frames off to a [RecordingWriter] so connections
+/// never wait on the disk
+#[derive(Debug)]
+pub struct Recorder {
+    sender: Sender<Line>,
+    sessions: Mutex<HashMap<RoomId, Session>>,
+    dropped: AtomicU64,
+}
+
+/// Writes recorded frames to files in a directory
+#[derive(Debug)]
+pub struct RecordingWriter {
+    dir: PathBuf,
+    receiver: Receiver<Line>,
+}
+
+/// Creates a recorder that holds up to `capacity` frames along with the writer that writes
+/// them to `dir`
+pub fn recording_channel(dir: PathBuf, capacity: usize) -> (Recorder, RecordingWriter) {
+    let (sender, receiver) = channel(capacity.max(1));
+    let recorder = Recorder {
+        sender,
+        sessions: Default::default(),
+        dropped: AtomicU64::new(0),
+    };
+    (recorder, RecordingWriter { dir, receiver })
+}
+
+impl Recorder {
+    /// Records something that happened on one of the room's connections, starting a recording
+    /// if it's the first to join and ending it if it's the last to leave
+    pub fn record(&self, room_id: RoomId, connection: ConnectionId, mut event: RecordedEvent) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = match event {
+            RecordedEvent::Joined => sessions.entry(room_id).or_insert_with(|| Session {
+                started: Instant::now(),
+                file_name: format!("{room_id}-{}.jsonl", unix_time_ms()),
+                connections: 0,
+                anonymizer: Default::default(),
+            }),
+            // Connections that joined before recording started aren't recorded
+            _ => match sessions.get_mut(&room_id) {
+                Some(session) => session,
+                None => return,
+            },
+        };
+        match &mut event {
+            RecordedEvent::Joined => session.connections += 1,
+            RecordedEvent::Left => session.connections = session.connections.saturating_sub(1),
+            RecordedEvent::Received { message } | RecordedEvent::Sent { message } => {
+                session.anonymizer.anonymize(message)
+            }
+        }
+        let last = session.connections == 0;
+        let line = Line {
+            file_name: session.file_name.clone(),
+            frame: RecordedFrame {
+                at_ms: session.started.elapsed().as_millis() as u64,
+                connection,
+                event,
+            },
+            last,
+        };
+        if last {
+            sessions.remove(&room_id);
+        }
+        drop(sessions);
+
+        match self.sender.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(event = "recorded_frame_dropped", %room_id, dropped);
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// How many frames were dropped because the writer had fallen behind
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl RecordingWriter {
+    /// Writes frames as they arrive until the [Recorder] is dropped, blocking the thread it
+    /// runs on
+    pub fn watch(mut self) {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            warn!(event = "recording_dir_unavailable", dir = %self.dir.display(), error = %e);
+            return;
+        }
+        let mut files: HashMap<String, BufWriter<File>> = HashMap::new();
+        while let Some(line) = self.receiver.blocking_recv() {
+            let mut batch = vec![line];
+            while let Ok(line) = self.receiver.try_recv() {
+                batch.push(line);
+            }
+            for line in batch {
+                if let Err(e) = self.write(&mut files, &line) {
+                    warn!(event = "recording_write_failed", file = line.file_name, error = %e);
+                    files.remove(&line.file_name);
+                }
+            }
+            for file in files.values_mut() {
+                if let Err(e) = file.flush() {
+                    warn!(event = "recording_write_failed", error = %e);
+                }
+            }
+        }
+        info!(event = "recording_writer_stopped");
+    }
+
+    fn write(&self, files: &mut HashMap<String, BufWriter<File>>, line: &Line) -> io::Result<()> {
+        let file = match files.get_mut(&line.file_name) {
+            Some(file) => file,
+            None => {
+                let file = File::options()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(&line.file_name))?;
+                files
+                    .entry(line.file_name.clone())
+                    .or_insert(BufWriter::new(file))
+            }
+        };
+        serde_json::to_writer(&mut *file, &line.frame)?;
+        file.write_all(b"\n")?;
+        if line.last {
+            files.remove(&line.file_name).map(|mut file| file.flush());
+        }
+        Ok(())
+    }
+}
+
+/// Reads the frames of a recording written by a [RecordingWriter]
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordedFrame>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Where the messages a connection was sent in a replay first differ from the recording
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub connection: ConnectionId,
+    /// The position of the first message that differs, counting the messages in batches
+    /// one by one
+    pub index: usize,
+    /// The message sent in the recording, `None` if the replay was sent more messages
+    pub recorded: Option<Value>,
+    /// The message sent in the replay, `None` if it was sent fewer messages
+    pub replayed: Option<Value>,
+}
+
+/// Takes the messages each connection was sent apart from their batches, leaving out the wall
+/// clock times that always differ between runs
+fn sent_messages(frames: &[RecordedFrame]) -> HashMap<ConnectionId, Vec<Value>> {
+    fn flatten(mut message: Value, into: &mut Vec<Value>) {
+        if message["type"] == "batch" {
+            if let Value::Array(messages) = message["messages"].take() {
+                messages
+                    .into_iter()
+                    .for_each(|message| flatten(message, into));
+            }
+            return;
+        }
+        strip_volatile(&mut message);
+        into.push(message);
+    }
+
+    fn strip_volatile(value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                fields.retain(|key, _| !VOLATILE_KEYS.contains(&key.as_str()));
+                fields.values_mut().for_each(strip_volatile);
+            }
+            Value::Array(values) => values.iter_mut().for_each(strip_volatile),
+            _ => {}
+        }
+    }
+
+    let mut sent: HashMap<_, Vec<_>> = HashMap::new();
+    for frame in frames {
+        if let RecordedEvent::Sent { message } = &frame.event {
+            flatten(message.clone(), sent.entry(frame.connection).or_default());
+        }
+    }
+    sent
+}
+
+/// Finds, for each connection, where what it was sent in `replayed` first differs from what
+/// it was sent in `recorded`, ignoring timing and wall clock times
+pub fn compare(recorded: &[RecordedFrame], replayed: &[RecordedFrame]) -> Vec<Divergence> {
+    let recorded = sent_messages(recorded);
+    let mut replayed = sent_messages(replayed);
+    let mut connections: Vec<_> = recorded.keys().chain(replayed.keys()).copied().collect();
+    connections.sort_by_key(|&connection| u64::from(connection));
+    connections.dedup();
+
+    connections
+        .into_iter()
+        .filter_map(|connection| {
+            let expected = recorded.get(&connection).map_or(&[][..], Vec::as_slice);
+            let actual = replayed.remove(&connection).unwrap_or_default();
+            let index = (0..expected.len().max(actual.len()))
+                .find(|&i| expected.get(i) != actual.get(i))?;
+            Some(Divergence {
+                connection,
+                index,
+                recorded: expected.get(index).cloned(),
+                replayed: actual.get(index).cloned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "test-support")]
+pub use replayer::replay;
+
+#[cfg(feature = "test-support")]
+mod replayer {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use tokio::time::Instant;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::{Anonymizer, RecordedEvent, RecordedFrame};
+    use crate::game::ConnectionId;
+    use crate::protocol::{ServerMessage, PROTOCOL_VERSION};
+    use crate::test_support::{TestClient, TestServer};
+
+    /// How long a connection must go without being sent anything before the replay stops
+    /// waiting for more
+    const QUIET_PERIOD: Duration = Duration::from_millis(100);
+
+    /// Takes what the client has been sent, as it would have been recorded
+    async fn drain(
+        client: &mut TestClient,
+        connection: ConnectionId,
+        start: Instant,
+        anonymizer: &mut Anonymizer,
+        into: &mut Vec<RecordedFrame>,
+    ) {
+        for message in client.drain(QUIET_PERIOD).await {
+            let mut message =
+                serde_json::to_value(message).expect("Server messages always serialize");
+            anonymizer.anonymize(&mut message);
+            into.push(RecordedFrame {
+                at_ms: start.elapsed().as_millis() as u64,
+                connection,
+                event: RecordedEvent::Sent { message },
+            });
+        }
+    }
+
+    /// Plays a recording back against a fresh room on the server, keeping to its timing, and
+    /// records what each connection is sent
+    ///
+    /// Every connection joins anonymously, speaking the protocol version it was welcomed
+    /// with, over JSON even if it was recorded speaking protobuf. Connections are numbered as
+    /// they were in the recording, so the two can be [compared][super::compare]. The time
+    /// each message is recorded at is when the replay got round to reading it.
+    pub async fn replay(server: &TestServer, recording: &[RecordedFrame]) -> Vec<RecordedFrame> {
+        let room_id = server.create_room().await;
+        let versions: HashMap<_, _> = recording
+            .iter()
+            .filter_map(|frame| match &frame.event {
+                RecordedEvent::Sent { message } if message["type"] == "welcome" => Some((
+                    frame.connection,
+                    message["protocol_version"].as_u64().unwrap_or(1),
+                )),
+                _ => None,
+            })
+            .collect();
+        let mut clients: HashMap<ConnectionId, TestClient> = HashMap::new();
+        let mut anonymizer = Anonymizer::default();
+        let mut replayed = Vec::new();
+        let start = Instant::now();
+
+        for frame in recording {
+            tokio::time::sleep_until(start + Duration::from_millis(frame.at_ms)).await;
+            let connection = frame.connection;
+            match &frame.event {
+                RecordedEvent::Joined => {
+                    let version = versions
+                        .get(&connection)
+                        .copied()
+                        .unwrap_or(u64::from(PROTOCOL_VERSION));
+                    let client = server
+                        .connect_with_query(room_id, &format!("protocol_version={version}"))
+                        .await;
+                    let mut welcome = serde_json::to_value(ServerMessage::Welcome {
+                        connection_id: client.connection_id(),
+                        protocol_version: version as u32,
+                        player_id: None,
+                    })
+                    .expect("Server messages always serialize");
+                    anonymizer.anonymize(&mut welcome);
+                    replayed.push(RecordedFrame {
+                        at_ms: start.elapsed().as_millis() as u64,
+                        connection,
+                        event: RecordedEvent::Joined,
+                    });
+                    replayed.push(RecordedFrame {
+                        at_ms: start.elapsed().as_millis() as u64,
+                        connection,
+                        event: RecordedEvent::Sent { message: welcome },
+                    });
+                    clients.insert(connection, client);
+                }
+                RecordedEvent::Received { message } => {
+                    let Some(client) = clients.get_mut(&connection) else {
+                        continue;
+                    };
+                    let text = match message {
+                        Value::String(text) => text.clone(),
+                        message => message.to_string(),
+                    };
+                    client.send_raw(Message::text(text)).await;
+                    replayed.push(RecordedFrame {
+                        at_ms: start.elapsed().as_millis() as u64,
+                        connection,
+                        event: frame.event.clone(),
+                    });
+                }
+                RecordedEvent::Left => {
+                    let Some(mut client) = clients.remove(&connection) else {
+                        continue;
+                    };
+                    drain(
+                        &mut client,
+                        connection,
+                        start,
+                        &mut anonymizer,
+                        &mut replayed,
+                    )
+                    .await;
+                    client.close().await;
+                    replayed.push(RecordedFrame {
+                        at_ms: start.elapsed().as_millis() as u64,
+                        connection,
+                        event: RecordedEvent::Left,
+                    });
+                }
+                RecordedEvent::Sent { .. } => {}
+            }
+        }
+
+        let mut connections: Vec<_> = clients.into_iter().collect();
+        connections.sort_by_key(|(connection, _)| u64::from(*connection));
+        for (connection, mut client) in connections {
+            drain(
+                &mut client,
+                connection,
+                start,
+                &mut anonymizer,
+                &mut replayed,
+            )
+            .await;
+            client.close().await;
+        }
+        replayed
+    }
+}
+
+#[cfg(test)]
+mod anonymize {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn swaps_player_ids_for_consistent_pseudonyms() {
+        let mut anonymizer = Anonymizer::default();
+        let alice = "6f9619ff-8b86-d011-b42d-00c04fc964ff";
+        let bob = "7f9619ff-8b86-d011-b42d-00c04fc964ff";
+        let mut message = json!({
+            "type": "invitation",
+            "from": alice,
+            "invite": { "token": "secret", "expires_at": 4 },
+            "messages": [{ "from": 1, "from_player": bob }, { "player_id": alice }],
+        });
+
+        anonymizer.anonymize(&mut message);
+
+        assert_eq!(
+            message,
+            json!({
+                "type": "invitation",
+                "from": Uuid::from_u128(1).to_string(),
+                "invite": { "token": "redacted", "expires_at": 4 },
+                "messages": [
+                    { "from": 1, "from_player": Uuid::from_u128(2).to_string() },
+                    { "player_id": Uuid::from_u128(1).to_string() },
+                ],
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod recorder {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn writes_a_recording_per_session_in_the_room() {
+        let dir = std::env::temp_dir().join(format!("wormhole-recording-{}", Uuid::new_v4()));
+        let (recorder, writer) = recording_channel(dir.clone(), 16);
+        let room_id = RoomId::from(1);
+        let message = json!({ "type": "broadcast", "payload": 1 });
+
+        recorder.record(room_id, 0.into(), RecordedEvent::Joined);
+        recorder.record(
+            room_id,
+            0.into(),
+            RecordedEvent::Received {
+                message: message.clone(),
+            },
+        );
+        recorder.record(room_id, 0.into(), RecordedEvent::Left);
+        // Nobody is in the room anymore, so this isn't recorded
+        recorder.record(room_id, 0.into(), RecordedEvent::Left);
+        drop(recorder);
+        writer.watch();
+
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let events: Vec<_> = read_recording(&files[0])
+            .unwrap()
+            .into_iter()
+            .map(|frame| frame.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                RecordedEvent::Joined,
+                RecordedEvent::Received { message },
+                RecordedEvent::Left
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod compare {
+    use super::*;
+    use serde_json::json;
+
+    fn sent(connection: u64, message: Value) -> RecordedFrame {
+        RecordedFrame {
+            at_ms: 0,
+            connection: connection.into(),
+            event: RecordedEvent::Sent { message },
+        }
+    }
+
+    #[test]
+    fn ignores_batching_and_wall_clock_times() {
+        let chat = |sent_at| json!({ "type": "chat", "text": "hi", "sent_at": sent_at });
+        let recorded = [sent(
+            0,
+            json!({ "type": "batch", "messages": [chat(1), chat(2)] }),
+        )];
+        let replayed = [sent(0, chat(3)), sent(0, chat(4))];
+
+        assert_eq!(compare(&recorded, &replayed), []);
+    }
+
+    #[test]
+    fn finds_where_each_connection_diverges() {
+        let recorded = [sent(0, json!(1)), sent(0, json!(2)), sent(1, json!(1))];
+        let replayed = [sent(0, json!(1)), sent(0, json!(3))];
+
+        assert_eq!(
+            compare(&recorded, &replayed),
+            [
+                Divergence {
+                    connection: 0.into(),
+                    index: 1,
+                    recorded: Some(json!(2)),
+                    replayed: Some(json!(3)),
+                },
+                Divergence {
+                    connection: 1.into(),
+                    index: 0,
+                    recorded: Some(json!(1)),
+                    replayed: None,
+                },
+            ]
+        );
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod replay {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::protocol::{ChatChannel, ClientMessage};
+    use crate::test_support::TestServer;
+
+    /// Waits for the writer to finish the room's only recording, once `connections` have left
+    async fn finished_recording(dir: &Path, connections: usize) -> Vec<RecordedFrame> {
+        for _ in 0..100 {
+            if let Some(Ok(file)) = fs::read_dir(dir).ok().and_then(|mut files| files.next()) {
+                let frames = read_recording(&file.path()).unwrap_or_default();
+                let left = frames
+                    .iter()
+                    .filter(|frame| frame.event == RecordedEvent::Left)
+                    .count();
+                if left == connections {
+                    return frames;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("The recording wasn't finished");
+    }
+
+    #[tokio::test]
+    async fn reproduces_what_each_connection_was_sent() {
+        let dir = std::env::temp_dir().join(format!("wormhole-recording-{}", Uuid::new_v4()));
+        let recorded = TestServer::builder()
+            .with_recording_dir(dir.clone())
+            .start()
+            .await;
+        let room_id = recorded.create_room().await;
+        let mut alice = recorded.connect(room_id).await;
+        let mut bob = recorded.connect(room_id).await;
+        alice
+            .send(ClientMessage::Broadcast {
+                payload: json!({ "move": "e4" }),
+            })
+            .await;
+        bob.send(ClientMessage::Chat {
+            text: "good luck".to_owned(),
+            channel: ChatChannel::Room,
+        })
+        .await;
+        alice.drain(Duration::from_millis(100)).await;
+        alice.close().await;
+        bob.drain(Duration::from_millis(100)).await;
+        bob.close().await;
+        let recording = finished_recording(&dir, 2).await;
+
+        let replayed = replay(&TestServer::start().await, &recording).await;
+
+        assert!(recording
+            .iter()
+            .any(|frame| matches!(&frame.event, RecordedEvent::Sent { message } if message["type"] == "chat")));
+        assert_eq!(compare(&recording, &replayed), []);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
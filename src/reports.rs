@@ -0,0 +1,567 @@
+//! Players' reports of other players, queued for moderators to review, act on and resolve
+//!
+//! Reports are held in memory and, when a reports file is configured, saved to it by
+//! [ReportQueue::run] soon after every change and read back from it on start, so the queue
+//! survives restarts. Resolved reports are dropped once they've been resolved for
+//! [RESOLVED_REPORT_RETENTION], so the file doesn't grow without bound.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::bans::BanId;
+use crate::clock::unix_time;
+use crate::files::write_atomically;
+use crate::game::{PlayerId, RoomId};
+use crate::protocol::ChatMessage;
+
+/// The most characters a report's reason may be
+pub const MAX_REPORT_REASON_CHARS: usize = 500;
+
+/// The most chat messages of the reported player captured with a report
+pub const CHAT_EXCERPT_LEN: usize = 20;
+
+/// How long resolved reports are kept for moderators to look back on
+pub const RESOLVED_REPORT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// An ID that identifies a [report][Report]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct ReportId(Uuid);
+
+impl std::fmt::Display for ReportId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Where a report is in the moderation queue
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    /// Waiting for a moderator
+    Open,
+    /// A moderator acted against the reported player, but hasn't resolved the report yet
+    Actioned,
+    /// Done with, whether or not anything was done about it
+    Resolved,
+}
+
+/// Something a moderator did to the reported player
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModerationAction {
+    Disconnected { closed_connections: usize },
+    Banned { ban_id: BanId },
+    DisplayNameWiped,
+}
+
+/// A [moderation action][ModerationAction] taken on a report
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TakenAction {
+    #[serde(flatten)]
+    pub action: ModerationAction,
+    pub actor: String,
+    /// Seconds since the Unix epoch
+    pub taken_at: u64,
+}
+
+/// How a report was resolved
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Resolution {
+    pub note: Option<String>,
+    pub actor: String,
+    /// Seconds since the Unix epoch
+    pub resolved_at: u64,
+}
+
+/// A player's report of another player
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Report {
+    pub id: ReportId,
+    /// The reporting player, `None` for reports the server made itself of players it
+    /// disconnected for abuse
+    pub reporter: Option<PlayerId>,
+    pub reported: PlayerId,
+    /// The room the reported player was in
+    pub room_id: RoomId,
+    pub reason: String,
+    /// The reported player's most recent chat in the room, as the server saw it when the
+    /// report was made, oldest first
+    pub chat_excerpt: Vec<ChatMessage>,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    pub status: ReportStatus,
+    /// What moderators did to the reported player, oldest first
+    pub actions: Vec<TakenAction>,
+    pub resolution: Option<Resolution>,
+}
+
+/// A report as the reporting player makes it
+#[derive(Debug, Clone)]
+pub struct NewReport {
+    pub reporter: Option<PlayerId>,
+    pub reported: PlayerId,
+    pub room_id: RoomId,
+    pub reason: String,
+    pub chat_excerpt: Vec<ChatMessage>,
+}
+
+/// Raised when a report can't be made or moderated
+#[derive(Error, Debug, PartialEq)]
+pub enum ReportError {
+    #[error("Players can't report themselves")]
+    SelfReport,
+    #[error("Reasons can't be empty")]
+    MissingReason,
+    #[error("Reasons can be at most {MAX_REPORT_REASON_CHARS} characters")]
+    ReasonTooLong,
+    #[error("The player already has an unresolved report of them in the room, {0}")]
+    Duplicate(ReportId),
+    #[error("No report has the ID")]
+    NotFound,
+    #[error("The report has already been resolved")]
+    AlreadyResolved,
+}
+
+/// The reported player's most recent messages in the room's chat history
+pub fn chat_excerpt(history: Vec<ChatMessage>, reported: PlayerId) -> Vec<ChatMessage> {
+    let mut excerpt: Vec<_> = history
+        .into_iter()
+        .rev()
+        .filter(|message| message.from_player == Some(reported))
+        .take(CHAT_EXCERPT_LEN)
+        .collect();
+    excerpt.reverse();
+    excerpt
+}
+
+/// Every report made, oldest first
+#[derive(Debug, Default)]
+pub struct ReportQueue {
+    reports: Mutex<Vec<Report>>,
+    /// Where the reports are written to, `None` to keep them in memory only
+    file: Option<PathBuf>,
+    /// Woken when the reports changed and should be saved
+    changed: Notify,
+}
+
+impl ReportQueue {
+    /// Creates a queue kept in the file, starting with the reports already in it that are
+    /// still worth keeping
+    pub fn with_file(file: PathBuf) -> io::Result<Self> {
+        let mut reports = match fs::read(&file) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        prune_resolved(&mut reports, unix_time());
+        Ok(Self {
+            reports: Mutex::new(reports),
+            file: Some(file),
+            changed: Notify::new(),
+        })
+    }
+
+    /// Adds the report to the queue, unless the reporter already has an unresolved report of
+    /// the same player in the room
+    pub fn submit(&self, report: NewReport) -> Result<Report, ReportError> {
+        let reason = report.reason.trim();
+        if report.reporter == Some(report.reported) {
+            return Err(ReportError::SelfReport);
+        }
+        if reason.is_empty() {
+            return Err(ReportError::MissingReason);
+        }
+        if reason.chars().count() > MAX_REPORT_REASON_CHARS {
+            return Err(ReportError::ReasonTooLong);
+        }
+
+        let mut reports = self.reports.lock().unwrap();
+        if let Some(duplicate) = reports.iter().find(|existing| {
+            existing.status != ReportStatus::Resolved
+                && existing.reporter == report.reporter
+                && existing.reported == report.reported
+                && existing.room_id == report.room_id
+        }) {
+            return Err(ReportError::Duplicate(duplicate.id));
+        }
+        let report = Report {
+            id: ReportId(Uuid::new_v4()),
+            reporter: report.reporter,
+            reported: report.reported,
+            room_id: report.room_id,
+            reason: reason.to_owned(),
+            chat_excerpt: report.chat_excerpt,
+            created_at: unix_time(),
+            status: ReportStatus::Open,
+            actions: Vec::new(),
+            resolution: None,
+        };
+        reports.push(report.clone());
+        self.changed.notify_one();
+        info!(
+            event = "report_submitted",
+            report_id = %report.id,
+            reporter = ?report.reporter,
+            reported = %report.reported,
+            room_id = %report.room_id
+        );
+        Ok(report)
+    }
+
+    pub fn get(&self, id: ReportId) -> Option<Report> {
+        self.reports
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|report| report.id == id)
+            .cloned()
+    }
+
+    /// The reports with the status, or every report if `None`, oldest first
+    pub fn list(&self, status: Option<ReportStatus>) -> Vec<Report> {
+        self.reports
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|report| status.is_none_or(|status| report.status == status))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds another reason to an unresolved report, for the server reporting the same player
+    /// in the same room again, dropping the oldest reasons to stay within
+    /// [MAX_REPORT_REASON_CHARS] and keeping the latest chat excerpt
+    pub fn add_reason(
+        &self,
+        id: ReportId,
+        reason: &str,
+        chat_excerpt: Vec<ChatMessage>,
+    ) -> Result<Report, ReportError> {
+        self.update(id, |report| {
+            info!(event = "report_reason_added", report_id = %id);
+            let mut reasons: VecDeque<_> = report.reason.lines().chain([reason]).collect();
+            while reasons.len() > 1
+                && reasons
+                    .iter()
+                    .map(|reason| reason.chars().count() + 1)
+                    .sum::<usize>()
+                    > MAX_REPORT_REASON_CHARS + 1
+            {
+                reasons.pop_front();
+            }
+            report.reason = Vec::from(reasons).join("\n");
+            report.chat_excerpt = chat_excerpt;
+        })
+    }
+
+    /// Records that a moderator acted against the reported player
+    pub fn record_action(
+        &self,
+        id: ReportId,
+        action: ModerationAction,
+        actor: &str,
+    ) -> Result<Report, ReportError> {
+        self.update(id, |report| {
+            info!(event = "report_actioned", report_id = %id, action = ?action, actor);
+            report.status = ReportStatus::Actioned;
+            report.actions.push(TakenAction {
+                action,
+                actor: actor.to_owned(),
+                taken_at: unix_time(),
+            });
+        })
+    }
+
+    /// Takes the report off the queue
+    pub fn resolve(
+        &self,
+        id: ReportId,
+        note: Option<String>,
+        actor: &str,
+    ) -> Result<Report, ReportError> {
+        self.update(id, |report| {
+            info!(event = "report_resolved", report_id = %id, actor);
+            report.status = ReportStatus::Resolved;
+            report.resolution = Some(Resolution {
+                note,
+                actor: actor.to_owned(),
+                resolved_at: unix_time(),
+            });
+        })
+    }
+
+    fn update(&self, id: ReportId, f: impl FnOnce(&mut Report)) -> Result<Report, ReportError> {
+        let mut reports = self.reports.lock().unwrap();
+        let report = reports
+            .iter_mut()
+            .find(|report| report.id == id)
+            .ok_or(ReportError::NotFound)?;
+        if report.status == ReportStatus::Resolved {
+            return Err(ReportError::AlreadyResolved);
+        }
+        f(report);
+        let report = report.clone();
+        self.changed.notify_one();
+        Ok(report)
+    }
+
+    /// Writes the reports to the file, if there is one, through a temporary file so a crash
+    /// can't leave it half written, dropping those resolved long enough ago first
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let reports = {
+            let mut reports = self.reports.lock().unwrap();
+            prune_resolved(&mut reports, unix_time());
+            reports.clone()
+        };
+        if let Err(e) = write_atomically(file, &reports) {
+            warn!(event = "reports_not_saved", file = %file.display(), error = %e);
+        }
+    }
+
+    /// Writes the reports to the file whenever they change until shut down, and once more on
+    /// the way out
+    pub async fn run(self: Arc<Self>, shutdown: CancellationToken) {
+        if self.file.is_none() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                _ = self.changed.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            self.save_in_background().await;
+        }
+        self.save_in_background().await;
+    }
+
+    async fn save_in_background(self: &Arc<Self>) {
+        let reports = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || reports.save()).await {
+            warn!(event = "reports_not_saved", error = %e);
+        }
+    }
+}
+
+/// Drops the reports resolved more than [RESOLVED_REPORT_RETENTION] before `now`
+fn prune_resolved(reports: &mut Vec<Report>, now: u64) {
+    let before = reports.len();
+    reports.retain(|report| {
+        report.resolution.as_ref().is_none_or(|resolution| {
+            resolution.resolved_at + RESOLVED_REPORT_RETENTION.as_secs() > now
+        })
+    });
+    if reports.len() < before {
+        info!(
+            event = "resolved_reports_pruned",
+            count = before - reports.len()
+        );
+    }
+}
+
+#[cfg(test)]
+fn new_report(reporter: u128, reported: u128) -> NewReport {
+    NewReport {
+        reporter: Some(reporter.into()),
+        reported: reported.into(),
+        room_id: 1_u128.into(),
+        reason: "cheating".to_owned(),
+        chat_excerpt: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod submit {
+    use super::*;
+
+    #[test]
+    fn turns_away_self_reports_and_bad_reasons() {
+        let queue = ReportQueue::default();
+
+        assert_eq!(queue.submit(new_report(1, 1)), Err(ReportError::SelfReport));
+        let report = NewReport {
+            reason: " ".to_owned(),
+            ..new_report(1, 2)
+        };
+        assert_eq!(queue.submit(report), Err(ReportError::MissingReason));
+        let report = NewReport {
+            reason: "x".repeat(MAX_REPORT_REASON_CHARS + 1),
+            ..new_report(1, 2)
+        };
+        assert_eq!(queue.submit(report), Err(ReportError::ReasonTooLong));
+        assert!(queue.list(None).is_empty());
+    }
+
+    #[test]
+    fn turns_away_duplicates_until_resolved() {
+        let queue = ReportQueue::default();
+        let report = queue.submit(new_report(1, 2)).unwrap();
+
+        assert_eq!(
+            queue.submit(new_report(1, 2)),
+            Err(ReportError::Duplicate(report.id))
+        );
+        assert!(queue.submit(new_report(3, 2)).is_ok());
+
+        queue.resolve(report.id, None, "api").unwrap();
+        assert!(queue.submit(new_report(1, 2)).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod add_reason {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_reasons() {
+        let queue = ReportQueue::default();
+        let report = queue.submit(new_report(1, 2)).unwrap();
+        let long = "x".repeat(MAX_REPORT_REASON_CHARS - 5);
+
+        let added = queue.add_reason(report.id, "spamming", Vec::new()).unwrap();
+        assert_eq!(added.reason, "cheating\nspamming");
+
+        let added = queue.add_reason(report.id, &long, Vec::new()).unwrap();
+        assert_eq!(added.reason, long);
+    }
+}
+
+#[cfg(test)]
+mod resolve {
+    use super::*;
+
+    #[test]
+    fn takes_the_report_off_the_queue_for_good() {
+        let queue = ReportQueue::default();
+        let report = queue.submit(new_report(1, 2)).unwrap();
+        queue
+            .record_action(report.id, ModerationAction::DisplayNameWiped, "api")
+            .unwrap();
+        assert_eq!(queue.list(Some(ReportStatus::Actioned)).len(), 1);
+
+        let resolved = queue
+            .resolve(report.id, Some("Warned".to_owned()), "api")
+            .unwrap();
+
+        assert_eq!(resolved.status, ReportStatus::Resolved);
+        assert_eq!(resolved.actions.len(), 1);
+        assert!(queue.list(Some(ReportStatus::Open)).is_empty());
+        assert_eq!(
+            queue.resolve(report.id, None, "api"),
+            Err(ReportError::AlreadyResolved)
+        );
+    }
+}
+
+#[cfg(test)]
+mod with_file {
+    use super::*;
+
+    #[test]
+    fn reads_back_the_reports_written() {
+        let file = std::env::temp_dir().join(format!("wormhole-reports-{}.json", Uuid::new_v4()));
+        let queue = ReportQueue::with_file(file.clone()).unwrap();
+        let report = queue.submit(new_report(1, 2)).unwrap();
+        queue.save();
+
+        let reopened = ReportQueue::with_file(file.clone()).unwrap();
+
+        assert_eq!(reopened.list(None), [report]);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn drops_reports_resolved_past_the_retention() {
+        let file = std::env::temp_dir().join(format!("wormhole-reports-{}.json", Uuid::new_v4()));
+        let queue = ReportQueue::with_file(file.clone()).unwrap();
+        let old = queue.submit(new_report(1, 2)).unwrap();
+        let recent = queue.submit(new_report(3, 2)).unwrap();
+        let open = queue.submit(new_report(4, 2)).unwrap();
+        queue.resolve(old.id, None, "api").unwrap();
+        let recent = queue.resolve(recent.id, None, "api").unwrap();
+        queue.reports.lock().unwrap()[0]
+            .resolution
+            .as_mut()
+            .unwrap()
+            .resolved_at -= RESOLVED_REPORT_RETENTION.as_secs();
+        queue.save();
+
+        let reopened = ReportQueue::with_file(file.clone()).unwrap();
+
+        assert_eq!(reopened.list(None), [recent, open]);
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod run {
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_reports_soon_after_they_change() {
+        let file = std::env::temp_dir().join(format!("wormhole-reports-{}.json", Uuid::new_v4()));
+        let queue = Arc::new(ReportQueue::with_file(file.clone()).unwrap());
+        let shutdown = CancellationToken::new();
+        let saver = tokio::spawn(queue.clone().run(shutdown.clone()));
+
+        let report = queue.submit(new_report(1, 2)).unwrap();
+        let saved = async {
+            loop {
+                let saved = ReportQueue::with_file(file.clone()).unwrap().list(None);
+                if !saved.is_empty() {
+                    return saved;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let saved = tokio::time::timeout(Duration::from_secs(5), saved).await;
+        shutdown.cancel();
+        saver.await.unwrap();
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(saved, Ok(vec![report]));
+    }
+}
+
+#[cfg(test)]
+mod chat_excerpt {
+    use super::*;
+
+    #[test]
+    fn keeps_the_reported_players_most_recent_messages() {
+        let message = |player: u128, text: String| ChatMessage {
+            from: 1.into(),
+            from_player: Some(player.into()),
+            text,
+            channel: Default::default(),
+            sent_at: 0,
+        };
+        let history = (0..CHAT_EXCERPT_LEN + 5)
+            .flat_map(|i| [message(2, format!("{i}")), message(3, "hi".to_owned())])
+            .collect();
+
+        let excerpt = chat_excerpt(history, 2.into());
+
+        assert_eq!(excerpt.len(), CHAT_EXCERPT_LEN);
+        assert_eq!(excerpt[0].text, "5");
+        assert_eq!(
+            excerpt.last().unwrap().text,
+            format!("{}", CHAT_EXCERPT_LEN + 4)
+        );
+    }
+}
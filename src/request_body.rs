@@ -0,0 +1,339 @@
+//! Limits on the size of REST request bodies, and problems explaining bodies that can't be read
+//!
+//! Each endpoint's bodies are cut off at its [limit][BodyLimits], by their `Content-Length`
+//! before anything is read or while they're read when they're sent in chunks. Bodies that
+//! are too large, aren't JSON, or don't match what the endpoint takes are answered with a
+//! problem saying which, where actix would answer with a bare `400`. Request types are
+//! expected to deny unknown fields, so a misspelled field is reported rather than ignored.
+
+use std::collections::HashMap;
+use std::future::{ready, Future};
+use std::pin::Pin;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::{JsonPayloadError, PayloadError};
+use actix_web::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+use tokio_stream::{Stream, StreamExt};
+use tracing::info;
+
+use crate::problem::Problem;
+
+/// How large REST request bodies may be unless configured otherwise
+pub const DEFAULT_BODY_LIMIT: usize = 16 * 1024;
+
+/// The scope every REST endpoint is served under
+const API_PREFIX: &str = "/api/v1";
+
+/// How large REST request bodies may be, given to the app as data for [limit_request_bodies]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyLimits {
+    /// The limit for endpoints that weren't given one of their own, in bytes
+    pub default: usize,
+    /// Limits in bytes keyed by the endpoint's path under `/api/v1`, such as
+    /// `/rooms/{room_id}/turn`
+    pub by_endpoint: HashMap<String, usize>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_BODY_LIMIT)
+    }
+}
+
+impl BodyLimits {
+    /// Limits every endpoint's bodies to `default` bytes
+    pub fn new(default: usize) -> Self {
+        Self {
+            default,
+            by_endpoint: HashMap::new(),
+        }
+    }
+
+    /// Limits the bodies of the endpoint at the path under `/api/v1` to `limit` bytes
+    pub fn with_endpoint_limit(mut self, path: impl Into<String>, limit: usize) -> Self {
+        self.by_endpoint.insert(path.into(), limit);
+        self
+    }
+
+    /// The limit for the endpoint the request is for
+    pub fn for_request(&self, req: &HttpRequest) -> usize {
+        req.match_pattern()
+            .as_deref()
+            .and_then(|pattern| pattern.strip_prefix(API_PREFIX))
+            .and_then(|path| self.by_endpoint.get(path))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// How JSON bodies are read, answering those that can't be with a [Problem]
+    ///
+    /// Its limit is the largest of any endpoint's, leaving each endpoint's own to
+    /// [limit_request_bodies].
+    pub fn json_config(&self) -> web::JsonConfig {
+        let largest = self
+            .by_endpoint
+            .values()
+            .copied()
+            .fold(self.default, usize::max);
+        web::JsonConfig::default()
+            .limit(largest)
+            .error_handler(json_error)
+    }
+}
+
+fn too_large(limit: usize) -> Problem {
+    Problem::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "/problems/body-too-large",
+        "The body is too large",
+    )
+    .with_detail(format!(
+        "Bodies sent to this endpoint can be at most {limit} bytes"
+    ))
+}
+
+/// Answers requests with bodies over the limit the app's [BodyLimits] set for their endpoint
+/// with a `413`, leaving requests alone if the app has none
+pub async fn limit_request_bodies(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(limit) = req
+        .app_data::<web::Data<BodyLimits>>()
+        .map(|limits| limits.for_request(req.request()))
+    else {
+        return next.call(req).await;
+    };
+
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok());
+    if length.is_some_and(|length| length > limit) {
+        info!(
+            event = "request_body_too_large",
+            path = req.path(),
+            length,
+            limit
+        );
+        return Err(too_large(limit).into());
+    }
+
+    let mut read = 0;
+    let limited = req.take_payload().map(move |chunk| {
+        let chunk = chunk?;
+        read += chunk.len();
+        if read > limit {
+            Err(PayloadError::Overflow)
+        } else {
+            Ok(chunk)
+        }
+    });
+    let limited: Pin<Box<dyn Stream<Item = Result<web::Bytes, PayloadError>>>> = Box::pin(limited);
+    req.set_payload(limited.into());
+    next.call(req).await
+}
+
+/// Turns a body that couldn't be read as JSON into a problem saying why
+fn json_error(error: JsonPayloadError, req: &HttpRequest) -> Error {
+    let problem = match &error {
+        JsonPayloadError::OverflowKnownLength { limit, .. }
+        | JsonPayloadError::Overflow { limit } => too_large(*limit),
+        JsonPayloadError::Payload(PayloadError::Overflow) => too_large(
+            req.app_data::<web::Data<BodyLimits>>()
+                .map_or(DEFAULT_BODY_LIMIT, |limits| limits.for_request(req)),
+        ),
+        JsonPayloadError::ContentType => Problem::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "/problems/not-json",
+            "The body isn't JSON",
+        )
+        .with_detail("Send the body as application/json"),
+        JsonPayloadError::Deserialize(e) if e.classify() != Category::Data => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/malformed-json",
+            "The body isn't valid JSON",
+        )
+        .with_detail(capitalized(e)),
+        JsonPayloadError::Deserialize(e) if e.to_string().starts_with("unknown field") => {
+            Problem::new(
+                StatusCode::BAD_REQUEST,
+                "/problems/unknown-field",
+                "The body has a field the endpoint doesn't take",
+            )
+            .with_detail(capitalized(e))
+        }
+        JsonPayloadError::Deserialize(e) => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/invalid-body",
+            "The body doesn't match what the endpoint takes",
+        )
+        .with_detail(capitalized(e)),
+        e => Problem::new(
+            StatusCode::BAD_REQUEST,
+            "/problems/unreadable-body",
+            "The body couldn't be read",
+        )
+        .with_detail(e.to_string()),
+    };
+    info!(
+        event = "request_body_rejected",
+        path = req.path(),
+        problem = problem.problem_type,
+        detail = problem.detail
+    );
+    problem.into()
+}
+
+/// serde's message about the body, which names the fields involved and where they are
+fn capitalized(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    let mut chars = message.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or(message)
+}
+
+/// A JSON body an endpoint can do without, `None` when the request has none
+///
+/// Unlike `Option<web::Json<T>>`, a body that's sent but can't be read is answered with a
+/// problem rather than ignored.
+#[derive(Debug)]
+pub struct OptionalJson<T>(pub Option<T>);
+
+impl<T: DeserializeOwned + 'static> FromRequest for OptionalJson<T> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let empty = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .is_some_and(|length| length == "0");
+        if empty || !req.headers().contains_key(CONTENT_TYPE) {
+            return Box::pin(ready(Ok(Self(None))));
+        }
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(Self(Some(json.await?.into_inner()))) })
+    }
+}
+
+#[cfg(test)]
+mod limit_request_bodies {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App, HttpResponse};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct NameRequest {
+        name: String,
+    }
+
+    async fn greet(body: web::Json<NameRequest>) -> HttpResponse {
+        HttpResponse::Ok().body(format!("Hello {}", body.name))
+    }
+
+    async fn call(path: &str, body: test::TestRequest) -> (StatusCode, serde_json::Value) {
+        let limits = BodyLimits::new(32).with_endpoint_limit("/large", 64);
+        let app = test::init_service(
+            App::new()
+                .app_data(limits.json_config())
+                .app_data(web::Data::new(limits))
+                .service(
+                    web::scope("api/v1")
+                        .wrap(from_fn(limit_request_bodies))
+                        .route("/small", web::post().to(greet))
+                        .route("/large", web::post().to(greet)),
+                ),
+        )
+        .await;
+        let request = body.uri(&format!("/api/v1{path}")).to_request();
+        let response = match test::try_call_service(&app, request).await {
+            Ok(res) => res.into_parts().1.map_into_boxed_body(),
+            Err(e) => e.error_response(),
+        };
+        let status = response.status();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn named(name: &str) -> serde_json::Value {
+        serde_json::json!({ "name": name })
+    }
+
+    #[actix_web::test]
+    async fn limits_each_endpoint_on_its_own() {
+        let name = "x".repeat(40);
+
+        let (small, problem) =
+            call("/small", test::TestRequest::post().set_json(named(&name))).await;
+        let (large, _) = call("/large", test::TestRequest::post().set_json(named(&name))).await;
+
+        assert_eq!(small, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(problem["type"], "/problems/body-too-large");
+        assert_eq!(
+            problem["detail"],
+            "Bodies sent to this endpoint can be at most 32 bytes"
+        );
+        assert_eq!(large, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn names_fields_the_endpoint_doesnt_take() {
+        let body = serde_json::json!({ "name": "ada", "nmae": "ada" });
+
+        let (status, problem) = call("/large", test::TestRequest::post().set_json(body)).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["type"], "/problems/unknown-field");
+        assert_eq!(
+            problem["detail"],
+            "Unknown field `nmae`, expected `name` at line 1 column 20"
+        );
+    }
+
+    #[actix_web::test]
+    async fn explains_bodies_that_arent_json() {
+        let (malformed, malformed_problem) = call(
+            "/small",
+            test::TestRequest::post()
+                .insert_header((CONTENT_TYPE, "application/json"))
+                .set_payload("{\"name\":"),
+        )
+        .await;
+        let (missing, missing_problem) = call(
+            "/small",
+            test::TestRequest::post().set_json(serde_json::json!({})),
+        )
+        .await;
+        let (text, text_problem) = call(
+            "/small",
+            test::TestRequest::post()
+                .insert_header((CONTENT_TYPE, "text/plain"))
+                .set_payload("ada"),
+        )
+        .await;
+
+        assert_eq!(malformed, StatusCode::BAD_REQUEST);
+        assert_eq!(malformed_problem["type"], "/problems/malformed-json");
+        assert_eq!(missing, StatusCode::BAD_REQUEST);
+        assert_eq!(missing_problem["type"], "/problems/invalid-body");
+        assert_eq!(
+            missing_problem["detail"],
+            "Missing field `name` at line 1 column 2"
+        );
+        assert_eq!(text, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(text_problem["type"], "/problems/not-json");
+    }
+}
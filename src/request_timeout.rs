@@ -0,0 +1,120 @@
+//! A deadline on each REST request
+//!
+//! Requests still being handled when their [deadline][RequestTimeout] passes are answered
+//! with a `504` and their handler is dropped, cancelling whatever it was waiting on, so a
+//! stuck storage call or lock can't hold the client's connection open indefinitely. Work that
+//! blocks the thread rather than awaiting can't be cancelled this way, and only gets its
+//! response replaced once it's done.
+
+use std::time::Duration;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use tracing::warn;
+
+use crate::problem::Problem;
+
+/// How long REST requests may take unless configured otherwise
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long each REST request may take, given to the app as data for [enforce_request_timeout]
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTimeout(pub Duration);
+
+/// Answers requests that aren't handled within the app's [RequestTimeout] with a `504`,
+/// leaving requests alone if the app has none
+pub async fn enforce_request_timeout(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(&RequestTimeout(timeout)) = req
+        .app_data::<web::Data<RequestTimeout>>()
+        .map(|timeout| timeout.get_ref())
+    else {
+        return next.call(req).await;
+    };
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+
+    match tokio::time::timeout(timeout, next.call(req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(event = "request_timed_out", %method, path, ?timeout);
+            Err(Problem::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                "/problems/request-timeout",
+                "The request took too long",
+            )
+            .with_detail(format!(
+                "The server gave up on the request after {timeout:?}"
+            ))
+            .into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod enforce_request_timeout {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App, HttpResponse};
+
+    /// Records being dropped, which is how a handler's work is cancelled
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    async fn call(handler_takes: Duration, dropped: Arc<AtomicBool>) -> StatusCode {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(RequestTimeout(Duration::from_millis(50))))
+                .service(
+                    web::scope("")
+                        .wrap(from_fn(enforce_request_timeout))
+                        .default_service(web::to(move || {
+                            let flag = DropFlag(dropped.clone());
+                            async move {
+                                tokio::time::sleep(handler_takes).await;
+                                std::mem::forget(flag);
+                                HttpResponse::Ok().finish()
+                            }
+                        })),
+                ),
+        )
+        .await;
+
+        match test::try_call_service(&app, test::TestRequest::default().to_request()).await {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn lets_quick_requests_through() {
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        assert_eq!(call(Duration::ZERO, dropped.clone()).await, StatusCode::OK);
+        assert!(!dropped.load(Ordering::SeqCst));
+    }
+
+    #[actix_web::test]
+    async fn cancels_requests_that_take_too_long() {
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        assert_eq!(
+            call(Duration::from_secs(60), dropped.clone()).await,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
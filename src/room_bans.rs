@@ -0,0 +1,319 @@
+//! Players kept out of a room by its owner or a moderator, along with every rematch of it
+//!
+//! Bans are kept under the room the line of rematches started with, its
+//! [lineage][crate::game::Room::lineage], so they outlive the room and a banned player can't
+//! wait for a rematch to slip back in. They're held in memory and, when a room bans file is
+//! configured, saved to it by [RoomBans::run] soon after every change and read back from it on
+//! start, so they survive restarts too. Bans can be given a duration, such as those following
+//! a passed [vote to kick][crate::kick_votes], and are left out once it's over, then dropped
+//! within [PRUNE_INTERVAL].
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::clock::unix_time;
+use crate::files::write_atomically;
+use crate::game::{PlayerId, RoomId};
+
+/// How often bans that have lapsed are dropped
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A player kept out of a room and its rematches
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomBan {
+    /// The room the line of rematches the ban applies to started with
+    pub lineage: RoomId,
+    pub player_id: PlayerId,
+    /// Who issued the ban, such as `api` or the owner's player id
+    pub issued_by: String,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    /// When the ban lapses, in seconds since the Unix epoch, `None` if it lasts until lifted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl RoomBan {
+    /// Whether the ban still keeps the player out at `now`, in seconds since the Unix epoch
+    pub fn in_force(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+/// Every ban from a room in force, oldest first
+#[derive(Debug, Default)]
+pub struct RoomBans {
+    bans: Mutex<Vec<RoomBan>>,
+    /// Where the bans are written to, `None` to keep them in memory only
+    file: Option<PathBuf>,
+    /// Woken when the bans changed and should be saved
+    changed: Notify,
+}
+
+impl RoomBans {
+    /// Creates a list kept in the file, starting with the bans already in it that are still
+    /// in force
+    pub fn with_file(file: PathBuf) -> io::Result<Self> {
+        let mut bans: Vec<RoomBan> = match fs::read(&file) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let now = unix_time();
+        bans.retain(|ban| ban.in_force(now));
+        Ok(Self {
+            bans: Mutex::new(bans),
+            file: Some(file),
+            changed: Notify::new(),
+        })
+    }
+
+    /// Keeps the player out of every room in the lineage for `duration`, or until the ban is
+    /// lifted if `None`, returning the ban already in force instead if it lasts as long
+    pub fn ban(
+        &self,
+        lineage: RoomId,
+        player_id: PlayerId,
+        issued_by: String,
+        duration: Option<Duration>,
+    ) -> RoomBan {
+        let now = unix_time();
+        let expires_at = duration.map(|duration| now + duration.as_secs());
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|ban| ban.in_force(now));
+        if let Some(ban) = find(&bans, lineage, player_id, now) {
+            let outlasts = match (ban.expires_at, expires_at) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(current), Some(new)) => current >= new,
+            };
+            if outlasts {
+                return ban.clone();
+            }
+            bans.retain(|ban| ban.lineage != lineage || ban.player_id != player_id);
+        }
+        let ban = RoomBan {
+            lineage,
+            player_id,
+            issued_by,
+            issued_at: now,
+            expires_at,
+        };
+        bans.push(ban.clone());
+        self.changed.notify_one();
+        info!(event = "room_ban_issued", %lineage, %player_id, issued_by = ban.issued_by, expires_at);
+        ban
+    }
+
+    /// The ban keeping the player out of the rooms in the lineage, if any
+    pub fn find(&self, lineage: RoomId, player_id: PlayerId) -> Option<RoomBan> {
+        find(&self.bans.lock().unwrap(), lineage, player_id, unix_time()).cloned()
+    }
+
+    /// The bans in force from the rooms in the lineage, oldest first
+    pub fn list(&self, lineage: RoomId) -> Vec<RoomBan> {
+        let now = unix_time();
+        self.bans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|ban| ban.lineage == lineage && ban.in_force(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Lets the player back into the rooms in the lineage, returning the ban if there was one
+    pub fn lift(&self, lineage: RoomId, player_id: PlayerId, actor: &str) -> Option<RoomBan> {
+        let mut bans = self.bans.lock().unwrap();
+        let index = bans
+            .iter()
+            .position(|ban| ban.lineage == lineage && ban.player_id == player_id)?;
+        let ban = bans.remove(index);
+        self.changed.notify_one();
+        info!(event = "room_ban_lifted", %lineage, %player_id, actor);
+        Some(ban)
+    }
+
+    /// Drops the bans that have lapsed, returning whether there were any
+    pub fn prune(&self) -> bool {
+        let now = unix_time();
+        let mut bans = self.bans.lock().unwrap();
+        let before = bans.len();
+        bans.retain(|ban| ban.in_force(now));
+        bans.len() < before
+    }
+
+    /// Writes the bans in force to the file, if there is one, dropping those that have lapsed
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        self.prune();
+        let bans = self.bans.lock().unwrap().clone();
+        if let Err(e) = write_atomically(file, &bans) {
+            warn!(event = "room_bans_not_saved", file = %file.display(), error = %e);
+        }
+    }
+
+    /// Writes the bans to the file whenever they change, and drops those that have lapsed
+    /// every [PRUNE_INTERVAL], until shut down, then writes them once more on the way out
+    pub async fn run(self: Arc<Self>, shutdown: CancellationToken) {
+        let mut prunes = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = prunes.tick() => {
+                    if !self.prune() {
+                        continue;
+                    }
+                }
+                _ = self.changed.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            self.save_in_background().await;
+        }
+        self.save_in_background().await;
+    }
+
+    async fn save_in_background(self: &Arc<Self>) {
+        if self.file.is_none() {
+            return;
+        }
+        let bans = self.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || bans.save()).await {
+            warn!(event = "room_bans_not_saved", error = %e);
+        }
+    }
+}
+
+fn find(bans: &[RoomBan], lineage: RoomId, player_id: PlayerId, now: u64) -> Option<&RoomBan> {
+    bans.iter()
+        .find(|ban| ban.lineage == lineage && ban.player_id == player_id && ban.in_force(now))
+}
+
+#[cfg(test)]
+mod ban {
+    use super::*;
+
+    #[test]
+    fn applies_to_the_lineage_alone() {
+        let bans = RoomBans::default();
+
+        bans.ban(1_u128.into(), 7.into(), "player:1".to_owned(), None);
+
+        assert!(bans.find(1_u128.into(), 7.into()).is_some());
+        assert_eq!(bans.find(2_u128.into(), 7.into()), None);
+        assert_eq!(bans.find(1_u128.into(), 8.into()), None);
+    }
+
+    #[test]
+    fn keeps_the_ban_already_in_force() {
+        let bans = RoomBans::default();
+
+        let first = bans.ban(1_u128.into(), 7.into(), "player:1".to_owned(), None);
+        let second = bans.ban(1_u128.into(), 7.into(), "api".to_owned(), None);
+
+        assert_eq!(second, first);
+        assert_eq!(bans.list(1_u128.into()), vec![first]);
+    }
+
+    #[test]
+    fn lapses_once_its_duration_is_over() {
+        let bans = RoomBans::default();
+
+        let ban = bans.ban(
+            1_u128.into(),
+            7.into(),
+            "vote".to_owned(),
+            Some(Duration::from_secs(300)),
+        );
+        let mut lapsed = ban.clone();
+        lapsed.expires_at = Some(ban.issued_at);
+
+        assert_eq!(bans.find(1_u128.into(), 7.into()), Some(ban.clone()));
+        assert!(!lapsed.in_force(ban.issued_at));
+        assert_eq!(
+            bans.ban(1_u128.into(), 7.into(), "player:1".to_owned(), None)
+                .expires_at,
+            None
+        );
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let file =
+            std::env::temp_dir().join(format!("wormhole-room-bans-{}.json", uuid::Uuid::new_v4()));
+        let bans = RoomBans::with_file(file.clone()).unwrap();
+        let ban = bans.ban(1_u128.into(), 7.into(), "player:1".to_owned(), None);
+        bans.ban(1_u128.into(), 8.into(), "player:1".to_owned(), None);
+        bans.lift(1_u128.into(), 8.into(), "api");
+        bans.save();
+
+        let restarted = RoomBans::with_file(file.clone()).unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(restarted.list(1_u128.into()), vec![ban]);
+    }
+
+    #[test]
+    fn drops_lapsed_bans_on_start() {
+        let file =
+            std::env::temp_dir().join(format!("wormhole-room-bans-{}.json", uuid::Uuid::new_v4()));
+        let now = unix_time();
+        let ban = |player_id: u128, expires_at| RoomBan {
+            lineage: 1_u128.into(),
+            player_id: player_id.into(),
+            issued_by: "vote".to_owned(),
+            issued_at: now - 600,
+            expires_at,
+        };
+        let lasting = ban(8, Some(now + 600));
+        write_atomically(&file, &[ban(7, Some(now - 300)), lasting.clone()]).unwrap();
+
+        let restarted = RoomBans::with_file(file.clone()).unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(*restarted.bans.lock().unwrap(), vec![lasting]);
+    }
+}
+
+#[cfg(test)]
+mod run {
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_bans_soon_after_they_change() {
+        let file =
+            std::env::temp_dir().join(format!("wormhole-room-bans-{}.json", uuid::Uuid::new_v4()));
+        let bans = Arc::new(RoomBans::with_file(file.clone()).unwrap());
+        let shutdown = CancellationToken::new();
+        let saver = tokio::spawn(bans.clone().run(shutdown.clone()));
+
+        let ban = bans.ban(1_u128.into(), 7.into(), "player:1".to_owned(), None);
+        let saved = async {
+            loop {
+                let saved = RoomBans::with_file(file.clone())
+                    .unwrap()
+                    .list(1_u128.into());
+                if !saved.is_empty() {
+                    return saved;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let saved = tokio::time::timeout(Duration::from_secs(5), saved).await;
+        shutdown.cancel();
+        saver.await.unwrap();
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(saved, Ok(vec![ban]));
+    }
+}
@@ -0,0 +1,127 @@
+//! Live feeds of what happens in single rooms, for operators moderating or debugging them
+//!
+//! Connections publish their joins, departures and the messages they send to the room's feed,
+//! which costs nothing while nobody is watching the room, and next to nothing while nobody is
+//! watching any room.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::game::{ConnectionId, PlayerId, RoomId};
+use crate::protocol::ClientMessage;
+
+/// How many events a watcher can fall behind by before it misses some
+const WATCH_CAPACITY: usize = 256;
+
+/// Something that happened in a watched room
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WatchedEvent {
+    Joined {
+        connection_id: ConnectionId,
+        player_id: Option<PlayerId>,
+    },
+    Left {
+        connection_id: ConnectionId,
+        player_id: Option<PlayerId>,
+    },
+    /// A message a connection sent, whether or not it was allowed to
+    Message {
+        connection_id: ConnectionId,
+        player_id: Option<PlayerId>,
+        message: ClientMessage,
+    },
+}
+
+impl WatchedEvent {
+    /// The name the event is sent under, as the `event` field of a Server-Sent Event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Joined { .. } => "joined",
+            Self::Left { .. } => "left",
+            Self::Message { .. } => "message",
+        }
+    }
+}
+
+/// The feeds of the rooms someone is watching
+#[derive(Debug, Default)]
+pub struct RoomWatchers {
+    feeds: Mutex<HashMap<RoomId, Sender<WatchedEvent>>>,
+    /// How many rooms have feeds, so publishing can skip the lock while there are none
+    watched: AtomicUsize,
+}
+
+impl RoomWatchers {
+    /// Receives every event in the room from now on
+    pub fn watch(&self, room_id: RoomId) -> Receiver<WatchedEvent> {
+        let mut feeds = self.feeds.lock().unwrap();
+        // Rooms that went quiet after their watchers left are only forgotten here
+        feeds.retain(|_, feed| feed.receiver_count() > 0);
+        let watcher = feeds
+            .entry(room_id)
+            .or_insert_with(|| broadcast::channel(WATCH_CAPACITY).0)
+            .subscribe();
+        self.watched.store(feeds.len(), Ordering::Relaxed);
+        watcher
+    }
+
+    /// Sends the event to everyone watching the room, only building it if someone is, and
+    /// forgets the room's feed once its last watcher has gone
+    pub fn publish(&self, room_id: RoomId, event: impl FnOnce() -> WatchedEvent) {
+        // A watcher of a room that had none may be missed by a moment, as they would be if
+        // they had started watching a moment later
+        if self.watched.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut feeds = self.feeds.lock().unwrap();
+        let Some(feed) = feeds.get(&room_id) else {
+            return;
+        };
+        if feed.send(event()).is_err() {
+            feeds.remove(&room_id);
+            self.watched.store(feeds.len(), Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod publish {
+    use super::*;
+
+    fn joined() -> WatchedEvent {
+        WatchedEvent::Joined {
+            connection_id: 1.into(),
+            player_id: None,
+        }
+    }
+
+    #[test]
+    fn reaches_only_the_rooms_watchers() {
+        let watchers = RoomWatchers::default();
+        let mut watcher = watchers.watch(1_u128.into());
+
+        watchers.publish(1_u128.into(), joined);
+        watchers.publish(2_u128.into(), || unreachable!("Nobody watches room 2"));
+
+        assert_eq!(watcher.try_recv().unwrap(), joined());
+        assert!(watcher.try_recv().is_err());
+    }
+
+    #[test]
+    fn forgets_rooms_nobody_watches_anymore() {
+        let watchers = RoomWatchers::default();
+        drop(watchers.watch(1_u128.into()));
+
+        watchers.publish(1_u128.into(), joined);
+
+        watchers.publish(1_u128.into(), || {
+            unreachable!("Nobody watches room 1 anymore")
+        });
+        assert_eq!(watchers.watched.load(Ordering::Relaxed), 0);
+    }
+}
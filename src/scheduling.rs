@@ -0,0 +1,205 @@
+//! Rooms scheduled to open at a set time, such as for an event announced ahead of time
+//!
+//! A scheduled room exists from the moment it's scheduled, so it can be listed and invited
+//! to, but it's listed with the time it [starts at][crate::game::RoomSummary::starts_at] and
+//! turns players away until then. Its lifecycle only begins once it opens: that's when the
+//! timeout for rooms nobody joins starts, and when a [RoomOpened] event naming its invited
+//! players is published, which [webhooks][crate::webhooks] deliver as `room_opened` so they
+//! can be told to join.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::info;
+
+use crate::clock::unix_time;
+use crate::clock::{system_clock, Clock};
+use crate::game::{PlayerId, RoomCreationError, RoomDeletionQueue, RoomId, RoomRegistry};
+
+/// How far ahead a room can be scheduled to open, which leaves the seats minted for its
+/// invited players an hour after it opens before they run out
+pub const MAX_SCHEDULE_LEAD: Duration = Duration::from_secs(6 * 24 * 60 * 60 + 23 * 60 * 60);
+
+/// How many opened rooms a subscriber may fall behind by before it misses some
+pub const ROOM_OPENED_CAPACITY: usize = 256;
+
+/// A scheduled room has opened
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct RoomOpened {
+    pub room_id: RoomId,
+    /// The players invited to the room when it was scheduled
+    pub invited: Vec<PlayerId>,
+}
+
+/// Raised when a room can't be scheduled
+#[derive(Error, Debug, PartialEq)]
+pub enum ScheduleError {
+    #[error("Rooms must be scheduled to open in the future")]
+    InPast,
+    #[error("Rooms can be scheduled at most {} hours ahead", MAX_SCHEDULE_LEAD.as_secs() / 3600)]
+    TooFarAhead,
+    #[error(transparent)]
+    Creation(#[from] RoomCreationError),
+}
+
+/// Opens scheduled rooms when their time comes
+#[derive(Debug)]
+pub struct RoomScheduler {
+    registry: Arc<RoomRegistry>,
+    deletion_queue: RoomDeletionQueue,
+    /// How long a room can sit empty after opening before it's deleted
+    unjoined_timeout: Duration,
+    opened: Sender<RoomOpened>,
+    /// The clock rooms wait on to open
+    clock: Arc<dyn Clock>,
+}
+
+impl RoomScheduler {
+    pub fn new(
+        registry: Arc<RoomRegistry>,
+        deletion_queue: RoomDeletionQueue,
+        unjoined_timeout: Duration,
+    ) -> Self {
+        Self {
+            registry,
+            deletion_queue,
+            unjoined_timeout,
+            opened: broadcast::channel(ROOM_OPENED_CAPACITY).0,
+            clock: system_clock(),
+        }
+    }
+
+    /// Has rooms wait on `clock` to open rather than the system clock
+    ///
+    /// When rooms open is still given in Unix time, so only how long they wait for it is
+    /// measured on `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Creates a room that opens at `starts_at`, in seconds since the Unix epoch, and is
+    /// private to the `invited` players unless there are none, returning the room along with
+    /// how long until it opens
+    pub fn schedule(
+        &self,
+        starts_at: u64,
+        invited: Vec<PlayerId>,
+    ) -> Result<(RoomId, Duration), ScheduleError> {
+        let now = unix_time();
+        if starts_at <= now {
+            return Err(ScheduleError::InPast);
+        }
+        let lead = Duration::from_secs(starts_at - now);
+        if lead > MAX_SCHEDULE_LEAD {
+            return Err(ScheduleError::TooFarAhead);
+        }
+        let room_id = self.registry.create_room_with(|room| {
+            room.set_starts_at(Some(starts_at));
+            room.set_private(!invited.is_empty());
+        })?;
+        info!(event = "room_scheduled", %room_id, starts_at);
+
+        let registry = self.registry.clone();
+        let deletion_queue = self.deletion_queue.clone();
+        let unjoined_timeout = self.unjoined_timeout;
+        let opened = self.opened.clone();
+        let sleep = self.clock.sleep(lead);
+        tokio::spawn(async move {
+            sleep.await;
+            // The room may have been deleted before it opened
+            let Some(room) = registry.get_room_for_id(room_id) else {
+                return;
+            };
+            room.set_starts_at(None);
+            room.schedule_deletion(room_id, unjoined_timeout, deletion_queue);
+            registry.room_updated(room_id);
+            info!(event = "room_opened", %room_id);
+            // Having nobody to tell is not an error
+            let _ = opened.send(RoomOpened { room_id, invited });
+        });
+        Ok((room_id, lead))
+    }
+
+    /// Receives every room opened from now on
+    pub fn subscribe(&self) -> Receiver<RoomOpened> {
+        self.opened.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod schedule {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy};
+
+    fn setup() -> (Arc<RoomRegistry>, RoomScheduler) {
+        let registry = Arc::new(RoomRegistry::new(4));
+        let (deletion_queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        let scheduler =
+            RoomScheduler::new(registry.clone(), deletion_queue, Duration::from_secs(60));
+        (registry, scheduler)
+    }
+
+    #[tokio::test]
+    async fn keeps_the_room_closed_until_it_starts() {
+        let (registry, scheduler) = setup();
+        let mut opened = scheduler.subscribe();
+        let player = PlayerId::from(1);
+
+        let (room_id, _) = scheduler.schedule(unix_time() + 1, vec![player]).unwrap();
+        let room = registry.get_room_for_id(room_id).unwrap();
+        assert!(!room.is_open());
+        assert!(room.is_private());
+        assert_eq!(registry.list_active_rooms()[0].starts_at, room.starts_at());
+
+        let event = tokio::time::timeout(Duration::from_secs(3), opened.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event,
+            RoomOpened {
+                room_id,
+                invited: vec![player]
+            }
+        );
+        assert!(room.is_open());
+        registry.publish_updates();
+        assert_eq!(registry.list_active_rooms()[0].starts_at, None);
+    }
+
+    #[tokio::test]
+    async fn opens_the_room_when_its_clock_reaches_the_start() {
+        let (registry, scheduler) = setup();
+        let clock = Arc::new(MockClock::new());
+        let scheduler = scheduler.with_clock(clock.clone());
+
+        let (room_id, lead) = scheduler.schedule(unix_time() + 3600, Vec::new()).unwrap();
+        let room = registry.get_room_for_id(room_id).unwrap();
+        clock.advance(lead - Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert!(!room.is_open());
+
+        clock.advance(Duration::from_secs(1));
+        tokio::task::yield_now().await;
+        assert!(room.is_open());
+    }
+
+    #[tokio::test]
+    async fn only_takes_times_in_the_near_future() {
+        let (registry, scheduler) = setup();
+        let schedule = |starts_at| scheduler.schedule(starts_at, Vec::new());
+
+        assert_eq!(schedule(unix_time() - 1), Err(ScheduleError::InPast));
+        assert_eq!(
+            schedule(unix_time() + MAX_SCHEDULE_LEAD.as_secs() + 60),
+            Err(ScheduleError::TooFarAhead)
+        );
+        assert_eq!(registry.room_count(), 0);
+    }
+}
@@ -0,0 +1,677 @@
+//! Revocation of players' sessions, cutting off both their tokens and their live connections,
+//! the rooms each player is connected to and the names they go by, and notices sent to a
+//! player wherever they're connected
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+
+use actix::{Message, Recipient};
+use serde::Serialize;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::clock::unix_time;
+use crate::game::{ConnectionId, PlayerId, RoomId};
+use crate::protocol::ServerMessage;
+
+/// The reason given to connections closed because their session was revoked
+pub const AUTH_REVOKED_REASON: &str = "auth_revoked";
+
+/// The reason given to connections an operator disconnected
+pub const ADMIN_DISCONNECTED_REASON: &str = "admin_disconnected";
+
+/// The reason given to connections closed because their player was banned
+pub const BANNED_REASON: &str = "banned";
+
+/// The reason connections are given when they're kicked from their room
+pub const KICKED_REASON: &str = "kicked";
+
+/// Tells a live connection to close with the reason, its player's session having been
+/// revoked or cut off
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct SessionEnded(pub &'static str);
+
+/// A message for a player rather than a room, sent to each of their live connections
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct PlayerNotice(pub ServerMessage<'static>);
+
+/// Identifies a connection [registered][SessionRegistry::register] with the registry
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct SessionConnectionId(u64);
+
+/// What a connection tells the registry about itself when it's registered
+#[derive(Debug, Clone)]
+pub struct ConnectionDetails {
+    pub room_id: RoomId,
+    /// The connection's ID within its room
+    pub connection_id: ConnectionId,
+    pub protocol_version: u32,
+    /// The name the player's token gives them
+    pub display_name: Option<String>,
+}
+
+/// A live connection of a player
+#[derive(Debug)]
+struct SessionConnection {
+    details: ConnectionDetails,
+    /// When the connection was registered, in seconds since the Unix epoch
+    connected_at: u64,
+    recipient: Recipient<SessionEnded>,
+    notices: Recipient<PlayerNotice>,
+}
+
+/// A live connection of a player, as shown to operators
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct SessionConnectionInfo {
+    pub room_id: RoomId,
+    pub connection_id: ConnectionId,
+    pub protocol_version: u32,
+    /// When the connection was opened, in seconds since the Unix epoch
+    pub connected_at: u64,
+}
+
+/// What's known about a player's sessions on this instance
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct PlayerSessions {
+    pub player_id: PlayerId,
+    /// The name the player's newest connection goes by, unless it was wiped
+    pub display_name: Option<String>,
+    /// Whether an operator wiped the player's display name
+    pub display_name_wiped: bool,
+    /// When the player's sessions were last revoked, in seconds since the Unix epoch
+    pub revoked_at: Option<u64>,
+    /// The player's live connections, oldest first
+    pub connections: Vec<SessionConnectionInfo>,
+}
+
+/// The players whose sessions have been revoked, and the live connections of every player
+///
+/// Revocations and wiped display names are held in memory only, so they don't survive a
+/// restart.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    /// When each player's sessions were last revoked, in seconds since the Unix epoch
+    revoked_at: RwLock<HashMap<PlayerId, u64>>,
+    connections: Mutex<HashMap<PlayerId, HashMap<SessionConnectionId, SessionConnection>>>,
+    /// The players whose display names are no longer shown, whatever their tokens say
+    wiped_names: RwLock<HashSet<PlayerId>>,
+    next_connection_id: AtomicU64,
+}
+
+impl SessionRegistry {
+    /// Whether a token issued to the player at `issued_at` has since been revoked,
+    /// treating tokens that don't say when they were issued as issued before any revocation
+    pub fn is_revoked(&self, player_id: PlayerId, issued_at: Option<u64>) -> bool {
+        self.revoked_at
+            .read()
+            .unwrap()
+            .get(&player_id)
+            .is_some_and(|&revoked_at| issued_at.is_none_or(|issued_at| issued_at <= revoked_at))
+    }
+
+    /// Tracks a live connection of the player to a room so it can be closed if their session
+    /// is revoked, and sent notices meant for the player
+    pub fn register(
+        &self,
+        player_id: PlayerId,
+        details: ConnectionDetails,
+        connection: Recipient<SessionEnded>,
+        notices: Recipient<PlayerNotice>,
+    ) -> SessionConnectionId {
+        let id = SessionConnectionId(self.next_connection_id.fetch_add(1, Ordering::Relaxed));
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(player_id)
+            .or_default()
+            .insert(
+                id,
+                SessionConnection {
+                    details,
+                    connected_at: unix_time(),
+                    recipient: connection,
+                    notices,
+                },
+            );
+        id
+    }
+
+    pub fn unregister(&self, player_id: PlayerId, id: SessionConnectionId) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(player_connections) = connections.get_mut(&player_id) {
+            player_connections.remove(&id);
+            if player_connections.is_empty() {
+                connections.remove(&player_id);
+            }
+        }
+    }
+
+    /// The rooms the player has live connections to
+    pub fn rooms_of(&self, player_id: PlayerId) -> Vec<RoomId> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&player_id)
+            .map(|connections| distinct_rooms(connections.values()))
+            .unwrap_or_default()
+    }
+
+    /// Every player with a live connection and the rooms they're connected to
+    pub fn online_players(&self) -> HashMap<PlayerId, Vec<RoomId>> {
+        self.connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&player_id, connections)| (player_id, distinct_rooms(connections.values())))
+            .collect()
+    }
+
+    /// Sends the message to each of the player's live connections on this instance, returning
+    /// how many were sent it
+    pub fn notify(&self, player_id: PlayerId, message: ServerMessage<'static>) -> usize {
+        let connections = self.connections.lock().unwrap();
+        let Some(connections) = connections.get(&player_id) else {
+            return 0;
+        };
+        for connection in connections.values() {
+            connection.notices.do_send(PlayerNotice(message.clone()));
+        }
+        connections.len()
+    }
+
+    /// What's known about the player's sessions, whether or not they're connected
+    pub fn player(&self, player_id: PlayerId) -> PlayerSessions {
+        let connections = self.connections.lock().unwrap();
+        self.describe(player_id, connections.get(&player_id))
+    }
+
+    /// The connected players whose display name contains `query`, ignoring case
+    pub fn find_by_display_name(&self, query: &str) -> Vec<PlayerSessions> {
+        let query = query.to_lowercase();
+        let connections = self.connections.lock().unwrap();
+        let mut found: Vec<_> = connections
+            .iter()
+            .map(|(&player_id, player_connections)| {
+                self.describe(player_id, Some(player_connections))
+            })
+            .filter(|player| {
+                player
+                    .display_name
+                    .as_ref()
+                    .is_some_and(|name| name.to_lowercase().contains(&query))
+            })
+            .collect();
+        found.sort_unstable_by_key(|player| player.player_id);
+        found
+    }
+
+    fn describe(
+        &self,
+        player_id: PlayerId,
+        connections: Option<&HashMap<SessionConnectionId, SessionConnection>>,
+    ) -> PlayerSessions {
+        let mut connections: Vec<_> = connections.into_iter().flatten().collect();
+        connections.sort_unstable_by_key(|(id, connection)| (connection.connected_at, id.0));
+        let connections: Vec<_> = connections
+            .into_iter()
+            .map(|(_, connection)| connection)
+            .collect();
+        let display_name_wiped = self.wiped_names.read().unwrap().contains(&player_id);
+        let display_name = connections
+            .last()
+            .and_then(|connection| connection.details.display_name.clone())
+            .filter(|_| !display_name_wiped);
+        PlayerSessions {
+            player_id,
+            display_name,
+            display_name_wiped,
+            revoked_at: self.revoked_at.read().unwrap().get(&player_id).copied(),
+            connections: connections
+                .into_iter()
+                .map(|connection| SessionConnectionInfo {
+                    room_id: connection.details.room_id,
+                    connection_id: connection.details.connection_id,
+                    protocol_version: connection.details.protocol_version,
+                    connected_at: connection.connected_at,
+                })
+                .collect(),
+        }
+    }
+
+    /// Stops showing the display name the player's tokens give them, returning whether one
+    /// was being shown
+    pub fn wipe_display_name(&self, player_id: PlayerId, actor: &str) -> bool {
+        let shown = self.player(player_id).display_name.is_some();
+        self.wiped_names.write().unwrap().insert(player_id);
+        info!(event = "display_name_wiped", player_id = %player_id, actor, shown);
+        shown
+    }
+
+    /// Rejects every token issued to the player until now and tells their live connections
+    /// to close, returning how many connections were told
+    pub fn revoke(&self, player_id: PlayerId, actor: &str) -> usize {
+        self.revoked_at
+            .write()
+            .unwrap()
+            .insert(player_id, unix_time());
+
+        let closed_connections = self.close_connections(player_id, AUTH_REVOKED_REASON);
+        info!(
+            event = "sessions_revoked",
+            player_id = %player_id,
+            actor,
+            closed_connections
+        );
+        closed_connections
+    }
+
+    /// Tells the player's live connections to close with the reason, leaving them free to
+    /// connect again, and returns how many were told
+    pub fn disconnect(&self, player_id: PlayerId, reason: &'static str, actor: &str) -> usize {
+        let closed_connections = self.close_connections(player_id, reason);
+        info!(
+            event = "player_disconnected",
+            player_id = %player_id,
+            reason,
+            actor,
+            closed_connections
+        );
+        closed_connections
+    }
+
+    /// Closes the player's connections to the room, telling them `reason`, and returns how
+    /// many were closed
+    pub fn disconnect_from_room(
+        &self,
+        player_id: PlayerId,
+        room_id: RoomId,
+        reason: &'static str,
+    ) -> usize {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(player_connections) = connections.get_mut(&player_id) else {
+            return 0;
+        };
+        let before = player_connections.len();
+        player_connections.retain(|_, connection| {
+            let in_room = connection.details.room_id == room_id;
+            if in_room {
+                connection.recipient.do_send(SessionEnded(reason));
+            }
+            !in_room
+        });
+        let closed = before - player_connections.len();
+        if player_connections.is_empty() {
+            connections.remove(&player_id);
+        }
+        closed
+    }
+
+    fn close_connections(&self, player_id: PlayerId, reason: &'static str) -> usize {
+        let connections = self
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&player_id)
+            .unwrap_or_default();
+        for connection in connections.values() {
+            connection.recipient.do_send(SessionEnded(reason));
+        }
+        connections.len()
+    }
+}
+
+fn distinct_rooms<'a>(connections: impl Iterator<Item = &'a SessionConnection>) -> Vec<RoomId> {
+    let mut rooms: Vec<_> = connections
+        .map(|connection| connection.details.room_id)
+        .collect();
+    rooms.sort_unstable();
+    rooms.dedup();
+    rooms
+}
+
+/// The details of a connection to the room, of a player going by "Ada"
+#[cfg(test)]
+fn details(room_id: u128) -> ConnectionDetails {
+    ConnectionDetails {
+        room_id: room_id.into(),
+        connection_id: 1.into(),
+        protocol_version: crate::protocol::PROTOCOL_VERSION,
+        display_name: Some("Ada".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod is_revoked {
+    use super::*;
+
+    #[test]
+    fn rejects_tokens_issued_before_revocation() {
+        let sessions = SessionRegistry::default();
+        let now = unix_time();
+        sessions.revoke(1.into(), "api");
+
+        assert!(sessions.is_revoked(1.into(), Some(now - 60)));
+        assert!(sessions.is_revoked(1.into(), None));
+        assert!(!sessions.is_revoked(1.into(), Some(now + 60)));
+        assert!(!sessions.is_revoked(2.into(), None));
+    }
+}
+
+#[cfg(test)]
+mod revoke {
+    use super::*;
+    use actix::{Actor, Context, Handler};
+    use std::sync::Arc;
+
+    struct Connection(Arc<AtomicU64>);
+
+    impl Actor for Connection {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEnded> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: SessionEnded, _: &mut Self::Context) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    impl Handler<PlayerNotice> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: PlayerNotice, _: &mut Self::Context) {}
+    }
+
+    fn register(
+        sessions: &SessionRegistry,
+        player_id: PlayerId,
+        connection: &actix::Addr<Connection>,
+    ) -> SessionConnectionId {
+        sessions.register(
+            player_id,
+            details(1),
+            connection.clone().recipient(),
+            connection.clone().recipient(),
+        )
+    }
+
+    #[actix_web::test]
+    async fn notifies_only_the_players_live_connections() {
+        let sessions = SessionRegistry::default();
+        let notified = Arc::new(AtomicU64::new(0));
+        let connection = Connection(notified.clone()).start();
+        register(&sessions, 1.into(), &connection);
+        let closed = register(&sessions, 1.into(), &connection);
+        sessions.unregister(1.into(), closed);
+        register(&sessions, 2.into(), &connection);
+
+        assert_eq!(sessions.revoke(1.into(), "api"), 1);
+        connection
+            .send(SessionEnded(AUTH_REVOKED_REASON))
+            .await
+            .unwrap();
+
+        assert_eq!(notified.load(Ordering::Relaxed), 2);
+        assert_eq!(sessions.revoke(1.into(), "api"), 0);
+    }
+}
+
+#[cfg(test)]
+mod rooms_of {
+    use super::*;
+    use actix::{Actor, Context, Handler};
+
+    struct Connection;
+
+    impl Actor for Connection {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEnded> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: SessionEnded, _: &mut Self::Context) {}
+    }
+
+    impl Handler<PlayerNotice> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: PlayerNotice, _: &mut Self::Context) {}
+    }
+
+    #[actix_web::test]
+    async fn lists_each_room_with_a_live_connection_once() {
+        let sessions = SessionRegistry::default();
+        let connection = Connection.start();
+        let register = |room_id: u128| {
+            sessions.register(
+                1.into(),
+                details(room_id),
+                connection.clone().recipient(),
+                connection.clone().recipient(),
+            )
+        };
+        register(2);
+        register(1);
+        register(2);
+        let closed = register(3);
+        sessions.unregister(1.into(), closed);
+
+        assert_eq!(
+            sessions.rooms_of(1.into()),
+            vec![1_u128.into(), 2_u128.into()]
+        );
+        assert!(sessions.rooms_of(2.into()).is_empty());
+        assert_eq!(sessions.online_players().len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod notify {
+    use super::*;
+    use actix::{Actor, Context, Handler};
+    use std::sync::Arc;
+
+    struct Connection(Arc<Mutex<Vec<ServerMessage<'static>>>>);
+
+    impl Actor for Connection {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEnded> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: SessionEnded, _: &mut Self::Context) {}
+    }
+
+    impl Handler<PlayerNotice> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, notice: PlayerNotice, _: &mut Self::Context) {
+            self.0.lock().unwrap().push(notice.0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn sends_the_message_to_every_connection_of_the_player() {
+        let sessions = SessionRegistry::default();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let connection = Connection(received.clone()).start();
+        for room_id in [1_u128, 2] {
+            sessions.register(
+                1.into(),
+                details(room_id),
+                connection.clone().recipient(),
+                connection.clone().recipient(),
+            );
+        }
+        let message = ServerMessage::Error {
+            reason: "hello".to_owned(),
+        };
+
+        assert_eq!(sessions.notify(1.into(), message.clone()), 2);
+        assert_eq!(sessions.notify(2.into(), message.clone()), 0);
+        connection
+            .send(SessionEnded(AUTH_REVOKED_REASON))
+            .await
+            .unwrap();
+
+        assert_eq!(*received.lock().unwrap(), [message.clone(), message]);
+    }
+}
+
+#[cfg(test)]
+mod find_by_display_name {
+    use super::*;
+    use actix::{Actor, Context, Handler};
+
+    struct Connection;
+
+    impl Actor for Connection {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEnded> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: SessionEnded, _: &mut Self::Context) {}
+    }
+
+    impl Handler<PlayerNotice> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: PlayerNotice, _: &mut Self::Context) {}
+    }
+
+    fn register(sessions: &SessionRegistry, player_id: u128, name: &str) {
+        let connection = Connection.start();
+        sessions.register(
+            player_id.into(),
+            ConnectionDetails {
+                display_name: Some(name.to_owned()),
+                ..details(1)
+            },
+            connection.clone().recipient(),
+            connection.recipient(),
+        );
+    }
+
+    #[actix_web::test]
+    async fn matches_part_of_the_name_ignoring_case() {
+        let sessions = SessionRegistry::default();
+        register(&sessions, 1, "Ada Lovelace");
+        register(&sessions, 2, "Grace Hopper");
+        register(&sessions, 3, "Adam");
+
+        let found: Vec<_> = sessions
+            .find_by_display_name("ADA")
+            .into_iter()
+            .map(|player| player.player_id)
+            .collect();
+
+        assert_eq!(found, [1.into(), 3.into()]);
+    }
+
+    #[actix_web::test]
+    async fn leaves_out_wiped_names() {
+        let sessions = SessionRegistry::default();
+        register(&sessions, 1, "Ada Lovelace");
+
+        assert!(sessions.wipe_display_name(1.into(), "api"));
+
+        assert!(sessions.find_by_display_name("ada").is_empty());
+        let player = sessions.player(1.into());
+        assert_eq!(player.display_name, None);
+        assert!(player.display_name_wiped);
+        assert_eq!(player.connections.len(), 1);
+        assert!(!sessions.wipe_display_name(1.into(), "api"));
+    }
+}
+
+#[cfg(test)]
+mod disconnect {
+    use super::*;
+    use actix::{Actor, Context, Handler};
+    use std::sync::Arc;
+
+    struct Connection(Arc<Mutex<Vec<&'static str>>>);
+
+    impl Actor for Connection {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<SessionEnded> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, SessionEnded(reason): SessionEnded, _: &mut Self::Context) {
+            self.0.lock().unwrap().push(reason);
+        }
+    }
+
+    impl Handler<PlayerNotice> for Connection {
+        type Result = ();
+
+        fn handle(&mut self, _: PlayerNotice, _: &mut Self::Context) {}
+    }
+
+    #[actix_web::test]
+    async fn closes_connections_without_revoking_the_session() {
+        let sessions = SessionRegistry::default();
+        let reasons = Arc::new(Mutex::new(Vec::new()));
+        let connection = Connection(reasons.clone()).start();
+        sessions.register(
+            1.into(),
+            details(1),
+            connection.clone().recipient(),
+            connection.clone().recipient(),
+        );
+
+        assert_eq!(
+            sessions.disconnect(1.into(), ADMIN_DISCONNECTED_REASON, "api"),
+            1
+        );
+        connection
+            .send(PlayerNotice(ServerMessage::Error {
+                reason: "flush".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(*reasons.lock().unwrap(), [ADMIN_DISCONNECTED_REASON]);
+        assert!(!sessions.is_revoked(1.into(), None));
+        assert!(sessions.player(1.into()).connections.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn closes_only_the_connections_to_the_room() {
+        let sessions = SessionRegistry::default();
+        let reasons = Arc::new(Mutex::new(Vec::new()));
+        let connection = Connection(reasons.clone()).start();
+        for room_id in [1, 2] {
+            sessions.register(
+                1.into(),
+                details(room_id),
+                connection.clone().recipient(),
+                connection.clone().recipient(),
+            );
+        }
+
+        assert_eq!(
+            sessions.disconnect_from_room(1.into(), 1_u128.into(), KICKED_REASON),
+            1
+        );
+        connection
+            .send(PlayerNotice(ServerMessage::Error {
+                reason: "flush".to_owned(),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(*reasons.lock().unwrap(), [KICKED_REASON]);
+        assert_eq!(sessions.rooms_of(1.into()), [RoomId::from(2_u128)]);
+    }
+}
@@ -0,0 +1,296 @@
+//! Automatic muting of players spamming chat
+//!
+//! Every line of chat a signed in player sends is checked against their recent lines: saying
+//! the same thing too many times, or saying too much at all, within the [window][SpamRules]
+//! mutes the player in the room. Each time a player is muted the mute lasts longer, until
+//! they've kept quiet for [STRIKE_MEMORY]. Mutes are published as they happen so moderators
+//! can review them. Anonymous connections can't be muted, their chat budget holds them back
+//! instead.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::info;
+
+use crate::game::{PlayerId, RoomId};
+
+/// How long a player's mutes count towards the length of their next one
+pub const STRIKE_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// How many automatic mutes a subscriber may fall behind by before it misses some
+const AUTO_MUTES_CAPACITY: usize = 64;
+
+/// When chat counts as spam, and how long spammers are muted for
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpamRules {
+    /// How long lines of chat count towards the limits below
+    pub window: Duration,
+    /// How many times a player can say the same thing within the window
+    pub max_repeats: usize,
+    /// How many lines a player can say within the window
+    pub max_lines: usize,
+    /// How long a player's first mute lasts, then their second, and so on, the last
+    /// lasting for every mute after it
+    pub mute_durations: Vec<Duration>,
+}
+
+impl Default for SpamRules {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            max_repeats: 3,
+            max_lines: 10,
+            mute_durations: [30, 120, 600, 3600]
+                .into_iter()
+                .map(Duration::from_secs)
+                .collect(),
+        }
+    }
+}
+
+/// What gave a spammer away
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpamKind {
+    /// Saying the same thing over and over
+    Repeated,
+    /// Saying too much too quickly
+    Flooding,
+}
+
+impl std::fmt::Display for SpamKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Repeated => "repeating yourself",
+            Self::Flooding => "flooding the chat",
+        })
+    }
+}
+
+/// A player muted for spamming
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct AutoMute {
+    pub player_id: PlayerId,
+    pub room_id: RoomId,
+    pub kind: SpamKind,
+    /// How many times the player has been muted without keeping quiet for long in between,
+    /// this mute included
+    pub strike: u32,
+    pub duration_secs: u64,
+}
+
+#[derive(Debug)]
+struct Speaker {
+    /// When each of the player's lines within the window was said, oldest first, along
+    /// with what was said, folded to lowercase
+    lines: VecDeque<(Instant, String)>,
+    strikes: u32,
+    last_strike: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Speakers {
+    by_player: HashMap<PlayerId, Speaker>,
+    /// When players with nothing worth keeping were last forgotten
+    swept_at: Option<Instant>,
+}
+
+/// Keeps track of what players have said recently, muting those who spam
+#[derive(Debug)]
+pub struct SpamGuard {
+    rules: SpamRules,
+    speakers: Mutex<Speakers>,
+    muted: Sender<AutoMute>,
+}
+
+impl Default for SpamGuard {
+    fn default() -> Self {
+        Self::new(SpamRules::default())
+    }
+}
+
+impl SpamGuard {
+    pub fn new(rules: SpamRules) -> Self {
+        Self {
+            rules,
+            speakers: Default::default(),
+            muted: broadcast::channel(AUTO_MUTES_CAPACITY).0,
+        }
+    }
+
+    /// Receives every automatic mute from now on
+    pub fn subscribe(&self) -> Receiver<AutoMute> {
+        self.muted.subscribe()
+    }
+
+    /// Notes the player saying the text in the room at `now`, returning the mute they've
+    /// earned if it makes them a spammer
+    pub fn check(
+        &self,
+        player_id: PlayerId,
+        room_id: RoomId,
+        text: &str,
+        now: Instant,
+    ) -> Option<AutoMute> {
+        let mut speakers = self.speakers.lock().unwrap();
+        if speakers
+            .swept_at
+            .is_none_or(|swept_at| now.duration_since(swept_at) >= self.rules.window)
+        {
+            // Players who have neither spoken nor been muted lately have nothing worth keeping
+            speakers.by_player.retain(|_, speaker| {
+                speaker
+                    .lines
+                    .back()
+                    .is_some_and(|&(said_at, _)| now.duration_since(said_at) < self.rules.window)
+                    || speaker
+                        .last_strike
+                        .is_some_and(|struck_at| now.duration_since(struck_at) < STRIKE_MEMORY)
+            });
+            speakers.swept_at = Some(now);
+        }
+        let speaker = speakers
+            .by_player
+            .entry(player_id)
+            .or_insert_with(|| Speaker {
+                lines: VecDeque::new(),
+                strikes: 0,
+                last_strike: None,
+            });
+        while speaker
+            .lines
+            .front()
+            .is_some_and(|&(said_at, _)| now.duration_since(said_at) >= self.rules.window)
+        {
+            speaker.lines.pop_front();
+        }
+        let text = text.trim().to_lowercase();
+        let repeats = speaker
+            .lines
+            .iter()
+            .filter(|(_, said)| *said == text)
+            .count()
+            + 1;
+        speaker.lines.push_back((now, text));
+
+        let kind = if repeats > self.rules.max_repeats {
+            SpamKind::Repeated
+        } else if speaker.lines.len() > self.rules.max_lines {
+            SpamKind::Flooding
+        } else {
+            return None;
+        };
+        if speaker
+            .last_strike
+            .is_none_or(|struck_at| now.duration_since(struck_at) >= STRIKE_MEMORY)
+        {
+            speaker.strikes = 0;
+        }
+        speaker.strikes += 1;
+        speaker.last_strike = Some(now);
+        speaker.lines.clear();
+        let duration = self
+            .rules
+            .mute_durations
+            .get(speaker.strikes as usize - 1)
+            .or(self.rules.mute_durations.last())
+            .copied()
+            .unwrap_or_default();
+        let mute = AutoMute {
+            player_id,
+            room_id,
+            kind,
+            strike: speaker.strikes,
+            duration_secs: duration.as_secs(),
+        };
+        drop(speakers);
+
+        info!(
+            event = "player_auto_muted",
+            %player_id,
+            %room_id,
+            ?kind,
+            strike = mute.strike,
+            duration_secs = mute.duration_secs
+        );
+        // Having nobody following the mutes is not an error
+        let _ = self.muted.send(mute.clone());
+        Some(mute)
+    }
+}
+
+#[cfg(test)]
+mod check {
+    use super::*;
+
+    fn guard() -> SpamGuard {
+        SpamGuard::new(SpamRules {
+            window: Duration::from_secs(10),
+            max_repeats: 2,
+            max_lines: 4,
+            mute_durations: vec![Duration::from_secs(30), Duration::from_secs(120)],
+        })
+    }
+
+    fn say(guard: &SpamGuard, text: &str, at: Instant) -> Option<AutoMute> {
+        guard.check(1.into(), 1_u128.into(), text, at)
+    }
+
+    #[test]
+    fn mutes_players_repeating_themselves() {
+        let guard = guard();
+        let now = Instant::now();
+
+        assert_eq!(say(&guard, "gg", now), None);
+        assert_eq!(say(&guard, "GG ", now), None);
+        let mute = say(&guard, "gg", now).unwrap();
+
+        assert_eq!(mute.kind, SpamKind::Repeated);
+        assert_eq!(mute.duration_secs, 30);
+    }
+
+    #[test]
+    fn mutes_players_flooding_the_chat() {
+        let guard = guard();
+        let now = Instant::now();
+
+        for line in ["a", "b", "c", "d"] {
+            assert_eq!(say(&guard, line, now), None);
+        }
+
+        assert_eq!(say(&guard, "e", now).unwrap().kind, SpamKind::Flooding);
+    }
+
+    #[test]
+    fn forgets_lines_older_than_the_window() {
+        let guard = guard();
+        let now = Instant::now();
+
+        say(&guard, "gg", now);
+        say(&guard, "gg", now);
+
+        assert_eq!(say(&guard, "gg", now + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn mutes_repeat_offenders_for_longer_until_they_keep_quiet() {
+        let guard = guard();
+        let mut muted = guard.subscribe();
+        let now = Instant::now();
+        let spam = |at| (0..3).find_map(|_| say(&guard, "buy gold", at)).unwrap();
+
+        assert_eq!(spam(now).duration_secs, 30);
+        let second = spam(now + Duration::from_secs(60));
+        assert_eq!((second.strike, second.duration_secs), (2, 120));
+        assert_eq!(spam(now + Duration::from_secs(120)).duration_secs, 120);
+        assert_eq!(
+            spam(now + Duration::from_secs(120) + STRIKE_MEMORY).duration_secs,
+            30
+        );
+
+        assert_eq!(muted.try_recv().unwrap().strike, 1);
+    }
+}
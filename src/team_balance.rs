@@ -0,0 +1,184 @@
+//! Spreading a room's players evenly across its teams
+//!
+//! Players who are in a [party][crate::parties] together are kept on the same team, so
+//! friends who came to play together still do. Each party, and each player on their own, is
+//! placed on whichever team has the fewest players so far, the lowest rated of those breaking
+//! ties, biggest parties first and strongest first among parties of a size. That keeps the
+//! teams' sizes within a party's size of each other and their ratings close.
+
+use std::collections::{BTreeMap, HashMap};
+
+use thiserror::Error;
+
+use crate::game::PlayerId;
+use crate::parties::PartyId;
+
+/// Raised when a room's players can't be balanced across teams
+#[derive(Error, Debug, PartialEq)]
+pub enum TeamBalanceError {
+    #[error("Players can only be balanced across two or more teams")]
+    TooFewTeams,
+    #[error("Team names can't be empty")]
+    EmptyTeamName,
+}
+
+/// A player to be placed on a team
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TeamCandidate {
+    pub player_id: PlayerId,
+    /// The player's rating in the room's game type
+    pub rating: f64,
+    /// The party the player is in, if any
+    pub party: Option<PartyId>,
+}
+
+#[derive(Debug)]
+struct Group {
+    players: Vec<PlayerId>,
+    rating: f64,
+}
+
+/// Which of the `teams` each of the `players` is placed on
+pub fn balance_teams(
+    players: &[TeamCandidate],
+    teams: &[String],
+) -> Result<BTreeMap<PlayerId, String>, TeamBalanceError> {
+    if teams.iter().any(String::is_empty) {
+        return Err(TeamBalanceError::EmptyTeamName);
+    }
+    let mut names: Vec<&String> = teams.iter().collect();
+    names.sort();
+    names.dedup();
+    if names.len() < 2 {
+        return Err(TeamBalanceError::TooFewTeams);
+    }
+
+    let mut parties: HashMap<PartyId, Group> = HashMap::new();
+    let mut groups = Vec::new();
+    for candidate in players {
+        let group = match candidate.party {
+            Some(party) => parties.entry(party).or_insert_with(|| Group {
+                players: Vec::new(),
+                rating: 0.0,
+            }),
+            None => {
+                groups.push(Group {
+                    players: Vec::new(),
+                    rating: 0.0,
+                });
+                groups.last_mut().unwrap()
+            }
+        };
+        group.players.push(candidate.player_id);
+        group.rating += candidate.rating;
+    }
+    groups.extend(parties.into_values());
+    groups.iter_mut().for_each(|group| group.players.sort());
+    groups.sort_by(|a, b| {
+        b.players
+            .len()
+            .cmp(&a.players.len())
+            .then(b.rating.total_cmp(&a.rating))
+            .then(a.players.cmp(&b.players))
+    });
+
+    // The number of players on each team so far, along with their total rating
+    let mut totals = vec![(0, 0.0_f64); names.len()];
+    let mut placed = BTreeMap::new();
+    for group in groups {
+        let (team, total) = totals
+            .iter_mut()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+            .unwrap();
+        total.0 += group.players.len();
+        total.1 += group.rating;
+        for player_id in group.players {
+            placed.insert(player_id, names[team].clone());
+        }
+    }
+    Ok(placed)
+}
+
+#[cfg(test)]
+mod balance_teams {
+    use super::*;
+    use crate::parties::PartyRegistry;
+
+    fn teams() -> Vec<String> {
+        vec!["red".to_owned(), "blue".to_owned()]
+    }
+
+    fn solo(id: u128, rating: f64) -> TeamCandidate {
+        TeamCandidate {
+            player_id: id.into(),
+            rating,
+            party: None,
+        }
+    }
+
+    fn team_of(placed: &BTreeMap<PlayerId, String>, id: u128) -> &str {
+        &placed[&PlayerId::from(id)]
+    }
+
+    #[test]
+    fn evens_out_ratings() {
+        let players = [
+            solo(1, 1800.0),
+            solo(2, 1700.0),
+            solo(3, 1300.0),
+            solo(4, 1200.0),
+        ];
+
+        let placed = balance_teams(&players, &teams()).unwrap();
+
+        assert_eq!(team_of(&placed, 1), team_of(&placed, 4));
+        assert_eq!(team_of(&placed, 2), team_of(&placed, 3));
+        assert_ne!(team_of(&placed, 1), team_of(&placed, 2));
+    }
+
+    #[test]
+    fn keeps_parties_together() {
+        let party = Some(PartyRegistry::default().create(1.into()).unwrap().id);
+        let players = [
+            TeamCandidate {
+                party,
+                ..solo(1, 1800.0)
+            },
+            TeamCandidate {
+                party,
+                ..solo(2, 1800.0)
+            },
+            solo(3, 1200.0),
+            solo(4, 1200.0),
+        ];
+
+        let placed = balance_teams(&players, &teams()).unwrap();
+
+        assert_eq!(team_of(&placed, 1), team_of(&placed, 2));
+        assert_eq!(team_of(&placed, 3), team_of(&placed, 4));
+        assert_ne!(team_of(&placed, 1), team_of(&placed, 3));
+    }
+
+    #[test]
+    fn keeps_team_sizes_even() {
+        let players: Vec<_> = (1..=5).map(|id| solo(id, 1500.0)).collect();
+
+        let placed = balance_teams(&players, &teams()).unwrap();
+
+        let red = placed.values().filter(|team| *team == "red").count();
+        assert!(red == 2 || red == 3, "{placed:?}");
+    }
+
+    #[test]
+    fn needs_two_named_teams() {
+        assert_eq!(
+            balance_teams(&[solo(1, 1500.0)], &["red".to_owned(), "red".to_owned()]),
+            Err(TeamBalanceError::TooFewTeams)
+        );
+        assert_eq!(
+            balance_teams(&[solo(1, 1500.0)], &["red".to_owned(), String::new()]),
+            Err(TeamBalanceError::EmptyTeamName)
+        );
+    }
+}
@@ -0,0 +1,884 @@
+//! Running the server in-process and driving fake clients against it, for end-to-end tests of
+//! games built on it
+//!
+//! A [TestServer] listens on an ephemeral port on the loopback interface, with every
+//! dependency kept in memory and the REST API left open, and [TestClient]s join its rooms
+//! over the real WebSocket protocol. A test is a script of what each client sends and
+//! expects to receive:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use serde_json::json;
+//! use wormhole::protocol::{ClientMessage, ServerMessage};
+//! use wormhole::test_support::TestServer;
+//!
+//! let server = TestServer::start().await;
+//! let room_id = server.create_room().await;
+//! let mut alice = server.connect(room_id).await;
+//! let mut bob = server.connect(room_id).await;
+//!
+//! alice.send(ClientMessage::Broadcast { payload: json!({ "move": "e4" }) }).await;
+//! let message = bob
+//!     .recv_matching(|message| matches!(message, ServerMessage::Broadcast { .. }))
+//!     .await;
+//! # }
+//! ```
+//!
+//! Clients connect anonymously, since the server isn't configured with an identity provider.
+//! Servers [started with][TestServerBuilder::with_clock] a [MockClock][crate::clock::MockClock]
+//! only time out rooms, open scheduled ones and run matchmaking rounds as the test advances it.
+//! Only built with the `test-support` feature.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::ServerHandle;
+use actix_web::{middleware::from_fn, web, App, HttpServer};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::abuse::AbuseRules;
+use crate::auth::{self, ApiTokens};
+use crate::clock::{system_clock, Clock};
+use crate::cluster::RemoteRooms;
+use crate::config::secrets::Reloadable;
+use crate::content_filter::{ContentFilter, ContentFilters};
+use crate::game::{
+    room_creation_channel, room_deletion_channel, ConnectionId, DeletionOverflowPolicy, PlayerId,
+    RegistryHandle, RoomId, RoomIdProvider, RoomRegistry, StateCodecs, UuidRoomIds,
+    DEFAULT_REGISTRY_LOCK_TIMEOUT,
+};
+use crate::identity::{test_authenticator, token, TEST_ISSUER};
+use crate::invites::InviteSigner;
+use crate::kick_votes::KickVotes;
+use crate::leaderboards::Leaderboards;
+use crate::matchmaking::{Matchmaker, MatchmakingRules};
+use crate::panics::catch_panics;
+use crate::presence::PresenceFeed;
+use crate::protocol::{ClientEnvelope, ClientMessage, ServerMessage, PROTOCOL_VERSION};
+use crate::rate_limit::{self, RateLimiter};
+use crate::recording::{recording_channel, RECORDING_QUEUE_CAPACITY};
+use crate::request_body::{limit_request_bodies, BodyLimits};
+use crate::request_timeout::{enforce_request_timeout, RequestTimeout, DEFAULT_REQUEST_TIMEOUT};
+use crate::scheduling::RoomScheduler;
+use crate::webhooks::RoomWebhooks;
+use crate::{api, ws, SharedAppState};
+
+/// How long a client waits for a message before failing the test
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for a [TestServer], which otherwise runs with the server's defaults
+#[derive(Debug, Clone)]
+pub struct TestServerBuilder {
+    room_capacity: Option<usize>,
+    room_memory_limit: Option<usize>,
+    unjoined_timeout: Duration,
+    broadcast_flush_interval: Duration,
+    spectator_delay: Duration,
+    abuse_rules: AbuseRules,
+    matchmaking_rules: MatchmakingRules,
+    clock: Arc<dyn Clock>,
+    room_id_provider: Arc<dyn RoomIdProvider>,
+    recording_dir: Option<PathBuf>,
+    room_webhooks: Option<Arc<RoomWebhooks>>,
+    signed_in_players: bool,
+    api_tokens: ApiTokens,
+    state_codecs: StateCodecs,
+    content_filter: Option<Arc<dyn ContentFilter>>,
+}
+
+impl Default for TestServerBuilder {
+    fn default() -> Self {
+        Self {
+            room_capacity: None,
+            room_memory_limit: None,
+            unjoined_timeout: Duration::from_secs(60),
+            broadcast_flush_interval: Duration::ZERO,
+            spectator_delay: Duration::ZERO,
+            abuse_rules: Default::default(),
+            matchmaking_rules: MatchmakingRules::new(2, 2, Duration::from_secs(30)),
+            clock: system_clock(),
+            room_id_provider: Arc::new(UuidRoomIds),
+            recording_dir: None,
+            room_webhooks: None,
+            signed_in_players: false,
+            api_tokens: ApiTokens::default(),
+            state_codecs: StateCodecs::default(),
+            content_filter: None,
+        }
+    }
+}
+
+impl TestServerBuilder {
+    /// Turns players away from rooms holding `capacity` of them
+    pub fn with_room_capacity(mut self, capacity: usize) -> Self {
+        self.room_capacity = Some(capacity);
+        self
+    }
+
+    /// Caps the memory each room may hold, in bytes
+    pub fn with_room_memory_limit(mut self, limit: usize) -> Self {
+        self.room_memory_limit = Some(limit);
+        self
+    }
+
+    /// Deletes rooms left empty for `timeout`
+    pub fn with_unjoined_timeout(mut self, timeout: Duration) -> Self {
+        self.unjoined_timeout = timeout;
+        self
+    }
+
+    /// Holds broadcasts back for `interval` so they're sent in batches
+    pub fn with_broadcast_flush_interval(mut self, interval: Duration) -> Self {
+        self.broadcast_flush_interval = interval;
+        self
+    }
+
+    /// Keeps spectators `delay` behind the players
+    pub fn with_spectator_delay(mut self, delay: Duration) -> Self {
+        self.spectator_delay = delay;
+        self
+    }
+
+    /// Acts on clients abusing the server by the `rules`
+    pub fn with_abuse_rules(mut self, rules: AbuseRules) -> Self {
+        self.abuse_rules = rules;
+        self
+    }
+
+    pub fn with_matchmaking_rules(mut self, rules: MatchmakingRules) -> Self {
+        self.matchmaking_rules = rules;
+        self
+    }
+
+    /// Has the server choose the identifiers of the rooms it creates with `provider`, such as
+    /// [SeededRoomIds][crate::game::SeededRoomIds] so they're known ahead of time
+    pub fn with_room_id_provider(mut self, provider: Arc<dyn RoomIdProvider>) -> Self {
+        self.room_id_provider = provider;
+        self
+    }
+
+    /// Records the traffic in every room to files in `dir`, as the server does when
+    /// `WORMHOLE_RECORDING_DIR` is set
+    pub fn with_recording_dir(mut self, dir: PathBuf) -> Self {
+        self.recording_dir = Some(dir);
+        self
+    }
+
+    /// Registers the webhooks rooms are created with in `room_webhooks`, as the server does
+    /// when it delivers webhooks
+    pub fn with_room_webhooks(mut self, room_webhooks: Arc<RoomWebhooks>) -> Self {
+        self.room_webhooks = Some(room_webhooks);
+        self
+    }
+
+    /// Has players sign in with the tokens [TestServer::connect_as] joins rooms with, which
+    /// are otherwise ignored
+    pub fn with_signed_in_players(mut self) -> Self {
+        self.signed_in_players = true;
+        self
+    }
+
+    /// Turns away requests to the API that don't carry one of `api_tokens`
+    pub fn with_api_tokens(mut self, api_tokens: ApiTokens) -> Self {
+        self.api_tokens = api_tokens;
+        self
+    }
+
+    /// Encodes game states and broadcasts with `state_codecs`
+    pub fn with_state_codecs(mut self, state_codecs: StateCodecs) -> Self {
+        self.state_codecs = state_codecs;
+        self
+    }
+
+    /// Passes chat and display names through `filter`, which otherwise go unfiltered
+    pub fn with_content_filter(mut self, filter: impl ContentFilter + 'static) -> Self {
+        self.content_filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Runs the server's timers on `clock`, such as a [MockClock][crate::clock::MockClock]
+    /// the test advances by hand
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Starts the server on an ephemeral port, serving until the [TestServer] is
+    /// [stopped][TestServer::stop] or dropped
+    pub async fn start(self) -> TestServer {
+        let mut room_registry = RoomRegistry::new(4)
+            .with_room_capacity(self.room_capacity)
+            .with_room_id_provider(self.room_id_provider);
+        if let Some(room_webhooks) = self.room_webhooks {
+            room_registry = room_registry.with_room_webhooks(room_webhooks);
+        }
+        if let Some(limit) = self.room_memory_limit {
+            room_registry = room_registry.with_room_memory_limit(limit);
+        }
+        let room_registry = Arc::new(room_registry);
+        let (room_deletion_queue, room_deletion_handler) = room_deletion_channel(
+            room_registry.clone(),
+            64,
+            16,
+            DeletionOverflowPolicy::DirectDelete,
+        );
+        let room_deletion_queue = room_deletion_queue.with_clock(self.clock.clone());
+        tokio::spawn(room_deletion_handler.supervise(CancellationToken::new()));
+        tokio::spawn(room_registry.clone().run_updates(CancellationToken::new()));
+        let (room_creation_queue, room_creation_worker) = room_creation_channel(
+            room_registry.clone(),
+            room_deletion_queue.clone(),
+            self.unjoined_timeout,
+            64,
+            Duration::from_secs(5),
+        );
+        tokio::spawn(room_creation_worker.watch());
+
+        let matchmaker =
+            Arc::new(Matchmaker::new(self.matchmaking_rules).with_clock(self.clock.clone()));
+        tokio::spawn(matchmaker.clone().run(
+            room_registry.clone(),
+            room_deletion_queue.clone(),
+            self.unjoined_timeout,
+        ));
+        let leaderboards = Arc::new(Leaderboards::new(100));
+        tokio::spawn(
+            leaderboards
+                .clone()
+                .run(Duration::from_secs(1), CancellationToken::new()),
+        );
+        let kick_votes = KickVotes::default().with_clock(self.clock.clone());
+        let room_scheduler = RoomScheduler::new(
+            room_registry.clone(),
+            room_deletion_queue.clone(),
+            self.unjoined_timeout,
+        )
+        .with_clock(self.clock);
+
+        let recorder = self.recording_dir.map(|dir| {
+            let (recorder, writer) = recording_channel(dir, RECORDING_QUEUE_CAPACITY);
+            tokio::task::spawn_blocking(move || writer.watch());
+            recorder
+        });
+
+        let state = web::Data::new(SharedAppState {
+            room_registry: RegistryHandle::new(room_registry, DEFAULT_REGISTRY_LOCK_TIMEOUT),
+            remote_rooms: Arc::new(RemoteRooms::default()),
+            cluster: None,
+            room_deletion_queue,
+            room_creation_queue,
+            room_creation_wait: Duration::from_secs(5),
+            broadcast_flush_interval: self.broadcast_flush_interval,
+            spectator_delay: self.spectator_delay,
+            state_codecs: self.state_codecs,
+            player_authenticator: self.signed_in_players.then(test_authenticator),
+            guest_challenge: None,
+            sessions: Default::default(),
+            ban_list: Default::default(),
+            rate_limiter: RateLimiter::new(0, 0, 0, 0),
+            content_filters: self
+                .content_filter
+                .map(|filter| ContentFilters::default().with_filter(filter))
+                .unwrap_or_default(),
+            emotes: Default::default(),
+            invite_signer: InviteSigner::random(),
+            invitations: Default::default(),
+            presence: PresenceFeed::new(None),
+            push: None,
+            abuse_counters: Default::default(),
+            abuse_scores: Default::default(),
+            panics: Default::default(),
+            maintenance: Default::default(),
+            matchmaker,
+            ratings: Default::default(),
+            leaderboards,
+            parties: Default::default(),
+            tournaments: Default::default(),
+            turn_based_rooms: Default::default(),
+            room_scheduler,
+            load_limits: Default::default(),
+            abuse_rules: self.abuse_rules.clone(),
+            recorder,
+            room_watchers: Default::default(),
+            reports: Default::default(),
+            room_bans: Default::default(),
+            spam_guard: Default::default(),
+            kick_votes,
+            waiting_lists: Default::default(),
+        });
+        let api_tokens = web::Data::new(Reloadable::fixed(self.api_tokens));
+        let request_timeout = web::Data::new(RequestTimeout(DEFAULT_REQUEST_TIMEOUT));
+        let body_limits = web::Data::new(BodyLimits::default());
+        let json_config = body_limits.json_config();
+
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .app_data(api_tokens.clone())
+                .app_data(request_timeout.clone())
+                .app_data(body_limits.clone())
+                .app_data(json_config.clone())
+                .service(web::resource("/metrics").route(web::get().to(api::get_metrics)))
+                .service(web::resource("/ws/{room_id}").route(web::get().to(ws::join_room)))
+                .service(web::resource("/challenge").route(web::get().to(ws::get_challenge)))
+                .configure(api::configure_player_routes)
+                .service(
+                    web::scope("api/v1")
+                        .wrap(from_fn(limit_request_bodies))
+                        .wrap(from_fn(auth::require_api_token))
+                        .wrap(from_fn(rate_limit::limit_requests))
+                        .wrap(from_fn(enforce_request_timeout))
+                        .wrap(from_fn(catch_panics))
+                        .configure(|cfg| {
+                            api::configure_api_scope(cfg);
+                            api::configure_admin_scope(cfg);
+                        }),
+                )
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .expect("The loopback interface always has a free port");
+        let address = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        TestServer {
+            address,
+            state,
+            handle,
+            http: reqwest::Client::new(),
+        }
+    }
+}
+
+/// The server running in-process, listening on the loopback interface
+pub struct TestServer {
+    address: SocketAddr,
+    state: web::Data<SharedAppState>,
+    handle: ServerHandle,
+    http: reqwest::Client,
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    /// Starts a server with the default options
+    pub async fn start() -> Self {
+        Self::builder().start().await
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The server's URL for `path`, which starts with a slash
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.address)
+    }
+
+    /// The state shared by the server's handlers, for setting up and inspecting what tests
+    /// can't reach through the API
+    pub fn state(&self) -> &SharedAppState {
+        &self.state
+    }
+
+    /// A client for the REST API, which every request can be sent without a token
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    /// Creates a room through the REST API
+    ///
+    /// # Panics
+    /// Panics if the room can't be created
+    pub async fn create_room(&self) -> RoomId {
+        let response = self
+            .http
+            .post(self.url("/api/v1/rooms/"))
+            .send()
+            .await
+            .expect("The test server is reachable");
+        assert_eq!(
+            response.status(),
+            reqwest::StatusCode::CREATED,
+            "The room couldn't be created"
+        );
+        response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| location.strip_prefix("/ws/"))
+            .and_then(|room_id| room_id.parse().ok())
+            .expect("Created rooms are located at their WebSocket URL")
+    }
+
+    /// Joins the room as a new client speaking the newest protocol version, once it has been
+    /// welcomed
+    ///
+    /// # Panics
+    /// Panics if the server turns the client away or doesn't welcome it
+    pub async fn connect(&self, room_id: RoomId) -> TestClient {
+        self.connect_with_query(room_id, "").await
+    }
+
+    /// Joins the room with extra query parameters, such as `invite=<token>`, or a
+    /// `protocol_version` to speak an older version of the protocol
+    ///
+    /// # Panics
+    /// Panics if the server turns the client away or doesn't welcome it
+    pub async fn connect_with_query(&self, room_id: RoomId, query: &str) -> TestClient {
+        let url = if query.contains("protocol_version=") {
+            format!("ws://{}/ws/{room_id}?{query}", self.address)
+        } else {
+            format!(
+                "ws://{}/ws/{room_id}?protocol_version={PROTOCOL_VERSION}&{query}",
+                self.address
+            )
+        };
+        let (socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .unwrap_or_else(|e| panic!("Couldn't join room {room_id}: {e}"));
+        let mut client = TestClient {
+            socket,
+            received: VecDeque::new(),
+            connection_id: 0.into(),
+        };
+        match client.recv().await {
+            ServerMessage::Welcome { connection_id, .. } => client.connection_id = connection_id,
+            message => panic!("Expected to be welcomed, got {message:?}"),
+        }
+        client
+    }
+
+    /// The id of the player signed in as `subject` on a server
+    /// [with signed in players][TestServerBuilder::with_signed_in_players]
+    pub fn player_id(subject: &str) -> PlayerId {
+        PlayerId::from_subject(TEST_ISSUER, subject)
+    }
+
+    /// Joins the room signed in as the player with the given `subject`, on a server
+    /// [with signed in players][TestServerBuilder::with_signed_in_players]
+    ///
+    /// # Panics
+    /// Panics if the server turns the client away or doesn't welcome it
+    pub async fn connect_as(&self, room_id: RoomId, subject: &str) -> TestClient {
+        let token = Self::player_token(subject);
+        self.connect_with_query(room_id, &format!("access_token={token}"))
+            .await
+    }
+
+    /// An identity token signing in the player with the given `subject`, on a server
+    /// [with signed in players][TestServerBuilder::with_signed_in_players]
+    pub fn player_token(subject: &str) -> String {
+        token(
+            "test-key",
+            serde_json::json!({
+                "sub": subject,
+                "iss": TEST_ISSUER,
+                "aud": "wormhole",
+                "exp": jsonwebtoken::get_current_timestamp() + 60,
+            }),
+        )
+    }
+
+    /// Stops accepting connections and closes the open ones
+    pub async fn stop(self) {
+        self.handle.stop(false).await;
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let handle = self.handle.clone();
+        // Dropping the future stopping the server would leave it running
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { handle.stop(false).await });
+        }
+    }
+}
+
+/// A fake client connected to a room on a [TestServer] over JSON text frames
+///
+/// Batch frames are taken apart, so every message is received on its own whether the
+/// server batched it or not.
+pub struct TestClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Messages taken out of batch frames that haven't been received yet
+    received: VecDeque<ServerMessage<'static>>,
+    connection_id: ConnectionId,
+}
+
+impl TestClient {
+    /// The connection ID the client was welcomed with
+    pub fn connection_id(&self) -> ConnectionId {
+        self.connection_id
+    }
+
+    /// Sends a message to the room
+    ///
+    /// # Panics
+    /// Panics if the connection has been closed
+    pub async fn send(&mut self, message: ClientMessage) {
+        self.send_envelope(ClientEnvelope::from(message)).await
+    }
+
+    /// Sends a message along with the identity the client claims to send it as
+    ///
+    /// # Panics
+    /// Panics if the connection has been closed
+    pub async fn send_envelope(&mut self, envelope: ClientEnvelope) {
+        let text = serde_json::to_string(&envelope).expect("Client messages always serialize");
+        self.send_raw(Message::text(text)).await
+    }
+
+    /// Sends a frame as is, such as one a well behaved client would never send
+    ///
+    /// # Panics
+    /// Panics if the connection has been closed
+    pub async fn send_raw(&mut self, frame: Message) {
+        self.socket
+            .send(frame)
+            .await
+            .expect("The connection is open");
+    }
+
+    /// Receives the next message, or `None` if the server closed the connection
+    ///
+    /// # Panics
+    /// Panics if nothing arrives within [RECV_TIMEOUT], or the server sends a frame that
+    /// isn't a server message
+    pub async fn try_recv(&mut self) -> Option<ServerMessage<'static>> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Some(message);
+            }
+            let frame = tokio::time::timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .unwrap_or_else(|_| panic!("Nothing was received within {RECV_TIMEOUT:?}"));
+            let text = match frame {
+                None | Some(Ok(Message::Close(_))) | Some(Err(_)) => return None,
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(frame)) if frame.is_binary() => {
+                    panic!("Expected a text frame, got {frame:?}")
+                }
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => continue,
+            };
+            match serde_json::from_str(&text) {
+                Ok(ServerMessage::Batch { messages }) => self.received.extend(messages),
+                Ok(message) => self.received.push_back(message),
+                Err(e) => panic!("{text:?} is not a server message: {e}"),
+            }
+        }
+    }
+
+    /// Receives the next message
+    ///
+    /// # Panics
+    /// Panics if nothing arrives within [RECV_TIMEOUT], or the connection is closed
+    pub async fn recv(&mut self) -> ServerMessage<'static> {
+        self.try_recv()
+            .await
+            .expect("The server closed the connection")
+    }
+
+    /// Skips messages until one matches `predicate`, and receives that one
+    ///
+    /// # Panics
+    /// Panics if no message matches within [RECV_TIMEOUT] of the last, or the connection is
+    /// closed
+    pub async fn recv_matching(
+        &mut self,
+        predicate: impl Fn(&ServerMessage) -> bool,
+    ) -> ServerMessage<'static> {
+        loop {
+            let message = self.recv().await;
+            if predicate(&message) {
+                return message;
+            }
+        }
+    }
+
+    /// Checks that nothing arrives for `duration`
+    ///
+    /// # Panics
+    /// Panics if a message arrives or the connection is closed
+    pub async fn expect_silence(&mut self, duration: Duration) {
+        if let Some(message) = self.received.pop_front() {
+            panic!("Expected nothing, got {message:?}");
+        }
+        if let Ok(frame) = tokio::time::timeout(duration, self.socket.next()).await {
+            panic!("Expected nothing, got {frame:?}");
+        }
+    }
+
+    /// Receives every message sent until nothing more arrives for `quiet`, or the connection
+    /// is closed
+    pub async fn drain(&mut self, quiet: Duration) -> Vec<ServerMessage<'static>> {
+        let mut messages: Vec<_> = self.received.drain(..).collect();
+        while let Ok(Some(frame)) = tokio::time::timeout(quiet, self.socket.next()).await {
+            let text = match frame {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            match serde_json::from_str(&text) {
+                Ok(ServerMessage::Batch { messages: batch }) => messages.extend(batch),
+                Ok(message) => messages.push(message),
+                Err(e) => panic!("{text:?} is not a server message: {e}"),
+            }
+        }
+        messages
+    }
+
+    /// Waits for the server to close the connection, skipping whatever it sends first, and
+    /// returns the reason it gave
+    ///
+    /// # Panics
+    /// Panics if the connection is still open after [RECV_TIMEOUT]
+    pub async fn close_reason(&mut self) -> Option<String> {
+        loop {
+            let frame = tokio::time::timeout(RECV_TIMEOUT, self.socket.next())
+                .await
+                .unwrap_or_else(|_| panic!("The connection wasn't closed within {RECV_TIMEOUT:?}"));
+            match frame {
+                Some(Ok(Message::Close(frame))) => {
+                    return frame.map(|frame| frame.reason.into_owned())
+                }
+                None | Some(Err(_)) => return None,
+                Some(Ok(_)) => continue,
+            }
+        }
+    }
+
+    /// Waits for the server to close the connection, skipping whatever it sends first
+    ///
+    /// # Panics
+    /// Panics if the connection is still open after [RECV_TIMEOUT]
+    pub async fn expect_closed(&mut self) {
+        while self.try_recv().await.is_some() {}
+    }
+
+    /// Leaves the room
+    pub async fn close(mut self) {
+        // The server may have closed the connection already
+        let _ = self.socket.close(None).await;
+    }
+}
+
+#[cfg(test)]
+mod test_server {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::content_filter::{ContentKind, FilterFuture, Verdict};
+    use crate::protocol::ChatChannel;
+    use serde_json::json;
+    use std::borrow::Cow;
+
+    #[tokio::test]
+    async fn relays_broadcasts_between_clients() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect(room_id).await;
+        let mut bob = server.connect(room_id).await;
+        let from = alice.connection_id();
+
+        alice
+            .send(ClientMessage::Broadcast {
+                payload: json!({ "move": "e4" }),
+            })
+            .await;
+
+        for client in [&mut alice, &mut bob] {
+            assert_eq!(
+                client.recv().await,
+                ServerMessage::Broadcast {
+                    from,
+                    from_player: None,
+                    payload: Cow::Owned(json!({ "move": "e4" })),
+                    seq: Some(1),
+                }
+            );
+        }
+        bob.expect_silence(Duration::from_millis(50)).await;
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn unpacks_batched_messages() {
+        let server = TestServer::builder()
+            .with_broadcast_flush_interval(Duration::from_millis(50))
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+
+        for i in 0..3 {
+            client
+                .send(ClientMessage::Broadcast { payload: json!(i) })
+                .await;
+        }
+
+        for i in 0..3 {
+            assert!(matches!(
+                client.recv().await,
+                ServerMessage::Broadcast { payload, .. } if *payload == json!(i)
+            ));
+        }
+    }
+
+    /// Holds back the line "first" so it's filtered after the lines sent after it
+    #[derive(Debug)]
+    struct SlowOnFirst;
+
+    impl ContentFilter for SlowOnFirst {
+        fn filter<'a>(&'a self, text: String, _kind: ContentKind) -> FilterFuture<'a> {
+            Box::pin(async move {
+                if text == "first" {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Verdict::Allow(text)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_chat_in_the_order_it_was_sent_while_filtering_it() {
+        let server = TestServer::builder()
+            .with_content_filter(SlowOnFirst)
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+
+        for text in ["first", "second"] {
+            client
+                .send(ClientMessage::Chat {
+                    text: text.to_owned(),
+                    channel: ChatChannel::Room,
+                })
+                .await;
+        }
+
+        for text in ["first", "second"] {
+            let message = client
+                .recv_matching(|message| matches!(message, ServerMessage::Chat(_)))
+                .await;
+            assert!(matches!(message, ServerMessage::Chat(chat) if chat.text == text));
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_out_big_batches_without_waiting() {
+        let server = TestServer::builder()
+            .with_broadcast_flush_interval(Duration::from_secs(3600))
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+        let padding = "x".repeat(30 * 1024);
+
+        for i in 0..3 {
+            client
+                .send(ClientMessage::Broadcast {
+                    payload: json!({ "i": i, "padding": padding }),
+                })
+                .await;
+        }
+
+        for i in 0..3 {
+            assert!(matches!(
+                client.recv().await,
+                ServerMessage::Broadcast { payload, .. } if payload["i"] == json!(i)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn disconnects_clients_that_keep_misbehaving() {
+        let server = TestServer::builder()
+            .with_abuse_rules(AbuseRules {
+                disconnect_at: 2,
+                ..Default::default()
+            })
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+
+        client.send(ClientMessage::CloseRoom).await;
+
+        assert!(matches!(client.recv().await, ServerMessage::Error { .. }));
+        client.expect_closed().await;
+    }
+
+    #[tokio::test]
+    async fn locks_out_clients_sending_oversized_frames() {
+        let server = TestServer::builder()
+            .with_abuse_rules(AbuseRules {
+                lock_at: 3,
+                max_frame_bytes: 64,
+                ..Default::default()
+            })
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut client = server.connect(room_id).await;
+
+        client
+            .send(ClientMessage::Broadcast {
+                payload: json!("x".repeat(64)),
+            })
+            .await;
+        assert!(matches!(
+            client.recv().await,
+            ServerMessage::Error { reason } if reason.starts_with("Frames can be at most 64 bytes")
+        ));
+        assert!(matches!(
+            client.recv().await,
+            ServerMessage::Error { reason } if reason.starts_with("You can't do anything for 10 seconds")
+        ));
+
+        client
+            .send(ClientMessage::Broadcast { payload: json!(1) })
+            .await;
+        assert!(matches!(
+            client.recv().await,
+            ServerMessage::Error { reason } if reason.starts_with("You can't do anything for another")
+        ));
+    }
+
+    #[tokio::test]
+    async fn times_rooms_out_on_its_clock() {
+        let clock = Arc::new(MockClock::new());
+        let server = TestServer::builder()
+            .with_unjoined_timeout(Duration::from_secs(600))
+            .with_clock(clock.clone())
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+
+        clock.advance(Duration::from_secs(600));
+
+        let registry = server.state().room_registry.registry();
+        tokio::time::timeout(RECV_TIMEOUT, async {
+            while registry.get_room_for_id(room_id).is_some() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("The room should be deleted once the clock reaches its timeout");
+    }
+}
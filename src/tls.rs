@@ -0,0 +1,120 @@
+//! TLS for the admin listener, optionally requiring clients to present a certificate
+//! issued by a trusted certificate authority
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use thiserror::Error;
+
+/// Where the admin listener's certificate, key, and client certificate authority are read from
+#[derive(Debug, Clone)]
+pub struct AdminTls {
+    /// PEM encoded certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM encoded private key
+    pub key_path: PathBuf,
+    /// PEM encoded certificates of the authorities client certificates must be issued by,
+    /// `None` to accept clients without certificates
+    pub client_ca_path: Option<PathBuf>,
+}
+
+#[derive(Error, Debug)]
+pub enum TlsConfigError {
+    #[error("{0} can't be read: {1}")]
+    Unreadable(PathBuf, #[source] std::io::Error),
+    #[error("{0} contains no certificates")]
+    NoCertificates(PathBuf),
+    #[error("{0} contains no private key")]
+    NoPrivateKey(PathBuf),
+    #[error("The client certificate authorities are not valid: {0}")]
+    InvalidClientCa(#[from] rustls::server::VerifierBuilderError),
+    #[error("The certificate or key is not valid: {0}")]
+    Invalid(#[from] rustls::Error),
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsConfigError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| TlsConfigError::Unreadable(path.to_owned(), e))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsConfigError> {
+    let certs = rustls_pemfile::certs(&mut open(path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsConfigError::Unreadable(path.to_owned(), e))?;
+    if certs.is_empty() {
+        return Err(TlsConfigError::NoCertificates(path.to_owned()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsConfigError> {
+    rustls_pemfile::private_key(&mut open(path)?)
+        .map_err(|e| TlsConfigError::Unreadable(path.to_owned(), e))?
+        .ok_or_else(|| TlsConfigError::NoPrivateKey(path.to_owned()))
+}
+
+impl AdminTls {
+    /// Reads the certificates and key into a config for the listener
+    pub fn server_config(&self) -> Result<ServerConfig, TlsConfigError> {
+        let builder =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_ca_path {
+            Some(client_ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(client_ca_path)? {
+                    roots.add(cert)?;
+                }
+                builder.with_client_cert_verifier(
+                    WebPkiClientVerifier::builder_with_provider(
+                        Arc::new(roots),
+                        Arc::new(rustls::crypto::ring::default_provider()),
+                    )
+                    .build()?,
+                )
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(builder.with_single_cert(read_certs(&self.cert_path)?, read_key(&self.key_path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod server_config {
+    use super::*;
+
+    fn tls(cert_path: &Path) -> AdminTls {
+        AdminTls {
+            cert_path: cert_path.to_owned(),
+            key_path: cert_path.to_owned(),
+            client_ca_path: None,
+        }
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        assert!(matches!(
+            tls(Path::new("/nonexistent/cert.pem")).server_config(),
+            Err(TlsConfigError::Unreadable(..))
+        ));
+    }
+
+    #[test]
+    fn reports_files_without_certificates() {
+        let path = std::env::temp_dir().join(format!("wormhole-cert-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+
+        let result = tls(&path).server_config();
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(TlsConfigError::NoCertificates(_))));
+    }
+}
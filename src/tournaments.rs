@@ -0,0 +1,450 @@
+//! Single-elimination tournaments between registered players
+//!
+//! A [tournament][Tournament] seeds its players by [rating][crate::ratings] and lays them out in
+//! a bracket whose first round is padded to a power of two with byes, given to the top seeds,
+//! so that the best players only meet late. Each match gets a private room of its own as soon
+//! as both of its players are known. Reporting a match's winner closes its room and advances
+//! the winner, until the final decides the champion.
+//!
+//! Every change to a tournament is published to [subscribers][TournamentRegistry::subscribe]
+//! with the whole bracket, so missing an update is made up for by the next one.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::game::{PlayerId, RoomDeletionQueue, RoomId, RoomRegistry};
+use crate::matchmaking::is_valid_game_type;
+
+/// The most players a tournament can be held between
+pub const MAX_TOURNAMENT_PLAYERS: usize = 256;
+
+/// How many updates a subscriber may fall behind by before it misses some
+const TOURNAMENT_UPDATE_CAPACITY: usize = 64;
+
+/// An ID that identifies a [tournament][Tournament]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+pub struct TournamentId(Uuid);
+
+impl std::fmt::Display for TournamentId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A match in a tournament's bracket
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct BracketMatch {
+    /// The players meeting in the match, absent while the match they come from is undecided,
+    /// or for a bye in the first round
+    pub players: [Option<PlayerId>; 2],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub winner: Option<PlayerId>,
+    /// The room the match is played in, once both of its players are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<RoomId>,
+}
+
+impl BracketMatch {
+    /// The two players, if the match is still to be played between them
+    fn pending_players(&self) -> Option<[PlayerId; 2]> {
+        match (self.players, self.winner) {
+            ([Some(a), Some(b)], None) => Some([a, b]),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Running,
+    Finished { champion: PlayerId },
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, ToSchema)]
+pub struct Tournament {
+    pub id: TournamentId,
+    pub game_type: String,
+    #[serde(flatten)]
+    pub status: TournamentStatus,
+    /// The bracket's matches round by round, the first round first and the final last
+    pub rounds: Vec<Vec<BracketMatch>>,
+}
+
+/// Raised when a tournament can't be held or updated as asked
+#[derive(Error, Debug, PartialEq)]
+pub enum TournamentError {
+    #[error("Game types must be between 1 and 32 characters")]
+    InvalidGameType,
+    #[error("A tournament needs between 2 and {MAX_TOURNAMENT_PLAYERS} players")]
+    InvalidPlayerCount,
+    #[error("{0} is registered more than once")]
+    DuplicatePlayer(PlayerId),
+    #[error("No tournament has the ID")]
+    NotFound,
+    #[error("The bracket has no such match")]
+    NoSuchMatch,
+    #[error("The match isn't waiting to be played")]
+    NotPlayable,
+    #[error("{0} isn't playing in the match")]
+    NotInMatch(PlayerId),
+}
+
+/// The seeds, from 1, in the order they're laid out in a first round of `size` slots, so
+/// that the top two seeds can only meet in the final, the top four in the semi-finals, and
+/// so on
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let slots = order.len() * 2;
+        order = order
+            .into_iter()
+            .flat_map(|seed| [seed, slots + 1 - seed])
+            .collect();
+    }
+    order
+}
+
+impl Tournament {
+    /// Lays the players out in a bracket, strongest first
+    fn new(game_type: String, seeded: &[PlayerId]) -> Self {
+        let size = seeded.len().next_power_of_two();
+        let order = seed_order(size);
+        let first_round = order
+            .chunks(2)
+            .map(|seeds| BracketMatch {
+                players: [seeds[0], seeds[1]].map(|seed| seeded.get(seed - 1).copied()),
+                winner: None,
+                room_id: None,
+            })
+            .collect();
+        let mut rounds = vec![first_round];
+        let mut matches = size / 2;
+        while matches > 1 {
+            matches /= 2;
+            rounds.push(vec![
+                BracketMatch {
+                    players: [None, None],
+                    winner: None,
+                    room_id: None,
+                };
+                matches
+            ]);
+        }
+        let mut tournament = Self {
+            id: TournamentId(Uuid::new_v4()),
+            game_type,
+            status: TournamentStatus::Running,
+            rounds,
+        };
+        for index in 0..tournament.rounds[0].len() {
+            if let [Some(player), None] = tournament.rounds[0][index].players {
+                tournament.advance(0, index, player);
+            }
+        }
+        tournament
+    }
+
+    fn get_match(
+        &mut self,
+        round: usize,
+        index: usize,
+    ) -> Result<&mut BracketMatch, TournamentError> {
+        self.rounds
+            .get_mut(round)
+            .and_then(|matches| matches.get_mut(index))
+            .ok_or(TournamentError::NoSuchMatch)
+    }
+
+    /// Records the winner of a match and moves them on to their next one
+    fn advance(&mut self, round: usize, index: usize, winner: PlayerId) {
+        self.rounds[round][index].winner = Some(winner);
+        match self.rounds.get_mut(round + 1) {
+            Some(next_round) => next_round[index / 2].players[index % 2] = Some(winner),
+            None => self.status = TournamentStatus::Finished { champion: winner },
+        }
+    }
+
+    /// The matches waiting to be played that don't have a room yet, by round and index
+    fn matches_without_rooms(&self) -> Vec<(usize, usize)> {
+        self.rounds
+            .iter()
+            .enumerate()
+            .flat_map(|(round, matches)| {
+                matches
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, bracket_match)| {
+                        (bracket_match.pending_players().is_some()
+                            && bracket_match.room_id.is_none())
+                        .then_some((round, index))
+                    })
+            })
+            .collect()
+    }
+
+    /// Every room the tournament still holds open
+    fn open_rooms(&self) -> impl Iterator<Item = RoomId> + '_ {
+        self.rounds
+            .iter()
+            .flatten()
+            .filter(|bracket_match| bracket_match.winner.is_none())
+            .filter_map(|bracket_match| bracket_match.room_id)
+    }
+}
+
+/// Creates a private room for the match, leaving it without one if the registry can't
+fn open_room(bracket_match: &mut BracketMatch, game_type: &str, registry: &RoomRegistry) {
+    match registry.create_room_with(|room| {
+        room.set_private(true);
+        room.set_game_type(game_type.to_owned());
+    }) {
+        Ok(room_id) => bracket_match.room_id = Some(room_id),
+        Err(e) => warn!(event = "tournament_room_creation_failed", error = %e),
+    }
+}
+
+/// Every tournament on the server
+#[derive(Debug)]
+pub struct TournamentRegistry {
+    tournaments: Mutex<HashMap<TournamentId, Tournament>>,
+    updates: Sender<Tournament>,
+}
+
+impl Default for TournamentRegistry {
+    fn default() -> Self {
+        Self {
+            tournaments: Default::default(),
+            updates: broadcast::channel(TOURNAMENT_UPDATE_CAPACITY).0,
+        }
+    }
+}
+
+impl TournamentRegistry {
+    /// Starts a tournament between the players, given strongest first, opening a room for
+    /// each first round match
+    pub fn create(
+        &self,
+        game_type: String,
+        seeded: &[PlayerId],
+        registry: &RoomRegistry,
+    ) -> Result<Tournament, TournamentError> {
+        if !is_valid_game_type(&game_type) {
+            return Err(TournamentError::InvalidGameType);
+        }
+        if !(2..=MAX_TOURNAMENT_PLAYERS).contains(&seeded.len()) {
+            return Err(TournamentError::InvalidPlayerCount);
+        }
+        let mut registered = HashSet::new();
+        if let Some(&player) = seeded.iter().find(|&&player| !registered.insert(player)) {
+            return Err(TournamentError::DuplicatePlayer(player));
+        }
+
+        let mut tournament = Tournament::new(game_type, seeded);
+        for (round, index) in tournament.matches_without_rooms() {
+            open_room(
+                &mut tournament.rounds[round][index],
+                &tournament.game_type,
+                registry,
+            );
+        }
+        info!(
+            event = "tournament_created",
+            tournament_id = %tournament.id,
+            players = seeded.len()
+        );
+        self.tournaments
+            .lock()
+            .unwrap()
+            .insert(tournament.id, tournament.clone());
+        let _ = self.updates.send(tournament.clone());
+        Ok(tournament)
+    }
+
+    pub fn get(&self, id: TournamentId) -> Option<Tournament> {
+        self.tournaments.lock().unwrap().get(&id).cloned()
+    }
+
+    /// The room a match waiting to be played is held in along with its players, opening a
+    /// new room if the one it had has been deleted, such as after everyone left it
+    pub fn match_room(
+        &self,
+        id: TournamentId,
+        round: usize,
+        index: usize,
+        registry: &RoomRegistry,
+    ) -> Result<(RoomId, [PlayerId; 2]), TournamentError> {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let tournament = tournaments.get_mut(&id).ok_or(TournamentError::NotFound)?;
+        let game_type = tournament.game_type.clone();
+        let bracket_match = tournament.get_match(round, index)?;
+        let players = bracket_match
+            .pending_players()
+            .ok_or(TournamentError::NotPlayable)?;
+        let room_exists = bracket_match.room_id.is_some_and(|room_id| {
+            registry
+                .list_active_rooms()
+                .iter()
+                .any(|room| room.id == room_id)
+        });
+        if !room_exists {
+            open_room(bracket_match, &game_type, registry);
+            let _ = self.updates.send(tournament.clone());
+        }
+        let room_id = tournament.rounds[round][index]
+            .room_id
+            .ok_or(TournamentError::NotPlayable)?;
+        Ok((room_id, players))
+    }
+
+    /// Records the winner of a match, closing its room, advancing the winner and opening a
+    /// room for their next match once their opponent is known
+    ///
+    /// Returns the tournament as it is afterwards along with the match's loser.
+    pub fn report(
+        &self,
+        id: TournamentId,
+        round: usize,
+        index: usize,
+        winner: PlayerId,
+        registry: &RoomRegistry,
+        deletion_queue: &RoomDeletionQueue,
+    ) -> Result<(Tournament, PlayerId), TournamentError> {
+        let mut tournaments = self.tournaments.lock().unwrap();
+        let tournament = tournaments.get_mut(&id).ok_or(TournamentError::NotFound)?;
+        let bracket_match = tournament.get_match(round, index)?;
+        let players = bracket_match
+            .pending_players()
+            .ok_or(TournamentError::NotPlayable)?;
+        let loser = match players {
+            [a, b] if a == winner => b,
+            [a, b] if b == winner => a,
+            _ => return Err(TournamentError::NotInMatch(winner)),
+        };
+        if let Some(room_id) = bracket_match.room_id {
+            deletion_queue.request_deletion(room_id);
+        }
+
+        tournament.advance(round, index, winner);
+        for (round, index) in tournament.matches_without_rooms() {
+            open_room(
+                &mut tournament.rounds[round][index],
+                &tournament.game_type,
+                registry,
+            );
+        }
+        info!(
+            event = "tournament_match_decided",
+            tournament_id = %id,
+            round,
+            index,
+            winner = %winner
+        );
+        let _ = self.updates.send(tournament.clone());
+        Ok((tournament.clone(), loser))
+    }
+
+    /// Calls the tournament off, closing the rooms of its undecided matches
+    pub fn cancel(&self, id: TournamentId, deletion_queue: &RoomDeletionQueue) -> bool {
+        let Some(tournament) = self.tournaments.lock().unwrap().remove(&id) else {
+            return false;
+        };
+        for room_id in tournament.open_rooms() {
+            deletion_queue.request_deletion(room_id);
+        }
+        info!(event = "tournament_cancelled", tournament_id = %id);
+        true
+    }
+
+    /// Receives every tournament each time it changes
+    pub fn subscribe(&self) -> Receiver<Tournament> {
+        self.updates.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod seed_order {
+    use super::*;
+
+    #[test]
+    fn keeps_the_top_seeds_apart() {
+        assert_eq!(seed_order(2), [1, 2]);
+        assert_eq!(seed_order(4), [1, 4, 2, 3]);
+        assert_eq!(seed_order(8), [1, 8, 4, 5, 2, 7, 3, 6]);
+    }
+}
+
+#[cfg(test)]
+mod new {
+    use super::*;
+
+    #[test]
+    fn gives_byes_to_the_top_seeds() {
+        let players = [1, 2, 3, 4, 5, 6].map(PlayerId::from);
+
+        let tournament = Tournament::new("duel".into(), &players);
+
+        assert_eq!(tournament.rounds.len(), 3);
+        assert_eq!(tournament.rounds[0][0].winner, Some(players[0]));
+        assert_eq!(tournament.rounds[0][2].winner, Some(players[1]));
+        assert_eq!(
+            tournament.rounds[1][0].players,
+            [Some(players[0]), None],
+            "The top seed waits for the winner of 4 v 5"
+        );
+        assert_eq!(tournament.matches_without_rooms(), [(0, 1), (0, 3)]);
+    }
+}
+
+#[cfg(test)]
+mod report {
+    use super::*;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy};
+
+    #[tokio::test]
+    async fn advances_winners_until_there_is_a_champion() {
+        let registry = std::sync::Arc::new(RoomRegistry::new(4));
+        let (deletion_queue, _) =
+            room_deletion_channel(registry.clone(), 8, 8, DeletionOverflowPolicy::DirectDelete);
+        let tournaments = TournamentRegistry::default();
+        let [a, b, c] = [1, 2, 3].map(PlayerId::from);
+        let tournament = tournaments
+            .create("duel".into(), &[a, b, c], &registry)
+            .unwrap();
+        let id = tournament.id;
+
+        assert_eq!(
+            tournaments.report(id, 1, 0, a, &registry, &deletion_queue),
+            Err(TournamentError::NotPlayable)
+        );
+        assert_eq!(
+            tournaments.report(id, 0, 1, a, &registry, &deletion_queue),
+            Err(TournamentError::NotInMatch(a))
+        );
+        let (room_id, players) = tournaments.match_room(id, 0, 1, &registry).unwrap();
+        assert_eq!(players, [b, c]);
+        assert!(registry.get_room_for_id(room_id).unwrap().is_private());
+
+        let (tournament, loser) = tournaments
+            .report(id, 0, 1, c, &registry, &deletion_queue)
+            .unwrap();
+        assert_eq!(loser, b);
+        assert!(tournament.rounds[1][0].room_id.is_some());
+
+        let (tournament, _) = tournaments
+            .report(id, 1, 0, c, &registry, &deletion_queue)
+            .unwrap();
+        assert_eq!(
+            tournament.status,
+            TournamentStatus::Finished { champion: c }
+        );
+    }
+}
@@ -0,0 +1,664 @@
+//! Turn-based rooms for correspondence-style play, lasting days rather than minutes
+//!
+//! A [turn-based][crate::game::Room::is_turn_based] room isn't deleted for sitting empty.
+//! Each turn has a deadline instead, the room's turn timeout after the turn was
+//! [passed][crate::game::Room::pass_turn] to the player whose turn it is, and the room is
+//! deleted if the turn isn't passed on by then. Players only connect for long enough to take
+//! their turn, so the last payload broadcast in the room is kept as the
+//! [state of its game][crate::game::GameState] and sent to each connection as it joins.
+//!
+//! When a turn-based rooms file is configured, the turn-based rooms this instance hosts are
+//! saved soon after a turn is passed or a game's state changes, and every [SAVE_INTERVAL] to
+//! catch everything else, then restored on start, so games survive restarts. Changes are only
+//! [marked][TurnBasedRooms::mark_changed] where they happen, and saved off the runtime's
+//! threads by [TurnBasedRooms::run], so changes made while a save is under way are saved
+//! together by the next one.
+//!
+//! Rather than writing every room out each time, the file holds a base of every room as of
+//! some point, and a log next to it holds the rooms that changed or were deleted in each save
+//! since, numbered so a restore only applies what came after the base. Every
+//! [COMPACT_AFTER_DELTAS] saves the log is folded back into a new base, and the first save
+//! after a start writes one too.
+//!
+//! On start, up to [DEFAULT_RESTORE_PARALLELISM] rooms, or as many as
+//! [configured][TurnBasedRooms::with_restore_parallelism], are registered at once, so restarts
+//! stay quick however many games are under way. Every room's game state is still read from the
+//! file up front, only decoding it for clients waits until a connection joins the room.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use tokio::task::{JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::clock::unix_time;
+use crate::files::write_atomically;
+use crate::game::{RoomDeletionQueue, RoomId, RoomRegistry, RoomState};
+
+/// How long each turn may take in a turn-based room created without saying
+pub const DEFAULT_TURN_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The longest a turn-based room can let each turn take
+pub const MAX_TURN_TIMEOUT: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often every turn-based room is written to the file, in case it changed in a way that
+/// isn't saved straight away, such as its settings or its deletion
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many saves are appended to the log before it's folded back into the base
+pub const COMPACT_AFTER_DELTAS: usize = 100;
+
+/// How many rooms are registered at once when they're restored, unless configured otherwise
+pub const DEFAULT_RESTORE_PARALLELISM: usize = 16;
+
+/// A turn-based room as written to the file
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+struct SavedRoom {
+    id: RoomId,
+    state: RoomState,
+    /// When the room is due to be deleted, in seconds since the Unix epoch
+    deletes_at: Option<u64>,
+}
+
+/// Every room as of the delta numbered `seq`, as written to the file
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Base {
+    Numbered {
+        seq: u64,
+        rooms: Vec<SavedRoom>,
+    },
+    /// Files written before the log was kept hold nothing but the rooms
+    Unnumbered(Vec<SavedRoom>),
+}
+
+/// The rooms that changed or were deleted in a save, as a line of the log
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Delta {
+    seq: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    saved: Vec<SavedRoom>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deleted: Vec<RoomId>,
+}
+
+/// What has been written so far
+#[derive(Debug, Default)]
+struct Written {
+    /// Each room as last written
+    rooms: HashMap<RoomId, SavedRoom>,
+    /// The number of the latest delta, which the next one follows
+    seq: u64,
+    /// How many deltas the log holds, `None` until a base has been written since the start
+    deltas: Option<usize>,
+}
+
+/// Keeps the turn-based rooms this instance hosts across restarts
+#[derive(Debug, Default)]
+pub struct TurnBasedRooms {
+    /// Where the rooms' base is written to, `None` to keep them in memory only
+    file: Option<PathBuf>,
+    compact_after: usize,
+    /// How many rooms are registered at once when they're restored
+    restore_parallelism: usize,
+    /// Held while writing, so saves made at the same time don't write over each other
+    written: Mutex<Written>,
+    /// Woken when a room changed in a way that should be saved straight away
+    changed: Notify,
+}
+
+impl TurnBasedRooms {
+    /// Keeps the rooms in the file, and the log next to it
+    pub fn with_file(file: PathBuf) -> Self {
+        Self {
+            file: Some(file),
+            compact_after: COMPACT_AFTER_DELTAS,
+            restore_parallelism: DEFAULT_RESTORE_PARALLELISM,
+            written: Default::default(),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Registers up to `parallelism` rooms at once when they're [restored][Self::restore]
+    pub fn with_restore_parallelism(mut self, parallelism: usize) -> Self {
+        self.restore_parallelism = parallelism;
+        self
+    }
+
+    /// Has the rooms saved as soon as the save under way, if any, is done
+    pub fn mark_changed(&self) {
+        self.changed.notify_one();
+    }
+
+    /// Saves every turn-based room in the registry, appending those that changed since the
+    /// last save to the log, or writing a new base if it's time to
+    pub fn save(&self, registry: &RoomRegistry) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let rooms: HashMap<_, _> = registry
+            .list_active_rooms()
+            .iter()
+            .filter_map(|summary| {
+                let room = registry.get_room_for_id(summary.id)?;
+                room.is_turn_based().then(|| {
+                    let saved = SavedRoom {
+                        id: summary.id,
+                        state: room.state(),
+                        deletes_at: room.deletes_at(),
+                    };
+                    (summary.id, saved)
+                })
+            })
+            .collect();
+        let mut written = self.written.lock().unwrap();
+        let saved = match written.deltas {
+            Some(deltas) if deltas < self.compact_after => append_delta(file, &written, &rooms),
+            _ => compact(file, written.seq, &rooms).map(|()| Some(0)),
+        };
+        match saved {
+            Ok(Some(deltas)) => {
+                if deltas > 0 {
+                    written.seq += 1;
+                }
+                written.deltas = Some(deltas);
+                written.rooms = rooms;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(event = "turn_based_rooms_not_saved", file = %file.display(), error = %e)
+            }
+        }
+    }
+
+    /// Registers the rooms saved to the file and its log, but for those whose turn ran out in
+    /// the meantime, each deleted when it was due to be, returning how many were restored
+    ///
+    /// Up to the [restore parallelism][Self::with_restore_parallelism] rooms are registered at
+    /// once, each off the runtime's threads.
+    pub async fn restore(
+        &self,
+        registry: &Arc<RoomRegistry>,
+        queue: &RoomDeletionQueue,
+    ) -> io::Result<usize> {
+        let Some(file) = self.file.clone() else {
+            return Ok(0);
+        };
+        let (seq, rooms) = tokio::task::spawn_blocking(move || read_rooms(&file))
+            .await
+            .map_err(io::Error::other)??;
+        self.written.lock().unwrap().seq = seq;
+        let now = unix_time();
+        let mut restored = 0;
+        let mut count = |joined: Result<bool, JoinError>| match joined {
+            Ok(true) => restored += 1,
+            Ok(false) => {}
+            Err(e) => warn!(event = "turn_based_room_not_restored", error = %e),
+        };
+        let mut restoring = JoinSet::new();
+        for room in rooms {
+            if restoring.len() >= self.restore_parallelism.max(1) {
+                if let Some(joined) = restoring.join_next().await {
+                    count(joined);
+                }
+            }
+            let (registry, queue) = (registry.clone(), queue.clone());
+            restoring.spawn_blocking(move || restore_room(&registry, &queue, room, now));
+        }
+        while let Some(joined) = restoring.join_next().await {
+            count(joined);
+        }
+        info!(event = "turn_based_rooms_restored", count = restored);
+        Ok(restored)
+    }
+
+    /// Writes the rooms to the file every [SAVE_INTERVAL] and whenever they're
+    /// [marked as changed][Self::mark_changed] until shut down, and once more on the way out
+    pub async fn run(self: Arc<Self>, registry: Arc<RoomRegistry>, shutdown: CancellationToken) {
+        if self.file.is_none() {
+            return;
+        }
+        let mut saves = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = saves.tick() => {}
+                _ = self.changed.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+            self.save_in_background(&registry).await;
+        }
+        self.save_in_background(&registry).await;
+    }
+
+    async fn save_in_background(self: &Arc<Self>, registry: &Arc<RoomRegistry>) {
+        let (rooms, registry) = (self.clone(), registry.clone());
+        if let Err(e) = tokio::task::spawn_blocking(move || rooms.save(&registry)).await {
+            warn!(event = "turn_based_rooms_not_saved", error = %e);
+        }
+    }
+}
+
+/// Registers a saved room unless its turn ran out by `now`, returning whether it was
+fn restore_room(
+    registry: &RoomRegistry,
+    queue: &RoomDeletionQueue,
+    SavedRoom {
+        id,
+        state,
+        deletes_at,
+    }: SavedRoom,
+    now: u64,
+) -> bool {
+    if deletes_at.is_some_and(|deletes_at| deletes_at <= now) {
+        info!(event = "turn_based_room_expired", room_id = %id);
+        return false;
+    }
+    if let Err(e) = registry.adopt_room(id, |room| room.restore(state)) {
+        warn!(event = "turn_based_room_not_restored", room_id = %id, error = %e);
+        return false;
+    }
+    if let Some(room) = registry.get_room_for_id(id) {
+        match deletes_at {
+            Some(deletes_at) => {
+                room.schedule_deletion(id, Duration::from_secs(deletes_at - now), queue.clone())
+            }
+            None => room.schedule_unjoined_deletion(id, Duration::ZERO, queue.clone()),
+        }
+        registry.room_updated(id);
+    }
+    true
+}
+
+/// The log of deltas kept next to the base in `file`
+fn log_file(file: &Path) -> PathBuf {
+    file.with_extension("log")
+}
+
+/// Appends the rooms that changed or were deleted since they were `written` to the log,
+/// returning how many deltas it then holds, or `None` if nothing had changed
+fn append_delta(
+    file: &Path,
+    written: &Written,
+    rooms: &HashMap<RoomId, SavedRoom>,
+) -> io::Result<Option<usize>> {
+    let mut saved: Vec<_> = rooms
+        .values()
+        .filter(|room| written.rooms.get(&room.id) != Some(room))
+        .cloned()
+        .collect();
+    saved.sort_by_key(|room| room.id);
+    let mut deleted: Vec<_> = written
+        .rooms
+        .keys()
+        .filter(|id| !rooms.contains_key(id))
+        .copied()
+        .collect();
+    deleted.sort();
+    if saved.is_empty() && deleted.is_empty() {
+        return Ok(None);
+    }
+    let delta = Delta {
+        seq: written.seq + 1,
+        saved,
+        deleted,
+    };
+    let mut line = serde_json::to_vec(&delta)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file(file))?
+        .write_all(&line)?;
+    Ok(Some(written.deltas.unwrap_or_default() + 1))
+}
+
+/// Writes every room as a new base covering the deltas up to `seq`, then empties the log
+///
+/// Should the log outlive the base being replaced, its deltas are all numbered `seq` or lower
+/// and so are skipped on restore.
+fn compact(file: &Path, seq: u64, rooms: &HashMap<RoomId, SavedRoom>) -> io::Result<()> {
+    let mut rooms: Vec<_> = rooms.values().cloned().collect();
+    rooms.sort_by_key(|room| room.id);
+    write_atomically(file, &Base::Numbered { seq, rooms })?;
+    match fs::remove_file(log_file(file)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Reads the base in `file` and applies the deltas in its log that came after it, returning
+/// the number of the latest delta along with the rooms
+///
+/// A line of the log that can't be read, as when the instance stopped partway through
+/// appending it, ends the log there.
+fn read_rooms(file: &Path) -> io::Result<(u64, Vec<SavedRoom>)> {
+    let (base_seq, base) = match fs::read(file) {
+        Ok(contents) => match serde_json::from_slice(&contents)? {
+            Base::Numbered { seq, rooms } => (seq, rooms),
+            Base::Unnumbered(rooms) => (0, rooms),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (0, Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut rooms: HashMap<_, _> = base.into_iter().map(|room| (room.id, room)).collect();
+    let mut seq = base_seq;
+    let log = match fs::File::open(log_file(file)) {
+        Ok(log) => Some(BufReader::new(log)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    for line in log.into_iter().flat_map(BufRead::lines) {
+        let delta: Delta = match serde_json::from_str(&line?) {
+            Ok(delta) => delta,
+            Err(e) => {
+                warn!(event = "turn_based_rooms_log_truncated", file = %file.display(), error = %e);
+                break;
+            }
+        };
+        if delta.seq <= base_seq {
+            continue;
+        }
+        seq = delta.seq;
+        for id in delta.deleted {
+            rooms.remove(&id);
+        }
+        rooms.extend(delta.saved.into_iter().map(|room| (room.id, room)));
+    }
+    Ok((seq, rooms.into_values().collect()))
+}
+
+#[cfg(test)]
+mod restore {
+    use super::*;
+    use crate::game::{room_deletion_channel, DeletionOverflowPolicy, PlayerId};
+
+    fn turn_based(turn_timeout: Duration) -> RoomState {
+        RoomState {
+            turn_timeout_secs: Some(turn_timeout.as_secs()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn picks_games_up_where_they_were_left() {
+        let file = std::env::temp_dir().join(format!(
+            "wormhole-turn-based-rooms-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let rooms = TurnBasedRooms::with_file(file.clone());
+        let registry = RoomRegistry::new(1);
+        let (queue, _handler) = room_deletion_channel(
+            Arc::new(RoomRegistry::new(1)),
+            8,
+            8,
+            DeletionOverflowPolicy::GrowAndWarn,
+        );
+        let id = registry
+            .create_room_with(|room| room.restore(turn_based(Duration::from_secs(3600))))
+            .unwrap();
+        let room = registry.get_room_for_id(id).unwrap();
+        let turn = room
+            .pass_turn(id, PlayerId::from(1), queue.clone())
+            .unwrap();
+        registry
+            .create_room_with(|room| room.restore(RoomState::default()))
+            .unwrap();
+        rooms.save(&registry);
+
+        let restarted = Arc::new(RoomRegistry::new(1));
+        let restored = rooms.restore(&restarted, &queue).await.unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(restored, 1);
+        let room = restarted.get_room_for_id(id).unwrap();
+        assert_eq!(room.turn(), Some(turn));
+        let remaining = room.time_until_deletion().unwrap();
+        assert!(remaining > Duration::from_secs(3590), "{remaining:?}");
+    }
+
+    #[tokio::test]
+    async fn leaves_out_rooms_whose_turn_ran_out() {
+        let file = std::env::temp_dir().join(format!(
+            "wormhole-turn-based-rooms-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let saved = [SavedRoom {
+            id: RoomId::from(1),
+            state: turn_based(Duration::from_secs(60)),
+            deletes_at: Some(unix_time() - 1),
+        }];
+        write_atomically(&file, &saved).unwrap();
+        let registry = Arc::new(RoomRegistry::new(1));
+        let (queue, _handler) = room_deletion_channel(
+            Arc::new(RoomRegistry::new(1)),
+            8,
+            8,
+            DeletionOverflowPolicy::GrowAndWarn,
+        );
+
+        let restored = TurnBasedRooms::with_file(file.clone())
+            .restore(&registry, &queue)
+            .await
+            .unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(restored, 0);
+        assert_eq!(registry.room_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn restores_many_rooms_a_few_at_a_time() {
+        let file = std::env::temp_dir().join(format!(
+            "wormhole-turn-based-rooms-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let saved: Vec<_> = (1..=100_u128)
+            .map(|id| SavedRoom {
+                id: RoomId::from(id),
+                state: turn_based(Duration::from_secs(60)),
+                deletes_at: Some(unix_time() + 60),
+            })
+            .collect();
+        write_atomically(&file, &saved).unwrap();
+        let registry = Arc::new(RoomRegistry::new(4));
+        let (queue, _handler) = room_deletion_channel(
+            Arc::new(RoomRegistry::new(1)),
+            8,
+            8,
+            DeletionOverflowPolicy::GrowAndWarn,
+        );
+
+        let restored = TurnBasedRooms::with_file(file.clone())
+            .with_restore_parallelism(3)
+            .restore(&registry, &queue)
+            .await
+            .unwrap();
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(restored, 100);
+        assert_eq!(registry.room_count(), 100);
+    }
+}
+
+#[cfg(test)]
+mod save {
+    use super::*;
+
+    fn temp_file() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "wormhole-turn-based-rooms-{}.json",
+            uuid::Uuid::new_v4()
+        ))
+    }
+
+    fn turn_based_room(registry: &RoomRegistry) -> RoomId {
+        registry
+            .create_room_with(|room| {
+                room.restore(RoomState {
+                    turn_timeout_secs: Some(3600),
+                    ..Default::default()
+                })
+            })
+            .unwrap()
+    }
+
+    fn logged_deltas(file: &Path) -> Vec<Delta> {
+        fs::read_to_string(log_file(file))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn remove_files(file: &Path) {
+        let _ = fs::remove_file(file);
+        let _ = fs::remove_file(log_file(file));
+    }
+
+    #[test]
+    fn logs_only_the_rooms_that_changed() {
+        let file = temp_file();
+        let rooms = TurnBasedRooms::with_file(file.clone());
+        let registry = RoomRegistry::new(1);
+        let renamed = turn_based_room(&registry);
+        let deleted = turn_based_room(&registry);
+        rooms.save(&registry);
+        rooms.save(&registry);
+        assert_eq!(logged_deltas(&file), []);
+
+        registry
+            .get_room_for_id(renamed)
+            .unwrap()
+            .set_name("Endgame".to_owned());
+        rooms.save(&registry);
+        registry.delete_room(deleted);
+        rooms.save(&registry);
+
+        let deltas = logged_deltas(&file);
+        let (restored_seq, restored) = read_rooms(&file).unwrap();
+        remove_files(&file);
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(
+            deltas[0]
+                .saved
+                .iter()
+                .map(|room| room.id)
+                .collect::<Vec<_>>(),
+            [renamed]
+        );
+        assert_eq!(deltas[1].deleted, [deleted]);
+        assert_eq!(restored_seq, 2);
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].state.name.as_deref(), Some("Endgame"));
+    }
+
+    #[test]
+    fn folds_the_log_into_a_new_base() {
+        let file = temp_file();
+        let rooms = TurnBasedRooms {
+            compact_after: 1,
+            ..TurnBasedRooms::with_file(file.clone())
+        };
+        let registry = RoomRegistry::new(1);
+        let id = turn_based_room(&registry);
+        let room = registry.get_room_for_id(id).unwrap();
+        rooms.save(&registry);
+        room.set_name("Opening".to_owned());
+        rooms.save(&registry);
+        assert_eq!(logged_deltas(&file).len(), 1);
+
+        room.set_name("Endgame".to_owned());
+        rooms.save(&registry);
+
+        let deltas = logged_deltas(&file);
+        let (restored_seq, restored) = read_rooms(&file).unwrap();
+        remove_files(&file);
+        assert_eq!(deltas, []);
+        assert_eq!(restored_seq, 1);
+        assert_eq!(restored[0].state.name.as_deref(), Some("Endgame"));
+    }
+
+    #[test]
+    fn skips_deltas_the_base_already_covers() {
+        let file = temp_file();
+        let room = SavedRoom {
+            id: RoomId::from(1),
+            state: RoomState::default(),
+            deletes_at: None,
+        };
+        write_atomically(
+            &file,
+            &Base::Numbered {
+                seq: 1,
+                rooms: vec![room.clone()],
+            },
+        )
+        .unwrap();
+        let stale = Delta {
+            seq: 1,
+            saved: Vec::new(),
+            deleted: vec![room.id],
+        };
+        fs::write(log_file(&file), serde_json::to_vec(&stale).unwrap()).unwrap();
+
+        let restored = read_rooms(&file).unwrap();
+        remove_files(&file);
+
+        assert_eq!(restored, (1, vec![room]));
+    }
+}
+
+#[cfg(test)]
+mod run {
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_rooms_marked_as_changed_without_waiting_for_the_interval() {
+        let file = std::env::temp_dir().join(format!(
+            "wormhole-turn-based-rooms-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let rooms = Arc::new(TurnBasedRooms::with_file(file.clone()));
+        let registry = Arc::new(RoomRegistry::new(1));
+        let id = registry
+            .create_room_with(|room| {
+                room.restore(RoomState {
+                    turn_timeout_secs: Some(3600),
+                    ..Default::default()
+                })
+            })
+            .unwrap();
+        let shutdown = CancellationToken::new();
+        let saver = tokio::spawn(rooms.clone().run(registry.clone(), shutdown.clone()));
+
+        registry
+            .get_room_for_id(id)
+            .unwrap()
+            .set_name("Endgame".to_owned());
+        rooms.mark_changed();
+        let saved_name = async {
+            loop {
+                if let Ok((_, saved)) = read_rooms(&file) {
+                    if let Some(name) = saved.first().and_then(|room| room.state.name.clone()) {
+                        return name;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let saved_name = tokio::time::timeout(Duration::from_secs(5), saved_name).await;
+        shutdown.cancel();
+        saver.await.unwrap();
+        let _ = fs::remove_file(&file);
+        let _ = fs::remove_file(log_file(&file));
+
+        assert_eq!(saved_name.as_deref(), Ok("Endgame"));
+    }
+}
@@ -0,0 +1,259 @@
+//! Players waiting for a seat in a full room
+//!
+//! A signed in player turned away from a full room can wait on its waiting list, for as long
+//! as they follow their place on it. Each time a player leaves the room, the seat they freed
+//! is held for whoever has waited longest, who is taken off the list and told to join before
+//! the hold runs out, the seat going to whoever waited longest next if they don't. Players
+//! who didn't wait can't take a seat held for someone who did.
+//! How long a player still has to wait is estimated from how often seats were freed while
+//! players were waiting, so it's only known once a few players have left the room since.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::game::{PlayerId, Room, RoomId};
+
+/// How long a seat freed for a waiting player is held for them to join
+pub const WAITING_SEAT_HOLD: Duration = Duration::from_secs(60);
+
+/// How many of the times a room's seats were freed the estimated wait is worked out from
+const FREED_SEATS_KEPT: usize = 10;
+
+/// How many changes to the waiting lists can be held for a subscriber that fell behind
+const UPDATE_CAPACITY: usize = 256;
+
+/// Where a player stands on a room's waiting list
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, ToSchema)]
+pub struct WaitingPosition {
+    /// The player's place on the list, from 1 for whoever is next to be seated
+    pub position: usize,
+    /// How long the player can expect to wait, in seconds, unless too few seats were freed
+    /// lately to tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_wait_secs: Option<u64>,
+}
+
+/// A change to a room's waiting list
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitingListUpdate {
+    pub room_id: RoomId,
+    /// The player a seat was just held for, who is no longer waiting
+    pub seated: Option<PlayerId>,
+}
+
+#[derive(Debug, Default)]
+struct WaitingList {
+    /// The players waiting, longest first
+    players: VecDeque<PlayerId>,
+    /// When the room's seats were last freed while players waited, oldest first
+    freed_at: VecDeque<Instant>,
+}
+
+impl WaitingList {
+    /// The mean time between the room's seats being freed lately, if enough have been
+    fn seat_interval(&self) -> Option<Duration> {
+        let (first, last) = (self.freed_at.front()?, self.freed_at.back()?);
+        let gaps = u32::try_from(self.freed_at.len() - 1).ok()?;
+        (gaps > 0).then(|| last.duration_since(*first) / gaps)
+    }
+}
+
+/// The waiting list of every room with players waiting on it
+#[derive(Debug)]
+pub struct WaitingLists {
+    lists: Mutex<HashMap<RoomId, WaitingList>>,
+    updates: Sender<WaitingListUpdate>,
+}
+
+impl Default for WaitingLists {
+    fn default() -> Self {
+        Self {
+            lists: Default::default(),
+            updates: broadcast::channel(UPDATE_CAPACITY).0,
+        }
+    }
+}
+
+impl WaitingLists {
+    /// Changes to every room's waiting list from now on
+    pub fn subscribe(&self) -> Receiver<WaitingListUpdate> {
+        self.updates.subscribe()
+    }
+
+    fn publish(&self, room_id: RoomId, seated: Option<PlayerId>) {
+        // Nobody following the lists is fine
+        let _ = self.updates.send(WaitingListUpdate { room_id, seated });
+    }
+
+    /// Puts the player at the back of the room's waiting list, unless they're on it already
+    pub fn join(&self, room_id: RoomId, player_id: PlayerId) {
+        let mut lists = self.lists.lock().unwrap();
+        let list = lists.entry(room_id).or_default();
+        if list.players.contains(&player_id) {
+            return;
+        }
+        list.players.push_back(player_id);
+        drop(lists);
+        info!(event = "waiting_list_joined", %room_id, %player_id);
+        self.publish(room_id, None);
+    }
+
+    /// Takes the player off the room's waiting list, if they're still on it
+    pub fn leave(&self, room_id: RoomId, player_id: PlayerId) {
+        let mut lists = self.lists.lock().unwrap();
+        let Some(list) = lists.get_mut(&room_id) else {
+            return;
+        };
+        let Some(index) = list.players.iter().position(|&id| id == player_id) else {
+            return;
+        };
+        list.players.remove(index);
+        if list.players.is_empty() {
+            lists.remove(&room_id);
+        }
+        drop(lists);
+        info!(event = "waiting_list_left", %room_id, %player_id);
+        self.publish(room_id, None);
+    }
+
+    /// Where the player stands on the room's waiting list, if they're on it
+    pub fn position(&self, room_id: RoomId, player_id: PlayerId) -> Option<WaitingPosition> {
+        let lists = self.lists.lock().unwrap();
+        let list = lists.get(&room_id)?;
+        let position = list.players.iter().position(|&id| id == player_id)? + 1;
+        Some(WaitingPosition {
+            position,
+            estimated_wait_secs: list
+                .seat_interval()
+                .map(|interval| (interval * position as u32).as_secs()),
+        })
+    }
+
+    /// Records that a player left the room at `now`, holding each seat now free for whoever
+    /// has waited longest
+    pub fn seat_freed(&self, room_id: RoomId, room: &Room, now: Instant) {
+        let mut lists = self.lists.lock().unwrap();
+        let Some(list) = lists.get_mut(&room_id) else {
+            return;
+        };
+        list.freed_at.push_back(now);
+        if list.freed_at.len() > FREED_SEATS_KEPT {
+            list.freed_at.pop_front();
+        }
+        drop(lists);
+        self.seat_waiting(room_id, room, now);
+    }
+
+    /// Holds each seat free in the room at `now` for whoever has waited longest, as when a
+    /// seat held for a waiting player ran out without them taking it
+    pub fn seat_waiting(&self, room_id: RoomId, room: &Room, now: Instant) {
+        let mut lists = self.lists.lock().unwrap();
+        let Some(list) = lists.get_mut(&room_id) else {
+            return;
+        };
+        let mut seated = Vec::new();
+        while let Some(&player_id) = list.players.front() {
+            if !room.hold_seats(&[player_id], now + WAITING_SEAT_HOLD) {
+                break;
+            }
+            list.players.pop_front();
+            seated.push(player_id);
+        }
+        if list.players.is_empty() {
+            lists.remove(&room_id);
+        }
+        drop(lists);
+        for player_id in seated {
+            info!(event = "waiting_player_seated", %room_id, %player_id);
+            self.publish(room_id, Some(player_id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod seat_waiting {
+    use super::*;
+
+    #[test]
+    fn seats_the_next_player_once_a_held_seat_runs_out() {
+        let lists = WaitingLists::default();
+        let room = Room::new().with_capacity(Some(1));
+        let room_id = RoomId::from(1_u128);
+        let now = Instant::now();
+        lists.join(room_id, 3.into());
+        room.hold_seats(&[2.into()], now + WAITING_SEAT_HOLD);
+
+        lists.seat_waiting(room_id, &room, now);
+        assert_eq!(lists.position(room_id, 3.into()).unwrap().position, 1);
+        // Holding the seat again until now has it run out
+        room.hold_seats(&[2.into()], Instant::now());
+        lists.seat_waiting(room_id, &room, Instant::now());
+
+        assert_eq!(lists.position(room_id, 3.into()), None);
+        assert!(room.has_seat_for(Some(3.into())));
+        assert!(!room.has_seat_for(Some(2.into())));
+    }
+}
+
+#[cfg(test)]
+mod seat_freed {
+    use super::*;
+
+    #[test]
+    fn seats_whoever_waited_longest() {
+        let lists = WaitingLists::default();
+        let room = Room::new().with_capacity(Some(1));
+        let room_id = RoomId::from(1_u128);
+        let now = Instant::now();
+        lists.join(room_id, 2.into());
+        lists.join(room_id, 3.into());
+        let mut updates = lists.subscribe();
+
+        lists.seat_freed(room_id, &room, now);
+
+        assert_eq!(
+            updates.try_recv().unwrap(),
+            WaitingListUpdate {
+                room_id,
+                seated: Some(2.into())
+            }
+        );
+        assert!(!room.has_seat_for(Some(4.into())));
+        assert_eq!(lists.position(room_id, 2.into()), None);
+        assert_eq!(lists.position(room_id, 3.into()).unwrap().position, 1);
+    }
+
+    #[test]
+    fn estimates_the_wait_from_how_often_seats_are_freed() {
+        let lists = WaitingLists::default();
+        let room = Room::new().with_capacity(Some(0));
+        let room_id = RoomId::from(1_u128);
+        let now = Instant::now();
+        lists.join(room_id, 2.into());
+        lists.join(room_id, 3.into());
+        assert_eq!(
+            lists.position(room_id, 3.into()),
+            Some(WaitingPosition {
+                position: 2,
+                estimated_wait_secs: None
+            })
+        );
+
+        lists.seat_freed(room_id, &room, now);
+        lists.seat_freed(room_id, &room, now + Duration::from_secs(30));
+
+        assert_eq!(
+            lists.position(room_id, 3.into()),
+            Some(WaitingPosition {
+                position: 2,
+                estimated_wait_secs: Some(60)
+            })
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! A small web UI for developing against the server, which lists rooms, shows their details
+//! and opens a console on a room's WebSocket
+//!
+//! The files under `web/` are built into the binary, so the UI needs nothing else deployed
+//! alongside the server.
+
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+
+const INDEX_HTML: &str = include_str!("../web/index.html");
+const APP_JS: &str = include_str!("../web/app.js");
+const STYLE_CSS: &str = include_str!("../web/style.css");
+
+fn serve(content_type: ContentType, body: &'static str) -> HttpResponse {
+    HttpResponse::Ok().content_type(content_type).body(body)
+}
+
+/// Serves the UI at `/ui/`
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/ui").route(web::get().to(|| async {
+        HttpResponse::PermanentRedirect()
+            .insert_header(("LOCATION", "/ui/"))
+            .finish()
+    })))
+    .service(
+        web::resource("/ui/")
+            .route(web::get().to(|| async { serve(ContentType::html(), INDEX_HTML) })),
+    )
+    .service(web::resource("/ui/app.js").route(
+        web::get().to(|| async { serve(ContentType(actix_web::mime::TEXT_JAVASCRIPT), APP_JS) }),
+    ))
+    .service(web::resource("/ui/style.css").route(
+        web::get().to(|| async { serve(ContentType(actix_web::mime::TEXT_CSS), STYLE_CSS) }),
+    ));
+}
+
+#[cfg(test)]
+mod configure {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn serves_every_file() {
+        let app = test::init_service(App::new().configure(configure)).await;
+
+        for (path, content_type) in [
+            ("/ui/", "text/html; charset=utf-8"),
+            ("/ui/app.js", "text/javascript"),
+            ("/ui/style.css", "text/css"),
+        ] {
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+            assert!(response.status().is_success(), "{path} wasn't served");
+            assert_eq!(
+                response.headers().get("content-type").unwrap(),
+                content_type
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn redirects_to_the_index() {
+        let app = test::init_service(App::new().configure(configure)).await;
+
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/ui").to_request()).await;
+        assert_eq!(response.headers().get("location").unwrap(), "/ui/");
+    }
+}
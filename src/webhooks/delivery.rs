@@ -0,0 +1,506 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{RoomWebhooks, WebhookSigner};
+use crate::bans::Ban;
+use crate::clock::unix_time;
+use crate::events::EventSink;
+use crate::game::{LobbyEvent, RoomId};
+use crate::presence::Presence;
+use crate::scheduling::RoomOpened;
+use crate::spam::AutoMute;
+
+/// How long a single delivery attempt may take before it counts as failed
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The kinds of [events][WebhookEvent] an endpoint can subscribe to
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    RoomCreated,
+    GameFinished,
+    PlayerBanned,
+    PresenceUpdated,
+    RoomOpened,
+    PlayerAutoMuted,
+}
+
+impl WebhookEventKind {
+    const ALL: [Self; 6] = [
+        Self::RoomCreated,
+        Self::GameFinished,
+        Self::PlayerBanned,
+        Self::PresenceUpdated,
+        Self::RoomOpened,
+        Self::PlayerAutoMuted,
+    ];
+}
+
+impl std::fmt::Display for WebhookEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RoomCreated => f.write_str("room_created"),
+            Self::GameFinished => f.write_str("game_finished"),
+            Self::PlayerBanned => f.write_str("player_banned"),
+            Self::PresenceUpdated => f.write_str("presence_updated"),
+            Self::RoomOpened => f.write_str("room_opened"),
+            Self::PlayerAutoMuted => f.write_str("player_auto_muted"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum WebhookSubscriptionParseError {
+    #[error("{0:?} is not a known webhook event, expected \"room_created\", \"game_finished\", \"player_banned\", \"presence_updated\", \"room_opened\", \"player_auto_muted\" or \"*\"")]
+    UnknownEvent(String),
+    #[error("{0:?} is not of the form <events>=<url>")]
+    Malformed(String),
+    #[error("{0:?} is not a valid URL")]
+    InvalidUrl(String),
+}
+
+impl FromStr for WebhookEventKind {
+    type Err = WebhookSubscriptionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| WebhookSubscriptionParseError::UnknownEvent(s.to_owned()))
+    }
+}
+
+/// Something that happened on the server that webhook endpoints can be told about
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    RoomCreated {
+        room_id: RoomId,
+    },
+    /// The room the game was played in was closed or removed for sitting empty
+    GameFinished {
+        room_id: RoomId,
+    },
+    PlayerBanned {
+        ban: Ban,
+    },
+    /// The room's party or settings changed, described for rich presence integrations
+    PresenceUpdated(Presence),
+    /// A room scheduled to open later has opened, and its invited players can join
+    RoomOpened(RoomOpened),
+    /// A player was muted for spamming chat, for moderators to review
+    PlayerAutoMuted(AutoMute),
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            Self::RoomCreated { .. } => WebhookEventKind::RoomCreated,
+            Self::GameFinished { .. } => WebhookEventKind::GameFinished,
+            Self::PlayerBanned { .. } => WebhookEventKind::PlayerBanned,
+            Self::PresenceUpdated(_) => WebhookEventKind::PresenceUpdated,
+            Self::RoomOpened(_) => WebhookEventKind::RoomOpened,
+            Self::PlayerAutoMuted(_) => WebhookEventKind::PlayerAutoMuted,
+        }
+    }
+
+    /// The room the event is about, if it's about a single room
+    pub fn room_id(&self) -> Option<RoomId> {
+        match self {
+            Self::RoomCreated { room_id } | Self::GameFinished { room_id } => Some(*room_id),
+            Self::PresenceUpdated(presence) => Some(presence.room_id),
+            Self::RoomOpened(opened) => Some(opened.room_id),
+            Self::PlayerAutoMuted(mute) => Some(mute.room_id),
+            Self::PlayerBanned { .. } => None,
+        }
+    }
+
+    /// The webhook event for a change to the lobby, if there is one
+    pub fn from_lobby(event: LobbyEvent) -> Option<Self> {
+        match event {
+            LobbyEvent::RoomCreated(room) => Some(Self::RoomCreated { room_id: room.id }),
+            LobbyEvent::RoomUpdated(_) => None,
+            LobbyEvent::RoomDeleted { id } => Some(Self::GameFinished { room_id: id }),
+        }
+    }
+
+    /// The event as a JSON object stamped with the current time, as delivered to webhook
+    /// endpoints and published to other sinks
+    pub fn to_json(&self) -> Vec<u8> {
+        let body = DeliveryBody {
+            event: self,
+            occurred_at: unix_time(),
+        };
+        serde_json::to_vec(&body).expect("Webhook events always serialize")
+    }
+}
+
+/// The body of a delivery
+#[derive(Debug, Serialize)]
+struct DeliveryBody<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// Seconds since the Unix epoch
+    occurred_at: u64,
+}
+
+/// An endpoint along with the events delivered to it
+///
+/// Endpoint URLs often carry a secret of their own, so only their host is ever logged.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WebhookSubscription {
+    pub url: Url,
+    pub events: Vec<WebhookEventKind>,
+}
+
+impl WebhookSubscription {
+    pub fn wants(&self, kind: WebhookEventKind) -> bool {
+        self.events.contains(&kind)
+    }
+}
+
+/// The endpoints deliveries are made to
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct WebhookSubscriptions(pub Vec<WebhookSubscription>);
+
+/// Parses `;` separated subscriptions of the form `<events>=<url>`, where `<events>` is a
+/// comma separated list of event kinds, or `*` for every kind
+impl FromStr for WebhookSubscriptions {
+    type Err = WebhookSubscriptionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (events, url) = entry
+                    .split_once('=')
+                    .filter(|(_, url)| !url.trim().is_empty())
+                    .ok_or_else(|| WebhookSubscriptionParseError::Malformed(entry.to_owned()))?;
+                let events = match events.trim() {
+                    "*" => WebhookEventKind::ALL.to_vec(),
+                    events => events
+                        .split(',')
+                        .map(|event| event.trim().parse())
+                        .collect::<Result<_, _>>()?,
+                };
+                let url = url.trim();
+                Ok(WebhookSubscription {
+                    url: Url::parse(url)
+                        .map_err(|_| WebhookSubscriptionParseError::InvalidUrl(url.to_owned()))?,
+                    events,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl WebhookSubscriptions {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// How failed deliveries are retried
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RetryPolicy {
+    /// How many times a delivery is attempted in total before it's given up on
+    pub max_attempts: u32,
+    /// How long to wait before the first retry, doubling with each retry after it
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the given failed attempt, counting from one, before retrying
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+/// Whether a delivery answered with `status` is worth attempting again
+pub(crate) fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Delivers events to the endpoints subscribed to them
+///
+/// Each delivery runs on its own task, so a slow or failing endpoint never holds up
+/// deliveries to the others.
+#[derive(Debug)]
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    signer: WebhookSigner,
+    subscriptions: WebhookSubscriptions,
+    /// The webhooks rooms were created with, each told about its own room's events
+    room_webhooks: Arc<RoomWebhooks>,
+    retry: RetryPolicy,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        subscriptions: WebhookSubscriptions,
+        signer: WebhookSigner,
+        retry: RetryPolicy,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("The webhook client has a valid configuration"),
+            signer,
+            subscriptions,
+            room_webhooks: Default::default(),
+            retry,
+        }
+    }
+
+    /// Also delivers each room's events to the webhook it was created with, as registered
+    /// in `room_webhooks`
+    pub fn with_room_webhooks(mut self, room_webhooks: Arc<RoomWebhooks>) -> Self {
+        self.room_webhooks = room_webhooks;
+        self
+    }
+
+    /// Starts delivering the event to every endpoint subscribed to it
+    pub fn dispatch(self: &Arc<Self>, event: &WebhookEvent) {
+        let body = event.to_json();
+        let urls = self
+            .subscriptions
+            .0
+            .iter()
+            .filter(|subscription| subscription.wants(event.kind()))
+            .map(|subscription| subscription.url.clone())
+            .chain(
+                self.room_webhooks
+                    .subscription_for(event)
+                    .map(|subscription| subscription.url),
+            );
+        for url in urls {
+            let dispatcher = self.clone();
+            let body = body.clone();
+            tokio::spawn(async move { dispatcher.deliver(&url, body).await });
+        }
+    }
+
+    /// Delivers the body to the endpoint, retrying until it's accepted or the attempts run
+    /// out
+    async fn deliver(&self, url: &Url, body: Vec<u8>) {
+        let host = url.host_str().unwrap_or_default();
+        let delivery_id = Uuid::new_v4();
+        for attempt in 1..=self.retry.max_attempts {
+            let signed = self.signer.sign_as(delivery_id, &body);
+            let mut request = self
+                .client
+                .post(url.clone())
+                .header("CONTENT-TYPE", "application/json")
+                .body(body.clone());
+            for (name, value) in signed.headers() {
+                request = request.header(name, value);
+            }
+
+            let retryable = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(event = "webhook_delivered", host, %delivery_id, attempt);
+                    return;
+                }
+                Ok(response) => {
+                    warn!(event = "webhook_rejected", host, %delivery_id, attempt, status = response.status().as_u16());
+                    is_retryable(response.status())
+                }
+                Err(e) => {
+                    warn!(event = "webhook_unreachable", host, %delivery_id, attempt, error = %e);
+                    true
+                }
+            };
+            if !retryable {
+                break;
+            }
+            if attempt < self.retry.max_attempts {
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+            }
+        }
+        warn!(event = "webhook_given_up", host, %delivery_id);
+    }
+}
+
+impl EventSink for Arc<WebhookDispatcher> {
+    fn publish(&self, event: &WebhookEvent) {
+        self.dispatch(event);
+    }
+}
+
+#[cfg(test)]
+mod webhook_subscriptions_from_str {
+    use super::*;
+
+    #[test]
+    fn parses_events_and_urls() {
+        let subscriptions: WebhookSubscriptions =
+            "room_created, game_finished=https://a.example/hook?x=1; *=https://b.example/hook"
+                .parse()
+                .unwrap();
+
+        assert_eq!(
+            subscriptions.0,
+            vec![
+                WebhookSubscription {
+                    url: Url::parse("https://a.example/hook?x=1").unwrap(),
+                    events: vec![
+                        WebhookEventKind::RoomCreated,
+                        WebhookEventKind::GameFinished
+                    ],
+                },
+                WebhookSubscription {
+                    url: Url::parse("https://b.example/hook").unwrap(),
+                    events: WebhookEventKind::ALL.to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_empty_string_as_no_subscriptions() {
+        assert!(" ".parse::<WebhookSubscriptions>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_events_and_missing_urls() {
+        assert_eq!(
+            "room_deleted=https://a.example".parse::<WebhookSubscriptions>(),
+            Err(WebhookSubscriptionParseError::UnknownEvent(
+                "room_deleted".to_owned()
+            ))
+        );
+        assert_eq!(
+            "room_created=".parse::<WebhookSubscriptions>(),
+            Err(WebhookSubscriptionParseError::Malformed(
+                "room_created=".to_owned()
+            ))
+        );
+        assert_eq!(
+            "*=not a url".parse::<WebhookSubscriptions>(),
+            Err(WebhookSubscriptionParseError::InvalidUrl(
+                "not a url".to_owned()
+            ))
+        );
+    }
+}
+
+#[cfg(test)]
+mod backoff {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_limit() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+
+        let backoffs: Vec<_> = (1..=5)
+            .map(|attempt| policy.backoff(attempt).as_secs())
+            .collect();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5]);
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+    }
+}
+
+#[cfg(test)]
+mod delivery_body {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn names_the_event_alongside_its_fields() {
+        let event = WebhookEvent::GameFinished {
+            room_id: 1_u128.into(),
+        };
+        let body = DeliveryBody {
+            event: &event,
+            occurred_at: 10,
+        };
+
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            json!({
+                "event": "game_finished",
+                "room_id": "00000000-0000-0000-0000-000000000001",
+                "occurred_at": 10,
+            })
+        );
+    }
+
+    #[test]
+    fn flattens_presence_into_the_body() {
+        let event = WebhookEvent::PresenceUpdated(Presence {
+            room_id: 1_u128.into(),
+            game_type: Some("chess".to_owned()),
+            room_name: None,
+            party_size: 2,
+            join_secret: "secret".to_owned(),
+            join_secret_expires_at: 20,
+        });
+        let body = DeliveryBody {
+            event: &event,
+            occurred_at: 10,
+        };
+
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            json!({
+                "event": "presence_updated",
+                "room_id": "00000000-0000-0000-0000-000000000001",
+                "game_type": "chess",
+                "party_size": 2,
+                "join_secret": "secret",
+                "join_secret_expires_at": 20,
+                "occurred_at": 10,
+            })
+        );
+    }
+
+    #[test]
+    fn leaves_settings_changes_out() {
+        let room = crate::game::RoomSummary {
+            id: 1_u128.into(),
+            name: None,
+            game_type: None,
+            player_count: 0,
+            spectator_count: 0,
+            capacity: None,
+            state: crate::game::RoomStatus::Open,
+            visibility: crate::game::Visibility::Public,
+            region: None,
+            starts_at: None,
+            deletes_at: None,
+            last_active_at: None,
+        };
+        assert_eq!(
+            WebhookEvent::from_lobby(LobbyEvent::RoomUpdated(room)),
+            None
+        );
+    }
+}
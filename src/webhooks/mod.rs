@@ -0,0 +1,16 @@
+//! Outbound webhooks notifying external services, such as chat bots and analytics
+//! pipelines, of activity on the server
+//!
+//! [Subscriptions][WebhookSubscription] pick the [events][WebhookEvent] each endpoint
+//! receives. Deliveries are made in the background, [signed][WebhookSigner] so receivers
+//! can tell they came from the server, and retried with exponential backoff while the
+//! endpoint is unreachable or failing. Rooms can also be created with a
+//! [webhook of their own][RoomWebhook], which is told about that room's events alone.
+
+mod delivery;
+mod room_webhooks;
+mod signing;
+
+pub use delivery::*;
+pub use room_webhooks::*;
+pub use signing::*;
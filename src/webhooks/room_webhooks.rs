@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use super::{WebhookEvent, WebhookEventKind, WebhookSubscription};
+use crate::game::RoomId;
+
+/// The kinds of events that are about a single room, which a room's own webhook can
+/// subscribe to
+const ROOM_EVENT_KINDS: [WebhookEventKind; 4] = [
+    WebhookEventKind::RoomCreated,
+    WebhookEventKind::GameFinished,
+    WebhookEventKind::PresenceUpdated,
+    WebhookEventKind::RoomOpened,
+];
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RoomWebhookError {
+    #[error("{0:?} is not a valid http or https URL")]
+    InvalidUrl(String),
+    #[error("A room's webhook must subscribe to at least one event")]
+    NoEvents,
+    #[error("A room's webhook can't subscribe to {0} events, which aren't about a single room")]
+    NotRoomEvent(WebhookEventKind),
+}
+
+/// An endpoint the room's creator asked to be told about the room's events at, on top of
+/// the endpoints subscribed to every room's
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RoomWebhook {
+    pub url: String,
+    /// The events delivered, any of `room_created`, `game_finished`, `presence_updated` and
+    /// `room_opened`
+    pub events: Vec<WebhookEventKind>,
+}
+
+impl RoomWebhook {
+    /// The subscription deliveries to the webhook are made for, if it's valid
+    pub fn subscription(&self) -> Result<WebhookSubscription, RoomWebhookError> {
+        let url = Url::parse(&self.url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| RoomWebhookError::InvalidUrl(self.url.clone()))?;
+        if self.events.is_empty() {
+            return Err(RoomWebhookError::NoEvents);
+        }
+        if let Some(&kind) = self
+            .events
+            .iter()
+            .find(|kind| !ROOM_EVENT_KINDS.contains(kind))
+        {
+            return Err(RoomWebhookError::NotRoomEvent(kind));
+        }
+        Ok(WebhookSubscription {
+            url,
+            events: self.events.clone(),
+        })
+    }
+}
+
+/// How long the webhook of a deleted room is kept for its `game_finished` event to be
+/// delivered to, in case the event never arrives
+const DELETED_ROOM_WEBHOOK_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct RoomSubscription {
+    subscription: WebhookSubscription,
+    /// When the room was deleted, if it has been
+    deleted_at: Option<Instant>,
+}
+
+/// The webhooks rooms were created with, from the moment each room exists until its game
+/// finishes or it's handed over to another instance
+#[derive(Debug, Default)]
+pub struct RoomWebhooks {
+    subscriptions: Mutex<HashMap<RoomId, RoomSubscription>>,
+}
+
+impl RoomWebhooks {
+    /// Delivers the room's events to its webhook from now on
+    pub fn register(&self, room_id: RoomId, webhook: &RoomWebhook) {
+        match webhook.subscription() {
+            Ok(subscription) => {
+                let host = subscription.url.host_str().unwrap_or_default().to_owned();
+                info!(event = "room_webhook_registered", %room_id, host);
+                self.subscriptions.lock().unwrap().insert(
+                    room_id,
+                    RoomSubscription {
+                        subscription,
+                        deleted_at: None,
+                    },
+                );
+            }
+            Err(e) => warn!(event = "room_webhook_not_registered", %room_id, error = %e),
+        }
+    }
+
+    /// Stops delivering the room's events to its webhook, as when the room is handed over to
+    /// the instance that delivers them from now on
+    pub fn unregister(&self, room_id: RoomId) {
+        if self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .remove(&room_id)
+            .is_some()
+        {
+            info!(event = "room_webhook_unregistered", %room_id);
+        }
+    }
+
+    /// Keeps the deleted room's webhook only for its `game_finished` event, forgetting it after
+    /// [DELETED_ROOM_WEBHOOK_TTL] if the event isn't delivered by then, along with the webhooks
+    /// of rooms deleted that long ago
+    pub fn room_deleted(&self, room_id: RoomId, now: Instant) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(room) = subscriptions.get_mut(&room_id) {
+            room.deleted_at = Some(now);
+        }
+        subscriptions.retain(|_, room| {
+            room.deleted_at
+                .is_none_or(|deleted_at| now < deleted_at + DELETED_ROOM_WEBHOOK_TTL)
+        });
+    }
+
+    /// The webhook of the room the event is about, if it has one subscribed to the event,
+    /// forgetting the webhook once the room's game has finished
+    pub fn subscription_for(&self, event: &WebhookEvent) -> Option<WebhookSubscription> {
+        let room_id = event.room_id()?;
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let subscription = match event {
+            WebhookEvent::GameFinished { .. } => subscriptions.remove(&room_id)?.subscription,
+            _ => subscriptions
+                .get(&room_id)
+                .filter(|room| room.deleted_at.is_none())?
+                .subscription
+                .clone(),
+        };
+        subscription.wants(event.kind()).then_some(subscription)
+    }
+}
+
+#[cfg(test)]
+mod subscription_for {
+    use super::*;
+
+    fn webhook(events: Vec<WebhookEventKind>) -> RoomWebhook {
+        RoomWebhook {
+            url: "https://tournaments.example/rooms".to_owned(),
+            events,
+        }
+    }
+
+    #[test]
+    fn delivers_only_the_rooms_own_events() {
+        let webhooks = RoomWebhooks::default();
+        webhooks.register(
+            1_u128.into(),
+            &webhook(vec![WebhookEventKind::GameFinished]),
+        );
+
+        let created = WebhookEvent::RoomCreated {
+            room_id: 1_u128.into(),
+        };
+        let elsewhere = WebhookEvent::GameFinished {
+            room_id: 2_u128.into(),
+        };
+        let finished = WebhookEvent::GameFinished {
+            room_id: 1_u128.into(),
+        };
+        assert_eq!(webhooks.subscription_for(&created), None);
+        assert_eq!(webhooks.subscription_for(&elsewhere), None);
+        assert!(webhooks.subscription_for(&finished).is_some());
+    }
+
+    #[test]
+    fn forgets_the_webhook_once_the_game_finishes() {
+        let webhooks = RoomWebhooks::default();
+        webhooks.register(1_u128.into(), &webhook(ROOM_EVENT_KINDS.to_vec()));
+        let finished = WebhookEvent::GameFinished {
+            room_id: 1_u128.into(),
+        };
+
+        assert!(webhooks.subscription_for(&finished).is_some());
+        assert_eq!(webhooks.subscription_for(&finished), None);
+    }
+
+    #[test]
+    fn forgets_the_webhook_of_rooms_handed_over() {
+        let webhooks = RoomWebhooks::default();
+        webhooks.register(1_u128.into(), &webhook(ROOM_EVENT_KINDS.to_vec()));
+
+        webhooks.unregister(1_u128.into());
+
+        let finished = WebhookEvent::GameFinished {
+            room_id: 1_u128.into(),
+        };
+        assert_eq!(webhooks.subscription_for(&finished), None);
+    }
+
+    #[test]
+    fn forgets_the_webhook_of_deleted_rooms_whose_game_never_finishes() {
+        let webhooks = RoomWebhooks::default();
+        webhooks.register(1_u128.into(), &webhook(ROOM_EVENT_KINDS.to_vec()));
+        webhooks.register(2_u128.into(), &webhook(ROOM_EVENT_KINDS.to_vec()));
+        let now = Instant::now();
+
+        webhooks.room_deleted(1_u128.into(), now);
+        webhooks.room_deleted(2_u128.into(), now + DELETED_ROOM_WEBHOOK_TTL);
+
+        let finished = |room_id: u128| WebhookEvent::GameFinished {
+            room_id: room_id.into(),
+        };
+        assert_eq!(webhooks.subscription_for(&finished(1)), None);
+        assert!(webhooks.subscription_for(&finished(2)).is_some());
+    }
+
+    #[test]
+    fn rejects_events_about_more_than_the_room() {
+        assert_eq!(
+            webhook(vec![WebhookEventKind::PlayerBanned]).subscription(),
+            Err(RoomWebhookError::NotRoomEvent(
+                WebhookEventKind::PlayerBanned
+            ))
+        );
+        assert_eq!(
+            webhook(Vec::new()).subscription(),
+            Err(RoomWebhookError::NoEvents)
+        );
+        assert_eq!(
+            RoomWebhook {
+                url: "ftp://tournaments.example".to_owned(),
+                events: vec![WebhookEventKind::RoomCreated],
+            }
+            .subscription(),
+            Err(RoomWebhookError::InvalidUrl(
+                "ftp://tournaments.example".to_owned()
+            ))
+        );
+    }
+}
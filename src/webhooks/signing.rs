@@ -0,0 +1,237 @@
+//! Signing of outbound webhook deliveries
+//!
+//! Each delivery carries a unique id in the [DELIVERY_HEADER] and a signature in the
+//! [SIGNATURE_HEADER] of the form `t=<unix seconds>,v1=<hex HMAC-SHA256>`, where the
+//! HMAC is taken with the shared secret over `<t>.<delivery id>.<body>`. Receivers
+//! verify the signature, reject timestamps outside their tolerance, and remember the
+//! delivery ids they've seen within that window to reject replays.
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::clock::unix_time;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "Wormhole-Signature";
+pub const DELIVERY_HEADER: &str = "Wormhole-Delivery";
+
+/// How far a delivery's timestamp may be from the receiver's clock by default
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(5 * 60);
+
+/// The headers that authenticate a single delivery
+#[derive(Debug, PartialEq, Clone)]
+pub struct SignedDelivery {
+    pub delivery_id: Uuid,
+    /// The value of the [SIGNATURE_HEADER]
+    pub signature: String,
+}
+
+impl SignedDelivery {
+    pub fn headers(&self) -> [(&'static str, String); 2] {
+        [
+            (DELIVERY_HEADER, self.delivery_id.to_string()),
+            (SIGNATURE_HEADER, self.signature.clone()),
+        ]
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum WebhookSignatureError {
+    #[error("The signature header is malformed")]
+    Malformed,
+    #[error("The delivery was signed too long ago or too far in the future")]
+    OutsideTolerance,
+    #[error("No signature matches the delivery")]
+    Mismatch,
+}
+
+fn mac(secret: &[u8], timestamp: u64, delivery_id: Uuid, body: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(secret)
+        .expect("HMAC accepts keys of any length")
+        .chain_update(timestamp.to_string())
+        .chain_update(b".")
+        .chain_update(delivery_id.to_string())
+        .chain_update(b".")
+        .chain_update(body)
+}
+
+/// Signs webhook deliveries with a secret shared with their receivers
+pub struct WebhookSigner {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl std::fmt::Debug for WebhookSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookSigner").finish_non_exhaustive()
+    }
+}
+
+impl WebhookSigner {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: Zeroizing::new(secret.into()),
+        }
+    }
+
+    /// Signs a new delivery of `body`, giving it a fresh delivery id
+    pub fn sign(&self, body: &[u8]) -> SignedDelivery {
+        self.sign_as(Uuid::new_v4(), body)
+    }
+
+    /// Signs `body` as of now under an existing delivery id, so retries of a delivery can be
+    /// told apart from new ones
+    pub fn sign_as(&self, delivery_id: Uuid, body: &[u8]) -> SignedDelivery {
+        self.sign_at(delivery_id, body, unix_time())
+    }
+
+    fn sign_at(&self, delivery_id: Uuid, body: &[u8], timestamp: u64) -> SignedDelivery {
+        let signature = mac(&self.secret, timestamp, delivery_id, body)
+            .finalize()
+            .into_bytes();
+        SignedDelivery {
+            delivery_id,
+            signature: format!("t={timestamp},v1={}", hex::encode(signature)),
+        }
+    }
+}
+
+/// Checks a delivery's signature the way a receiver would, accepting it if any `v1`
+/// signature in the header matches, so secrets can be rotated by signing with both
+pub fn verify_signature(
+    secret: &[u8],
+    signature_header: &str,
+    delivery_id: Uuid,
+    body: &[u8],
+    tolerance: Duration,
+) -> Result<(), WebhookSignatureError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in signature_header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => {
+                timestamp = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| WebhookSignatureError::Malformed)?,
+                )
+            }
+            Some(("v1", value)) => {
+                signatures.push(hex::decode(value).map_err(|_| WebhookSignatureError::Malformed)?)
+            }
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(WebhookSignatureError::Malformed)?;
+    if unix_time().abs_diff(timestamp) > tolerance.as_secs() {
+        return Err(WebhookSignatureError::OutsideTolerance);
+    }
+
+    signatures
+        .iter()
+        .any(|signature| {
+            mac(secret, timestamp, delivery_id, body)
+                .verify_slice(signature)
+                .is_ok()
+        })
+        .then_some(())
+        .ok_or(WebhookSignatureError::Mismatch)
+}
+
+#[cfg(test)]
+mod verify_signature {
+    use super::*;
+
+    const SECRET: &[u8] = b"shared-secret";
+    const BODY: &[u8] = br#"{"event":"room_created"}"#;
+
+    #[test]
+    fn accepts_signed_deliveries() {
+        let delivery = WebhookSigner::new(SECRET).sign(BODY);
+
+        assert_eq!(
+            verify_signature(
+                SECRET,
+                &delivery.signature,
+                delivery.delivery_id,
+                BODY,
+                DEFAULT_TOLERANCE
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_bodies_and_delivery_ids() {
+        let delivery = WebhookSigner::new(SECRET).sign(BODY);
+
+        assert_eq!(
+            verify_signature(
+                SECRET,
+                &delivery.signature,
+                delivery.delivery_id,
+                b"{}",
+                DEFAULT_TOLERANCE
+            ),
+            Err(WebhookSignatureError::Mismatch)
+        );
+        assert_eq!(
+            verify_signature(
+                SECRET,
+                &delivery.signature,
+                Uuid::new_v4(),
+                BODY,
+                DEFAULT_TOLERANCE
+            ),
+            Err(WebhookSignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_stale_deliveries() {
+        let delivery = WebhookSigner::new(SECRET).sign_at(Uuid::new_v4(), BODY, unix_time() - 3600);
+
+        assert_eq!(
+            verify_signature(
+                SECRET,
+                &delivery.signature,
+                delivery.delivery_id,
+                BODY,
+                DEFAULT_TOLERANCE
+            ),
+            Err(WebhookSignatureError::OutsideTolerance)
+        );
+    }
+
+    #[test]
+    fn accepts_any_matching_signature_during_rotation() {
+        let delivery = WebhookSigner::new(SECRET).sign(BODY);
+        let old = WebhookSigner::new("old-secret").sign_at(delivery.delivery_id, BODY, unix_time());
+        let (_, old_signature) = old.signature.split_once(",v1=").unwrap();
+        let header = format!("{},v1={old_signature}", delivery.signature);
+
+        assert_eq!(
+            verify_signature(
+                SECRET,
+                &header,
+                delivery.delivery_id,
+                BODY,
+                DEFAULT_TOLERANCE
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_headers_without_timestamp() {
+        assert_eq!(
+            verify_signature(SECRET, "v1=00", Uuid::new_v4(), BODY, DEFAULT_TOLERANCE),
+            Err(WebhookSignatureError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,2363 @@
+//! WebSocket connections through which clients take part in a [room][Room]
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use actix::{
+    Actor, ActorContext, ActorFuture, ActorFutureExt, AsyncContext, Handler, StreamHandler,
+    WrapFuture,
+};
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use bytestring::ByteString;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::{error, info, info_span, instrument, warn};
+use uuid::Uuid;
+
+use crate::abuse::{AbuseVerdict, Offence};
+use crate::authorization::{Permission, Role};
+use crate::bans::BanTarget;
+use crate::challenge::ChallengeResponse;
+use crate::chat::check_chat_text;
+use crate::clock::unix_time_ms;
+use crate::content_filter::{ContentKind, Verdict};
+use crate::faults;
+use crate::game::{
+    BroadcastError, ConnectionId, EncodedState, GameState, PlayerId, Replay, Room, RoomId,
+    StateCodecError, StateCodecs, Visibility,
+};
+use crate::identity::{AuthenticatedPlayer, PlayerAuthError};
+use crate::invitations::{deliver_answer, InvitationId};
+use crate::invites::{InviteError, DEFAULT_INVITE_TTL};
+use crate::panics::panic_message;
+use crate::problem::Problem;
+use crate::protocol::{
+    batch_frame, is_announcement_frame, is_chat_frame, is_custom_data_frame,
+    is_encoded_broadcast_frame, is_kick_vote_frame, is_reaction_frame, is_teams_balanced_frame,
+    ChatChannel, ChatMessage, ClientEnvelope, ClientMessage, ServerMessage, WireFormat,
+    PROTOCOL_VERSION,
+};
+use crate::rate_limit::{ClientKey, RouteBudget};
+use crate::ratings::INITIAL_RATING;
+use crate::recording::RecordedEvent;
+use crate::reports::{self, NewReport, ReportError};
+use crate::room_watch::WatchedEvent;
+use crate::sessions::{
+    ConnectionDetails, PlayerNotice, SessionConnectionId, SessionEnded, KICKED_REASON,
+};
+use crate::team_balance::{balance_teams, TeamCandidate};
+use crate::SharedAppState;
+
+/// The oldest protocol version still served, with messages translated for clients speaking it
+const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// The protocol version that introduced batch frames, older clients get each message in its own frame
+const BATCH_FRAMES_VERSION: u32 = 2;
+
+/// The protocol version that introduced chat, older clients are neither sent chat nor the
+/// room's chat history
+const CHAT_VERSION: u32 = 3;
+
+/// The protocol version that introduced reactions, older clients aren't sent them
+const REACTIONS_VERSION: u32 = 4;
+
+/// The protocol version that introduced invitations between players, older clients aren't
+/// sent them
+const INVITATIONS_VERSION: u32 = 5;
+
+/// The protocol version that introduced announcements, older clients aren't sent them
+pub(crate) const ANNOUNCEMENTS_VERSION: u32 = 6;
+
+/// The protocol version that introduced replaying missed broadcasts, older clients aren't
+/// replayed anything even if they ask
+const REPLAY_VERSION: u32 = 7;
+
+/// The protocol version that introduced rooms' custom data, older clients aren't sent it
+const CUSTOM_DATA_VERSION: u32 = 8;
+
+/// The protocol version that introduced votes to kick, older clients aren't sent how they're
+/// going
+const KICK_VOTES_VERSION: u32 = 9;
+
+/// The protocol version that introduced balancing teams, older clients aren't sent the teams
+/// players were balanced across
+const TEAM_BALANCE_VERSION: u32 = 10;
+
+/// The protocol version that introduced encoded broadcasts, older clients are sent the payloads
+/// of game types with a codec other than JSON decoded
+const ENCODED_BROADCASTS_VERSION: u32 = 11;
+
+/// The longest name a room's owner can give it
+const MAX_ROOM_NAME_CHARS: usize = 64;
+
+/// The most custom data a room's owner can set, in bytes of JSON
+const MAX_CUSTOM_DATA_BYTES: usize = 8 * 1024;
+
+/// The most broadcast bytes held back for a batch before it's written out early, as frames
+/// handed over by the room's broadcaster no longer count against the room's memory budget
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// The most lines of chat a connection can have waiting to be charged and filtered
+const MAX_QUEUED_CHAT: usize = 16;
+
+/// Why a spectator whose delayed queue went over its budget was disconnected
+const SPECTATOR_OVERFLOWED_REASON: &str = "Fell too far behind the room, reconnect to watch it";
+
+/// The protocol version to speak with a client asking for `requested`, or why it can't be served
+pub fn negotiate_protocol_version(requested: Option<u32>) -> Result<u32, String> {
+    // Clients that predate negotiation speak the oldest version
+    let version = requested.unwrap_or(MIN_PROTOCOL_VERSION);
+    if version < MIN_PROTOCOL_VERSION {
+        Err(format!(
+            "Protocol version {version} is no longer supported, please update to version {MIN_PROTOCOL_VERSION} or later"
+        ))
+    } else if version > PROTOCOL_VERSION {
+        Err(format!(
+            "Protocol version {version} is not supported yet, the newest is {PROTOCOL_VERSION}"
+        ))
+    } else {
+        Ok(version)
+    }
+}
+
+/// Counts misbehaving clients across every connection
+#[derive(Debug, Default)]
+pub struct AbuseCounters {
+    violations: AtomicU64,
+    disconnects: AtomicU64,
+}
+
+impl AbuseCounters {
+    fn record_violation(&self) {
+        self.violations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_disconnect(&self) {
+        self.disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of messages rejected for claiming someone else's identity or
+    /// asking for something the sender isn't permitted to do
+    pub fn violation_count(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    /// The number of connections closed for abusing the server
+    pub fn disconnect_count(&self) -> u64 {
+        self.disconnects.load(Ordering::Relaxed)
+    }
+}
+
+/// An actor that owns a single client's WebSocket for the lifetime of its connection to a room
+struct RoomConnection {
+    room_id: RoomId,
+    room: Arc<Room>,
+    connection_id: Option<ConnectionId>,
+    /// The player behind the connection, `None` for anonymous connections
+    player: Option<AuthenticatedPlayer>,
+    /// The client's address, which anonymous connections' chat is charged to
+    address: Option<IpAddr>,
+    /// What the connection's chat is charged to when it has neither a player nor an address
+    connection_key: Uuid,
+    /// The protocol version negotiated with the client
+    protocol_version: u32,
+    format: WireFormat,
+    /// The connection's registration with the session registry, for authenticated players
+    session_connection_id: Option<SessionConnectionId>,
+    state: web::Data<SharedAppState>,
+    /// How long broadcasts are held back so they can be written out as a single batch,
+    /// zero to write each one as soon as it arrives
+    flush_interval: Duration,
+    pending_broadcasts: Vec<Bytes>,
+    /// The size of the broadcasts held back, kept under [MAX_BATCH_BYTES]
+    pending_bytes: usize,
+    /// Whether the connection is watching the room rather than playing in it
+    spectator: bool,
+    /// Lines of chat waiting for the ones before them to be charged and filtered, so they're
+    /// sent in the order they were written
+    queued_chat: VecDeque<QueuedChat>,
+    /// Whether a line of chat is being charged and filtered
+    sending_chat: bool,
+    /// The last broadcast the client saw before rejoining, which it's replayed everything
+    /// after
+    last_seq: Option<u64>,
+}
+
+/// A line of chat a connection is yet to charge and filter
+struct QueuedChat {
+    from: ConnectionId,
+    text: String,
+    channel: ChatChannel,
+}
+
+impl RoomConnection {
+    fn new(
+        room_id: RoomId,
+        room: Arc<Room>,
+        player: Option<AuthenticatedPlayer>,
+        address: Option<IpAddr>,
+        protocol_version: u32,
+        format: WireFormat,
+        state: web::Data<SharedAppState>,
+    ) -> Self {
+        let flush_interval = state.broadcast_flush_interval;
+        Self {
+            room_id,
+            room,
+            connection_id: None,
+            player,
+            address,
+            connection_key: Uuid::new_v4(),
+            protocol_version,
+            format,
+            session_connection_id: None,
+            state,
+            flush_interval,
+            pending_broadcasts: Vec::new(),
+            pending_bytes: 0,
+            spectator: false,
+            queued_chat: VecDeque::new(),
+            sending_chat: false,
+            last_seq: None,
+        }
+    }
+
+    /// Has the connection replay the broadcasts after the one numbered `last_seq`, if the
+    /// client speaks a protocol version that can tell it's rejoining
+    fn replaying_after(mut self, last_seq: Option<u64>) -> Self {
+        self.last_seq = last_seq.filter(|_| self.protocol_version >= REPLAY_VERSION);
+        self
+    }
+
+    /// Has the connection watch the room rather than play in it
+    fn spectating(mut self, spectator: bool) -> Self {
+        self.spectator = spectator;
+        self
+    }
+
+    /// Records what happened on the connection, if the server is recording
+    fn record(&self, event: impl FnOnce() -> RecordedEvent) {
+        if let (Some(recorder), Some(connection_id)) = (&self.state.recorder, self.connection_id) {
+            recorder.record(self.room_id, connection_id, event());
+        }
+    }
+
+    /// Tells the operators watching the room what happened on the connection
+    fn watch(&self, event: impl FnOnce(ConnectionId, Option<PlayerId>) -> WatchedEvent) {
+        if let Some(connection_id) = self.connection_id {
+            let player_id = self.player.as_ref().map(|player| player.id);
+            self.state
+                .room_watchers
+                .publish(self.room_id, || event(connection_id, player_id));
+        }
+    }
+
+    /// Writes out a message the broadcaster serialized as JSON, transcoding it for
+    /// connections that speak protobuf
+    fn write_payload(&self, ctx: &mut ws::WebsocketContext<Self>, payload: Bytes) {
+        self.record(|| RecordedEvent::Sent {
+            message: serde_json::from_slice(&payload).unwrap_or_default(),
+        });
+        match self.format {
+            WireFormat::Json => match ByteString::try_from(payload) {
+                Ok(text) => ctx.text(text),
+                Err(e) => warn!(event = "broadcast_payload_not_utf8", error = %e),
+            },
+            WireFormat::Protobuf => match serde_json::from_slice::<ServerMessage>(&payload) {
+                Ok(message) => ctx.binary(message.encode_protobuf()),
+                Err(e) => warn!(event = "broadcast_payload_not_transcodable", error = %e),
+            },
+        }
+    }
+
+    /// Decodes encoded broadcasts for clients speaking a protocol version from before they were
+    /// introduced, leaving every other frame as it is
+    fn readable_frame(&self, frame: Bytes) -> Option<Bytes> {
+        if self.protocol_version >= ENCODED_BROADCASTS_VERSION
+            || !is_encoded_broadcast_frame(&frame)
+        {
+            return Some(frame);
+        }
+        decode_broadcast(&self.state.state_codecs, &frame)
+            .inspect_err(|e| {
+                warn!(event = "encoded_broadcast_not_decoded", room_id = %self.room_id, error = %e)
+            })
+            .ok()
+    }
+
+    /// The broadcast sending a client joining a turn-based room its game's state, encoded if
+    /// the client can read it as broadcasts in the room are encoded now
+    fn game_state_message<'a>(&self, state: &'a GameState) -> Option<ServerMessage<'a>> {
+        let game_type = self.state.room_registry.game_type_of(&self.room);
+        let codecs = &self.state.state_codecs;
+        if self.protocol_version >= ENCODED_BROADCASTS_VERSION
+            && !codecs.encodes_as_json(game_type.as_deref())
+            && codecs.for_game_type(game_type.as_deref()).name() == state.payload.codec
+        {
+            return Some(ServerMessage::EncodedBroadcast {
+                from: state.from,
+                from_player: state.from_player,
+                codec: state.payload.codec.clone(),
+                payload: Cow::Borrowed(&state.payload.bytes),
+                seq: None,
+            });
+        }
+        let payload = codecs
+            .decode(&state.payload)
+            .inspect_err(
+                |e| warn!(event = "game_state_not_decoded", room_id = %self.room_id, error = %e),
+            )
+            .ok()?;
+        Some(ServerMessage::Broadcast {
+            from: state.from,
+            from_player: state.from_player,
+            payload: Cow::Owned(payload),
+            seq: None,
+        })
+    }
+
+    fn flush_broadcasts(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.pending_bytes = 0;
+        let frame = match self.pending_broadcasts.len() {
+            0 => return,
+            1 => self.pending_broadcasts.pop().unwrap(),
+            _ => batch_frame(&std::mem::take(&mut self.pending_broadcasts)),
+        };
+        self.write_payload(ctx, frame);
+    }
+
+    /// Sends what the room is like as the client joins, through its queue if it's `delayed`
+    /// so it's held back as long as everything sent after it, rather than showing a delayed
+    /// spectator the game as it is now
+    fn send_on_joining(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        delayed: bool,
+        message: &ServerMessage,
+    ) {
+        let Some(connection_id) = self.connection_id.filter(|_| delayed) else {
+            self.send(ctx, message);
+            return;
+        };
+        if let Err(e) = self.room.broadcaster().send_to(&[connection_id], message) {
+            warn!(event = "join_state_not_queued", room_id = %self.room_id, error = %e);
+        }
+    }
+
+    fn send(&self, ctx: &mut ws::WebsocketContext<Self>, message: &ServerMessage) {
+        self.record(|| RecordedEvent::Sent {
+            message: serde_json::to_value(message).unwrap_or_default(),
+        });
+        match self.format {
+            WireFormat::Json => match serde_json::to_string(message) {
+                Ok(text) => ctx.text(text),
+                Err(e) => warn!(event = "server_message_serialization_error", error = %e),
+            },
+            WireFormat::Protobuf => ctx.binary(message.encode_protobuf()),
+        }
+    }
+
+    /// Whether the player's server-wide roles, or their ownership of the room, grant `permission`
+    fn is_permitted(&self, permission: Permission) -> bool {
+        let Some(player) = &self.player else {
+            return false;
+        };
+        let roles = if self.room.owner() == Some(player.id) {
+            player.roles.with(Role::RoomOwner)
+        } else {
+            player.roles
+        };
+        roles.allow(permission)
+    }
+
+    /// Tells the client what it did wrong, and scores it as an invalid action
+    fn record_violation(&mut self, ctx: &mut ws::WebsocketContext<Self>, reason: String) {
+        self.state.abuse_counters.record_violation();
+        warn!(event = "protocol_violation", room_id = %self.room_id, reason);
+        self.send(ctx, &ServerMessage::Error { reason });
+        self.record_offence(ctx, Offence::InvalidAction);
+    }
+
+    /// Adds the offence to the client's abuse score, warning, locking or disconnecting it as
+    /// the score calls for
+    fn record_offence(&mut self, ctx: &mut ws::WebsocketContext<Self>, offence: Offence) {
+        let (verdict, score) = self.state.abuse_scores.record(
+            &self.client_key(),
+            &self.state.abuse_rules,
+            offence,
+            Instant::now(),
+        );
+        match verdict {
+            AbuseVerdict::Tolerated => {}
+            AbuseVerdict::Warned => {
+                warn!(event = "abuse_warned", room_id = %self.room_id, %offence, score);
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: "Keep this up and you'll be disconnected".to_owned(),
+                    },
+                );
+            }
+            AbuseVerdict::Locked(duration) => {
+                warn!(event = "abuse_locked", room_id = %self.room_id, %offence, score, duration_secs = duration.as_secs());
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: format!(
+                            "You can't do anything for {} seconds, keep this up and you'll be disconnected",
+                            duration.as_secs()
+                        ),
+                    },
+                );
+            }
+            AbuseVerdict::Disconnected => {
+                warn!(event = "abuse_disconnected", room_id = %self.room_id, %offence, score);
+                self.state.abuse_counters.record_disconnect();
+                if let Some(player) = &self.player {
+                    self.report_abuse(player.id);
+                }
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Disconnected for abusing the server".to_owned()),
+                }));
+                ctx.stop();
+            }
+        }
+    }
+
+    /// Reports the player for moderators to review, now they've been disconnected for abuse
+    fn report_abuse(&self, player_id: PlayerId) {
+        let report = NewReport {
+            reporter: None,
+            reported: player_id,
+            room_id: self.room_id,
+            reason: self.state.abuse_scores.summary(&self.client_key()),
+            chat_excerpt: reports::chat_excerpt(self.room.chat_history(), player_id),
+        };
+        let reported = match self.state.reports.submit(report.clone()) {
+            // The server already reported the player in the room, so the moderators reviewing
+            // that report are told they were disconnected again
+            Err(ReportError::Duplicate(id)) => {
+                self.state
+                    .reports
+                    .add_reason(id, &report.reason, report.chat_excerpt)
+            }
+            reported => reported,
+        };
+        if let Err(e) = reported {
+            warn!(event = "abuse_not_reported", room_id = %self.room_id, %player_id, error = %e);
+        }
+    }
+
+    /// Sends the message to the `recipients`, or every connection in the room if `None`,
+    /// telling the client if the room can't hold any more undelivered messages
+    fn broadcast(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        message: &ServerMessage,
+        recipients: Option<&[ConnectionId]>,
+    ) -> bool {
+        let broadcaster = self.room.broadcaster();
+        let queued = match recipients {
+            Some(recipients) => broadcaster.send_to(recipients, message),
+            None => broadcaster.broadcast(message),
+        };
+        self.check_queued(ctx, queued)
+    }
+
+    /// Whether a broadcast was queued, telling the client if the room can't hold any more
+    /// undelivered messages
+    fn check_queued(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        queued: Result<usize, BroadcastError>,
+    ) -> bool {
+        match queued {
+            Ok(_) => true,
+            Err(e @ BroadcastError::MemoryBudgetExceeded(_)) => {
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: e.to_string(),
+                    },
+                );
+                false
+            }
+            Err(e) => {
+                warn!(event = "broadcast_serialization_error", error = %e);
+                false
+            }
+        }
+    }
+
+    /// Who the connection's chat and reactions are charged to
+    fn client_keys(&self) -> Vec<ClientKey> {
+        vec![self.client_key()]
+    }
+
+    /// Who the client is, for charging its messages and scoring its offences to
+    fn client_key(&self) -> ClientKey {
+        match (&self.player, self.address) {
+            (Some(player), _) => ClientKey::Player(player.id),
+            (None, Some(address)) => ClientKey::Address(address),
+            (None, None) => ClientKey::Connection(self.connection_key),
+        }
+    }
+
+    /// Charges a message to the client's budget, then carries on with `then`, or tells the
+    /// client when to try again if the budget has been used up
+    fn charge(
+        &self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        budget: RouteBudget,
+        then: impl FnOnce(&mut Self, &mut ws::WebsocketContext<Self>) + 'static,
+    ) {
+        ctx.spawn(self.charged(budget).map(move |charged, connection, ctx| {
+            if charged {
+                then(connection, ctx);
+            }
+        }));
+    }
+
+    /// Charges the request to the budget, resolving to whether it could be, telling the
+    /// client and recording an offence if it couldn't
+    fn charged(&self, budget: RouteBudget) -> impl ActorFuture<Self, Output = bool> {
+        let state = self.state.clone();
+        let keys = self.client_keys();
+        let charge = async move { state.rate_limiter.check(budget, &keys).await };
+        charge
+            .into_actor(self)
+            .map(move |charged, connection, ctx| match charged {
+                Ok(()) => true,
+                Err(wait) => {
+                    connection.send(
+                        ctx,
+                        &ServerMessage::Error {
+                            reason: format!(
+                                "The {} budget has been used up, try again in {} seconds",
+                                budget.name(),
+                                wait.as_secs_f64().ceil()
+                            ),
+                        },
+                    );
+                    connection.record_offence(ctx, Offence::RateLimited);
+                    false
+                }
+            })
+    }
+
+    /// Charges a line of chat to the sender's budget, then filters it and sends it on its
+    /// channel, unless the sender has been muted
+    fn send_chat(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        from: ConnectionId,
+        text: String,
+        channel: ChatChannel,
+    ) {
+        if let Err(reason) = check_chat_text(&text) {
+            self.send(ctx, &ServerMessage::Error { reason });
+            return;
+        }
+        let from_player = self.player.as_ref().map(|player| player.id);
+        if from_player.is_some_and(|id| self.room.is_muted(id)) {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: "You are muted in this room".to_owned(),
+                },
+            );
+            return;
+        }
+        if let Some(player_id) = from_player {
+            if let Some(mute) =
+                self.state
+                    .spam_guard
+                    .check(player_id, self.room_id, &text, Instant::now())
+            {
+                self.room
+                    .mute(player_id, Some(Duration::from_secs(mute.duration_secs)));
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: format!(
+                            "You are muted in this room for {} seconds for {}",
+                            mute.duration_secs, mute.kind
+                        ),
+                    },
+                );
+                return;
+            }
+        }
+
+        if self.queued_chat.len() >= MAX_QUEUED_CHAT {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: "Too much chat is waiting to be sent, slow down".to_owned(),
+                },
+            );
+            return;
+        }
+        self.queued_chat.push_back(QueuedChat {
+            from,
+            text,
+            channel,
+        });
+        if !self.sending_chat {
+            self.send_queued_chat(ctx);
+        }
+    }
+
+    /// Charges and filters the next line of chat waiting to be sent, then sends it and moves
+    /// on to the one after it, one at a time so filters taking longer for some lines than
+    /// others can't reorder them
+    fn send_queued_chat(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let Some(QueuedChat {
+            from,
+            text,
+            channel,
+        }) = self.queued_chat.pop_front()
+        else {
+            self.sending_chat = false;
+            return;
+        };
+        self.sending_chat = true;
+        let from_player = self.player.as_ref().map(|player| player.id);
+        let sent = self
+            .charged(RouteBudget::Chat)
+            .then(move |charged, connection, _| {
+                let state = connection.state.clone();
+                async move {
+                    if charged {
+                        Some(state.content_filters.filter(text, ContentKind::Chat).await)
+                    } else {
+                        None
+                    }
+                }
+                .into_actor(connection)
+            })
+            .map(move |verdict, connection, ctx| {
+                if let Some(verdict) = verdict {
+                    connection.deliver_chat(ctx, from, from_player, channel, verdict);
+                }
+                connection.send_queued_chat(ctx);
+            });
+        ctx.spawn(sent);
+    }
+
+    /// Sends a line of chat that was charged and filtered to its channel, unless the filter
+    /// rejected it
+    fn deliver_chat(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        from: ConnectionId,
+        from_player: Option<PlayerId>,
+        channel: ChatChannel,
+        verdict: Verdict,
+    ) {
+        let text = match verdict {
+            Verdict::Allow(text) => text,
+            Verdict::Reject { reason } => {
+                self.send(ctx, &ServerMessage::Error { reason });
+                return;
+            }
+        };
+        // Teams, parties and the players in the room may have changed while the message was
+        // charged and filtered
+        let party = from_player
+            .and_then(|id| self.state.parties.party_of(id))
+            .map(|party| party.members)
+            .unwrap_or_default();
+        let recipients = match self
+            .room
+            .chat_recipients(from, from_player, &party, &channel)
+        {
+            Ok(recipients) => recipients,
+            Err(e) => {
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: e.to_string(),
+                    },
+                );
+                return;
+            }
+        };
+        let message = ChatMessage {
+            from,
+            from_player,
+            text,
+            channel,
+            sent_at: unix_time_ms(),
+        };
+        let sent = self.broadcast(
+            ctx,
+            &ServerMessage::Chat(message.clone()),
+            recipients.as_deref(),
+        );
+        if sent && recipients.is_none() {
+            self.room.record_chat(message);
+        }
+    }
+
+    /// Charges a reaction to the sender's budget, then shows it to the room, provided the
+    /// emote is in the server's catalog
+    fn send_reaction(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        from: ConnectionId,
+        emote: String,
+    ) {
+        if !self.state.emotes.contains(&emote) {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: format!("{emote:?} is not a known emote"),
+                },
+            );
+            return;
+        }
+
+        let from_player = self.player.as_ref().map(|player| player.id);
+        self.charge(ctx, RouteBudget::Reactions, move |connection, ctx| {
+            connection.broadcast(
+                ctx,
+                &ServerMessage::Reaction {
+                    from,
+                    from_player,
+                    emote,
+                },
+                None,
+            );
+        });
+    }
+
+    fn publish_presence(&self) {
+        self.state
+            .presence
+            .publish(self.room_id, &self.room, &self.state.invite_signer);
+    }
+
+    /// Acts on a message from the client, or tells it why the message couldn't be decoded
+    fn handle_client_message(
+        &mut self,
+        envelope: Result<ClientEnvelope, String>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let Some(connection_id) = self.connection_id else {
+            return;
+        };
+
+        if let Some(locked_for) = self
+            .state
+            .abuse_scores
+            .locked_for(&self.client_key(), Instant::now())
+        {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: format!(
+                        "You can't do anything for another {} seconds",
+                        locked_for.as_secs_f64().ceil()
+                    ),
+                },
+            );
+            self.record_offence(ctx, Offence::RateLimited);
+            return;
+        }
+        let ClientEnvelope { sender, message } = match envelope {
+            Ok(envelope) => envelope,
+            Err(reason) => {
+                self.send(ctx, &ServerMessage::Error { reason });
+                self.record_offence(ctx, Offence::InvalidAction);
+                return;
+            }
+        };
+        self.watch(|connection_id, player_id| WatchedEvent::Message {
+            connection_id,
+            player_id,
+            message: message.clone(),
+        });
+        let player_id = self.player.as_ref().map(|player| player.id);
+        if sender.is_some() && sender != player_id {
+            self.record_violation(
+                ctx,
+                "The claimed sender doesn't match the connection's player".to_owned(),
+            );
+            return;
+        }
+        if let Some(permission) = Permission::required_to_send(&message) {
+            if !self.is_permitted(permission) {
+                self.record_violation(ctx, format!("Not permitted to {permission}"));
+                return;
+            }
+        }
+
+        match message {
+            ClientMessage::Broadcast { .. } if self.spectator => {
+                self.record_violation(ctx, "Spectators can't take part in the game".to_owned());
+            }
+            ClientMessage::Broadcast { payload } => {
+                // Payloads of game types with a codec other than JSON are encoded once, both to
+                // be sent and to be kept as the game's state
+                let game_type = self.state.room_registry.game_type_of(&self.room);
+                let codecs = &self.state.state_codecs;
+                let encoded = match codecs.encodes_as_json(game_type.as_deref()) {
+                    true => None,
+                    false => match codecs.encode(game_type.as_deref(), &payload) {
+                        Ok(encoded) => Some(encoded),
+                        Err(e) => {
+                            warn!(event = "broadcast_not_encoded", room_id = %self.room_id, error = %e);
+                            self.send(
+                                ctx,
+                                &ServerMessage::Error {
+                                    reason: e.to_string(),
+                                },
+                            );
+                            return;
+                        }
+                    },
+                };
+                let broadcaster = self.room.broadcaster();
+                let queued = match &encoded {
+                    None => broadcaster.broadcast_sequenced(|seq| ServerMessage::Broadcast {
+                        from: connection_id,
+                        from_player: player_id,
+                        payload: Cow::Borrowed(&payload),
+                        seq: Some(seq),
+                    }),
+                    Some(encoded) => {
+                        broadcaster.broadcast_sequenced(|seq| ServerMessage::EncodedBroadcast {
+                            from: connection_id,
+                            from_player: player_id,
+                            codec: encoded.codec.clone(),
+                            payload: Cow::Borrowed(&encoded.bytes),
+                            seq: Some(seq),
+                        })
+                    }
+                };
+                let queued = self.check_queued(ctx, queued);
+                if self.room.record_activity() {
+                    self.state
+                        .room_registry
+                        .registry()
+                        .room_updated(self.room_id);
+                }
+                // Only turn-based rooms keep their game's state, and only of payloads sent on
+                if !queued || !self.room.is_turn_based() {
+                    return;
+                }
+                match encoded.map_or_else(|| codecs.encode(game_type.as_deref(), &payload), Ok) {
+                    Ok(payload) => {
+                        let game_state = GameState {
+                            from: connection_id,
+                            from_player: player_id,
+                            payload,
+                        };
+                        if self.room.set_game_state(game_state) {
+                            self.state.turn_based_rooms.mark_changed();
+                        }
+                    }
+                    Err(e) => {
+                        warn!(event = "game_state_not_encoded", room_id = %self.room_id, error = %e)
+                    }
+                }
+            }
+            ClientMessage::CloseRoom => {
+                info!(event = "room_closed", room_id = %self.room_id, connection_id = %connection_id);
+                self.room.close();
+                self.state
+                    .room_deletion_queue
+                    .request_deletion(self.room_id);
+            }
+            ClientMessage::BanPlayer {
+                player_id,
+                reason,
+                duration_secs,
+            } => {
+                // Only authenticated players can hold the permission to ban
+                if let Some(moderator) = &self.player {
+                    self.state.ban_list.ban(
+                        BanTarget::Player { player_id },
+                        reason,
+                        duration_secs.map(Duration::from_secs),
+                        format!("player:{}", moderator.id),
+                    );
+                }
+            }
+            ClientMessage::UpdateSettings {
+                private,
+                name,
+                visibility,
+            } => {
+                if name
+                    .as_ref()
+                    .is_some_and(|name| name.chars().count() > MAX_ROOM_NAME_CHARS)
+                {
+                    self.send(
+                        ctx,
+                        &ServerMessage::Error {
+                            reason: format!(
+                                "Room names can be at most {MAX_ROOM_NAME_CHARS} characters"
+                            ),
+                        },
+                    );
+                    return;
+                }
+                let visibility = visibility.unwrap_or(if private {
+                    Visibility::Private
+                } else {
+                    Visibility::Public
+                });
+                info!(event = "room_settings_updated", room_id = %self.room_id, ?visibility, ?name);
+                self.room.set_visibility(visibility);
+                if let Some(name) = name {
+                    self.room.set_name(name);
+                }
+                self.state
+                    .room_registry
+                    .registry()
+                    .room_updated(self.room_id);
+                self.publish_presence();
+            }
+            ClientMessage::CreateInvite { ttl_secs, max_uses } => {
+                let invite = self.state.invite_signer.mint(
+                    self.room_id,
+                    ttl_secs.map_or(DEFAULT_INVITE_TTL, Duration::from_secs),
+                    max_uses,
+                );
+                self.send(ctx, &ServerMessage::Invite(invite));
+            }
+            ClientMessage::Chat { text, channel } => {
+                self.send_chat(ctx, connection_id, text, channel)
+            }
+            ClientMessage::React { emote } => self.send_reaction(ctx, connection_id, emote),
+            ClientMessage::MutePlayer {
+                player_id,
+                duration_secs,
+            } => {
+                info!(event = "player_muted", room_id = %self.room_id, %player_id, duration_secs);
+                self.room
+                    .mute(player_id, duration_secs.map(Duration::from_secs));
+            }
+            ClientMessage::UnmutePlayer { player_id } => {
+                info!(event = "player_unmuted", room_id = %self.room_id, %player_id);
+                self.room.unmute(player_id);
+            }
+            ClientMessage::KickPlayer { player_id, ban } => {
+                // Only authenticated players can hold the permission to kick
+                if let Some(kicker) = &self.player {
+                    if ban {
+                        self.state.room_bans.ban(
+                            self.room.lineage(self.room_id),
+                            player_id,
+                            format!("player:{}", kicker.id),
+                            None,
+                        );
+                    }
+                    let closed_connections = self.state.sessions.disconnect_from_room(
+                        player_id,
+                        self.room_id,
+                        KICKED_REASON,
+                    );
+                    info!(event = "player_kicked", room_id = %self.room_id, %player_id, ban, closed_connections);
+                }
+            }
+            ClientMessage::AnswerInvitation {
+                invitation_id,
+                accept,
+            } => self.answer_invitation(ctx, invitation_id, accept),
+            ClientMessage::SetCustomData { data } => self.set_custom_data(ctx, data),
+            ClientMessage::VoteKick { player_id } => self.vote_kick(ctx, player_id),
+            ClientMessage::BalanceTeams { teams } => self.balance_teams(ctx, teams),
+        }
+    }
+
+    /// Spreads the room's players across the teams, or those they're already on if none are
+    /// given, and tells everyone in the room which team each of them is now on
+    fn balance_teams(&mut self, ctx: &mut ws::WebsocketContext<Self>, mut teams: Vec<String>) {
+        if teams.is_empty() {
+            teams = self.room.team_names();
+        }
+        let game_type = self.state.room_registry.game_type_of(&self.room);
+        let players: Vec<_> = self
+            .room
+            .player_ids()
+            .into_iter()
+            .map(|player_id| TeamCandidate {
+                player_id,
+                rating: game_type.as_ref().map_or(INITIAL_RATING, |game_type| {
+                    self.state.ratings.rating(game_type, player_id).rating
+                }),
+                party: self.state.parties.party_of(player_id).map(|party| party.id),
+            })
+            .collect();
+        let placed = match balance_teams(&players, &teams) {
+            Ok(placed) => placed,
+            Err(e) => {
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: e.to_string(),
+                    },
+                );
+                return;
+            }
+        };
+
+        for (&player_id, team) in &placed {
+            self.room.set_team(player_id, team.clone());
+        }
+        info!(event = "teams_balanced", room_id = %self.room_id, players = placed.len());
+        self.broadcast(ctx, &ServerMessage::TeamsBalanced { teams: placed }, None);
+    }
+
+    /// Votes to kick the target out of the room, kicking them if the vote passes and
+    /// telling the room it failed if it times out first
+    fn vote_kick(&mut self, ctx: &mut ws::WebsocketContext<Self>, target: PlayerId) {
+        let Some(voter) = self.player.as_ref().filter(|_| !self.spectator) else {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: "Only signed in players taking part in the game can vote to kick"
+                        .to_owned(),
+                },
+            );
+            return;
+        };
+        if !self.room.has_player(target) {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: format!("{target} isn't playing in this room"),
+                },
+            );
+            return;
+        }
+        let voters = self.room.player_count() - 1;
+        let clock = self.state.kick_votes.clock().clone();
+        let tally =
+            match self
+                .state
+                .kick_votes
+                .vote(self.room_id, target, voter.id, voters, clock.now())
+            {
+                Ok(tally) => tally,
+                Err(e) => {
+                    self.send(
+                        ctx,
+                        &ServerMessage::Error {
+                            reason: e.to_string(),
+                        },
+                    );
+                    return;
+                }
+            };
+
+        if tally.passed() {
+            let ended = ServerMessage::KickVoteEnded {
+                player_id: target,
+                kicked: true,
+            };
+            self.broadcast(ctx, &ended, None);
+            self.state.room_bans.ban(
+                self.room.lineage(self.room_id),
+                target,
+                "vote".to_owned(),
+                Some(self.state.kick_votes.rules().cooldown),
+            );
+            let closed_connections =
+                self.state
+                    .sessions
+                    .disconnect_from_room(target, self.room_id, KICKED_REASON);
+            info!(event = "player_vote_kicked", room_id = %self.room_id, player_id = %target, votes = tally.votes, closed_connections);
+            return;
+        }
+        // Timed on the same clock as the vote, so it ends when clients are told it does
+        let ends_at = (clock.system_time() + tally.ends_in)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let vote = ServerMessage::KickVote {
+            player_id: target,
+            votes: tally.votes as u32,
+            votes_needed: tally.needed as u32,
+            ends_at,
+        };
+        self.broadcast(ctx, &vote, None);
+        if tally.first {
+            let state = self.state.clone();
+            let room = self.room.clone();
+            let room_id = self.room_id;
+            let ended = clock.sleep(tally.ends_in);
+            tokio::spawn(async move {
+                ended.await;
+                if state
+                    .kick_votes
+                    .has_failed(room_id, target, tally.started_at, clock.now())
+                {
+                    let _ = room.broadcaster().broadcast(&ServerMessage::KickVoteEnded {
+                        player_id: target,
+                        kicked: false,
+                    });
+                }
+            });
+        }
+    }
+
+    /// Replaces the room's custom data and sends it to everyone in the room, or tells the
+    /// client why it's too big
+    fn set_custom_data(&mut self, ctx: &mut ws::WebsocketContext<Self>, data: Map<String, Value>) {
+        let bytes = serde_json::to_vec(&data)
+            .expect("JSON values always serialize")
+            .len();
+        if bytes > MAX_CUSTOM_DATA_BYTES {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: format!(
+                        "Custom data can be at most {MAX_CUSTOM_DATA_BYTES} bytes of JSON, not {bytes}"
+                    ),
+                },
+            );
+            return;
+        }
+        info!(event = "custom_data_set", room_id = %self.room_id, bytes);
+        self.room.set_custom_data(data.clone());
+        self.broadcast(ctx, &ServerMessage::CustomData { data }, None);
+    }
+
+    /// Answers an invitation sent to the connection's player, telling whoever sent it
+    fn answer_invitation(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        invitation_id: InvitationId,
+        accept: bool,
+    ) {
+        let Some(player) = &self.player else {
+            self.send(
+                ctx,
+                &ServerMessage::Error {
+                    reason: "Only signed in players can be invited".to_owned(),
+                },
+            );
+            return;
+        };
+        let invitation = match self
+            .state
+            .invitations
+            .answer(invitation_id, player.id, accept)
+        {
+            Ok(invitation) => invitation,
+            Err(e) => {
+                self.send(
+                    ctx,
+                    &ServerMessage::Error {
+                        reason: e.to_string(),
+                    },
+                );
+                return;
+            }
+        };
+        let state = self.state.clone();
+        let player_id = player.id;
+        ctx.spawn(
+            async move {
+                deliver_answer(
+                    &state.sessions,
+                    state.cluster.as_deref(),
+                    &invitation,
+                    player_id,
+                    accept,
+                )
+                .await;
+            }
+            .into_actor(self),
+        );
+    }
+}
+
+impl Actor for RoomConnection {
+    type Context = ws::WebsocketContext<Self>;
+
+    #[instrument(skip_all, fields(room_id = %self.room_id))]
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, Self::open);
+    }
+
+    #[instrument(skip_all, fields(room_id = %self.room_id))]
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, |connection, _| connection.clean_up());
+    }
+}
+
+impl RoomConnection {
+    /// Joins the room and sends the client what it needs to catch up with it
+    fn open(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        // Turn-based rooms are deleted once the turn runs out, whoever's connected
+        let deletion_cancelled = !self.room.is_turn_based() && self.room.cancel_deletion();
+        // Spectators are kept behind the players so they can't be used to ghost them, and
+        // aren't replayed anything that would catch them up
+        let delay = if self.spectator {
+            self.state.spectator_delay
+        } else {
+            Duration::ZERO
+        };
+        let (connection_id, outbound, replay) = match self.last_seq.filter(|_| delay.is_zero()) {
+            Some(last_seq) => {
+                let (connection_id, outbound, replay) =
+                    self.room.broadcaster().subscribe_since(last_seq);
+                (connection_id, outbound, Some(replay))
+            }
+            None => {
+                let (connection_id, outbound) = self.room.broadcaster().subscribe_delayed(delay);
+                (connection_id, outbound, None)
+            }
+        };
+        self.connection_id = Some(connection_id);
+        self.room
+            .broadcaster()
+            .set_protocol_version(connection_id, self.protocol_version);
+        self.record(|| RecordedEvent::Joined);
+        self.watch(|connection_id, player_id| WatchedEvent::Joined {
+            connection_id,
+            player_id,
+        });
+        ctx.add_stream(outbound);
+        if self.spectator {
+            self.room.add_spectator();
+        }
+        if let Some(player) = &self.player {
+            if !self.spectator {
+                self.room.add_player(player.id, connection_id);
+            }
+            self.session_connection_id = Some(self.state.sessions.register(
+                player.id,
+                ConnectionDetails {
+                    room_id: self.room_id,
+                    connection_id,
+                    protocol_version: self.protocol_version,
+                    display_name: player.display_name.clone(),
+                },
+                ctx.address().recipient(),
+                ctx.address().recipient(),
+            ));
+            self.publish_presence();
+        }
+        if deletion_cancelled || self.player.is_some() || self.spectator {
+            self.state
+                .room_registry
+                .registry()
+                .room_updated(self.room_id);
+        }
+
+        info!(event = "connection_opened", connection_id = %connection_id, spectator = self.spectator);
+        self.send(
+            ctx,
+            &ServerMessage::Welcome {
+                connection_id,
+                protocol_version: self.protocol_version,
+                player_id: self.player.as_ref().map(|player| player.id),
+            },
+        );
+        let delayed = !delay.is_zero();
+        let chat_history = self.room.chat_history();
+        if self.protocol_version >= CHAT_VERSION && !chat_history.is_empty() {
+            self.send_on_joining(
+                ctx,
+                delayed,
+                &ServerMessage::ChatHistory {
+                    messages: chat_history,
+                },
+            );
+        }
+        let custom_data = self.room.custom_data();
+        if self.protocol_version >= CUSTOM_DATA_VERSION && !custom_data.is_empty() {
+            self.send_on_joining(
+                ctx,
+                delayed,
+                &ServerMessage::CustomData { data: custom_data },
+            );
+        }
+        let caught_up = match replay {
+            Some(Replay::Complete(broadcasts)) => {
+                info!(event = "broadcasts_replayed", count = broadcasts.len());
+                let broadcasts: Vec<_> = broadcasts
+                    .into_iter()
+                    .filter_map(|frame| self.readable_frame(frame))
+                    .collect();
+                match broadcasts.len() {
+                    0 => {}
+                    1 => self.write_payload(ctx, broadcasts[0].clone()),
+                    _ => self.write_payload(ctx, batch_frame(&broadcasts)),
+                }
+                true
+            }
+            Some(Replay::Incomplete { latest_seq }) => {
+                info!(
+                    event = "replay_unavailable",
+                    last_seq = self.last_seq,
+                    latest_seq
+                );
+                self.send(ctx, &ServerMessage::ReplayUnavailable { latest_seq });
+                false
+            }
+            None => false,
+        };
+        // Clients that caught up on every broadcast already have the last one
+        let game_state = self.room.game_state().filter(|_| !caught_up);
+        if let Some(message) = game_state
+            .as_ref()
+            .and_then(|state| self.game_state_message(state))
+        {
+            self.send_on_joining(ctx, delayed, &message);
+        }
+        if let Some(player) = &self.player {
+            if self.protocol_version >= INVITATIONS_VERSION {
+                for invitation in self.state.invitations.take_undelivered(player.id) {
+                    self.send(ctx, &ServerMessage::Invitation(invitation));
+                }
+            }
+        }
+    }
+
+    /// Leaves the room, once the connection has stopped
+    fn clean_up(&mut self) {
+        self.record(|| RecordedEvent::Left);
+        self.watch(|connection_id, player_id| WatchedEvent::Left {
+            connection_id,
+            player_id,
+        });
+        if let Some(connection_id) = self.connection_id.take() {
+            self.room.broadcaster().unsubscribe(connection_id);
+            if self.spectator {
+                self.room.remove_spectator();
+            }
+            let deletion_scheduled = self.room.remove_player(
+                self.room_id,
+                self.player
+                    .as_ref()
+                    .filter(|_| !self.spectator)
+                    .map(|player| player.id),
+                connection_id,
+                &self.state.room_deletion_queue,
+            );
+            if deletion_scheduled || self.player.is_some() || self.spectator {
+                self.state
+                    .room_registry
+                    .registry()
+                    .room_updated(self.room_id);
+            }
+            let seat_freed = self
+                .player
+                .as_ref()
+                .is_some_and(|player| !self.spectator && !self.room.has_player(player.id));
+            if seat_freed {
+                self.state
+                    .waiting_lists
+                    .seat_freed(self.room_id, &self.room, Instant::now());
+            }
+            if self.player.is_some() {
+                self.publish_presence();
+            }
+            info!(event = "connection_closed", connection_id = %connection_id);
+        }
+        if let (Some(player), Some(id)) = (&self.player, self.session_connection_id.take()) {
+            self.state.sessions.unregister(player.id, id);
+        }
+    }
+}
+
+/// The player's session has been revoked or cut off, so the connection is closed
+impl Handler<SessionEnded> for RoomConnection {
+    type Result = ();
+
+    fn handle(&mut self, SessionEnded(reason): SessionEnded, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, |connection, ctx| connection.end_session(reason, ctx));
+    }
+}
+
+impl RoomConnection {
+    fn end_session(&mut self, reason: &'static str, ctx: &mut ws::WebsocketContext<Self>) {
+        info!(event = "connection_revoked", room_id = %self.room_id, reason);
+        // The registry has already forgotten the connection
+        self.session_connection_id = None;
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(reason.to_owned()),
+        }));
+        ctx.stop();
+    }
+}
+
+/// A message for the connection's player, which invitations are recorded from so they can be
+/// answered over any of the player's connections to this instance
+impl Handler<PlayerNotice> for RoomConnection {
+    type Result = ();
+
+    fn handle(&mut self, PlayerNotice(message): PlayerNotice, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, |connection, ctx| {
+            connection.handle_notice(message, ctx)
+        });
+    }
+}
+
+impl RoomConnection {
+    fn handle_notice(
+        &mut self,
+        message: ServerMessage<'static>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let Some(player) = &self.player else {
+            return;
+        };
+        if self.protocol_version < INVITATIONS_VERSION {
+            return;
+        }
+        if let ServerMessage::Invitation(invitation) = &message {
+            self.state.invitations.delivered(player.id, invitation);
+        }
+        self.send(ctx, &message);
+    }
+}
+
+/// Messages queued for this connection by the room's broadcaster
+impl StreamHandler<Bytes> for RoomConnection {
+    fn handle(&mut self, payload: Bytes, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, |connection, ctx| {
+            connection.handle_broadcast(payload, ctx)
+        });
+    }
+
+    /// The room has been closed, or the spectator fell too far behind it, so the connection
+    /// goes with it
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.flush_broadcasts(ctx);
+        let overflowed = self
+            .connection_id
+            .is_some_and(|id| self.room.broadcaster().take_overflowed(id));
+        if overflowed {
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Again,
+                description: Some(SPECTATOR_OVERFLOWED_REASON.to_owned()),
+            }));
+            ctx.stop();
+            return;
+        }
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Away,
+            description: Some(
+                self.room
+                    .close_reason()
+                    .unwrap_or("The room was closed")
+                    .to_owned(),
+            ),
+        }));
+        ctx.stop();
+    }
+}
+
+impl RoomConnection {
+    /// Runs `handle`, closing the connection with an internal error if it panics rather than
+    /// letting the panic take down every other connection on the worker
+    ///
+    /// Every handler of the connection's messages and streams runs through this, as do
+    /// `started` and `stopped`, but the callbacks of futures the connection spawns, such as
+    /// charging and filtering chat, don't. A panic while a lock on the room is held poisons
+    /// it, so every later connection to the room that takes the lock panics in turn and is
+    /// closed, until the room is deleted.
+    fn catching_panics(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        handle: impl FnOnce(&mut Self, &mut ws::WebsocketContext<Self>),
+    ) {
+        // The panic hook logs the backtrace within this span
+        let span = info_span!(
+            "room_connection",
+            room_id = %self.room_id,
+            connection_id = ?self.connection_id
+        );
+        let handled = span.in_scope(|| panic::catch_unwind(AssertUnwindSafe(|| handle(self, ctx))));
+        let Err(payload) = handled else {
+            return;
+        };
+        self.state.panics.record_connection();
+        error!(
+            event = "connection_panicked",
+            room_id = %self.room_id,
+            connection_id = ?self.connection_id,
+            message = panic_message(&*payload)
+        );
+        if !ctx.state().alive() {
+            return;
+        }
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Error,
+            description: Some("Something went wrong handling the connection".to_owned()),
+        }));
+        ctx.stop();
+    }
+
+    fn handle_broadcast(&mut self, payload: Bytes, ctx: &mut ws::WebsocketContext<Self>) {
+        if (self.protocol_version < CHAT_VERSION && is_chat_frame(&payload))
+            || (self.protocol_version < REACTIONS_VERSION && is_reaction_frame(&payload))
+            || (self.protocol_version < ANNOUNCEMENTS_VERSION && is_announcement_frame(&payload))
+            || (self.protocol_version < CUSTOM_DATA_VERSION && is_custom_data_frame(&payload))
+            || (self.protocol_version < KICK_VOTES_VERSION && is_kick_vote_frame(&payload))
+            || (self.protocol_version < TEAM_BALANCE_VERSION && is_teams_balanced_frame(&payload))
+        {
+            return;
+        }
+        let Some(payload) = self.readable_frame(payload) else {
+            return;
+        };
+        if self.flush_interval.is_zero() || self.protocol_version < BATCH_FRAMES_VERSION {
+            self.write_payload(ctx, payload);
+            return;
+        }
+
+        self.pending_bytes += payload.len();
+        self.pending_broadcasts.push(payload);
+        if self.pending_bytes >= MAX_BATCH_BYTES {
+            // The timer already set for the batch finds nothing left to write
+            self.flush_broadcasts(ctx);
+        } else if self.pending_broadcasts.len() == 1 {
+            ctx.run_later(self.flush_interval, |connection, ctx| {
+                connection.flush_broadcasts(ctx)
+            });
+        }
+    }
+}
+
+/// Frames received from the client
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for RoomConnection {
+    fn handle(&mut self, frame: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        self.catching_panics(ctx, |connection, ctx| connection.handle_frame(frame, ctx));
+    }
+}
+
+impl RoomConnection {
+    fn handle_frame(
+        &mut self,
+        frame: Result<ws::Message, ws::ProtocolError>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if let Some(connection_id) = self.connection_id {
+            if faults::disconnect(self.room_id, connection_id) {
+                ctx.stop();
+                return;
+            }
+        }
+        let max_frame_bytes = self.state.abuse_rules.max_frame_bytes;
+        match frame {
+            Ok(ws::Message::Text(text)) if text.len() > max_frame_bytes => {
+                self.refuse_oversized_frame(ctx, text.len())
+            }
+            Ok(ws::Message::Binary(bytes)) if bytes.len() > max_frame_bytes => {
+                self.refuse_oversized_frame(ctx, bytes.len())
+            }
+            Ok(ws::Message::Text(text)) => {
+                self.record(|| RecordedEvent::Received {
+                    message: serde_json::from_str(&text)
+                        .unwrap_or_else(|_| serde_json::Value::String(text.to_string())),
+                });
+                self.handle_client_message(
+                    serde_json::from_str(&text).map_err(|e| e.to_string()),
+                    ctx,
+                )
+            }
+            Ok(ws::Message::Binary(bytes)) if self.format == WireFormat::Protobuf => {
+                let envelope = ClientEnvelope::decode_protobuf(&bytes);
+                if let Ok(envelope) = &envelope {
+                    self.record(|| RecordedEvent::Received {
+                        message: serde_json::to_value(envelope).unwrap_or_default(),
+                    });
+                }
+                self.handle_client_message(envelope.map_err(|e| e.to_string()), ctx)
+            }
+            Ok(ws::Message::Ping(bytes)) => ctx.pong(&bytes),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(event = "websocket_protocol_error", error = %e);
+                ctx.stop();
+            }
+        }
+    }
+
+    /// Tells the client its frame was too big to be read, and scores it as an oversized frame
+    fn refuse_oversized_frame(&mut self, ctx: &mut ws::WebsocketContext<Self>, bytes: usize) {
+        let max_frame_bytes = self.state.abuse_rules.max_frame_bytes;
+        self.send(
+            ctx,
+            &ServerMessage::Error {
+                reason: format!("Frames can be at most {max_frame_bytes} bytes, not {bytes}"),
+            },
+        );
+        self.record_offence(ctx, Offence::OversizedFrame);
+    }
+}
+
+/// A WebSocket closed as soon as it opens, so clients that can't read the status of a failed
+/// upgrade, such as browsers, can still be told why they were turned away
+struct RejectedConnection {
+    reason: String,
+}
+
+impl Actor for RejectedConnection {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some(std::mem::take(&mut self.reason)),
+        }));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for RejectedConnection {
+    fn handle(&mut self, _: Result<ws::Message, ws::ProtocolError>, _: &mut Self::Context) {}
+}
+
+/// What a client asks for in the query of its request to [join a room][join_room]
+#[derive(Debug, Default, Deserialize)]
+pub struct JoinQuery {
+    /// The protocol version the client speaks, the oldest supported version if omitted
+    pub protocol_version: Option<u32>,
+    /// How messages are encoded, JSON if omitted
+    #[serde(default)]
+    pub format: WireFormat,
+    /// An invite token, required to join private rooms
+    pub invite: Option<String>,
+    /// The answer to the guest challenge, required to join anonymously when one is configured
+    #[serde(flatten)]
+    pub challenge: ChallengeResponse,
+    /// Whether to watch the room rather than play in it
+    #[serde(default)]
+    pub spectate: bool,
+    /// The `seq` of the last broadcast the client saw, when it's rejoining, or zero for a
+    /// client joining late that wants every broadcast the room still keeps
+    pub last_seq: Option<u64>,
+}
+
+impl JoinQuery {
+    /// Reads the query string of a request to join a room, ignoring it entirely if it's
+    /// malformed
+    pub fn parse(query: &str) -> Self {
+        web::Query::<Self>::from_query(query)
+            .map(web::Query::into_inner)
+            .unwrap_or_default()
+    }
+}
+
+/// Issues a challenge for a guest to solve before joining a room, or `404` if guests
+/// don't need to solve one
+pub async fn get_challenge(state: web::Data<SharedAppState>) -> HttpResponse {
+    match &state.guest_challenge {
+        Some(verifier) => HttpResponse::Ok().json(verifier.issue()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Turns an already serialized encoded broadcast into a plain one, with its payload decoded
+fn decode_broadcast(codecs: &StateCodecs, frame: &[u8]) -> Result<Bytes, StateCodecError> {
+    let message: ServerMessage =
+        serde_json::from_slice(frame).map_err(|e| StateCodecError::Decode(e.to_string()))?;
+    let ServerMessage::EncodedBroadcast {
+        from,
+        from_player,
+        codec,
+        payload,
+        seq,
+    } = message
+    else {
+        return Err(StateCodecError::Decode(
+            "not an encoded broadcast".to_owned(),
+        ));
+    };
+    let payload = codecs.decode(&EncodedState {
+        codec,
+        bytes: payload.into_owned(),
+    })?;
+    let broadcast = ServerMessage::Broadcast {
+        from,
+        from_player,
+        payload: Cow::Owned(payload),
+        seq,
+    };
+    serde_json::to_vec(&broadcast)
+        .map(Bytes::from)
+        .map_err(|e| StateCodecError::Encode(e.to_string()))
+}
+
+/// Where to join a room hosted by the instance at `host`, keeping the original query
+fn remote_join_url(host: &str, room_id: RoomId, query: &str) -> String {
+    if query.is_empty() {
+        format!("{host}/ws/{room_id}")
+    } else {
+        format!("{host}/ws/{room_id}?{query}")
+    }
+}
+
+/// Upgrades the request to a WebSocket connected to the room identified in the path
+///
+/// When an identity provider is configured the request must carry a player token, either as a
+/// bearer token or in the `access_token` query parameter, since browsers can't set headers on
+/// WebSocket requests. Tokens issued before the player's sessions were last revoked are refused.
+/// Banned players and addresses are turned away with a `403`, as is anyone other than the owner
+/// joining a private room without a valid `invite` query parameter, or with an invite holding
+/// a seat for another player. Players are turned away with a `409` when the room is at
+/// capacity, unless a seat is held for them or they join with `spectate=true` to watch
+/// rather than play. Spectators don't take a seat and can't broadcast, but are sent
+/// everything players are, held back by the spectator delay if one is configured, including
+/// the room's state as they join. Delayed spectators aren't replayed anything. When a guest challenge is
+/// configured, anonymous players must also pass a solution from [get_challenge] in the
+/// `challenge` and `solution` query parameters.
+///
+/// Clients asking for a `protocol_version` the server no longer or doesn't yet support are
+/// connected and immediately disconnected with a close reason saying so. Clients joining with
+/// `format=protobuf` exchange protobuf binary frames rather than JSON text frames.
+///
+/// Clients rejoining with the `seq` of the last broadcast they saw as `last_seq` are sent
+/// every broadcast since, if the room still keeps them all, or told it doesn't with a
+/// [ServerMessage::ReplayUnavailable].
+///
+/// Players joining a room hosted by another instance sharing the lobby are redirected to it
+/// with a `307`, query parameters and all, provided that instance has a public URL.
+pub async fn join_room(
+    req: HttpRequest,
+    stream: web::Payload,
+    path: web::Path<RoomId>,
+    state: web::Data<SharedAppState>,
+) -> Result<HttpResponse, Error> {
+    let room_id = path.into_inner();
+    if let Some(host) = state.remote_rooms.host_of(room_id) {
+        info!(event = "join_redirected", room_id = %room_id, %host);
+        return Ok(HttpResponse::TemporaryRedirect()
+            .insert_header((
+                header::LOCATION,
+                remote_join_url(&host, room_id, req.query_string()),
+            ))
+            .finish());
+    }
+    let query = JoinQuery::parse(req.query_string());
+    let protocol_version = match negotiate_protocol_version(query.protocol_version) {
+        Ok(version) => version,
+        Err(reason) => {
+            info!(
+                event = "protocol_version_rejected",
+                requested = query.protocol_version
+            );
+            return ws::start(RejectedConnection { reason }, &req, stream);
+        }
+    };
+    let mut player = match &state.player_authenticator {
+        Some(authenticator) => Some(
+            authenticator
+                .authenticate_request(&req)
+                .await
+                .map_err(Problem::from)?,
+        ),
+        None => None,
+    };
+    if let Some(player) = &player {
+        if state.sessions.is_revoked(player.id, player.issued_at) {
+            return Err(Problem::from(PlayerAuthError::Revoked).into());
+        }
+    }
+
+    let ip = req.peer_addr().map(|address| address.ip());
+    if let Some(ban) = state
+        .ban_list
+        .find(player.as_ref().map(|player| player.id), ip)
+    {
+        return Err(Problem::new(
+            StatusCode::FORBIDDEN,
+            "/problems/banned",
+            "Banned from the server",
+        )
+        .with_detail(ban.reason)
+        .into());
+    }
+
+    let Some(room) = state
+        .room_registry
+        .get_room(room_id)
+        .await
+        .map_err(Problem::from)?
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    if let Some(player) = &player {
+        if state
+            .room_bans
+            .find(room.lineage(room_id), player.id)
+            .is_some()
+        {
+            return Err(Problem::new(
+                StatusCode::FORBIDDEN,
+                "/problems/banned-from-room",
+                "Banned from the room and its rematches",
+            )
+            .into());
+        }
+    }
+
+    if !room.is_open() {
+        let mut problem = Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/room-not-open",
+            "The room hasn't opened yet",
+        );
+        if let Some(starts_at) = room.starts_at() {
+            problem = problem.with_detail(format!(
+                "The room opens at {starts_at} seconds since the Unix epoch"
+            ));
+        }
+        return Err(problem.into());
+    }
+
+    let is_owner = player
+        .as_ref()
+        .is_some_and(|player| room.owner() == Some(player.id));
+    if room.is_private() && !is_owner {
+        let invite = query
+            .invite
+            .ok_or(InviteError::Missing)
+            .and_then(|token| state.invite_signer.verify(&token, room_id))
+            .and_then(|claims| {
+                room.redeem_invite(&claims, player.as_ref().map(|player| player.id))?;
+                Ok(claims.team)
+            });
+        match invite {
+            Ok(Some(team)) => {
+                // Anonymous players can't be told apart, so only players can be on a team
+                if let Some(player) = &player {
+                    room.set_team(player.id, team);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return Err(Problem::new(
+                    StatusCode::FORBIDDEN,
+                    "/problems/invite-required",
+                    "An invite is required to join this room",
+                )
+                .with_detail(e.to_string())
+                .into())
+            }
+        }
+    }
+
+    if !query.spectate && !room.has_seat_for(player.as_ref().map(|player| player.id)) {
+        let mut problem = Problem::new(
+            StatusCode::CONFLICT,
+            "/problems/room-full",
+            "The room has no free seats",
+        );
+        if player.is_some() {
+            problem = problem.with_detail(format!(
+                "Wait for a seat by following /api/v1/rooms/{room_id}/waiting-list with your \
+                 identity token as a bearer token or in the access_token query parameter"
+            ));
+        }
+        return Err(problem.into());
+    }
+
+    // Checked last, as verifying the solution redeems the challenge, which a guest turned
+    // away for any other reason would otherwise have to solve again
+    if let (None, Some(verifier)) = (&player, &state.guest_challenge) {
+        if let Err(e) = verifier.verify(&query.challenge).await {
+            return Err(Problem::new(
+                StatusCode::FORBIDDEN,
+                "/problems/challenge-required",
+                "A challenge must be solved to join as a guest",
+            )
+            .with_detail(e.to_string())
+            .into());
+        }
+    }
+
+    if let Some(player) = &mut player {
+        if let Some(name) = player.display_name.take() {
+            player.display_name = match state
+                .content_filters
+                .filter(name, ContentKind::DisplayName)
+                .await
+            {
+                Verdict::Allow(name) => Some(name),
+                // The player can still play, just without a name others would see
+                Verdict::Reject { .. } => None,
+            };
+        }
+    }
+
+    ws::start(
+        RoomConnection::new(
+            room_id,
+            room,
+            player,
+            ip,
+            protocol_version,
+            query.format,
+            state.clone(),
+        )
+        .spectating(query.spectate)
+        .replaying_after(query.last_seq),
+        &req,
+        stream,
+    )
+}
+
+#[cfg(test)]
+mod negotiate_protocol_version {
+    use super::*;
+
+    #[test]
+    fn serves_versions_within_the_window() {
+        assert_eq!(
+            negotiate_protocol_version(Some(PROTOCOL_VERSION)),
+            Ok(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate_protocol_version(Some(MIN_PROTOCOL_VERSION)),
+            Ok(MIN_PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn treats_clients_without_a_version_as_the_oldest() {
+        assert_eq!(negotiate_protocol_version(None), Ok(MIN_PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn asks_outdated_clients_to_update() {
+        let reason = negotiate_protocol_version(Some(MIN_PROTOCOL_VERSION - 1)).unwrap_err();
+        assert!(reason.contains("please update"), "{reason}");
+        // Close reasons must fit in a control frame
+        assert!(reason.len() <= 123);
+    }
+
+    #[test]
+    fn rejects_versions_from_the_future() {
+        assert!(negotiate_protocol_version(Some(PROTOCOL_VERSION + 1)).is_err());
+    }
+}
+
+#[cfg(test)]
+mod join_query {
+    use super::*;
+
+    fn parse(query: &str) -> JoinQuery {
+        web::Query::<JoinQuery>::from_query(query)
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn defaults_to_json() {
+        assert_eq!(parse("protocol_version=2").format, WireFormat::Json);
+    }
+
+    #[test]
+    fn accepts_protobuf() {
+        assert_eq!(parse("format=protobuf").format, WireFormat::Protobuf);
+    }
+
+    #[test]
+    fn plays_unless_asked_to_spectate() {
+        assert!(!parse("").spectate);
+        assert!(parse("spectate=true").spectate);
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        assert!(web::Query::<JoinQuery>::from_query("format=xml").is_err());
+    }
+}
+
+#[cfg(test)]
+mod remote_join_url {
+    use super::*;
+
+    #[test]
+    fn keeps_the_query() {
+        let id = RoomId::from(1_u128);
+
+        assert_eq!(
+            remote_join_url("https://b.example.com", id, "format=protobuf&invite=abc"),
+            "https://b.example.com/ws/00000000-0000-0000-0000-000000000001?format=protobuf&invite=abc"
+        );
+        assert_eq!(
+            remote_join_url("https://b.example.com", id, ""),
+            "https://b.example.com/ws/00000000-0000-0000-0000-000000000001"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod join_room {
+    use serde_json::json;
+
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use crate::game::{MessagePackCodec, StateCodec};
+    use crate::kick_votes::KickVoteRules;
+    use crate::test_support::TestServer;
+
+    #[tokio::test]
+    async fn replays_what_rejoining_clients_missed() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect(room_id).await;
+        for i in 1..=3 {
+            alice
+                .send(ClientMessage::Broadcast { payload: json!(i) })
+                .await;
+            alice.recv().await;
+        }
+
+        let mut bob = server.connect_with_query(room_id, "last_seq=1").await;
+        for i in 2..=3 {
+            assert!(matches!(
+                bob.recv().await,
+                ServerMessage::Broadcast { payload, seq, .. }
+                    if *payload == json!(i) && seq == Some(i)
+            ));
+        }
+        bob.expect_silence(Duration::from_millis(50)).await;
+
+        let mut carol = server.connect_with_query(room_id, "last_seq=7").await;
+        assert_eq!(
+            carol.recv().await,
+            ServerMessage::ReplayUnavailable { latest_seq: 3 }
+        );
+    }
+
+    fn is_kick_vote(message: &ServerMessage) -> bool {
+        matches!(message, ServerMessage::KickVote { .. })
+    }
+
+    fn is_kick_vote_ended(message: &ServerMessage) -> bool {
+        matches!(message, ServerMessage::KickVoteEnded { .. })
+    }
+
+    #[tokio::test]
+    async fn kicks_and_bans_players_voted_out() {
+        let server = TestServer::builder().with_signed_in_players().start().await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect_as(room_id, "alice").await;
+        let mut bob = server.connect_as(room_id, "bob").await;
+        let mut carol = server.connect_as(room_id, "carol").await;
+        let carol_id = TestServer::player_id("carol");
+
+        alice
+            .send(ClientMessage::VoteKick {
+                player_id: carol_id,
+            })
+            .await;
+        assert!(matches!(
+            bob.recv_matching(is_kick_vote).await,
+            ServerMessage::KickVote {
+                votes: 1,
+                votes_needed: 2,
+                ..
+            }
+        ));
+        bob.send(ClientMessage::VoteKick {
+            player_id: carol_id,
+        })
+        .await;
+
+        assert_eq!(
+            alice.recv_matching(is_kick_vote_ended).await,
+            ServerMessage::KickVoteEnded {
+                player_id: carol_id,
+                kicked: true
+            }
+        );
+        assert_eq!(carol.close_reason().await.as_deref(), Some(KICKED_REASON));
+        let ban = server.state().room_bans.find(room_id, carol_id).unwrap();
+        assert_eq!(
+            ban.expires_at,
+            Some(ban.issued_at + KickVoteRules::default().cooldown.as_secs())
+        );
+    }
+
+    #[tokio::test]
+    async fn tells_players_when_votes_to_kick_end_on_the_votes_clock() {
+        let clock = Arc::new(MockClock::new());
+        let server = TestServer::builder()
+            .with_signed_in_players()
+            .with_clock(clock.clone())
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect_as(room_id, "alice").await;
+        let _bob = server.connect_as(room_id, "bob").await;
+        let _carol = server.connect_as(room_id, "carol").await;
+        clock.advance(Duration::from_secs(3600));
+
+        alice
+            .send(ClientMessage::VoteKick {
+                player_id: TestServer::player_id("carol"),
+            })
+            .await;
+
+        let ends_at = (clock.system_time() + KickVoteRules::default().timeout)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(matches!(
+            alice.recv_matching(is_kick_vote).await,
+            ServerMessage::KickVote { ends_at: sent, .. } if sent == ends_at
+        ));
+    }
+
+    #[tokio::test]
+    async fn ends_votes_to_kick_that_time_out() {
+        let clock = Arc::new(MockClock::new());
+        let server = TestServer::builder()
+            .with_signed_in_players()
+            .with_clock(clock.clone())
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect_as(room_id, "alice").await;
+        let mut bob = server.connect_as(room_id, "bob").await;
+        let _carol = server.connect_as(room_id, "carol").await;
+        let carol_id = TestServer::player_id("carol");
+        alice
+            .send(ClientMessage::VoteKick {
+                player_id: carol_id,
+            })
+            .await;
+        bob.recv_matching(is_kick_vote).await;
+
+        clock.advance(KickVoteRules::default().timeout);
+
+        assert_eq!(
+            bob.recv_matching(is_kick_vote_ended).await,
+            ServerMessage::KickVoteEnded {
+                player_id: carol_id,
+                kicked: false
+            }
+        );
+        assert_eq!(server.state().room_bans.find(room_id, carol_id), None);
+    }
+
+    #[tokio::test]
+    async fn only_votes_to_kick_players_in_the_room() {
+        let server = TestServer::builder().with_signed_in_players().start().await;
+        let room_id = server.create_room().await;
+        let mut alice = server.connect_as(room_id, "alice").await;
+
+        alice
+            .send(ClientMessage::VoteKick {
+                player_id: TestServer::player_id("dave"),
+            })
+            .await;
+
+        assert!(matches!(
+            alice
+                .recv_matching(|message| matches!(message, ServerMessage::Error { .. }))
+                .await,
+            ServerMessage::Error { reason } if reason.ends_with("isn't playing in this room")
+        ));
+    }
+
+    #[tokio::test]
+    async fn points_players_turned_away_from_full_rooms_at_the_waiting_list() {
+        let server = TestServer::builder()
+            .with_room_capacity(1)
+            .with_signed_in_players()
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let _alice = server.connect_as(room_id, "alice").await;
+        let bob = TestServer::player_token("bob");
+
+        let response = server
+            .http()
+            .get(server.url(&format!(
+                "/ws/{room_id}?protocol_version={PROTOCOL_VERSION}&access_token={bob}"
+            )))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 409);
+        let problem: serde_json::Value = response.json().await.unwrap();
+        let path = problem["detail"]
+            .as_str()
+            .unwrap()
+            .split_whitespace()
+            .find(|word| word.starts_with("/api/v1/"))
+            .unwrap();
+        assert_eq!(path, format!("/api/v1/rooms/{room_id}/waiting-list"));
+
+        let mut waiting = server
+            .http()
+            .get(server.url(path))
+            .bearer_auth(&bob)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(waiting.status(), 200);
+        let event = waiting.chunk().await.unwrap().unwrap();
+        assert!(std::str::from_utf8(&event)
+            .unwrap()
+            .starts_with("event: position\n"));
+    }
+
+    #[tokio::test]
+    async fn sends_the_rooms_custom_data_to_clients_that_understand_it() {
+        let server = TestServer::start().await;
+        let room_id = server.create_room().await;
+        let data = json!({ "map": "de_dust2", "mods": ["no_fog"] })
+            .as_object()
+            .unwrap()
+            .clone();
+        server
+            .state()
+            .room_registry
+            .registry()
+            .get_room_for_id(room_id)
+            .unwrap()
+            .set_custom_data(data.clone());
+
+        let mut current = server.connect(room_id).await;
+        let mut outdated = server
+            .connect_with_query(room_id, "protocol_version=7")
+            .await;
+
+        assert_eq!(current.recv().await, ServerMessage::CustomData { data });
+        outdated.expect_silence(Duration::from_millis(50)).await;
+    }
+
+    #[tokio::test]
+    async fn encodes_broadcasts_with_the_game_types_codec() {
+        let server = TestServer::builder()
+            .with_state_codecs(StateCodecs::new(Arc::new(MessagePackCodec)))
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut current = server.connect(room_id).await;
+        let mut outdated = server
+            .connect_with_query(room_id, "protocol_version=10")
+            .await;
+        let payload = json!({ "move": "e5" });
+        let bytes = MessagePackCodec.encode(&payload).unwrap();
+
+        current
+            .send(ClientMessage::Broadcast {
+                payload: payload.clone(),
+            })
+            .await;
+
+        assert!(matches!(
+            current.recv_matching(|message| matches!(message, ServerMessage::EncodedBroadcast { .. })).await,
+            ServerMessage::EncodedBroadcast { codec, payload, .. } if codec == "msgpack" && *payload == bytes
+        ));
+        assert!(matches!(
+            outdated.recv_matching(|message| matches!(message, ServerMessage::Broadcast { .. })).await,
+            ServerMessage::Broadcast { payload: received, .. } if *received == payload
+        ));
+    }
+
+    #[tokio::test]
+    async fn keeps_spectators_behind_the_players() {
+        let server = TestServer::builder()
+            .with_spectator_delay(Duration::from_millis(300))
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut player = server.connect(room_id).await;
+        let mut spectator = server.connect_with_query(room_id, "spectate=true").await;
+
+        player
+            .send(ClientMessage::Broadcast {
+                payload: json!({ "move": "e4" }),
+            })
+            .await;
+
+        assert!(matches!(
+            player.recv().await,
+            ServerMessage::Broadcast { .. }
+        ));
+        spectator.expect_silence(Duration::from_millis(150)).await;
+        assert!(matches!(
+            spectator.recv().await,
+            ServerMessage::Broadcast { payload, .. } if *payload == json!({ "move": "e4" })
+        ));
+    }
+
+    #[tokio::test]
+    async fn disconnects_spectators_rather_than_rejecting_players_broadcasts() {
+        let server = TestServer::builder()
+            .with_spectator_delay(Duration::from_millis(300))
+            .with_room_memory_limit(512)
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let mut player = server.connect(room_id).await;
+        let mut spectator = server.connect_with_query(room_id, "spectate=true").await;
+
+        for i in 0..10 {
+            player
+                .send(ClientMessage::Broadcast {
+                    payload: json!({ "move": i, "padding": "x".repeat(64) }),
+                })
+                .await;
+            assert!(matches!(
+                player
+                    .recv_matching(|message| matches!(
+                        message,
+                        ServerMessage::Broadcast { .. } | ServerMessage::Error { .. }
+                    ))
+                    .await,
+                ServerMessage::Broadcast { .. }
+            ));
+        }
+
+        assert_eq!(
+            spectator.close_reason().await.as_deref(),
+            Some(SPECTATOR_OVERFLOWED_REASON)
+        );
+    }
+
+    #[tokio::test]
+    async fn holds_the_room_as_it_was_back_from_delayed_spectators() {
+        let server = TestServer::builder()
+            .with_spectator_delay(Duration::from_millis(300))
+            .start()
+            .await;
+        let room_id = server.create_room().await;
+        let data = json!({ "map": "de_dust2" }).as_object().unwrap().clone();
+        server
+            .state()
+            .room_registry
+            .registry()
+            .get_room_for_id(room_id)
+            .unwrap()
+            .set_custom_data(data.clone());
+
+        let mut spectator = server.connect_with_query(room_id, "spectate=true").await;
+
+        spectator.expect_silence(Duration::from_millis(150)).await;
+        assert_eq!(spectator.recv().await, ServerMessage::CustomData { data });
+    }
+}
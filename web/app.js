@@ -0,0 +1,114 @@
+// A small console for poking at a wormhole server during development. Tokens are kept in
+// local storage so they survive reloads.
+
+const $ = (id) => document.getElementById(id);
+
+let selectedRoom = null;
+let socket = null;
+
+for (const id of ["api-token", "player-token"]) {
+  $(id).value = localStorage.getItem(id) ?? "";
+  $(id).addEventListener("change", () => localStorage.setItem(id, $(id).value));
+}
+
+function log(line) {
+  const output = $("console");
+  output.textContent += `${new Date().toLocaleTimeString()} ${line}\n`;
+  output.scrollTop = output.scrollHeight;
+}
+
+async function api(method, path) {
+  const headers = {};
+  const token = $("api-token").value;
+  if (token) {
+    headers.Authorization = `Bearer ${token}`;
+  }
+  const response = await fetch(`/api/v1${path}`, { method, headers });
+  if (!response.ok) {
+    const problem = await response.json().catch(() => null);
+    throw new Error(problem?.detail ?? problem?.title ?? response.statusText);
+  }
+  return response;
+}
+
+async function refreshRooms() {
+  try {
+    const rooms = await (await api("GET", "/rooms/")).json();
+    const list = $("room-list");
+    list.replaceChildren(...rooms.map(({ id }) => {
+      const item = document.createElement("li");
+      item.textContent = id;
+      item.classList.toggle("selected", id === selectedRoom);
+      item.addEventListener("click", () => selectRoom(id));
+      return item;
+    }));
+  } catch (e) {
+    log(`Couldn't list rooms: ${e.message}`);
+  }
+}
+
+async function selectRoom(id) {
+  selectedRoom = id;
+  $("connect").disabled = socket !== null;
+  try {
+    const room = await (await api("GET", `/rooms/${id}`)).json();
+    $("room-details").textContent = JSON.stringify(room, null, 2);
+  } catch (e) {
+    $("room-details").textContent = `Couldn't load the room: ${e.message}`;
+  }
+  refreshRooms();
+}
+
+async function createRoom() {
+  try {
+    const response = await api("POST", "/rooms/");
+    const location = response.headers.get("Location") ?? "";
+    log(`Created ${location}`);
+    if (location.startsWith("/ws/")) {
+      await selectRoom(location.slice("/ws/".length));
+    }
+  } catch (e) {
+    log(`Couldn't create a room: ${e.message}`);
+  }
+}
+
+function setConnected(connected) {
+  $("connect").disabled = connected || selectedRoom === null;
+  $("disconnect").disabled = !connected;
+  document.querySelector("#send-form button").disabled = !connected;
+}
+
+function connect() {
+  const url = new URL(`/ws/${selectedRoom}`, location.href);
+  url.protocol = url.protocol === "https:" ? "wss:" : "ws:";
+  const token = $("player-token").value;
+  if (token) {
+    url.searchParams.set("access_token", token);
+  }
+  url.searchParams.set("protocol_version", "2");
+
+  socket = new WebSocket(url);
+  socket.addEventListener("open", () => {
+    log(`Connected to ${selectedRoom}`);
+    setConnected(true);
+  });
+  socket.addEventListener("message", ({ data }) => log(`< ${data}`));
+  socket.addEventListener("close", ({ code, reason }) => {
+    log(`Disconnected (${code}${reason ? `: ${reason}` : ""})`);
+    socket = null;
+    setConnected(false);
+  });
+}
+
+$("refresh-rooms").addEventListener("click", refreshRooms);
+$("create-room").addEventListener("click", createRoom);
+$("connect").addEventListener("click", connect);
+$("disconnect").addEventListener("click", () => socket?.close());
+$("send-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+  const message = $("message").value;
+  socket?.send(message);
+  log(`> ${message}`);
+});
+
+refreshRooms();